@@ -0,0 +1,75 @@
+//! Compares the running time of the max flow algorithms on a safes database.
+//!
+//! Run with `cargo bench --bench flow_algorithms [-- <safes.db>]`.
+//! Defaults to the snapshot `graph_at_20230523_15_00.db` in the repository root.
+
+use std::env;
+use std::path::Path;
+use std::time::Instant;
+
+use pathfinder2::graph::{compute_flow, FlowAlgorithm};
+use pathfinder2::io::import_from_safes_binary;
+use pathfinder2::types::{Address, U256};
+
+const QUERIES: [(&str, &str, Option<u64>); 4] = [
+    (
+        "0x9BA1Bcd88E99d6E1E03252A70A63FEa83Bf1208c",
+        "0x42cEDde51198D1773590311E2A340DC06B24cB37",
+        Some(3),
+    ),
+    (
+        "0x8DC7e86fF693e9032A0F41711b5581a04b26Be2E",
+        "0x42cEDde51198D1773590311E2A340DC06B24cB37",
+        Some(3),
+    ),
+    (
+        "0x9BA1Bcd88E99d6E1E03252A70A63FEa83Bf1208c",
+        "0x939b2731997922f21ab0a0bab500a949c0fc3550",
+        Some(4),
+    ),
+    (
+        "0x9BA1Bcd88E99d6E1E03252A70A63FEa83Bf1208c",
+        "0x42cEDde51198D1773590311E2A340DC06B24cB37",
+        None,
+    ),
+];
+
+fn main() {
+    let file = env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .unwrap_or_else(|| "graph_at_20230523_15_00.db".to_string());
+    if !Path::new(&file).exists() {
+        println!("Safes database {file} not found, skipping benchmark.");
+        return;
+    }
+    let edges = import_from_safes_binary(&file).unwrap().edges().clone();
+    println!("Loaded {} edges from {file}", edges.edge_count());
+
+    for (from, to, max_distance) in QUERIES {
+        for algorithm in [
+            FlowAlgorithm::FordFulkerson,
+            FlowAlgorithm::PushRelabel,
+            FlowAlgorithm::Dinic,
+            FlowAlgorithm::MinCost,
+        ] {
+            let start = Instant::now();
+            let (flow, transfers) = compute_flow(
+                &Address::from(from),
+                &Address::from(to),
+                &edges,
+                U256::MAX,
+                max_distance,
+                None,
+                algorithm,
+            )
+            .unwrap();
+            println!(
+                "{from} -> {to} max_distance {max_distance:?} {algorithm:?}: {} in {} transfers, {:?}",
+                flow.to_decimal(),
+                transfers.len(),
+                start.elapsed()
+            );
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Benchmarks of the hot paths on generated scale-free trust graphs of several sizes:
+//! importing binary edge files, building the adjacency index, computing unlimited max flows
+//! without extracting transfers, and computing flows with each algorithm including their
+//! decomposition into transfers.
+//!
+//! Run with `cargo bench --bench hot_paths [-- [<filter>] [--save-baseline <name>]
+//! [--baseline <name>] [--threshold <percent>]]`. Each benchmark runs until it has
+//! `SAMPLES` samples or `TIME_BUDGET` is used up. With `--baseline`, the fastest samples
+//! are compared with those saved by `--save-baseline` (they vary less between runs than
+//! the medians) and the benchmark fails if one is more than `threshold` percent slower
+//! (25 by default, timings of the small graphs vary a lot on busy machines).
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use pathfinder2::graph::{
+    compute_flow_with_options, is_transfer_possible, Cancellation, FlowAlgorithm, FlowOptions,
+};
+use pathfinder2::io::{read_edges_binary, write_edges_binary};
+use pathfinder2::testing::{account, GraphConfig, Topology};
+use pathfinder2::types::edge::EdgeDB;
+
+/// The numbers of accounts of the generated graphs.
+const SIZES: [usize; 3] = [1_000, 10_000, 50_000];
+/// The number of accounts each new account trusts.
+const TRUST_PER_ACCOUNT: usize = 4;
+const SAMPLES: usize = 20;
+const TIME_BUDGET: Duration = Duration::from_secs(3);
+
+fn main() -> ExitCode {
+    let options = Options::parse();
+    let mut fastest = BTreeMap::new();
+    for size in SIZES {
+        let db = GraphConfig {
+            accounts: size,
+            topology: Topology::ScaleFree {
+                trusts_per_account: TRUST_PER_ACCOUNT,
+            },
+            ..GraphConfig::default()
+        }
+        .generate();
+        let edges = db.edges();
+        let file = env::temp_dir().join(format!("pathfinder_bench_{size}.dat"));
+        let file = file.to_str().unwrap();
+        write_edges_binary(edges, file).unwrap();
+        let mut run = |name: String, f: &mut dyn FnMut()| {
+            if options
+                .filter
+                .as_ref()
+                .is_none_or(|filter| name.contains(filter))
+            {
+                fastest.insert(name.clone(), measure(&name, f));
+            }
+        };
+        run(format!("import/{size}"), &mut || {
+            read_edges_binary(file).unwrap();
+        });
+        let raw = edges.edges().to_vec();
+        run(format!("adjacency/{size}"), &mut || {
+            EdgeDB::new(raw.clone());
+        });
+        // The accounts are ordered by age, so the first ones are the hubs.
+        let (hub, leaf) = (account(0), account(size - 1));
+        let (middle, other) = (account(size / 2), account(size / 3));
+        for (query, from, to, max_distance) in [
+            ("hub_to_leaf", hub, leaf, Some(3)),
+            ("leaf_to_leaf", leaf, middle, Some(3)),
+            ("unlimited", other, middle, None),
+        ] {
+            // Searching augmenting paths up to the maximum flow, without extracting transfers.
+            let max_flow = compute_flow_with_options(&from, &to, edges, &FlowOptions::default())
+                .unwrap()
+                .flow;
+            run(format!("max_flow/{size}/{query}"), &mut || {
+                assert!(is_transfer_possible(
+                    &from,
+                    &to,
+                    edges,
+                    max_flow,
+                    &Cancellation::default()
+                )
+                .unwrap());
+            });
+            for algorithm in FlowAlgorithm::ALL {
+                let flow_options = FlowOptions {
+                    max_distance,
+                    algorithm,
+                    ..FlowOptions::default()
+                };
+                let name = format!("transfers/{size}/{query}/{}", algorithm.as_str());
+                run(name, &mut || {
+                    compute_flow_with_options(&from, &to, edges, &flow_options).unwrap();
+                });
+            }
+        }
+        let _ = fs::remove_file(file);
+    }
+    options.compare_and_save(&fastest)
+}
+
+/// Runs `f` once to warm up and then up to `SAMPLES` times within `TIME_BUDGET`
+/// (at least three times), prints the median and returns the fastest sample.
+fn measure(name: &str, f: &mut dyn FnMut()) -> Duration {
+    f();
+    let start = Instant::now();
+    let mut samples = vec![];
+    while samples.len() < SAMPLES && (samples.len() < 3 || start.elapsed() < TIME_BUDGET) {
+        let sample = Instant::now();
+        f();
+        samples.push(sample.elapsed());
+    }
+    samples.sort();
+    println!(
+        "{name:<48} median {:>12.3?}  min {:>12.3?}  max {:>12.3?}  ({} samples)",
+        samples[samples.len() / 2],
+        samples[0],
+        samples[samples.len() - 1],
+        samples.len()
+    );
+    samples[0]
+}
+
+struct Options {
+    filter: Option<String>,
+    save_baseline: Option<String>,
+    baseline: Option<String>,
+    threshold: f64,
+}
+
+impl Options {
+    fn parse() -> Options {
+        let mut options = Options {
+            filter: None,
+            save_baseline: None,
+            baseline: None,
+            threshold: 25.0,
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--save-baseline" => options.save_baseline = args.next(),
+                "--baseline" => options.baseline = args.next(),
+                "--threshold" => {
+                    options.threshold = args
+                        .next()
+                        .and_then(|t| t.parse().ok())
+                        .expect("Expected a percentage after --threshold.")
+                }
+                // Passed by `cargo bench`.
+                "--bench" => {}
+                _ => options.filter = Some(arg),
+            }
+        }
+        options
+    }
+
+    fn baseline_file(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("target")
+            .join("bench-baselines")
+            .join(format!("hot_paths_{name}.json"))
+    }
+
+    /// Compares the fastest samples with the baseline and saves them, fails on regressions.
+    fn compare_and_save(&self, fastest: &BTreeMap<String, Duration>) -> ExitCode {
+        let mut regressions = 0;
+        if let Some(baseline) = &self.baseline {
+            let text = fs::read_to_string(Self::baseline_file(baseline))
+                .unwrap_or_else(|e| panic!("Could not read baseline {baseline}: {e}"));
+            let saved = json::parse(&text).unwrap();
+            println!("\nCompared with baseline {baseline}:");
+            for (name, time) in fastest {
+                let Some(before) = saved[name.as_str()].as_f64() else {
+                    continue;
+                };
+                let change = (time.as_nanos() as f64 / before - 1.0) * 100.0;
+                let regressed = change > self.threshold;
+                regressions += regressed as usize;
+                let mark = if regressed { "  REGRESSION" } else { "" };
+                println!("{name:<48} {change:>+8.1}%{mark}");
+            }
+        }
+        if let Some(name) = &self.save_baseline {
+            let file = Self::baseline_file(name);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            let mut saved = json::JsonValue::new_object();
+            for (name, time) in fastest {
+                saved[name.as_str()] = (time.as_nanos() as f64).into();
+            }
+            fs::write(&file, saved.pretty(2)).unwrap();
+            println!("Saved baseline {name} to {}.", file.display());
+        }
+        if regressions > 0 {
+            println!(
+                "{regressions} benchmarks are more than {}% slower.",
+                self.threshold
+            );
+            return ExitCode::FAILURE;
+        }
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,12 @@
+fn main() {
+    // Compiles the gRPC interface without requiring protoc.
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/pathfinder.proto");
+        let descriptors = protox::compile(["proto/pathfinder.proto"], ["proto"])
+            .expect("Could not compile proto/pathfinder.proto");
+        tonic_build::configure()
+            .compile_fds(descriptors)
+            .expect("Could not generate the gRPC service");
+    }
+}
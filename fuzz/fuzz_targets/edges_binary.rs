@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pathfinder2::io::read_edges_binary_from;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(edges) = read_edges_binary_from(data) {
+        for edge in edges.edges() {
+            assert!(edges.edge(edge).is_some());
+        }
+    }
+});
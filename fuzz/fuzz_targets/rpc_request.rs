@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pathfinder2::server::decode_request;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decode_request(data);
+});
@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pathfinder2::types::U256;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(input) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(value) = input.parse::<U256>() {
+        assert_eq!(value.to_decimal().parse::<U256>(), Ok(value));
+        assert_eq!(value.to_string().parse::<U256>(), Ok(value));
+    }
+    if let Ok(value) = U256::from_decimal_units(input, 18) {
+        assert_eq!(
+            U256::from_decimal_units(&value.to_decimal_units(18), 18),
+            Ok(value)
+        );
+    }
+});
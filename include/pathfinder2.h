@@ -0,0 +1,51 @@
+/* The C interface of the pathfinder, built with `cargo build --release --features ffi`.
+ * Link against target/release/libpathfinder2.so or libpathfinder2.a. See src/ffi.rs. */
+
+#ifndef PATHFINDER2_H
+#define PATHFINDER2_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Pathfinder2Graph Pathfinder2Graph;
+
+/* A transfer of a flow. Addresses are 20 bytes, the value is 32 bytes big-endian. */
+typedef struct {
+    uint8_t from[20];
+    uint8_t to[20];
+    uint8_t token_owner[20];
+    uint8_t value[32];
+} Pathfinder2TransferStep;
+
+/* If error is not NULL, the computation failed, flow is zero and there are no steps. */
+typedef struct {
+    uint8_t flow[32];
+    Pathfinder2TransferStep *steps;
+    size_t step_count;
+    char *error;
+} Pathfinder2FlowResult;
+
+/* Loads an edge file in any format. Returns NULL on failure and stores the message
+ * in *error unless error is NULL, to be freed with pathfinder2_free_string. */
+Pathfinder2Graph *pathfinder2_load_edges(const char *path, char **error);
+
+/* Computes the maximum flow up to value (decimal, no limit if NULL). Addresses are
+ * in hex. max_distance and max_transfers of zero mean no limit. Never returns NULL. */
+Pathfinder2FlowResult *pathfinder2_compute_flow(const Pathfinder2Graph *graph,
+                                                const char *from, const char *to,
+                                                const char *value, uint64_t max_distance,
+                                                uint64_t max_transfers);
+
+void pathfinder2_free_result(Pathfinder2FlowResult *result);
+void pathfinder2_free_graph(Pathfinder2Graph *graph);
+void pathfinder2_free_string(char *string);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,196 @@
+//! The pathfinder for use in-process, without running the server: a `Graph` holds the
+//! edges, computes flows on them and applies updates. Queries run on an immutable
+//! snapshot of the edges, so they can run concurrently with each other and with updates.
+//!
+//! ```no_run
+//! use pathfinder2::graph::FlowOptions;
+//! use pathfinder2::Graph;
+//!
+//! let graph = Graph::load("edges.dat")?;
+//! let from = "0x9BA1Bcd88E99d6E1E03252A70A63FEa83Bf1208c".parse()?;
+//! let to = "0x42cEDde51198D1773590311E2A340DC06B24cB37".parse()?;
+//! let result = graph.compute_flow(&from, &to, &FlowOptions::default())?;
+//! println!("{} in {} transfers", result.flow, result.transfers.len());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::sync::Arc;
+
+use crate::config::DataStore;
+use crate::error::PathfinderError;
+use crate::graph::{self, FlowOptions, FlowResult};
+use crate::indexer::balances;
+use crate::io::wal::Change;
+use crate::io::{import_from_safes_binary, read_edges_file};
+use crate::safe_db::safes_json::import_from_safes_json;
+use crate::server::snapshot::VersionedGraph;
+use crate::types::edge::EdgeDB;
+use crate::types::{Address, Edge, U256};
+
+#[derive(Default)]
+pub struct Graph {
+    edges: VersionedGraph,
+}
+
+impl Graph {
+    pub fn new(edges: EdgeDB) -> Graph {
+        let graph = Graph::default();
+        graph.edges.refresh(|_| Some(edges));
+        graph
+    }
+
+    /// Loads an edge file in any format, see `io::read_edges_file`.
+    pub fn load(path: &str) -> Result<Graph, PathfinderError> {
+        Ok(Graph::new(read_edges_file(path)?))
+    }
+
+    /// Loads a safes file, binary or JSON if the name ends with `.json`. Capacities of
+    /// trust connections are computed from the trust limits, so that they follow
+    /// balance updates.
+    pub fn load_safes(path: &str) -> Result<Graph, PathfinderError> {
+        let db = match path.ends_with(".json") {
+            true => import_from_safes_json(path)?,
+            false => import_from_safes_binary(path)?,
+        };
+        Ok(Graph::new(db.edges_with_trust_limits()))
+    }
+
+    /// Restores the edges from `directory` and persists all updates there, in a binary
+    /// edge file and a write-ahead log, see `server::snapshot`.
+    pub fn persistent(directory: &str, compact_after: usize) -> Result<Graph, PathfinderError> {
+        Graph::persistent_in(directory, DataStore::Files, compact_after)
+    }
+
+    /// Like `persistent`, in the files or the database of `data_store`.
+    pub fn persistent_in(
+        directory: &str,
+        data_store: DataStore,
+        compact_after: usize,
+    ) -> Result<Graph, PathfinderError> {
+        Ok(Graph {
+            edges: VersionedGraph::persistent(directory, data_store, compact_after)?,
+        })
+    }
+
+    /// The current edges. They do not change, updates replace them.
+    pub fn edges(&self) -> Arc<EdgeDB> {
+        self.edges.snapshot().edges.clone()
+    }
+
+    /// Incremented by every load and update.
+    pub fn version(&self) -> u64 {
+        self.edges.snapshot().version
+    }
+
+    /// Computes the maximum flow from `from` to `to` (up to the requested value of the
+    /// options) and the transfers that realize it.
+    pub fn compute_flow(
+        &self,
+        from: &Address,
+        to: &Address,
+        options: &FlowOptions,
+    ) -> Result<FlowResult, PathfinderError> {
+        Ok(graph::compute_flow_with_options(
+            from,
+            to,
+            &self.edges(),
+            options,
+        )?)
+    }
+
+    /// Replaces all edges.
+    pub fn replace(&self, edges: EdgeDB) -> Result<(), PathfinderError> {
+        self.edges.replace(edges)?;
+        Ok(())
+    }
+
+    /// Sets the capacities of the edges, adding new ones. Edges with zero capacity
+    /// are removed.
+    pub fn update_edges(&self, edges: &[Edge]) -> Result<(), PathfinderError> {
+        let changes = edges
+            .iter()
+            .map(|edge| Change::Update {
+                edge: *edge,
+                block_number: None,
+                token_type: None,
+            })
+            .collect::<Vec<_>>();
+        self.apply(&changes)
+    }
+
+    /// Sets the trust limit percentage of `truster` for the tokens of `trustee`.
+    pub fn set_trust(
+        &self,
+        truster: Address,
+        trustee: Address,
+        percentage: u8,
+    ) -> Result<(), PathfinderError> {
+        if percentage > 100 {
+            return Err(PathfinderError::Rpc(format!(
+                "Invalid trust limit: {percentage}"
+            )));
+        }
+        let edge = Edge {
+            from: trustee,
+            to: truster,
+            token: trustee,
+            capacity: U256::ZERO,
+        };
+        self.apply(&[Change::LimitPercentage {
+            edge,
+            percentage,
+            block_number: None,
+        }])
+    }
+
+    /// Sets the balance of `holder` in the tokens of `token` (the token owner) and the
+    /// capacities of the edges that depend on it.
+    pub fn set_balance(
+        &self,
+        holder: Address,
+        token: Address,
+        amount: U256,
+    ) -> Result<(), PathfinderError> {
+        let changes = balances::balance_changes(holder, token, amount);
+        self.apply(&changes)
+    }
+
+    /// Applies the changes at once, queries see either none or all of them.
+    pub fn apply(&self, changes: &[Change]) -> Result<(), PathfinderError> {
+        self.edges.apply(changes)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::edge::edge;
+
+    fn address(i: u8) -> Address {
+        Address::from([i; 20])
+    }
+
+    #[test]
+    fn embedded_graph() {
+        let (a, b, c) = (address(1), address(2), address(3));
+        let graph = Graph::new(EdgeDB::new(vec![edge(a, b, a, 10)]));
+        assert_eq!(graph.version(), 0);
+        let flow = |graph: &Graph| {
+            graph
+                .compute_flow(&a, &c, &FlowOptions::default())
+                .unwrap()
+                .flow
+        };
+        assert_eq!(flow(&graph), U256::ZERO);
+
+        graph.update_edges(&[edge(b, c, b, 7)]).unwrap();
+        assert_eq!(graph.version(), 1);
+        assert_eq!(flow(&graph), U256::from(7u128));
+        graph.update_edges(&[edge(a, c, a, 5)]).unwrap();
+        // The edges of a in its own tokens share the balance of at most 10.
+        assert_eq!(flow(&graph), U256::from(10u128));
+        assert_eq!(graph.edges().edge_count(), 3);
+        assert!(graph.set_trust(c, a, 101).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! Async variants of the long-running operations, for embedding the pathfinder into
+//! tokio-based applications (feature `async`). The work runs on the blocking thread pool
+//! of the runtime via `spawn_blocking`, so it does not need a thread pool of its own and
+//! does not block the async tasks.
+
+use tokio::task::{self, JoinError};
+
+use crate::config::ConfigSource;
+use crate::error::PathfinderError;
+use crate::graph::{self, FlowOptions, FlowResult};
+use crate::indexer::rpc::EthClient;
+use crate::indexer::Indexer;
+use crate::server;
+use crate::types::edge::EdgeDB;
+use crate::types::Address;
+use crate::Graph;
+
+/// Computes a flow on the current edges of the graph, see `Graph::compute_flow`.
+/// Updates while it runs do not affect the result.
+pub async fn compute_flow(
+    graph: &Graph,
+    from: Address,
+    to: Address,
+    options: FlowOptions,
+) -> Result<FlowResult, PathfinderError> {
+    let edges = graph.edges();
+    task::spawn_blocking(move || graph::compute_flow_with_options(&from, &to, &edges, &options))
+        .await
+        .map_err(join_error)?
+        .map_err(PathfinderError::from)
+}
+
+/// Loads an edge file, see `Graph::load`.
+pub async fn load(path: &str) -> Result<Graph, PathfinderError> {
+    let path = path.to_string();
+    task::spawn_blocking(move || Graph::load(&path))
+        .await
+        .map_err(join_error)?
+}
+
+/// Downloads an edge file in any format, named like the file of the URL.
+pub async fn download_edges(url: &str) -> Result<EdgeDB, PathfinderError> {
+    let url = url.to_string();
+    task::spawn_blocking(move || {
+        server::download_edges(&url)
+            .map_err(|e| PathfinderError::Chain(format!("Error downloading {url}: {e}")))
+    })
+    .await
+    .map_err(join_error)?
+}
+
+/// Indexes the events of the hub up to the latest block, see `Indexer::sync`,
+/// and returns the indexer to continue later.
+pub async fn sync(mut indexer: Indexer, client: EthClient) -> Result<Indexer, PathfinderError> {
+    task::spawn_blocking(move || match indexer.sync(&client) {
+        Ok(()) => Ok(indexer),
+        Err(e) => Err(PathfinderError::Chain(e.to_string())),
+    })
+    .await
+    .map_err(join_error)?
+}
+
+/// Runs the server until it is shut down, see `server::start_server`.
+pub async fn start_server(source: ConfigSource) -> Result<(), PathfinderError> {
+    task::spawn_blocking(move || server::start_server(source))
+        .await
+        .map_err(join_error)
+}
+
+fn join_error(error: JoinError) -> PathfinderError {
+    match error.try_into_panic() {
+        Ok(panic) => std::panic::resume_unwind(panic),
+        Err(error) => PathfinderError::Io(std::io::Error::other(error)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::{Edge, U256};
+
+    #[test]
+    fn flow_on_blocking_pool() {
+        let (a, b) = (Address::from([1; 20]), Address::from([2; 20]));
+        let graph = Graph::new(EdgeDB::new(vec![Edge {
+            from: a,
+            to: b,
+            token: a,
+            capacity: U256::from(10u128),
+        }]));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let result = runtime
+            .block_on(compute_flow(&graph, a, b, FlowOptions::default()))
+            .unwrap();
+        assert_eq!(result.flow, U256::from(10u128));
+        assert!(runtime.block_on(load("/nonexistent/edges.dat")).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+//! Optional API-key authentication with per-key limits.
+//!
+//! If the environment variable `PATHFINDER_API_KEYS` is set to the path of a JSON file,
+//! requests have to carry one of the keys in the file in the `X-API-Key` header. The file
+//! maps each key to its (optional) limits:
+//!
+//! ```json
+//! {
+//!     "3b1f...": { "requests_per_minute": 60, "max_value": "1000000000000000000000", "max_concurrency": 2 }
+//! }
+//! ```
+//!
+//! The file is read again whenever it changes, so keys can be added or revoked at runtime.
+
+use crate::types::U256;
+use json::JsonValue;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub requests_per_minute: Option<u64>,
+    /// The maximum value (in atto-Circles) of a flow computation.
+    pub max_value: Option<U256>,
+    /// The maximum number of connections at the same time.
+    pub max_concurrency: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+    /// The request carries no key or a key that is not in the file.
+    Unauthorized,
+    RateLimited,
+    TooManyConnections,
+}
+
+impl Error for AuthError {}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::Unauthorized => write!(f, "Missing or unknown API key"),
+            AuthError::RateLimited => write!(f, "Rate limit exceeded, try again later"),
+            AuthError::TooManyConnections => write!(f, "Too many concurrent requests"),
+        }
+    }
+}
+
+struct Key {
+    /// The limits and the number of requests that can be made right now.
+    state: Mutex<(Limits, RequestBucket)>,
+    connections: AtomicUsize,
+}
+
+/// A token bucket that holds up to `requests_per_minute` requests and is refilled
+/// continuously.
+#[derive(Debug, Clone)]
+struct RequestBucket {
+    available: f64,
+    refilled_at: Instant,
+}
+
+impl RequestBucket {
+    fn new(limits: &Limits) -> RequestBucket {
+        RequestBucket {
+            available: limits.requests_per_minute.unwrap_or_default() as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    fn take(&mut self, requests_per_minute: u64, now: Instant) -> bool {
+        let capacity = requests_per_minute as f64;
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.available = (self.available + elapsed * capacity / 60.0).min(capacity);
+        self.refilled_at = now;
+        if self.available >= 1.0 {
+            self.available -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub struct ApiKeys {
+    path: String,
+    keys: Mutex<KeyFile>,
+}
+
+#[derive(Default)]
+struct KeyFile {
+    keys: HashMap<String, Arc<Key>>,
+    /// The modification time of the file when the keys were read.
+    modified: Option<SystemTime>,
+}
+
+impl ApiKeys {
+    pub fn from_env() -> Result<Option<ApiKeys>, Box<dyn Error>> {
+        match env::var("PATHFINDER_API_KEYS") {
+            Ok(path) => Ok(Some(ApiKeys::load(&path)?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<ApiKeys, Box<dyn Error>> {
+        let keys = ApiKeys {
+            path: path.to_string(),
+            keys: Default::default(),
+        };
+        keys.reload()?;
+        Ok(keys)
+    }
+
+    /// Reads the file again if it changed since it was last read.
+    fn reload(&self) -> Result<(), Box<dyn Error>> {
+        let modified = fs::metadata(&self.path)?.modified().ok();
+        let mut file = self.keys.lock().unwrap();
+        if modified.is_some() && modified == file.modified {
+            return Ok(());
+        }
+        let limits = parse_keys(&fs::read_to_string(&self.path)?)?;
+        // The state of keys that are kept is kept as well.
+        file.keys.retain(|key, _| limits.contains_key(key));
+        for (key, limits) in limits {
+            match file.keys.get(&key) {
+                Some(existing) => existing.state.lock().unwrap().0 = limits,
+                None => {
+                    let bucket = RequestBucket::new(&limits);
+                    file.keys.insert(
+                        key,
+                        Arc::new(Key {
+                            state: Mutex::new((limits, bucket)),
+                            connections: AtomicUsize::new(0),
+                        }),
+                    );
+                }
+            }
+        }
+        file.modified = modified;
+        Ok(())
+    }
+
+    /// Checks the key of a new connection. The connection counts towards the
+    /// concurrency limit of the key until the returned permit is dropped.
+    pub fn authorize(&self, key: Option<&str>) -> Result<Permit, AuthError> {
+        if let Err(e) = self.reload() {
+            tracing::error!("Error reading API keys, keeping the previous keys: {e}");
+        }
+        let key = key
+            .and_then(|key| self.keys.lock().unwrap().keys.get(key).cloned())
+            .ok_or(AuthError::Unauthorized)?;
+        let max_concurrency = key.state.lock().unwrap().0.max_concurrency;
+        let connections = key.connections.fetch_add(1, Ordering::SeqCst);
+        let permit = Permit { key };
+        if max_concurrency.is_some_and(|max| connections >= max) {
+            return Err(AuthError::TooManyConnections);
+        }
+        Ok(permit)
+    }
+}
+
+/// The authorization of a connection.
+pub struct Permit {
+    key: Arc<Key>,
+}
+
+impl Permit {
+    /// Counts a request towards the rate limit of the key.
+    pub fn request(&self) -> Result<(), AuthError> {
+        let mut state = self.key.state.lock().unwrap();
+        let (limits, bucket) = &mut *state;
+        match limits.requests_per_minute {
+            Some(requests_per_minute) if !bucket.take(requests_per_minute, Instant::now()) => {
+                Err(AuthError::RateLimited)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn max_value(&self) -> Option<U256> {
+        self.key.state.lock().unwrap().0.max_value
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.key.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn parse_keys(text: &str) -> Result<HashMap<String, Limits>, Box<dyn Error>> {
+    let keys = json::parse(text)?;
+    if !keys.is_object() {
+        return Err("Expected an object mapping API keys to limits.".into());
+    }
+    let optional_u64 = |value: &JsonValue, name: &str| match value {
+        JsonValue::Null => Ok(None),
+        value => value
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| format!("Invalid {name}: {value}")),
+    };
+    keys.entries()
+        .map(|(key, limits)| {
+            let max_value = match &limits["max_value"] {
+                JsonValue::Null => None,
+                value => Some(value.to_string().parse::<U256>()?),
+            };
+            let limits = Limits {
+                requests_per_minute: optional_u64(
+                    &limits["requests_per_minute"],
+                    "requests_per_minute",
+                )?,
+                max_value,
+                max_concurrency: optional_u64(&limits["max_concurrency"], "max_concurrency")?
+                    .map(|max| max as usize),
+            };
+            Ok((key.to_string(), limits))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn limits() {
+        let path = env::temp_dir().join(format!("pathfinder_api_keys_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(
+            path,
+            r#"{"a": {"requests_per_minute": 2, "max_value": "1000", "max_concurrency": 1}, "b": {}}"#,
+        )
+        .unwrap();
+        let keys = ApiKeys::load(path).unwrap();
+        assert_eq!(keys.authorize(None).err(), Some(AuthError::Unauthorized));
+        assert_eq!(
+            keys.authorize(Some("c")).err(),
+            Some(AuthError::Unauthorized)
+        );
+
+        let permit = keys.authorize(Some("a")).unwrap();
+        assert_eq!(permit.max_value(), Some(U256::from(1000u128)));
+        assert_eq!(
+            keys.authorize(Some("a")).err(),
+            Some(AuthError::TooManyConnections)
+        );
+        assert_eq!(permit.request(), Ok(()));
+        assert_eq!(permit.request(), Ok(()));
+        assert_eq!(permit.request(), Err(AuthError::RateLimited));
+        drop(permit);
+        assert!(keys.authorize(Some("a")).is_ok());
+
+        let unlimited = keys.authorize(Some("b")).unwrap();
+        assert_eq!(unlimited.max_value(), None);
+        for _ in 0..10 {
+            assert_eq!(unlimited.request(), Ok(()));
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn refill() {
+        let start = Instant::now();
+        let mut bucket = RequestBucket {
+            available: 0.0,
+            refilled_at: start,
+        };
+        assert!(!bucket.take(60, start));
+        assert!(bucket.take(60, start + Duration::from_secs(1)));
+        assert!(!bucket.take(60, start + Duration::from_secs(1)));
+        // The bucket holds at most a minute of requests.
+        assert!(bucket.take(60, start + Duration::from_secs(3600)));
+        assert!(bucket.available <= 59.0);
+    }
+}
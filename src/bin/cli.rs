@@ -2,8 +2,11 @@ use std::env;
 use std::fs::File;
 use std::io::Write;
 
+use pathfinder2::diff;
 use pathfinder2::graph;
+use pathfinder2::graph::FlowAlgorithm;
 use pathfinder2::io;
+use pathfinder2::types::edge::EdgeDB;
 use pathfinder2::types::Address;
 use pathfinder2::types::U256;
 
@@ -15,15 +18,26 @@ const TRANSFER_THROUGH_SIG: &str = "transferThrough(address[],address[],address[
 const RPC_URL: &str = "https://rpc.gnosischain.com";
 
 fn main() {
-    let (dotfile, mut args) =
-        if env::args().len() >= 2 && env::args().nth_back(1).unwrap() == "--dot" {
-            (
-                Some(env::args().last().unwrap()),
-                env::args().rev().skip(2).rev().collect::<Vec<_>>(),
-            )
-        } else {
-            (None, env::args().collect::<Vec<_>>())
-        };
+    if env::args().nth(1).as_deref() == Some("diff") {
+        diff_snapshots(&env::args().skip(2).collect::<Vec<_>>());
+        return;
+    }
+    // The output options come last, in any order.
+    let (parquet_file, args) = trailing_option(env::args().collect(), "--parquet");
+    let (dotfile, args) = trailing_option(args, "--dot");
+    let (parquet_file, mut args) = match parquet_file {
+        Some(parquet_file) => (Some(parquet_file), args),
+        None => trailing_option(args, "--parquet"),
+    };
+    let algorithm = if args.get(1) == Some(&"--algorithm".to_string()) && args.len() > 2 {
+        let algorithm = args[2]
+            .parse::<FlowAlgorithm>()
+            .unwrap_or_else(|e| panic!("{e}"));
+        args = [vec![args[0].clone()], args[3..].to_vec()].concat();
+        algorithm
+    } else {
+        FlowAlgorithm::default()
+    };
     let csv = if args.get(1) == Some(&"--csv".to_string()) {
         args = [vec![args[0].clone()], args[2..].to_vec()].concat();
         true
@@ -36,24 +50,49 @@ fn main() {
     } else {
         false
     };
-    if safes && csv {
-        println!("Options --safes and --csv cannot be used together.");
+    let sqlite = if args.get(1) == Some(&"--sqlite".to_string()) {
+        args = [vec![args[0].clone()], args[2..].to_vec()].concat();
+        true
+    } else {
+        false
+    };
+    let rocksdb = if args.get(1) == Some(&"--rocksdb".to_string()) {
+        args = [vec![args[0].clone()], args[2..].to_vec()].concat();
+        true
+    } else {
+        false
+    };
+    if [safes, csv, sqlite, rocksdb]
+        .iter()
+        .filter(|option| **option)
+        .count()
+        > 1
+    {
+        println!("Options --safes, --csv, --sqlite and --rocksdb cannot be used together.");
         return;
     }
 
     if args.len() < 4 {
-        println!("Usage: cli [--csv] [--safes] <from> <to> <edges.dat> [--dot <dotfile>]");
+        println!("Usage: cli [--algorithm <algorithm>] [--csv | --safes | --sqlite | --rocksdb] <from> <to> <edges.dat> [--dot <dotfile>]");
         println!(
-            "Usage: cli [--csv] [--safes] <from> <to> <edges.dat> <max_hops>  [--dot <dotfile>]"
+            "Usage: cli [--algorithm <algorithm>] [--csv | --safes | --sqlite | --rocksdb] <from> <to> <edges.dat> <max_hops>  [--dot <dotfile>]"
         );
         println!(
-            "Usage: cli [--csv] [--safes] <from> <to> <edges.dat> <max_hops> <max_flow> [--dot <dotfile>]"
+            "Usage: cli [--algorithm <algorithm>] [--csv | --safes | --sqlite | --rocksdb] <from> <to> <edges.dat> <max_hops> <max_flow> [--dot <dotfile>]"
         );
         println!(
-            "Usage: cli [--csv] [--safes] <from> <to> <edges.dat> <max_hops> <max_flow> <max_transfers> [--dot <dotfile>]"
+            "Usage: cli [--algorithm <algorithm>] [--csv | --safes | --sqlite | --rocksdb] <from> <to> <edges.dat> <max_hops> <max_flow> <max_transfers> [--dot <dotfile>]"
+        );
+        println!(
+            "Option --algorithm selects the max flow algorithm: ford_fulkerson (default), push_relabel, dinic or min_cost."
         );
         println!("Option --csv reads edges.dat in csv format instead of binary.");
         println!("Option --safes reads a safes.dat file instead of an edges.dat file.");
+        println!("Option --sqlite reads edges.dat as a SQLite database, only the edges within max_hops if given (needs the feature sqlite).");
+        println!("Option --rocksdb reads edges.dat as a RocksDB database directory, only the edges within max_hops if given (needs the feature rocksdb).");
+        println!("Option --parquet <file> writes the transfers to a Parquet file (needs the feature parquet).");
+        println!("Usage: cli diff <old_edges> <new_edges> [<max_accounts>]");
+        println!("Compares two edge files (binary, mapped or csv) and reports the changed edges.");
         return;
     }
     let mut max_hops = None;
@@ -79,6 +118,10 @@ fn main() {
         io::read_edges_csv(edges_file)
     } else if safes {
         io::import_from_safes_binary(edges_file).map(|db| db.edges().clone())
+    } else if sqlite {
+        read_edges_sqlite(edges_file, from_str, max_hops)
+    } else if rocksdb {
+        read_edges_rocksdb(edges_file, from_str, max_hops)
     } else {
         io::read_edges_binary(edges_file)
     })
@@ -91,7 +134,9 @@ fn main() {
         max_flow,
         max_hops,
         max_transfers,
-    );
+        algorithm,
+    )
+    .unwrap_or_else(|e| panic!("Error computing flow: {e}"));
     println!("Found flow: {}", flow.to_decimal());
     //println!("{:?}", transfers);
 
@@ -140,4 +185,83 @@ fn main() {
             .unwrap();
         println!("Wrote dotfile {dotfile}.");
     }
+    if let Some(parquet_file) = parquet_file {
+        #[cfg(feature = "parquet")]
+        {
+            io::write_transfers_parquet(&transfers, &parquet_file).unwrap();
+            println!("Wrote {} transfers to {parquet_file}.", transfers.len());
+        }
+        #[cfg(not(feature = "parquet"))]
+        println!("Cannot write {parquet_file}, Parquet needs the feature parquet.");
+    }
+}
+
+/// Reads the edges of a SQLite database, only those within `max_hops` of `from` if given.
+#[cfg(feature = "sqlite")]
+fn read_edges_sqlite(
+    edges_file: &str,
+    from: &str,
+    max_hops: Option<u64>,
+) -> Result<EdgeDB, std::io::Error> {
+    match max_hops {
+        Some(max_hops) => {
+            io::SqliteEdgeStore::open(edges_file)?.neighborhood(&Address::from(from), max_hops)
+        }
+        None => io::read_edges_sqlite(edges_file),
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn read_edges_sqlite(_: &str, _: &str, _: Option<u64>) -> Result<EdgeDB, std::io::Error> {
+    panic!("Reading SQLite databases needs the feature sqlite.");
+}
+
+/// Reads the edges of a RocksDB database, only those within `max_hops` of `from` if given.
+#[cfg(feature = "rocksdb")]
+fn read_edges_rocksdb(
+    edges_file: &str,
+    from: &str,
+    max_hops: Option<u64>,
+) -> Result<EdgeDB, std::io::Error> {
+    match max_hops {
+        Some(max_hops) => {
+            io::RocksDbEdgeStore::open(edges_file)?.neighborhood(&Address::from(from), max_hops)
+        }
+        None => io::read_edges_rocksdb(edges_file),
+    }
+}
+
+#[cfg(not(feature = "rocksdb"))]
+fn read_edges_rocksdb(_: &str, _: &str, _: Option<u64>) -> Result<EdgeDB, std::io::Error> {
+    panic!("Reading RocksDB databases needs the feature rocksdb.");
+}
+
+/// Removes the option `name` with its value from the end of the arguments, if it is there.
+fn trailing_option(mut args: Vec<String>, name: &str) -> (Option<String>, Vec<String>) {
+    if args.len() >= 3 && args[args.len() - 2] == name {
+        let value = args.pop();
+        args.pop();
+        (value, args)
+    } else {
+        (None, args)
+    }
+}
+
+fn diff_snapshots(args: &[String]) {
+    if args.len() < 2 {
+        println!("Usage: cli diff <old_edges> <new_edges> [<max_accounts>]");
+        return;
+    }
+    let read = |file: &String| {
+        io::read_edges_file(file)
+            .unwrap_or_else(|e| panic!("Error loading edges from file \"{file}\": {e}"))
+    };
+    let (old, new) = (read(&args[0]), read(&args[1]));
+    let limit = args.get(2).map_or(20, |limit| {
+        limit
+            .parse()
+            .unwrap_or_else(|_| panic!("Expected number of accounts, but got: {limit}"))
+    });
+    println!("Read {} and {} edges", old.edge_count(), new.edge_count());
+    println!("{}", diff::diff(&old, &new).to_json(limit).pretty(2));
 }
@@ -7,7 +7,13 @@ fn main() {
     let input_format = env::args().nth(1).and_then(|op| {
         if matches!(
             op.as_str(),
-            "--safes-json" | "--safes-bin" | "--edges-csv" | "--edges-bin"
+            "--safes-json"
+                | "--safes-bin"
+                | "--edges-csv"
+                | "--edges-bin"
+                | "--edges-mmap"
+                | "--edges-sqlite"
+                | "--edges-rocksdb"
         ) {
             Some(op)
         } else {
@@ -15,7 +21,10 @@ fn main() {
         }
     });
     let output_format = env::args().nth(3).and_then(|op| {
-        if matches!(op.as_str(), "--edges-csv" | "--edges-bin") {
+        if matches!(
+            op.as_str(),
+            "--edges-csv" | "--edges-bin" | "--edges-mmap" | "--edges-sqlite" | "--edges-rocksdb"
+        ) {
             Some(op)
         } else {
             None
@@ -28,16 +37,22 @@ fn main() {
         println!("    --safes-bin");
         println!("    --edges-csv");
         println!("    --edges-bin");
+        println!("    --edges-mmap");
+        println!("    --edges-sqlite (needs the feature sqlite)");
+        println!("    --edges-rocksdb (needs the feature rocksdb)");
         println!("  and <output>is one of:");
         println!("    --edges-csv");
         println!("    --edges-bin");
+        println!("    --edges-mmap");
+        println!("    --edges-sqlite (needs the feature sqlite)");
+        println!("    --edges-rocksdb (needs the feature rocksdb)");
         return;
     }
 
     let input_file = env::args().nth(2).unwrap();
     let edges = match input_format.unwrap().as_str() {
         "--safes-json" => {
-            let safes = import_from_safes_json(&input_file);
+            let safes = import_from_safes_json(&input_file).unwrap();
             safes.edges().clone()
         }
         "--safes-bin" => {
@@ -46,6 +61,15 @@ fn main() {
         }
         "--edges-csv" => read_edges_csv(&input_file).unwrap(),
         "--edges-bin" => read_edges_binary(&input_file).unwrap(),
+        "--edges-mmap" => map_edges(&input_file).unwrap(),
+        #[cfg(feature = "sqlite")]
+        "--edges-sqlite" => read_edges_sqlite(&input_file).unwrap(),
+        #[cfg(not(feature = "sqlite"))]
+        "--edges-sqlite" => panic!("SQLite databases need the feature sqlite."),
+        #[cfg(feature = "rocksdb")]
+        "--edges-rocksdb" => read_edges_rocksdb(&input_file).unwrap(),
+        #[cfg(not(feature = "rocksdb"))]
+        "--edges-rocksdb" => panic!("RocksDB databases need the feature rocksdb."),
         _ => unreachable!(),
     };
     println!("Imported {} edges.", edges.edge_count());
@@ -54,6 +78,15 @@ fn main() {
     match output_format.unwrap().as_str() {
         "--edges-csv" => write_edges_csv(&edges, &output_file).unwrap(),
         "--edges-bin" => write_edges_binary(&edges, &output_file).unwrap(),
+        "--edges-mmap" => write_edges_mapped(&edges, &output_file).unwrap(),
+        #[cfg(feature = "sqlite")]
+        "--edges-sqlite" => write_edges_sqlite(&edges, &output_file).unwrap(),
+        #[cfg(not(feature = "sqlite"))]
+        "--edges-sqlite" => panic!("SQLite databases need the feature sqlite."),
+        #[cfg(feature = "rocksdb")]
+        "--edges-rocksdb" => write_edges_rocksdb(&edges, &output_file).unwrap(),
+        #[cfg(not(feature = "rocksdb"))]
+        "--edges-rocksdb" => panic!("RocksDB databases need the feature rocksdb."),
         _ => unreachable!(),
     }
     println!("Export done.");
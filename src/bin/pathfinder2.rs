@@ -0,0 +1,370 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt::Display;
+use std::process::ExitCode;
+use std::str::FromStr;
+
+use json::JsonValue;
+use pathfinder2::config::{self, ConfigSource};
+use pathfinder2::io;
+use pathfinder2::safe_db::safes_json::import_from_safes_json;
+use pathfinder2::server::{self, LocalServer};
+use pathfinder2::testing::{GraphConfig, Topology};
+use pathfinder2::types::edge::EdgeDB;
+use pathfinder2::types::U256;
+
+const USAGE: &str = "Usage: pathfinder2 <command> [<arguments>]
+
+Commands:
+  serve [--config <file>] [--<setting> <value> ...]
+      Starts the JSON-RPC server, with the settings of the configuration (e.g. --listen_at).
+  convert (<edges> | --safes <file>) <output> [--format binary|csv|mmap]
+      Converts an edge file (or a safes file, binary or JSON) to another format. The
+      format is taken from the name of the output if not given (.csv, .mmap or binary).
+  query (--edges <file> | --safes <file>) --from <address> --to <address> [--value <value>]
+        [--max_hops <hops>] [--max_transfers <transfers>] [--algorithm <algorithm>]
+      Computes a transfer like the JSON-RPC method compute_transfer.
+  stats (--edges <file> | --safes <file>) [--hubs <count>]
+      Prints statistics of the graph like the JSON-RPC method graph_stats.
+  diff <old_edges> <new_edges> [--limit <accounts>]
+      Reports the changed edges like the JSON-RPC method diff_edges.
+  generate <output> [--accounts <count>] [--trust_probability <probability>
+           | --trusts_per_account <count>] [--hubs <count>] [--seed <seed>]
+      Writes a binary safes file of a random Circles-like graph, e.g. for load tests.";
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    let command = args.next().unwrap_or_default();
+    let result = Args::parse(args).and_then(|args| match command.as_str() {
+        "serve" => serve(args),
+        "convert" => convert(args),
+        "query" => query(args),
+        "stats" => stats(args),
+        "diff" => diff(args),
+        "generate" => generate(args),
+        _ => Err(USAGE.into()),
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The arguments of a command: positional arguments and options `--<name> <value>`.
+struct Args {
+    positional: Vec<String>,
+    options: BTreeMap<String, String>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, Box<dyn Error>> {
+        let mut parsed = Args {
+            positional: vec![],
+            options: BTreeMap::new(),
+        };
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    let value = args.next().ok_or(format!("Missing value of --{name}."))?;
+                    parsed.options.insert(name.replace('-', "_"), value);
+                }
+                None => parsed.positional.push(arg),
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    /// The parsed value of an option, if given.
+    fn parsed<T: FromStr>(&self, name: &str) -> Result<Option<T>, Box<dyn Error>>
+    where
+        T::Err: Display,
+    {
+        match self.option(name) {
+            Some(value) => Ok(Some(
+                value
+                    .parse()
+                    .map_err(|e| format!("Invalid --{name}: {e}"))?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    fn required(&self, name: &str) -> Result<&str, Box<dyn Error>> {
+        Ok(self
+            .option(name)
+            .ok_or(format!("Missing --{name}.\n\n{USAGE}"))?)
+    }
+
+    fn positional(&self, count: usize) -> Result<&[String], Box<dyn Error>> {
+        match self.positional.len() == count {
+            true => Ok(&self.positional),
+            false => Err(USAGE.into()),
+        }
+    }
+
+    /// Fails if there are options other than `names`.
+    fn only(&self, names: &[&str]) -> Result<(), Box<dyn Error>> {
+        match self
+            .options
+            .keys()
+            .find(|name| !names.contains(&name.as_str()))
+        {
+            Some(name) => Err(format!("Unknown option --{name}.\n\n{USAGE}").into()),
+            None => Ok(()),
+        }
+    }
+
+    /// The edges of `--edges` (any format) or `--safes` (binary or JSON).
+    fn load_edges(&self) -> Result<EdgeDB, Box<dyn Error>> {
+        match (self.option("edges"), self.option("safes")) {
+            (Some(file), None) => Ok(io::read_edges_file(file)?),
+            (None, Some(file)) => load_safes(file),
+            _ => Err(format!("Expected either --edges or --safes.\n\n{USAGE}").into()),
+        }
+    }
+}
+
+fn load_safes(file: &str) -> Result<EdgeDB, Box<dyn Error>> {
+    let db = match file.ends_with(".json") {
+        true => import_from_safes_json(file)?,
+        false => io::import_from_safes_binary(file)?,
+    };
+    Ok(db.edges().clone())
+}
+
+fn serve(args: Args) -> Result<(), Box<dyn Error>> {
+    args.positional(0)?;
+    let mut source = ConfigSource::from_env();
+    for (name, value) in &args.options {
+        if name == "config" {
+            source.file = Some(value.clone());
+            continue;
+        }
+        let setting = config::SETTINGS
+            .iter()
+            .find(|setting| *setting == name)
+            .ok_or(format!("Unknown setting --{name}."))?;
+        source.arguments.push((setting, value.clone()));
+    }
+    // Fails early on invalid settings instead of panicking in the server.
+    source.load()?;
+    server::start_server(source);
+    Ok(())
+}
+
+fn convert(args: Args) -> Result<(), Box<dyn Error>> {
+    args.only(&["safes", "format"])?;
+    let (edges, output) = match args.option("safes") {
+        Some(file) => (load_safes(file)?, &args.positional(1)?[0]),
+        None => {
+            let [input, output] = args.positional(2)? else {
+                unreachable!()
+            };
+            (io::read_edges_file(input)?, output)
+        }
+    };
+    println!("Imported {} edges.", edges.edge_count());
+    let format = args.option("format").unwrap_or_else(|| {
+        let name = output.trim_end_matches(".gz").trim_end_matches(".zst");
+        match name.rsplit_once('.').map(|(_, extension)| extension) {
+            Some("csv") => "csv",
+            Some("mmap") => "mmap",
+            _ => "binary",
+        }
+    });
+    match format {
+        "binary" => io::write_edges_binary(&edges, output)?,
+        "csv" => io::write_edges_csv(&edges, output)?,
+        "mmap" => io::write_edges_mapped(&edges, output)?,
+        _ => return Err(format!("Unknown format {format}, expected binary, csv or mmap.").into()),
+    }
+    println!("Exported to {output}.");
+    Ok(())
+}
+
+fn query(args: Args) -> Result<(), Box<dyn Error>> {
+    args.positional(0)?;
+    args.only(&[
+        "edges",
+        "safes",
+        "from",
+        "to",
+        "value",
+        "max_hops",
+        "max_transfers",
+        "algorithm",
+    ])?;
+    let mut params = json::object! {
+        from: args.required("from")?,
+        to: args.required("to")?,
+        value: args.option("value").unwrap_or(&U256::MAX.to_decimal()),
+    };
+    for name in ["max_hops", "max_transfers"] {
+        if let Some(value) = args.option(name) {
+            params[name] = value
+                .parse::<u64>()
+                .map_err(|e| format!("Invalid --{name}: {e}"))?
+                .into();
+        }
+    }
+    if let Some(algorithm) = args.option("algorithm") {
+        params["algorithm"] = algorithm.into();
+    }
+    let server = LocalServer::new(ConfigSource::from_env().load()?, args.load_edges()?)?;
+    print_response(server.call("compute_transfer", params))
+}
+
+fn stats(args: Args) -> Result<(), Box<dyn Error>> {
+    args.positional(0)?;
+    args.only(&["edges", "safes", "hubs"])?;
+    let mut params = json::object! {};
+    if let Some(hubs) = args.option("hubs") {
+        params["hubs"] = hubs
+            .parse::<usize>()
+            .map_err(|e| format!("Invalid --hubs: {e}"))?
+            .into();
+    }
+    let server = LocalServer::new(ConfigSource::from_env().load()?, args.load_edges()?)?;
+    print_response(server.call("graph_stats", params))
+}
+
+fn diff(args: Args) -> Result<(), Box<dyn Error>> {
+    args.only(&["limit"])?;
+    let [old, new] = args.positional(2)? else {
+        unreachable!()
+    };
+    let mut params = json::object! { old: old.as_str(), new: new.as_str() };
+    if let Some(limit) = args.option("limit") {
+        params["limit"] = limit
+            .parse::<usize>()
+            .map_err(|e| format!("Invalid --limit: {e}"))?
+            .into();
+    }
+    let server = LocalServer::new(ConfigSource::from_env().load()?, EdgeDB::default())?;
+    print_response(server.call("diff_edges", params))
+}
+
+fn generate(args: Args) -> Result<(), Box<dyn Error>> {
+    args.only(&[
+        "accounts",
+        "trust_probability",
+        "trusts_per_account",
+        "hubs",
+        "seed",
+    ])?;
+    let output = &args.positional(1)?[0];
+    let mut config = GraphConfig::default();
+    if let Some(accounts) = args.parsed("accounts")? {
+        config.accounts = accounts;
+    }
+    if let Some(hubs) = args.parsed("hubs")? {
+        config.hubs = hubs;
+    }
+    if let Some(seed) = args.parsed("seed")? {
+        config.seed = seed;
+    }
+    match (
+        args.parsed("trust_probability")?,
+        args.parsed("trusts_per_account")?,
+    ) {
+        (Some(_), Some(_)) => {
+            return Err("Expected either --trust_probability or --trusts_per_account.".into())
+        }
+        (Some(trust_probability), None) => config.topology = Topology::Random { trust_probability },
+        (None, Some(trusts_per_account)) => {
+            config.topology = Topology::ScaleFree { trusts_per_account }
+        }
+        (None, None) => {}
+    }
+    let db = config.generate();
+    io::export_safes_to_binary(&db, output)?;
+    println!(
+        "Generated {} accounts with {} edges to {output}.",
+        db.safes().len(),
+        db.edges().edge_count()
+    );
+    Ok(())
+}
+
+/// Prints the result of a JSON-RPC response, or fails with its error.
+fn print_response(mut response: JsonValue) -> Result<(), Box<dyn Error>> {
+    match response["error"].is_null() {
+        true => {
+            println!("{}", response["result"].take().pretty(2));
+            Ok(())
+        }
+        false => Err(response["error"]["message"].to_string().into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, Box<dyn Error>> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn arguments() {
+        let args = parse(&["old.dat", "--max-hops", "3", "new.dat", "--limit", "x"]).unwrap();
+        assert_eq!(args.positional(2).unwrap(), ["old.dat", "new.dat"]);
+        assert_eq!(args.positional(1).unwrap_err().to_string(), USAGE);
+        // Dashes in option names are read as underscores.
+        assert_eq!(args.option("max_hops"), Some("3"));
+        assert_eq!(args.parsed::<u64>("max_hops").unwrap(), Some(3));
+        assert_eq!(args.parsed::<u64>("seed").unwrap(), None);
+        assert_eq!(
+            args.parsed::<usize>("limit").unwrap_err().to_string(),
+            "Invalid --limit: invalid digit found in string"
+        );
+        assert!(args.only(&["max_hops", "limit"]).is_ok());
+        assert!(args
+            .only(&["limit"])
+            .unwrap_err()
+            .to_string()
+            .starts_with("Unknown option --max_hops.\n\n"));
+        assert!(args
+            .required("from")
+            .unwrap_err()
+            .to_string()
+            .starts_with("Missing --from.\n\n"));
+
+        assert_eq!(
+            parse(&["--from", "0x1", "--to"]).err().unwrap().to_string(),
+            "Missing value of --to."
+        );
+        // The last value of a repeated option is taken.
+        let args = parse(&["--hubs", "1", "--hubs", "2"]).unwrap();
+        assert_eq!(args.option("hubs"), Some("2"));
+    }
+
+    #[test]
+    fn query_arguments() {
+        let error = |args: &[&str]| query(parse(args).unwrap()).unwrap_err().to_string();
+        for args in [
+            &["--from", "0x1", "--to", "0x2"][..],
+            &[
+                "--from", "0x1", "--to", "0x2", "--edges", "a.dat", "--safes", "b.dat",
+            ],
+        ] {
+            assert!(error(args).starts_with("Expected either --edges or --safes.\n\n"));
+        }
+        assert!(error(&["--edges", "a.dat"]).starts_with("Missing --from.\n\n"));
+        assert!(
+            error(&["--edges", "a.dat", "--hops", "2"]).starts_with("Unknown option --hops.\n\n")
+        );
+        assert_eq!(
+            error(&["--from", "0x1", "--to", "0x2", "--max_hops", "-1"]),
+            "Invalid --max_hops: invalid digit found in string"
+        );
+    }
+}
@@ -1,23 +1,22 @@
 use std::env;
 
+use pathfinder2::config::ConfigSource;
 use pathfinder2::server;
 
-fn main() {
-    let listen_at = env::args()
-        .nth(1)
-        .unwrap_or_else(|| "127.0.0.1:8080".to_string());
-
-    let queue_size = env::args()
-        .nth(2)
-        .unwrap_or_else(|| "10".to_string())
-        .parse::<usize>()
-        .unwrap();
+/// The settings that can be given as positional arguments, in order.
+const ARGUMENTS: &[&str] = &[
+    "listen_at",
+    "queue_size",
+    "threads",
+    "flow_workers",
+    "flow_queue_size",
+    "flow_timeout_ms",
+    "fast_workers",
+    "cache_size",
+];
 
-    let thread_count = env::args()
-        .nth(3)
-        .unwrap_or_else(|| "4".to_string())
-        .parse::<u64>()
-        .unwrap();
-
-    server::start_server(&listen_at, queue_size, thread_count);
+fn main() {
+    let mut source = ConfigSource::from_env();
+    source.arguments = ARGUMENTS.iter().copied().zip(env::args().skip(1)).collect();
+    server::start_server(source);
 }
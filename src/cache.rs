@@ -0,0 +1,90 @@
+//! A cache that evicts the least recently used entry when it is full.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+pub struct LruCache<K, V> {
+    capacity: usize,
+    /// The values and the time they were last used.
+    entries: HashMap<K, (V, u64)>,
+    /// The keys by the time they were last used.
+    recency: BTreeMap<u64, K>,
+    time: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    /// A cache of at most `capacity` entries. With a capacity of 0 nothing is cached.
+    pub fn new(capacity: usize) -> LruCache<K, V> {
+        LruCache {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            time: 0,
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let (value, used) = self.entries.get_mut(key)?;
+        self.time += 1;
+        let key = self.recency.remove(used).unwrap();
+        self.recency.insert(self.time, key);
+        *used = self.time;
+        Some(value)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.time += 1;
+        if let Some((_, used)) = self.entries.insert(key.clone(), (value, self.time)) {
+            self.recency.remove(&used);
+        } else if self.entries.len() > self.capacity {
+            let (_, oldest) = self.recency.pop_first().unwrap();
+            self.entries.remove(&oldest);
+        }
+        self.recency.insert(self.time, key);
+    }
+
+    /// Changes the capacity, evicting the least recently used entries if necessary.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            let (_, oldest) = self.recency.pop_first().unwrap();
+            self.entries.remove(&oldest);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get(&"a"), Some(&1));
+        cache.insert("c", 3);
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.get(&"a"), Some(&1));
+        assert_eq!(cache.get(&"c"), Some(&3));
+        cache.insert("c", 4);
+        assert_eq!(cache.entries.len(), 2);
+        cache.set_capacity(1);
+        assert_eq!(cache.get(&"a"), None);
+        assert_eq!(cache.get(&"c"), Some(&4));
+        cache.clear();
+        assert_eq!(cache.get(&"a"), None);
+
+        let mut disabled = LruCache::new(0);
+        disabled.insert("a", 1);
+        assert_eq!(disabled.get(&"a"), None);
+    }
+}
@@ -0,0 +1,624 @@
+//! The configuration of the server.
+//!
+//! Settings are read from a JSON file given by the environment variable `PATHFINDER_CONFIG`,
+//! then from environment variables named after the settings (such as `PATHFINDER_FLOW_WORKERS`),
+//! then from the command line. On SIGHUP, the settings are read again and those that can
+//! change at runtime are applied: the flow timeout, the cache size, the algorithm defaults
+//! and the source of the edges.
+//!
+//! The settings apply to the network of `chain_id`. Further networks are configured with
+//! `networks`, which maps their chain ids to the settings that differ from the main network,
+//! e.g. `{"10200": {"edge_source": "subgraph", "subgraph_url": "..."}}`. Only the settings
+//! of the edge sources (`NETWORK_SETTINGS`) can differ.
+
+use crate::graph::FlowAlgorithm;
+use crate::indexer;
+use crate::types::{Address, U256};
+use json::JsonValue;
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub listen_at: String,
+    /// The number of connections waiting for a thread.
+    pub queue_size: usize,
+    /// The number of threads handling connections.
+    pub threads: u64,
+    pub flow_workers: usize,
+    /// The number of flow computations waiting for a flow worker.
+    pub flow_queue_size: usize,
+    pub flow_timeout: Duration,
+    /// The number of threads computing cheap flows.
+    pub fast_workers: usize,
+    /// The number of transfer results cached.
+    pub cache_size: usize,
+    /// The algorithm of flow computations that do not specify one.
+    pub algorithm: FlowAlgorithm,
+    /// The hop limit of flow computations that do not specify one.
+    pub max_hops: Option<u64>,
+    /// Whether the flows of transfers on small graphs are compared with a slow reference
+    /// implementation, see `graph::reference_max_flow`. Meant for staging.
+    pub reference_check: bool,
+    /// The maximum value (in atto-Circles) of flow computations, if any. Flow methods
+    /// without a value ask for the maximum, so they are rejected.
+    pub max_value: Option<U256>,
+    /// The maximum percentage of the accounts of the graph a flow search reaches.
+    pub max_explored_percent: u8,
+    /// The maximum number of transfers a flow is decomposed into, see `graph::FlowLimits`.
+    pub max_decomposition_steps: Option<usize>,
+    /// The number of replaced versions of the graph that are kept for queries that pin
+    /// a version with `graph_version`. Each one holds a copy of the edges.
+    pub retained_versions: usize,
+    /// Where the edges come from, besides the JSON-RPC methods that load them.
+    pub edge_source: EdgeSource,
+    /// The URL the edges are downloaded from periodically, if any.
+    pub refresh_url: Option<String>,
+    /// The time between two downloads from `refresh_url` or the subgraph.
+    pub refresh_interval: Duration,
+    /// The directory the edges and the updates since they were loaded are persisted in, if any.
+    pub data_dir: Option<String>,
+    /// How the edges are stored in `data_dir`.
+    pub data_store: DataStore,
+    /// The number of logged updates after which the edges are written to `data_dir` again.
+    pub compact_after_updates: usize,
+    /// The directory loaded edges are archived in by block number, for queries with
+    /// `at_block`, if any.
+    pub archive_dir: Option<String>,
+    /// The Parquet file the edges of the main network are exported to periodically, if
+    /// any. Needs the feature `parquet`.
+    pub parquet_export_file: Option<String>,
+    /// The time between two exports to `parquet_export_file`.
+    pub parquet_export_interval: Duration,
+    /// The JSON-RPC URL of the node the edges are indexed from, and balances of transfers
+    /// are verified with, if any.
+    pub indexer_rpc_url: Option<String>,
+    /// The WebSocket URL of the node new blocks are followed from, if any.
+    pub indexer_ws_url: Option<String>,
+    /// The hub contract whose events are indexed.
+    pub indexer_hub: Address,
+    /// The block indexing starts at, usually the deployment block of the hub.
+    pub indexer_start_block: u64,
+    /// The time between two checks for new blocks.
+    pub indexer_interval: Duration,
+    /// The GraphQL URL of the Circles subgraph.
+    pub subgraph_url: Option<String>,
+    /// The chain id of the network the settings apply to.
+    pub chain_id: u64,
+    /// The settings of further networks by chain id, see `network`.
+    pub networks: BTreeMap<u64, Vec<(String, String)>>,
+}
+
+/// The chain id of Gnosis Chain, where the Circles hub is deployed.
+pub const GNOSIS_CHAIN_ID: u64 = 100;
+
+/// The sources of the edges that keep them up to date in the background.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeSource {
+    /// Edge files, downloaded from `refresh_url` if it is set.
+    #[default]
+    Files,
+    /// The events of the hub, read from a node, see `indexer`.
+    Indexer,
+    /// The Circles subgraph, see `safe_db::subgraph`.
+    Subgraph,
+}
+
+impl FromStr for EdgeSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "files" => Ok(EdgeSource::Files),
+            "indexer" => Ok(EdgeSource::Indexer),
+            "subgraph" => Ok(EdgeSource::Subgraph),
+            _ => Err(format!("Unknown edge source: {s}")),
+        }
+    }
+}
+
+/// How the edges and the updates since they were loaded are stored in `data_dir`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DataStore {
+    /// A binary edge file and a write-ahead log, see `io::wal`.
+    #[default]
+    Files,
+    /// A SQLite database, see `io::SqliteEdgeStore`. Needs the feature `sqlite`.
+    Sqlite,
+    /// A RocksDB database, see `io::RocksDbEdgeStore`. Needs the feature `rocksdb`.
+    RocksDb,
+}
+
+impl FromStr for DataStore {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "files" => Ok(DataStore::Files),
+            "sqlite" => Ok(DataStore::Sqlite),
+            "rocksdb" => Ok(DataStore::RocksDb),
+            _ => Err(format!("Unknown data store: {s}")),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen_at: "127.0.0.1:8080".to_string(),
+            queue_size: 10,
+            threads: 4,
+            flow_workers: 4,
+            flow_queue_size: 16,
+            flow_timeout: Duration::from_secs(30),
+            fast_workers: 2,
+            cache_size: 1000,
+            algorithm: FlowAlgorithm::default(),
+            max_hops: None,
+            reference_check: false,
+            max_value: None,
+            max_explored_percent: 100,
+            max_decomposition_steps: None,
+            retained_versions: 0,
+            edge_source: EdgeSource::default(),
+            refresh_url: None,
+            refresh_interval: Duration::from_secs(300),
+            data_dir: None,
+            data_store: DataStore::default(),
+            archive_dir: None,
+            parquet_export_file: None,
+            parquet_export_interval: Duration::from_secs(3600),
+            compact_after_updates: 10000,
+            indexer_rpc_url: None,
+            indexer_ws_url: None,
+            indexer_hub: Address::from(indexer::HUB_ADDRESS),
+            indexer_start_block: indexer::HUB_DEPLOYMENT_BLOCK,
+            indexer_interval: Duration::from_secs(10),
+            subgraph_url: None,
+            chain_id: GNOSIS_CHAIN_ID,
+            networks: BTreeMap::new(),
+        }
+    }
+}
+
+/// The names of the settings, as used in the configuration file.
+pub const SETTINGS: &[&str] = &[
+    "listen_at",
+    "queue_size",
+    "threads",
+    "flow_workers",
+    "flow_queue_size",
+    "flow_timeout_ms",
+    "fast_workers",
+    "cache_size",
+    "algorithm",
+    "max_hops",
+    "reference_check",
+    "max_value",
+    "max_explored_percent",
+    "max_decomposition_steps",
+    "retained_versions",
+    "edge_source",
+    "refresh_url",
+    "refresh_interval_secs",
+    "data_dir",
+    "data_store",
+    "archive_dir",
+    "parquet_export_file",
+    "parquet_export_interval_secs",
+    "compact_after_updates",
+    "indexer_rpc_url",
+    "indexer_ws_url",
+    "indexer_hub",
+    "indexer_start_block",
+    "indexer_interval_secs",
+    "subgraph_url",
+    "chain_id",
+    "networks",
+];
+
+/// The settings that can differ between networks.
+pub const NETWORK_SETTINGS: &[&str] = &[
+    "edge_source",
+    "refresh_url",
+    "refresh_interval_secs",
+    "indexer_rpc_url",
+    "indexer_ws_url",
+    "indexer_hub",
+    "indexer_start_block",
+    "indexer_interval_secs",
+    "subgraph_url",
+];
+
+impl Config {
+    /// Changes a setting, given as a string.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        let invalid = |e: &dyn Display| format!("Invalid {name}: {value}: {e}");
+        match name {
+            "listen_at" => self.listen_at = value.to_string(),
+            "queue_size" => self.queue_size = value.parse().map_err(|e| invalid(&e))?,
+            "threads" => self.threads = value.parse().map_err(|e| invalid(&e))?,
+            "flow_workers" => self.flow_workers = value.parse().map_err(|e| invalid(&e))?,
+            "flow_queue_size" => self.flow_queue_size = value.parse().map_err(|e| invalid(&e))?,
+            "flow_timeout_ms" => {
+                self.flow_timeout = Duration::from_millis(value.parse().map_err(|e| invalid(&e))?)
+            }
+            "fast_workers" => self.fast_workers = value.parse().map_err(|e| invalid(&e))?,
+            "cache_size" => self.cache_size = value.parse().map_err(|e| invalid(&e))?,
+            "algorithm" => self.algorithm = value.parse().map_err(|e| invalid(&e))?,
+            "max_hops" => {
+                self.max_hops = match value {
+                    "" | "null" => None,
+                    value => Some(value.parse().map_err(|e| invalid(&e))?),
+                }
+            }
+            "reference_check" => self.reference_check = value.parse().map_err(|e| invalid(&e))?,
+            "max_value" => {
+                self.max_value = match value {
+                    "" | "null" => None,
+                    value => Some(value.parse().map_err(|e| invalid(&e))?),
+                }
+            }
+            "max_explored_percent" => {
+                let percent: u8 = value.parse().map_err(|e| invalid(&e))?;
+                if !(1..=100).contains(&percent) {
+                    return Err(invalid(&"expected a percentage from 1 to 100").into());
+                }
+                self.max_explored_percent = percent;
+            }
+            "max_decomposition_steps" => {
+                self.max_decomposition_steps = match value {
+                    "" | "null" => None,
+                    value => Some(value.parse().map_err(|e| invalid(&e))?),
+                }
+            }
+            "retained_versions" => {
+                self.retained_versions = value.parse().map_err(|e| invalid(&e))?
+            }
+            "edge_source" => self.edge_source = value.parse().map_err(|e| invalid(&e))?,
+            "refresh_url" => {
+                self.refresh_url = match value {
+                    "" | "null" => None,
+                    value => Some(value.to_string()),
+                }
+            }
+            "refresh_interval_secs" => {
+                let seconds: u64 = value.parse().map_err(|e| invalid(&e))?;
+                if seconds == 0 {
+                    return Err(invalid(&"expected at least one second").into());
+                }
+                self.refresh_interval = Duration::from_secs(seconds);
+            }
+            "data_dir" => {
+                self.data_dir = match value {
+                    "" | "null" => None,
+                    value => Some(value.to_string()),
+                }
+            }
+            "data_store" => self.data_store = value.parse().map_err(|e| invalid(&e))?,
+            "compact_after_updates" => {
+                self.compact_after_updates = value.parse().map_err(|e| invalid(&e))?
+            }
+            "archive_dir" => {
+                self.archive_dir = match value {
+                    "" | "null" => None,
+                    value => Some(value.to_string()),
+                }
+            }
+            "parquet_export_file" => {
+                self.parquet_export_file = match value {
+                    "" | "null" => None,
+                    value => Some(value.to_string()),
+                }
+            }
+            "parquet_export_interval_secs" => {
+                let seconds: u64 = value.parse().map_err(|e| invalid(&e))?;
+                if seconds == 0 {
+                    return Err(invalid(&"expected at least one second").into());
+                }
+                self.parquet_export_interval = Duration::from_secs(seconds);
+            }
+            "indexer_rpc_url" => {
+                self.indexer_rpc_url = match value {
+                    "" | "null" => None,
+                    value => Some(value.to_string()),
+                }
+            }
+            "indexer_ws_url" => {
+                self.indexer_ws_url = match value {
+                    "" | "null" => None,
+                    value => Some(value.to_string()),
+                }
+            }
+            "indexer_hub" => {
+                self.indexer_hub = value.parse().map_err(|e| invalid(&e))?;
+            }
+            "indexer_start_block" => {
+                self.indexer_start_block = value.parse().map_err(|e| invalid(&e))?
+            }
+            "indexer_interval_secs" => {
+                let seconds: u64 = value.parse().map_err(|e| invalid(&e))?;
+                if seconds == 0 {
+                    return Err(invalid(&"expected at least one second").into());
+                }
+                self.indexer_interval = Duration::from_secs(seconds);
+            }
+            "subgraph_url" => {
+                self.subgraph_url = match value {
+                    "" | "null" => None,
+                    value => Some(value.to_string()),
+                }
+            }
+            "chain_id" => self.chain_id = value.parse().map_err(|e| invalid(&e))?,
+            "networks" => {
+                let networks = json::parse(value).map_err(|e| invalid(&e))?;
+                if !networks.is_object() {
+                    return Err(invalid(&"expected an object mapping chain ids to settings").into());
+                }
+                let mut parsed = BTreeMap::new();
+                for (chain_id, settings) in networks.entries() {
+                    let chain_id: u64 = chain_id.parse().map_err(|e| invalid(&e))?;
+                    if !settings.is_object() {
+                        return Err(invalid(&"expected an object of settings").into());
+                    }
+                    let settings = settings
+                        .entries()
+                        .map(|(name, value)| (name.to_string(), value.to_string()))
+                        .collect::<Vec<_>>();
+                    // The settings are validated here and applied by `network`.
+                    let mut config = Config::default();
+                    for (name, value) in &settings {
+                        if !NETWORK_SETTINGS.contains(&name.as_str()) {
+                            return Err(format!(
+                                "Invalid networks: {name} cannot differ between networks"
+                            )
+                            .into());
+                        }
+                        config.set(name, value)?;
+                    }
+                    parsed.insert(chain_id, settings);
+                }
+                self.networks = parsed;
+            }
+            _ => return Err(format!("Unknown setting: {name}").into()),
+        }
+        Ok(())
+    }
+
+    /// The chain ids of all networks, the main one first.
+    pub fn chain_ids(&self) -> Vec<u64> {
+        let others = self.networks.keys().filter(|id| **id != self.chain_id);
+        [self.chain_id].into_iter().chain(others.copied()).collect()
+    }
+
+    /// The settings of the network with the given chain id, None if it is not configured.
+    pub fn network(&self, chain_id: u64) -> Option<Config> {
+        if chain_id == self.chain_id {
+            return Some(self.clone());
+        }
+        let mut config = self.clone();
+        for (name, value) in self.networks.get(&chain_id)? {
+            // Validated by `set`.
+            config.set(name, value).ok()?;
+        }
+        config.chain_id = chain_id;
+        config.networks.clear();
+        Some(config)
+    }
+
+    /// Applies the settings of a configuration file, a JSON object.
+    fn set_from_json(&mut self, settings: &JsonValue) -> Result<(), Box<dyn Error>> {
+        if !settings.is_object() {
+            return Err("Expected an object mapping settings to values.".into());
+        }
+        for (name, value) in settings.entries() {
+            self.set(name, &value.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Applies the settings found by `lookup`, the environment variables.
+    fn set_from_env(
+        &mut self,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        for name in SETTINGS {
+            if let Some(value) = lookup(&format!("PATHFINDER_{}", name.to_uppercase())) {
+                self.set(name, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The settings that differ from `other` but only take effect after a restart.
+    pub fn restart_required(&self, other: &Config) -> Vec<&'static str> {
+        [
+            ("listen_at", self.listen_at != other.listen_at),
+            ("queue_size", self.queue_size != other.queue_size),
+            ("threads", self.threads != other.threads),
+            ("flow_workers", self.flow_workers != other.flow_workers),
+            (
+                "flow_queue_size",
+                self.flow_queue_size != other.flow_queue_size,
+            ),
+            ("fast_workers", self.fast_workers != other.fast_workers),
+            ("data_dir", self.data_dir != other.data_dir),
+            ("data_store", self.data_store != other.data_store),
+            ("archive_dir", self.archive_dir != other.archive_dir),
+            (
+                "compact_after_updates",
+                self.compact_after_updates != other.compact_after_updates,
+            ),
+            ("indexer_hub", self.indexer_hub != other.indexer_hub),
+            ("chain_id", self.chain_id != other.chain_id),
+            ("networks", self.chain_ids() != other.chain_ids()),
+            (
+                "indexer_start_block",
+                self.indexer_start_block != other.indexer_start_block,
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(name, _)| name)
+        .collect()
+    }
+}
+
+/// Where the configuration comes from: an optional file, the environment and
+/// settings given on the command line, in increasing order of precedence.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSource {
+    pub file: Option<String>,
+    pub arguments: Vec<(&'static str, String)>,
+}
+
+impl ConfigSource {
+    /// A source with the file given by `PATHFINDER_CONFIG`, if any.
+    pub fn from_env() -> ConfigSource {
+        ConfigSource {
+            file: env::var("PATHFINDER_CONFIG").ok(),
+            arguments: vec![],
+        }
+    }
+
+    pub fn load(&self) -> Result<Config, Box<dyn Error>> {
+        let mut config = Config::default();
+        if let Some(file) = &self.file {
+            let text = fs::read_to_string(file)
+                .map_err(|e| format!("Error reading configuration from {file}: {e}"))?;
+            config.set_from_json(&json::parse(&text)?)?;
+        }
+        config.set_from_env(|name| env::var(name).ok())?;
+        for (name, value) in &self.arguments {
+            config.set(name, value)?;
+        }
+        Ok(config)
+    }
+}
+
+/// Calls `on_signal` on a separate thread whenever the process receives SIGHUP.
+pub fn on_reload(on_signal: impl Fn() + Send + 'static) -> io::Result<()> {
+    let mut signals = Signals::new([SIGHUP])?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            on_signal();
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn precedence() {
+        let mut config = Config::default();
+        config
+            .set_from_json(
+                &json::parse(r#"{"flow_workers": 8, "algorithm": "dinic", "max_hops": 5}"#)
+                    .unwrap(),
+            )
+            .unwrap();
+        config
+            .set_from_env(|name| (name == "PATHFINDER_FLOW_WORKERS").then(|| "6".to_string()))
+            .unwrap();
+        assert_eq!(config.flow_workers, 6);
+        assert_eq!(config.algorithm, FlowAlgorithm::Dinic);
+        assert_eq!(config.max_hops, Some(5));
+        config.set("reference_check", "true").unwrap();
+        assert!(config.reference_check);
+        assert!(config.set("reference_check", "yes").is_err());
+        config.set("max_value", "1000").unwrap();
+        assert_eq!(config.max_value, Some(U256::from(1000u128)));
+        assert!(config.set("max_explored_percent", "0").is_err());
+        config.set("max_decomposition_steps", "500").unwrap();
+        assert_eq!(config.max_decomposition_steps, Some(500));
+        config.set("retained_versions", "3").unwrap();
+        assert_eq!(config.retained_versions, 3);
+        config
+            .set("archive_dir", "/var/lib/pathfinder/archive")
+            .unwrap();
+        assert_eq!(
+            config.archive_dir.as_deref(),
+            Some("/var/lib/pathfinder/archive")
+        );
+        config.set("data_store", "sqlite").unwrap();
+        assert_eq!(config.data_store, DataStore::Sqlite);
+        config.set("data_store", "rocksdb").unwrap();
+        assert_eq!(config.data_store, DataStore::RocksDb);
+        assert!(config.set("data_store", "postgres").is_err());
+        config.set("parquet_export_interval_secs", "600").unwrap();
+        assert_eq!(config.parquet_export_interval, Duration::from_secs(600));
+        config.set("flow_timeout_ms", "1500").unwrap();
+        assert_eq!(config.flow_timeout, Duration::from_millis(1500));
+
+        assert!(config.set("threads", "many").is_err());
+        assert!(config.set("algorithm", "fastest").is_err());
+        assert!(config.set("refresh_interval_secs", "0").is_err());
+        config
+            .set("refresh_url", "https://example.com/edges.db")
+            .unwrap();
+        config.set("refresh_url", "null").unwrap();
+        assert_eq!(config.refresh_url, None);
+        assert!(config.set("indexer_hub", "0x29b9a7fbb8995b2423").is_err());
+        config.set("edge_source", "subgraph").unwrap();
+        assert_eq!(config.edge_source, EdgeSource::Subgraph);
+        assert!(config.set("edge_source", "chain").is_err());
+        assert!(config
+            .set_from_json(&json::parse(r#"{"thread": 4}"#).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn restart_required() {
+        let config = Config::default();
+        let mut changed = config.clone();
+        changed.cache_size = 10;
+        changed.flow_timeout = Duration::from_secs(1);
+        assert!(config.restart_required(&changed).is_empty());
+        changed.threads = 16;
+        changed.listen_at = "0.0.0.0:80".to_string();
+        assert_eq!(
+            config.restart_required(&changed),
+            vec!["listen_at", "threads"]
+        );
+    }
+    #[test]
+    fn networks() {
+        let mut config = Config::default();
+        config.set("refresh_interval_secs", "60").unwrap();
+        config
+            .set(
+                "networks",
+                r#"{"10200": {"edge_source": "subgraph", "subgraph_url": "http://chiado"}}"#,
+            )
+            .unwrap();
+        assert_eq!(config.chain_ids(), vec![GNOSIS_CHAIN_ID, 10200]);
+        let chiado = config.network(10200).unwrap();
+        assert_eq!(chiado.chain_id, 10200);
+        assert_eq!(chiado.edge_source, EdgeSource::Subgraph);
+        assert_eq!(chiado.subgraph_url.as_deref(), Some("http://chiado"));
+        // Other settings are those of the main network.
+        assert_eq!(chiado.refresh_interval, Duration::from_secs(60));
+        assert_eq!(config.network(GNOSIS_CHAIN_ID).unwrap(), config);
+        assert!(config.network(1).is_none());
+
+        assert!(config
+            .set("networks", r#"{"10200": {"threads": 2}}"#)
+            .is_err());
+        assert!(config.set("networks", r#"{"chiado": {}}"#).is_err());
+        assert!(config
+            .set("networks", r#"{"10200": {"edge_source": "x"}}"#)
+            .is_err());
+    }
+}
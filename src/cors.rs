@@ -0,0 +1,96 @@
+//! Cross-origin resource sharing, so that browsers can call the server directly.
+//!
+//! The environment variable `PATHFINDER_CORS_ORIGINS` sets the allowed origins:
+//! `*` for any origin or a comma-separated list such as
+//! `https://app.example.org,http://localhost:3000`. Without it, no CORS headers are sent.
+
+use std::env;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AllowedOrigins {
+    #[default]
+    None,
+    Any,
+    List(Vec<String>),
+}
+
+impl AllowedOrigins {
+    pub fn from_env() -> AllowedOrigins {
+        match env::var("PATHFINDER_CORS_ORIGINS") {
+            Ok(origins) => AllowedOrigins::parse(&origins),
+            Err(_) => AllowedOrigins::None,
+        }
+    }
+
+    pub fn parse(origins: &str) -> AllowedOrigins {
+        let origins = origins
+            .split(',')
+            .map(|origin| origin.trim().trim_end_matches('/'))
+            .filter(|origin| !origin.is_empty())
+            .collect::<Vec<_>>();
+        if origins.is_empty() {
+            AllowedOrigins::None
+        } else if origins.contains(&"*") {
+            AllowedOrigins::Any
+        } else {
+            AllowedOrigins::List(origins.into_iter().map(str::to_string).collect())
+        }
+    }
+
+    /// The headers to add to a response to a request from `origin`, each
+    /// terminated by CRLF. Empty if the origin is not allowed.
+    pub fn headers(&self, origin: Option<&str>) -> String {
+        match (self, origin) {
+            (AllowedOrigins::Any, _) => "Access-Control-Allow-Origin: *\r\n".to_string(),
+            (AllowedOrigins::List(origins), Some(origin))
+                if origins
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(origin)) =>
+            {
+                format!("Access-Control-Allow-Origin: {origin}\r\nVary: Origin\r\n")
+            }
+            (AllowedOrigins::List(_), _) => "Vary: Origin\r\n".to_string(),
+            (AllowedOrigins::None, _) => String::new(),
+        }
+    }
+
+    /// The response to a preflight (OPTIONS) request from `origin`.
+    pub fn preflight_response(&self, origin: Option<&str>) -> String {
+        let mut headers = self.headers(origin);
+        if headers.contains("Access-Control-Allow-Origin") {
+            headers += "Access-Control-Allow-Methods: GET, POST, OPTIONS\r\n";
+            headers += "Access-Control-Allow-Headers: Content-Type, X-API-Key\r\n";
+            headers += "Access-Control-Max-Age: 86400\r\n";
+        }
+        format!("HTTP/1.1 204 No Content\r\n{headers}\r\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn origins() {
+        assert_eq!(AllowedOrigins::parse(""), AllowedOrigins::None);
+        assert_eq!(
+            AllowedOrigins::parse("https://a.org, *"),
+            AllowedOrigins::Any
+        );
+        let origins = AllowedOrigins::parse("https://a.org/, http://localhost:3000");
+        assert_eq!(
+            origins.headers(Some("https://a.org")),
+            "Access-Control-Allow-Origin: https://a.org\r\nVary: Origin\r\n"
+        );
+        assert_eq!(origins.headers(Some("https://b.org")), "Vary: Origin\r\n");
+        assert_eq!(origins.headers(None), "Vary: Origin\r\n");
+        assert_eq!(AllowedOrigins::None.headers(Some("https://a.org")), "");
+        assert!(origins
+            .preflight_response(Some("http://localhost:3000"))
+            .contains("Access-Control-Allow-Methods: GET, POST, OPTIONS\r\n"));
+        assert_eq!(
+            origins.preflight_response(Some("https://b.org")),
+            "HTTP/1.1 204 No Content\r\nVary: Origin\r\n\r\n"
+        );
+    }
+}
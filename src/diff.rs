@@ -0,0 +1,199 @@
+//! Differences between two edge databases, e.g. to check a new snapshot before loading it.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+use json::JsonValue;
+
+use crate::types::edge::EdgeDB;
+use crate::types::{Address, Edge, U256};
+
+/// The edges added, removed and changed from one edge database to another,
+/// compared by their effective capacities.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct EdgeDiff {
+    pub added: Vec<Edge>,
+    pub removed: Vec<Edge>,
+    /// The changed edges with their old capacity and their new capacity.
+    pub changed: Vec<(Edge, U256)>,
+}
+
+/// The changes of the edges from or to an account.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AccountDiff {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+    /// The total capacity of the changed outgoing edges before and after.
+    pub capacity_before: U256,
+    pub capacity_after: U256,
+}
+
+impl AccountDiff {
+    fn total(&self) -> usize {
+        self.added + self.removed + self.changed
+    }
+}
+
+pub fn diff(old: &EdgeDB, new: &EdgeDB) -> EdgeDiff {
+    let key = |e: &Edge| (e.from, e.to, e.token);
+    let mut old_capacities = old
+        .effective_edges()
+        .into_iter()
+        .map(|e| (key(&e), e.capacity))
+        .collect::<HashMap<_, _>>();
+    let mut diff = EdgeDiff::default();
+    for edge in new.effective_edges() {
+        match old_capacities.remove(&key(&edge)) {
+            None => diff.added.push(edge),
+            Some(capacity) if capacity != edge.capacity => diff
+                .changed
+                .push((Edge { capacity, ..edge }, edge.capacity)),
+            Some(_) => {}
+        }
+    }
+    diff.removed = old_capacities
+        .into_iter()
+        .map(|((from, to, token), capacity)| Edge {
+            from,
+            to,
+            token,
+            capacity,
+        })
+        .collect();
+    diff.added.sort();
+    diff.removed.sort();
+    diff.changed.sort();
+    diff
+}
+
+impl EdgeDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// The changes aggregated per account. Each edge counts for both its ends.
+    pub fn by_address(&self) -> BTreeMap<Address, AccountDiff> {
+        let mut accounts = BTreeMap::new();
+        for edge in &self.added {
+            record(
+                &mut accounts,
+                edge,
+                |a| &mut a.added,
+                U256::ZERO,
+                edge.capacity,
+            );
+        }
+        for edge in &self.removed {
+            record(
+                &mut accounts,
+                edge,
+                |a| &mut a.removed,
+                edge.capacity,
+                U256::ZERO,
+            );
+        }
+        for (edge, capacity) in &self.changed {
+            record(
+                &mut accounts,
+                edge,
+                |a| &mut a.changed,
+                edge.capacity,
+                *capacity,
+            );
+        }
+        accounts
+    }
+
+    /// A summary with the counts of changed edges and the accounts with the most
+    /// changes, at most `limit` of them.
+    pub fn to_json(&self, limit: usize) -> JsonValue {
+        let mut accounts = self.by_address().into_iter().collect::<Vec<_>>();
+        accounts.sort_by_key(|(address, account)| (Reverse(account.total()), *address));
+        json::object! {
+            added: self.added.len(),
+            removed: self.removed.len(),
+            changed: self.changed.len(),
+            accountCount: accounts.len(),
+            accounts: accounts
+                .iter()
+                .take(limit)
+                .map(|(address, account)| json::object! {
+                    address: address.to_checksummed_hex(),
+                    added: account.added,
+                    removed: account.removed,
+                    changed: account.changed,
+                    capacityBefore: account.capacity_before.to_decimal(),
+                    capacityAfter: account.capacity_after.to_decimal(),
+                })
+                .collect::<Vec<_>>(),
+        }
+    }
+}
+
+/// Counts a change of `edge` for both its ends and adds its capacity before
+/// and after to the sender.
+fn record(
+    accounts: &mut BTreeMap<Address, AccountDiff>,
+    edge: &Edge,
+    count: fn(&mut AccountDiff) -> &mut usize,
+    before: U256,
+    after: U256,
+) {
+    *count(accounts.entry(edge.to).or_default()) += 1;
+    let from = accounts.entry(edge.from).or_default();
+    *count(from) += 1;
+    from.capacity_before = from.capacity_before.saturating_add(before);
+    from.capacity_after = from.capacity_after.saturating_add(after);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::edge::edge;
+
+    #[test]
+    fn diff_edges() {
+        let address = |i: u8| Address::from([i; 20]);
+        let old = EdgeDB::new(vec![
+            edge([1; 20], [2; 20], [1; 20], 10),
+            edge([2; 20], [3; 20], [2; 20], 10),
+            edge([3; 20], [1; 20], [3; 20], 5),
+        ]);
+        let new = EdgeDB::new(vec![
+            edge([1; 20], [2; 20], [1; 20], 10),
+            edge([2; 20], [3; 20], [2; 20], 20),
+            edge([1; 20], [3; 20], [1; 20], 5),
+        ]);
+        assert!(diff(&old, &old).is_empty());
+
+        let changes = diff(&old, &new);
+        assert_eq!(changes.added, vec![edge([1; 20], [3; 20], [1; 20], 5)]);
+        assert_eq!(changes.removed, vec![edge([3; 20], [1; 20], [3; 20], 5)]);
+        assert_eq!(
+            changes.changed,
+            vec![(edge([2; 20], [3; 20], [2; 20], 10), U256::from(20u128))]
+        );
+
+        let accounts = changes.by_address();
+        assert_eq!(
+            accounts[&address(3)],
+            AccountDiff {
+                added: 1,
+                removed: 1,
+                changed: 1,
+                capacity_before: U256::from(5u128),
+                capacity_after: U256::from(0u128),
+            }
+        );
+        assert_eq!(accounts[&address(2)].capacity_after, U256::from(20u128));
+
+        let summary = changes.to_json(1);
+        assert_eq!(summary["accountCount"], 3);
+        assert_eq!(
+            summary["accounts"][0]["address"],
+            address(3).to_checksummed_hex()
+        );
+        assert_eq!(summary["accounts"].len(), 1);
+    }
+}
@@ -0,0 +1,143 @@
+//! The errors of the crate. Functions that only do I/O return `io::Error`, which
+//! converts into `PathfinderError::Io`; everything else returns `PathfinderError`
+//! or a boxed error that is one of these, so that the server can map it to a
+//! JSON-RPC error code with `error_code`.
+
+use std::error::Error;
+use std::io;
+
+use crate::graph::FlowError;
+use crate::types::{ParseU256Error, TryFromU256Error};
+
+#[derive(Debug, thiserror::Error)]
+pub enum PathfinderError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// Malformed data, e.g. in an edge or safes file.
+    #[error("{0}")]
+    Parse(String),
+    /// The edges or a flow violate an invariant, which indicates a bug.
+    #[error("Inconsistent graph: {0}")]
+    Graph(String),
+    #[error(transparent)]
+    Flow(FlowError),
+    /// Invalid parameters of a request.
+    #[error("{0}")]
+    Rpc(String),
+    #[error(transparent)]
+    Request(#[from] RequestError),
+    /// A failed call to an Ethereum node or to the subgraph.
+    #[error("{0}")]
+    Chain(String),
+}
+
+impl PathfinderError {
+    /// The JSON-RPC error code, see `error_code`.
+    pub fn code(&self) -> i64 {
+        match self {
+            PathfinderError::Io(e) if e.kind() == io::ErrorKind::InvalidData => -32002,
+            PathfinderError::Io(_) => -32000,
+            PathfinderError::Parse(_) => -32002,
+            PathfinderError::Graph(_) => -32603,
+            PathfinderError::Flow(FlowError::Cancelled) => -32004,
+            PathfinderError::Flow(FlowError::LimitExceeded(_)) => -32007,
+            PathfinderError::Flow(_) => -32000,
+            PathfinderError::Rpc(_) => -32602,
+            PathfinderError::Request(e) => e.code(),
+            PathfinderError::Chain(_) => -32003,
+        }
+    }
+}
+
+/// Parameters of a request that are rejected before computing anything, with their own
+/// error codes so that clients can tell them apart.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum RequestError {
+    /// A malformed address, with the reason.
+    #[error("{0}")]
+    InvalidAddress(String),
+    /// A transfer from an account to itself.
+    #[error("The source and the sink are the same account {0}.")]
+    SameAccount(String),
+    /// A transfer of nothing.
+    #[error("The value is zero.")]
+    ZeroValue,
+}
+
+impl RequestError {
+    pub fn code(&self) -> i64 {
+        match self {
+            RequestError::InvalidAddress(_) => -32010,
+            RequestError::SameAccount(_) => -32011,
+            RequestError::ZeroValue => -32012,
+        }
+    }
+}
+
+impl From<FlowError> for PathfinderError {
+    fn from(error: FlowError) -> Self {
+        match error {
+            FlowError::Inconsistent(message) => PathfinderError::Graph(message),
+            error => PathfinderError::Flow(error),
+        }
+    }
+}
+
+impl From<ParseU256Error> for PathfinderError {
+    fn from(error: ParseU256Error) -> Self {
+        PathfinderError::Parse(error.to_string())
+    }
+}
+
+impl From<TryFromU256Error> for PathfinderError {
+    fn from(error: TryFromU256Error) -> Self {
+        PathfinderError::Parse(error.to_string())
+    }
+}
+
+/// The JSON-RPC error code of a (boxed) error: -32602 for invalid parameters (-32010 for
+/// malformed addresses, -32011 for transfers to the sender itself, -32012 for transfers of
+/// zero), -32002 for malformed data, -32003 for failed calls to a node, -32004 for
+/// computations that timed out, -32007 for computations that exceeded the limits of the
+/// server, -32603 for internal errors and -32000 for all other errors.
+pub fn error_code(error: &(dyn Error + 'static)) -> i64 {
+    if let Some(error) = error.downcast_ref::<PathfinderError>() {
+        error.code()
+    } else if let Some(error) = error.downcast_ref::<FlowError>() {
+        PathfinderError::from(error.clone()).code()
+    } else if error.is::<ParseU256Error>() {
+        -32002
+    } else if let Some(error) = error.downcast_ref::<io::Error>() {
+        match error.kind() {
+            io::ErrorKind::InvalidData => -32002,
+            _ => -32000,
+        }
+    } else {
+        -32000
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn error_codes() {
+        let boxed = |e: PathfinderError| Box::new(e) as Box<dyn Error>;
+        assert_eq!(
+            error_code(&*boxed(PathfinderError::Rpc("x".into()))),
+            -32602
+        );
+        assert_eq!(error_code(&*boxed(io::Error::other("x").into())), -32000);
+        let invalid = io::Error::new(io::ErrorKind::InvalidData, "x");
+        assert_eq!(error_code(&invalid), -32002);
+        assert_eq!(error_code(&FlowError::Cancelled), -32004);
+        assert_eq!(error_code(&FlowError::LimitExceeded("x".into())), -32007);
+        let inconsistent = PathfinderError::from(FlowError::Inconsistent("x".into()));
+        assert_eq!(inconsistent.to_string(), "Inconsistent graph: x");
+        assert_eq!(error_code(&*boxed(inconsistent)), -32603);
+        assert_eq!(error_code(&*Box::<dyn Error>::from("x")), -32000);
+        let same = PathfinderError::from(RequestError::SameAccount("0x1".into()));
+        assert_eq!(error_code(&*boxed(same)), -32011);
+    }
+}
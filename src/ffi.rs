@@ -0,0 +1,254 @@
+//! A C interface to the flow engine (feature `ffi`), so that services in other languages
+//! can link the library (`libpathfinder2.so`/`.a`) instead of running the server. The
+//! declarations are in `include/pathfinder2.h`.
+//!
+//! Graphs and results are owned by the library and have to be freed with
+//! `pathfinder2_free_graph` and `pathfinder2_free_result`. Strings are NUL-terminated
+//! UTF-8, addresses are given in hex, values in decimal.
+
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+use crate::graph::FlowOptions;
+use crate::types::{Address, U256};
+use crate::Graph;
+
+/// A transfer of a flow. Addresses are 20 bytes, the value is 32 bytes big-endian.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferStep {
+    pub from: [u8; 20],
+    pub to: [u8; 20],
+    pub token_owner: [u8; 20],
+    pub value: [u8; 32],
+}
+
+/// The result of `pathfinder2_compute_flow`. If `error` is not null, the computation
+/// failed, `flow` is zero and there are no steps.
+#[repr(C)]
+#[derive(Debug)]
+pub struct FlowResult {
+    /// 32 bytes big-endian.
+    pub flow: [u8; 32],
+    pub steps: *mut TransferStep,
+    pub step_count: usize,
+    pub error: *mut c_char,
+}
+
+/// Loads an edge file in any format, see `io::read_edges_file`. Returns null if the
+/// file cannot be loaded and stores the message in `error` unless it is null, to be
+/// freed with `pathfinder2_free_string`.
+///
+/// # Safety
+///
+/// `path` has to be a valid C string and `error` null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn pathfinder2_load_edges(
+    path: *const c_char,
+    error: *mut *mut c_char,
+) -> *mut Graph {
+    let graph = string(path).and_then(|path| Graph::load(path).map_err(|e| e.to_string()));
+    match graph {
+        Ok(graph) => Box::into_raw(Box::new(graph)),
+        Err(message) => {
+            if !error.is_null() {
+                *error = c_string(message);
+            }
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Computes the maximum flow from `from` to `to`, up to `value` (no limit if null).
+/// `max_distance` and `max_transfers` of zero mean no limit. Never returns null.
+///
+/// # Safety
+///
+/// `graph` has to be returned by `pathfinder2_load_edges` and not freed, the strings
+/// have to be valid C strings (`value` can be null).
+#[no_mangle]
+pub unsafe extern "C" fn pathfinder2_compute_flow(
+    graph: *const Graph,
+    from: *const c_char,
+    to: *const c_char,
+    value: *const c_char,
+    max_distance: u64,
+    max_transfers: u64,
+) -> *mut FlowResult {
+    let result = compute_flow(&*graph, from, to, value, max_distance, max_transfers);
+    let result = match result {
+        Ok((flow, steps)) => {
+            let steps = Box::into_raw(steps.into_boxed_slice());
+            FlowResult {
+                flow: flow.to_be_bytes(),
+                steps: steps as *mut TransferStep,
+                step_count: steps.len(),
+                error: ptr::null_mut(),
+            }
+        }
+        Err(message) => FlowResult {
+            flow: [0; 32],
+            steps: ptr::null_mut(),
+            step_count: 0,
+            error: c_string(message),
+        },
+    };
+    Box::into_raw(Box::new(result))
+}
+
+unsafe fn compute_flow(
+    graph: &Graph,
+    from: *const c_char,
+    to: *const c_char,
+    value: *const c_char,
+    max_distance: u64,
+    max_transfers: u64,
+) -> Result<(U256, Vec<TransferStep>), String> {
+    let from: Address = string(from)?.parse()?;
+    let to: Address = string(to)?.parse()?;
+    let options = FlowOptions {
+        requested_flow: match value.is_null() {
+            true => U256::MAX,
+            false => {
+                let value = string(value)?;
+                value
+                    .parse()
+                    .map_err(|e| format!("Invalid value {value}: {e}"))?
+            }
+        },
+        max_distance: Some(max_distance).filter(|d| *d != 0),
+        max_transfers: Some(max_transfers).filter(|t| *t != 0),
+        ..Default::default()
+    };
+    let result = graph
+        .compute_flow(&from, &to, &options)
+        .map_err(|e| e.to_string())?;
+    let steps = result
+        .transfers
+        .iter()
+        .map(|edge| TransferStep {
+            from: edge.from.to_bytes(),
+            to: edge.to.to_bytes(),
+            token_owner: edge.token.to_bytes(),
+            value: edge.capacity.to_be_bytes(),
+        })
+        .collect();
+    Ok((result.flow, steps))
+}
+
+/// # Safety
+///
+/// `result` has to be returned by `pathfinder2_compute_flow` and not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn pathfinder2_free_result(result: *mut FlowResult) {
+    if result.is_null() {
+        return;
+    }
+    let result = Box::from_raw(result);
+    if !result.steps.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            result.steps,
+            result.step_count,
+        )));
+    }
+    pathfinder2_free_string(result.error);
+}
+
+/// # Safety
+///
+/// `graph` has to be returned by `pathfinder2_load_edges` and not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn pathfinder2_free_graph(graph: *mut Graph) {
+    if !graph.is_null() {
+        drop(Box::from_raw(graph));
+    }
+}
+
+/// # Safety
+///
+/// `string` has to be an error message of the library and not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn pathfinder2_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+unsafe fn string<'a>(string: *const c_char) -> Result<&'a str, String> {
+    if string.is_null() {
+        return Err("Missing argument".to_string());
+    }
+    CStr::from_ptr(string)
+        .to_str()
+        .map_err(|e| format!("Invalid UTF-8: {e}"))
+}
+
+fn c_string(message: String) -> *mut c_char {
+    CString::new(message.replace('\0', " ")).unwrap().into_raw()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::write_edges_binary;
+    use crate::types::edge::edge;
+    use crate::types::edge::EdgeDB;
+
+    #[test]
+    fn flow_over_c_abi() {
+        let (a, b, c) = (
+            Address::from([1; 20]),
+            Address::from([2; 20]),
+            Address::from([3; 20]),
+        );
+        let path = std::env::temp_dir().join(format!("ffi_{}.dat", std::process::id()));
+        let path = path.to_str().unwrap();
+        write_edges_binary(
+            &EdgeDB::new(vec![edge(a, b, a, 10), edge(b, c, b, 7)]),
+            path,
+        )
+        .unwrap();
+        let c_str = |s: &str| CString::new(s).unwrap();
+
+        unsafe {
+            let mut error = ptr::null_mut();
+            let graph = pathfinder2_load_edges(c_str(path).as_ptr(), &mut error);
+            std::fs::remove_file(path).unwrap();
+            assert!(!graph.is_null() && error.is_null());
+
+            let (from, to) = (c_str(&a.to_string()), c_str(&c.to_string()));
+            let value = c_str("5");
+            let result =
+                pathfinder2_compute_flow(graph, from.as_ptr(), to.as_ptr(), value.as_ptr(), 0, 0);
+            assert!((*result).error.is_null());
+            assert_eq!(U256::from_be_bytes((*result).flow), U256::from(5u128));
+            let steps = std::slice::from_raw_parts((*result).steps, (*result).step_count);
+            assert_eq!(steps.len(), 2);
+            assert_eq!(steps[1].token_owner, b.to_bytes());
+            assert_eq!(U256::from_be_bytes(steps[1].value), U256::from(5u128));
+            pathfinder2_free_result(result);
+
+            let result = pathfinder2_compute_flow(
+                graph,
+                c_str("0x12").as_ptr(),
+                to.as_ptr(),
+                ptr::null(),
+                0,
+                0,
+            );
+            assert_eq!(
+                CStr::from_ptr((*result).error).to_str(),
+                Ok("Invalid address: 0x12")
+            );
+            assert_eq!((*result).step_count, 0);
+            pathfinder2_free_result(result);
+            pathfinder2_free_graph(graph);
+
+            assert!(
+                pathfinder2_load_edges(c_str("/nonexistent.dat").as_ptr(), &mut error).is_null()
+            );
+            assert!(!error.is_null());
+            pathfinder2_free_string(error);
+        }
+    }
+}
@@ -1,15 +1,43 @@
-use crate::graph::Node;
+use crate::graph::{FlowError, Node};
 use crate::types::edge::EdgeDB;
-use crate::types::{Edge, U256};
-use std::cmp::{max, Reverse};
-use std::collections::HashMap;
+use crate::types::{Address, Edge, U256};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 
 pub struct Adjacencies<'a> {
     edges: &'a EdgeDB,
+    token_filter: TokenFilter,
+    /// If set, only edges updated at or after this block are used.
+    min_block: Option<u64>,
+    /// Accounts that neither send nor receive tokens.
+    excluded_accounts: HashSet<Address>,
+    /// The maximum number of accounts a search reaches, see `check_explored`.
+    max_accounts: Option<usize>,
     lazy_adjacencies: HashMap<Node, HashMap<Node, U256>>,
+    /// The number of accounts in `lazy_adjacencies`.
+    explored_accounts: usize,
     capacity_adjustments: HashMap<Node, HashMap<Node, U256>>,
 }
 
+/// Restricts the tokens that can be transferred.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TokenFilter {
+    /// If set, only these tokens are transferred.
+    pub accepted: Option<HashSet<Address>>,
+    /// These tokens are never transferred.
+    pub excluded: HashSet<Address>,
+}
+
+impl TokenFilter {
+    pub fn accepts(&self, token: &Address) -> bool {
+        !self.excluded.contains(token)
+            && self
+                .accepted
+                .as_ref()
+                .is_none_or(|accepted| accepted.contains(token))
+    }
+}
+
 // fn pseudo_node(edge: Edge) -> Node {
 //     Node::TokenEdge(edge.from, edge.token)
 // }
@@ -33,13 +61,76 @@ impl<'a> Adjacencies<'a> {
     pub fn new(edges: &'a EdgeDB) -> Self {
         Adjacencies {
             edges,
+            token_filter: TokenFilter::default(),
+            min_block: None,
+            excluded_accounts: HashSet::new(),
+            max_accounts: None,
             lazy_adjacencies: HashMap::new(),
+            explored_accounts: 0,
             capacity_adjustments: HashMap::new(),
         }
     }
 
+    /// Only uses edges of tokens accepted by the filter.
+    pub fn with_token_filter(mut self, token_filter: &TokenFilter) -> Self {
+        self.token_filter = token_filter.clone();
+        self
+    }
+
+    /// Only uses edges updated at or after `min_block`, if given.
+    pub fn with_min_block(mut self, min_block: Option<u64>) -> Self {
+        self.min_block = min_block;
+        self
+    }
+
+    /// Does not use any edges from or to the given accounts.
+    pub fn with_excluded_accounts(mut self, accounts: HashSet<Address>) -> Self {
+        self.excluded_accounts = accounts;
+        self
+    }
+
+    /// Limits the number of accounts a search reaches, if given.
+    pub fn with_max_accounts(mut self, max_accounts: Option<usize>) -> Self {
+        self.max_accounts = max_accounts;
+        self
+    }
+
+    /// Fails with `FlowError::LimitExceeded` if a search reached more `accounts` than
+    /// the maximum.
+    pub fn check_explored(&self, accounts: usize) -> Result<(), FlowError> {
+        match self.max_accounts {
+            Some(max) if accounts > max => Err(FlowError::LimitExceeded(format!(
+                "The search reached more than the maximum of {max} accounts."
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// The number of accounts whose outgoing edges were read by the lazy search.
+    pub fn explored_accounts(&self) -> usize {
+        self.explored_accounts
+    }
+
     pub fn outgoing_edges_sorted_by_capacity(&mut self, from: &Node) -> Vec<(Node, U256)> {
-        let mut adjacencies = self.adjacencies_from(from);
+        let adjacencies = self.adjacencies_from(from);
+        self.adjusted_and_sorted(from, adjacencies)
+    }
+
+    /// Like `outgoing_edges_sorted_by_capacity`, but does not cache the
+    /// adjacencies, so it can be called for many nodes in parallel.
+    pub fn outgoing_edges_sorted_by_capacity_uncached(&self, from: &Node) -> Vec<(Node, U256)> {
+        let adjacencies = match self.lazy_adjacencies.get(from) {
+            Some(adjacencies) => adjacencies.clone(),
+            None => self.compute_adjacencies(from),
+        };
+        self.adjusted_and_sorted(from, adjacencies)
+    }
+
+    fn adjusted_and_sorted(
+        &self,
+        from: &Node,
+        mut adjacencies: HashMap<Node, U256>,
+    ) -> Vec<(Node, U256)> {
         if let Some(adjustments) = self.capacity_adjustments.get(from) {
             for (node, c) in adjustments {
                 *adjacencies.entry(node.clone()).or_default() += *c;
@@ -47,12 +138,14 @@ impl<'a> Adjacencies<'a> {
         }
         let mut result = adjacencies
             .into_iter()
-            .filter(|(_, cap)| *cap != U256::from(0))
+            .filter(|(_, cap)| !cap.is_zero())
             .collect::<Vec<(Node, U256)>>();
         result.sort_unstable_by_key(|(addr, capacity)| (Reverse(*capacity), addr.clone()));
         result
     }
 
+    /// Adjusts the residual capacity of an edge. The adjustments are accumulated
+    /// with wrapping arithmetic, so a reduction can be expressed as `-amount`.
     pub fn adjust_capacity(&mut self, from: &Node, to: &Node, adjustment: U256) {
         *self
             .capacity_adjustments
@@ -66,60 +159,70 @@ impl<'a> Adjacencies<'a> {
     pub fn is_adjacent(&mut self, from: &Node, to: &Node) -> bool {
         // TODO More efficiently?
         if let Some(capacity) = self.adjacencies_from(from).get(to) {
-            *capacity > U256::from(0)
+            *capacity > U256::from(0u128)
         } else {
             false
         }
     }
 
     fn adjacencies_from(&mut self, from: &Node) -> HashMap<Node, U256> {
-        self.lazy_adjacencies
-            .entry(from.clone())
-            .or_insert_with(|| {
-                let mut result: HashMap<Node, U256> = HashMap::new();
-                // Plain edges are (from, to, token) labeled with capacity
-                match from {
-                    Node::Node(from) => {
-                        for edge in self.edges.outgoing(from) {
-                            // One edge from "from" to "from x token" with a capacity
-                            // as the max over all "to" addresses (the balance of the sender)
-                            result
-                                .entry(balance_node(edge))
-                                .and_modify(|c| {
-                                    if edge.capacity > *c {
-                                        *c = edge.capacity;
-                                    }
-                                })
-                                .or_insert(edge.capacity);
-                        }
-                    }
-                    Node::BalanceNode(from, token) => {
-                        for edge in self.edges.outgoing(from) {
-                            // The actual capacity of the edge / the send limit.
-                            if edge.from == *from && edge.token == *token {
-                                result.insert(trust_node(edge), edge.capacity);
-                            }
-                        }
+        if !self.lazy_adjacencies.contains_key(from) {
+            let adjacencies = self.compute_adjacencies(from);
+            self.lazy_adjacencies.insert(from.clone(), adjacencies);
+            self.explored_accounts += matches!(from, Node::Node(_)) as usize;
+        }
+        self.lazy_adjacencies[from].clone()
+    }
+
+    fn compute_adjacencies(&self, from: &Node) -> HashMap<Node, U256> {
+        let mut result: HashMap<Node, U256> = HashMap::new();
+        // Plain edges are (from, to, token) labeled with capacity
+        match from {
+            Node::Node(from) | Node::TrustNode(from, _)
+                if self.excluded_accounts.contains(from) => {}
+            Node::Node(from) => {
+                for edge in self
+                    .edges
+                    .outgoing_updated_since(from, self.min_block)
+                    .into_iter()
+                    .filter(|edge| self.token_filter.accepts(&edge.token))
+                {
+                    // One edge from "from" to "from x token" with a capacity
+                    // as the max over all "to" addresses (the balance of the sender)
+                    result
+                        .entry(balance_node(&edge))
+                        .and_modify(|c| *c = (*c).max(edge.capacity))
+                        .or_insert(edge.capacity);
+                }
+            }
+            Node::BalanceNode(from, token) => {
+                for edge in self.edges.outgoing_updated_since(from, self.min_block) {
+                    // The actual capacity of the edge / the send limit.
+                    if edge.from == *from && edge.token == *token {
+                        result.insert(trust_node(&edge), edge.capacity);
                     }
-                    Node::TrustNode(to, token) => {
-                        let is_return_to_owner = *to == *token;
-                        // If token is to's token: send back to owner, infinite capacity.
-                        // Otherwise, the max of the incoming edges (the trust limit)
-                        let mut capacity = U256::from(0);
-                        for edge in self.edges.incoming(to) {
-                            if edge.token == *token {
-                                if is_return_to_owner {
-                                    capacity += edge.capacity
-                                } else {
-                                    capacity = max(capacity, edge.capacity)
-                                }
-                            }
-                            result.insert(Node::Node(*to), capacity);
+                }
+            }
+            Node::TrustNode(to, token) => {
+                // Wrappers hold any amount of the token they wrap as collateral.
+                let is_return_to_owner =
+                    *to == *token || self.edges.wrapped_token(to) == Some(*token);
+                // If token is to's token: send back to owner, infinite capacity.
+                // Otherwise, the max of the incoming edges (the trust limit)
+                let mut capacity = U256::from(0u128);
+                for edge in self.edges.incoming_updated_since(to, self.min_block) {
+                    if edge.token == *token {
+                        if is_return_to_owner {
+                            capacity = capacity.saturating_add(edge.capacity)
+                        } else {
+                            capacity = capacity.max(edge.capacity)
                         }
                     }
+                    result.insert(Node::Node(*to), capacity);
                 }
-                result
-            })
-            .clone()
+            }
+            Node::SuperSink | Node::SuperSource => {}
+        }
+        result
     }
 }
@@ -0,0 +1,77 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Stops a flow computation early. The flow algorithms check the token in
+/// their loops and stop once it is cancelled or its deadline has passed.
+/// Clones share the cancellation, so a computation can be cancelled from
+/// another thread.
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+}
+
+impl Cancellation {
+    /// A token that is cancelled automatically after `timeout`.
+    pub fn with_timeout(timeout: Duration) -> Cancellation {
+        Cancellation::with_deadline(Instant::now() + timeout)
+    }
+
+    pub fn with_deadline(deadline: Instant) -> Cancellation {
+        Cancellation {
+            deadline: Some(deadline),
+            ..Default::default()
+        }
+    }
+
+    /// The earlier of the deadline of this token and `deadline`, sharing the
+    /// cancellation with this token.
+    pub fn until(&self, deadline: Instant) -> Cancellation {
+        Cancellation {
+            cancelled: self.cancelled.clone(),
+            deadline: Some(self.deadline.map_or(deadline, |d| d.min(deadline))),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Tokens are equal if they share the cancellation and have the same deadline.
+impl PartialEq for Cancellation {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.cancelled, &other.cancelled) && self.deadline == other.deadline
+    }
+}
+
+impl Eq for Cancellation {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cancel() {
+        let token = Cancellation::default();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        token.cancel();
+        assert!(clone.is_cancelled());
+        assert_ne!(token, Cancellation::default());
+
+        let expired = Cancellation::default().until(Instant::now());
+        assert!(expired.is_cancelled());
+        let later = Cancellation::with_timeout(Duration::from_secs(60));
+        assert!(!later.is_cancelled());
+        assert!(later.until(Instant::now()).is_cancelled());
+    }
+}
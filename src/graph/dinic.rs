@@ -0,0 +1,149 @@
+use crate::graph::cancellation::Cancellation;
+use crate::graph::flow::{checked_add, FlowError};
+use crate::graph::network::Network;
+use crate::types::U256;
+use std::collections::VecDeque;
+
+/// Computes a maximum flow using Dinic's algorithm: Repeatedly builds the
+/// level graph of shortest paths with a breadth-first search and saturates it
+/// with a blocking flow.
+///
+/// If `max_path_length` is given, only paths with at most that many arcs are used.
+/// Returns the value of the flow, the flow itself is left in the
+/// residual capacities of the network and is free of cycles.
+pub fn max_flow(network: &mut Network, max_path_length: Option<usize>) -> Result<U256, FlowError> {
+    max_flow_with_progress(
+        network,
+        max_path_length,
+        &Cancellation::default(),
+        &mut |_| true,
+    )
+}
+
+/// Like `max_flow`, but calls `progress` with the value of the flow after each
+/// phase and stops early if it returns false or if `cancellation` is cancelled.
+/// The flow is valid after each augmenting path.
+pub fn max_flow_with_progress(
+    network: &mut Network,
+    max_path_length: Option<usize>,
+    cancellation: &Cancellation,
+    progress: &mut dyn FnMut(U256) -> bool,
+) -> Result<U256, FlowError> {
+    let sink = match network.sink {
+        Some(sink) => sink,
+        None => return Ok(U256::from(0u128)),
+    };
+    let mut flow = U256::from(0u128);
+    while let Some(mut level) = levels(network, sink, |_, _| true) {
+        if let Some(max) = max_path_length {
+            if level[sink] > max {
+                break;
+            }
+        }
+        let new_flow = blocking_flow(
+            network,
+            sink,
+            &mut level,
+            U256::MAX,
+            |_, _| true,
+            cancellation,
+        )?;
+        flow = checked_add(flow, new_flow)?;
+        if !progress(flow) || cancellation.is_cancelled() {
+            break;
+        }
+    }
+    network.cancel_cycles();
+    Ok(flow)
+}
+
+/// Breadth-first search from the source in the residual network,
+/// only using arcs for which `admissible` returns true.
+/// Returns None if the sink is not reachable.
+pub(super) fn levels(
+    network: &Network,
+    sink: usize,
+    admissible: impl Fn(&Network, usize) -> bool,
+) -> Option<Vec<usize>> {
+    let mut level = vec![usize::MAX; network.node_count()];
+    level[network.source] = 0;
+    let mut queue = VecDeque::from([network.source]);
+    while let Some(u) = queue.pop_front() {
+        if u == sink {
+            return Some(level);
+        }
+        for arc in &network.outgoing[u] {
+            let v = network.arcs[*arc].to;
+            if level[v] == usize::MAX
+                && !network.arcs[*arc].residual.is_zero()
+                && admissible(network, *arc)
+            {
+                level[v] = level[u] + 1;
+                queue.push_back(v);
+            }
+        }
+    }
+    None
+}
+
+/// Saturates all shortest paths in the level graph using depth-first search,
+/// sending at most `limit`. Stops after the current path if `cancellation` is cancelled.
+/// Nodes that cannot reach the sink are removed by setting their level to `usize::MAX`.
+pub(super) fn blocking_flow(
+    network: &mut Network,
+    sink: usize,
+    level: &mut [usize],
+    limit: U256,
+    admissible: impl Fn(&Network, usize) -> bool,
+    cancellation: &Cancellation,
+) -> Result<U256, FlowError> {
+    let mut flow = U256::from(0u128);
+    let mut current = vec![0usize; network.node_count()];
+    let mut path: Vec<usize> = vec![];
+    let mut u = network.source;
+    loop {
+        if u == sink {
+            let amount = path
+                .iter()
+                .map(|arc| network.arcs[*arc].residual)
+                .min()
+                .unwrap()
+                .min(limit - flow);
+            for arc in &path {
+                network.push(*arc, amount);
+            }
+            flow = checked_add(flow, amount)?;
+            if flow == limit || cancellation.is_cancelled() {
+                return Ok(flow);
+            }
+            path.clear();
+            u = network.source;
+            continue;
+        }
+        let mut advanced = false;
+        while current[u] < network.outgoing[u].len() {
+            let arc = network.outgoing[u][current[u]];
+            let v = network.arcs[arc].to;
+            if !network.arcs[arc].residual.is_zero()
+                && level[v] != usize::MAX
+                && level[v] == level[u] + 1
+                && admissible(network, arc)
+            {
+                path.push(arc);
+                u = v;
+                advanced = true;
+                break;
+            }
+            current[u] += 1;
+        }
+        if !advanced {
+            if u == network.source {
+                return Ok(flow);
+            }
+            level[u] = usize::MAX;
+            let arc = path.pop().unwrap();
+            u = network.arc_from(arc);
+            current[u] += 1;
+        }
+    }
+}
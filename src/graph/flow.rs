@@ -1,11 +1,199 @@
-use crate::graph::adjacencies::Adjacencies;
-use crate::graph::{as_trust_node, Node};
+use crate::graph::adjacencies::{Adjacencies, TokenFilter};
+use crate::graph::cancellation::Cancellation;
+use crate::graph::network::{Network, NetworkStatistics};
+use crate::graph::{as_trust_node, dinic, min_cost, node_as_address, push_relabel, Node};
 use crate::types::edge::EdgeDB;
 use crate::types::{Address, Edge, U256};
-use std::cmp::min;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::collections::{HashMap, VecDeque};
 use std::fmt::Write;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum FlowError {
+    /// A sum of capacities or flows does not fit into 256 bits.
+    #[error("Capacity overflow during flow computation")]
+    CapacityOverflow,
+    /// The computation was cancelled before a result was found.
+    #[error("Flow computation cancelled or timed out")]
+    Cancelled,
+    /// The flow violates an invariant, which indicates a bug.
+    #[error("Inconsistent flow: {0}")]
+    Inconsistent(String),
+    /// The computation was stopped because it exceeded one of the `FlowLimits`.
+    #[error("{0}")]
+    LimitExceeded(String),
+}
+
+pub(super) fn checked_add(a: U256, b: U256) -> Result<U256, FlowError> {
+    a.checked_add(b).ok_or(FlowError::CapacityOverflow)
+}
+
+pub(super) fn checked_sub(a: U256, b: U256) -> Result<U256, FlowError> {
+    a.checked_sub(b).ok_or(FlowError::CapacityOverflow)
+}
+
+/// The algorithm used to find the maximum flow before it is pruned
+/// and decomposed into transfers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FlowAlgorithm {
+    /// Repeated breadth-first search for shortest augmenting paths
+    /// on a lazily explored graph.
+    #[default]
+    FordFulkerson,
+    /// Highest-label push-relabel with gap heuristic on the explicit
+    /// network of all nodes reachable from the source.
+    /// Performs better on dense graphs and for large requested values.
+    /// With `max_distance`, only arcs that lie on a short enough path are used,
+    /// but the flow can still combine them into longer paths.
+    PushRelabel,
+    /// Dinic's algorithm with blocking flows on the explicit network of all
+    /// nodes reachable from the source. Performs well on large graphs
+    /// and for large requested values.
+    Dinic,
+    /// Min-cost flow where every transfer costs one per unit of flow,
+    /// i.e. prefers short transfer chains which need less gas on-chain.
+    /// Only computes as much flow as requested, so no pruning is needed.
+    MinCost,
+}
+
+impl FlowAlgorithm {
+    pub const ALL: [FlowAlgorithm; 4] = [
+        FlowAlgorithm::FordFulkerson,
+        FlowAlgorithm::PushRelabel,
+        FlowAlgorithm::Dinic,
+        FlowAlgorithm::MinCost,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FlowAlgorithm::FordFulkerson => "ford_fulkerson",
+            FlowAlgorithm::PushRelabel => "push_relabel",
+            FlowAlgorithm::Dinic => "dinic",
+            FlowAlgorithm::MinCost => "min_cost",
+        }
+    }
+}
+
+impl FromStr for FlowAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ford_fulkerson" => Ok(FlowAlgorithm::FordFulkerson),
+            "push_relabel" => Ok(FlowAlgorithm::PushRelabel),
+            "dinic" => Ok(FlowAlgorithm::Dinic),
+            "min_cost" => Ok(FlowAlgorithm::MinCost),
+            _ => Err(format!("Unknown flow algorithm: {s}")),
+        }
+    }
+}
+
+/// Options of a flow computation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowOptions {
+    /// The value to transfer, the flow is reduced to this value if it is larger.
+    pub requested_flow: U256,
+    /// The maximum number of hops of each path.
+    pub max_distance: Option<u64>,
+    /// The maximum number of transfers, transfers are removed after the flow is computed.
+    pub max_transfers: Option<u64>,
+    pub algorithm: FlowAlgorithm,
+    pub token_filter: TokenFilter,
+    /// If set, only edges updated at most that many blocks before the latest
+    /// update of the edge database are used. Edges without a block number are not used.
+    pub max_staleness_blocks: Option<u64>,
+    /// If set, stopped accounts are not used as intermediate hops. The source can
+    /// still send the tokens it holds and the sink still receives tokens.
+    pub exclude_stopped: bool,
+    /// Stops the search early, see `compute_flow_anytime`.
+    pub cancellation: Cancellation,
+    pub limits: FlowLimits,
+}
+
+/// Limits of the work of a flow computation, which fails with `FlowError::LimitExceeded`
+/// once it exceeds one of them, to stop runaway queries on shared servers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FlowLimits {
+    /// The maximum number of accounts the search reaches.
+    pub max_explored_accounts: Option<usize>,
+    /// The maximum number of transfers the flow is decomposed into, before transfers
+    /// between the same accounts are merged.
+    pub max_decomposition_steps: Option<usize>,
+}
+
+impl Default for FlowOptions {
+    fn default() -> Self {
+        FlowOptions {
+            requested_flow: U256::MAX,
+            max_distance: None,
+            max_transfers: None,
+            algorithm: FlowAlgorithm::default(),
+            token_filter: TokenFilter::default(),
+            max_staleness_blocks: None,
+            exclude_stopped: false,
+            cancellation: Cancellation::default(),
+            limits: FlowLimits::default(),
+        }
+    }
+}
+
+/// The result of a flow computation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowResult {
+    pub flow: U256,
+    /// The transfers in an order in which they can be executed.
+    pub transfers: Vec<Edge>,
+    /// The size of the network before and after pruning, None for `FordFulkerson`.
+    pub statistics: Option<NetworkStatistics>,
+    /// If the maximum flow is less than the requested value, the limits that prevent
+    /// a larger flow (a minimum cut). None if the flow is not maximal because the search
+    /// was restricted (e.g. by `max_distance`) or stopped early.
+    pub min_cut: Option<Vec<Bottleneck>>,
+}
+
+/// A saturated arc of the flow network that is part of a minimum cut.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Bottleneck {
+    /// `account` cannot send more than `capacity` of `token` tokens, i.e. its balance.
+    Balance {
+        account: Address,
+        token: Address,
+        capacity: U256,
+    },
+    /// The transfer of `token` tokens from `from` to `to` is limited to `capacity`.
+    Edge(Edge),
+    /// `account` does not accept more than `capacity` of `token` tokens, i.e. its trust limit.
+    Trust {
+        account: Address,
+        token: Address,
+        capacity: U256,
+    },
+}
+
+impl Bottleneck {
+    fn from_arc(from: &Node, to: &Node, capacity: U256) -> Bottleneck {
+        match (from, to) {
+            (Node::Node(account), Node::BalanceNode(_, token)) => Bottleneck::Balance {
+                account: *account,
+                token: *token,
+                capacity,
+            },
+            (Node::BalanceNode(from, token), Node::TrustNode(to, _)) => Bottleneck::Edge(Edge {
+                from: *from,
+                to: *to,
+                token: *token,
+                capacity,
+            }),
+            (Node::TrustNode(account, token), Node::Node(_)) => Bottleneck::Trust {
+                account: *account,
+                token: *token,
+                capacity,
+            },
+            _ => panic!("Unexpected arc from {from} to {to}"),
+        }
+    }
+}
 
 pub fn compute_flow(
     source: &Address,
@@ -14,74 +202,611 @@ pub fn compute_flow(
     requested_flow: U256,
     max_distance: Option<u64>,
     max_transfers: Option<u64>,
-) -> (U256, Vec<Edge>) {
+    algorithm: FlowAlgorithm,
+) -> Result<(U256, Vec<Edge>), FlowError> {
+    compute_flow_with_options(
+        source,
+        sink,
+        edges,
+        &FlowOptions {
+            requested_flow,
+            max_distance,
+            max_transfers,
+            algorithm,
+            ..FlowOptions::default()
+        },
+    )
+    .map(|result| (result.flow, result.transfers))
+}
+
+/// Like `compute_flow`, but also returns the size of the network the flow was
+/// computed on before and after pruning it to the nodes that lie on a path from
+/// the source to the sink and the minimum cut if not enough can be transferred.
+/// The lazy search of `FordFulkerson` does not build a network, so there are no
+/// statistics for it.
+pub fn compute_flow_with_options(
+    source: &Address,
+    sink: &Address,
+    edges: &EdgeDB,
+    options: &FlowOptions,
+) -> Result<FlowResult, FlowError> {
+    compute_flow_anytime(source, sink, edges, options, |_| true)
+}
+
+/// Like `compute_flow_with_options`, but calls `progress` with the value of the best
+/// flow found so far whenever it improves. If `progress` returns false or `options.cancellation`
+/// is cancelled, the search stops and the transfers for the best flow found so far are returned.
+///
+/// `FordFulkerson` reports progress after each augmenting path, `Dinic` and `MinCost`
+/// after each phase, but they check the cancellation after each augmenting path.
+/// `PushRelabel` only has a valid flow at the end and never stops early because of
+/// `progress`, if it is cancelled, `FlowError::Cancelled` is returned.
+pub fn compute_flow_anytime(
+    source: &Address,
+    sink: &Address,
+    edges: &EdgeDB,
+    options: &FlowOptions,
+    mut progress: impl FnMut(U256) -> bool,
+) -> Result<FlowResult, FlowError> {
+    let search = tracing::info_span!("search").entered();
+    let cancellation = &options.cancellation;
+    let mut progress = |flow| progress(flow) && !cancellation.is_cancelled();
+    let min_block = options
+        .max_staleness_blocks
+        .map(|max| edges.block_number().unwrap_or_default().saturating_sub(max));
+    let excluded_accounts = match options.exclude_stopped {
+        true => edges
+            .balances()
+            .stopped()
+            .filter(|account| *account != source && *account != sink)
+            .copied()
+            .collect(),
+        false => HashSet::new(),
+    };
+    let mut adjacencies = Adjacencies::new(edges)
+        .with_token_filter(&options.token_filter)
+        .with_min_block(min_block)
+        .with_excluded_accounts(excluded_accounts)
+        .with_max_accounts(options.limits.max_explored_accounts);
+    let (flow, used_edges, statistics, reachable) = match options.algorithm {
+        FlowAlgorithm::FordFulkerson => {
+            let (flow, used_edges) = augmenting_path_flow(
+                source,
+                sink,
+                &mut adjacencies,
+                options.max_distance,
+                &mut progress,
+            )?;
+            // A flow that was stopped early is not maximal.
+            let reachable = (flow < options.requested_flow && !cancellation.is_cancelled())
+                .then(|| residual_reachable(source, &mut adjacencies));
+            (flow, used_edges, None, reachable)
+        }
+        algorithm => {
+            let mut network = Network::build(source, sink, &adjacencies, options.max_distance);
+            adjacencies.check_explored(network.accounts())?;
+            let statistics = network.prune();
+            let flow = network_flow(
+                &mut network,
+                algorithm,
+                options.requested_flow,
+                options.max_distance,
+                0,
+                cancellation,
+                &mut progress,
+            )?;
+            // With `max_distance`, arcs on long paths are removed from the network,
+            // so a cut in the network is not necessarily a cut in the graph.
+            let reachable = (flow < options.requested_flow
+                && options.max_distance.is_none()
+                && !cancellation.is_cancelled())
+            .then(|| network.residual_reachable());
+            (flow, network.used_edges(), Some(statistics), reachable)
+        }
+    };
+
+    search.exit();
+    tracing::info!("Max flow: {}", flow.to_decimal());
+
+    let _decomposition = tracing::info_span!("decomposition").entered();
+    let min_cut = reachable.and_then(|reachable| min_cut(sink, &reachable, &used_edges));
+    let (flow, transfers) = transfers_from_flow(
+        source,
+        sink,
+        flow,
+        used_edges,
+        options.requested_flow,
+        options.max_transfers,
+        options.limits.max_decomposition_steps,
+    )?;
+    Ok(FlowResult {
+        flow,
+        transfers,
+        statistics,
+        min_cut,
+    })
+}
+
+/// Returns the arcs from the nodes reachable from the source in the residual network
+/// of the flow to all other nodes, which are saturated and form a minimum cut.
+/// Returns None if the sink is reachable, i.e. the flow is not maximal.
+fn min_cut(
+    sink: &Address,
+    reachable: &HashSet<Node>,
+    used_edges: &UsedEdges,
+) -> Option<Vec<Bottleneck>> {
+    if reachable.contains(&Node::Node(*sink)) {
+        return None;
+    }
+    let mut cut = used_edges
+        .iter()
+        .filter(|(from, _)| reachable.contains(from))
+        .flat_map(|(from, out)| {
+            out.iter()
+                .filter(|(to, _)| !reachable.contains(to))
+                .map(move |(to, flow)| Bottleneck::from_arc(from, to, *flow))
+        })
+        .collect::<Vec<_>>();
+    cut.sort();
+    Some(cut)
+}
+
+/// Returns all nodes reachable from the source in the residual network
+/// after `augmenting_path_flow`.
+fn residual_reachable(source: &Address, adjacencies: &mut Adjacencies) -> HashSet<Node> {
+    let mut reachable = HashSet::from([Node::Node(*source)]);
+    let mut queue = VecDeque::from([Node::Node(*source)]);
+    while let Some(node) = queue.pop_front() {
+        for (next, _) in adjacencies.outgoing_edges_sorted_by_capacity(&node) {
+            if reachable.insert(next.clone()) {
+                queue.push_back(next);
+            }
+        }
+    }
+    reachable
+}
+
+/// Returns true if at least `value` can be transferred from `source` to `sink`.
+///
+/// Faster than `compute_flow` if only feasibility is needed: Most impossible transfers
+/// are detected by comparing `value` to the capacities leaving the source and entering
+/// the sink and by a bidirectional search for any path between them. Otherwise augmenting
+/// paths are only searched until `value` is reached and no transfers are extracted.
+/// Returns `FlowError::Cancelled` if `cancellation` is cancelled before that.
+pub fn is_transfer_possible(
+    source: &Address,
+    sink: &Address,
+    edges: &EdgeDB,
+    value: U256,
+    cancellation: &Cancellation,
+) -> Result<bool, FlowError> {
+    if value.is_zero() {
+        return Ok(true);
+    }
     let mut adjacencies = Adjacencies::new(edges);
-    let mut used_edges: HashMap<Node, HashMap<Node, U256>> = HashMap::new();
+    let sum = |capacities: Vec<(Node, U256)>| {
+        capacities
+            .into_iter()
+            .fold(U256::from(0u128), |sum, (_, capacity)| {
+                sum.saturating_add(capacity)
+            })
+    };
+    let sendable = sum(adjacencies.outgoing_edges_sorted_by_capacity(&Node::Node(*source)));
+    let receivable = edges
+        .incoming(sink)
+        .into_iter()
+        .map(|edge| edge.token)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .map(|token| {
+            sum(adjacencies.outgoing_edges_sorted_by_capacity(&Node::TrustNode(*sink, token)))
+        })
+        .fold(U256::from(0u128), |total, capacity| {
+            total.saturating_add(capacity)
+        });
+    if sendable < value || receivable < value || !is_connected(source, sink, edges) {
+        return Ok(false);
+    }
+
+    let mut flow = U256::from(0u128);
+    while flow < value {
+        if cancellation.is_cancelled() {
+            return Err(FlowError::Cancelled);
+        }
+        let (new_flow, parents) = augmenting_path(source, sink, &mut adjacencies, None)?;
+        if new_flow.is_zero() {
+            return Ok(false);
+        }
+        flow = checked_add(flow, new_flow)?;
+        for window in parents.windows(2) {
+            adjacencies.adjust_capacity(&window[1], &window[0], -new_flow);
+            adjacencies.adjust_capacity(&window[0], &window[1], new_flow);
+        }
+    }
+    Ok(true)
+}
+
+/// Bidirectional breadth-first search for a path of edges with non-zero capacity,
+/// always expanding the smaller of the two frontiers.
+fn is_connected(source: &Address, sink: &Address, edges: &EdgeDB) -> bool {
+    let mut forward = HashSet::from([*source]);
+    let mut backward = HashSet::from([*sink]);
+    let mut forward_frontier = vec![*source];
+    let mut backward_frontier = vec![*sink];
+    while !forward_frontier.is_empty() && !backward_frontier.is_empty() {
+        let expand_forward = forward_frontier.len() <= backward_frontier.len();
+        let (frontier, visited, other) = if expand_forward {
+            (&mut forward_frontier, &mut forward, &backward)
+        } else {
+            (&mut backward_frontier, &mut backward, &forward)
+        };
+        let mut next = vec![];
+        for address in frontier.drain(..) {
+            let neighbors = if expand_forward {
+                edges.outgoing(&address).into_iter().map(|e| e.to).collect()
+            } else {
+                edges
+                    .incoming(&address)
+                    .into_iter()
+                    .map(|e| e.from)
+                    .collect::<Vec<_>>()
+            };
+            for neighbor in neighbors {
+                if other.contains(&neighbor) {
+                    return true;
+                }
+                if visited.insert(neighbor) {
+                    next.push(neighbor);
+                }
+            }
+        }
+        *frontier = next;
+    }
+    false
+}
+
+/// Computes a flow from `source` to several sinks at once, sending at most
+/// the target value to each sink.
+/// Returns the value received by each sink that receives anything and the transfers.
+///
+/// All sinks are connected to a super sink in the flow network. The lazy search
+/// of `FordFulkerson` only supports a single sink, Dinic's algorithm is used instead.
+/// Returns `FlowError::Cancelled` if `cancellation` is cancelled before the flow is found.
+pub fn compute_flow_to_sinks(
+    source: &Address,
+    sinks: &BTreeMap<Address, U256>,
+    edges: &EdgeDB,
+    max_distance: Option<u64>,
+    max_transfers: Option<u64>,
+    algorithm: FlowAlgorithm,
+    cancellation: &Cancellation,
+) -> Result<(BTreeMap<Address, U256>, Vec<Edge>), FlowError> {
+    let search = tracing::info_span!("search").entered();
+    let requested_flow = sinks
+        .values()
+        .fold(U256::from(0u128), |sum, value| sum.saturating_add(*value));
+    let adjacencies = Adjacencies::new(edges);
+    let mut network = Network::build_to_sinks(source, sinks, &adjacencies, max_distance);
+    network.prune();
+    let mut flow = network_flow(
+        &mut network,
+        algorithm,
+        requested_flow,
+        max_distance,
+        1,
+        cancellation,
+        &mut |_| true,
+    )?;
+    if cancellation.is_cancelled() {
+        return Err(FlowError::Cancelled);
+    }
+    search.exit();
+    tracing::info!("Max flow: {}", flow.to_decimal());
+
+    let _decomposition = tracing::info_span!("decomposition").entered();
+    let mut used_edges = network.used_edges();
+    if let Some(max_transfers) = max_transfers {
+        let lost = reduce_transfers(
+            max_transfers
+                .saturating_mul(3)
+                .saturating_add(sinks.len() as u64),
+            &mut used_edges,
+        );
+        flow = checked_sub(flow, lost)?;
+    }
+    // The arcs to the super sink are not transfers, their flow is the value received by the sinks.
+    let mut received = BTreeMap::new();
+    for (node, out) in used_edges.iter_mut() {
+        if let Some(value) = out.remove(&Node::SuperSink) {
+            received.insert(*node_as_address(node), value);
+        }
+    }
+    used_edges.retain(|_, out| !out.is_empty());
+
+    let transfers = if flow.is_zero() {
+        vec![]
+    } else {
+        extract_transfers(source, &received, &flow, used_edges)?
+    };
+    let simplified_transfers = simplify_transfers(transfers);
+    debug_assert!(
+        received
+            .iter()
+            .all(|(sink, value)| sink == source
+                || net_received(sink, &simplified_transfers) == *value)
+    );
+    Ok((received, sort_transfers(simplified_transfers)))
+}
+
+/// Computes a combined flow from several sources (e.g. accounts owned by the same
+/// entity) to `sink`. Returns for each distinct source the value it contributes and
+/// its own list of transfers.
+///
+/// All sources are connected to a super source in the flow network and the flow is
+/// decomposed into paths to attribute it to the sources. If the flow exceeds the
+/// requested value, the longest paths are removed first.
+/// As for multiple sinks, `FordFulkerson` uses Dinic's algorithm.
+/// Returns `FlowError::Cancelled` if `cancellation` is cancelled before the flow is found.
+#[allow(clippy::too_many_arguments)]
+pub fn compute_flow_from_sources(
+    sources: &[Address],
+    sink: &Address,
+    edges: &EdgeDB,
+    requested_flow: U256,
+    max_distance: Option<u64>,
+    max_transfers: Option<u64>,
+    algorithm: FlowAlgorithm,
+    cancellation: &Cancellation,
+) -> Result<Vec<(Address, U256, Vec<Edge>)>, FlowError> {
+    let search = tracing::info_span!("search").entered();
+    let adjacencies = Adjacencies::new(edges);
+    let mut network = Network::build_from_sources(sources, sink, &adjacencies, max_distance);
+    network.prune();
+    let flow = network_flow(
+        &mut network,
+        algorithm,
+        requested_flow,
+        max_distance,
+        1,
+        cancellation,
+        &mut |_| true,
+    )?;
+    if cancellation.is_cancelled() {
+        return Err(FlowError::Cancelled);
+    }
+    search.exit();
+    tracing::info!("Max flow: {}", flow.to_decimal());
+
+    let _decomposition = tracing::info_span!("decomposition").entered();
+    let mut used_edges = network.used_edges();
+    if let Some(max_transfers) = max_transfers {
+        reduce_transfers(
+            max_transfers
+                .saturating_mul(3)
+                .saturating_add(sources.len() as u64),
+            &mut used_edges,
+        );
+    }
+    let mut paths = decompose_into_paths(&Node::SuperSource, &Node::Node(*sink), used_edges);
+    paths.sort_by_key(|(path, _)| path.len());
+
+    let mut remaining = requested_flow;
+    let mut contributions: HashMap<Address, (U256, UsedEdges)> = HashMap::new();
+    for (path, amount) in paths {
+        let amount = amount.min(remaining);
+        if amount.is_zero() {
+            break;
+        }
+        remaining = checked_sub(remaining, amount)?;
+        // The first node is the super source, the second one the source.
+        let (contributed, used_edges) =
+            contributions.entry(*node_as_address(&path[1])).or_default();
+        *contributed = checked_add(*contributed, amount)?;
+        for window in path[1..].windows(2) {
+            let used = used_edges
+                .entry(window[0].clone())
+                .or_default()
+                .entry(window[1].clone())
+                .or_default();
+            *used = checked_add(*used, amount)?;
+        }
+    }
+
+    let mut result = vec![];
+    for source in sources {
+        if result.iter().any(|(s, _, _)| s == source) {
+            continue;
+        }
+        let (amount, transfers) = match contributions.remove(source) {
+            Some((amount, used_edges)) => {
+                let targets = BTreeMap::from([(*sink, amount)]);
+                let transfers = extract_transfers(source, &targets, &amount, used_edges)?;
+                (amount, sort_transfers(simplify_transfers(transfers)))
+            }
+            None => (U256::from(0u128), vec![]),
+        };
+        result.push((*source, amount, transfers));
+    }
+    Ok(result)
+}
+
+/// Decomposes an acyclic flow into paths from `source` to `sink` with their flow.
+fn decompose_into_paths(
+    source: &Node,
+    sink: &Node,
+    mut used_edges: UsedEdges,
+) -> Vec<(Vec<Node>, U256)> {
+    let mut paths = vec![];
+    while let Some(first) = used_edges
+        .get(source)
+        .and_then(|out| out.keys().min().cloned())
+    {
+        let mut path = vec![source.clone(), first];
+        while path.last() != Some(sink) {
+            let next = used_edges[path.last().unwrap()].keys().min().unwrap();
+            path.push(next.clone());
+        }
+        let amount = path
+            .windows(2)
+            .map(|window| used_edges[&window[0]][&window[1]])
+            .min()
+            .unwrap();
+        for window in path.windows(2) {
+            reduce_capacity(&mut used_edges, (&window[0], &window[1]), &amount);
+        }
+        paths.push((path, amount));
+    }
+    paths
+}
+
+/// Runs one of the algorithms that work on the explicit network.
+/// `extra_arcs` is the number of arcs on each path that do not belong
+/// to a trust connection.
+fn network_flow(
+    network: &mut Network,
+    algorithm: FlowAlgorithm,
+    requested_flow: U256,
+    max_distance: Option<u64>,
+    extra_arcs: usize,
+    cancellation: &Cancellation,
+    progress: &mut dyn FnMut(U256) -> bool,
+) -> Result<U256, FlowError> {
+    match algorithm {
+        FlowAlgorithm::PushRelabel => push_relabel::max_flow(network, cancellation),
+        FlowAlgorithm::FordFulkerson | FlowAlgorithm::Dinic => {
+            // * 3 because we have three edges per trust connection (two intermediate nodes).
+            let max_path_length = max_distance.map(|d| d as usize * 3 + extra_arcs);
+            dinic::max_flow_with_progress(network, max_path_length, cancellation, progress)
+        }
+        FlowAlgorithm::MinCost => {
+            let max_cost = max_distance.map(|d| d as i64);
+            min_cost::min_cost_flow(network, requested_flow, max_cost, cancellation, progress)
+        }
+    }
+}
+
+/// Reduces the flow to the requested value and number of transfers and
+/// turns it into a sorted list of transfers. Fails if the flow would be decomposed
+/// into more than `max_steps` transfers.
+pub(super) fn transfers_from_flow(
+    source: &Address,
+    sink: &Address,
+    mut flow: U256,
+    mut used_edges: UsedEdges,
+    requested_flow: U256,
+    max_transfers: Option<u64>,
+    max_steps: Option<usize>,
+) -> Result<(U256, Vec<Edge>), FlowError> {
+    cancel_cycles(&mut used_edges);
+
+    if flow > requested_flow {
+        let still_to_prune = prune_flow(source, sink, flow - requested_flow, &mut used_edges);
+        flow = checked_add(requested_flow, still_to_prune)?;
+    }
+
+    if let Some(max_transfers) = max_transfers {
+        let lost = reduce_transfers(max_transfers.saturating_mul(3), &mut used_edges);
+        tracing::debug!(
+            "Capacity lost by transfer count reduction: {}",
+            lost.to_decimal_fraction()
+        );
+        flow = checked_sub(flow, lost)?;
+    }
+
+    if let Some(max_steps) = max_steps {
+        // Each used arc between a balance and a trust node is extracted as one transfer.
+        let steps = used_edges
+            .iter()
+            .filter(|(node, _)| matches!(node, Node::BalanceNode(..)))
+            .map(|(_, out)| out.len())
+            .sum::<usize>();
+        if steps > max_steps {
+            return Err(FlowError::LimitExceeded(format!(
+                "The flow consists of {steps} transfers, more than the maximum of {max_steps}."
+            )));
+        }
+    }
+
+    let transfers = if flow.is_zero() {
+        vec![]
+    } else {
+        extract_transfers(source, &BTreeMap::from([(*sink, flow)]), &flow, used_edges)?
+    };
+    tracing::debug!("Num transfers: {}", transfers.len());
+    let simplified_transfers = simplify_transfers(transfers);
+    tracing::debug!("After simplification: {}", simplified_transfers.len());
+    debug_assert_eq!(net_received(sink, &simplified_transfers), flow);
+    let sorted_transfers = sort_transfers(simplified_transfers);
+    Ok((flow, sorted_transfers))
+}
+
+/// Returns the amount the account receives minus the amount it sends.
+fn net_received(account: &Address, transfers: &[Edge]) -> U256 {
+    transfers.iter().fold(U256::from(0u128), |net, transfer| {
+        if transfer.to == *account {
+            net + transfer.capacity
+        } else if transfer.from == *account {
+            net - transfer.capacity
+        } else {
+            net
+        }
+    })
+}
+
+pub(super) type UsedEdges = HashMap<Node, HashMap<Node, U256>>;
+
+fn augmenting_path_flow(
+    source: &Address,
+    sink: &Address,
+    adjacencies: &mut Adjacencies,
+    max_distance: Option<u64>,
+    progress: &mut dyn FnMut(U256) -> bool,
+) -> Result<(U256, UsedEdges), FlowError> {
+    let mut used_edges: UsedEdges = HashMap::new();
 
     let mut flow = U256::default();
     loop {
-        let (new_flow, parents) = augmenting_path(source, sink, &mut adjacencies, max_distance);
+        let (new_flow, parents) = augmenting_path(source, sink, adjacencies, max_distance)?;
         if new_flow == U256::default() {
             break;
         }
-        flow += new_flow;
+        flow = checked_add(flow, new_flow)?;
         for window in parents.windows(2) {
             if let [node, prev] = window {
                 adjacencies.adjust_capacity(prev, node, -new_flow);
                 adjacencies.adjust_capacity(node, prev, new_flow);
                 if adjacencies.is_adjacent(node, prev) {
-                    *used_edges
+                    let used = used_edges
                         .entry(node.clone())
                         .or_default()
                         .entry(prev.clone())
-                        .or_default() -= new_flow;
+                        .or_default();
+                    *used = checked_sub(*used, new_flow)?;
                 } else {
-                    *used_edges
+                    let used = used_edges
                         .entry(prev.clone())
                         .or_default()
                         .entry(node.clone())
-                        .or_default() += new_flow;
+                        .or_default();
+                    *used = checked_add(*used, new_flow)?;
                 }
             } else {
                 panic!();
             }
         }
+        if !progress(flow) {
+            break;
+        }
     }
 
     used_edges.retain(|_, out| {
-        out.retain(|_, c| *c != U256::from(0));
+        out.retain(|_, c| !c.is_zero());
         !out.is_empty()
     });
-
-    println!("Max flow: {}", flow.to_decimal());
-
-    if flow > requested_flow {
-        let still_to_prune = prune_flow(source, sink, flow - requested_flow, &mut used_edges);
-        flow = requested_flow + still_to_prune;
-    }
-
-    if let Some(max_transfers) = max_transfers {
-        let lost = reduce_transfers(max_transfers * 3, &mut used_edges);
-        println!(
-            "Capacity lost by transfer count reduction: {}",
-            lost.to_decimal_fraction()
-        );
-        flow -= lost;
-    }
-
-    let transfers = if flow == U256::from(0) {
-        vec![]
-    } else {
-        extract_transfers(source, sink, &flow, used_edges)
-    };
-    println!("Num transfers: {}", transfers.len());
-    let simplified_transfers = simplify_transfers(transfers);
-    println!("After simplification: {}", simplified_transfers.len());
-    let sorted_transfers = sort_transfers(simplified_transfers);
-    (flow, sorted_transfers)
+    Ok((flow, used_edges))
 }
 
-pub fn transfers_to_dot(edges: &Vec<Edge>) -> String {
+pub fn transfers_to_dot(edges: &[Edge]) -> String {
     let mut out = String::new();
     writeln!(out, "digraph transfers {{").expect("");
 
@@ -118,13 +843,13 @@ fn augmenting_path(
     sink: &Address,
     adjacencies: &mut Adjacencies,
     max_distance: Option<u64>,
-) -> (U256, Vec<Node>) {
+) -> Result<(U256, Vec<Node>), FlowError> {
     let mut parent = HashMap::new();
     if *source == *sink {
-        return (U256::default(), vec![]);
+        return Ok((U256::default(), vec![]));
     }
     let mut queue = VecDeque::<(Node, (u64, U256))>::new();
-    queue.push_back((Node::Node(*source), (0, U256::default() - U256::from(1))));
+    queue.push_back((Node::Node(*source), (0, U256::MAX)));
     while let Some((node, (depth, flow))) = queue.pop_front() {
         if let Some(max) = max_distance {
             // * 3 because we have three edges per trust connection (two intermediate nodes).
@@ -132,21 +857,23 @@ fn augmenting_path(
                 continue;
             }
         }
-        for (target, capacity) in adjacencies.outgoing_edges_sorted_by_capacity(&node) {
+        let outgoing = adjacencies.outgoing_edges_sorted_by_capacity(&node);
+        adjacencies.check_explored(adjacencies.explored_accounts())?;
+        for (target, capacity) in outgoing {
             if !parent.contains_key(&target) && capacity > U256::default() {
                 parent.insert(target.clone(), node.clone());
-                let new_flow = min(flow, capacity);
+                let new_flow = flow.min(capacity);
                 if target == Node::Node(*sink) {
-                    return (
+                    return Ok((
                         new_flow,
                         trace(parent, &Node::Node(*source), &Node::Node(*sink)),
-                    );
+                    ));
                 }
                 queue.push_back((target, (depth + 1, new_flow)));
             }
         }
     }
-    (U256::default(), vec![])
+    Ok((U256::default(), vec![]))
 }
 
 fn trace(parent: HashMap<Node, Node>, source: &Node, sink: &Node) -> Vec<Node> {
@@ -182,6 +909,55 @@ fn to_dot(
     out
 }
 
+/// Removes flow that goes around cycles. Such flow does not contribute to the
+/// value of the flow, but would result in tokens being sent around in circles.
+/// The net flow at every node stays the same.
+fn cancel_cycles(used_edges: &mut UsedEdges) {
+    let mut done: HashSet<Node> = HashSet::new();
+    let mut starts = used_edges.keys().cloned().collect::<Vec<_>>();
+    starts.sort();
+    for start in starts {
+        if done.contains(&start) {
+            continue;
+        }
+        let mut stack = vec![start.clone()];
+        let mut on_stack = HashSet::from([start]);
+        while let Some(u) = stack.last().cloned() {
+            let next = used_edges
+                .get(&u)
+                .and_then(|out| out.keys().filter(|v| !done.contains(*v)).min().cloned());
+            match next {
+                None => {
+                    on_stack.remove(&u);
+                    done.insert(u);
+                    stack.pop();
+                }
+                Some(v) if on_stack.contains(&v) => {
+                    let pos = stack.iter().rposition(|w| *w == v).unwrap();
+                    let cycle = stack[pos..].iter().cloned().chain([v]).collect::<Vec<_>>();
+                    let amount = cycle
+                        .windows(2)
+                        .map(|edge| used_edges[&edge[0]][&edge[1]])
+                        .min()
+                        .unwrap();
+                    for edge in cycle.windows(2) {
+                        reduce_capacity(used_edges, (&edge[0], &edge[1]), &amount);
+                    }
+                    // Some edges of the cycle are gone now, continue from its start.
+                    for w in stack.drain(pos + 1..) {
+                        on_stack.remove(&w);
+                    }
+                }
+                Some(v) => {
+                    on_stack.insert(v.clone());
+                    stack.push(v);
+                }
+            }
+        }
+    }
+    used_edges.retain(|_, out| !out.is_empty());
+}
+
 fn prune_flow(
     source: &Address,
     sink: &Address,
@@ -195,7 +971,7 @@ fn prune_flow(
         //println!("Shorter path.");
         // As long as `edges` contain an edge with smaller weight than the weight still to prune:
         //   take the smallest such edge and prune it.
-        while flow_to_prune > U256::from(0) && !edges_here.is_empty() {
+        while flow_to_prune > U256::from(0u128) && !edges_here.is_empty() {
             //println!("Still to prune: {}", flow_to_prune);
             if let Some((s, t)) = smallest_edge_in_set(used_edges, edges_here) {
                 if used_edges[&s][&t] > flow_to_prune {
@@ -209,7 +985,7 @@ fn prune_flow(
     }
     // If there is still flow to prune, take the first element in edgesByPathLength
     // and partially prune its path.
-    if flow_to_prune > U256::from(0) {
+    if flow_to_prune > U256::from(0u128) {
         //println!("Final stage: Still to prune: {}", flow_to_prune);
         for edges_here in edges_by_path_length.values() {
             for (a, b) in edges_here {
@@ -217,12 +993,12 @@ fn prune_flow(
                     continue;
                 }
                 flow_to_prune = prune_edge(used_edges, (a, b), flow_to_prune);
-                if flow_to_prune == U256::from(0) {
-                    return U256::from(0);
+                if flow_to_prune.is_zero() {
+                    return U256::from(0u128);
                 }
             }
-            if flow_to_prune == U256::from(0) {
-                return U256::from(0);
+            if flow_to_prune.is_zero() {
+                return U256::from(0u128);
             }
         }
     }
@@ -233,7 +1009,7 @@ fn reduce_transfers(
     max_transfers: u64,
     used_edges: &mut HashMap<Node, HashMap<Node, U256>>,
 ) -> U256 {
-    let mut reduced_flow = U256::from(0);
+    let mut reduced_flow = U256::from(0u128);
     while used_edges.len() > max_transfers as usize {
         let all_edges = used_edges
             .iter()
@@ -253,12 +1029,13 @@ fn reduce_transfers(
 /// Returns a map from the negative shortest path length to the edge.
 /// The shortest path length is negative so that it is sorted by
 /// longest paths first - those are the ones we want to eliminate first.
+/// The edges are ordered, so that the same flow is always pruned the same way.
 fn compute_edges_by_path_length(
     source: &Address,
     sink: &Address,
     used_edges: &HashMap<Node, HashMap<Node, U256>>,
-) -> BTreeMap<i64, HashSet<(Node, Node)>> {
-    let mut result = BTreeMap::<i64, HashSet<(Node, Node)>>::new();
+) -> BTreeMap<i64, BTreeSet<(Node, Node)>> {
+    let mut result = BTreeMap::<i64, BTreeSet<(Node, Node)>>::new();
     let from_source = distance_from_source(&Node::Node(*source), used_edges);
     let to_sink = distance_to_sink(&Node::Node(*sink), used_edges);
     for (s, edges) in used_edges {
@@ -284,7 +1061,7 @@ fn distance_from_source(
 
     while let Some(n) = to_process.pop_front() {
         for (t, capacity) in used_edges.get(&n).unwrap_or(&HashMap::new()) {
-            if *capacity > U256::from(0) && !distances.contains_key(t) {
+            if *capacity > U256::from(0u128) && !distances.contains_key(t) {
                 distances.insert(t.clone(), distances[&n] + 1);
                 to_process.push_back(t.clone());
             }
@@ -318,14 +1095,14 @@ fn reverse_edges(
 
 fn smallest_edge_in_set(
     all_edges: &HashMap<Node, HashMap<Node, U256>>,
-    edge_set: &HashSet<(Node, Node)>,
+    edge_set: &BTreeSet<(Node, Node)>,
 ) -> Option<(Node, Node)> {
     if let Some((a, b, _)) = edge_set
         .iter()
         .map(|(a, b)| {
             let capacity = if let Some(out) = all_edges.get(a) {
                 if let Some(capacity) = out.get(b) {
-                    assert!(*capacity != U256::from(0));
+                    assert!(!capacity.is_zero());
                     Some(capacity)
                 } else {
                     None
@@ -351,7 +1128,7 @@ fn smallest_edge_from(
     used_edges.get(n).and_then(|out| {
         out.iter()
             .min_by_key(|(addr, c)| {
-                assert!(**c != U256::from(0));
+                assert!(!c.is_zero());
                 (*c, *addr)
             })
             .map(|(t, c)| (t.clone(), *c))
@@ -367,7 +1144,7 @@ fn smallest_edge_to(
         .filter(|(_, out)| out.contains_key(n))
         .map(|(t, out)| (t, out[n]))
         .min_by_key(|(addr, c)| {
-            assert!(*c != U256::from(0));
+            assert!(!c.is_zero());
             (*c, *addr)
         })
         .map(|(t, c)| (t.clone(), c))
@@ -380,7 +1157,7 @@ fn prune_edge(
     edge: (&Node, &Node),
     flow_to_prune: U256,
 ) -> U256 {
-    let edge_size = min(flow_to_prune, used_edges[edge.0][edge.1]);
+    let edge_size = flow_to_prune.min(used_edges[edge.0][edge.1]);
     reduce_capacity(used_edges, edge, &edge_size);
     prune_path(used_edges, edge.1, edge_size, PruneDirection::Forwards);
     prune_path(used_edges, edge.0, edge_size, PruneDirection::Backwards);
@@ -394,7 +1171,7 @@ fn reduce_capacity(
 ) {
     let out_edges = used_edges.get_mut(a).unwrap();
     *out_edges.get_mut(b).unwrap() -= *reduction;
-    if out_edges[b] == U256::from(0) {
+    if out_edges[b].is_zero() {
         out_edges.remove_entry(b);
     }
 }
@@ -415,56 +1192,55 @@ fn prune_path(
         PruneDirection::Forwards => smallest_edge_from(used_edges, n),
         PruneDirection::Backwards => smallest_edge_to(used_edges, n),
     } {
-        capacity = min(flow_to_prune, capacity);
+        capacity = flow_to_prune.min(capacity);
         match direction {
             PruneDirection::Forwards => reduce_capacity(used_edges, (n, &next), &capacity),
             PruneDirection::Backwards => reduce_capacity(used_edges, (&next, n), &capacity),
         };
         prune_path(used_edges, &next, capacity, direction);
         flow_to_prune -= capacity;
-        if flow_to_prune == U256::from(0) {
+        if flow_to_prune.is_zero() {
             return;
         }
     }
 }
 
+/// Turns the flow into transfers, starting with `amount` at the source,
+/// until the accounts hold exactly the `targets` balances.
 fn extract_transfers(
     source: &Address,
-    sink: &Address,
+    targets: &BTreeMap<Address, U256>,
     amount: &U256,
     mut used_edges: HashMap<Node, HashMap<Node, U256>>,
-) -> Vec<Edge> {
+) -> Result<Vec<Edge>, FlowError> {
     let mut transfers: Vec<Edge> = Vec::new();
     let mut account_balances: BTreeMap<Address, U256> = BTreeMap::new();
     account_balances.insert(*source, *amount);
 
-    while !account_balances.is_empty()
-        && (account_balances.len() > 1 || *account_balances.iter().next().unwrap().0 != *sink)
-    {
-        let edge = next_full_capacity_edge(&used_edges, &account_balances);
-        assert!(account_balances[&edge.from] >= edge.capacity);
+    while account_balances != *targets {
+        let edge = next_full_capacity_edge(&used_edges, &account_balances).ok_or_else(|| {
+            FlowError::Inconsistent("No edge can be sent with the current balances".to_string())
+        })?;
         account_balances
             .entry(edge.from)
             .and_modify(|balance| *balance -= edge.capacity);
-        *account_balances.entry(edge.to).or_default() += edge.capacity;
-        account_balances.retain(|_account, balance| balance > &mut U256::from(0));
-        assert!(used_edges.contains_key(&Node::BalanceNode(edge.from, edge.token)));
+        let balance = account_balances.entry(edge.to).or_default();
+        *balance = checked_add(*balance, edge.capacity)?;
+        account_balances.retain(|_account, balance| balance > &mut U256::from(0u128));
         used_edges
-            .entry(Node::BalanceNode(edge.from, edge.token))
-            .and_modify(|outgoing| {
-                assert!(outgoing.contains_key(&Node::TrustNode(edge.to, edge.token)));
-                outgoing.remove(&Node::TrustNode(edge.to, edge.token));
-            });
+            .get_mut(&Node::BalanceNode(edge.from, edge.token))
+            .and_then(|outgoing| outgoing.remove(&Node::TrustNode(edge.to, edge.token)))
+            .ok_or_else(|| FlowError::Inconsistent(format!("The edge {edge:?} is not used")))?;
         transfers.push(edge);
     }
 
-    transfers
+    Ok(transfers)
 }
 
 fn next_full_capacity_edge(
     used_edges: &HashMap<Node, HashMap<Node, U256>>,
     account_balances: &BTreeMap<Address, U256>,
-) -> Edge {
+) -> Option<Edge> {
     for (account, balance) in account_balances {
         let edge = used_edges
             .get(&Node::Node(*account))
@@ -485,14 +1261,14 @@ fn next_full_capacity_edge(
                 })
             })
             .and_then(|edges| edges.min());
-        if let Some(edge) = edge {
+        if edge.is_some() {
             return edge;
         }
     }
-    panic!();
+    None
 }
 
-fn find_pair_to_simplify(transfers: &Vec<Edge>) -> Option<(usize, usize)> {
+fn find_pair_to_simplify(transfers: &[Edge]) -> Option<(usize, usize)> {
     let l = transfers.len();
     (0..l)
         .flat_map(move |x| (0..l).map(move |y| (x, y)))
@@ -510,11 +1286,58 @@ fn simplify_transfers(mut transfers: Vec<Edge>) -> Vec<Edge> {
     // If we have a transfer (A, B, T) and a transfer (B, C, T),
     // We can always replace both by (A, C, T).
 
-    while let Some((i, j)) = find_pair_to_simplify(&transfers) {
-        transfers[i].to = transfers[j].to;
-        transfers.remove(j);
+    // Chaining can create parallel or opposing transfers, and merging those
+    // can create new chains, so we repeat until nothing changes.
+    loop {
+        while let Some((i, j)) = find_pair_to_simplify(&transfers) {
+            transfers[i].to = transfers[j].to;
+            transfers.remove(j);
+        }
+        let count = transfers.len();
+        transfers = merge_transfers(transfers);
+        if transfers.len() == count {
+            return transfers;
+        }
+    }
+}
+
+/// Merges transfers with the same sender, receiver and token into one,
+/// cancels transfers of the same token in opposite directions against each
+/// other and removes transfers to the sender itself.
+/// Keeps the order of the first occurrence of each pair of accounts and token.
+/// This does not change the net amount any account sends or receives in any token.
+fn merge_transfers(transfers: Vec<Edge>) -> Vec<Edge> {
+    let mut index: HashMap<(Address, Address, Address), usize> = HashMap::new();
+    let mut merged: Vec<Edge> = Vec::new();
+    for transfer in transfers {
+        if transfer.from == transfer.to {
+            continue;
+        }
+        if let Some(i) = index.get(&(transfer.from, transfer.to, transfer.token)) {
+            merged[*i].capacity += transfer.capacity;
+        } else if let Some(i) = index
+            .get(&(transfer.to, transfer.from, transfer.token))
+            .copied()
+        {
+            let opposing = &mut merged[i];
+            if opposing.capacity >= transfer.capacity {
+                opposing.capacity -= transfer.capacity;
+            } else {
+                // Turn the opposing transfer around.
+                *opposing = Edge {
+                    capacity: transfer.capacity - opposing.capacity,
+                    ..transfer
+                };
+                index.remove(&(transfer.to, transfer.from, transfer.token));
+                index.insert((transfer.from, transfer.to, transfer.token), i);
+            }
+        } else {
+            index.insert((transfer.from, transfer.to, transfer.token), merged.len());
+            merged.push(transfer);
+        }
     }
-    transfers
+    merged.retain(|transfer| !transfer.capacity.is_zero());
+    merged
 }
 
 fn sort_transfers(transfers: Vec<Edge>) -> Vec<Edge> {
@@ -543,6 +1366,7 @@ fn sort_transfers(transfers: Vec<Edge>) -> Vec<Edge> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::types::edge::edge;
 
     fn addresses() -> (Address, Address, Address, Address, Address, Address) {
         (
@@ -557,6 +1381,17 @@ mod test {
     fn build_edges(input: Vec<Edge>) -> EdgeDB {
         EdgeDB::new(input)
     }
+    /// One path of length one and one of length two from a to d,
+    /// and a dead end at c.
+    fn two_paths() -> EdgeDB {
+        let (a, b, c, d, t1, t2) = addresses();
+        build_edges(vec![
+            edge(a, d, t1, 5),
+            edge(a, b, t2, 7),
+            edge(b, d, t2, 7),
+            edge(a, c, t2, 3),
+        ])
+    }
 
     #[test]
     fn direct() {
@@ -565,18 +1400,27 @@ mod test {
             from: a,
             to: b,
             token: t,
-            capacity: U256::from(10),
+            capacity: U256::from(10u128),
         }]);
-        let flow = compute_flow(&a, &b, &edges, U256::MAX, None, None);
+        let flow = compute_flow(
+            &a,
+            &b,
+            &edges,
+            U256::MAX,
+            None,
+            None,
+            FlowAlgorithm::FordFulkerson,
+        )
+        .unwrap();
         assert_eq!(
             flow,
             (
-                U256::from(10),
+                U256::from(10u128),
                 vec![Edge {
                     from: a,
                     to: b,
                     token: t,
-                    capacity: U256::from(10)
+                    capacity: U256::from(10u128)
                 }]
             )
         );
@@ -590,32 +1434,41 @@ mod test {
                 from: a,
                 to: b,
                 token: t1,
-                capacity: U256::from(10),
+                capacity: U256::from(10u128),
             },
             Edge {
                 from: b,
                 to: c,
                 token: t2,
-                capacity: U256::from(8),
+                capacity: U256::from(8u128),
             },
         ]);
-        let flow = compute_flow(&a, &c, &edges, U256::MAX, None, None);
+        let flow = compute_flow(
+            &a,
+            &c,
+            &edges,
+            U256::MAX,
+            None,
+            None,
+            FlowAlgorithm::FordFulkerson,
+        )
+        .unwrap();
         assert_eq!(
             flow,
             (
-                U256::from(8),
+                U256::from(8u128),
                 vec![
                     Edge {
                         from: a,
                         to: b,
                         token: t1,
-                        capacity: U256::from(8)
+                        capacity: U256::from(8u128)
                     },
                     Edge {
                         from: b,
                         to: c,
                         token: t2,
-                        capacity: U256::from(8)
+                        capacity: U256::from(8u128)
                     },
                 ]
             )
@@ -630,79 +1483,97 @@ mod test {
                 from: a,
                 to: b,
                 token: t1,
-                capacity: U256::from(10),
+                capacity: U256::from(10u128),
             },
             Edge {
                 from: a,
                 to: c,
                 token: t2,
-                capacity: U256::from(7),
+                capacity: U256::from(7u128),
             },
             Edge {
                 from: b,
                 to: d,
                 token: t2,
-                capacity: U256::from(9),
+                capacity: U256::from(9u128),
             },
             Edge {
                 from: c,
                 to: d,
                 token: t1,
-                capacity: U256::from(8),
+                capacity: U256::from(8u128),
             },
         ]);
-        let mut flow = compute_flow(&a, &d, &edges, U256::MAX, None, None);
+        let mut flow = compute_flow(
+            &a,
+            &d,
+            &edges,
+            U256::MAX,
+            None,
+            None,
+            FlowAlgorithm::FordFulkerson,
+        )
+        .unwrap();
         flow.1.sort();
         assert_eq!(
             flow,
             (
-                U256::from(16),
+                U256::from(16u128),
                 vec![
                     Edge {
                         from: a,
                         to: b,
                         token: t1,
-                        capacity: U256::from(9)
+                        capacity: U256::from(9u128)
                     },
                     Edge {
                         from: a,
                         to: c,
                         token: t2,
-                        capacity: U256::from(7)
+                        capacity: U256::from(7u128)
                     },
                     Edge {
                         from: b,
                         to: d,
                         token: t2,
-                        capacity: U256::from(9)
+                        capacity: U256::from(9u128)
                     },
                     Edge {
                         from: c,
                         to: d,
                         token: t1,
-                        capacity: U256::from(7)
+                        capacity: U256::from(7u128)
                     },
                 ]
             )
         );
-        let mut pruned_flow = compute_flow(&a, &d, &edges, U256::from(6), None, None);
+        let mut pruned_flow = compute_flow(
+            &a,
+            &d,
+            &edges,
+            U256::from(6u128),
+            None,
+            None,
+            FlowAlgorithm::FordFulkerson,
+        )
+        .unwrap();
         pruned_flow.1.sort();
         assert_eq!(
             pruned_flow,
             (
-                U256::from(6),
+                U256::from(6u128),
                 vec![
                     Edge {
                         from: a,
                         to: b,
                         token: t1,
-                        capacity: U256::from(6)
+                        capacity: U256::from(6u128)
                     },
                     Edge {
                         from: b,
                         to: d,
                         token: t2,
-                        capacity: U256::from(6)
+                        capacity: U256::from(6u128)
                     },
                 ]
             )
@@ -719,13 +1590,13 @@ mod test {
                 from: a,
                 to: b,
                 token: a,
-                capacity: U256::from(10),
+                capacity: U256::from(10u128),
             },
             Edge {
                 from: a,
                 to: c,
                 token: a,
-                capacity: U256::from(11),
+                capacity: U256::from(11u128),
             },
             // The following two edges should be trust-limited,
             // i.e. the edge from the second (pre-) intermediate is limited
@@ -734,18 +1605,914 @@ mod test {
                 from: b,
                 to: d,
                 token: a,
-                capacity: U256::from(9),
+                capacity: U256::from(9u128),
             },
             Edge {
                 from: c,
                 to: d,
                 token: a,
-                capacity: U256::from(8),
+                capacity: U256::from(8u128),
             },
         ]);
-        let mut flow = compute_flow(&a, &d, &edges, U256::MAX, None, None);
+        let mut flow = compute_flow(
+            &a,
+            &d,
+            &edges,
+            U256::MAX,
+            None,
+            None,
+            FlowAlgorithm::FordFulkerson,
+        )
+        .unwrap();
         flow.1.sort();
         println!("{:?}", &flow.1);
-        assert_eq!(flow.0, U256::from(9));
+        assert_eq!(flow.0, U256::from(9u128));
+    }
+
+    #[test]
+    fn network_algorithms() {
+        let (a, b, c, d, t1, t2) = addresses();
+        let edges = build_edges(vec![
+            Edge {
+                from: a,
+                to: b,
+                token: t1,
+                capacity: U256::from(10u128),
+            },
+            Edge {
+                from: a,
+                to: c,
+                token: t2,
+                capacity: U256::from(7u128),
+            },
+            Edge {
+                from: b,
+                to: d,
+                token: t2,
+                capacity: U256::from(9u128),
+            },
+            Edge {
+                from: c,
+                to: d,
+                token: t1,
+                capacity: U256::from(8u128),
+            },
+            Edge {
+                from: b,
+                to: c,
+                token: t1,
+                capacity: U256::from(3u128),
+            },
+        ]);
+        for (sink, requested) in [
+            (b, U256::MAX),
+            (c, U256::MAX),
+            (d, U256::MAX),
+            (d, U256::from(5u128)),
+        ] {
+            let expected = compute_flow(
+                &a,
+                &sink,
+                &edges,
+                requested,
+                None,
+                None,
+                FlowAlgorithm::FordFulkerson,
+            )
+            .unwrap();
+            let flow = compute_flow(
+                &a,
+                &sink,
+                &edges,
+                requested,
+                None,
+                None,
+                FlowAlgorithm::PushRelabel,
+            )
+            .unwrap();
+            assert_eq!(flow.0, expected.0);
+            let sent: U256 = flow
+                .1
+                .iter()
+                .filter(|e| e.to == sink)
+                .fold(U256::from(0u128), |acc, e| acc + e.capacity);
+            assert_eq!(sent, flow.0);
+        }
+        let unreachable = compute_flow(
+            &d,
+            &a,
+            &edges,
+            U256::MAX,
+            None,
+            None,
+            FlowAlgorithm::PushRelabel,
+        )
+        .unwrap();
+        assert_eq!(unreachable, (U256::from(0u128), vec![]));
+    }
+
+    #[test]
+    fn min_cost() {
+        let (a, b, c, d, t1, t2) = addresses();
+        let edges = build_edges(vec![
+            Edge {
+                from: a,
+                to: b,
+                token: t1,
+                capacity: U256::from(10u128),
+            },
+            Edge {
+                from: b,
+                to: c,
+                token: t1,
+                capacity: U256::from(10u128),
+            },
+            Edge {
+                from: c,
+                to: d,
+                token: t1,
+                capacity: U256::from(10u128),
+            },
+            Edge {
+                from: a,
+                to: d,
+                token: t2,
+                capacity: U256::from(4u128),
+            },
+        ]);
+        // The direct transfer is used first.
+        let flow = compute_flow(
+            &a,
+            &d,
+            &edges,
+            U256::from(4u128),
+            None,
+            None,
+            FlowAlgorithm::MinCost,
+        )
+        .unwrap();
+        assert_eq!(
+            flow,
+            (
+                U256::from(4u128),
+                vec![Edge {
+                    from: a,
+                    to: d,
+                    token: t2,
+                    capacity: U256::from(4u128)
+                }]
+            )
+        );
+        let flow = compute_flow(
+            &a,
+            &d,
+            &edges,
+            U256::from(6u128),
+            None,
+            None,
+            FlowAlgorithm::MinCost,
+        )
+        .unwrap();
+        assert_eq!(flow.0, U256::from(6u128));
+        assert_eq!(flow.1.len(), 2);
+        let flow = compute_flow(
+            &a,
+            &d,
+            &edges,
+            U256::MAX,
+            None,
+            None,
+            FlowAlgorithm::MinCost,
+        )
+        .unwrap();
+        assert_eq!(flow.0, U256::from(14u128));
+        let flow = compute_flow(
+            &a,
+            &d,
+            &edges,
+            U256::MAX,
+            Some(2),
+            None,
+            FlowAlgorithm::MinCost,
+        )
+        .unwrap();
+        assert_eq!(flow.0, U256::from(4u128));
+    }
+
+    #[test]
+    fn capacity_overflow() {
+        let (a, b, c, t1, t2, t3) = addresses();
+        let half = U256::new(1 << 127, 0);
+        let edges = build_edges(vec![
+            Edge {
+                from: a,
+                to: b,
+                token: t1,
+                capacity: half,
+            },
+            Edge {
+                from: a,
+                to: c,
+                token: t2,
+                capacity: half,
+            },
+            Edge {
+                from: b,
+                to: c,
+                token: t3,
+                capacity: half,
+            },
+        ]);
+        assert_eq!(
+            compute_flow(
+                &a,
+                &c,
+                &edges,
+                U256::MAX,
+                None,
+                None,
+                FlowAlgorithm::FordFulkerson
+            ),
+            Err(FlowError::CapacityOverflow)
+        );
+    }
+
+    #[test]
+    fn merge() {
+        let (a, b, c, _, t1, t2) = addresses();
+        let transfers = vec![
+            edge(a, b, t1, 5),
+            edge(a, b, t2, 3),
+            edge(b, a, t1, 7),
+            edge(a, b, t1, 4),
+            edge(b, c, t2, 2),
+            edge(c, c, t2, 2),
+            edge(c, b, t2, 2),
+        ];
+        assert_eq!(
+            merge_transfers(transfers),
+            vec![edge(a, b, t1, 2), edge(a, b, t2, 3)]
+        );
+    }
+
+    #[test]
+    fn simplify_to_merge() {
+        let (a, b, c, _, t1, _) = addresses();
+        // Chaining a -> b -> c creates a second transfer a -> c.
+        let transfers = vec![edge(a, b, t1, 4), edge(a, c, t1, 1), edge(b, c, t1, 4)];
+        assert_eq!(simplify_transfers(transfers), vec![edge(a, c, t1, 5)]);
+    }
+
+    #[test]
+    fn multi_sink() {
+        let (a, b, c, _, t1, _) = addresses();
+        let edges = build_edges(vec![
+            Edge {
+                from: a,
+                to: b,
+                token: a,
+                capacity: U256::from(10u128),
+            },
+            Edge {
+                from: a,
+                to: c,
+                token: t1,
+                capacity: U256::from(5u128),
+            },
+            Edge {
+                from: b,
+                to: c,
+                token: b,
+                capacity: U256::from(3u128),
+            },
+        ]);
+        for algorithm in [
+            FlowAlgorithm::FordFulkerson,
+            FlowAlgorithm::PushRelabel,
+            FlowAlgorithm::Dinic,
+            FlowAlgorithm::MinCost,
+        ] {
+            let sinks = BTreeMap::from([(b, U256::from(4u128)), (c, U256::from(7u128))]);
+            let (received, transfers) = compute_flow_to_sinks(
+                &a,
+                &sinks,
+                &edges,
+                None,
+                None,
+                algorithm,
+                &Cancellation::default(),
+            )
+            .unwrap();
+            assert_eq!(received, sinks);
+            for (sink, value) in &received {
+                assert_eq!(net_received(sink, &transfers), *value);
+            }
+
+            let sinks = BTreeMap::from([(b, U256::from(20u128)), (c, U256::from(20u128))]);
+            let (received, transfers) = compute_flow_to_sinks(
+                &a,
+                &sinks,
+                &edges,
+                None,
+                None,
+                algorithm,
+                &Cancellation::default(),
+            )
+            .unwrap();
+            assert_eq!(
+                received.values().fold(U256::from(0u128), |sum, v| sum + *v),
+                U256::from(15u128)
+            );
+            for (sink, value) in &received {
+                assert_eq!(net_received(sink, &transfers), *value);
+            }
+
+            // Only paths of one hop.
+            let (received, _) = compute_flow_to_sinks(
+                &a,
+                &sinks,
+                &edges,
+                Some(1),
+                None,
+                algorithm,
+                &Cancellation::default(),
+            )
+            .unwrap();
+            assert_eq!(
+                received,
+                BTreeMap::from([(b, U256::from(10u128)), (c, U256::from(5u128))])
+            );
+        }
+    }
+
+    #[test]
+    fn multi_source() {
+        let (a, b, c, d, t1, _) = addresses();
+        let edges = build_edges(vec![
+            Edge {
+                from: a,
+                to: c,
+                token: a,
+                capacity: U256::from(5u128),
+            },
+            Edge {
+                from: b,
+                to: c,
+                token: b,
+                capacity: U256::from(3u128),
+            },
+            Edge {
+                from: d,
+                to: b,
+                token: t1,
+                capacity: U256::from(2u128),
+            },
+        ]);
+        for algorithm in [
+            FlowAlgorithm::FordFulkerson,
+            FlowAlgorithm::PushRelabel,
+            FlowAlgorithm::Dinic,
+            FlowAlgorithm::MinCost,
+        ] {
+            let result = compute_flow_from_sources(
+                &[a, b, a, c],
+                &c,
+                &edges,
+                U256::MAX,
+                None,
+                None,
+                algorithm,
+                &Cancellation::default(),
+            )
+            .unwrap();
+            assert_eq!(result.len(), 3);
+            assert_eq!(result[0].0, a);
+            assert_eq!(result[0].1, U256::from(5u128));
+            assert_eq!(
+                result[0].2,
+                vec![Edge {
+                    from: a,
+                    to: c,
+                    token: a,
+                    capacity: U256::from(5u128),
+                }]
+            );
+            assert_eq!(result[1].0, b);
+            assert_eq!(result[1].1, U256::from(3u128));
+            assert_eq!(net_received(&c, &result[1].2), U256::from(3u128));
+            assert_eq!(result[2], (c, U256::from(0u128), vec![]));
+
+            // d can only send via b, so it competes with b for the capacity to c.
+            let result = compute_flow_from_sources(
+                &[d, a],
+                &c,
+                &edges,
+                U256::from(6u128),
+                None,
+                None,
+                algorithm,
+                &Cancellation::default(),
+            )
+            .unwrap();
+            let total = result
+                .iter()
+                .fold(U256::from(0u128), |sum, (_, v, _)| sum + *v);
+            assert_eq!(total, U256::from(6u128));
+            // The shorter path from a is used first.
+            assert_eq!(result[1].1, U256::from(5u128));
+            assert_eq!(net_received(&c, &result[0].2), U256::from(1u128));
+        }
+    }
+
+    #[test]
+    fn token_filter() {
+        let (a, b, c, _, t1, t2) = addresses();
+        let edges = build_edges(vec![
+            Edge {
+                from: a,
+                to: c,
+                token: t1,
+                capacity: U256::from(10u128),
+            },
+            Edge {
+                from: a,
+                to: b,
+                token: t2,
+                capacity: U256::from(5u128),
+            },
+            Edge {
+                from: b,
+                to: c,
+                token: t2,
+                capacity: U256::from(5u128),
+            },
+        ]);
+        for algorithm in [FlowAlgorithm::FordFulkerson, FlowAlgorithm::Dinic] {
+            let compute = |token_filter| {
+                compute_flow_with_options(
+                    &a,
+                    &c,
+                    &edges,
+                    &FlowOptions {
+                        algorithm,
+                        token_filter,
+                        ..FlowOptions::default()
+                    },
+                )
+                .unwrap()
+            };
+            let result = compute(TokenFilter {
+                excluded: HashSet::from([t1]),
+                ..TokenFilter::default()
+            });
+            assert_eq!(result.flow, U256::from(5u128));
+            assert!(result.transfers.iter().all(|t| t.token == t2));
+
+            let result = compute(TokenFilter {
+                accepted: Some(HashSet::from([t1])),
+                ..TokenFilter::default()
+            });
+            assert_eq!(result.flow, U256::from(10u128));
+            assert!(result.transfers.iter().all(|t| t.token == t1));
+
+            assert_eq!(compute(TokenFilter::default()).flow, U256::from(15u128));
+        }
+    }
+
+    #[test]
+    fn exclude_stopped() {
+        let (a, b, c, _, t1, t2) = addresses();
+        let mut edges = build_edges(vec![
+            edge(a, b, t1, 10),
+            edge(b, c, t1, 10),
+            edge(a, c, t2, 3),
+        ]);
+        // The source and the sink are stopped as well, but can still send and receive.
+        for account in [a, b, c] {
+            edges.balances_mut().set_stopped(account, true);
+        }
+        for algorithm in [FlowAlgorithm::FordFulkerson, FlowAlgorithm::Dinic] {
+            let compute = |exclude_stopped| {
+                compute_flow_with_options(
+                    &a,
+                    &c,
+                    &edges,
+                    &FlowOptions {
+                        algorithm,
+                        exclude_stopped,
+                        ..FlowOptions::default()
+                    },
+                )
+                .unwrap()
+            };
+            assert_eq!(compute(false).flow, U256::from(13u128));
+            let result = compute(true);
+            assert_eq!(result.flow, U256::from(3u128));
+            assert_eq!(result.transfers, vec![edge(a, c, t2, 3)]);
+        }
+    }
+
+    #[test]
+    fn max_staleness() {
+        let (a, b, c, _, t1, t2) = addresses();
+        let mut edges = EdgeDB::default();
+        edges.update_at_block(edge(a, c, t1, 10), 100);
+        edges.update_at_block(edge(a, b, t2, 5), 120);
+        edges.update_at_block(edge(b, c, t2, 5), 150);
+        for algorithm in [FlowAlgorithm::FordFulkerson, FlowAlgorithm::Dinic] {
+            let compute = |max_staleness_blocks| {
+                compute_flow_with_options(
+                    &a,
+                    &c,
+                    &edges,
+                    &FlowOptions {
+                        algorithm,
+                        max_staleness_blocks,
+                        ..FlowOptions::default()
+                    },
+                )
+                .unwrap()
+                .flow
+            };
+            assert_eq!(compute(None), U256::from(15u128));
+            assert_eq!(compute(Some(50)), U256::from(15u128));
+            assert_eq!(compute(Some(30)), U256::from(5u128));
+            assert_eq!(compute(Some(10)), U256::from(0u128));
+        }
+    }
+
+    #[test]
+    fn pruning() {
+        let (a, b, c, d, t1, _) = addresses();
+        // b is a dead end and d can only be reached via the sink.
+        let edges = build_edges(vec![
+            Edge {
+                from: a,
+                to: b,
+                token: t1,
+                capacity: U256::from(10u128),
+            },
+            Edge {
+                from: a,
+                to: c,
+                token: t1,
+                capacity: U256::from(10u128),
+            },
+            Edge {
+                from: c,
+                to: d,
+                token: t1,
+                capacity: U256::from(10u128),
+            },
+        ]);
+        for algorithm in [
+            FlowAlgorithm::PushRelabel,
+            FlowAlgorithm::Dinic,
+            FlowAlgorithm::MinCost,
+        ] {
+            let options = FlowOptions {
+                algorithm,
+                ..FlowOptions::default()
+            };
+            let result = compute_flow_with_options(&a, &c, &edges, &options).unwrap();
+            assert_eq!(result.flow, U256::from(10u128));
+            assert_eq!(result.transfers.len(), 1);
+            assert_eq!(
+                result.statistics,
+                Some(NetworkStatistics {
+                    // a, b, c, the shared balance node of a and the trust nodes
+                    // of b and c. The sink c is not expanded.
+                    explored_nodes: 6,
+                    explored_arcs: 5,
+                    relevant_nodes: 4,
+                    relevant_arcs: 3,
+                })
+            );
+        }
+        let result = compute_flow_with_options(&a, &c, &edges, &FlowOptions::default()).unwrap();
+        assert_eq!(result.statistics, None);
+    }
+
+    #[test]
+    fn transfer_possible() {
+        let (a, b, c, d, t1, t2) = addresses();
+        let edges = build_edges(vec![
+            edge(a, b, t1, 10),
+            edge(a, c, t2, 7),
+            edge(b, d, t2, 9),
+            edge(c, d, t1, 8),
+            edge(b, c, t1, 3),
+        ]);
+        for (from, to) in [(a, b), (a, c), (a, d), (b, d), (d, a), (c, b)] {
+            let (max_flow, _) = compute_flow(
+                &from,
+                &to,
+                &edges,
+                U256::MAX,
+                None,
+                None,
+                FlowAlgorithm::FordFulkerson,
+            )
+            .unwrap();
+            assert!(
+                is_transfer_possible(&from, &to, &edges, max_flow, &Cancellation::default())
+                    .unwrap()
+            );
+            assert!(!is_transfer_possible(
+                &from,
+                &to,
+                &edges,
+                max_flow + U256::from(1u128),
+                &Cancellation::default()
+            )
+            .unwrap());
+        }
+        assert!(
+            is_transfer_possible(&a, &d, &edges, U256::from(0u128), &Cancellation::default())
+                .unwrap()
+        );
+        assert!(
+            !is_transfer_possible(&a, &a, &edges, U256::from(1u128), &Cancellation::default())
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn anytime() {
+        let (a, _, _, d, t1, _) = addresses();
+        let edges = two_paths();
+        for algorithm in [
+            FlowAlgorithm::FordFulkerson,
+            FlowAlgorithm::Dinic,
+            FlowAlgorithm::MinCost,
+        ] {
+            let options = FlowOptions {
+                algorithm,
+                ..FlowOptions::default()
+            };
+            let mut reported = vec![];
+            let result = compute_flow_anytime(&a, &d, &edges, &options, |flow| {
+                reported.push(flow);
+                true
+            })
+            .unwrap();
+            assert_eq!(result.flow, U256::from(12u128));
+            assert_eq!(reported, vec![U256::from(5u128), U256::from(12u128)]);
+
+            let result = compute_flow_anytime(&a, &d, &edges, &options, |_| false).unwrap();
+            assert_eq!(result.flow, U256::from(5u128));
+            assert_eq!(result.transfers, vec![edge(a, d, t1, 5)]);
+            // The flow is not maximal, so there is no minimum cut.
+            assert_eq!(result.min_cut, None);
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let (a, _, _, d, ..) = addresses();
+        let edges = two_paths();
+        let limited = |algorithm, max_explored_accounts, max_decomposition_steps| {
+            let options = FlowOptions {
+                algorithm,
+                limits: FlowLimits {
+                    max_explored_accounts,
+                    max_decomposition_steps,
+                },
+                ..FlowOptions::default()
+            };
+            compute_flow_with_options(&a, &d, &edges, &options).map(|result| result.flow)
+        };
+        for algorithm in FlowAlgorithm::ALL {
+            // All four accounts are reached and the flow consists of three transfers.
+            assert_eq!(limited(algorithm, Some(4), Some(3)), Ok(U256::from(12u128)));
+            assert!(matches!(
+                limited(algorithm, Some(2), None),
+                Err(FlowError::LimitExceeded(_))
+            ));
+            assert!(matches!(
+                limited(algorithm, None, Some(2)),
+                Err(FlowError::LimitExceeded(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn cancelled() {
+        let (a, _, _, d, ..) = addresses();
+        let edges = two_paths();
+        let cancellation = Cancellation::default();
+        cancellation.cancel();
+        for algorithm in [
+            FlowAlgorithm::FordFulkerson,
+            FlowAlgorithm::Dinic,
+            FlowAlgorithm::MinCost,
+        ] {
+            let options = FlowOptions {
+                algorithm,
+                cancellation: cancellation.clone(),
+                ..FlowOptions::default()
+            };
+            // The search stops after the first augmenting path.
+            let result = compute_flow_anytime(&a, &d, &edges, &options, |_| true).unwrap();
+            assert_eq!(result.flow, U256::from(5u128));
+            assert_eq!(result.min_cut, None);
+        }
+        let options = FlowOptions {
+            algorithm: FlowAlgorithm::PushRelabel,
+            cancellation: cancellation.clone(),
+            ..FlowOptions::default()
+        };
+        assert_eq!(
+            compute_flow_with_options(&a, &d, &edges, &options),
+            Err(FlowError::Cancelled)
+        );
+        let sinks = BTreeMap::from([(d, U256::from(1u128))]);
+        assert_eq!(
+            compute_flow_to_sinks(
+                &a,
+                &sinks,
+                &edges,
+                None,
+                None,
+                FlowAlgorithm::Dinic,
+                &cancellation
+            ),
+            Err(FlowError::Cancelled)
+        );
+        assert_eq!(
+            is_transfer_possible(&a, &d, &edges, U256::from(1u128), &cancellation),
+            Err(FlowError::Cancelled)
+        );
+    }
+
+    #[test]
+    fn min_cut() {
+        let (a, b, c, d, t1, t2) = addresses();
+        // a -> b is limited by the edge, a -> c -> d by the balance of c
+        // and a -> d by the balance of a.
+        let edges = build_edges(vec![
+            edge(a, b, t1, 4),
+            edge(a, c, t1, 10),
+            edge(b, d, t2, 9),
+            edge(c, d, t2, 3),
+            edge(a, d, a, 2),
+        ]);
+        for algorithm in [
+            FlowAlgorithm::FordFulkerson,
+            FlowAlgorithm::PushRelabel,
+            FlowAlgorithm::Dinic,
+            FlowAlgorithm::MinCost,
+        ] {
+            let options = FlowOptions {
+                requested_flow: U256::from(10u128),
+                algorithm,
+                ..FlowOptions::default()
+            };
+            let result = compute_flow_with_options(&a, &d, &edges, &options).unwrap();
+            assert_eq!(result.flow, U256::from(9u128));
+            let mut expected = vec![
+                Bottleneck::Balance {
+                    account: a,
+                    token: a,
+                    capacity: U256::from(2u128),
+                },
+                Bottleneck::Balance {
+                    account: c,
+                    token: t2,
+                    capacity: U256::from(3u128),
+                },
+                Bottleneck::Edge(edge(a, b, t1, 4)),
+            ];
+            expected.sort();
+            assert_eq!(result.min_cut, Some(expected));
+            let cut_capacity =
+                result
+                    .min_cut
+                    .unwrap()
+                    .iter()
+                    .fold(U256::from(0u128), |sum, bottleneck| {
+                        sum + match bottleneck {
+                            Bottleneck::Balance { capacity, .. }
+                            | Bottleneck::Trust { capacity, .. } => *capacity,
+                            Bottleneck::Edge(edge) => edge.capacity,
+                        }
+                    });
+            assert_eq!(cut_capacity, result.flow);
+
+            let options = FlowOptions {
+                requested_flow: U256::from(9u128),
+                ..options
+            };
+            let result = compute_flow_with_options(&a, &d, &edges, &options).unwrap();
+            assert_eq!(result.min_cut, None);
+        }
+    }
+
+    /// Returns the incoming minus the outgoing flow of all nodes.
+    fn net_flows(used_edges: &UsedEdges) -> HashMap<Node, U256> {
+        let mut net: HashMap<Node, U256> = HashMap::new();
+        for (from, out) in used_edges {
+            for (to, flow) in out {
+                *net.entry(from.clone()).or_default() -= *flow;
+                *net.entry(to.clone()).or_default() += *flow;
+            }
+        }
+        net
+    }
+
+    fn is_acyclic(used_edges: &UsedEdges) -> bool {
+        // Repeatedly remove nodes without incoming edges.
+        let mut edges = used_edges.clone();
+        loop {
+            let targets = edges
+                .values()
+                .flat_map(|out| out.keys().cloned())
+                .collect::<HashSet<_>>();
+            let count = edges.len();
+            edges.retain(|from, _| targets.contains(from));
+            if edges.is_empty() {
+                return true;
+            }
+            if edges.len() == count {
+                return false;
+            }
+        }
+    }
+
+    fn check_cycle_cancellation(mut used_edges: UsedEdges, source: &Node, sink: &Node) {
+        let before = net_flows(&used_edges);
+        cancel_cycles(&mut used_edges);
+        assert!(is_acyclic(&used_edges));
+        let after = net_flows(&used_edges);
+        for (node, net) in &before {
+            if node != source && node != sink {
+                assert_eq!(*net, U256::from(0u128));
+            }
+            assert_eq!(after.get(node).copied().unwrap_or_default(), *net);
+        }
+    }
+
+    #[test]
+    fn cycle_cancellation() {
+        let (s, a, b, c, t, _) = addresses();
+        let (s, a, b, c, t) = (
+            Node::Node(s),
+            Node::Node(a),
+            Node::Node(b),
+            Node::Node(c),
+            Node::Node(t),
+        );
+        let mut used_edges: UsedEdges = HashMap::new();
+        for (from, to, flow) in [
+            (&s, &a, 5u128),
+            (&a, &b, 7),
+            (&b, &c, 2),
+            (&c, &a, 2),
+            (&b, &t, 5),
+        ] {
+            used_edges
+                .entry(from.clone())
+                .or_default()
+                .insert(to.clone(), U256::from(flow));
+        }
+        check_cycle_cancellation(used_edges.clone(), &s, &t);
+        cancel_cycles(&mut used_edges);
+        assert_eq!(used_edges[&a][&b], U256::from(5u128));
+        assert!(!used_edges.contains_key(&c));
+    }
+
+    #[test]
+    fn cycle_cancellation_random_flows() {
+        let addresses = <[Address; 6]>::from(addresses());
+        let mut state = 17u64;
+        let mut random = |bound: usize| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) as usize % bound
+        };
+        for _ in 0..50 {
+            let edges = build_edges(
+                (0..15)
+                    .map(|_| Edge {
+                        from: addresses[random(6)],
+                        to: addresses[random(6)],
+                        token: addresses[random(6)],
+                        capacity: U256::from(1 + random(20) as u128),
+                    })
+                    .filter(|e| e.from != e.to)
+                    .collect(),
+            );
+            let (source, sink) = (addresses[0], addresses[5]);
+            let (_, used_edges) = augmenting_path_flow(
+                &source,
+                &sink,
+                &mut Adjacencies::new(&edges),
+                None,
+                &mut |_| true,
+            )
+            .unwrap();
+            check_cycle_cancellation(used_edges, &Node::Node(source), &Node::Node(sink));
+        }
     }
 }
@@ -0,0 +1,202 @@
+use crate::graph::adjacencies::Adjacencies;
+use crate::graph::flow::{checked_add, checked_sub, transfers_from_flow, FlowError};
+use crate::graph::network::Network;
+use crate::graph::{dinic, Node};
+use crate::types::edge::EdgeDB;
+use crate::types::{Address, Edge, U256};
+
+/// A maximum flow between a fixed source and sink that is kept up to date
+/// while the edge database changes.
+///
+/// Instead of recomputing the flow from scratch after every update, the
+/// residual network is kept and only the arcs affected by an updated edge are
+/// changed. Flow that exceeds a reduced capacity is removed and the flow is
+/// then augmented again starting from the previous result.
+pub struct IncrementalFlow {
+    source: Address,
+    sink: Address,
+    network: Network,
+    flow: U256,
+}
+
+impl IncrementalFlow {
+    pub fn new(source: &Address, sink: &Address, edges: &EdgeDB) -> Result<Self, FlowError> {
+        let adjacencies = Adjacencies::new(edges);
+        let mut network = Network::build(source, sink, &adjacencies, None);
+        let flow = dinic::max_flow(&mut network, None)?;
+        Ok(IncrementalFlow {
+            source: *source,
+            sink: *sink,
+            network,
+            flow,
+        })
+    }
+
+    pub fn source(&self) -> &Address {
+        &self.source
+    }
+
+    pub fn sink(&self) -> &Address {
+        &self.sink
+    }
+
+    /// The value of the maximum flow.
+    pub fn flow(&self) -> U256 {
+        self.flow
+    }
+
+    /// Updates the flow after the given edges have been changed in `edges`
+    /// (which has to contain the updates already).
+    pub fn update(&mut self, edges: &EdgeDB, updates: &[Edge]) -> Result<(), FlowError> {
+        let adjacencies = Adjacencies::new(edges);
+        // An edge influences the capacities of all three arcs of its trust connection.
+        for update in updates {
+            for node in [
+                Node::Node(update.from),
+                Node::BalanceNode(update.from, update.token),
+                Node::TrustNode(update.to, update.token),
+            ] {
+                let removed = self.network.refresh_node(&node, &self.sink, &adjacencies);
+                self.flow = checked_sub(self.flow, removed)?;
+            }
+        }
+        let additional = dinic::max_flow(&mut self.network, None)?;
+        self.flow = checked_add(self.flow, additional)?;
+        Ok(())
+    }
+
+    /// Returns the transfers for at most `requested_flow`, like `compute_flow`.
+    pub fn transfers(
+        &self,
+        requested_flow: U256,
+        max_transfers: Option<u64>,
+    ) -> Result<(U256, Vec<Edge>), FlowError> {
+        transfers_from_flow(
+            &self.source,
+            &self.sink,
+            self.flow,
+            self.network.used_edges(),
+            requested_flow,
+            max_transfers,
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graph::{compute_flow, FlowAlgorithm};
+
+    /// Simple deterministic pseudo-random numbers.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) % bound
+        }
+    }
+
+    fn address(i: u64) -> Address {
+        Address::from(format!("0x{:040x}", i + 1).as_str())
+    }
+
+    fn random_edge(rng: &mut Lcg, nodes: u64) -> Edge {
+        let from = rng.next(nodes);
+        let to = (from + 1 + rng.next(nodes - 1)) % nodes;
+        let token = if rng.next(2) == 0 { from } else { to };
+        Edge {
+            from: address(from),
+            to: address(to),
+            token: address(token),
+            // Zero capacities remove edges.
+            capacity: U256::from(rng.next(20)),
+        }
+    }
+
+    fn from_scratch(source: &Address, sink: &Address, edges: &EdgeDB) -> U256 {
+        compute_flow(
+            source,
+            sink,
+            edges,
+            U256::MAX,
+            None,
+            None,
+            FlowAlgorithm::FordFulkerson,
+        )
+        .unwrap()
+        .0
+    }
+
+    #[test]
+    fn matches_recomputation() {
+        let mut rng = Lcg(7);
+        let nodes = 12;
+        let (source, sink) = (address(0), address(nodes - 1));
+        let mut edges = EdgeDB::new((0..30).map(|_| random_edge(&mut rng, nodes)).collect());
+        let mut incremental = IncrementalFlow::new(&source, &sink, &edges).unwrap();
+        assert_eq!(incremental.flow(), from_scratch(&source, &sink, &edges));
+        for round in 0..200 {
+            let updates = (0..1 + rng.next(3))
+                .map(|_| {
+                    // Mostly change existing edges, sometimes add new ones.
+                    if rng.next(3) == 0 {
+                        random_edge(&mut rng, nodes)
+                    } else {
+                        let i = rng.next(edges.edge_count() as u64) as usize;
+                        Edge {
+                            capacity: U256::from(rng.next(20)),
+                            ..edges.edges()[i]
+                        }
+                    }
+                })
+                .collect::<Vec<_>>();
+            for update in &updates {
+                edges.update(*update);
+            }
+            incremental.update(&edges, &updates).unwrap();
+            let expected = from_scratch(&source, &sink, &edges);
+            assert_eq!(incremental.flow(), expected, "round {round}");
+            let (flow, transfers) = incremental.transfers(U256::MAX, None).unwrap();
+            assert_eq!(flow, expected);
+            let received = transfers
+                .iter()
+                .filter(|t| t.to == sink)
+                .fold(U256::from(0u128), |sum, t| sum + t.capacity);
+            assert_eq!(received, expected);
+        }
+    }
+
+    #[test]
+    fn sink_becomes_reachable() {
+        let (a, b, c) = (address(0), address(1), address(2));
+        let mut edges = EdgeDB::new(vec![Edge {
+            from: a,
+            to: b,
+            token: a,
+            capacity: U256::from(10u128),
+        }]);
+        let mut incremental = IncrementalFlow::new(&a, &c, &edges).unwrap();
+        assert_eq!(incremental.flow(), U256::from(0u128));
+        let update = Edge {
+            from: b,
+            to: c,
+            token: a,
+            capacity: U256::from(5u128),
+        };
+        edges.update(update);
+        incremental.update(&edges, &[update]).unwrap();
+        assert_eq!(incremental.flow(), U256::from(5u128));
+        let update = Edge {
+            capacity: U256::from(2u128),
+            ..update
+        };
+        edges.update(update);
+        incremental.update(&edges, &[update]).unwrap();
+        assert_eq!(incremental.flow(), U256::from(2u128));
+    }
+}
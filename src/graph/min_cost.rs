@@ -0,0 +1,132 @@
+use crate::graph::cancellation::Cancellation;
+use crate::graph::dinic::{blocking_flow, levels};
+use crate::graph::flow::{checked_add, FlowError};
+use crate::graph::network::{is_reverse, Network};
+use crate::graph::Node;
+use crate::types::U256;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Computes a flow of at most `requested_flow` that is minimal in cost among
+/// all flows of the same value, where each unit of flow costs one per transfer
+/// it uses. This prefers short transfer chains.
+///
+/// Uses successive shortest paths with Dijkstra and node potentials, where all
+/// shortest paths of the same cost are saturated at once using blocking flows.
+/// If `max_cost` is given, only paths with at most that many transfers are used.
+/// `progress` is called with the value of the flow after each phase and the computation
+/// stops early if it returns false or if `cancellation` is cancelled.
+/// Returns the value of the flow, the flow itself is left in the
+/// residual capacities of the network and is free of cycles.
+pub fn min_cost_flow(
+    network: &mut Network,
+    requested_flow: U256,
+    max_cost: Option<i64>,
+    cancellation: &Cancellation,
+    progress: &mut dyn FnMut(U256) -> bool,
+) -> Result<U256, FlowError> {
+    let sink = match network.sink {
+        Some(sink) => sink,
+        None => return Ok(U256::from(0u128)),
+    };
+    let costs = arc_costs(network);
+    let mut potential = vec![0i64; network.node_count()];
+    let mut flow = U256::from(0u128);
+    while flow < requested_flow {
+        let distance = distances(network, &costs, &potential);
+        if distance[sink] == i64::MAX {
+            break;
+        }
+        // Nodes that are not reachable now will never be reachable again,
+        // so we do not need to keep their potentials up to date.
+        for (p, d) in potential.iter_mut().zip(&distance) {
+            if *d != i64::MAX {
+                *p += *d;
+            }
+        }
+        if let Some(max) = max_cost {
+            if potential[sink] > max {
+                break;
+            }
+        }
+        let admissible = |network: &Network, arc: usize| {
+            let from = network.arc_from(arc);
+            let to = network.arcs[arc].to;
+            distance[from] != i64::MAX
+                && distance[to] != i64::MAX
+                && costs[arc] + potential[from] - potential[to] == 0
+        };
+        let mut phase_network_flow = U256::from(0u128);
+        while let Some(mut level) = levels(network, sink, admissible) {
+            let remaining = requested_flow - flow - phase_network_flow;
+            phase_network_flow = checked_add(
+                phase_network_flow,
+                blocking_flow(
+                    network,
+                    sink,
+                    &mut level,
+                    remaining,
+                    admissible,
+                    cancellation,
+                )?,
+            )?;
+            if phase_network_flow == requested_flow - flow || cancellation.is_cancelled() {
+                break;
+            }
+        }
+        flow = checked_add(flow, phase_network_flow)?;
+        if !progress(flow) || cancellation.is_cancelled() {
+            break;
+        }
+    }
+    network.cancel_cycles();
+    Ok(flow)
+}
+
+/// One per unit of flow for each arc that corresponds to a transfer,
+/// i.e. from a balance node to a trust node.
+fn arc_costs(network: &Network) -> Vec<i64> {
+    (0..network.arcs.len())
+        .map(|arc| {
+            let (from, to) = if is_reverse(arc) {
+                (network.arcs[arc].to, network.arc_from(arc))
+            } else {
+                (network.arc_from(arc), network.arcs[arc].to)
+            };
+            let cost = match (&network.nodes[from], &network.nodes[to]) {
+                (Node::BalanceNode(..), Node::TrustNode(..)) => 1,
+                _ => 0,
+            };
+            if is_reverse(arc) {
+                -cost
+            } else {
+                cost
+            }
+        })
+        .collect()
+}
+
+/// Dijkstra's algorithm from the source in the residual network using the
+/// reduced costs, which are non-negative. Unreachable nodes get `i64::MAX`.
+fn distances(network: &Network, costs: &[i64], potential: &[i64]) -> Vec<i64> {
+    let mut distance = vec![i64::MAX; network.node_count()];
+    distance[network.source] = 0;
+    let mut queue = BinaryHeap::from([Reverse((0, network.source))]);
+    while let Some(Reverse((d, u))) = queue.pop() {
+        if d > distance[u] {
+            continue;
+        }
+        for arc in &network.outgoing[u] {
+            if network.arcs[*arc].residual.is_zero() {
+                continue;
+            }
+            let v = network.arcs[*arc].to;
+            let reduced_cost = costs[*arc] + potential[u] - potential[v];
+            if d + reduced_cost < distance[v] {
+                distance[v] = d + reduced_cost;
+                queue.push(Reverse((distance[v], v)));
+            }
+        }
+    }
+    distance
+}
@@ -2,7 +2,17 @@ use crate::types::Address;
 use std::fmt::{Display, Formatter};
 
 mod adjacencies;
+mod cancellation;
+mod dinic;
 mod flow;
+mod incremental;
+mod min_cost;
+mod network;
+mod push_relabel;
+mod reference;
+mod routes;
+mod simulation;
+mod wrapping;
 
 // An edge from the capacity network is
 // from, token, to -> capacity
@@ -23,6 +33,10 @@ pub enum Node {
     Node(Address),
     BalanceNode(Address, Address),
     TrustNode(Address, Address),
+    /// Virtual node all sinks of a multi-sink flow are connected to.
+    SuperSink,
+    /// Virtual node connected to all sources of a multi-source flow.
+    SuperSource,
 }
 
 pub fn node_as_address(node: &Node) -> &Address {
@@ -47,9 +61,34 @@ impl Display for Node {
             Node::Node(address) => write!(f, "{address}"),
             Node::BalanceNode(from, token) => write!(f, "(bal {from} x {token})"),
             Node::TrustNode(to, token) => write!(f, "(trust {to} x {token})"),
+            Node::SuperSink => write!(f, "(super sink)"),
+            Node::SuperSource => write!(f, "(super source)"),
         }
     }
 }
 
+pub use crate::graph::adjacencies::TokenFilter;
+pub use crate::graph::cancellation::Cancellation;
 pub use crate::graph::flow::compute_flow;
+pub use crate::graph::flow::compute_flow_anytime;
+pub use crate::graph::flow::compute_flow_from_sources;
+pub use crate::graph::flow::compute_flow_to_sinks;
+pub use crate::graph::flow::compute_flow_with_options;
+pub use crate::graph::flow::is_transfer_possible;
 pub use crate::graph::flow::transfers_to_dot;
+pub use crate::graph::flow::Bottleneck;
+pub use crate::graph::flow::FlowAlgorithm;
+pub use crate::graph::flow::FlowError;
+pub use crate::graph::flow::FlowLimits;
+pub use crate::graph::flow::FlowOptions;
+pub use crate::graph::flow::FlowResult;
+pub use crate::graph::incremental::IncrementalFlow;
+pub use crate::graph::network::NetworkStatistics;
+pub use crate::graph::reference::{
+    is_reference_checkable, reference_max_flow, REFERENCE_MAX_EDGES,
+};
+pub use crate::graph::routes::{compute_alternate_routes, Route};
+pub use crate::graph::simulation::{
+    repair_transfer_order, simulate_transfer_through, verify_flow, SimulationError,
+};
+pub use crate::graph::wrapping::deliver_wrapped;
@@ -0,0 +1,521 @@
+use crate::graph::adjacencies::Adjacencies;
+use crate::graph::Node;
+use crate::types::{Address, U256};
+#[cfg(feature = "native")]
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+/// An explicit residual network over all nodes reachable from the source.
+///
+/// The augmenting path algorithm explores the graph lazily, but algorithms
+/// like push-relabel need random access to all nodes and arcs.
+pub struct Network {
+    pub nodes: Vec<Node>,
+    pub source: usize,
+    /// None if the sink is not reachable from the source.
+    pub sink: Option<usize>,
+    /// Arcs are stored in pairs: arc `i ^ 1` is the reverse arc of arc `i`
+    /// and even arcs are the arcs of the original graph.
+    pub arcs: Vec<ResidualArc>,
+    /// Indices into `arcs` for each node.
+    pub outgoing: Vec<Vec<usize>>,
+    index: HashMap<Node, usize>,
+    /// The number of account nodes.
+    accounts: usize,
+}
+
+/// The size of a network before and after pruning.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkStatistics {
+    /// Nodes and arcs reachable from the source.
+    pub explored_nodes: usize,
+    pub explored_arcs: usize,
+    /// Nodes and arcs that lie on a path from the source to the sink.
+    pub relevant_nodes: usize,
+    pub relevant_arcs: usize,
+}
+
+pub struct ResidualArc {
+    pub to: usize,
+    pub capacity: U256,
+    pub residual: U256,
+}
+
+impl Network {
+    /// Builds the network of all nodes reachable from `source`.
+    /// If `max_distance` is given, only arcs that lie on a path from the source
+    /// to the sink with at most that many hops (in the capacity network)
+    /// are usable, all other arcs have zero capacity.
+    pub fn build(
+        source: &Address,
+        sink: &Address,
+        adjacencies: &Adjacencies,
+        max_distance: Option<u64>,
+    ) -> Network {
+        // * 3 because we have three edges per trust connection (two intermediate nodes).
+        let max_length = max_distance.map(|max| max as usize * 3);
+        let mut network =
+            Network::reachable_from(source, &Node::Node(*sink), adjacencies, max_length);
+        if let Some(max) = max_length {
+            network.restrict_path_length(max);
+        }
+        network
+    }
+
+    /// Builds the network of all nodes reachable from `source` and adds a super sink
+    /// as the sink of the network. Each of the `sinks` is connected to the super sink
+    /// with its target value as capacity.
+    pub fn build_to_sinks(
+        source: &Address,
+        sinks: &BTreeMap<Address, U256>,
+        adjacencies: &Adjacencies,
+        max_distance: Option<u64>,
+    ) -> Network {
+        let max_length = max_distance.map(|max| max as usize * 3);
+        let mut network =
+            Network::reachable_from(source, &Node::SuperSink, adjacencies, max_length);
+        let super_sink = network.add_node(&Node::SuperSink);
+        for (sink, value) in sinks {
+            if let Some(sink) = network.index.get(&Node::Node(*sink)).copied() {
+                network.add_arc(sink, super_sink, *value);
+            }
+        }
+        network.sink = Some(super_sink);
+        if let Some(max) = max_length {
+            // One more arc for the connection to the super sink.
+            network.restrict_path_length(max + 1);
+        }
+        network
+    }
+
+    /// Builds the network of all nodes reachable from any of the `sources` with a super
+    /// source as the source of the network. The super source is connected to each of the
+    /// `sources` with the sum of the capacities of their outgoing arcs.
+    /// Sources that are equal to the sink are ignored.
+    pub fn build_from_sources(
+        sources: &[Address],
+        sink: &Address,
+        adjacencies: &Adjacencies,
+        max_distance: Option<u64>,
+    ) -> Network {
+        let max_length = max_distance.map(|max| max as usize * 3);
+        let mut network = Network::empty();
+        let super_source = network.add_node(&Node::SuperSource);
+        let mut frontier = vec![];
+        for source in sources.iter().filter(|source| *source != sink) {
+            let node = Node::Node(*source);
+            if !network.index.contains_key(&node) {
+                frontier.push(network.add_node(&node));
+            }
+        }
+        network.explore(
+            &Node::Node(*sink),
+            adjacencies,
+            frontier.clone(),
+            max_length,
+        );
+        for source in frontier {
+            let capacity = network.outgoing[source]
+                .iter()
+                .filter(|arc| !is_reverse(**arc))
+                .fold(U256::from(0u128), |sum, arc| {
+                    sum.saturating_add(network.arcs[*arc].capacity)
+                });
+            network.add_arc(super_source, source, capacity);
+        }
+        if let Some(max) = max_length {
+            // One more arc for the connection from the super source.
+            network.restrict_path_length(max + 1);
+        }
+        network
+    }
+
+    fn reachable_from(
+        source: &Address,
+        sink_node: &Node,
+        adjacencies: &Adjacencies,
+        max_length: Option<usize>,
+    ) -> Network {
+        let mut network = Network::empty();
+        let source_node = Node::Node(*source);
+        network.add_node(&source_node);
+        network.explore(sink_node, adjacencies, vec![0], max_length);
+        network
+    }
+
+    fn empty() -> Network {
+        Network {
+            nodes: vec![],
+            source: 0,
+            sink: None,
+            arcs: vec![],
+            outgoing: vec![],
+            index: HashMap::new(),
+            accounts: 0,
+        }
+    }
+
+    /// The number of accounts in the network.
+    pub fn accounts(&self) -> usize {
+        self.accounts
+    }
+
+    /// Adds all nodes reachable from the nodes in `frontier`
+    /// and the arcs between them. Does not explore beyond the sink, nor beyond the
+    /// level at which the maximum number of accounts of `adjacencies` is exceeded.
+    /// The outgoing edges of each breadth-first search level are computed in parallel.
+    fn explore(
+        &mut self,
+        sink_node: &Node,
+        adjacencies: &Adjacencies,
+        mut frontier: Vec<usize>,
+        max_length: Option<usize>,
+    ) {
+        let mut depth = 0;
+        while !frontier.is_empty()
+            && max_length.is_none_or(|max| depth < max)
+            && adjacencies.check_explored(self.accounts).is_ok()
+        {
+            frontier.retain(|node| self.nodes[*node] != *sink_node);
+            #[cfg(feature = "native")]
+            let frontier_iter = frontier.par_iter();
+            #[cfg(not(feature = "native"))]
+            let frontier_iter = frontier.iter();
+            let outgoing = frontier_iter
+                .map(|from| {
+                    adjacencies.outgoing_edges_sorted_by_capacity_uncached(&self.nodes[*from])
+                })
+                .collect::<Vec<_>>();
+            let mut next = vec![];
+            for (from, edges) in frontier.into_iter().zip(outgoing) {
+                for (target, capacity) in edges {
+                    let to = match self.index.get(&target) {
+                        Some(to) => *to,
+                        None => {
+                            let to = self.add_node(&target);
+                            next.push(to);
+                            to
+                        }
+                    };
+                    self.add_arc(from, to, capacity);
+                }
+            }
+            frontier = next;
+            depth += 1;
+        }
+        self.sink = self.index.get(sink_node).copied();
+    }
+
+    /// Re-reads the outgoing arcs of `node` from `adjacencies` after the
+    /// underlying edges changed, keeping as much of the current flow as possible.
+    /// Flow above a reduced capacity is removed along flow paths through the arc
+    /// and newly reachable nodes are added to the network.
+    /// Returns the amount by which the value of the flow decreased.
+    pub fn refresh_node(&mut self, node: &Node, sink: &Address, adjacencies: &Adjacencies) -> U256 {
+        let from = match self.index.get(node) {
+            Some(from) if Some(*from) != self.sink => *from,
+            _ => return U256::from(0u128),
+        };
+        let mut capacities: HashMap<Node, U256> = adjacencies
+            .outgoing_edges_sorted_by_capacity_uncached(node)
+            .into_iter()
+            .collect();
+        let mut removed = U256::from(0u128);
+        for i in 0..self.outgoing[from].len() {
+            let arc = self.outgoing[from][i];
+            if is_reverse(arc) {
+                continue;
+            }
+            let capacity = capacities
+                .remove(&self.nodes[self.arcs[arc].to])
+                .unwrap_or_default();
+            let flow = self.flow(arc);
+            if capacity < flow {
+                self.remove_flow(arc, flow - capacity);
+                removed += flow - capacity;
+            }
+            let flow = self.flow(arc);
+            self.arcs[arc].capacity = capacity;
+            self.arcs[arc].residual = capacity - flow;
+        }
+        let mut new_nodes = vec![];
+        for (target, capacity) in capacities {
+            let to = match self.index.get(&target) {
+                Some(to) => *to,
+                None => {
+                    let to = self.add_node(&target);
+                    new_nodes.push(to);
+                    to
+                }
+            };
+            self.add_arc(from, to, capacity);
+        }
+        self.explore(&Node::Node(*sink), adjacencies, new_nodes, None);
+        removed
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn arc_from(&self, arc: usize) -> usize {
+        self.arcs[arc ^ 1].to
+    }
+
+    /// Sends `amount` along the arc, updating the residual capacities of the arc
+    /// and its reverse.
+    pub fn push(&mut self, arc: usize, amount: U256) {
+        self.arcs[arc].residual -= amount;
+        self.arcs[arc ^ 1].residual += amount;
+    }
+
+    /// Sets the capacity of all arcs that are not on a path from the source
+    /// to the sink of length at most `max_length` to zero.
+    fn restrict_path_length(&mut self, max_length: usize) {
+        let from_source = self.distances(self.source, false);
+        let to_sink = match self.sink {
+            Some(sink) => self.distances(sink, true),
+            None => vec![usize::MAX; self.node_count()],
+        };
+        for arc in (0..self.arcs.len()).filter(|arc| !is_reverse(*arc)) {
+            let (from, to) = (self.arc_from(arc), self.arcs[arc].to);
+            if from_source[from] == usize::MAX
+                || to_sink[to] == usize::MAX
+                || from_source[from] + 1 + to_sink[to] > max_length
+            {
+                self.arcs[arc].capacity = U256::from(0u128);
+                self.arcs[arc].residual = U256::from(0u128);
+            }
+        }
+    }
+
+    /// Removes all nodes that are not both reachable from the source and able
+    /// to reach the sink, together with their arcs and all arcs without capacity.
+    /// Uses a forward search from the source and a backward search from the sink.
+    /// Such nodes cannot carry flow, but the algorithms would still visit them.
+    /// Has to be called before a flow is computed. Must not be combined with
+    /// `refresh_node`, since removed nodes can become relevant after an update.
+    pub fn prune(&mut self) -> NetworkStatistics {
+        let mut statistics = NetworkStatistics {
+            explored_nodes: self.node_count(),
+            explored_arcs: self.arcs.len() / 2,
+            ..NetworkStatistics::default()
+        };
+        let from_source = self.distances(self.source, false);
+        let to_sink = match self.sink {
+            Some(sink) => self.distances(sink, true),
+            None => vec![usize::MAX; self.node_count()],
+        };
+        let mut pruned = Network::empty();
+        let mut new_index = vec![None; self.node_count()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            if i == self.source || (from_source[i] != usize::MAX && to_sink[i] != usize::MAX) {
+                new_index[i] = Some(pruned.add_node(node));
+            }
+        }
+        for arc in (0..self.arcs.len()).step_by(2) {
+            let (from, to) = (self.arc_from(arc), self.arcs[arc].to);
+            if let (Some(from), Some(to), true) = (
+                new_index[from],
+                new_index[to],
+                !self.arcs[arc].capacity.is_zero(),
+            ) {
+                pruned.add_arc(from, to, self.arcs[arc].capacity);
+            }
+        }
+        pruned.source = new_index[self.source].unwrap();
+        pruned.sink = self.sink.and_then(|sink| new_index[sink]);
+        *self = pruned;
+        statistics.relevant_nodes = self.node_count();
+        statistics.relevant_arcs = self.arcs.len() / 2;
+        statistics
+    }
+
+    /// Returns all nodes reachable from the source in the residual network.
+    pub fn residual_reachable(&self) -> HashSet<Node> {
+        self.distances(self.source, false)
+            .into_iter()
+            .zip(&self.nodes)
+            .filter(|(distance, _)| *distance != usize::MAX)
+            .map(|(_, node)| node.clone())
+            .collect()
+    }
+
+    /// Breadth-first search along arcs with residual capacity,
+    /// against the direction of the arcs if `reverse` is true.
+    fn distances(&self, start: usize, reverse: bool) -> Vec<usize> {
+        let mut distance = vec![usize::MAX; self.node_count()];
+        distance[start] = 0;
+        let mut queue = VecDeque::from([start]);
+        while let Some(u) = queue.pop_front() {
+            for arc in &self.outgoing[u] {
+                let v = self.arcs[*arc].to;
+                let residual = if reverse {
+                    self.arcs[*arc ^ 1].residual
+                } else {
+                    self.arcs[*arc].residual
+                };
+                if distance[v] == usize::MAX && !residual.is_zero() {
+                    distance[v] = distance[u] + 1;
+                    queue.push_back(v);
+                }
+            }
+        }
+        distance
+    }
+
+    /// Removes circulations from the flow, so that it can be decomposed
+    /// into paths from the source to the sink.
+    pub fn cancel_cycles(&mut self) {
+        const UNVISITED: u8 = 0;
+        const ON_STACK: u8 = 1;
+        const DONE: u8 = 2;
+        let n = self.node_count();
+        let mut state = vec![UNVISITED; n];
+        let mut current = vec![0usize; n];
+        for start in 0..n {
+            if state[start] != UNVISITED {
+                continue;
+            }
+            state[start] = ON_STACK;
+            let mut stack = vec![start];
+            let mut stack_arcs: Vec<usize> = vec![];
+            while let Some(&u) = stack.last() {
+                if current[u] == self.outgoing[u].len() {
+                    state[u] = DONE;
+                    stack.pop();
+                    stack_arcs.pop();
+                    continue;
+                }
+                let arc = self.outgoing[u][current[u]];
+                let v = self.arcs[arc].to;
+                if is_reverse(arc) || self.flow(arc).is_zero() || state[v] == DONE {
+                    current[u] += 1;
+                } else if state[v] == UNVISITED {
+                    state[v] = ON_STACK;
+                    stack.push(v);
+                    stack_arcs.push(arc);
+                } else {
+                    let pos = stack.iter().rposition(|w| *w == v).unwrap();
+                    let cycle = stack_arcs[pos..]
+                        .iter()
+                        .copied()
+                        .chain([arc])
+                        .collect::<Vec<_>>();
+                    let amount = cycle.iter().map(|a| self.flow(*a)).min().unwrap();
+                    for a in &cycle {
+                        self.push(*a ^ 1, amount);
+                    }
+                    for w in stack.drain(pos + 1..) {
+                        state[w] = UNVISITED;
+                    }
+                    stack_arcs.truncate(pos);
+                }
+            }
+        }
+    }
+
+    /// Returns the flow on the original arcs in the format used by the
+    /// rest of the flow computation, dropping flow that is not reachable
+    /// from the source (i.e. circulations).
+    pub fn used_edges(&self) -> HashMap<Node, HashMap<Node, U256>> {
+        let mut reachable = vec![false; self.node_count()];
+        reachable[self.source] = true;
+        let mut queue = VecDeque::from([self.source]);
+        while let Some(n) = queue.pop_front() {
+            for arc in &self.outgoing[n] {
+                let to = self.arcs[*arc].to;
+                if !is_reverse(*arc) && !self.flow(*arc).is_zero() && !reachable[to] {
+                    reachable[to] = true;
+                    queue.push_back(to);
+                }
+            }
+        }
+
+        let mut used_edges: HashMap<Node, HashMap<Node, U256>> = HashMap::new();
+        for arc in (0..self.arcs.len()).step_by(2) {
+            let from = self.arc_from(arc);
+            let flow = self.flow(arc);
+            if reachable[from] && !flow.is_zero() {
+                used_edges
+                    .entry(self.nodes[from].clone())
+                    .or_default()
+                    .insert(self.nodes[self.arcs[arc].to].clone(), flow);
+            }
+        }
+        used_edges
+    }
+
+    fn flow(&self, arc: usize) -> U256 {
+        self.arcs[arc].capacity - self.arcs[arc].residual
+    }
+
+    /// Removes `amount` of flow from the arc and from flow paths from the
+    /// source to the arc and from the arc to the sink. Requires the flow to be acyclic.
+    fn remove_flow(&mut self, arc: usize, amount: U256) {
+        let mut remaining = amount;
+        while !remaining.is_zero() {
+            let mut path = vec![];
+            let mut u = self.arc_from(arc);
+            while u != self.source {
+                let incoming = self.outgoing[u]
+                    .iter()
+                    .map(|a| *a ^ 1)
+                    .find(|a| !is_reverse(*a) && !self.flow(*a).is_zero())
+                    .expect("Flow conservation violated.");
+                path.push(incoming);
+                u = self.arc_from(incoming);
+            }
+            path.push(arc);
+            let mut v = self.arcs[arc].to;
+            while Some(v) != self.sink {
+                let outgoing = *self.outgoing[v]
+                    .iter()
+                    .find(|a| !is_reverse(**a) && !self.flow(**a).is_zero())
+                    .expect("Flow conservation violated.");
+                path.push(outgoing);
+                v = self.arcs[outgoing].to;
+            }
+            let amount = path
+                .iter()
+                .map(|a| self.flow(*a))
+                .min()
+                .unwrap()
+                .min(remaining);
+            for a in &path {
+                self.push(*a ^ 1, amount);
+            }
+            remaining -= amount;
+        }
+    }
+
+    fn add_node(&mut self, node: &Node) -> usize {
+        let i = self.nodes.len();
+        self.nodes.push(node.clone());
+        self.outgoing.push(vec![]);
+        self.index.insert(node.clone(), i);
+        self.accounts += matches!(node, Node::Node(_)) as usize;
+        i
+    }
+
+    fn add_arc(&mut self, from: usize, to: usize, capacity: U256) {
+        let i = self.arcs.len();
+        self.arcs.push(ResidualArc {
+            to,
+            capacity,
+            residual: capacity,
+        });
+        self.arcs.push(ResidualArc {
+            to: from,
+            capacity: U256::from(0u128),
+            residual: U256::from(0u128),
+        });
+        self.outgoing[from].push(i);
+        self.outgoing[to].push(i + 1);
+    }
+}
+
+/// Returns true if the arc is the reverse arc of an arc of the original graph.
+pub fn is_reverse(arc: usize) -> bool {
+    arc & 1 == 1
+}
@@ -0,0 +1,262 @@
+use crate::graph::cancellation::Cancellation;
+use crate::graph::flow::{checked_add, FlowError};
+use crate::graph::network::{is_reverse, Network};
+use crate::types::U256;
+use std::cmp::max;
+use std::collections::VecDeque;
+
+/// Computes a maximum flow using the highest-label push-relabel algorithm
+/// with the gap and global relabeling heuristics.
+///
+/// The first phase computes a maximum preflow, the second phase returns
+/// the excess that cannot reach the sink back to the source.
+/// Returns the value of the flow, the flow itself is left in the
+/// residual capacities of the network and is free of cycles.
+/// There is no valid flow before the end, so if `cancellation` is cancelled,
+/// the computation stops with an error.
+pub fn max_flow(network: &mut Network, cancellation: &Cancellation) -> Result<U256, FlowError> {
+    let sink = match network.sink {
+        Some(sink) => sink,
+        None => return Ok(U256::from(0u128)),
+    };
+    let mut state = PushRelabel::new(network, sink);
+    state.saturate_source_arcs()?;
+    state.max_preflow(cancellation)?;
+    state.network.cancel_cycles();
+    state.return_excess();
+    Ok(state.excess[sink])
+}
+
+struct PushRelabel<'a> {
+    network: &'a mut Network,
+    sink: usize,
+    /// Number of nodes, also the height of the source and of all nodes
+    /// that cannot reach the sink any more.
+    n: usize,
+    height: Vec<usize>,
+    excess: Vec<U256>,
+    /// Index of the next arc to try in `network.outgoing` for each node.
+    current: Vec<usize>,
+    /// Number of nodes at each height below `n`, used to detect gaps.
+    count: Vec<usize>,
+    /// Nodes at each height below `n`, may contain stale entries.
+    members: Vec<Vec<usize>>,
+    max_height: usize,
+    /// Active nodes by height, may contain stale entries.
+    active: Vec<Vec<usize>>,
+    highest_active: usize,
+    work_since_global_relabel: usize,
+}
+
+impl<'a> PushRelabel<'a> {
+    fn new(network: &'a mut Network, sink: usize) -> PushRelabel<'a> {
+        let n = network.node_count();
+        PushRelabel {
+            network,
+            sink,
+            n,
+            height: vec![0; n],
+            excess: vec![U256::from(0u128); n],
+            current: vec![0; n],
+            count: vec![0; n],
+            members: vec![vec![]; n],
+            max_height: 0,
+            active: vec![vec![]; n],
+            highest_active: 0,
+            work_since_global_relabel: 0,
+        }
+    }
+
+    fn saturate_source_arcs(&mut self) -> Result<(), FlowError> {
+        let source = self.network.source;
+        for i in 0..self.network.outgoing[source].len() {
+            let arc = self.network.outgoing[source][i];
+            let amount = self.network.arcs[arc].residual;
+            if !amount.is_zero() {
+                let to = self.network.arcs[arc].to;
+                self.network.push(arc, amount);
+                self.excess[to] = checked_add(self.excess[to], amount)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn max_preflow(&mut self, cancellation: &Cancellation) -> Result<(), FlowError> {
+        self.global_relabel();
+        loop {
+            if cancellation.is_cancelled() {
+                return Err(FlowError::Cancelled);
+            }
+            while self.highest_active > 0 && self.active[self.highest_active].is_empty() {
+                self.highest_active -= 1;
+            }
+            let u = match self.active[self.highest_active].pop() {
+                Some(u) => u,
+                None => return Ok(()),
+            };
+            if self.height[u] != self.highest_active || self.excess[u].is_zero() {
+                // Stale entry, the node was lifted by the gap heuristic.
+                continue;
+            }
+            self.discharge(u)?;
+            if self.work_since_global_relabel > self.n {
+                self.global_relabel();
+            }
+        }
+    }
+
+    fn discharge(&mut self, u: usize) -> Result<(), FlowError> {
+        while !self.excess[u].is_zero() {
+            if self.current[u] == self.network.outgoing[u].len() {
+                self.relabel(u);
+                if self.height[u] >= self.n {
+                    return Ok(());
+                }
+                continue;
+            }
+            let arc = self.network.outgoing[u][self.current[u]];
+            let v = self.network.arcs[arc].to;
+            let residual = self.network.arcs[arc].residual;
+            if !residual.is_zero() && self.height[u] == self.height[v] + 1 {
+                let amount = self.excess[u].min(residual);
+                self.network.push(arc, amount);
+                self.excess[u] -= amount;
+                if v != self.sink && self.excess[v].is_zero() {
+                    self.activate(v);
+                }
+                self.excess[v] = checked_add(self.excess[v], amount)?;
+            } else {
+                self.current[u] += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn relabel(&mut self, u: usize) {
+        self.work_since_global_relabel += 1;
+        let old = self.height[u];
+        self.count[old] -= 1;
+        if self.count[old] == 0 {
+            // Gap: nodes above `old` cannot reach the sink any more.
+            for h in old + 1..=self.max_height {
+                for w in std::mem::take(&mut self.members[h]) {
+                    if self.height[w] == h {
+                        self.height[w] = self.n;
+                        self.count[h] -= 1;
+                    }
+                }
+            }
+            self.max_height = old - 1;
+            self.height[u] = self.n;
+            return;
+        }
+        let new_height = self.network.outgoing[u]
+            .iter()
+            .filter(|arc| !self.network.arcs[**arc].residual.is_zero())
+            .map(|arc| self.height[self.network.arcs[*arc].to] + 1)
+            .min()
+            .unwrap_or(self.n);
+        self.current[u] = 0;
+        if new_height >= self.n {
+            self.height[u] = self.n;
+        } else {
+            self.set_height(u, new_height);
+        }
+    }
+
+    fn set_height(&mut self, u: usize, h: usize) {
+        self.height[u] = h;
+        self.count[h] += 1;
+        self.members[h].push(u);
+        self.max_height = max(self.max_height, h);
+    }
+
+    fn activate(&mut self, u: usize) {
+        let h = self.height[u];
+        if h < self.n {
+            self.active[h].push(u);
+            self.highest_active = max(self.highest_active, h);
+        }
+    }
+
+    /// Sets all heights to the exact distance to the sink in the residual network.
+    fn global_relabel(&mut self) {
+        self.work_since_global_relabel = 0;
+        self.height.iter_mut().for_each(|h| *h = self.n);
+        self.count.iter_mut().for_each(|c| *c = 0);
+        self.members.iter_mut().for_each(|m| m.clear());
+        self.active.iter_mut().for_each(|a| a.clear());
+        self.current.iter_mut().for_each(|c| *c = 0);
+        self.max_height = 0;
+        self.highest_active = 0;
+
+        self.set_height(self.sink, 0);
+        let mut queue = VecDeque::from([self.sink]);
+        while let Some(v) = queue.pop_front() {
+            for i in 0..self.network.outgoing[v].len() {
+                let arc = self.network.outgoing[v][i];
+                let u = self.network.arcs[arc].to;
+                if self.height[u] == self.n
+                    && u != self.network.source
+                    && !self.network.arcs[arc ^ 1].residual.is_zero()
+                {
+                    self.set_height(u, self.height[v] + 1);
+                    if !self.excess[u].is_zero() {
+                        self.activate(u);
+                    }
+                    queue.push_back(u);
+                }
+            }
+        }
+    }
+
+    /// Turns the preflow into a flow by sending the excess of all nodes
+    /// back along the arcs it came in. Requires the flow to be acyclic.
+    fn return_excess(&mut self) {
+        for u in self.reverse_topological_order() {
+            if u == self.sink || u == self.network.source {
+                continue;
+            }
+            for i in 0..self.network.outgoing[u].len() {
+                if self.excess[u].is_zero() {
+                    break;
+                }
+                let arc = self.network.outgoing[u][i];
+                // Odd arcs are reverse arcs, their residual capacity is the
+                // flow on the original arc into `u`.
+                let residual = self.network.arcs[arc].residual;
+                if is_reverse(arc) && !residual.is_zero() {
+                    let amount = self.excess[u].min(residual);
+                    let v = self.network.arcs[arc].to;
+                    self.network.push(arc, amount);
+                    self.excess[u] -= amount;
+                    self.excess[v] += amount;
+                }
+            }
+        }
+    }
+
+    /// Returns all nodes reachable from the source via arcs carrying flow,
+    /// such that each node comes before its predecessors.
+    fn reverse_topological_order(&self) -> Vec<usize> {
+        let mut visited = vec![false; self.n];
+        let mut order = vec![];
+        let source = self.network.source;
+        visited[source] = true;
+        let mut stack = vec![(source, 0)];
+        while let Some((u, i)) = stack.pop() {
+            if i == self.network.outgoing[u].len() {
+                order.push(u);
+                continue;
+            }
+            stack.push((u, i + 1));
+            let arc = self.network.outgoing[u][i];
+            let v = self.network.arcs[arc].to;
+            if !is_reverse(arc) && !visited[v] && !self.network.arcs[arc ^ 1].residual.is_zero() {
+                visited[v] = true;
+                stack.push((v, 0));
+            }
+        }
+        order
+    }
+}
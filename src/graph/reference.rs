@@ -0,0 +1,140 @@
+//! A slow but simple max flow implementation to cross-check the flow algorithms with:
+//! Edmonds-Karp on the flow network of all edges (see the comment in `graph`), built
+//! up front and with `BigUint` capacities so that nothing saturates.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use num_bigint::BigUint;
+
+use crate::graph::{FlowOptions, Node};
+use crate::types::edge::EdgeDB;
+use crate::types::Address;
+
+/// The maximum number of edges of graphs the flows are cross-checked on.
+pub const REFERENCE_MAX_EDGES: usize = 10_000;
+
+/// Whether flows computed with `options` can be compared with `reference_max_flow`:
+/// the graph is small and no option restricts the paths or transfers.
+pub fn is_reference_checkable(edges: &EdgeDB, options: &FlowOptions) -> bool {
+    edges.edge_count() <= REFERENCE_MAX_EDGES
+        && options.max_distance.is_none()
+        && options.max_transfers.is_none()
+        && options.token_filter == Default::default()
+        && options.max_staleness_blocks.is_none()
+        && !options.exclude_stopped
+}
+
+/// The maximum flow from `source` to `sink` without limits on the hops or transfers.
+pub fn reference_max_flow(source: &Address, sink: &Address, edges: &EdgeDB) -> BigUint {
+    let mut ids = HashMap::<Node, usize>::new();
+    let mut id = |node: Node| {
+        let next = ids.len();
+        *ids.entry(node).or_insert(next)
+    };
+    // The capacities of the flow network, by (from, to).
+    let mut capacities = BTreeMap::<(usize, usize), BigUint>::new();
+    for edge in edges.effective_edges() {
+        let capacity = BigUint::from(edge.capacity);
+        let from = id(Node::Node(edge.from));
+        let balance = id(Node::BalanceNode(edge.from, edge.token));
+        let trust = id(Node::TrustNode(edge.to, edge.token));
+        let to = id(Node::Node(edge.to));
+        let sending = capacities.entry((from, balance)).or_default();
+        *sending = sending.clone().max(capacity.clone());
+        capacities.insert((balance, trust), capacity.clone());
+        // Owners (and wrappers) accept any amount of their own tokens.
+        let receiving = capacities.entry((trust, to)).or_default();
+        if edge.to == edge.token || edges.wrapped_token(&edge.to) == Some(edge.token) {
+            *receiving += capacity;
+        } else {
+            *receiving = receiving.clone().max(capacity);
+        }
+    }
+    let (Some(&source), Some(&sink)) = (ids.get(&Node::Node(*source)), ids.get(&Node::Node(*sink)))
+    else {
+        return BigUint::default();
+    };
+    if source == sink {
+        return BigUint::default();
+    }
+
+    let mut neighbors = vec![vec![]; ids.len()];
+    for &(from, to) in capacities.keys() {
+        neighbors[from].push(to);
+        neighbors[to].push(from);
+    }
+    let mut flow = BigUint::default();
+    loop {
+        // The shortest path with residual capacity, by breadth-first search.
+        let mut parents = vec![None; ids.len()];
+        parents[source] = Some(source);
+        let mut queue = VecDeque::from([source]);
+        while let Some(node) = queue.pop_front() {
+            for &next in &neighbors[node] {
+                let residual = capacities.get(&(node, next));
+                if parents[next].is_none() && residual.is_some_and(|c| *c > BigUint::default()) {
+                    parents[next] = Some(node);
+                    queue.push_back(next);
+                }
+            }
+        }
+        if parents[sink].is_none() {
+            return flow;
+        }
+        let mut path = vec![];
+        let mut node = sink;
+        while node != source {
+            let parent = parents[node].unwrap();
+            path.push((parent, node));
+            node = parent;
+        }
+        let bottleneck = path
+            .iter()
+            .map(|edge| capacities[edge].clone())
+            .min()
+            .unwrap();
+        for (from, to) in path {
+            *capacities.get_mut(&(from, to)).unwrap() -= &bottleneck;
+            *capacities.entry((to, from)).or_default() += &bottleneck;
+        }
+        flow += bottleneck;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graph::{compute_flow_with_options, FlowAlgorithm};
+    use crate::types::edge::edge;
+
+    #[test]
+    fn matches_flow_algorithms() {
+        let address = |i: u8| Address::from([i; 20]);
+        // Two routes from 1 to 4, a trust limit of 3 for token 1 at 3 shared by 1 and 5,
+        // and tokens of 4 returned to their owner by 3 and 5.
+        let edges = EdgeDB::new(vec![
+            edge([1; 20], [2; 20], [1; 20], 10),
+            edge([1; 20], [3; 20], [1; 20], 3),
+            edge([5; 20], [3; 20], [1; 20], 3),
+            edge([2; 20], [4; 20], [2; 20], 6),
+            edge([3; 20], [4; 20], [4; 20], 8),
+            edge([5; 20], [4; 20], [4; 20], 1),
+            edge([3; 20], [5; 20], [3; 20], 2),
+        ]);
+        for (from, to, expected) in [(1u8, 4u8, 9u128), (1, 5, 2), (5, 4, 4), (4, 1, 0)] {
+            let reference = reference_max_flow(&address(from), &address(to), &edges);
+            assert_eq!(reference, BigUint::from(expected), "{from} -> {to}");
+            for algorithm in FlowAlgorithm::ALL {
+                let options = FlowOptions {
+                    algorithm,
+                    ..FlowOptions::default()
+                };
+                assert!(is_reference_checkable(&edges, &options));
+                let result =
+                    compute_flow_with_options(&address(from), &address(to), &edges, &options)
+                        .unwrap();
+                assert_eq!(BigUint::from(result.flow), reference, "{from} -> {to}");
+            }
+        }
+    }
+}
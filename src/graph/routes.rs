@@ -0,0 +1,219 @@
+use crate::graph::{
+    compute_flow_with_options, Cancellation, FlowAlgorithm, FlowError, FlowOptions,
+};
+use crate::types::edge::EdgeDB;
+use crate::types::{Address, Edge, U256};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// One way to transfer value from a source to a sink.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub flow: U256,
+    /// The transfers in an order in which they can be executed.
+    pub transfers: Vec<Edge>,
+}
+
+impl Route {
+    /// The largest number of consecutive transfers value passes through.
+    pub fn hops(&self) -> usize {
+        let mut hops: HashMap<Address, usize> = HashMap::new();
+        let mut max_hops = 0;
+        // The transfers are sorted such that all transfers to an account
+        // come before the transfers from that account.
+        for transfer in &self.transfers {
+            let h = hops.get(&transfer.from).copied().unwrap_or_default() + 1;
+            let to = hops.entry(transfer.to).or_default();
+            *to = (*to).max(h);
+            max_hops = max_hops.max(h);
+        }
+        max_hops
+    }
+
+    /// The number of distinct tokens used by the transfers.
+    pub fn token_count(&self) -> usize {
+        self.transfers
+            .iter()
+            .map(|t| t.token)
+            .collect::<HashSet<_>>()
+            .len()
+    }
+}
+
+/// Computes up to `count` distinct routes for the same transfer, all of them
+/// transferring the same (maximal) value, ranked by the number of hops and then
+/// by the number of distinct tokens.
+///
+/// Alternatives are found by running different flow algorithms and by
+/// avoiding transfers of routes found before, so that a wallet can fall back
+/// to another route if a transfer fails on-chain.
+/// Returns `FlowError::Cancelled` if `cancellation` is cancelled before all routes are found.
+#[allow(clippy::too_many_arguments)]
+pub fn compute_alternate_routes(
+    source: &Address,
+    sink: &Address,
+    edges: &EdgeDB,
+    requested_flow: U256,
+    max_distance: Option<u64>,
+    max_transfers: Option<u64>,
+    count: usize,
+    cancellation: &Cancellation,
+) -> Result<Vec<Route>, FlowError> {
+    let route = |edges: &EdgeDB, algorithm| {
+        let options = FlowOptions {
+            requested_flow,
+            max_distance,
+            max_transfers,
+            algorithm,
+            cancellation: cancellation.clone(),
+            ..FlowOptions::default()
+        };
+        let result = compute_flow_with_options(source, sink, edges, &options)?;
+        // A flow that was stopped early is not a route of the same value.
+        if cancellation.is_cancelled() {
+            return Err(FlowError::Cancelled);
+        }
+        Ok(Route {
+            flow: result.flow,
+            transfers: result.transfers,
+        })
+    };
+
+    let mut routes = Routes::default();
+    for algorithm in [FlowAlgorithm::MinCost, FlowAlgorithm::FordFulkerson] {
+        routes.add(route(edges, algorithm)?);
+    }
+    let Some(best) = routes.ranked().into_iter().next() else {
+        return Ok(vec![]);
+    };
+
+    // Like Yen's algorithm for shortest paths: Each alternative avoids all
+    // transfers avoided by the route it is derived from plus one of its transfers.
+    let mut queue = VecDeque::from([(best, vec![])]);
+    let mut attempts = 0;
+    while let Some((base, avoided)) = queue.pop_front() {
+        let mut transfers = base.transfers;
+        transfers.sort_by_key(|t| Reverse(t.capacity));
+        for transfer in transfers {
+            if attempts >= 2 * count || routes.ranked().len() >= count {
+                break;
+            }
+            attempts += 1;
+            let mut avoided = avoided.clone();
+            avoided.push(transfer);
+            let mut reduced = edges.clone();
+            for edge in &avoided {
+                reduced.update(Edge {
+                    capacity: U256::from(0u128),
+                    ..*edge
+                });
+            }
+            let alternative = route(&reduced, FlowAlgorithm::MinCost)?;
+            if routes.add(alternative.clone()) {
+                queue.push_back((alternative, avoided));
+            }
+        }
+    }
+
+    let mut ranked = routes.ranked();
+    ranked.truncate(count);
+    Ok(ranked)
+}
+
+#[derive(Default)]
+struct Routes {
+    routes: Vec<Route>,
+    seen: HashSet<Vec<Edge>>,
+}
+
+impl Routes {
+    /// Adds the route if it is new, returns false otherwise.
+    fn add(&mut self, route: Route) -> bool {
+        let mut key = route.transfers.clone();
+        key.sort();
+        if route.transfers.is_empty() || !self.seen.insert(key) {
+            return false;
+        }
+        self.routes.push(route);
+        true
+    }
+
+    /// Returns the routes that transfer the largest value, best first.
+    fn ranked(&self) -> Vec<Route> {
+        let max_flow = self.routes.iter().map(|r| r.flow).max();
+        let mut ranked = self
+            .routes
+            .iter()
+            .filter(|r| Some(r.flow) == max_flow)
+            .cloned()
+            .collect::<Vec<_>>();
+        ranked.sort_by_key(|r| (r.hops(), r.token_count()));
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::edge::edge;
+
+    fn address(i: u64) -> Address {
+        Address::from(format!("0x{:040x}", i + 1).as_str())
+    }
+
+    #[test]
+    fn alternatives() {
+        let (a, b, c, d) = (address(0), address(1), address(2), address(3));
+        // A direct route and two routes over one intermediate account.
+        let edges = EdgeDB::new(vec![
+            edge(a, d, a, 10),
+            edge(a, b, a, 10),
+            edge(b, d, b, 10),
+            edge(a, c, a, 10),
+            edge(c, d, c, 10),
+        ]);
+        let routes = compute_alternate_routes(
+            &a,
+            &d,
+            &edges,
+            U256::from(10u128),
+            None,
+            None,
+            3,
+            &Cancellation::default(),
+        )
+        .unwrap();
+        assert_eq!(routes.len(), 3);
+        assert!(routes.iter().all(|r| r.flow == U256::from(10u128)));
+        assert_eq!(routes[0].transfers, vec![edge(a, d, a, 10)]);
+        assert_eq!(routes[0].hops(), 1);
+        assert_eq!(routes[1].hops(), 2);
+        assert_eq!(routes[1].token_count(), 2);
+        assert_ne!(routes[1], routes[2]);
+
+        let routes = compute_alternate_routes(
+            &a,
+            &d,
+            &edges,
+            U256::from(10u128),
+            None,
+            None,
+            1,
+            &Cancellation::default(),
+        )
+        .unwrap();
+        assert_eq!(routes.len(), 1);
+        assert!(compute_alternate_routes(
+            &d,
+            &a,
+            &edges,
+            U256::from(10u128),
+            None,
+            None,
+            3,
+            &Cancellation::default()
+        )
+        .unwrap()
+        .is_empty());
+    }
+}
@@ -0,0 +1,424 @@
+use crate::types::edge::{Balances, EdgeDB, EdgeKind};
+use crate::types::safe::trust_transfer_limit;
+use crate::types::{Address, Edge, TokenType, U256};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// The reason why a list of transfers would revert in the hub's `transferThrough`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimulationError {
+    /// The sender does not hold enough tokens at this point of the transfers.
+    InsufficientBalance { transfer: Box<Edge>, balance: U256 },
+    /// The receiver does not accept that many tokens at this point of the transfers.
+    TrustLimitExceeded { transfer: Box<Edge>, limit: U256 },
+    /// An intermediate account does not end up with a net transfer of zero.
+    Unbalanced { account: Address },
+    /// The source sends or the sink receives another net value than expected.
+    UnexpectedValue {
+        account: Address,
+        expected: U256,
+        actual: U256,
+    },
+}
+
+impl Error for SimulationError {}
+
+impl Display for SimulationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SimulationError::InsufficientBalance { transfer, balance } => write!(
+                f,
+                "{} cannot send {} of token {} to {}, its balance is {}",
+                transfer.from,
+                transfer.capacity.to_decimal(),
+                transfer.token,
+                transfer.to,
+                balance.to_decimal()
+            ),
+            SimulationError::TrustLimitExceeded { transfer, limit } => write!(
+                f,
+                "{} does not accept {} of token {} from {}, its limit is {}",
+                transfer.to,
+                transfer.capacity.to_decimal(),
+                transfer.token,
+                transfer.from,
+                limit.to_decimal()
+            ),
+            SimulationError::Unbalanced { account } => {
+                write!(f, "The transfers do not cancel out at {account}")
+            }
+            SimulationError::UnexpectedValue {
+                account,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "The transfers move a net value of {} instead of {} at {account}",
+                actual.to_decimal(),
+                expected.to_decimal()
+            ),
+        }
+    }
+}
+
+/// Replays the transfers in the given order against a model of the hub's
+/// `transferThrough`: Before each transfer, the trust limit of the receiver and
+/// (if the edge database contains balances) the balance of the sender are checked
+/// using the balances after all previous transfers. At the end, all accounts
+/// except the source and the sink have to receive as much as they send.
+///
+/// If the edge database contains no trust limit for a transfer, the capacity
+/// of the edge is used as the limit of all transfers along it.
+pub fn simulate_transfer_through(
+    transfers: &[Edge],
+    edges: &EdgeDB,
+) -> Result<(), SimulationError> {
+    let mut state = HubState::new(edges);
+    for transfer in transfers {
+        state.check(transfer)?;
+        state.apply(transfer);
+    }
+    state.check_balanced()
+}
+
+/// Checks a transfer plan against the edges: executed in the given order, each transfer
+/// passes the checks of `simulate_transfer_through`, every account except `source` and
+/// `sink` receives as much as it sends, and in net, `source` sends and `sink` receives
+/// exactly `value`.
+pub fn verify_flow(
+    transfers: &[Edge],
+    source: &Address,
+    sink: &Address,
+    value: U256,
+    edges: &EdgeDB,
+) -> Result<(), SimulationError> {
+    let mut state = HubState::new(edges);
+    for transfer in transfers {
+        state.check(transfer)?;
+        state.apply(transfer);
+    }
+    let mut accounts = state.net.iter().collect::<Vec<_>>();
+    accounts.sort();
+    if let Some((account, _)) = accounts.into_iter().find(|(account, (received, sent))| {
+        *account != source && *account != sink && received != sent
+    }) {
+        return Err(SimulationError::Unbalanced { account: *account });
+    }
+    let net = |account: &Address| state.net.get(account).copied().unwrap_or_default();
+    let ((source_received, source_sent), (sink_received, sink_sent)) = (net(source), net(sink));
+    // The net value sent by the source and received by the sink, along the transfers.
+    for (account, along, against) in [
+        (source, source_sent, source_received),
+        (sink, sink_received, sink_sent),
+    ] {
+        let actual = along.saturating_sub(against);
+        if along < against || actual != value {
+            return Err(SimulationError::UnexpectedValue {
+                account: *account,
+                expected: value,
+                actual,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Reorders the transfers such that they do not revert in the hub's `transferThrough`,
+/// keeping the order where possible. Repeatedly executes the first transfer that
+/// passes the checks of `simulate_transfer_through`. Returns an error if at some point
+/// no transfer passes them.
+pub fn repair_transfer_order(
+    transfers: Vec<Edge>,
+    edges: &EdgeDB,
+) -> Result<Vec<Edge>, SimulationError> {
+    let mut state = HubState::new(edges);
+    let mut pending = transfers;
+    let mut ordered = Vec::with_capacity(pending.len());
+    while !pending.is_empty() {
+        let i = match pending.iter().position(|t| state.check(t).is_ok()) {
+            Some(i) => i,
+            None => return Err(state.check(&pending[0]).unwrap_err()),
+        };
+        let transfer = pending.remove(i);
+        state.apply(&transfer);
+        ordered.push(transfer);
+    }
+    state.check_balanced()?;
+    Ok(ordered)
+}
+
+struct HubState<'a> {
+    edges: &'a EdgeDB,
+    balances: Balances,
+    /// Whether the balances are known, otherwise only the capacities of edges are checked.
+    check_balances: bool,
+    /// The amount sent along each edge so far.
+    sent: HashMap<(Address, Address, Address), U256>,
+    /// The amounts received and sent by each account so far.
+    net: HashMap<Address, (U256, U256)>,
+}
+
+impl<'a> HubState<'a> {
+    fn new(edges: &'a EdgeDB) -> HubState<'a> {
+        HubState {
+            edges,
+            balances: edges.balances().clone(),
+            check_balances: !edges.balances().is_empty(),
+            sent: HashMap::new(),
+            net: HashMap::new(),
+        }
+    }
+
+    fn check(&self, transfer: &Edge) -> Result<(), SimulationError> {
+        let Edge {
+            from, to, token, ..
+        } = transfer;
+        let balance = self.balances.balance(from, token);
+        if self.check_balances && transfer.capacity > balance {
+            return Err(SimulationError::InsufficientBalance {
+                transfer: Box::new(*transfer),
+                balance,
+            });
+        }
+        // Wrapping and ERC-20 transfers do not depend on trust.
+        if self.check_balances && self.edges.edge_kind(transfer) != EdgeKind::Transfer {
+            return Ok(());
+        }
+        // The receiver's trust in the token owner limits all transfers of the token to it.
+        let trust = Edge {
+            from: *token,
+            to: *to,
+            token: *token,
+            capacity: U256::from(0u128),
+        };
+        let limit = match self.edges.limit_percentage(&trust) {
+            // Trust in a group is not limited.
+            Some(_) if self.edges.token_type(token) == TokenType::Group => U256::MAX,
+            Some(percentage) => trust_transfer_limit(
+                balance,
+                self.balances.balance(to, token),
+                self.balances.balance(to, to),
+                self.edges.account_type(to),
+                percentage,
+            ),
+            // Owners always accept their own tokens.
+            None if self.check_balances && to == token => U256::MAX,
+            None => {
+                let capacity = self
+                    .edges
+                    .edge(transfer)
+                    .map(|edge| edge.capacity)
+                    .unwrap_or_default();
+                let sent = self
+                    .sent
+                    .get(&(*from, *to, *token))
+                    .copied()
+                    .unwrap_or_default();
+                capacity.saturating_sub(sent)
+            }
+        };
+        if transfer.capacity > limit {
+            return Err(SimulationError::TrustLimitExceeded {
+                transfer: Box::new(*transfer),
+                limit,
+            });
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, transfer: &Edge) {
+        let Edge {
+            from,
+            to,
+            token,
+            capacity,
+        } = *transfer;
+        let balance = self.balances.balance(&from, &token);
+        self.balances
+            .set_balance(from, token, balance.saturating_sub(capacity));
+        *self.sent.entry((from, to, token)).or_default() += capacity;
+        if self.edges.edge_kind(transfer) == EdgeKind::Wrap {
+            // The wrapper keeps the tokens, `from` receives wrapped tokens instead.
+            let wrapped = self.balances.balance(&from, &to);
+            self.balances
+                .set_balance(from, to, wrapped.saturating_add(capacity));
+            return;
+        }
+        let balance = self.balances.balance(&to, &token);
+        self.balances
+            .set_balance(to, token, balance.saturating_add(capacity));
+        self.net.entry(from).or_default().1 += capacity;
+        self.net.entry(to).or_default().0 += capacity;
+    }
+
+    /// Like the hub, requires exactly one account that sends more than it receives
+    /// and one account that receives more than it sends.
+    fn check_balanced(&self) -> Result<(), SimulationError> {
+        let mut senders = 0;
+        let mut receivers = 0;
+        let mut accounts = self.net.iter().collect::<Vec<_>>();
+        accounts.sort();
+        for (account, (received, sent)) in accounts {
+            if received < sent {
+                senders += 1;
+            } else if received > sent {
+                receivers += 1;
+            } else {
+                continue;
+            }
+            if senders > 1 || receivers > 1 {
+                return Err(SimulationError::Unbalanced { account: *account });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn address(i: u64) -> Address {
+        Address::from(format!("0x{:040x}", i + 1).as_str())
+    }
+
+    fn transfer(from: Address, to: Address, token: Address, amount: u128) -> Edge {
+        Edge {
+            from,
+            to,
+            token,
+            capacity: U256::from(amount),
+        }
+    }
+
+    #[test]
+    fn intermediate_balance() {
+        let (a, b, c) = (address(0), address(1), address(2));
+        let mut edges = EdgeDB::default();
+        // b and c trust a.
+        edges.set_limit_percentage(&transfer(a, b, a, 0), 50);
+        edges.set_limit_percentage(&transfer(a, c, a, 0), 100);
+        for account in [a, b, c] {
+            edges
+                .balances_mut()
+                .set_balance(account, account, U256::from(100u128));
+        }
+
+        // b can only forward a's tokens after receiving them.
+        let in_order = vec![transfer(a, b, a, 30), transfer(b, c, a, 30)];
+        assert_eq!(simulate_transfer_through(&in_order, &edges), Ok(()));
+        let reversed = in_order.iter().rev().copied().collect::<Vec<_>>();
+        assert_eq!(
+            simulate_transfer_through(&reversed, &edges),
+            Err(SimulationError::InsufficientBalance {
+                transfer: Box::new(transfer(b, c, a, 30)),
+                balance: U256::from(0u128),
+            })
+        );
+        assert_eq!(repair_transfer_order(reversed, &edges), Ok(in_order));
+    }
+
+    #[test]
+    fn verify() {
+        let (a, b, c) = (address(0), address(1), address(2));
+        let mut edges = EdgeDB::default();
+        edges.set_limit_percentage(&transfer(a, b, a, 0), 50);
+        edges.set_limit_percentage(&transfer(a, c, a, 0), 100);
+        for account in [a, b, c] {
+            edges
+                .balances_mut()
+                .set_balance(account, account, U256::from(100u128));
+        }
+        let plan = vec![transfer(a, b, a, 30), transfer(b, c, a, 30)];
+        assert_eq!(
+            verify_flow(&plan, &a, &c, U256::from(30u128), &edges),
+            Ok(())
+        );
+        assert_eq!(
+            verify_flow(&plan, &a, &c, U256::from(40u128), &edges),
+            Err(SimulationError::UnexpectedValue {
+                account: a,
+                expected: U256::from(40u128),
+                actual: U256::from(30u128),
+            })
+        );
+        assert_eq!(
+            verify_flow(&plan, &a, &c, U256::from(40u128), &edges)
+                .unwrap_err()
+                .to_string(),
+            format!("The transfers move a net value of 30 instead of 40 at {a}")
+        );
+        // b keeps the tokens.
+        assert_eq!(
+            verify_flow(&plan[..1], &a, &c, U256::from(30u128), &edges),
+            Err(SimulationError::Unbalanced { account: b })
+        );
+        // b cannot forward tokens before receiving them.
+        let reversed = vec![transfer(b, c, a, 30), transfer(a, b, a, 30)];
+        assert!(matches!(
+            verify_flow(&reversed, &a, &c, U256::from(30u128), &edges),
+            Err(SimulationError::InsufficientBalance { .. })
+        ));
+        assert!(verify_flow(&[], &a, &c, U256::from(0u128), &edges).is_ok());
+    }
+
+    #[test]
+    fn trust_limit() {
+        let (a, b, c) = (address(0), address(1), address(2));
+        let mut edges = EdgeDB::default();
+        edges.set_limit_percentage(&transfer(a, c, a, 0), 50);
+        edges.balances_mut().set_balance(a, a, U256::from(100u128));
+        edges.balances_mut().set_balance(b, a, U256::from(100u128));
+        edges.balances_mut().set_balance(c, c, U256::from(100u128));
+        // c accepts 50 of a's tokens, but less once it holds some of them.
+        assert_eq!(
+            simulate_transfer_through(&[transfer(a, c, a, 40)], &edges),
+            Ok(())
+        );
+        let too_much = vec![transfer(a, c, a, 40), transfer(b, c, a, 40)];
+        assert_eq!(
+            repair_transfer_order(too_much, &edges),
+            Err(SimulationError::TrustLimitExceeded {
+                transfer: Box::new(transfer(b, c, a, 40)),
+                limit: U256::from(30u128),
+            })
+        );
+        let error = SimulationError::TrustLimitExceeded {
+            transfer: Box::new(transfer(b, c, a, 40)),
+            limit: U256::from(30u128),
+        };
+        assert_eq!(
+            error.to_string(),
+            format!("{c} does not accept 40 of token {a} from {b}, its limit is 30")
+        );
+    }
+
+    #[test]
+    fn capacities_and_balance() {
+        let (a, b, c, d) = (address(0), address(1), address(2), address(3));
+        // Without balances, only the capacities of the edges are checked.
+        let edges = EdgeDB::new(vec![transfer(a, b, a, 10), transfer(b, c, b, 10)]);
+        let transfers = vec![transfer(a, b, a, 10), transfer(b, c, b, 10)];
+        assert_eq!(simulate_transfer_through(&transfers, &edges), Ok(()));
+        assert!(matches!(
+            simulate_transfer_through(&[transfer(a, b, a, 11)], &edges),
+            Err(SimulationError::TrustLimitExceeded { .. })
+        ));
+        // Two senders.
+        let edges = EdgeDB::new(vec![transfer(a, c, a, 10), transfer(b, c, b, 10)]);
+        let transfers = vec![transfer(a, c, a, 10), transfer(b, c, b, 10)];
+        assert!(matches!(
+            simulate_transfer_through(&transfers, &edges),
+            Err(SimulationError::Unbalanced { .. })
+        ));
+        assert_eq!(simulate_transfer_through(&[], &edges), Ok(()));
+        assert_eq!(
+            simulate_transfer_through(&[transfer(a, d, a, 1)], &edges),
+            Err(SimulationError::TrustLimitExceeded {
+                transfer: Box::new(transfer(a, d, a, 1)),
+                limit: U256::from(0u128),
+            })
+        );
+    }
+}
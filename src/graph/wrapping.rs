@@ -0,0 +1,96 @@
+use crate::types::{Address, Edge};
+use std::collections::BTreeMap;
+
+/// Turns the transfers of a flow from a source to an ERC-20 `wrapper` into
+/// transfers that deliver the wrapped tokens to `recipient`: Every account
+/// that wraps tokens sends the wrapped tokens it receives to `recipient`
+/// right after its last wrap.
+pub fn deliver_wrapped(transfers: Vec<Edge>, wrapper: &Address, recipient: &Address) -> Vec<Edge> {
+    let mut wrapped: BTreeMap<Address, Edge> = BTreeMap::new();
+    let mut last_wrap = BTreeMap::new();
+    for (i, transfer) in transfers.iter().enumerate() {
+        if transfer.to == *wrapper {
+            let delivery = wrapped.entry(transfer.from).or_insert(Edge {
+                from: transfer.from,
+                to: *recipient,
+                token: *wrapper,
+                capacity: Default::default(),
+            });
+            delivery.capacity += transfer.capacity;
+            last_wrap.insert(transfer.from, i);
+        }
+    }
+    let mut result = Vec::with_capacity(transfers.len() + wrapped.len());
+    for (i, transfer) in transfers.into_iter().enumerate() {
+        let from = transfer.from;
+        result.push(transfer);
+        if last_wrap.get(&from) == Some(&i) {
+            result.push(wrapped[&from]);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graph::{compute_flow, repair_transfer_order, FlowAlgorithm};
+    use crate::types::edge::{edge, EdgeDB, EdgeKind};
+    use crate::types::U256;
+
+    fn address(i: u64) -> Address {
+        Address::from(format!("0x{:040x}", i + 1).as_str())
+    }
+
+    #[test]
+    fn wrapped_delivery() {
+        let (a, b, c, w) = (address(0), address(1), address(2), address(3));
+        let mut edges = EdgeDB::default();
+        // b trusts a, so a can send its tokens to b. c only accepts b's tokens wrapped in w.
+        edges.set_limit_percentage(&edge(a, b, a, 0), 100);
+        edges.set_limit_percentage(&edge(b, a, b, 0), 100);
+        for account in [a, b, c] {
+            edges
+                .balances_mut()
+                .set_balance(account, account, U256::from(100u128));
+        }
+        edges.balances_mut().set_balance(a, b, U256::from(30u128));
+        edges.add_wrapper(w, b);
+        assert_eq!(edges.edge_kind(&edge(a, w, b, 0)), EdgeKind::Wrap);
+        assert_eq!(
+            edges.edge_kind(&edge(a, c, w, 0)),
+            EdgeKind::WrappedTransfer
+        );
+        assert_eq!(edges.edge_kind(&edge(a, b, a, 0)), EdgeKind::Transfer);
+
+        let (flow, transfers) = compute_flow(
+            &a,
+            &w,
+            &edges,
+            U256::MAX,
+            None,
+            None,
+            FlowAlgorithm::FordFulkerson,
+        )
+        .unwrap();
+        // a wraps the 30 of b's tokens it holds, b wraps its own tokens after receiving a's.
+        assert_eq!(flow, U256::from(130u128));
+        let transfers = deliver_wrapped(transfers, &w, &c);
+        let mut sorted = transfers.clone();
+        sorted.sort();
+        assert_eq!(
+            sorted,
+            vec![
+                edge(a, b, a, 100),
+                edge(a, c, w, 30),
+                edge(a, w, b, 30),
+                edge(b, c, w, 100),
+                edge(b, w, b, 100),
+            ]
+        );
+        assert_eq!(
+            repair_transfer_order(transfers.clone(), &edges),
+            Ok(transfers)
+        );
+    }
+}
@@ -0,0 +1,746 @@
+//! Builds the edges from the events of the Circles hub (v1) and its tokens, read
+//! from the JSON-RPC interface of a node, instead of from an exported safes database.
+//!
+//! `Signup` and `OrganizationSignup` events of the hub register safes and their tokens,
+//! `Trust` events set trust limit percentages and the `Transfer` events of the tokens
+//! (which `HubTransfer`s consist of) change the balances. The edges are then computed
+//! from the safes like for a safes database, see `DB::edges_with_trust_limits`.
+//!
+//! Once the confirmed blocks are indexed, new blocks can be followed as they are announced
+//! (see `heads`), each turned into the changes of the edges it causes, see `follow`. The
+//! changes of the last `RECENT_BLOCKS` blocks can be undone, to follow reorgs.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::error::Error;
+use std::mem;
+
+use crypto::digest::Digest;
+use crypto::sha3::Sha3;
+
+use crate::io::wal::Change;
+use crate::safe_db::db::DB;
+use crate::types::edge::EdgeDB;
+use crate::types::{AccountType, Address, Edge, Safe, U256};
+
+pub mod balances;
+pub mod heads;
+pub mod rpc;
+
+use rpc::{EthClient, Header, Log};
+
+/// The Circles v1 hub on Gnosis chain and the block it was deployed at.
+pub const HUB_ADDRESS: &str = "0x29b9a7fbb8995b2423a71cc17cf9810798f6c543";
+pub const HUB_DEPLOYMENT_BLOCK: u64 = 12529458;
+
+/// Blocks this close to the latest one are not indexed yet, as they could still be reorganized.
+pub const CONFIRMATIONS: u64 = 12;
+
+/// The number of blocks whose logs are requested at once, reduced if the node refuses.
+const MAX_BLOCK_RANGE: u64 = 5000;
+
+/// The number of followed blocks that can be rolled back on a reorg.
+pub const RECENT_BLOCKS: usize = 64;
+
+/// An event relevant to the edges.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Signup {
+        user: Address,
+        token: Address,
+    },
+    OrganizationSignup {
+        organization: Address,
+    },
+    /// `user` can send their tokens to `can_send_to`, up to a limit percentage.
+    Trust {
+        can_send_to: Address,
+        user: Address,
+        limit: u8,
+    },
+    Transfer {
+        token: Address,
+        from: Address,
+        to: Address,
+        value: U256,
+    },
+}
+
+/// The first topic of an event with the given signature.
+pub fn event_topic(signature: &str) -> [u8; 32] {
+    let mut keccak = Sha3::keccak256();
+    keccak.input_str(signature);
+    let mut topic = [0u8; 32];
+    keccak.result(&mut topic);
+    topic
+}
+
+/// The selector of a function with the given signature, the start of the data of calls.
+pub fn selector(signature: &str) -> [u8; 4] {
+    event_topic(signature)[..4].try_into().unwrap()
+}
+
+/// The topics of the hub events and of token transfers.
+struct Topics {
+    signup: [u8; 32],
+    organization_signup: [u8; 32],
+    trust: [u8; 32],
+    transfer: [u8; 32],
+}
+
+impl Default for Topics {
+    fn default() -> Self {
+        Topics {
+            signup: event_topic("Signup(address,address)"),
+            organization_signup: event_topic("OrganizationSignup(address)"),
+            trust: event_topic("Trust(address,address,uint256)"),
+            transfer: event_topic("Transfer(address,address,uint256)"),
+        }
+    }
+}
+
+impl Topics {
+    /// Decodes a log of the hub or of a token, returns None for other logs.
+    fn decode(&self, log: &Log) -> Option<Event> {
+        let topic = log.topics.first()?;
+        if *topic == self.signup {
+            Some(Event::Signup {
+                user: log.address_topic(1)?,
+                token: log.data_address(0)?,
+            })
+        } else if *topic == self.organization_signup {
+            Some(Event::OrganizationSignup {
+                organization: log.address_topic(1)?,
+            })
+        } else if *topic == self.trust {
+            let limit = log.data_u256(0)?;
+            Some(Event::Trust {
+                can_send_to: log.address_topic(1)?,
+                user: log.address_topic(2)?,
+                limit: limit.min(U256::from(100u128)).to_be_bytes()[31],
+            })
+        } else if *topic == self.transfer {
+            Some(Event::Transfer {
+                token: log.address,
+                from: log.address_topic(1)?,
+                to: log.address_topic(2)?,
+                value: log.data_u256(0)?,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// The state before an event changed it.
+#[derive(Debug, Clone)]
+enum Undo {
+    Safe {
+        address: Address,
+        previous: Option<Safe>,
+    },
+    TokenOwner {
+        token: Address,
+        /// The owner set by the event.
+        owner: Address,
+        previous: Option<Address>,
+    },
+    Limit {
+        user: Address,
+        can_send_to: Address,
+        previous: Option<u8>,
+    },
+    Balance {
+        holder: Address,
+        token: Address,
+        previous: Option<U256>,
+    },
+}
+
+/// A followed block, with the state before its events.
+struct RecentBlock {
+    header: Header,
+    undo: Vec<Undo>,
+}
+
+/// The state of the safes, built from the events up to `next_block`.
+pub struct Indexer {
+    hub: Address,
+    topics: Topics,
+    /// The first block not indexed yet.
+    next_block: u64,
+    safes: BTreeMap<Address, Safe>,
+    token_owner: BTreeMap<Address, Address>,
+    /// Balances by holder and token, also of holders that are not (yet) safes.
+    balances: HashMap<Address, BTreeMap<Address, U256>>,
+    /// The number of relevant events applied or rolled back.
+    changes: u64,
+    /// The state before the events applied since the journal was last taken.
+    journal: Vec<Undo>,
+    /// The last followed blocks, oldest first. The last one is `next_block - 1`.
+    recent: VecDeque<RecentBlock>,
+}
+
+impl Indexer {
+    pub fn new(hub: Address, start_block: u64) -> Indexer {
+        Indexer {
+            hub,
+            topics: Topics::default(),
+            next_block: start_block,
+            safes: Default::default(),
+            token_owner: Default::default(),
+            balances: Default::default(),
+            changes: 0,
+            journal: vec![],
+            recent: VecDeque::new(),
+        }
+    }
+
+    /// The last block indexed.
+    pub fn block_number(&self) -> Option<u64> {
+        self.next_block.checked_sub(1)
+    }
+
+    /// The number of relevant events applied so far, to tell whether the edges changed.
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    /// Indexes the events of the blocks up to the latest confirmed one. On error, the
+    /// blocks indexed before are kept and the next sync continues after them.
+    pub fn sync(&mut self, client: &EthClient) -> Result<(), Box<dyn Error>> {
+        let latest = client.block_number()?.saturating_sub(CONFIRMATIONS);
+        if self.next_block <= latest {
+            // The blocks of ranges cannot be rolled back.
+            self.recent.clear();
+        }
+        let mut range = MAX_BLOCK_RANGE;
+        while self.next_block <= latest {
+            let to = latest.min(self.next_block + range - 1);
+            match self.index_blocks(client, self.next_block, to) {
+                Ok(()) => {
+                    self.next_block = to + 1;
+                    range = MAX_BLOCK_RANGE;
+                }
+                // Nodes limit the number of logs per request.
+                Err(e) if range > 1 => {
+                    tracing::debug!("Error reading logs, retrying fewer blocks: {e}");
+                    range /= 2;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads and applies the events of the blocks `from` to `to`. The hub events
+    /// are applied first, so that tokens are known before their transfers.
+    fn index_blocks(
+        &mut self,
+        client: &EthClient,
+        from: u64,
+        to: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        let topics = &self.topics;
+        let mut hub_logs = client.logs(
+            from,
+            to,
+            Some(self.hub),
+            &[topics.signup, topics.organization_signup, topics.trust],
+        )?;
+        let mut transfer_logs = client.logs(from, to, None, &[topics.transfer])?;
+        self.apply_logs(&mut hub_logs, &mut transfer_logs);
+        self.journal.clear();
+        Ok(())
+    }
+
+    fn apply_logs(&mut self, hub_logs: &mut [Log], transfer_logs: &mut [Log]) {
+        hub_logs.sort_by_key(|log| (log.block_number, log.log_index));
+        transfer_logs.sort_by_key(|log| (log.block_number, log.log_index));
+        for log in hub_logs.iter().chain(transfer_logs.iter()) {
+            if let Some(event) = self.topics.decode(log) {
+                self.apply(event);
+            }
+        }
+    }
+
+    /// Indexes the blocks up to the new block `head`, after rolling back the followed
+    /// blocks that are not its ancestors. Returns the resulting changes of the edges.
+    /// Fails if the head is too far ahead or the reorg is deeper than the recent blocks,
+    /// then `sync` and `edges` have to be used instead.
+    pub fn follow(
+        &mut self,
+        client: &EthClient,
+        head: Header,
+    ) -> Result<Vec<Change>, Box<dyn Error>> {
+        if self
+            .recent
+            .back()
+            .is_some_and(|last| last.header.hash == head.hash)
+        {
+            return Ok(vec![]);
+        }
+        let mut changes = vec![];
+        // The blocks to index, from the head back to `next_block`.
+        let mut headers = vec![head];
+        loop {
+            let first = headers.last().unwrap();
+            while self.next_block > first.number {
+                changes.extend(
+                    self.rollback()
+                        .ok_or("The reorg is deeper than the recent blocks.")?,
+                );
+            }
+            if first.number == self.next_block {
+                match self.recent.back() {
+                    Some(last) if last.header.hash != first.parent_hash => {
+                        changes.extend(self.rollback().unwrap())
+                    }
+                    _ => break,
+                }
+            }
+            if headers.len() > RECENT_BLOCKS {
+                return Err(format!("Block {} is too far ahead.", headers[0].number).into());
+            }
+            let parent = client.header(&first.parent_hash)?;
+            headers.push(parent);
+        }
+        for header in headers.into_iter().rev() {
+            let topics = &self.topics;
+            let mut hub_logs = client.block_logs(
+                &header.hash,
+                Some(self.hub),
+                &[topics.signup, topics.organization_signup, topics.trust],
+            )?;
+            let mut transfer_logs = client.block_logs(&header.hash, None, &[topics.transfer])?;
+            self.apply_logs(&mut hub_logs, &mut transfer_logs);
+            changes.extend(self.finish_block(header));
+        }
+        changes.push(Change::BlockNumber {
+            block_number: self.next_block - 1,
+        });
+        Ok(changes)
+    }
+
+    /// Records the events applied since the last block as those of the block `header`,
+    /// returns the resulting changes of the edges.
+    fn finish_block(&mut self, header: Header) -> Vec<Change> {
+        let undo = mem::take(&mut self.journal);
+        let changes = self.edge_changes(&undo);
+        self.next_block = header.number + 1;
+        self.recent.push_back(RecentBlock { header, undo });
+        if self.recent.len() > RECENT_BLOCKS {
+            self.recent.pop_front();
+        }
+        changes
+    }
+
+    /// Undoes the events of the last followed block, returns the resulting changes of the edges.
+    fn rollback(&mut self) -> Option<Vec<Change>> {
+        let block = self.recent.pop_back()?;
+        tracing::info!("Rolling back block {}.", block.header.number);
+        for undo in block.undo.iter().rev() {
+            match undo.clone() {
+                Undo::Safe { address, previous } => {
+                    match previous {
+                        Some(safe) => self.safes.insert(address, safe),
+                        None => self.safes.remove(&address),
+                    };
+                }
+                Undo::TokenOwner {
+                    token, previous, ..
+                } => {
+                    match previous {
+                        Some(owner) => self.token_owner.insert(token, owner),
+                        None => self.token_owner.remove(&token),
+                    };
+                }
+                Undo::Limit {
+                    user,
+                    can_send_to,
+                    previous,
+                } => {
+                    // The safe exists as it did when the limit was set.
+                    let limits = &mut self.safes.get_mut(&user).unwrap().limit_percentage;
+                    match previous {
+                        Some(limit) => limits.insert(can_send_to, limit),
+                        None => limits.remove(&can_send_to),
+                    };
+                }
+                Undo::Balance {
+                    holder,
+                    token,
+                    previous,
+                } => {
+                    let balances = self.balances.entry(holder).or_default();
+                    match previous {
+                        Some(balance) => balances.insert(token, balance),
+                        None => balances.remove(&token),
+                    };
+                }
+            }
+        }
+        self.next_block = block.header.number;
+        self.changes += 1;
+        Some(self.edge_changes(&block.undo))
+    }
+
+    /// The changes of the edges that make them match the current state of everything `undo`
+    /// refers to, like the edges computed by `edges`.
+    fn edge_changes(&self, undo: &[Undo]) -> Vec<Change> {
+        // Safes and tokens that were added or removed, whose edges change as a whole.
+        let mut safes = BTreeSet::new();
+        let mut tokens = BTreeMap::new();
+        let mut limits = BTreeSet::new();
+        let mut balances = BTreeSet::new();
+        for undo in undo {
+            match undo {
+                Undo::Safe { address, .. } => {
+                    safes.insert(*address);
+                    // Balances held before the signup are part of the edges from then on.
+                    for token in self
+                        .balances
+                        .get(address)
+                        .into_iter()
+                        .flat_map(|b| b.keys())
+                    {
+                        balances.insert((*address, *token));
+                    }
+                }
+                Undo::TokenOwner { token, owner, .. } => {
+                    tokens.insert(*token, *owner);
+                }
+                Undo::Limit {
+                    user, can_send_to, ..
+                } => {
+                    limits.insert((*user, *can_send_to));
+                }
+                Undo::Balance { holder, token, .. } => {
+                    balances.insert((*holder, *token));
+                }
+            }
+        }
+        let mut changes = vec![];
+        for address in &safes {
+            changes.push(Change::Organization {
+                address: *address,
+                organization: self
+                    .safes
+                    .get(address)
+                    .is_some_and(|s| s.account_type == AccountType::Organization),
+            });
+        }
+        for (user, can_send_to) in limits {
+            let receiver = self.safes.contains_key(&can_send_to);
+            if user == can_send_to || !(receiver || safes.contains(&can_send_to)) {
+                continue;
+            }
+            let percentage = self
+                .safes
+                .get(&user)
+                .filter(|_| receiver)
+                .and_then(|safe| safe.limit_percentage.get(&can_send_to))
+                .copied()
+                .unwrap_or_default();
+            changes.push(Change::LimitPercentage {
+                edge: Edge {
+                    from: user,
+                    to: can_send_to,
+                    token: user,
+                    capacity: U256::from(0u128),
+                },
+                percentage,
+                block_number: None,
+            });
+        }
+        for (holder, token) in balances {
+            let owned = self.token_owner.get(&token).copied();
+            let Some(owner) = owned.or(tokens.get(&token).copied()) else {
+                continue;
+            };
+            let safe = self.safes.contains_key(&holder);
+            if !safe && !safes.contains(&holder) {
+                continue;
+            }
+            let amount = match (safe, owned) {
+                (true, Some(_)) => self
+                    .balances
+                    .get(&holder)
+                    .and_then(|balances| balances.get(&token))
+                    .copied()
+                    .unwrap_or_default(),
+                _ => U256::from(0u128),
+            };
+            // The edge back to the owner is implied by the balance.
+            changes.push(Change::Balance {
+                holder,
+                token: owner,
+                amount,
+            });
+        }
+        changes
+    }
+
+    /// Applies an event, returns false if it is not relevant, e.g. a transfer of another token.
+    pub fn apply(&mut self, event: Event) -> bool {
+        match event {
+            Event::Signup { user, token } => {
+                self.journal.push(Undo::Safe {
+                    address: user,
+                    previous: self.safes.get(&user).cloned(),
+                });
+                self.journal.push(Undo::TokenOwner {
+                    token,
+                    owner: user,
+                    previous: self.token_owner.get(&token).copied(),
+                });
+                self.safes.entry(user).or_default().token_address = token;
+                self.token_owner.insert(token, user);
+            }
+            Event::OrganizationSignup { organization } => {
+                self.journal.push(Undo::Safe {
+                    address: organization,
+                    previous: self.safes.get(&organization).cloned(),
+                });
+                self.safes.entry(organization).or_default().account_type =
+                    AccountType::Organization;
+            }
+            Event::Trust {
+                can_send_to,
+                user,
+                limit,
+            } => {
+                // The hub only allows trust between signed up accounts.
+                let Some(safe) = self.safes.get_mut(&user) else {
+                    return false;
+                };
+                self.journal.push(Undo::Limit {
+                    user,
+                    can_send_to,
+                    previous: safe.limit_percentage.get(&can_send_to).copied(),
+                });
+                match limit {
+                    0 => safe.limit_percentage.remove(&can_send_to),
+                    limit => safe.limit_percentage.insert(can_send_to, limit),
+                };
+            }
+            Event::Transfer {
+                token,
+                from,
+                to,
+                value,
+            } => {
+                if !self.token_owner.contains_key(&token) {
+                    return false;
+                }
+                if from != Address::default() {
+                    let balance = self.balances.entry(from).or_default();
+                    let previous = balance.insert(token, U256::default());
+                    balance.insert(token, previous.unwrap_or_default() - value);
+                    self.journal.push(Undo::Balance {
+                        holder: from,
+                        token,
+                        previous,
+                    });
+                }
+                if to != Address::default() {
+                    let balance = self.balances.entry(to).or_default();
+                    let previous = balance.insert(token, U256::default());
+                    balance.insert(token, previous.unwrap_or_default() + value);
+                    self.journal.push(Undo::Balance {
+                        holder: to,
+                        token,
+                        previous,
+                    });
+                }
+            }
+        }
+        self.changes += 1;
+        true
+    }
+
+    /// The edges of the safes, as of the last block indexed.
+    pub fn edges(&self) -> EdgeDB {
+        let safes = self
+            .safes
+            .iter()
+            .map(|(address, safe)| {
+                let safe = Safe {
+                    token_address: safe.token_address,
+                    balances: self.balances.get(address).cloned().unwrap_or_default(),
+                    balance_days: Default::default(),
+                    limit_percentage: safe.limit_percentage.clone(),
+                    account_type: safe.account_type,
+                    stopped: safe.stopped,
+                };
+                (*address, safe)
+            })
+            .collect();
+        let mut edges = DB::new(safes, self.token_owner.clone()).edges_with_trust_limits();
+        if let Some(block_number) = self.block_number() {
+            edges.set_block_number(block_number);
+        }
+        edges
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::Edge;
+
+    fn log(address: Address, topics: &[[u8; 32]], data: &[[u8; 32]]) -> Log {
+        Log {
+            address,
+            topics: topics.to_vec(),
+            data: data.concat(),
+            block_number: 1,
+            block_hash: [0; 32],
+            log_index: 0,
+        }
+    }
+
+    fn word(address: Address) -> [u8; 32] {
+        let mut word = [0u8; 32];
+        word[12..].copy_from_slice(&address.to_bytes());
+        word
+    }
+
+    #[test]
+    fn events() {
+        let hub = Address::from(HUB_ADDRESS);
+        let (alice, bob) = (Address::from([1; 20]), Address::from([2; 20]));
+        let (alice_token, bob_token) = (Address::from([11; 20]), Address::from([12; 20]));
+        let topics = Topics::default();
+        assert_eq!(
+            rpc::to_hex(&topics.transfer),
+            "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"
+        );
+
+        let mut indexer = Indexer::new(hub, 0);
+        let logs = [
+            log(hub, &[topics.signup, word(alice)], &[word(alice_token)]),
+            log(hub, &[topics.signup, word(bob)], &[word(bob_token)]),
+            // Bob trusts Alice with 50%.
+            log(
+                hub,
+                &[topics.trust, word(bob), word(alice)],
+                &[U256::from(50u128).to_be_bytes()],
+            ),
+            // Minting of Alice's tokens.
+            log(
+                alice_token,
+                &[topics.transfer, word(Address::default()), word(alice)],
+                &[U256::from(100u128).to_be_bytes()],
+            ),
+            log(
+                bob_token,
+                &[topics.transfer, word(Address::default()), word(bob)],
+                &[U256::from(100u128).to_be_bytes()],
+            ),
+            // A transfer of another token.
+            log(
+                Address::from([99; 20]),
+                &[topics.transfer, word(alice), word(bob)],
+                &[U256::from(1u128).to_be_bytes()],
+            ),
+        ];
+        let applied = logs
+            .iter()
+            .map(|log| indexer.apply(topics.decode(log).unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(applied, vec![true, true, true, true, true, false]);
+
+        let edges = indexer.edges();
+        let trust = Edge {
+            from: alice,
+            to: bob,
+            token: alice,
+            capacity: U256::from(0u128),
+        };
+        assert_eq!(edges.limit_percentage(&trust), Some(50));
+        assert_eq!(edges.edge(&trust).unwrap().capacity, U256::from(50u128));
+
+        // Revoking trust removes the edge.
+        indexer.apply(Event::Trust {
+            can_send_to: bob,
+            user: alice,
+            limit: 0,
+        });
+        assert_eq!(indexer.edges().edge(&trust), None);
+    }
+
+    #[test]
+    fn rollback() {
+        let (alice, bob) = (Address::from([1; 20]), Address::from([2; 20]));
+        let header = |number: u64| Header {
+            number,
+            hash: [number as u8; 32],
+            parent_hash: [number as u8 - 1; 32],
+        };
+        let mut indexer = Indexer::new(Address::from(HUB_ADDRESS), 10);
+        indexer.apply(Event::Signup {
+            user: alice,
+            token: alice,
+        });
+        let mut edges = EdgeDB::default();
+        for change in indexer.finish_block(header(10)) {
+            change.apply(&mut edges);
+        }
+
+        // Bob signs up, Bob trusts Alice and Alice sends Bob some of her tokens.
+        indexer.apply(Event::Signup {
+            user: bob,
+            token: bob,
+        });
+        indexer.apply(Event::Trust {
+            can_send_to: bob,
+            user: alice,
+            limit: 50,
+        });
+        indexer.apply(Event::Transfer {
+            token: alice,
+            from: Address::default(),
+            to: alice,
+            value: U256::from(100u128),
+        });
+        indexer.apply(Event::Transfer {
+            token: alice,
+            from: alice,
+            to: bob,
+            value: U256::from(30u128),
+        });
+        let mut followed = edges.clone();
+        for change in indexer.finish_block(header(11)) {
+            change.apply(&mut followed);
+        }
+        assert_eq!(indexer.block_number(), Some(11));
+        // The changes lead to the same edges as computing them from the safes.
+        let computed = indexer.edges();
+        assert_eq!(followed.effective_edges(), computed.effective_edges());
+        let back_to_alice = Edge {
+            from: bob,
+            to: alice,
+            token: alice,
+            capacity: U256::from(0u128),
+        };
+        assert_eq!(
+            followed.edge(&back_to_alice).unwrap().capacity,
+            U256::from(30u128)
+        );
+
+        // Rolling back block 11 restores the state of block 10.
+        let mut rolled_back = followed.clone();
+        for change in indexer.rollback().unwrap() {
+            change.apply(&mut rolled_back);
+        }
+        assert_eq!(indexer.block_number(), Some(10));
+        assert!(!indexer.safes.contains_key(&bob));
+        assert_eq!(rolled_back.edge(&back_to_alice), None);
+        assert_eq!(
+            rolled_back.balances().balance(&alice, &alice),
+            U256::from(0u128)
+        );
+        assert!(indexer.rollback().is_some());
+        assert!(indexer.rollback().is_none());
+    }
+}
@@ -0,0 +1,367 @@
+//! Current token balances read from the chain, to find transfer steps that were computed
+//! with stale balances. The calls are batched into one `eth_call` of Multicall3 per round:
+//! one to find the token of each token owner (`userToToken` of the hub), one for the
+//! balances (`balanceOf` of the tokens).
+//!
+//! New balances, read from the chain or sent by an indexer, are applied to the edges
+//! with `balance_changes`.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+
+use super::rpc::EthClient;
+use super::selector;
+use crate::io::wal::Change;
+use crate::types::edge::EdgeDB;
+use crate::types::{Address, Edge, U256};
+
+/// Multicall3, at the same address on all chains.
+pub const MULTICALL_ADDRESS: &str = "0xca11bde05977b3631167028862be2a173976ca11";
+
+/// An account that sends more tokens in a transfer than it holds and receives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shortfall {
+    pub holder: Address,
+    /// The token owner.
+    pub token: Address,
+    pub needed: U256,
+    /// The balance on-chain.
+    pub balance: U256,
+}
+
+/// Reads the current balances of the holders in the tokens of the token owners, for each
+/// `(holder, token owner)` pair. None if the token owner has no token at the hub.
+pub fn read_balances(
+    client: &EthClient,
+    hub: Address,
+    pairs: &[(Address, Address)],
+) -> Result<Vec<Option<U256>>, Box<dyn Error>> {
+    let owners = pairs
+        .iter()
+        .map(|(_, owner)| *owner)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    let user_to_token = selector("userToToken(address)");
+    let calls = owners
+        .iter()
+        .map(|owner| (hub, encode_call(user_to_token, owner)))
+        .collect::<Vec<_>>();
+    let tokens = owners
+        .iter()
+        .zip(aggregate(client, &calls)?)
+        .filter_map(|(owner, token)| Some((*owner, decode_word(&token?)?)))
+        .map(|(owner, token)| (owner, Address::from(word_address(&token))))
+        .filter(|(_, token)| *token != Address::default())
+        .collect::<HashMap<_, _>>();
+
+    let balance_of = selector("balanceOf(address)");
+    let queried = pairs
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (holder, owner))| Some((i, *tokens.get(owner)?, *holder)))
+        .collect::<Vec<_>>();
+    let calls = queried
+        .iter()
+        .map(|(_, token, holder)| (*token, encode_call(balance_of, holder)))
+        .collect::<Vec<_>>();
+    let mut balances = vec![None; pairs.len()];
+    for ((i, _, _), balance) in queried.iter().zip(aggregate(client, &calls)?) {
+        balances[*i] = balance
+            .as_deref()
+            .and_then(decode_word)
+            .map(U256::from_be_bytes);
+    }
+    Ok(balances)
+}
+
+/// The accounts that send more of a token in `transfers` than their `balance` plus
+/// what they receive in the transfers. Balances that are not known are not checked.
+pub fn shortfalls(
+    transfers: &[Edge],
+    balance: impl Fn(&Address, &Address) -> Option<U256>,
+) -> Vec<Shortfall> {
+    let mut amounts: BTreeMap<(Address, Address), (U256, U256)> = BTreeMap::new();
+    for transfer in transfers {
+        amounts
+            .entry((transfer.from, transfer.token))
+            .or_default()
+            .0 += transfer.capacity;
+        amounts.entry((transfer.to, transfer.token)).or_default().1 += transfer.capacity;
+    }
+    amounts
+        .into_iter()
+        .filter(|(_, (sent, received))| sent > received)
+        .filter_map(|((holder, token), (sent, received))| {
+            let balance = balance(&holder, &token)?;
+            let needed = sent - received;
+            (balance < needed).then_some(Shortfall {
+                holder,
+                token,
+                needed,
+                balance,
+            })
+        })
+        .collect()
+}
+
+/// A copy of the edges in which the holders of the shortfalls can send at most their
+/// balance of the token.
+pub fn with_balances(edges: &EdgeDB, shortfalls: &[Shortfall]) -> EdgeDB {
+    let mut edges = edges.clone();
+    for shortfall in shortfalls {
+        // The capacities follow known balances.
+        if !edges.balances().is_empty() {
+            edges
+                .balances_mut()
+                .set_balance(shortfall.holder, shortfall.token, shortfall.balance);
+            continue;
+        }
+        for edge in edges.outgoing(&shortfall.holder) {
+            if edge.token == shortfall.token
+                && edge.capacity > shortfall.balance
+                && edges.limit_percentage(&edge).is_none()
+            {
+                edges.update(Edge {
+                    capacity: shortfall.balance,
+                    ..edge
+                });
+            }
+        }
+    }
+    edges
+}
+
+/// The changes that set the balance of `holder` in the tokens of `token` (the token owner)
+/// to `amount`. The capacities of the edges of the holder in the token, including the
+/// edge back to the token owner implied by the balance, follow the balance.
+pub fn balance_changes(holder: Address, token: Address, amount: U256) -> Vec<Change> {
+    vec![Change::Balance {
+        holder,
+        token,
+        amount,
+    }]
+}
+
+fn encode_call(selector: [u8; 4], argument: &Address) -> Vec<u8> {
+    let mut data = selector.to_vec();
+    data.extend(address_word(argument));
+    data
+}
+
+/// Calls all `(target, data)` pairs in one `eth_call` of `aggregate3` and returns the
+/// data returned by each call, None for failed calls.
+fn aggregate(
+    client: &EthClient,
+    calls: &[(Address, Vec<u8>)],
+) -> Result<Vec<Option<Vec<u8>>>, Box<dyn Error>> {
+    if calls.is_empty() {
+        return Ok(vec![]);
+    }
+    let returned = client.eth_call(Address::from(MULTICALL_ADDRESS), &encode_aggregate(calls))?;
+    Ok(decode_aggregate(&returned, calls.len())?)
+}
+
+/// Encodes a call of `aggregate3((address,bool,bytes)[])` that allows each call to fail.
+fn encode_aggregate(calls: &[(Address, Vec<u8>)]) -> Vec<u8> {
+    let tuples = calls
+        .iter()
+        .map(|(target, data)| {
+            let mut tuple = address_word(target).to_vec();
+            tuple.extend(uint_word(1));
+            tuple.extend(uint_word(0x60));
+            tuple.extend(bytes_encoding(data));
+            tuple
+        })
+        .collect::<Vec<_>>();
+    let mut encoded = selector("aggregate3((address,bool,bytes)[])").to_vec();
+    encoded.extend(uint_word(0x20));
+    encoded.extend(uint_word(calls.len()));
+    let mut offset = 32 * calls.len();
+    for tuple in &tuples {
+        encoded.extend(uint_word(offset));
+        offset += tuple.len();
+    }
+    encoded.extend(tuples.concat());
+    encoded
+}
+
+/// Decodes the `(bool success, bytes returnData)[]` returned by `aggregate3`.
+fn decode_aggregate(data: &[u8], count: usize) -> Result<Vec<Option<Vec<u8>>>, String> {
+    let invalid = || "Invalid data returned by Multicall3".to_string();
+    let word = |at: usize| -> Result<usize, String> {
+        let word = data.get(at..at + 32).ok_or_else(invalid)?;
+        match word[..24].iter().all(|b| *b == 0) {
+            true => Ok(u64::from_be_bytes(word[24..].try_into().unwrap()) as usize),
+            false => Err(invalid()),
+        }
+    };
+    let array = word(0)?;
+    if word(array)? != count {
+        return Err(invalid());
+    }
+    let base = array + 32;
+    (0..count)
+        .map(|i| {
+            let tuple = base + word(base + 32 * i)?;
+            let success = word(tuple)? != 0;
+            let bytes = tuple + word(tuple + 32)?;
+            let length = word(bytes)?;
+            let returned = data
+                .get(bytes + 32..bytes + 32 + length)
+                .ok_or_else(invalid)?;
+            Ok(success.then(|| returned.to_vec()))
+        })
+        .collect()
+}
+
+fn decode_word(data: &[u8]) -> Option<[u8; 32]> {
+    data.get(..32)?.try_into().ok()
+}
+
+fn word_address(word: &[u8; 32]) -> [u8; 20] {
+    word[12..].try_into().unwrap()
+}
+
+fn address_word(address: &Address) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[12..].copy_from_slice(&address.to_bytes());
+    word
+}
+
+fn uint_word(value: usize) -> [u8; 32] {
+    U256::from(value as u128).to_be_bytes()
+}
+
+/// The length and the data, padded to a multiple of 32 bytes.
+fn bytes_encoding(data: &[u8]) -> Vec<u8> {
+    let mut encoded = uint_word(data.len()).to_vec();
+    encoded.extend(data);
+    encoded.resize(32 + data.len().div_ceil(32) * 32, 0);
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::edge::edge;
+
+    #[test]
+    fn multicall_encoding() {
+        let (a, b) = (Address::from([1; 20]), Address::from([2; 20]));
+        let balance_of = selector("balanceOf(address)");
+        assert_eq!(balance_of, [0x70, 0xa0, 0x82, 0x31]);
+        let encoded = encode_aggregate(&[(a, encode_call(balance_of, &b))]);
+        assert_eq!(encoded[..4], selector("aggregate3((address,bool,bytes)[])"));
+        // Offset and length of the array, offset, target, allowFailure, offset and length
+        // of the data and the data, padded to 64 bytes.
+        assert_eq!(encoded.len(), 4 + 32 * 7 + 64);
+        assert_eq!(encoded[4 + 32 * 3..4 + 32 * 4], address_word(&a));
+        assert_eq!(encoded[4 + 32 * 7..4 + 32 * 7 + 4], balance_of);
+
+        // A successful and a failed call.
+        let returned = [
+            uint_word(0x20),
+            uint_word(2),
+            uint_word(0x40),
+            uint_word(0xc0),
+            uint_word(1),
+            uint_word(0x40),
+            uint_word(32),
+            U256::from(70u128).to_be_bytes(),
+            uint_word(0),
+            uint_word(0x40),
+            uint_word(0),
+        ]
+        .concat();
+        let decoded = decode_aggregate(&returned, 2).unwrap();
+        assert_eq!(decoded[0], Some(U256::from(70u128).to_be_bytes().to_vec()));
+        assert_eq!(decoded[1], None);
+        assert!(decode_aggregate(&returned[..100], 2).is_err());
+    }
+
+    #[test]
+    fn stale_balances() {
+        let (a, b, c) = (
+            Address::from([1; 20]),
+            Address::from([2; 20]),
+            Address::from([3; 20]),
+        );
+        let edges = EdgeDB::new(vec![edge(a, b, a, 100), edge(b, c, a, 100)]);
+        // b forwards the tokens of a it receives, so only a needs a balance.
+        let transfers = [edge(a, b, a, 80), edge(b, c, a, 80)];
+        let found = shortfalls(&transfers, |holder, _| {
+            Some(U256::from(if *holder == a { 50u128 } else { 0 }))
+        });
+        assert_eq!(
+            found,
+            vec![Shortfall {
+                holder: a,
+                token: a,
+                needed: U256::from(80u128),
+                balance: U256::from(50u128),
+            }]
+        );
+        assert!(shortfalls(&transfers, |_, _| None).is_empty());
+
+        let corrected = with_balances(&edges, &found);
+        assert_eq!(
+            corrected.edge(&transfers[0]).unwrap().capacity,
+            U256::from(50u128)
+        );
+        assert_eq!(
+            corrected.edge(&transfers[1]).unwrap().capacity,
+            U256::from(100u128)
+        );
+    }
+
+    #[test]
+    fn balance_updates() {
+        let (a, b, c) = (
+            Address::from([1; 20]),
+            Address::from([2; 20]),
+            Address::from([3; 20]),
+        );
+        // a holds 100 tokens of b and can send 60 of them to c.
+        let mut edges = EdgeDB::new(vec![edge(a, b, b, 100), edge(a, c, b, 60)]);
+        edges.set_limit_percentage(&edge(c, a, c, 0), 50);
+        let apply = |edges: &mut EdgeDB, amount: u128| {
+            for change in balance_changes(a, b, U256::from(amount)) {
+                change.apply(edges);
+            }
+        };
+        apply(&mut edges, 40);
+        assert_eq!(edges.balances().balance(&a, &b), U256::from(40u128));
+        assert_eq!(
+            edges.edge(&edge(a, b, b, 0)).unwrap().capacity,
+            U256::from(40u128)
+        );
+        assert_eq!(
+            edges.edge(&edge(a, c, b, 0)).unwrap().capacity,
+            U256::from(40u128)
+        );
+        // The stored capacity of the edge to c limits it again once the balance is higher.
+        apply(&mut edges, 200);
+        assert_eq!(
+            edges.edge(&edge(a, b, b, 0)).unwrap().capacity,
+            U256::from(200u128)
+        );
+        assert_eq!(
+            edges.edge(&edge(a, c, b, 0)).unwrap().capacity,
+            U256::from(60u128)
+        );
+        assert_eq!(
+            edges.edges().iter().map(|e| e.capacity).collect::<Vec<_>>(),
+            vec![U256::from(100u128), U256::from(60u128), U256::from(0u128)],
+            "Balances are not copied into the stored capacities"
+        );
+        assert_eq!(edges.limit_percentage(&edge(c, a, c, 0)), Some(50));
+
+        // The edge back to the owner is implied by a new balance, but not stored.
+        for change in balance_changes(c, b, U256::from(5u128)) {
+            change.apply(&mut edges);
+        }
+        assert_eq!(edges.outgoing(&c), vec![edge(c, b, b, 5)]);
+        assert_eq!(edges.edge_count(), 3);
+    }
+}
@@ -0,0 +1,134 @@
+//! A subscription to the new blocks of a node (`eth_subscribe` to `newHeads`) over
+//! a WebSocket connection, `ws://` or `wss://`.
+
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use super::rpc::Header;
+use crate::error::PathfinderError;
+use crate::websocket::{self, Message};
+
+/// New blocks are expected every few seconds, a connection without any for this long is
+/// considered broken.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct NewHeads {
+    stream: Stream,
+}
+
+enum Stream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl NewHeads {
+    /// Connects to the node at `url` and subscribes to new blocks.
+    pub fn subscribe(url: &str) -> Result<NewHeads, Box<dyn Error>> {
+        let (tls, rest) = match url.split_once("://") {
+            Some(("ws", rest)) => (false, rest),
+            Some(("wss", rest)) => (true, rest),
+            _ => return Err(format!("Expected a ws:// or wss:// URL, got {url}").into()),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let host = match authority.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() => host,
+            _ => authority,
+        };
+        let address = match authority == host {
+            true => format!("{host}:{}", if tls { 443 } else { 80 }),
+            false => authority.to_string(),
+        };
+        let socket = TcpStream::connect(address)?;
+        socket.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut stream = match tls {
+            false => Stream::Plain(socket),
+            true => {
+                let connection =
+                    ClientConnection::new(tls_config()?, ServerName::try_from(host.to_string())?)?;
+                Stream::Tls(Box::new(StreamOwned::new(connection, socket)))
+            }
+        };
+        websocket::client_handshake(&mut stream, authority, path)?;
+        let request = json::object! {
+            jsonrpc: "2.0", id: 1, method: "eth_subscribe", params: ["newHeads"]
+        };
+        websocket::write_client_text(&mut stream, &request.dump())?;
+        let mut heads = NewHeads { stream };
+        let response = heads.next_message()?;
+        if !response["error"].is_null() {
+            let message = format!("eth_subscribe failed: {}", response["error"]["message"]);
+            return Err(PathfinderError::Chain(message).into());
+        }
+        Ok(heads)
+    }
+
+    /// Waits for the next block.
+    pub fn next_head(&mut self) -> Result<Header, Box<dyn Error>> {
+        loop {
+            let message = self.next_message()?;
+            if message["method"] == "eth_subscription" {
+                return Ok(Header::from_json(&message["params"]["result"])?);
+            }
+        }
+    }
+
+    fn next_message(&mut self) -> Result<json::JsonValue, Box<dyn Error>> {
+        loop {
+            match websocket::read_message(&mut self.stream)? {
+                Message::Text(text) => return Ok(json::parse(&text)?),
+                Message::Ping(payload) => websocket::write_client_pong(&mut self.stream, &payload)?,
+                Message::Close => {
+                    return Err(
+                        PathfinderError::Chain("The node closed the connection.".into()).into(),
+                    )
+                }
+            }
+        }
+    }
+}
+
+fn tls_config() -> Result<Arc<ClientConfig>, Box<dyn Error>> {
+    let roots = RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    let config =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(socket) => socket.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(socket) => socket.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(socket) => socket.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
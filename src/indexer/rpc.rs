@@ -0,0 +1,222 @@
+//! A minimal client of the JSON-RPC interface of an Ethereum (or Gnosis chain) node.
+
+use std::error::Error;
+use std::time::Duration;
+
+use json::JsonValue;
+use rustc_serialize::hex::FromHex;
+
+use crate::error::PathfinderError;
+use crate::types::{Address, U256};
+
+/// The time to connect and the longest time without receiving data.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct EthClient {
+    agent: ureq::Agent,
+    url: String,
+}
+
+/// An event emitted by a contract.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Log {
+    /// The contract that emitted the event.
+    pub address: Address,
+    pub topics: Vec<[u8; 32]>,
+    pub data: Vec<u8>,
+    pub block_number: u64,
+    pub block_hash: [u8; 32],
+    pub log_index: u64,
+}
+
+/// The header fields of a block needed to follow the chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub number: u64,
+    pub hash: [u8; 32],
+    pub parent_hash: [u8; 32],
+}
+
+impl Header {
+    pub fn from_json(header: &JsonValue) -> Result<Header, String> {
+        Ok(Header {
+            number: parse_quantity(&header["number"])?,
+            hash: parse_word(&header["hash"])?,
+            parent_hash: parse_word(&header["parentHash"])?,
+        })
+    }
+}
+
+impl Log {
+    pub fn from_json(log: &JsonValue) -> Result<Log, String> {
+        Ok(Log {
+            address: parse_address(&log["address"])?,
+            topics: log["topics"]
+                .members()
+                .map(parse_word)
+                .collect::<Result<_, _>>()?,
+            data: parse_hex(&log["data"])?,
+            block_number: parse_quantity(&log["blockNumber"])?,
+            block_hash: parse_word(&log["blockHash"])?,
+            log_index: parse_quantity(&log["logIndex"])?,
+        })
+    }
+
+    /// The address in the topic at `index`, if any.
+    pub fn address_topic(&self, index: usize) -> Option<Address> {
+        let topic = self.topics.get(index)?;
+        Some(Address::from(<[u8; 20]>::try_from(&topic[12..]).unwrap()))
+    }
+
+    /// The 32-byte word of the data at `index`, if any.
+    pub fn data_word(&self, index: usize) -> Option<[u8; 32]> {
+        self.data.get(index * 32..(index + 1) * 32)?.try_into().ok()
+    }
+
+    pub fn data_address(&self, index: usize) -> Option<Address> {
+        let word = self.data_word(index)?;
+        Some(Address::from(<[u8; 20]>::try_from(&word[12..]).unwrap()))
+    }
+
+    pub fn data_u256(&self, index: usize) -> Option<U256> {
+        Some(U256::from_be_bytes(self.data_word(index)?))
+    }
+}
+
+impl EthClient {
+    pub fn new(url: &str) -> EthClient {
+        EthClient {
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(TIMEOUT)
+                .timeout_read(TIMEOUT)
+                .build(),
+            url: url.to_string(),
+        }
+    }
+
+    /// The number of the latest block.
+    pub fn block_number(&self) -> Result<u64, Box<dyn Error>> {
+        Ok(parse_quantity(
+            &self.call("eth_blockNumber", json::array![])?,
+        )?)
+    }
+
+    /// The logs of the blocks `from` to `to` (inclusive), optionally only those
+    /// of the contract at `address`, whose first topic is one of `topics`.
+    pub fn logs(
+        &self,
+        from: u64,
+        to: u64,
+        address: Option<Address>,
+        topics: &[[u8; 32]],
+    ) -> Result<Vec<Log>, Box<dyn Error>> {
+        let filter = json::object! {
+            fromBlock: format!("{from:#x}"),
+            toBlock: format!("{to:#x}"),
+        };
+        self.filter_logs(filter, address, topics)
+    }
+
+    /// Like `logs`, but of the block with the given hash, so that the logs are
+    /// of that block even if it is not part of the chain (anymore).
+    pub fn block_logs(
+        &self,
+        hash: &[u8; 32],
+        address: Option<Address>,
+        topics: &[[u8; 32]],
+    ) -> Result<Vec<Log>, Box<dyn Error>> {
+        self.filter_logs(json::object! { blockHash: to_hex(hash) }, address, topics)
+    }
+
+    /// Calls the contract at `to` with `data` at the latest block, returns the returned data.
+    pub fn eth_call(&self, to: Address, data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let call = json::object! { to: to.to_string(), data: to_hex_bytes(data) };
+        Ok(parse_hex(
+            &self.call("eth_call", json::array![call, "latest"])?,
+        )?)
+    }
+
+    /// The header of the block with the given hash.
+    pub fn header(&self, hash: &[u8; 32]) -> Result<Header, Box<dyn Error>> {
+        let block = self.call("eth_getBlockByHash", json::array![to_hex(hash), false])?;
+        if block.is_null() {
+            return Err(PathfinderError::Chain(format!("Unknown block {}", to_hex(hash))).into());
+        }
+        Ok(Header::from_json(&block)?)
+    }
+
+    fn filter_logs(
+        &self,
+        mut filter: JsonValue,
+        address: Option<Address>,
+        topics: &[[u8; 32]],
+    ) -> Result<Vec<Log>, Box<dyn Error>> {
+        filter["topics"] = json::array![topics.iter().map(to_hex).collect::<Vec<_>>()];
+        if let Some(address) = address {
+            filter["address"] = address.to_string().into();
+        }
+        let logs = self.call("eth_getLogs", json::array![filter])?;
+        Ok(logs
+            .members()
+            .map(Log::from_json)
+            .collect::<Result<_, _>>()?)
+    }
+
+    fn call(&self, method: &str, params: JsonValue) -> Result<JsonValue, Box<dyn Error>> {
+        let request = json::object! { jsonrpc: "2.0", id: 1, method: method, params: params };
+        let response = self
+            .agent
+            .post(&self.url)
+            .set("Content-Type", "application/json")
+            .send_string(&request.dump())?
+            .into_string()?;
+        let mut response = json::parse(&response)?;
+        if !response["error"].is_null() {
+            let message = format!("{method} failed: {}", response["error"]["message"]);
+            return Err(PathfinderError::Chain(message).into());
+        }
+        Ok(response["result"].take())
+    }
+}
+
+pub fn to_hex(word: &[u8; 32]) -> String {
+    to_hex_bytes(word)
+}
+
+pub fn to_hex_bytes(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 + 2 * bytes.len());
+    hex.push_str("0x");
+    for b in bytes {
+        hex.push_str(&format!("{b:02x}"));
+    }
+    hex
+}
+
+fn parse_hex(value: &JsonValue) -> Result<Vec<u8>, String> {
+    value
+        .as_str()
+        .and_then(|v| v.strip_prefix("0x"))
+        .and_then(|v| v.from_hex().ok())
+        .ok_or_else(|| format!("Expected hex data, got {value}"))
+}
+
+fn parse_word(value: &JsonValue) -> Result<[u8; 32], String> {
+    parse_hex(value)?
+        .try_into()
+        .map_err(|_| format!("Expected 32 bytes, got {value}"))
+}
+
+fn parse_address(value: &JsonValue) -> Result<Address, String> {
+    let bytes: [u8; 20] = parse_hex(value)?
+        .try_into()
+        .map_err(|_| format!("Expected an address, got {value}"))?;
+    Ok(Address::from(bytes))
+}
+
+fn parse_quantity(value: &JsonValue) -> Result<u64, String> {
+    value
+        .as_str()
+        .and_then(|v| v.strip_prefix("0x"))
+        .and_then(|v| u64::from_str_radix(v, 16).ok())
+        .ok_or_else(|| format!("Expected a hex quantity, got {value}"))
+}
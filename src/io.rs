@@ -1,73 +1,332 @@
+use std::collections::HashMap;
 use std::collections::{BTreeMap, BTreeSet};
-use std::fs::File;
 use std::io::{self, BufRead};
 use std::io::{Read, Write};
-use std::{collections::HashMap, io::BufReader};
+
+pub mod compression;
+mod graph;
+mod mapped;
+#[cfg(feature = "parquet")]
+mod parquet;
+#[cfg(feature = "rocksdb")]
+mod rocksdb;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+pub mod wal;
+
+pub use compression::Compression;
+use compression::FileWriter;
+pub use graph::{write_graph, GraphFormat};
+pub use mapped::{map_edges, write_edges_mapped};
+#[cfg(feature = "parquet")]
+pub use parquet::{write_edges_parquet, write_transfers_parquet};
+#[cfg(feature = "rocksdb")]
+pub use rocksdb::{read_edges_rocksdb, write_edges_rocksdb, RocksDbEdgeStore};
+#[cfg(feature = "sqlite")]
+pub use sqlite::{read_edges_sqlite, write_edges_sqlite, SqliteEdgeStore};
 
 use crate::safe_db::db::DB;
 use crate::types::edge::EdgeDB;
-use crate::types::{Address, Edge, Safe, U256};
+use crate::types::{AccountType, Address, Edge, Safe, TokenType, U256};
+
+/// Binary edge files in the v2 format start with these bytes, followed by the version,
+/// the block number of the latest update (zero if unknown), the number of edges, the
+/// length of the payload and its CRC32. The payload has the v1 format: the address index
+/// followed by the edges.
+const EDGES_MAGIC: &[u8; 8] = b"PFEDGEDB";
+const EDGES_VERSION: u32 = 2;
+
+/// Reads an edge file in any format: CSV if the name ends with `.csv` (optionally
+/// followed by `.gz` or `.zst`), a mapped edge file or a binary edge file otherwise.
+pub fn read_edges_file(path: &str) -> Result<EdgeDB, io::Error> {
+    let name = path
+        .strip_suffix(".gz")
+        .or(path.strip_suffix(".zst"))
+        .unwrap_or(path);
+    if name.ends_with(".csv") {
+        read_edges_csv(path)
+    } else if compression::open(path)?
+        .fill_buf()?
+        .starts_with(mapped::MAPPED_MAGIC)
+    {
+        map_edges(path)
+    } else {
+        read_edges_binary(path)
+    }
+}
 
-pub fn read_edges_binary(path: &String) -> Result<EdgeDB, io::Error> {
-    let mut f = File::open(path)?;
-    let address_index = read_address_index(&mut f)?;
-    read_edges(&mut f, &address_index)
+/// Reads a binary edge file in the v2 format, or in the v1 format without a header.
+pub fn read_edges_binary(path: &str) -> Result<EdgeDB, io::Error> {
+    read_edges_binary_from(compression::open(path)?)
 }
 
-pub fn read_edges_csv(path: &String) -> Result<EdgeDB, io::Error> {
+/// Reads binary edges like `read_edges_binary`, malformed input results in an error.
+pub fn read_edges_binary_from(mut f: impl BufRead) -> Result<EdgeDB, io::Error> {
+    if !f.fill_buf()?.starts_with(EDGES_MAGIC) {
+        let address_index = read_address_index(&mut f)?;
+        return read_edges(&mut f, &address_index);
+    }
+    f.consume(EDGES_MAGIC.len());
+    let version = read_u32(&mut f)?;
+    if version != EDGES_VERSION {
+        return Err(invalid_data(format!(
+            "Unsupported edge file version {version}"
+        )));
+    }
+    let _block_number = read_u64(&mut f)?;
+    let edge_count = read_u64(&mut f)?;
+    let payload_length = read_u64(&mut f)?;
+    let checksum = read_u32(&mut f)?;
+
+    let mut payload = ChecksumReader {
+        inner: f.take(payload_length),
+        hasher: crc32fast::Hasher::new(),
+        length: 0,
+    };
+    let edges = read_address_index(&mut payload)
+        .and_then(|address_index| read_edges(&mut payload, &address_index));
+    // The rest of the payload is read to tell truncated files from damaged ones.
+    io::copy(&mut payload, &mut io::sink())?;
+    if payload.length != payload_length {
+        return Err(invalid_data(format!(
+            "Truncated edge file: expected {payload_length} bytes of edges, found {}",
+            payload.length
+        )));
+    }
+    if payload.hasher.finalize() != checksum {
+        return Err(invalid_data(
+            "Checksum mismatch, the edge file is damaged".to_string(),
+        ));
+    }
+    let edges = edges?;
+    if edges.edge_count() as u64 != edge_count {
+        return Err(invalid_data(format!(
+            "Expected {edge_count} edges, found {}",
+            edges.edge_count()
+        )));
+    }
+    Ok(edges)
+}
+
+/// Reads lines of the form `from,to,token,capacity` with an optional
+/// fifth column containing the block number of the last update.
+/// A header line starting with `from,` is skipped.
+pub fn read_edges_csv(path: &str) -> Result<EdgeDB, io::Error> {
     let mut edges = Vec::new();
-    let f = BufReader::new(File::open(path)?);
-    for line in f.lines() {
+    let mut block_numbers = Vec::new();
+    let f = compression::open(path)?;
+    for (i, line) in f.lines().enumerate() {
         let line = line?;
-        match &line.split(',').collect::<Vec<_>>()[..] {
+        if i == 0 && line.starts_with("from,") {
+            continue;
+        }
+        let (edge, block_number) = match &line.split(',').collect::<Vec<_>>()[..] {
             [] => continue,
-            [from, to, token, capacity] => {
-                let from = Address::from(unescape(from));
-                let to = Address::from(unescape(to));
-                let token = Address::from(unescape(token));
-                let capacity = U256::from(unescape(capacity));
-                edges.push(Edge {
-                    from,
-                    to,
-                    token,
-                    capacity,
-                });
-            }
+            [from, to, token, capacity] => (parse_csv_edge(from, to, token, capacity)?, ""),
+            [from, to, token, capacity, block_number] => (
+                parse_csv_edge(from, to, token, capacity)?,
+                unescape(block_number)?,
+            ),
             _ => {
-                return Result::Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Expected from,to,token,capacity, but got {line}"),
-                ))
+                return Result::Err(io::Error::other(format!(
+                    "Expected from,to,token,capacity[,block_number], but got {line}"
+                )))
             }
+        };
+        if !block_number.is_empty() {
+            let block_number = block_number
+                .parse::<u64>()
+                .map_err(|e| io::Error::other(format!("Invalid block number in {line}: {e}")))?;
+            block_numbers.push((edge, block_number));
         }
+        edges.push(edge);
+    }
+    let mut edges = EdgeDB::new(edges);
+    for (edge, block_number) in block_numbers {
+        edges.set_updated_at(&edge, block_number);
     }
-    Ok(EdgeDB::new(edges))
+    Ok(edges)
+}
+
+fn parse_csv_edge(from: &str, to: &str, token: &str, capacity: &str) -> Result<Edge, io::Error> {
+    let capacity = unescape(capacity)?;
+    Ok(Edge {
+        from: parse_csv_address(unescape(from)?)?,
+        to: parse_csv_address(unescape(to)?)?,
+        token: parse_csv_address(unescape(token)?)?,
+        capacity: capacity
+            .parse()
+            .map_err(|e| invalid_data(format!("Invalid capacity {capacity}: {e}")))?,
+    })
 }
 
-pub fn write_edges_binary(edges: &EdgeDB, path: &String) -> Result<(), io::Error> {
-    let mut file = File::create(path)?;
-    let address_index = write_address_index(&mut file, addresses_from_edges(edges))?;
-    write_edges(&mut file, edges, &address_index)
+/// Writes a binary edge file in the v2 format, compressed if the path ends
+/// with `.gz` or `.zst`. Edge files contain no balances, so the edges back to the
+/// token owners implied by them are written like stored edges.
+pub fn write_edges_binary(edges: &EdgeDB, path: &str) -> Result<(), io::Error> {
+    let mut file = FileWriter::create(path)?;
+    write_edges_binary_to(edges, &mut file)?;
+    file.finish()
 }
 
-pub fn write_edges_csv(edges: &EdgeDB, path: &String) -> Result<(), io::Error> {
-    let mut file = File::create(path)?;
-    let mut sorted_edges = edges.edges().clone();
+/// Writes binary edges like `write_edges_binary`, uncompressed, e.g. to a socket.
+pub fn write_edges_binary_to(edges: &EdgeDB, out: &mut impl Write) -> Result<(), io::Error> {
+    let mut sorted_edges = edges.effective_edges();
     sorted_edges.sort();
-    for Edge {
-        from,
-        to,
-        token,
-        capacity,
-    } in sorted_edges
-    {
-        writeln!(file, "{from},{to},{token},{capacity}")?;
+    let addresses = addresses_from_edges(edges, &sorted_edges);
+    // The header contains the length and checksum of the payload,
+    // so the payload is serialized once to compute them.
+    let mut payload = ChecksumWriter {
+        inner: io::sink(),
+        hasher: crc32fast::Hasher::new(),
+        length: 0,
+    };
+    let address_index = write_address_index(&mut payload, addresses.clone())?;
+    write_edges(&mut payload, edges, &sorted_edges, &address_index)?;
+
+    write_edges_header(
+        out,
+        edges,
+        sorted_edges.len(),
+        payload.length,
+        payload.hasher.finalize(),
+    )?;
+    let address_index = write_address_index(out, addresses)?;
+    write_edges(out, edges, &sorted_edges, &address_index)
+}
+
+fn write_edges_header(
+    file: &mut impl Write,
+    edges: &EdgeDB,
+    edge_count: usize,
+    payload_length: u64,
+    checksum: u32,
+) -> Result<(), io::Error> {
+    file.write_all(EDGES_MAGIC)?;
+    write_u32(file, EDGES_VERSION)?;
+    write_u64(file, edges.block_number().unwrap_or_default())?;
+    write_u64(file, edge_count as u64)?;
+    write_u64(file, payload_length)?;
+    write_u32(file, checksum)
+}
+
+/// Computes the length and CRC32 of the data read through it.
+struct ChecksumReader<R: Read> {
+    inner: R,
+    hasher: crc32fast::Hasher,
+    length: u64,
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.length += read as u64;
+        Ok(read)
     }
-    Ok(())
+}
+
+/// Computes the length and CRC32 of the data written through it.
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+    length: u64,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.length += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub fn write_edges_csv(edges: &EdgeDB, path: &str) -> Result<(), io::Error> {
+    let mut file = FileWriter::create(path)?;
+    let mut sorted_edges = edges.effective_edges();
+    sorted_edges.sort();
+    for edge in sorted_edges {
+        let Edge {
+            from,
+            to,
+            token,
+            capacity,
+        } = edge;
+        if edges.block_number().is_some() {
+            let block_number = edges
+                .updated_at(&edge)
+                .map(|b| b.to_string())
+                .unwrap_or_default();
+            writeln!(file, "{from},{to},{token},{capacity},{block_number}")?;
+        } else {
+            writeln!(file, "{from},{to},{token},{capacity}")?;
+        }
+    }
+    file.finish()
+}
+
+/// Reads trust relations from lines of the form `truster,trustee,limit`, where `limit`
+/// is the trust limit percentage. A header line starting with `truster,` is skipped.
+pub fn read_trust_csv(path: &str) -> Result<Vec<(Address, Address, u8)>, io::Error> {
+    let mut trust = Vec::new();
+    let f = compression::open(path)?;
+    for (i, line) in f.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() || (i == 0 && line.starts_with("truster,")) {
+            continue;
+        }
+        let fields = line
+            .split(',')
+            .map(unescape)
+            .collect::<Result<Vec<_>, _>>()?;
+        let [truster, trustee, limit] = fields[..] else {
+            return Err(invalid_data(format!(
+                "Expected truster,trustee,limit, but got {line}"
+            )));
+        };
+        let limit = limit
+            .parse::<u8>()
+            .ok()
+            .filter(|limit| *limit <= 100)
+            .ok_or_else(|| invalid_data(format!("Invalid trust limit in {line}")))?;
+        trust.push((
+            parse_csv_address(truster)?,
+            parse_csv_address(trustee)?,
+            limit,
+        ));
+    }
+    Ok(trust)
+}
+
+/// Writes the trust relations of the edges with a trust limit percentage
+/// as `truster,trustee,limit`, with a header line, and returns their number.
+pub fn write_trust_csv(edges: &EdgeDB, path: &str) -> Result<usize, io::Error> {
+    let mut file = FileWriter::create(path)?;
+    // The trustee can send its own tokens to the truster.
+    let mut trust = edges
+        .limit_percentages()
+        .map(|(edge, limit)| (edge.to, edge.from, limit))
+        .collect::<Vec<_>>();
+    trust.sort();
+    writeln!(file, "truster,trustee,limit")?;
+    for (truster, trustee, limit) in &trust {
+        writeln!(file, "{truster},{trustee},{limit}")?;
+    }
+    file.finish()?;
+    Ok(trust.len())
+}
+
+fn parse_csv_address(address: &str) -> Result<Address, io::Error> {
+    address.parse().map_err(invalid_data)
 }
 
 pub fn import_from_safes_binary(path: &str) -> Result<DB, io::Error> {
-    let mut f = File::open(path)?;
+    let mut f = compression::open(path)?;
 
     let mut safes: BTreeMap<Address, Safe> = Default::default();
 
@@ -76,17 +335,19 @@ pub fn import_from_safes_binary(path: &str) -> Result<DB, io::Error> {
     // organizations
     for _ in 0..read_u32(&mut f)? {
         let org_address = read_address(&mut f, &address_index)?;
-        safes.entry(org_address).or_default().organization = true;
+        safes.entry(org_address).or_default().account_type = AccountType::Organization;
     }
 
     // trust edges
     for _ in 0..read_u32(&mut f)? {
-        let user = read_address(&mut f, &address_index)?;
-        assert!(user != Address::default());
-        let send_to = read_address(&mut f, &address_index)?;
-        assert!(send_to != Address::default());
+        let user = read_nonzero_address(&mut f, &address_index)?;
+        let send_to = read_nonzero_address(&mut f, &address_index)?;
         let limit_percentage = read_u8(&mut f)?;
-        assert!(limit_percentage <= 100);
+        if limit_percentage > 100 {
+            return Err(invalid_data(format!(
+                "Invalid trust limit {limit_percentage} of {user} for {send_to}"
+            )));
+        }
 
         if send_to != user && limit_percentage > 0 {
             safes
@@ -99,12 +360,10 @@ pub fn import_from_safes_binary(path: &str) -> Result<DB, io::Error> {
 
     // balances
     for _ in 0..read_u32(&mut f)? {
-        let user = read_address(&mut f, &address_index)?;
-        assert!(user != Address::default());
-        let token_owner = read_address(&mut f, &address_index)?;
-        assert!(token_owner != Address::default());
+        let user = read_nonzero_address(&mut f, &address_index)?;
+        let token_owner = read_nonzero_address(&mut f, &address_index)?;
         let balance = read_u256(&mut f)?;
-        if balance != U256::from(0) {
+        if balance != U256::from(0u128) {
             safes
                 .entry(user)
                 .or_default()
@@ -113,6 +372,18 @@ pub fn import_from_safes_binary(path: &str) -> Result<DB, io::Error> {
         }
     }
 
+    // groups (optional, Circles v2)
+    for _ in 0..read_optional(&mut f, read_u32)?.unwrap_or_default() {
+        let group_address = read_address(&mut f, &address_index)?;
+        safes.entry(group_address).or_default().account_type = AccountType::Group;
+    }
+
+    // stopped accounts (optional)
+    for _ in 0..read_optional(&mut f, read_u32)?.unwrap_or_default() {
+        let stopped_address = read_address(&mut f, &address_index)?;
+        safes.entry(stopped_address).or_default().stopped = true;
+    }
+
     // we use the safe address as token address
     let mut token_owner = BTreeMap::default();
     for (addr, safe) in &mut safes {
@@ -124,12 +395,15 @@ pub fn import_from_safes_binary(path: &str) -> Result<DB, io::Error> {
 }
 
 pub fn export_safes_to_binary(db: &DB, path: &str) -> Result<(), io::Error> {
-    let mut file = File::create(path)?;
+    let mut file = FileWriter::create(path)?;
 
     let address_index = write_address_index(&mut file, addresses_from_safes(db.safes()))?;
 
     // organizations
-    let organizations = db.safes().iter().filter(|s| s.1.organization);
+    let organizations = db
+        .safes()
+        .iter()
+        .filter(|s| s.1.account_type == AccountType::Organization);
     write_u32(&mut file, organizations.clone().count() as u32)?;
     for (user, _) in organizations {
         write_address(&mut file, user, &address_index)?;
@@ -160,10 +434,29 @@ pub fn export_safes_to_binary(db: &DB, path: &str) -> Result<(), io::Error> {
         write_address(&mut file, token_owner, &address_index)?;
         write_u256(&mut file, amount)?;
     }
-    Ok(())
+
+    // groups and stopped accounts, omitted if there are none to keep the v1 format
+    let groups = db
+        .safes()
+        .iter()
+        .filter(|s| s.1.account_type == AccountType::Group);
+    let stopped = db.safes().iter().filter(|s| s.1.stopped);
+    if groups.clone().next().is_some() || stopped.clone().next().is_some() {
+        write_u32(&mut file, groups.clone().count() as u32)?;
+        for (group, _) in groups {
+            write_address(&mut file, group, &address_index)?;
+        }
+    }
+    if stopped.clone().next().is_some() {
+        write_u32(&mut file, stopped.clone().count() as u32)?;
+        for (user, _) in stopped {
+            write_address(&mut file, user, &address_index)?;
+        }
+    }
+    file.finish()
 }
 
-fn read_address_index(file: &mut File) -> Result<HashMap<u32, Address>, io::Error> {
+fn read_address_index(file: &mut impl Read) -> Result<HashMap<u32, Address>, io::Error> {
     let address_count = read_u32(file)?;
     let mut addresses = HashMap::new();
     for i in 0..address_count {
@@ -174,16 +467,17 @@ fn read_address_index(file: &mut File) -> Result<HashMap<u32, Address>, io::Erro
     Ok(addresses)
 }
 
-fn addresses_from_edges(edges: &EdgeDB) -> BTreeSet<Address> {
+fn addresses_from_edges(edges: &EdgeDB, effective_edges: &[Edge]) -> BTreeSet<Address> {
     let mut addresses = BTreeSet::new();
     for Edge {
         from, to, token, ..
-    } in edges.edges()
+    } in effective_edges
     {
         addresses.insert(*from);
         addresses.insert(*to);
         addresses.insert(*token);
     }
+    addresses.extend(edges.token_types().keys());
     addresses
 }
 
@@ -194,8 +488,10 @@ fn addresses_from_safes(safes: &BTreeMap<Address, Safe>) -> BTreeSet<Address> {
         Safe {
             token_address,
             balances,
+            balance_days: _,
             limit_percentage,
-            organization: _,
+            account_type: _,
+            stopped: _,
         },
     ) in safes
     {
@@ -212,7 +508,7 @@ fn addresses_from_safes(safes: &BTreeMap<Address, Safe>) -> BTreeSet<Address> {
 }
 
 fn write_address_index(
-    file: &mut File,
+    file: &mut impl Write,
     addresses: BTreeSet<Address>,
 ) -> Result<HashMap<Address, u32>, io::Error> {
     write_u32(file, addresses.len() as u32)?;
@@ -224,54 +520,81 @@ fn write_address_index(
     Ok(index)
 }
 
-fn read_u32(file: &mut File) -> Result<u32, io::Error> {
+fn read_u32(file: &mut impl Read) -> Result<u32, io::Error> {
     let mut buf = [0; 4];
     file.read_exact(&mut buf)?;
     Ok(u32::from_be_bytes(buf))
 }
 
-fn write_u32(file: &mut File, v: u32) -> Result<(), io::Error> {
+fn write_u32(file: &mut impl Write, v: u32) -> Result<(), io::Error> {
     let buf = v.to_be_bytes();
     file.write_all(&buf)
 }
 
-fn read_u8(file: &mut File) -> Result<u8, io::Error> {
+fn read_u64(file: &mut impl Read) -> Result<u64, io::Error> {
+    let mut buf = [0; 8];
+    file.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+fn write_u64(file: &mut impl Write, v: u64) -> Result<(), io::Error> {
+    let buf = v.to_be_bytes();
+    file.write_all(&buf)
+}
+
+fn read_u8(file: &mut impl Read) -> Result<u8, io::Error> {
     let mut buf = [0; 1];
     file.read_exact(&mut buf)?;
     Ok(u8::from_be_bytes(buf))
 }
 
-fn write_u8(file: &mut File, v: u8) -> Result<(), io::Error> {
+fn write_u8(file: &mut impl Write, v: u8) -> Result<(), io::Error> {
     let buf = v.to_be_bytes();
     file.write_all(&buf)
 }
 
 fn read_address(
-    file: &mut File,
+    file: &mut impl Read,
     address_index: &HashMap<u32, Address>,
 ) -> Result<Address, io::Error> {
     let index = read_u32(file)?;
-    Ok(address_index[&index])
+    address_index
+        .get(&index)
+        .copied()
+        .ok_or_else(|| invalid_data(format!("Invalid address index {index}")))
+}
+
+fn read_nonzero_address(
+    file: &mut impl Read,
+    address_index: &HashMap<u32, Address>,
+) -> Result<Address, io::Error> {
+    match read_address(file, address_index)? {
+        address if address == Address::default() => {
+            Err(invalid_data("Unexpected zero address".to_string()))
+        }
+        address => Ok(address),
+    }
 }
 
 fn write_address(
-    file: &mut File,
+    file: &mut impl Write,
     address: &Address,
     address_index: &HashMap<Address, u32>,
 ) -> Result<(), io::Error> {
     write_u32(file, *address_index.get(address).unwrap())
 }
 
-fn read_u256(file: &mut File) -> Result<U256, io::Error> {
+fn read_u256(file: &mut impl Read) -> Result<U256, io::Error> {
     let length = read_u8(file)? as usize;
+    if length > 32 {
+        return Err(invalid_data(format!("Invalid length of a U256: {length}")));
+    }
     let mut bytes = [0u8; 32];
     file.read_exact(&mut bytes[32 - length..32])?;
-    let high = u128::from_be_bytes(*<&[u8; 16]>::try_from(&bytes[0..16]).unwrap());
-    let low = u128::from_be_bytes(*<&[u8; 16]>::try_from(&bytes[16..32]).unwrap());
-    Ok(U256::new(high, low))
+    Ok(U256::from_be_bytes(bytes))
 }
 
-fn write_u256(file: &mut File, v: &U256) -> Result<(), io::Error> {
+fn write_u256(file: &mut impl Write, v: &U256) -> Result<(), io::Error> {
     let v_bytes = v.to_bytes();
     if v_bytes.is_empty() {
         file.write_all(&[1, 0])
@@ -281,7 +604,13 @@ fn write_u256(file: &mut File, v: &U256) -> Result<(), io::Error> {
     }
 }
 
-fn read_edges(file: &mut File, address_index: &HashMap<u32, Address>) -> Result<EdgeDB, io::Error> {
+/// The edges are optionally followed by the block number of the last update
+/// of each edge (zero if unknown) and then by the list of group tokens,
+/// older files end after the edges.
+fn read_edges(
+    file: &mut impl Read,
+    address_index: &HashMap<u32, Address>,
+) -> Result<EdgeDB, io::Error> {
     let edge_count = read_u32(file)?;
     let mut edges = Vec::new();
     for _i in 0..edge_count {
@@ -296,38 +625,271 @@ fn read_edges(file: &mut File, address_index: &HashMap<u32, Address>) -> Result<
             capacity,
         });
     }
-    Ok(EdgeDB::new(edges))
+    let mut edge_db = EdgeDB::new(edges);
+    for i in 0..edge_db.edge_count() {
+        let block_number = if i == 0 {
+            match read_optional(file, read_u64)? {
+                Some(block_number) => block_number,
+                None => return Ok(edge_db),
+            }
+        } else {
+            read_u64(file)?
+        };
+        if block_number != 0 {
+            let edge = edge_db.edges()[i];
+            edge_db.set_updated_at(&edge, block_number);
+        }
+    }
+    for _ in 0..read_optional(file, read_u32)?.unwrap_or_default() {
+        let token = read_address(file, address_index)?;
+        edge_db.set_token_type(token, TokenType::Group);
+    }
+    Ok(edge_db)
 }
 
+/// Writes the `sorted_edges`, the effective edges of `edges`.
 fn write_edges(
-    file: &mut File,
+    file: &mut impl Write,
     edges: &EdgeDB,
+    sorted_edges: &[Edge],
     address_index: &HashMap<Address, u32>,
 ) -> Result<(), io::Error> {
-    write_u32(file, edges.edge_count() as u32)?;
-    let mut sorted_edges = edges.edges().clone();
-    sorted_edges.sort();
+    write_u32(file, sorted_edges.len() as u32)?;
     for Edge {
         from,
         to,
         token,
         capacity,
-    } in &sorted_edges
+    } in sorted_edges
     {
         write_address(file, from, address_index)?;
         write_address(file, to, address_index)?;
         write_address(file, token, address_index)?;
         write_u256(file, capacity)?;
     }
+    if edges.block_number().is_some() || !edges.token_types().is_empty() {
+        for edge in sorted_edges {
+            write_u64(file, edges.updated_at(edge).unwrap_or_default())?;
+        }
+    }
+    if !edges.token_types().is_empty() {
+        let groups = edges
+            .token_types()
+            .iter()
+            .filter(|(_, token_type)| **token_type == TokenType::Group)
+            .map(|(token, _)| *token)
+            .collect::<BTreeSet<_>>();
+        write_u32(file, groups.len() as u32)?;
+        for token in &groups {
+            write_address(file, token, address_index)?;
+        }
+    }
     Ok(())
 }
 
-fn unescape(input: &str) -> &str {
+/// Reads an optional trailing section of a file, returns None at the end of the file.
+fn read_optional<R: Read, T>(
+    file: &mut R,
+    read: fn(&mut R) -> Result<T, io::Error>,
+) -> Result<Option<T>, io::Error> {
+    match read(file) {
+        Ok(v) => Ok(Some(v)),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn unescape(input: &str) -> Result<&str, io::Error> {
     match input.chars().next() {
-        Some('"') | Some('\'') => {
-            assert!(input.len() >= 2 && input.chars().last() == input.chars().next());
-            &input[1..input.len() - 1]
+        Some(quote @ ('"' | '\'')) => input
+            .strip_prefix(quote)
+            .and_then(|input| input.strip_suffix(quote))
+            .ok_or_else(|| invalid_data(format!("Unterminated quote in {input}"))),
+        _ => Ok(input),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::edge::edge;
+    use std::env;
+    use std::fs;
+
+    fn test_edges() -> EdgeDB {
+        let first = edge(
+            "0x1000000000000000000000000000000000000001",
+            "0x2000000000000000000000000000000000000002",
+            "0x1000000000000000000000000000000000000001",
+            10,
+        );
+        let second = edge(
+            "0x2000000000000000000000000000000000000002",
+            "0x3000000000000000000000000000000000000003",
+            "0x2000000000000000000000000000000000000002",
+            20,
+        );
+        let mut edges = EdgeDB::new(vec![first, second]);
+        edges.set_updated_at(&first, 17);
+        edges.set_token_type(second.token, TokenType::Group);
+        edges
+    }
+
+    #[test]
+    fn edges_binary() {
+        let path = env::temp_dir()
+            .join(format!("pathfinder_edges_{}.db", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let edges = test_edges();
+        write_edges_binary(&edges, &path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert!(bytes.starts_with(EDGES_MAGIC));
+        let mut streamed = vec![];
+        write_edges_binary_to(&edges, &mut streamed).unwrap();
+        assert_eq!(streamed, bytes);
+        let read = read_edges_binary(&path).unwrap();
+        assert_eq!(read.edges(), edges.edges());
+        assert_eq!(read.block_number(), Some(17));
+        assert_eq!(read.token_type(&edges.edges()[1].token), TokenType::Group);
+
+        // Files without a header are read in the v1 format.
+        fs::write(&path, &bytes[40..]).unwrap();
+        assert_eq!(read_edges_binary(&path).unwrap().edges(), edges.edges());
+
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        let error = read_edges_binary(&path).unwrap_err();
+        assert!(error.to_string().starts_with("Truncated edge file"));
+
+        let mut damaged = bytes.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        fs::write(&path, &damaged).unwrap();
+        let error = read_edges_binary(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().starts_with("Checksum mismatch"));
+        // A capacity longer than 32 bytes in a v1 file.
+        let mut oversized = vec![0, 0, 0, 1];
+        oversized.extend([0; 20]);
+        oversized.extend([0, 0, 0, 1]);
+        oversized.extend([0; 12]);
+        oversized.push(33);
+        let error = read_edges_binary_from(&oversized[..]).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid length of a U256: 33");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn compressed_edges() {
+        let edges = test_edges();
+        for (extension, magic) in [
+            ("gz", &[0x1f, 0x8b][..]),
+            ("zst", &[0x28, 0xb5, 0x2f, 0xfd]),
+        ] {
+            let path = env::temp_dir()
+                .join(format!(
+                    "pathfinder_edges_{}.db.{extension}",
+                    std::process::id()
+                ))
+                .to_str()
+                .unwrap()
+                .to_string();
+            write_edges_binary(&edges, &path).unwrap();
+            assert!(fs::read(&path).unwrap().starts_with(magic));
+            assert_eq!(read_edges_binary(&path).unwrap().edges(), edges.edges());
+
+            write_edges_csv(&edges, &path).unwrap();
+            assert_eq!(read_edges_csv(&path).unwrap().edges(), edges.edges());
+            fs::remove_file(&path).unwrap();
         }
-        _ => input,
+    }
+    #[test]
+    fn trust_csv() {
+        let path = env::temp_dir()
+            .join(format!("pathfinder_trust_{}.csv", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let mut edges = test_edges();
+        let trust = edges.edges()[0];
+        edges.set_limit_percentage(&trust, 50);
+        assert_eq!(write_trust_csv(&edges, &path).unwrap(), 1);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("truster,trustee,limit\n{},{},50\n", trust.to, trust.from)
+        );
+        assert_eq!(
+            read_trust_csv(&path).unwrap(),
+            vec![(trust.to, trust.from, 50)]
+        );
+
+        fs::write(&path, format!("{},{},150\n", trust.to, trust.from)).unwrap();
+        assert!(read_trust_csv(&path).is_err());
+        fs::write(&path, format!("{},0x12,50\n", trust.to)).unwrap();
+        assert!(read_trust_csv(&path).is_err());
+
+        // Edge files exported from spreadsheets can have a header line.
+        fs::write(
+            &path,
+            format!(
+                "from,to,token,capacity\n{},{},{},10\n",
+                trust.from, trust.to, trust.token
+            ),
+        )
+        .unwrap();
+        assert_eq!(read_edges_csv(&path).unwrap().edge_count(), 1);
+        // Malformed lines are errors instead of panics.
+        for line in [
+            format!("{},0x12,{},10", trust.from, trust.token),
+            format!("{},{},{},-1", trust.from, trust.to, trust.token),
+            format!("\"{},{},{},10", trust.from, trust.to, trust.token),
+        ] {
+            fs::write(&path, line).unwrap();
+            let error = read_edges_csv(&path).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mapped_edges() {
+        let path = env::temp_dir()
+            .join(format!("pathfinder_edges_{}.mapped", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let edges = test_edges();
+        write_edges_mapped(&edges, &path).unwrap();
+        let mut mapped = map_edges(&path).unwrap();
+        assert!(mapped.is_mapped());
+        assert_eq!(mapped.edges(), edges.edges());
+        assert_eq!(mapped.block_number(), Some(17));
+        assert_eq!(mapped.token_type(&edges.edges()[1].token), TokenType::Group);
+        assert_eq!(
+            mapped.outgoing(&edges.edges()[1].from),
+            vec![edges.edges()[1]]
+        );
+
+        // Changes copy the edges out of the file.
+        let update = Edge {
+            capacity: U256::from(5u128),
+            ..edges.edges()[0]
+        };
+        mapped.update(update);
+        assert!(!mapped.is_mapped());
+        assert_eq!(mapped.edge(&update), Some(update));
+        assert_eq!(map_edges(&path).unwrap().edges(), edges.edges());
+
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        let error = map_edges(&path).unwrap_err();
+        assert!(error.to_string().starts_with("Truncated edge file"));
+        fs::remove_file(&path).unwrap();
+
+        assert!(write_edges_mapped(&edges, &format!("{path}.zst")).is_err());
     }
 }
@@ -0,0 +1,98 @@
+//! Transparent compression of database files. Compressed files are recognized by
+//! their magic bytes when reading and by their extension when writing.
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression of a file to be written: gzip for `.gz`, zstd for `.zst`.
+    pub fn from_path(path: &str) -> Compression {
+        if path.ends_with(".gz") {
+            Compression::Gzip
+        } else if path.ends_with(".zst") {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    fn detect(start: &[u8]) -> Compression {
+        if start.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if start.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Opens a file for reading, decompressing it if it is compressed.
+pub fn open(path: &str) -> io::Result<Box<dyn BufRead>> {
+    let mut file = BufReader::new(File::open(path)?);
+    Ok(match Compression::detect(file.fill_buf()?) {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(file)?)),
+    })
+}
+
+/// A file being written, compressed according to its extension.
+pub enum FileWriter {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl FileWriter {
+    pub fn create(path: &str) -> io::Result<FileWriter> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match Compression::from_path(path) {
+            Compression::None => FileWriter::Plain(file),
+            Compression::Gzip => {
+                FileWriter::Gzip(GzEncoder::new(file, flate2::Compression::default()))
+            }
+            Compression::Zstd => FileWriter::Zstd(zstd::Encoder::new(file, 0)?),
+        })
+    }
+
+    /// Completes the compressed stream and flushes the file.
+    pub fn finish(self) -> io::Result<()> {
+        let mut file = match self {
+            FileWriter::Plain(file) => file,
+            FileWriter::Gzip(encoder) => encoder.finish()?,
+            FileWriter::Zstd(encoder) => encoder.finish()?,
+        };
+        file.flush()
+    }
+}
+
+impl Write for FileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            FileWriter::Plain(file) => file.write(buf),
+            FileWriter::Gzip(encoder) => encoder.write(buf),
+            FileWriter::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            FileWriter::Plain(file) => file.flush(),
+            FileWriter::Gzip(encoder) => encoder.flush(),
+            FileWriter::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
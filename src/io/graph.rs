@@ -0,0 +1,156 @@
+//! Export of edges as a graph for visualization, in GraphML (for Gephi)
+//! or in the DOT language of Graphviz.
+
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use super::compression::FileWriter;
+use crate::types::{Address, Edge};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    GraphML,
+    Dot,
+}
+
+impl GraphFormat {
+    /// The format of a file with the extension `.graphml`, `.dot` or `.gv`,
+    /// also if it is compressed.
+    pub fn from_path(path: &str) -> Option<GraphFormat> {
+        let path = path
+            .strip_suffix(".gz")
+            .or(path.strip_suffix(".zst"))
+            .unwrap_or(path);
+        let (_, extension) = path.rsplit_once('.')?;
+        match extension {
+            "graphml" => Some(GraphFormat::GraphML),
+            "dot" | "gv" => Some(GraphFormat::Dot),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "graphml" => Ok(GraphFormat::GraphML),
+            "dot" => Ok(GraphFormat::Dot),
+            _ => Err(format!("Unknown graph format: {s}")),
+        }
+    }
+}
+
+/// Writes the edges as a graph to a file, compressed if the path ends with `.gz` or `.zst`.
+pub fn write_graph(edges: &[Edge], format: GraphFormat, path: &str) -> Result<(), io::Error> {
+    let mut file = FileWriter::create(path)?;
+    match format {
+        GraphFormat::GraphML => write_graphml(edges, &mut file)?,
+        GraphFormat::Dot => write_dot(edges, &mut file)?,
+    }
+    file.finish()
+}
+
+/// Writes a directed graph with the accounts as nodes and one edge per
+/// edge, with the token and the capacity (in wei) as attributes.
+pub fn write_graphml(edges: &[Edge], out: &mut impl Write) -> Result<(), io::Error> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    writeln!(
+        out,
+        r#"  <key id="token" for="edge" attr.name="token" attr.type="string"/>"#
+    )?;
+    writeln!(
+        out,
+        r#"  <key id="capacity" for="edge" attr.name="capacity" attr.type="string"/>"#
+    )?;
+    writeln!(out, r#"  <graph id="circles" edgedefault="directed">"#)?;
+    for node in nodes(edges) {
+        writeln!(out, r#"    <node id="{node}"/>"#)?;
+    }
+    for (i, edge) in edges.iter().enumerate() {
+        writeln!(
+            out,
+            r#"    <edge id="e{i}" source="{}" target="{}">"#,
+            edge.from, edge.to
+        )?;
+        writeln!(out, r#"      <data key="token">{}</data>"#, edge.token)?;
+        writeln!(
+            out,
+            r#"      <data key="capacity">{}</data>"#,
+            edge.capacity.to_decimal()
+        )?;
+        writeln!(out, "    </edge>")?;
+    }
+    writeln!(out, "  </graph>")?;
+    writeln!(out, "</graphml>")
+}
+
+/// Writes a directed graph with the accounts as nodes, edges are labelled with
+/// the short token address and have the full token and capacity as attributes.
+pub fn write_dot(edges: &[Edge], out: &mut impl Write) -> Result<(), io::Error> {
+    writeln!(out, "digraph circles {{")?;
+    for node in nodes(edges) {
+        writeln!(out, r#"  "{node}" [label="{}"];"#, node.short())?;
+    }
+    for edge in edges {
+        writeln!(
+            out,
+            r#"  "{}" -> "{}" [label="{}", token="{}", capacity="{}"];"#,
+            edge.from,
+            edge.to,
+            edge.token.short(),
+            edge.token,
+            edge.capacity.to_decimal()
+        )?;
+    }
+    writeln!(out, "}}")
+}
+
+fn nodes(edges: &[Edge]) -> BTreeSet<Address> {
+    edges.iter().flat_map(|edge| [edge.from, edge.to]).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::U256;
+
+    fn edges() -> Vec<Edge> {
+        vec![Edge {
+            from: Address::from("0x1000000000000000000000000000000000000001"),
+            to: Address::from("0x2000000000000000000000000000000000000002"),
+            token: Address::from("0x1000000000000000000000000000000000000001"),
+            capacity: U256::from(1000u128),
+        }]
+    }
+
+    #[test]
+    fn formats() {
+        let mut dot = Vec::new();
+        write_dot(&edges(), &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph circles {\n"));
+        assert!(dot.contains(
+            r#""0x1000000000000000000000000000000000000001" -> "0x2000000000000000000000000000000000000002" [label="0x100000", token="0x1000000000000000000000000000000000000001", capacity="1000"];"#
+        ));
+
+        let mut graphml = Vec::new();
+        write_graphml(&edges(), &mut graphml).unwrap();
+        let graphml = String::from_utf8(graphml).unwrap();
+        assert_eq!(graphml.matches("<node id=").count(), 2);
+        assert!(graphml.contains(r#"<data key="capacity">1000</data>"#));
+
+        assert_eq!(
+            GraphFormat::from_path("trust.graphml.gz"),
+            Some(GraphFormat::GraphML)
+        );
+        assert_eq!(GraphFormat::from_path("trust.gv"), Some(GraphFormat::Dot));
+        assert_eq!(GraphFormat::from_path("trust.csv"), None);
+    }
+}
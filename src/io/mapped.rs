@@ -0,0 +1,123 @@
+//! Edge files in a fixed-width layout that is used in place after mapping the file
+//! into memory, so that loading only has to build the adjacency index.
+//!
+//! The file starts with a 64-byte header: the magic bytes `PFEDGEMM`, the version as
+//! `u32`, four zero bytes, then the block number of the latest update (zero if unknown),
+//! the number of edges and the number of group tokens as `u64`, followed by zeros.
+//! Then come the edges in the memory layout of `Edge` (96 bytes each), the block number
+//! of the last update of each edge as `u64` (zero if unknown) and the group tokens.
+//! All integers are little-endian.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::Arc;
+
+use memmap2::Mmap;
+
+use super::{invalid_data, Compression};
+use crate::types::edge::{mapped, EdgeDB, MappedEdges};
+use crate::types::{Address, TokenType};
+
+pub(super) const MAPPED_MAGIC: &[u8; 8] = b"PFEDGEMM";
+const MAPPED_VERSION: u32 = 1;
+const HEADER_LENGTH: usize = 64;
+
+/// Maps an edge file into memory and uses its edges in place.
+/// The file must not be changed while the edges are in use.
+pub fn map_edges(path: &str) -> Result<EdgeDB, io::Error> {
+    let file = File::open(path)?;
+    // Safety: the mapping is read-only, changes to the file by other processes
+    // are ruled out by the contract above.
+    let map = unsafe { Mmap::map(&file)? };
+    if map.len() < HEADER_LENGTH || !map.starts_with(MAPPED_MAGIC) {
+        return Err(invalid_data(format!("Not a mapped edge file: {path}")));
+    }
+    let version = u32::from_le_bytes(map[8..12].try_into().unwrap());
+    if version != MAPPED_VERSION {
+        return Err(invalid_data(format!(
+            "Unsupported mapped edge file version {version}"
+        )));
+    }
+    let edge_count = u64_at(&map, 24) as usize;
+    let group_count = u64_at(&map, 32) as usize;
+    let Some((updated_at_offset, groups_offset)) = layout(edge_count, group_count)
+        .filter(|(_, _, length)| *length == map.len())
+        .map(|(updated_at_offset, groups_offset, _)| (updated_at_offset, groups_offset))
+    else {
+        return Err(invalid_data(format!(
+            "Truncated edge file: expected {edge_count} edges and {group_count} groups, found {} bytes",
+            map.len()
+        )));
+    };
+
+    let map = Arc::new(map);
+    let mut edges = EdgeDB::mapped(
+        MappedEdges::new(map.clone(), HEADER_LENGTH, edge_count).map_err(invalid_data)?,
+    );
+    for i in 0..edge_count {
+        let block_number = u64_at(&map, updated_at_offset + 8 * i);
+        if block_number != 0 {
+            let edge = edges.edges()[i];
+            edges.set_updated_at(&edge, block_number);
+        }
+    }
+    for group in map[groups_offset..].chunks(20) {
+        edges.set_token_type(
+            Address::from(<[u8; 20]>::try_from(group).unwrap()),
+            TokenType::Group,
+        );
+    }
+    Ok(edges)
+}
+
+/// Writes an edge file that can be used by `map_edges`. The file cannot be compressed.
+pub fn write_edges_mapped(edges: &EdgeDB, path: &str) -> Result<(), io::Error> {
+    if Compression::from_path(path) != Compression::None {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Mapped edge files cannot be compressed: {path}"),
+        ));
+    }
+    let mut sorted_edges = edges.effective_edges();
+    sorted_edges.sort();
+    let mut groups = edges
+        .token_types()
+        .iter()
+        .filter(|(_, token_type)| **token_type == TokenType::Group)
+        .map(|(token, _)| *token)
+        .collect::<Vec<_>>();
+    groups.sort();
+
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut header = [0u8; HEADER_LENGTH];
+    header[0..8].copy_from_slice(MAPPED_MAGIC);
+    header[8..12].copy_from_slice(&MAPPED_VERSION.to_le_bytes());
+    header[16..24].copy_from_slice(&edges.block_number().unwrap_or_default().to_le_bytes());
+    header[24..32].copy_from_slice(&(sorted_edges.len() as u64).to_le_bytes());
+    header[32..40].copy_from_slice(&(groups.len() as u64).to_le_bytes());
+    file.write_all(&header)?;
+    for edge in &sorted_edges {
+        file.write_all(&mapped::to_bytes(edge))?;
+    }
+    for edge in &sorted_edges {
+        file.write_all(&edges.updated_at(edge).unwrap_or_default().to_le_bytes())?;
+    }
+    for group in groups {
+        file.write_all(&group.to_bytes())?;
+    }
+    file.flush()
+}
+
+/// The offsets of the block numbers and of the group tokens and the length of the file,
+/// None if they overflow.
+fn layout(edge_count: usize, group_count: usize) -> Option<(usize, usize, usize)> {
+    let updated_at_offset =
+        HEADER_LENGTH.checked_add(edge_count.checked_mul(mapped::EDGE_SIZE)?)?;
+    let groups_offset = updated_at_offset.checked_add(edge_count.checked_mul(8)?)?;
+    let length = groups_offset.checked_add(group_count.checked_mul(20)?)?;
+    Some((updated_at_offset, groups_offset, length))
+}
+
+fn u64_at(map: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(map[offset..offset + 8].try_into().unwrap())
+}
@@ -0,0 +1,128 @@
+//! Parquet files of the edges and of transfers, for analysis in e.g. DuckDB or pandas.
+//!
+//! Addresses are lowercase hex strings and values decimal strings in atto-Circles, as
+//! they do not fit into 128 bits in general.
+
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
+
+use arrow_array::builder::{StringBuilder, UInt32Builder, UInt64Builder};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+
+use crate::types::edge::EdgeDB;
+use crate::types::Edge;
+
+/// Writes the edges with their effective capacities, sorted, with the columns `from`,
+/// `to`, `token_owner`, `capacity`, `token_type` and `block_number` (of the last update,
+/// null if unknown).
+pub fn write_edges_parquet(edges: &EdgeDB, path: &str) -> Result<(), io::Error> {
+    let mut sorted_edges = edges.effective_edges();
+    sorted_edges.sort();
+    let mut token_types = StringBuilder::new();
+    let mut block_numbers = UInt64Builder::new();
+    for edge in &sorted_edges {
+        token_types.append_value(edges.token_type(&edge.token).as_str());
+        block_numbers.append_option(edges.updated_at(edge));
+    }
+    let (mut columns, mut fields) = edge_columns(&sorted_edges, "capacity");
+    columns.push(Arc::new(token_types.finish()));
+    fields.push(Field::new("token_type", DataType::Utf8, false));
+    columns.push(Arc::new(block_numbers.finish()));
+    fields.push(Field::new("block_number", DataType::UInt64, true));
+    write_parquet(fields, columns, path)
+}
+
+/// Writes the steps of a transfer in their order, with the columns `step` (starting at 0),
+/// `from`, `to`, `token_owner` and `value`.
+pub fn write_transfers_parquet(transfers: &[Edge], path: &str) -> Result<(), io::Error> {
+    let mut steps = UInt32Builder::new();
+    for step in 0..transfers.len() {
+        steps.append_value(step as u32);
+    }
+    let (mut columns, mut fields) = edge_columns(transfers, "value");
+    columns.insert(0, Arc::new(steps.finish()));
+    fields.insert(0, Field::new("step", DataType::UInt32, false));
+    write_parquet(fields, columns, path)
+}
+
+/// The columns `from`, `to`, `token_owner` and the capacities named `capacity`.
+fn edge_columns(edges: &[Edge], capacity: &str) -> (Vec<ArrayRef>, Vec<Field>) {
+    let mut builders: [StringBuilder; 4] = Default::default();
+    for edge in edges {
+        let [from, to, token, value] = &mut builders;
+        from.append_value(edge.from.to_string());
+        to.append_value(edge.to.to_string());
+        token.append_value(edge.token.to_string());
+        value.append_value(edge.capacity.to_decimal());
+    }
+    let columns = builders
+        .iter_mut()
+        .map(|builder| Arc::new(builder.finish()) as ArrayRef)
+        .collect();
+    let fields = ["from", "to", "token_owner", capacity]
+        .into_iter()
+        .map(|name| Field::new(name, DataType::Utf8, false))
+        .collect();
+    (columns, fields)
+}
+
+fn write_parquet(fields: Vec<Field>, columns: Vec<ArrayRef>, path: &str) -> Result<(), io::Error> {
+    let batch =
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).map_err(io::Error::other)?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(File::create(path)?, batch.schema(), Some(properties))
+        .map_err(io::Error::other)?;
+    writer.write(&batch).map_err(io::Error::other)?;
+    writer.close().map_err(io::Error::other)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::edge::edge;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::UInt64Type;
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn edges() {
+        let path = env::temp_dir()
+            .join(format!("pathfinder_{}_edges.parquet", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let mut edges = EdgeDB::new(vec![edge([2; 20], [3; 20], [2; 20], 20)]);
+        edges.update_at_block(edge([1; 20], [2; 20], [1; 20], 10), 100);
+        write_edges_parquet(&edges, &path).unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let batch = reader.into_iter().next().unwrap().unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        let column = |name: &str| batch.column_by_name(name).unwrap().clone();
+        let from = column("from");
+        assert_eq!(
+            from.as_string::<i32>().value(0),
+            edge([1; 20], [2; 20], [1; 20], 0).from.to_string()
+        );
+        assert_eq!(column("capacity").as_string::<i32>().value(1), "20");
+        assert_eq!(column("token_type").as_string::<i32>().value(0), "personal");
+        let block_numbers = column("block_number");
+        let block_numbers = block_numbers.as_primitive::<UInt64Type>();
+        assert_eq!(block_numbers.value(0), 100);
+        assert!(block_numbers.is_null(1));
+        fs::remove_file(&path).unwrap();
+    }
+}
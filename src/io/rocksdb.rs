@@ -0,0 +1,487 @@
+//! Edges in a RocksDB database, for graphs larger than the memory of the machine: the
+//! edges, the balances and the trust limits stay on disk and are only read for the
+//! accounts a search reaches, see `RocksDbEdgeStore::neighborhood`. Recently read
+//! blocks of the database are kept in an LRU cache.
+//!
+//! Unlike a binary edge file or the SQLite store, the database holds the stored edges
+//! with their balances and trust limit percentages rather than the effective
+//! capacities, so that these are computed like in memory. It has these column families:
+//!
+//! - `edges`: The stored edges by sender, receiver and token owner (20 bytes each).
+//!   The value is the capacity as 32-byte big-endian number, followed by the block
+//!   number of the last update as 8-byte big-endian number if it is known.
+//! - `trust`: The trust limit percentage (1 byte) of edges, with the keys of `edges`.
+//! - `balances`: The balances by holder and token owner (20 bytes each). The value is
+//!   the amount as 32-byte big-endian number, followed by the day it was set at as
+//!   8-byte big-endian number if it decays.
+//! - `accounts`: Flags of accounts by address: 1 if its token is a group token, 2 if
+//!   it is an organization and 4 if it is stopped.
+//! - `changes`: The changes since the edges were written by number (8-byte big-endian),
+//!   encoded like the records of the write-ahead log.
+//!
+//! The default column family holds the block number of the edges under `block_number`.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+use rocksdb::{
+    BlockBasedOptions, Cache, ColumnFamily, ColumnFamilyDescriptor, Direction, IteratorMode,
+    Options, WriteBatch, DB,
+};
+
+use super::wal::{wrapper_changes, Change};
+use crate::types::edge::EdgeDB;
+use crate::types::{Address, Edge, TokenType, U256};
+
+const EDGES: &str = "edges";
+const TRUST: &str = "trust";
+const BALANCES: &str = "balances";
+const ACCOUNTS: &str = "accounts";
+const CHANGES: &str = "changes";
+const COLUMN_FAMILIES: [&str; 5] = [EDGES, TRUST, BALANCES, ACCOUNTS, CHANGES];
+
+const BLOCK_NUMBER_KEY: &[u8] = b"block_number";
+
+const GROUP_FLAG: u8 = 1;
+const ORGANIZATION_FLAG: u8 = 2;
+const STOPPED_FLAG: u8 = 4;
+
+/// The size of the LRU cache of blocks read from the database, in bytes.
+const BLOCK_CACHE_SIZE: usize = 256 << 20;
+
+/// Writes the edges to a RocksDB database at `path`, replacing the edges in it.
+pub fn write_edges_rocksdb(edges: &EdgeDB, path: &str) -> Result<(), io::Error> {
+    RocksDbEdgeStore::open(path)?.replace(edges)
+}
+
+/// Reads all edges of a RocksDB database, with the changes logged since they were written.
+pub fn read_edges_rocksdb(path: &str) -> Result<EdgeDB, io::Error> {
+    let store = RocksDbEdgeStore::open(path)?;
+    let mut edges = store.edges()?;
+    for change in store.changes()? {
+        change.apply(&mut edges);
+    }
+    Ok(edges)
+}
+
+/// Keys and values of a column family.
+type Entries = Vec<(Box<[u8]>, Box<[u8]>)>;
+
+pub struct RocksDbEdgeStore {
+    db: DB,
+    /// The number of logged changes.
+    change_count: usize,
+}
+
+/// The contents of the column families for some accounts, from which an `EdgeDB`
+/// is built.
+#[derive(Default)]
+struct Contents {
+    edges: Vec<(Edge, Option<u64>)>,
+    limit_percentages: Vec<(Edge, u8)>,
+    balances: Vec<(Address, Address, U256, Option<u64>)>,
+    account_flags: Vec<(Address, u8)>,
+}
+
+impl RocksDbEdgeStore {
+    /// Opens the database at `path`, or creates an empty one.
+    pub fn open(path: &str) -> Result<RocksDbEdgeStore, io::Error> {
+        let cache = Cache::new_lru_cache(BLOCK_CACHE_SIZE);
+        let mut table_options = BlockBasedOptions::default();
+        table_options.set_block_cache(&cache);
+        let mut options = Options::default();
+        options.create_if_missing(true);
+        options.create_missing_column_families(true);
+        options.set_block_based_table_factory(&table_options);
+        let column_families = COLUMN_FAMILIES.map(|name| {
+            let mut cf_options = Options::default();
+            cf_options.set_block_based_table_factory(&table_options);
+            ColumnFamilyDescriptor::new(name, cf_options)
+        });
+        let db = DB::open_cf_descriptors(&options, path, column_families).map_err(db_error)?;
+        let mut store = RocksDbEdgeStore {
+            db,
+            change_count: 0,
+        };
+        store.change_count = store.scan(CHANGES, &[])?.len();
+        Ok(store)
+    }
+
+    /// Replaces the edges and empties the log, in one write. Wrappers are not part of
+    /// the edges, so they are logged again.
+    pub fn replace(&mut self, edges: &EdgeDB) -> Result<(), io::Error> {
+        let wrappers = wrapper_changes(edges);
+        let mut batch = WriteBatch::default();
+        for name in COLUMN_FAMILIES {
+            // All keys are shorter than 61 bytes.
+            batch.delete_range_cf(self.cf(name)?, vec![], vec![0xff; 61]);
+        }
+        for edge in edges.edges() {
+            let mut value = edge.capacity.to_be_bytes().to_vec();
+            if let Some(block_number) = edges.updated_at(edge) {
+                value.extend(block_number.to_be_bytes());
+            }
+            batch.put_cf(self.cf(EDGES)?, edge_key(edge), value);
+        }
+        for (edge, percentage) in edges.limit_percentages() {
+            batch.put_cf(self.cf(TRUST)?, edge_key(edge), [percentage]);
+        }
+        for (holder, token, amount, day) in edges.balances().stored() {
+            let mut value = amount.to_be_bytes().to_vec();
+            if let Some(day) = day {
+                value.extend(day.to_be_bytes());
+            }
+            batch.put_cf(self.cf(BALANCES)?, pair_key(&holder, &token), value);
+        }
+        let mut flags = HashMap::<Address, u8>::new();
+        for (token, token_type) in edges.token_types() {
+            if *token_type == TokenType::Group {
+                *flags.entry(*token).or_default() |= GROUP_FLAG;
+            }
+        }
+        for address in edges.balances().organizations() {
+            *flags.entry(*address).or_default() |= ORGANIZATION_FLAG;
+        }
+        for address in edges.balances().stopped() {
+            *flags.entry(*address).or_default() |= STOPPED_FLAG;
+        }
+        for (address, flags) in flags {
+            batch.put_cf(self.cf(ACCOUNTS)?, address.to_bytes(), [flags]);
+        }
+        match edges.block_number() {
+            Some(block_number) => batch.put(BLOCK_NUMBER_KEY, block_number.to_be_bytes()),
+            None => batch.delete(BLOCK_NUMBER_KEY),
+        }
+        self.put_changes(&mut batch, 0, &wrappers)?;
+        self.db.write(batch).map_err(db_error)?;
+        self.change_count = wrappers.len();
+        Ok(())
+    }
+
+    /// Logs the changes in one write, so that either all or none of them are kept.
+    pub fn append(&mut self, changes: &[Change]) -> Result<(), io::Error> {
+        let mut batch = WriteBatch::default();
+        self.put_changes(&mut batch, self.change_count, changes)?;
+        self.db.write(batch).map_err(db_error)?;
+        self.change_count += changes.len();
+        Ok(())
+    }
+
+    /// The number of logged changes.
+    pub fn len(&self) -> usize {
+        self.change_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.change_count == 0
+    }
+
+    /// All edges as written by the last `replace`, without the logged changes.
+    pub fn edges(&self) -> Result<EdgeDB, io::Error> {
+        let contents = Contents {
+            edges: self
+                .scan(EDGES, &[])?
+                .iter()
+                .map(|(key, value)| read_edge(key, value))
+                .collect(),
+            limit_percentages: self
+                .scan(TRUST, &[])?
+                .iter()
+                .map(|(key, value)| (read_edge(key, &[0; 32]).0, value[0]))
+                .collect(),
+            balances: self
+                .scan(BALANCES, &[])?
+                .iter()
+                .map(|(key, value)| read_balance(key, value))
+                .collect(),
+            account_flags: self
+                .scan(ACCOUNTS, &[])?
+                .iter()
+                .map(|(key, value)| (read_address(key), value[0]))
+                .collect(),
+        };
+        self.edge_db(contents)
+    }
+
+    /// The logged changes, in the order they were logged.
+    pub fn changes(&self) -> Result<Vec<Change>, io::Error> {
+        self.scan(CHANGES, &[])?
+            .iter()
+            .map(|(_, record)| Change::read(&mut &record[..]))
+            .collect()
+    }
+
+    /// The edges that are at most `max_hops` hops away from `source`, which are all
+    /// edges a flow search from `source` with that hop limit can use, including the
+    /// edges back to the token owners implied by the balances. Only the edges, the
+    /// balances and the flags of the accounts reached and of their neighbors are read.
+    /// Changes logged since the last `replace` are not included.
+    pub fn neighborhood(&self, source: &Address, max_hops: u64) -> Result<EdgeDB, io::Error> {
+        let mut contents = Contents::default();
+        let mut reached = HashSet::from([*source]);
+        // The accounts whose balances and flags are needed for the capacities.
+        let mut involved = HashSet::from([*source]);
+        let mut frontier = vec![*source];
+        for _ in 0..max_hops {
+            let mut next = vec![];
+            for account in &frontier {
+                let prefix = account.to_bytes();
+                let mut receivers = vec![];
+                for (key, value) in self.scan(EDGES, &prefix)? {
+                    let (edge, updated_at) = read_edge(&key, &value);
+                    receivers.push(edge.to);
+                    involved.extend([edge.to, edge.token]);
+                    contents.edges.push((edge, updated_at));
+                }
+                for (key, value) in self.scan(TRUST, &prefix)? {
+                    contents
+                        .limit_percentages
+                        .push((read_edge(&key, &[0; 32]).0, value[0]));
+                }
+                for (key, value) in self.scan(BALANCES, &prefix)? {
+                    let (holder, token, amount, _) = read_balance(&key, &value);
+                    if holder != token && !amount.is_zero() {
+                        receivers.push(token);
+                        involved.insert(token);
+                    }
+                }
+                for receiver in receivers {
+                    if reached.insert(receiver) {
+                        next.push(receiver);
+                    }
+                }
+            }
+            frontier = next;
+        }
+        for account in &involved {
+            for (key, value) in self.scan(BALANCES, &account.to_bytes())? {
+                contents.balances.push(read_balance(&key, &value));
+            }
+            if let Some(value) = self
+                .db
+                .get_cf(self.cf(ACCOUNTS)?, account.to_bytes())
+                .map_err(db_error)?
+            {
+                contents.account_flags.push((*account, value[0]));
+            }
+        }
+        self.edge_db(contents)
+    }
+
+    /// The edges of the contents, with the block number of the database.
+    fn edge_db(&self, contents: Contents) -> Result<EdgeDB, io::Error> {
+        let mut edges = EdgeDB::new(contents.edges.iter().map(|(edge, _)| *edge).collect());
+        for (edge, updated_at) in &contents.edges {
+            if let Some(block_number) = updated_at {
+                edges.set_updated_at(edge, *block_number);
+            }
+        }
+        for (edge, percentage) in &contents.limit_percentages {
+            edges.set_limit_percentage(edge, *percentage);
+        }
+        for (holder, token, amount, day) in contents.balances {
+            match day {
+                Some(day) => edges
+                    .balances_mut()
+                    .set_balance_at_day(holder, token, amount, day),
+                None => edges.balances_mut().set_balance(holder, token, amount),
+            }
+        }
+        for (address, flags) in contents.account_flags {
+            if flags & GROUP_FLAG != 0 {
+                edges.set_token_type(address, TokenType::Group);
+            }
+            edges
+                .balances_mut()
+                .set_organization(address, flags & ORGANIZATION_FLAG != 0);
+            edges
+                .balances_mut()
+                .set_stopped(address, flags & STOPPED_FLAG != 0);
+        }
+        if let Some(value) = self.db.get(BLOCK_NUMBER_KEY).map_err(db_error)? {
+            edges.set_block_number(read_u64(&value));
+        }
+        Ok(edges)
+    }
+
+    fn put_changes(
+        &self,
+        batch: &mut WriteBatch,
+        first: usize,
+        changes: &[Change],
+    ) -> Result<(), io::Error> {
+        for (i, change) in changes.iter().enumerate() {
+            let mut record = Vec::new();
+            change.write(&mut record)?;
+            batch.put_cf(
+                self.cf(CHANGES)?,
+                ((first + i) as u64).to_be_bytes(),
+                record,
+            );
+        }
+        Ok(())
+    }
+
+    /// The keys and values of a column family that start with `prefix`, in key order.
+    fn scan(&self, name: &str, prefix: &[u8]) -> Result<Entries, io::Error> {
+        let mut entries = vec![];
+        for entry in self.db.iterator_cf(
+            self.cf(name)?,
+            IteratorMode::From(prefix, Direction::Forward),
+        ) {
+            let (key, value) = entry.map_err(db_error)?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            entries.push((key, value));
+        }
+        Ok(entries)
+    }
+
+    fn cf(&self, name: &str) -> Result<&ColumnFamily, io::Error> {
+        self.db
+            .cf_handle(name)
+            .ok_or_else(|| io::Error::other(format!("Missing column family {name}")))
+    }
+}
+
+fn edge_key(edge: &Edge) -> Vec<u8> {
+    [
+        edge.from.to_bytes(),
+        edge.to.to_bytes(),
+        edge.token.to_bytes(),
+    ]
+    .concat()
+}
+
+fn pair_key(first: &Address, second: &Address) -> Vec<u8> {
+    [first.to_bytes(), second.to_bytes()].concat()
+}
+
+/// Reads an entry of `edges`: the edge and the block number of its last update.
+fn read_edge(key: &[u8], value: &[u8]) -> (Edge, Option<u64>) {
+    let edge = Edge {
+        from: read_address(&key[0..20]),
+        to: read_address(&key[20..40]),
+        token: read_address(&key[40..60]),
+        capacity: U256::from_be_bytes(value[..32].try_into().unwrap()),
+    };
+    (edge, (value.len() > 32).then(|| read_u64(&value[32..])))
+}
+
+/// Reads an entry of `balances`: holder, token, amount and the day it was set at.
+fn read_balance(key: &[u8], value: &[u8]) -> (Address, Address, U256, Option<u64>) {
+    (
+        read_address(&key[0..20]),
+        read_address(&key[20..40]),
+        U256::from_be_bytes(value[..32].try_into().unwrap()),
+        (value.len() > 32).then(|| read_u64(&value[32..])),
+    )
+}
+
+fn read_address(bytes: &[u8]) -> Address {
+    Address::from(<[u8; 20]>::try_from(&bytes[..20]).unwrap())
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_be_bytes(bytes[..8].try_into().unwrap())
+}
+
+fn db_error(e: rocksdb::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn store() {
+        let path = env::temp_dir()
+            .join(format!("pathfinder_{}_edges.rocksdb", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let address = |i: u8| Address::from([i; 20]);
+        let edge = |from: u8, to: u8| Edge {
+            from: address(from),
+            to: address(to),
+            token: address(from),
+            capacity: U256::from(from as u128),
+        };
+        // A chain 1 -> 2 -> 3 -> 4 and an edge 5 -> 1. 3 trusts 2 by 50% and 2 holds
+        // tokens of the group 6, which it can send back to 6.
+        let mut edges = EdgeDB::new(vec![edge(1, 2), edge(3, 4), edge(5, 1)]);
+        edges.update_at_block(edge(1, 2), 7);
+        edges.set_limit_percentage(&edge(2, 3), 50);
+        for account in 1..=6 {
+            edges.balances_mut().set_balance(
+                address(account),
+                address(account),
+                U256::from(100u128),
+            );
+        }
+        edges
+            .balances_mut()
+            .set_balance_at_day(address(2), address(6), U256::from(30u128), 12);
+        edges.set_token_type(address(6), TokenType::Group);
+        edges.balances_mut().set_organization(address(4), true);
+        edges.balances_mut().set_stopped(address(5), true);
+        write_edges_rocksdb(&edges, &path).unwrap();
+
+        let mut store = RocksDbEdgeStore::open(&path).unwrap();
+        store
+            .append(&[
+                Change::Update {
+                    edge: edge(4, 5),
+                    block_number: Some(9),
+                    token_type: None,
+                },
+                Change::Remove { edge: edge(5, 1) },
+            ])
+            .unwrap();
+        assert_eq!(store.len(), 2);
+
+        // The neighborhood has the edges reachable in two hops, including the edge back
+        // to 6, with the capacities computed from the balances, but not the changes.
+        let neighborhood = store.neighborhood(&address(1), 2).unwrap();
+        let mut reached = neighborhood.effective_edges();
+        reached.sort();
+        let mut expected = vec![
+            edge(1, 2),
+            Edge {
+                capacity: U256::from(50u128),
+                ..edge(2, 3)
+            },
+            Edge {
+                token: address(6),
+                capacity: U256::from(30u128),
+                ..edge(2, 6)
+            },
+        ];
+        expected.sort();
+        assert_eq!(reached, expected);
+        assert_eq!(neighborhood.updated_at(&edge(1, 2)), Some(7));
+        assert_eq!(neighborhood.token_type(&address(6)), TokenType::Group);
+        drop(store);
+
+        let restored = read_edges_rocksdb(&path).unwrap();
+        let mut restored_edges = restored.edges().to_vec();
+        restored_edges.sort();
+        assert_eq!(
+            restored_edges,
+            vec![edge(1, 2), edge(2, 3), edge(3, 4), edge(4, 5)]
+        );
+        assert_eq!(restored.limit_percentage(&edge(2, 3)), Some(50));
+        assert_eq!(restored.block_number(), Some(9));
+        assert_eq!(restored.token_type(&address(6)), TokenType::Group);
+        assert!(restored.balances().is_organization(&address(4)));
+        assert!(restored.balances().is_stopped(&address(5)));
+        let mut balances = restored.balances().stored().collect::<Vec<_>>();
+        balances.sort();
+        assert_eq!(balances.len(), 7);
+        assert!(balances.contains(&(address(2), address(6), U256::from(30u128), Some(12))));
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
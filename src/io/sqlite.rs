@@ -0,0 +1,380 @@
+//! Edges in a SQLite database, for deployments that trade latency for memory: the
+//! edges stay on disk and the outgoing edges of an account are only read when a
+//! search reaches it, see `SqliteEdgeStore::neighborhood`.
+//!
+//! The table `edges` holds the edges with their effective capacities, like a binary
+//! edge file: the addresses as 20-byte blobs, the capacity as a 32-byte big-endian
+//! blob and the block number of the last update (null if unknown). It is indexed by
+//! sender, receiver and token owner, so that the file can also be queried directly.
+//! `groups` holds the owners of group tokens, `metadata` the block number of the edges
+//! and `changes` the changes since the edges were written, encoded like the records of
+//! the write-ahead log.
+
+use std::collections::{BTreeSet, HashSet};
+use std::io;
+use std::sync::Arc;
+
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use super::wal::{wrapper_changes, Change};
+use crate::cache::LruCache;
+use crate::types::edge::EdgeDB;
+use crate::types::{Address, Edge, TokenType, U256};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS edges (
+        from_address BLOB NOT NULL,
+        to_address BLOB NOT NULL,
+        token BLOB NOT NULL,
+        capacity BLOB NOT NULL,
+        updated_at INTEGER,
+        PRIMARY KEY (from_address, to_address, token)
+    ) WITHOUT ROWID;
+    CREATE INDEX IF NOT EXISTS edges_to ON edges (to_address);
+    CREATE INDEX IF NOT EXISTS edges_token ON edges (token);
+    CREATE TABLE IF NOT EXISTS groups (token BLOB PRIMARY KEY) WITHOUT ROWID;
+    CREATE TABLE IF NOT EXISTS metadata (key TEXT PRIMARY KEY, value INTEGER NOT NULL);
+    CREATE TABLE IF NOT EXISTS changes (id INTEGER PRIMARY KEY, change BLOB NOT NULL);
+";
+
+/// Edges with the block numbers of their last updates, if known.
+type Rows = Vec<(Edge, Option<u64>)>;
+
+/// The number of accounts whose outgoing edges are kept in memory.
+const ADJACENCY_CACHE_SIZE: usize = 10000;
+
+/// Writes the edges to a SQLite database at `path`, replacing the edges in it.
+pub fn write_edges_sqlite(edges: &EdgeDB, path: &str) -> Result<(), io::Error> {
+    SqliteEdgeStore::open(path)?.replace(edges)
+}
+
+/// Reads all edges of a SQLite database, with the changes logged since they were written.
+pub fn read_edges_sqlite(path: &str) -> Result<EdgeDB, io::Error> {
+    let store = SqliteEdgeStore::open(path)?;
+    let mut edges = store.edges()?;
+    for change in store.changes()? {
+        change.apply(&mut edges);
+    }
+    Ok(edges)
+}
+
+pub struct SqliteEdgeStore {
+    connection: Connection,
+    /// The number of logged changes.
+    change_count: usize,
+    /// The outgoing edges of the accounts used last, with the block numbers of their
+    /// last updates.
+    adjacency: LruCache<Address, Arc<Rows>>,
+}
+
+impl SqliteEdgeStore {
+    /// Opens the database at `path`, or creates an empty one.
+    pub fn open(path: &str) -> Result<SqliteEdgeStore, io::Error> {
+        let connection = Connection::open(path).map_err(sql_error)?;
+        connection.execute_batch(SCHEMA).map_err(sql_error)?;
+        let change_count = connection
+            .query_row("SELECT count(*) FROM changes", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .map_err(sql_error)? as usize;
+        Ok(SqliteEdgeStore {
+            connection,
+            change_count,
+            adjacency: LruCache::new(ADJACENCY_CACHE_SIZE),
+        })
+    }
+
+    /// Replaces the edges and empties the log, in one transaction. Wrappers are not
+    /// part of the edges, so they are logged again.
+    pub fn replace(&mut self, edges: &EdgeDB) -> Result<(), io::Error> {
+        let mut sorted_edges = edges.effective_edges();
+        sorted_edges.sort();
+        let groups = edges
+            .token_types()
+            .iter()
+            .filter(|(_, token_type)| **token_type == TokenType::Group)
+            .map(|(token, _)| *token)
+            .collect::<BTreeSet<_>>();
+        let wrappers = wrapper_changes(edges);
+        let transaction = self.connection.transaction().map_err(sql_error)?;
+        transaction
+            .execute_batch(
+                "DELETE FROM edges; DELETE FROM groups; DELETE FROM metadata; DELETE FROM changes;",
+            )
+            .map_err(sql_error)?;
+        {
+            let mut insert = transaction
+                .prepare("INSERT INTO edges VALUES (?1, ?2, ?3, ?4, ?5)")
+                .map_err(sql_error)?;
+            for edge in &sorted_edges {
+                insert
+                    .execute(params![
+                        edge.from.to_bytes(),
+                        edge.to.to_bytes(),
+                        edge.token.to_bytes(),
+                        edge.capacity.to_be_bytes(),
+                        edges.updated_at(edge).map(|block| block as i64),
+                    ])
+                    .map_err(sql_error)?;
+            }
+            let mut insert = transaction
+                .prepare("INSERT INTO groups VALUES (?1)")
+                .map_err(sql_error)?;
+            for token in &groups {
+                insert.execute([token.to_bytes()]).map_err(sql_error)?;
+            }
+        }
+        if let Some(block_number) = edges.block_number() {
+            transaction
+                .execute(
+                    "INSERT INTO metadata VALUES ('block_number', ?1)",
+                    [block_number as i64],
+                )
+                .map_err(sql_error)?;
+        }
+        insert_changes(&transaction, &wrappers)?;
+        transaction.commit().map_err(sql_error)?;
+        self.change_count = wrappers.len();
+        self.adjacency.clear();
+        Ok(())
+    }
+
+    /// Logs the changes in one transaction, so that either all or none of them are kept.
+    pub fn append(&mut self, changes: &[Change]) -> Result<(), io::Error> {
+        let transaction = self.connection.transaction().map_err(sql_error)?;
+        insert_changes(&transaction, changes)?;
+        transaction.commit().map_err(sql_error)?;
+        self.change_count += changes.len();
+        Ok(())
+    }
+
+    /// The number of logged changes.
+    pub fn len(&self) -> usize {
+        self.change_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.change_count == 0
+    }
+
+    /// All edges as written by the last `replace`, without the logged changes.
+    pub fn edges(&self) -> Result<EdgeDB, io::Error> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT * FROM edges ORDER BY from_address, to_address, token")
+            .map_err(sql_error)?;
+        let rows = statement
+            .query_map([], read_edge)
+            .map_err(sql_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(sql_error)?;
+        self.edge_db(rows)
+    }
+
+    /// The logged changes, in the order they were logged.
+    pub fn changes(&self) -> Result<Vec<Change>, io::Error> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT change FROM changes ORDER BY id")
+            .map_err(sql_error)?;
+        let records = statement
+            .query_map([], |row| row.get::<_, Vec<u8>>(0))
+            .map_err(sql_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(sql_error)?;
+        records
+            .iter()
+            .map(|record| Change::read(&mut record.as_slice()))
+            .collect()
+    }
+
+    /// The outgoing edges of `source` as written by the last `replace`, with the block
+    /// numbers of their last updates, read from the database unless they were used recently.
+    pub fn outgoing(&mut self, source: &Address) -> Result<Arc<Rows>, io::Error> {
+        if let Some(edges) = self.adjacency.get(source) {
+            return Ok(edges.clone());
+        }
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT * FROM edges WHERE from_address = ?1")
+            .map_err(sql_error)?;
+        let edges = statement
+            .query_map([source.to_bytes()], read_edge)
+            .map_err(sql_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(sql_error)?;
+        let edges = Arc::new(edges);
+        self.adjacency.insert(*source, edges.clone());
+        Ok(edges)
+    }
+
+    /// The edges that are at most `max_hops` hops away from `source`, which are all
+    /// edges a flow search from `source` with that hop limit can use. Only the outgoing
+    /// edges of the accounts reached are read. Changes logged since the last `replace`
+    /// are not included.
+    pub fn neighborhood(&mut self, source: &Address, max_hops: u64) -> Result<EdgeDB, io::Error> {
+        let mut reached = HashSet::from([*source]);
+        let mut frontier = vec![*source];
+        let mut rows = vec![];
+        for _ in 0..max_hops {
+            let mut next = vec![];
+            for account in &frontier {
+                for (edge, updated_at) in self.outgoing(account)?.iter() {
+                    rows.push((*edge, *updated_at));
+                    if reached.insert(edge.to) {
+                        next.push(edge.to);
+                    }
+                }
+            }
+            frontier = next;
+        }
+        self.edge_db(rows)
+    }
+
+    /// The edges of the rows, with the groups and the block number of the database.
+    fn edge_db(&self, rows: Rows) -> Result<EdgeDB, io::Error> {
+        let mut edges = EdgeDB::new(rows.iter().map(|(edge, _)| *edge).collect());
+        for (edge, updated_at) in &rows {
+            if let Some(block_number) = updated_at {
+                edges.set_updated_at(edge, *block_number);
+            }
+        }
+        let mut statement = self
+            .connection
+            .prepare("SELECT token FROM groups")
+            .map_err(sql_error)?;
+        let groups = statement
+            .query_map([], |row| read_address(row, 0))
+            .map_err(sql_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(sql_error)?;
+        for token in groups {
+            edges.set_token_type(token, TokenType::Group);
+        }
+        let block_number = self
+            .connection
+            .query_row(
+                "SELECT value FROM metadata WHERE key = 'block_number'",
+                [],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()
+            .map_err(sql_error)?;
+        if let Some(block_number) = block_number {
+            edges.set_block_number(block_number as u64);
+        }
+        Ok(edges)
+    }
+}
+
+fn insert_changes(connection: &Connection, changes: &[Change]) -> Result<(), io::Error> {
+    let mut insert = connection
+        .prepare_cached("INSERT INTO changes (change) VALUES (?1)")
+        .map_err(sql_error)?;
+    for change in changes {
+        let mut record = Vec::new();
+        change.write(&mut record)?;
+        insert.execute([record]).map_err(sql_error)?;
+    }
+    Ok(())
+}
+
+/// Reads a row of `edges`: the edge and the block number of its last update.
+fn read_edge(row: &Row) -> Result<(Edge, Option<u64>), rusqlite::Error> {
+    let capacity: [u8; 32] = row.get(3)?;
+    let updated_at: Option<i64> = row.get(4)?;
+    let edge = Edge {
+        from: read_address(row, 0)?,
+        to: read_address(row, 1)?,
+        token: read_address(row, 2)?,
+        capacity: U256::from_be_bytes(capacity),
+    };
+    Ok((edge, updated_at.map(|block| block as u64)))
+}
+
+fn read_address(row: &Row, column: usize) -> Result<Address, rusqlite::Error> {
+    Ok(Address::from(row.get::<_, [u8; 20]>(column)?))
+}
+
+fn sql_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::edge::edge;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn store() {
+        let path = env::temp_dir()
+            .join(format!("pathfinder_{}_edges.sqlite", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        // A chain 1 -> 2 -> 3 -> 4 and an edge 5 -> 1.
+        let mut edges = EdgeDB::new(vec![
+            edge([1; 20], [2; 20], [1; 20], 1),
+            edge([2; 20], [3; 20], [2; 20], 2),
+            edge([3; 20], [4; 20], [3; 20], 3),
+            edge([5; 20], [1; 20], [5; 20], 5),
+        ]);
+        edges.update_at_block(edge([2; 20], [3; 20], [2; 20], 2), 7);
+        edges.set_token_type(Address::from([3; 20]), TokenType::Group);
+        write_edges_sqlite(&edges, &path).unwrap();
+
+        let mut store = SqliteEdgeStore::open(&path).unwrap();
+        store
+            .append(&[
+                Change::Update {
+                    edge: edge([4; 20], [5; 20], [4; 20], 4),
+                    block_number: Some(9),
+                    token_type: None,
+                },
+                Change::Remove {
+                    edge: edge([5; 20], [1; 20], [5; 20], 5),
+                },
+            ])
+            .unwrap();
+        assert_eq!(store.len(), 2);
+
+        // The neighborhood only has the edges reachable in two hops, without changes.
+        let neighborhood = store.neighborhood(&Address::from([1; 20]), 2).unwrap();
+        assert_eq!(
+            neighborhood.edges(),
+            &[
+                edge([1; 20], [2; 20], [1; 20], 1),
+                edge([2; 20], [3; 20], [2; 20], 2)
+            ]
+        );
+        assert_eq!(
+            neighborhood.updated_at(&edge([2; 20], [3; 20], [2; 20], 2)),
+            Some(7)
+        );
+        assert_eq!(
+            neighborhood.token_type(&Address::from([3; 20])),
+            TokenType::Group
+        );
+        drop(store);
+
+        let restored = read_edges_sqlite(&path).unwrap();
+        let mut restored_edges = restored.edges().to_vec();
+        restored_edges.sort();
+        assert_eq!(
+            restored_edges,
+            vec![
+                edge([1; 20], [2; 20], [1; 20], 1),
+                edge([2; 20], [3; 20], [2; 20], 2),
+                edge([3; 20], [4; 20], [3; 20], 3),
+                edge([4; 20], [5; 20], [4; 20], 4)
+            ]
+        );
+        assert_eq!(restored.block_number(), Some(9));
+        assert_eq!(
+            restored.token_type(&Address::from([3; 20])),
+            TokenType::Group
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,586 @@
+//! A write-ahead log of the changes to the edges since the last snapshot, so that
+//! updates are not lost on restart.
+//!
+//! The log starts with the magic bytes `PFEDGWAL` and the version as `u32`, followed by
+//! one record per change: the length of the change and its CRC32 as `u32`, then the change.
+//! The changes of an `append` of more than one change are preceded by a record with their
+//! number. A record that was not written completely, e.g. because the process was killed,
+//! is dropped when the log is opened, together with the rest of its batch.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+
+use super::{
+    invalid_data, read_optional, read_u256, read_u32, read_u64, read_u8, write_edges_binary,
+    write_u256, write_u32, write_u64, write_u8,
+};
+use crate::types::edge::EdgeDB;
+use crate::types::{Address, Edge, TokenType, U256};
+
+const WAL_MAGIC: &[u8; 8] = b"PFEDGWAL";
+const WAL_VERSION: u32 = 1;
+const HEADER_LENGTH: u64 = 12;
+
+/// Records are much smaller, longer ones can only be the result of damage.
+const MAX_RECORD_LENGTH: u32 = 1024;
+
+/// A change to the edges, as made through the JSON-RPC methods or by the indexer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// A new capacity of an edge, optionally read at a block, and the type of its token.
+    Update {
+        edge: Edge,
+        block_number: Option<u64>,
+        token_type: Option<TokenType>,
+    },
+    Wrapper {
+        wrapper: Address,
+        token: Address,
+    },
+    /// A new trust limit percentage of an edge, optionally set at a block.
+    LimitPercentage {
+        edge: Edge,
+        percentage: u8,
+        block_number: Option<u64>,
+    },
+    /// A new balance of `holder` in the tokens of `token`, the token owner.
+    Balance {
+        holder: Address,
+        token: Address,
+        amount: U256,
+    },
+    Organization {
+        address: Address,
+        organization: bool,
+    },
+    /// The block the edges are at, also if it is before the current one after a reorg.
+    BlockNumber {
+        block_number: u64,
+    },
+    /// The removal of a stored edge, see `EdgeDB::remove`.
+    Remove {
+        edge: Edge,
+    },
+}
+
+/// The kind of the record that starts a batch of changes, followed by their number.
+const BATCH_KIND: u8 = 7;
+
+/// A record of the log.
+enum Record {
+    Change(Change),
+    /// The start of a batch of this many changes.
+    Batch(u32),
+}
+
+impl Change {
+    pub fn apply(&self, edges: &mut EdgeDB) {
+        match self {
+            Change::Update {
+                edge,
+                block_number,
+                token_type,
+            } => {
+                match block_number {
+                    Some(block_number) => edges.update_at_block(*edge, *block_number),
+                    None => edges.update(*edge),
+                }
+                if let Some(token_type) = token_type {
+                    edges.set_token_type(edge.token, *token_type);
+                }
+            }
+            Change::Wrapper { wrapper, token } => edges.add_wrapper(*wrapper, *token),
+            Change::LimitPercentage {
+                edge,
+                percentage,
+                block_number,
+            } => {
+                edges.set_limit_percentage(edge, *percentage);
+                if let Some(block_number) = block_number {
+                    edges.set_updated_at(edge, *block_number);
+                }
+            }
+            Change::Balance {
+                holder,
+                token,
+                amount,
+            } => edges.balances_mut().set_balance(*holder, *token, *amount),
+            Change::Organization {
+                address,
+                organization,
+            } => edges
+                .balances_mut()
+                .set_organization(*address, *organization),
+            Change::BlockNumber { block_number } => edges.set_block_number(*block_number),
+            Change::Remove { edge } => {
+                edges.remove(edge);
+            }
+        }
+    }
+
+    pub(super) fn write(&self, out: &mut impl Write) -> Result<(), io::Error> {
+        match self {
+            Change::Update {
+                edge,
+                block_number,
+                token_type,
+            } => {
+                write_u8(out, 0)?;
+                write_edge(out, edge)?;
+                match block_number {
+                    Some(block_number) => {
+                        write_u8(out, 1)?;
+                        write_u64(out, *block_number)
+                    }
+                    None => write_u8(out, 0),
+                }?;
+                write_u8(
+                    out,
+                    match token_type {
+                        None => 0,
+                        Some(TokenType::Personal) => 1,
+                        Some(TokenType::Group) => 2,
+                    },
+                )
+            }
+            Change::Wrapper { wrapper, token } => {
+                write_u8(out, 1)?;
+                out.write_all(&wrapper.to_bytes())?;
+                out.write_all(&token.to_bytes())
+            }
+            Change::LimitPercentage {
+                edge,
+                percentage,
+                block_number,
+            } => {
+                write_u8(out, 2)?;
+                write_edge(out, edge)?;
+                write_u8(out, *percentage)?;
+                // Optional at the end, so that records written without it can be read.
+                match block_number {
+                    Some(block_number) => write_u64(out, *block_number),
+                    None => Ok(()),
+                }
+            }
+            Change::Balance {
+                holder,
+                token,
+                amount,
+            } => {
+                write_u8(out, 3)?;
+                out.write_all(&holder.to_bytes())?;
+                out.write_all(&token.to_bytes())?;
+                write_u256(out, amount)
+            }
+            Change::Organization {
+                address,
+                organization,
+            } => {
+                write_u8(out, 4)?;
+                out.write_all(&address.to_bytes())?;
+                write_u8(out, *organization as u8)
+            }
+            Change::BlockNumber { block_number } => {
+                write_u8(out, 5)?;
+                write_u64(out, *block_number)
+            }
+            Change::Remove { edge } => {
+                write_u8(out, 6)?;
+                write_edge(out, edge)
+            }
+        }
+    }
+
+    pub(super) fn read(input: &mut impl Read) -> Result<Change, io::Error> {
+        match read_u8(input)? {
+            0 => {
+                let edge = read_edge(input)?;
+                let block_number = match read_u8(input)? {
+                    0 => None,
+                    _ => Some(read_u64(input)?),
+                };
+                let token_type = match read_u8(input)? {
+                    0 => None,
+                    1 => Some(TokenType::Personal),
+                    2 => Some(TokenType::Group),
+                    t => return Err(invalid_data(format!("Invalid token type {t}"))),
+                };
+                Ok(Change::Update {
+                    edge,
+                    block_number,
+                    token_type,
+                })
+            }
+            1 => Ok(Change::Wrapper {
+                wrapper: read_raw_address(input)?,
+                token: read_raw_address(input)?,
+            }),
+            2 => Ok(Change::LimitPercentage {
+                edge: read_edge(input)?,
+                percentage: read_u8(input)?,
+                block_number: read_optional(input, read_u64)?,
+            }),
+            3 => Ok(Change::Balance {
+                holder: read_raw_address(input)?,
+                token: read_raw_address(input)?,
+                amount: read_u256(input)?,
+            }),
+            4 => Ok(Change::Organization {
+                address: read_raw_address(input)?,
+                organization: read_u8(input)? != 0,
+            }),
+            5 => Ok(Change::BlockNumber {
+                block_number: read_u64(input)?,
+            }),
+            6 => Ok(Change::Remove {
+                edge: read_edge(input)?,
+            }),
+            kind => Err(invalid_data(format!("Invalid change kind {kind}"))),
+        }
+    }
+}
+
+pub struct WriteAheadLog {
+    file: File,
+    /// The length of the complete records.
+    length: u64,
+    records: usize,
+}
+
+impl WriteAheadLog {
+    /// Opens the log at `path`, or creates an empty one, and returns it with the changes in it.
+    pub fn open(path: &str) -> Result<(WriteAheadLog, Vec<Change>), io::Error> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let file_length = file.metadata()?.len();
+        if file_length == 0 {
+            file.write_all(WAL_MAGIC)?;
+            write_u32(&mut file, WAL_VERSION)?;
+            file.sync_all()?;
+            let log = WriteAheadLog {
+                file,
+                length: HEADER_LENGTH,
+                records: 0,
+            };
+            return Ok((log, vec![]));
+        }
+
+        let mut reader = BufReader::new(&file);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != WAL_MAGIC {
+            return Err(invalid_data(format!("{path} is not a write-ahead log")));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != WAL_VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported write-ahead log version {version}"
+            )));
+        }
+        let mut length = HEADER_LENGTH;
+        let mut changes = vec![];
+        // The length and number of changes up to the last complete batch.
+        let mut complete = (length, 0);
+        let mut batch_remaining = 0;
+        while let Some((record, record_length)) = read_record(&mut reader, file_length - length)? {
+            length += record_length;
+            match record {
+                Record::Batch(count) => batch_remaining = count,
+                Record::Change(change) => {
+                    changes.push(change);
+                    batch_remaining = batch_remaining.saturating_sub(1);
+                }
+            }
+            if batch_remaining == 0 {
+                complete = (length, changes.len());
+            }
+        }
+        drop(reader);
+        // Drops an incomplete last record or batch.
+        let (length, count) = complete;
+        changes.truncate(count);
+        file.set_len(length)?;
+        file.seek(SeekFrom::Start(length))?;
+        let log = WriteAheadLog {
+            file,
+            length,
+            records: changes.len(),
+        };
+        Ok((log, changes))
+    }
+
+    /// Appends the changes and waits until they are on disk. If writing fails, or the
+    /// process stops before the last change is written, none of the changes are kept.
+    pub fn append(&mut self, changes: &[Change]) -> Result<(), io::Error> {
+        let mut records = Vec::new();
+        let mut write_record = |payload: Vec<u8>| -> Result<(), io::Error> {
+            write_u32(&mut records, payload.len() as u32)?;
+            write_u32(&mut records, crc32fast::hash(&payload))?;
+            records.extend(payload);
+            Ok(())
+        };
+        if changes.len() > 1 {
+            let mut payload = vec![BATCH_KIND];
+            write_u32(&mut payload, changes.len() as u32)?;
+            write_record(payload)?;
+        }
+        for change in changes {
+            let mut payload = Vec::new();
+            change.write(&mut payload)?;
+            write_record(payload)?;
+        }
+        let result = self
+            .file
+            .write_all(&records)
+            .and_then(|_| self.file.sync_data());
+        if let Err(e) = result {
+            let _ = self.file.set_len(self.length);
+            let _ = self.file.seek(SeekFrom::Start(self.length));
+            return Err(e);
+        }
+        self.length += records.len() as u64;
+        self.records += changes.len();
+        Ok(())
+    }
+
+    /// The number of changes in the log.
+    pub fn len(&self) -> usize {
+        self.records
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records == 0
+    }
+
+    /// Writes the edges as a new snapshot to `snapshot` and empties the log. Wrappers
+    /// are not part of the snapshot, so they are logged again.
+    /// If the process stops in between, the changes are applied again to the new
+    /// snapshot, which does not change it.
+    pub fn compact(&mut self, edges: &EdgeDB, snapshot: &str) -> Result<(), io::Error> {
+        write_snapshot(edges, snapshot)?;
+        self.file.set_len(HEADER_LENGTH)?;
+        self.file.seek(SeekFrom::Start(HEADER_LENGTH))?;
+        self.length = HEADER_LENGTH;
+        self.records = 0;
+        self.append(&wrapper_changes(edges))
+    }
+}
+
+/// The wrappers of the edges as changes, sorted by wrapper.
+pub(super) fn wrapper_changes(edges: &EdgeDB) -> Vec<Change> {
+    let mut wrappers = edges
+        .wrappers()
+        .iter()
+        .map(|(wrapper, token)| Change::Wrapper {
+            wrapper: *wrapper,
+            token: *token,
+        })
+        .collect::<Vec<_>>();
+    wrappers.sort_by_key(|change| match change {
+        Change::Wrapper { wrapper, .. } => *wrapper,
+        _ => unreachable!(),
+    });
+    wrappers
+}
+
+/// Writes the edges as a binary edge file through a temporary file, so that `path`
+/// always contains either the previous or the new edges.
+pub fn write_snapshot(edges: &EdgeDB, path: &str) -> Result<(), io::Error> {
+    let temporary = format!("{path}.tmp");
+    write_edges_binary(edges, &temporary)?;
+    File::open(&temporary)?.sync_all()?;
+    fs::rename(&temporary, path)
+}
+
+/// Reads the next record, of at most `remaining` bytes. Returns None at the end of
+/// the log or if the rest of the log is an incomplete record.
+fn read_record(input: &mut impl Read, remaining: u64) -> Result<Option<(Record, u64)>, io::Error> {
+    let Some(length) = read_optional(input, read_u32)? else {
+        return Ok(None);
+    };
+    let record_length = 8 + length as u64;
+    if record_length > remaining {
+        return Ok(None);
+    }
+    let checksum = read_u32(input)?;
+    if length > MAX_RECORD_LENGTH {
+        return Err(invalid_data(format!("Invalid record length {length}")));
+    }
+    let mut payload = vec![0u8; length as usize];
+    input.read_exact(&mut payload)?;
+    if crc32fast::hash(&payload) != checksum {
+        return match record_length == remaining {
+            true => Ok(None),
+            false => Err(invalid_data(
+                "Checksum mismatch, the write-ahead log is damaged".to_string(),
+            )),
+        };
+    }
+    let record = match payload.first() {
+        Some(&BATCH_KIND) => Record::Batch(read_u32(&mut &payload[1..])?),
+        _ => Record::Change(Change::read(&mut payload.as_slice())?),
+    };
+    Ok(Some((record, record_length)))
+}
+
+fn write_edge(out: &mut impl Write, edge: &Edge) -> Result<(), io::Error> {
+    out.write_all(&edge.from.to_bytes())?;
+    out.write_all(&edge.to.to_bytes())?;
+    out.write_all(&edge.token.to_bytes())?;
+    write_u256(out, &edge.capacity)
+}
+
+fn read_edge(input: &mut impl Read) -> Result<Edge, io::Error> {
+    Ok(Edge {
+        from: read_raw_address(input)?,
+        to: read_raw_address(input)?,
+        token: read_raw_address(input)?,
+        capacity: read_u256(input)?,
+    })
+}
+
+fn read_raw_address(input: &mut impl Read) -> Result<Address, io::Error> {
+    let mut bytes = [0u8; 20];
+    input.read_exact(&mut bytes)?;
+    Ok(Address::from(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::read_edges_binary;
+    use crate::types::edge::edge;
+    use std::env;
+
+    #[test]
+    fn replay() {
+        let path = |name: &str| {
+            env::temp_dir()
+                .join(format!("pathfinder_{}_{name}", std::process::id()))
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        let (log_file, snapshot) = (path("updates.wal"), path("snapshot.dat"));
+        let changes = vec![
+            Change::Update {
+                edge: edge([1; 20], [2; 20], [1; 20], 10),
+                block_number: Some(7),
+                token_type: Some(TokenType::Group),
+            },
+            Change::Update {
+                edge: edge([2; 20], [3; 20], [2; 20], 0),
+                block_number: None,
+                token_type: None,
+            },
+            Change::Wrapper {
+                wrapper: Address::from([9; 20]),
+                token: Address::from([1; 20]),
+            },
+            Change::LimitPercentage {
+                edge: edge([3; 20], [4; 20], [3; 20], 0),
+                percentage: 50,
+                block_number: Some(5),
+            },
+            Change::Balance {
+                holder: Address::from([3; 20]),
+                token: Address::from([3; 20]),
+                amount: U256::from(100u128),
+            },
+            Change::Organization {
+                address: Address::from([4; 20]),
+                organization: true,
+            },
+        ];
+        let (mut log, replayed) = WriteAheadLog::open(&log_file).unwrap();
+        assert!(replayed.is_empty());
+        log.append(&changes[..2]).unwrap();
+        log.append(&changes[2..]).unwrap();
+        drop(log);
+
+        // A record cut off at the end is dropped.
+        let mut file = OpenOptions::new().append(true).open(&log_file).unwrap();
+        file.write_all(&[0, 0, 0, 40, 1, 2]).unwrap();
+        drop(file);
+        let (mut log, replayed) = WriteAheadLog::open(&log_file).unwrap();
+        assert_eq!(replayed, changes);
+
+        let mut edges = EdgeDB::default();
+        for change in &replayed {
+            change.apply(&mut edges);
+        }
+        assert_eq!(edges.block_number(), Some(7));
+        // Organizations accept all tokens their trusted accounts hold.
+        assert!(edges.balances().is_organization(&Address::from([4; 20])));
+        assert_eq!(
+            edges
+                .edge(&edge([3; 20], [4; 20], [3; 20], 0))
+                .unwrap()
+                .capacity,
+            U256::from(100u128)
+        );
+        assert_eq!(
+            edges.updated_at(&edge([3; 20], [4; 20], [3; 20], 0)),
+            Some(5)
+        );
+        assert_eq!(
+            edges.wrapped_token(&Address::from([9; 20])),
+            Some(edge([1; 20], [2; 20], [1; 20], 0).token)
+        );
+
+        log.compact(&edges, &snapshot).unwrap();
+        assert_eq!(log.len(), 1);
+        drop(log);
+        let (_, replayed) = WriteAheadLog::open(&log_file).unwrap();
+        assert_eq!(replayed, changes[2..3]);
+        assert_eq!(read_edges_binary(&snapshot).unwrap().edge_count(), 3);
+        fs::remove_file(&log_file).unwrap();
+        fs::remove_file(&snapshot).unwrap();
+    }
+
+    #[test]
+    fn incomplete_batch() {
+        let log_file = env::temp_dir()
+            .join(format!("pathfinder_{}_batch.wal", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let edge = Edge {
+            from: Address::from([1; 20]),
+            to: Address::from([2; 20]),
+            token: Address::from([1; 20]),
+            capacity: U256::from(10u128),
+        };
+        let update = Change::Update {
+            edge,
+            block_number: None,
+            token_type: None,
+        };
+        let batch = [update.clone(), Change::Remove { edge }, update.clone()];
+        let (mut log, _) = WriteAheadLog::open(&log_file).unwrap();
+        log.append(&batch).unwrap();
+        let length = log.length;
+        log.append(&batch).unwrap();
+        drop(log);
+
+        // Only the last record of the second batch is cut off, but the whole batch is dropped.
+        let file = OpenOptions::new().write(true).open(&log_file).unwrap();
+        file.set_len(file.metadata().unwrap().len() - 1).unwrap();
+        drop(file);
+        let (log, replayed) = WriteAheadLog::open(&log_file).unwrap();
+        assert_eq!(replayed, batch);
+        assert_eq!(log.len(), 3);
+        assert_eq!(fs::metadata(&log_file).unwrap().len(), length);
+
+        let mut edges = EdgeDB::default();
+        for change in &replayed[..2] {
+            change.apply(&mut edges);
+        }
+        assert_eq!(edges.edge_count(), 0);
+        fs::remove_file(&log_file).unwrap();
+    }
+}
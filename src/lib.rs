@@ -1,5 +1,54 @@
+//! The pathfinder computes transfers of Circles tokens along the trust graph. Use `Graph`
+//! to embed it in-process, or run the JSON-RPC server of `server`.
+//!
+//! Without the default feature `native`, only the flow engine and the graph types are
+//! built, so that the crate compiles to `wasm32-unknown-unknown`, see `wasm`.
+
+#[cfg(feature = "native")]
+mod api;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+#[cfg(feature = "native")]
+mod auth;
+#[cfg(feature = "native")]
+mod cache;
+#[cfg(feature = "native")]
+pub mod config;
+#[cfg(feature = "native")]
+mod cors;
+#[cfg(feature = "native")]
+pub mod diff;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod graph;
+#[cfg(feature = "native")]
+pub mod indexer;
+#[cfg(feature = "native")]
 pub mod io;
+#[cfg(feature = "native")]
+mod metrics;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "native")]
 pub mod safe_db;
+#[cfg(feature = "native")]
 pub mod server;
+#[cfg(feature = "native")]
+mod shutdown;
+#[cfg(feature = "native")]
+pub mod stats;
+#[cfg(feature = "native")]
+pub mod testing;
+#[cfg(feature = "native")]
+pub mod tls;
 pub mod types;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "native")]
+mod websocket;
+#[cfg(feature = "native")]
+mod work_queue;
+
+#[cfg(feature = "native")]
+pub use api::Graph;
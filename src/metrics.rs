@@ -0,0 +1,285 @@
+//! Metrics of the server, exported in the Prometheus text format.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds of the buckets of durations in seconds.
+const DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// Upper bounds of the buckets of flow values in Circles.
+const FLOW_VALUE_BUCKETS: &[f64] = &[0.0, 1.0, 10.0, 100.0, 1000.0, 10000.0, 100000.0, 1000000.0];
+
+/// Upper bounds of the buckets of the number of transfers of a flow.
+const TRANSFER_COUNT_BUCKETS: &[f64] = &[0.0, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0];
+
+#[derive(Debug, Clone)]
+struct Histogram {
+    buckets: &'static [f64],
+    /// The number of observations per bucket (not cumulative).
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Histogram {
+        Histogram {
+            buckets,
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = self.buckets.iter().position(|bound| value <= *bound) {
+            self.counts[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in self.buckets.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels}{}le=\"{bound}\"}} {cumulative}",
+                if labels.is_empty() { "" } else { "," }
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{{labels}{}le=\"+Inf\"}} {}",
+            if labels.is_empty() { "" } else { "," },
+            self.count
+        );
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{labels}}}")
+        };
+        let _ = writeln!(out, "{name}_sum{labels} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{labels} {}", self.count);
+    }
+}
+
+struct State {
+    /// The number of requests by method and whether they succeeded.
+    requests: BTreeMap<(String, bool), u64>,
+    durations: BTreeMap<String, Histogram>,
+    flow_values: Histogram,
+    transfer_counts: Histogram,
+    load_durations: Histogram,
+    edges: usize,
+    nodes: usize,
+}
+
+pub struct Metrics {
+    state: Mutex<State>,
+    /// The number of connections waiting for a worker.
+    queue_depth: AtomicU64,
+    /// The number of connections rejected because the queue was full.
+    rejected: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            state: Mutex::new(State {
+                requests: BTreeMap::new(),
+                durations: BTreeMap::new(),
+                flow_values: Histogram::new(FLOW_VALUE_BUCKETS),
+                transfer_counts: Histogram::new(TRANSFER_COUNT_BUCKETS),
+                load_durations: Histogram::new(DURATION_BUCKETS),
+                edges: 0,
+                nodes: 0,
+            }),
+            queue_depth: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn record_request(&self, method: &str, duration: Duration, success: bool) {
+        let mut state = self.state.lock().unwrap();
+        *state
+            .requests
+            .entry((method.to_string(), success))
+            .or_default() += 1;
+        state
+            .durations
+            .entry(method.to_string())
+            .or_insert_with(|| Histogram::new(DURATION_BUCKETS))
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Records the value (in Circles) and the number of transfers of a computed flow.
+    pub fn record_flow(&self, value: f64, transfer_count: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.flow_values.observe(value);
+        state.transfer_counts.observe(transfer_count as f64);
+    }
+
+    /// Records the duration of a successful load of the edges.
+    pub fn record_load(&self, duration: Duration) {
+        self.state
+            .lock()
+            .unwrap()
+            .load_durations
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Records the size of the graph after a load or update.
+    pub fn record_graph_size(&self, edges: usize, nodes: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.edges = edges;
+        state.nodes = nodes;
+    }
+
+    pub fn enqueued(&self) {
+        self.queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dequeued(&self) {
+        self.queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a lookup in the cache of transfers.
+    pub fn record_cache(&self, hit: bool) {
+        let counter = if hit {
+            &self.cache_hits
+        } else {
+            &self.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut out = String::new();
+        out += "# HELP pathfinder_requests_total JSON-RPC requests by method and outcome.\n";
+        out += "# TYPE pathfinder_requests_total counter\n";
+        for ((method, success), count) in &state.requests {
+            let status = if *success { "ok" } else { "error" };
+            let _ = writeln!(
+                out,
+                "pathfinder_requests_total{{method=\"{method}\",status=\"{status}\"}} {count}"
+            );
+        }
+        out += "# HELP pathfinder_request_duration_seconds Time to handle a JSON-RPC request.\n";
+        out += "# TYPE pathfinder_request_duration_seconds histogram\n";
+        for (method, histogram) in &state.durations {
+            histogram.write(
+                &mut out,
+                "pathfinder_request_duration_seconds",
+                &format!("method=\"{method}\""),
+            );
+        }
+        out += "# HELP pathfinder_flow_value_circles Value of computed flows in Circles.\n";
+        out += "# TYPE pathfinder_flow_value_circles histogram\n";
+        state
+            .flow_values
+            .write(&mut out, "pathfinder_flow_value_circles", "");
+        out += "# HELP pathfinder_flow_transfers Number of transfers of computed flows.\n";
+        out += "# TYPE pathfinder_flow_transfers histogram\n";
+        state
+            .transfer_counts
+            .write(&mut out, "pathfinder_flow_transfers", "");
+        out += "# HELP pathfinder_edge_db_load_duration_seconds Time to load the edges.\n";
+        out += "# TYPE pathfinder_edge_db_load_duration_seconds histogram\n";
+        state
+            .load_durations
+            .write(&mut out, "pathfinder_edge_db_load_duration_seconds", "");
+        out += "# HELP pathfinder_graph_edges Number of edges of the graph.\n";
+        out += "# TYPE pathfinder_graph_edges gauge\n";
+        let _ = writeln!(out, "pathfinder_graph_edges {}", state.edges);
+        out += "# HELP pathfinder_graph_nodes Number of accounts with edges.\n";
+        out += "# TYPE pathfinder_graph_nodes gauge\n";
+        let _ = writeln!(out, "pathfinder_graph_nodes {}", state.nodes);
+        out += "# HELP pathfinder_queue_depth Connections waiting for a worker.\n";
+        out += "# TYPE pathfinder_queue_depth gauge\n";
+        let _ = writeln!(
+            out,
+            "pathfinder_queue_depth {}",
+            self.queue_depth.load(Ordering::Relaxed)
+        );
+        out += "# HELP pathfinder_queue_rejected_total Connections rejected because the queue was full.\n";
+        out += "# TYPE pathfinder_queue_rejected_total counter\n";
+        let _ = writeln!(
+            out,
+            "pathfinder_queue_rejected_total {}",
+            self.rejected.load(Ordering::Relaxed)
+        );
+        out += "# HELP pathfinder_transfer_cache_hits_total Transfers answered from the cache.\n";
+        out += "# TYPE pathfinder_transfer_cache_hits_total counter\n";
+        let _ = writeln!(
+            out,
+            "pathfinder_transfer_cache_hits_total {}",
+            self.cache_hits.load(Ordering::Relaxed)
+        );
+        out += "# HELP pathfinder_transfer_cache_misses_total Transfers not found in the cache.\n";
+        out += "# TYPE pathfinder_transfer_cache_misses_total counter\n";
+        let _ = writeln!(
+            out,
+            "pathfinder_transfer_cache_misses_total {}",
+            self.cache_misses.load(Ordering::Relaxed)
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::default();
+        metrics.record_request("compute_transfer", Duration::from_millis(20), true);
+        metrics.record_request("compute_transfer", Duration::from_secs(100), false);
+        metrics.record_flow(150.0, 3);
+        metrics.record_load(Duration::from_secs(2));
+        metrics.record_graph_size(10, 4);
+        metrics.enqueued();
+        metrics.record_cache(true);
+        metrics.record_cache(false);
+        metrics.record_cache(false);
+        let out = metrics.render();
+        for line in [
+            "pathfinder_requests_total{method=\"compute_transfer\",status=\"error\"} 1",
+            "pathfinder_requests_total{method=\"compute_transfer\",status=\"ok\"} 1",
+            "pathfinder_request_duration_seconds_bucket{method=\"compute_transfer\",le=\"0.05\"} 1",
+            "pathfinder_request_duration_seconds_bucket{method=\"compute_transfer\",le=\"60\"} 1",
+            "pathfinder_request_duration_seconds_bucket{method=\"compute_transfer\",le=\"+Inf\"} 2",
+            "pathfinder_request_duration_seconds_count{method=\"compute_transfer\"} 2",
+            "pathfinder_flow_value_circles_bucket{le=\"100\"} 0",
+            "pathfinder_flow_value_circles_bucket{le=\"1000\"} 1",
+            "pathfinder_flow_transfers_sum 3",
+            "pathfinder_edge_db_load_duration_seconds_count 1",
+            "pathfinder_graph_edges 10",
+            "pathfinder_graph_nodes 4",
+            "pathfinder_queue_depth 1",
+            "pathfinder_transfer_cache_hits_total 1",
+            "pathfinder_transfer_cache_misses_total 2",
+        ] {
+            assert!(out.lines().any(|l| l == line), "missing {line}");
+        }
+    }
+}
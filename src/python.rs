@@ -0,0 +1,178 @@
+//! A Python extension module (feature `python`), so that analyses in Python can compute
+//! flows in-process instead of running the CLI and parsing its output. Build it with
+//! `maturin build --release --features python`, see `pyproject.toml`.
+//!
+//! ```python
+//! import pathfinder2
+//!
+//! graph = pathfinder2.Graph.load("edges.dat")
+//! flow, steps = graph.compute_flow(source, sink, value=10**18, max_transfers=10)
+//! steps["from"]  # numpy array of shape (n, 20), the addresses as bytes
+//! ```
+//!
+//! The transfer steps are columns of numpy arrays: `from`, `to` and `token_owner` of type
+//! `uint8` and shape `(n, 20)`, `value` with Python integers (dtype `object`), since
+//! values exceed 64 bits. Errors raise `pathfinder2.PathfinderError`.
+
+use num_bigint::BigUint;
+use numpy::{PyArray1, PyArrayMethods};
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::graph::FlowOptions;
+use crate::types::{Address, Edge, U256};
+
+create_exception!(pathfinder2, PathfinderError, PyException);
+
+fn error(e: impl ToString) -> PyErr {
+    PyErr::new::<PathfinderError, _>(e.to_string())
+}
+
+/// The edges, see `crate::Graph`.
+#[pyclass(name = "Graph", module = "pathfinder2", frozen)]
+struct PyGraph(crate::Graph);
+
+#[pymethods]
+impl PyGraph {
+    /// Loads an edge file in any format.
+    #[staticmethod]
+    fn load(py: Python<'_>, path: &str) -> PyResult<PyGraph> {
+        py.allow_threads(|| crate::Graph::load(path))
+            .map(PyGraph)
+            .map_err(error)
+    }
+
+    /// Loads a safes file, binary or JSON if the name ends with `.json`.
+    #[staticmethod]
+    fn load_safes(py: Python<'_>, path: &str) -> PyResult<PyGraph> {
+        py.allow_threads(|| crate::Graph::load_safes(path))
+            .map(PyGraph)
+            .map_err(error)
+    }
+
+    #[getter]
+    fn edge_count(&self) -> usize {
+        self.0.edges().edge_count()
+    }
+
+    /// Computes the maximum flow from `source` to `sink`, up to `value` (no limit if None),
+    /// and returns it with the columns of the transfer steps.
+    #[pyo3(signature = (source, sink, value=None, max_distance=None, max_transfers=None))]
+    fn compute_flow<'py>(
+        &self,
+        py: Python<'py>,
+        source: &str,
+        sink: &str,
+        value: Option<BigUint>,
+        max_distance: Option<u64>,
+        max_transfers: Option<u64>,
+    ) -> PyResult<(BigUint, Bound<'py, PyDict>)> {
+        let source = source
+            .parse::<Address>()
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let sink = sink
+            .parse::<Address>()
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        let options = FlowOptions {
+            requested_flow: match value {
+                Some(value) => u256(value)?,
+                None => U256::MAX,
+            },
+            max_distance,
+            max_transfers,
+            ..Default::default()
+        };
+        let result = py
+            .allow_threads(|| self.0.compute_flow(&source, &sink, &options))
+            .map_err(error)?;
+        let columns = TransferColumns::new(&result.transfers);
+        let steps = PyDict::new(py);
+        for (name, addresses) in [
+            ("from", columns.from),
+            ("to", columns.to),
+            ("token_owner", columns.token_owner),
+        ] {
+            let array = PyArray1::from_vec(py, addresses).reshape([columns.len, 20])?;
+            steps.set_item(name, array)?;
+        }
+        let values = columns
+            .value
+            .into_iter()
+            .map(|value| Ok(value.into_pyobject(py)?.into_any().unbind()))
+            .collect::<PyResult<_>>()?;
+        steps.set_item("value", PyArray1::<PyObject>::from_vec(py, values))?;
+        Ok((result.flow.into(), steps))
+    }
+}
+
+fn u256(value: BigUint) -> PyResult<U256> {
+    match value.bits() <= 256 {
+        true => Ok(U256::from_bigint_truncating(value)),
+        false => Err(PyValueError::new_err(format!("Value too large: {value}"))),
+    }
+}
+
+/// The transfer steps as columns, the addresses as 20 bytes per step.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct TransferColumns {
+    len: usize,
+    from: Vec<u8>,
+    to: Vec<u8>,
+    token_owner: Vec<u8>,
+    value: Vec<BigUint>,
+}
+
+impl TransferColumns {
+    fn new(transfers: &[Edge]) -> TransferColumns {
+        let mut columns = TransferColumns {
+            len: transfers.len(),
+            ..Default::default()
+        };
+        for transfer in transfers {
+            columns.from.extend(transfer.from.to_bytes());
+            columns.to.extend(transfer.to.to_bytes());
+            columns.token_owner.extend(transfer.token.to_bytes());
+            columns.value.push(transfer.capacity.into());
+        }
+        columns
+    }
+}
+
+#[pymodule]
+fn pathfinder2(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    // Fails on import rather than on the first flow if numpy is not installed.
+    m.py().import("numpy")?;
+    m.add_class::<PyGraph>()?;
+    m.add("PathfinderError", m.py().get_type::<PathfinderError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::edge::edge;
+
+    #[test]
+    fn transfer_columns() {
+        let columns = TransferColumns::new(&[
+            edge([1; 20], [2; 20], [1; 20], 10),
+            edge([2; 20], [3; 20], [2; 20], 7),
+        ]);
+        assert_eq!(columns.len, 2);
+        assert_eq!(columns.from, [[1; 20], [2; 20]].concat());
+        assert_eq!(columns.to, [[2; 20], [3; 20]].concat());
+        assert_eq!(columns.token_owner, [[1; 20], [2; 20]].concat());
+        assert_eq!(columns.value, [BigUint::from(10u32), BigUint::from(7u32)]);
+        assert_eq!(TransferColumns::new(&[]), TransferColumns::default());
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(u256(BigUint::from(5u32)).unwrap(), U256::from(5u128));
+        let max = BigUint::from(U256::MAX);
+        assert_eq!(u256(max.clone()).unwrap(), U256::MAX);
+        assert!(u256(max + 1u32).is_err());
+    }
+}
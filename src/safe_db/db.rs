@@ -1,6 +1,6 @@
 use std::collections::BTreeMap;
 
-use crate::types::{edge::EdgeDB, Address, Edge, Safe, U256};
+use crate::types::{edge::EdgeDB, AccountType, Address, Edge, Safe, TokenType, U256};
 
 #[derive(Default, Debug)]
 pub struct DB {
@@ -11,7 +11,7 @@ pub struct DB {
 
 impl DB {
     pub fn new(safes: BTreeMap<Address, Safe>, token_owner: BTreeMap<Address, Address>) -> DB {
-        println!("{} safes, {} tokens", safes.len(), token_owner.len());
+        tracing::info!("{} safes, {} tokens", safes.len(), token_owner.len());
         let mut db = DB {
             safes,
             token_owner,
@@ -29,6 +29,83 @@ impl DB {
         &self.edges
     }
 
+    /// Returns the edges with the trust limit percentages instead of
+    /// absolute capacities for trust connections, together with the balances,
+    /// so that the capacities can be computed at query time and stay correct
+    /// when balances change.
+    pub fn edges_with_trust_limits(&self) -> EdgeDB {
+        let mut edges = EdgeDB::default();
+        for (user, safe) in &self.safes {
+            // Organizations have no token of their own to send.
+            let limits = safe
+                .limit_percentage
+                .iter()
+                .filter(|_| safe.account_type.mints());
+            for (send_to, percentage) in limits {
+                if *user != *send_to && self.safes.contains_key(send_to) {
+                    let edge = Edge {
+                        from: *user,
+                        to: *send_to,
+                        token: *user,
+                        capacity: U256::from(0u128),
+                    };
+                    edges.set_limit_percentage(&edge, *percentage);
+                }
+            }
+            for (token, balance) in &safe.balances {
+                if let Some(owner) = self.token_owner.get(token) {
+                    match safe.balance_days.get(token) {
+                        Some(day) => edges
+                            .balances_mut()
+                            .set_balance_at_day(*user, *owner, *balance, *day),
+                        None => edges.balances_mut().set_balance(*user, *owner, *balance),
+                    }
+                }
+            }
+            edges
+                .balances_mut()
+                .set_organization(*user, safe.account_type == AccountType::Organization);
+            edges.balances_mut().set_stopped(*user, safe.stopped);
+            if safe.account_type == AccountType::Group {
+                edges.set_token_type(*user, TokenType::Group);
+            }
+        }
+        for (edge, percentage) in self.group_token_edges() {
+            edges.set_limit_percentage(&edge, percentage);
+        }
+        edges
+    }
+
+    /// Returns the edges along which group tokens can be sent: Every holder of
+    /// a group token can send it to every account that trusts the group.
+    /// The capacity of the edges is the balance of the holder.
+    fn group_token_edges(&self) -> Vec<(Edge, u8)> {
+        let mut edges = vec![];
+        for (holder, safe) in &self.safes {
+            for (token, balance) in &safe.balances {
+                let Some(owner) = self.token_owner.get(token) else {
+                    continue;
+                };
+                let group = self.safes.get(owner);
+                let Some(group) = group.filter(|s| s.account_type == AccountType::Group) else {
+                    continue;
+                };
+                for (send_to, percentage) in &group.limit_percentage {
+                    if send_to != holder && send_to != owner && self.safes.contains_key(send_to) {
+                        let edge = Edge {
+                            from: *holder,
+                            to: *send_to,
+                            token: *owner,
+                            capacity: *balance,
+                        };
+                        edges.push((edge, *percentage));
+                    }
+                }
+            }
+        }
+        edges
+    }
+
     fn compute_edges(&mut self) {
         let mut edges = vec![];
         for (user, safe) in &self.safes {
@@ -41,7 +118,7 @@ impl DB {
                     // TODO should return "limited or not"
                     // edge should contain token balance and transfer limit (which can be unlimited)
                     let limit = safe.trust_transfer_limit(receiver_safe, *percentage);
-                    if limit != U256::from(0) {
+                    if limit != U256::from(0u128) {
                         edges.push(Edge {
                             from: *user,
                             to: *send_to,
@@ -54,7 +131,7 @@ impl DB {
             // send tokens back to owner
             for (token, balance) in &safe.balances {
                 if let Some(owner) = self.token_owner.get(token) {
-                    if *user != *owner && *balance != U256::from(0) {
+                    if *user != *owner && *balance != U256::from(0u128) {
                         edges.push(Edge {
                             from: *user,
                             to: *owner,
@@ -66,6 +143,20 @@ impl DB {
                 }
             }
         }
-        self.edges = EdgeDB::new(edges)
+        let group_token_edges = self.group_token_edges();
+        edges.extend(
+            group_token_edges
+                .into_iter()
+                .map(|(edge, _)| edge)
+                .filter(|edge| edge.capacity != U256::from(0u128)),
+        );
+        let mut edges = EdgeDB::new(edges);
+        for (user, safe) in &self.safes {
+            if safe.account_type == AccountType::Group {
+                edges.set_token_type(*user, TokenType::Group);
+            }
+            edges.balances_mut().set_stopped(*user, safe.stopped);
+        }
+        self.edges = edges
     }
 }
@@ -1,2 +1,3 @@
 pub mod db;
 pub mod safes_json;
+pub mod subgraph;
@@ -1,108 +1,655 @@
-use serde::Deserialize;
+use json::JsonValue;
+use serde::de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::cell::Cell;
 use std::collections::BTreeMap;
-use std::fs::read_to_string;
+use std::fmt;
+use std::io::{self, Read};
+use std::rc::Rc;
 
-use crate::types::{Address, Safe};
+use crate::io::compression;
+use crate::types::{AccountType, Address, Safe, U256};
 
 use super::db::DB;
 
-pub fn import_from_safes_json(file: &str) -> DB {
-    let contents = read_to_string(file).unwrap();
-    let db: Safes = serde_json::from_str(&contents).unwrap();
+/// Imports a safes JSON file. The safes are added one by one while the file is
+/// parsed, so the file is never held in memory as a whole. Fails on the first
+/// invalid entry.
+pub fn import_from_safes_json(file: &str) -> Result<DB, io::Error> {
+    let (db, _) = import(file, false)?;
+    Ok(db)
+}
+
+/// Like `import_from_safes_json`, but validates every entry against the schema and
+/// skips invalid entries and trust connections instead of failing, and reports them.
+/// Only files that are not valid JSON or have no list of safes fail.
+pub fn import_from_safes_json_strict(file: &str) -> Result<(DB, ImportReport), io::Error> {
+    import(file, true)
+}
+
+fn import(file: &str, strict: bool) -> Result<(DB, ImportReport), io::Error> {
+    let lines = Rc::new(Cell::new(1));
+    let mut importer = Importer {
+        strict,
+        lines: lines.clone(),
+        ..Default::default()
+    };
+    let reader = LineCounter {
+        inner: compression::open(file)?,
+        lines,
+    };
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    SafesSeed(&mut importer).deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    importer.finish()
+}
+
+/// The result of a strict import.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ImportReport {
+    /// The number of safes imported.
+    pub loaded: usize,
+    /// The number of invalid entries that were skipped.
+    pub skipped: usize,
+    /// Why entries or single trust connections were skipped.
+    pub errors: Vec<ImportError>,
+}
+
+/// An invalid entry or trust connection, with the position of the entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportError {
+    /// The index of the entry in the list of safes.
+    pub entry: usize,
+    /// The line the entry starts at.
+    pub line: usize,
+    /// The id of the entry, if it has one.
+    pub id: Option<String>,
+    pub message: String,
+}
+
+impl ImportReport {
+    /// The report with at most `limit` errors.
+    pub fn to_json(&self, limit: usize) -> JsonValue {
+        json::object! {
+            loaded: self.loaded,
+            skipped: self.skipped,
+            errorCount: self.errors.len(),
+            errors: self
+                .errors
+                .iter()
+                .take(limit)
+                .map(|e| json::object! {
+                    entry: e.entry,
+                    line: e.line,
+                    id: e.id.clone(),
+                    message: e.message.clone(),
+                })
+                .collect::<Vec<_>>(),
+        }
+    }
+}
+
+/// Collects the safes. Trust connections are applied at the end because
+/// they can refer to safes that come later in the file.
+#[derive(Default)]
+struct Importer {
+    strict: bool,
+    /// The line the reader is at.
+    lines: Rc<Cell<usize>>,
+    safes: BTreeMap<Address, Safe>,
+    token_owner: BTreeMap<Address, Address>,
+    /// The trust connections as user, address it can send to, limit percentage
+    /// and the index of the entry they are listed in.
+    limit_percentages: Vec<(Address, Address, u8, usize)>,
+    /// The line each entry starts at, in strict mode.
+    entry_lines: Vec<usize>,
+    entries: usize,
+    report: ImportReport,
+}
 
-    let mut safes: BTreeMap<Address, Safe> = Default::default();
-    let mut token_owner: BTreeMap<Address, Address> = Default::default();
+impl Importer {
+    /// Adds an entry of a strict import, skipping it if it is invalid.
+    fn add_entry(&mut self, line: usize, entry: Option<serde_json::Value>) {
+        let index = self.next_entry();
+        let id = entry
+            .as_ref()
+            .and_then(|e| e["id"].as_str())
+            .map(str::to_string);
+        let result = match entry {
+            Some(entry) => serde_json::from_value(entry)
+                .map_err(|e| e.to_string())
+                .and_then(|safe| self.add(safe, index)),
+            None => Err("Expected an object".to_string()),
+        };
+        self.entry_lines.push(line);
+        if let Err(message) = result {
+            self.report.skipped += 1;
+            self.report.errors.push(ImportError {
+                entry: index,
+                line,
+                id,
+                message,
+            });
+        }
+    }
+
+    fn next_entry(&mut self) -> usize {
+        self.entries += 1;
+        self.entries - 1
+    }
 
-    for json_safe in &db.safes {
-        let address: Address = json_safe.id.into();
+    fn add(&mut self, json_safe: JsonSafe, entry: usize) -> Result<(), String> {
+        let address = parse_address(&json_safe.id)?;
+        if self.strict && self.safes.contains_key(&address) {
+            return Err(format!("Duplicate safe {address}"));
+        }
         let mut s = Safe {
-            organization: json_safe.organization,
+            account_type: match (json_safe.group, json_safe.organization) {
+                (true, _) => AccountType::Group,
+                (false, true) => AccountType::Organization,
+                (false, false) => AccountType::Human,
+            },
+            stopped: json_safe.stopped,
             ..Default::default()
         };
+        let mut token_owner = vec![];
         for balance in &json_safe.balances {
-            let token_address: Address = balance.token.id.into();
-            let owner: Address = balance.token.owner.id.into();
-            s.balances.insert(token_address, balance.amount.into());
+            let token_address = parse_address(&balance.token.id)?;
+            let owner = parse_address(&balance.token.owner.id)?;
+            s.balances.insert(token_address, balance.amount);
+            if let Some(day) = balance.last_updated_day {
+                s.balance_days.insert(token_address, day);
+            }
             if owner == address {
                 s.token_address = token_address;
             }
-            token_owner.insert(token_address, owner);
+            token_owner.push((token_address, owner));
         }
-        safes.insert(address, s);
-    }
-
-    for json_safe in db.safes {
+        let mut limit_percentages = vec![];
         for connection in json_safe.outgoing.iter().chain(json_safe.incoming.iter()) {
-            let send_to: Address = connection.can_send_to_address.into();
-            let user: Address = connection.user_address.into();
-            let limit_percentage: u8 = connection.limit_percentage.parse().unwrap();
-            assert!(limit_percentage <= 100);
+            let send_to = parse_address(&connection.can_send_to_address)?;
+            let user = parse_address(&connection.user_address)?;
+            let limit_percentage: u8 = connection
+                .limit_percentage
+                .parse()
+                .ok()
+                .filter(|percentage| *percentage <= 100)
+                .ok_or_else(|| {
+                    format!("Invalid limit percentage: {}", connection.limit_percentage)
+                })?;
             if send_to != Address::default()
                 && user != Address::default()
                 && send_to != user
                 && limit_percentage > 0
             {
-                safes
-                    .get_mut(&user)
-                    .unwrap()
-                    .limit_percentage
-                    .insert(send_to, limit_percentage);
+                limit_percentages.push((user, send_to, limit_percentage, entry));
             }
         }
+        // The entry is only added once it is known to be valid.
+        self.safes.insert(address, s);
+        self.token_owner.extend(token_owner);
+        self.limit_percentages.extend(limit_percentages);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(DB, ImportReport), io::Error> {
+        for (user, send_to, limit_percentage, entry) in self.limit_percentages {
+            match self.safes.get_mut(&user) {
+                Some(safe) => {
+                    safe.limit_percentage.insert(send_to, limit_percentage);
+                }
+                None if self.strict => self.report.errors.push(ImportError {
+                    entry,
+                    line: self.entry_lines[entry],
+                    id: None,
+                    message: format!("Trust connection of unknown safe {user} skipped"),
+                }),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Trust connection of unknown safe {user}"),
+                    ))
+                }
+            }
+        }
+        self.report.loaded = self.safes.len();
+        self.report.errors.sort_by_key(|e| e.entry);
+        Ok((DB::new(self.safes, self.token_owner), self.report))
     }
-    DB::new(safes, token_owner)
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields)]
-#[serde(rename_all = "camelCase")]
-struct Safes<'a> {
-    #[allow(dead_code)]
-    block_number: &'a str,
-    safes: Vec<JsonSafe<'a>>,
+fn parse_address(address: &str) -> Result<Address, String> {
+    match address.starts_with("0x") {
+        true => address.parse(),
+        false => Err(format!("Invalid address: {address}")),
+    }
+}
+
+/// Counts the lines read, to report the line of invalid entries.
+struct LineCounter<R: Read> {
+    inner: R,
+    lines: Rc<Cell<usize>>,
+}
+
+impl<R: Read> Read for LineCounter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let newlines = buf[..read].iter().filter(|b| **b == b'\n').count();
+        self.lines.set(self.lines.get() + newlines);
+        Ok(read)
+    }
+}
+
+/// Deserializes the top-level object, passing each safe to the importer.
+struct SafesSeed<'a>(&'a mut Importer);
+
+impl<'de> DeserializeSeed<'de> for SafesSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for SafesSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an object with blockNumber and safes")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut has_safes = false;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "blockNumber" => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+                "safes" => {
+                    map.next_value_seed(SafeListSeed(&mut *self.0))?;
+                    has_safes = true;
+                }
+                _ => return Err(de::Error::unknown_field(&key, &["blockNumber", "safes"])),
+            }
+        }
+        match has_safes {
+            true => Ok(()),
+            false => Err(de::Error::missing_field("safes")),
+        }
+    }
+}
+
+struct SafeListSeed<'a>(&'a mut Importer);
+
+impl<'de> DeserializeSeed<'de> for SafeListSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for SafeListSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of safes")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        if self.0.strict {
+            let lines = self.0.lines.clone();
+            while let Some((line, entry)) = seq.next_element_seed(EntrySeed(&lines))? {
+                self.0.add_entry(line, entry);
+            }
+            return Ok(());
+        }
+        while let Some(safe) = seq.next_element::<JsonSafe>()? {
+            let entry = self.0.next_entry();
+            self.0.add(safe, entry).map_err(de::Error::custom)?;
+        }
+        Ok(())
+    }
+}
+
+/// Deserializes an entry of a strict import as any JSON value, with the line it starts at.
+/// Entries that are not objects are returned as None.
+struct EntrySeed<'a>(&'a Cell<usize>);
+
+impl<'de> DeserializeSeed<'de> for EntrySeed<'_> {
+    type Value = (usize, Option<serde_json::Value>);
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntrySeed<'_> {
+    type Value = (usize, Option<serde_json::Value>);
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a safe")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        // The opening brace was just read.
+        let line = self.0.get();
+        let entry = serde_json::Value::deserialize(de::value::MapAccessDeserializer::new(map))?;
+        Ok((line, Some(entry)))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok((self.0.get(), None))
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<Self::Value, E> {
+        Ok((self.0.get(), None))
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<Self::Value, E> {
+        Ok((self.0.get(), None))
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<Self::Value, E> {
+        Ok((self.0.get(), None))
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<Self::Value, E> {
+        Ok((self.0.get(), None))
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<Self::Value, E> {
+        Ok((self.0.get(), None))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok((self.0.get(), None))
+    }
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 #[serde(rename_all = "camelCase")]
-struct JsonSafe<'a> {
-    id: &'a str,
+struct JsonSafe {
+    id: String,
     organization: bool,
-    outgoing: Vec<Edge<'a>>,
-    incoming: Vec<Edge<'a>>,
-    balances: Vec<Balance<'a>>,
+    /// Circles v2 groups, absent for v1 safes.
+    #[serde(default)]
+    group: bool,
+    /// Deactivated safes or stopped tokens, absent if the export does not track them.
+    #[serde(default)]
+    stopped: bool,
+    outgoing: Vec<Edge>,
+    incoming: Vec<Edge>,
+    balances: Vec<Balance>,
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 #[serde(rename_all = "camelCase")]
-struct Edge<'a> {
+struct Edge {
     #[allow(dead_code)]
-    limit: Option<&'a str>,
-    limit_percentage: &'a str,
-    can_send_to_address: &'a str,
-    user_address: &'a str,
+    limit: Option<String>,
+    limit_percentage: String,
+    can_send_to_address: String,
+    user_address: String,
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 #[serde(rename_all = "camelCase")]
-struct Balance<'a> {
-    amount: &'a str,
-    token: Token<'a>,
+struct Balance {
+    amount: U256,
+    /// The day of the last update of Circles v2 balances, which decay over time.
+    #[serde(default)]
+    last_updated_day: Option<u64>,
+    token: Token,
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 #[serde(rename_all = "camelCase")]
-struct Token<'a> {
-    id: &'a str,
-    owner: Owner<'a>,
+struct Token {
+    id: String,
+    owner: Owner,
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 #[serde(rename_all = "camelCase")]
-struct Owner<'a> {
-    id: &'a str,
+struct Owner {
+    id: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn streaming_import() {
+        let path = env::temp_dir()
+            .join(format!("pathfinder_safes_{}.json", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let balance = |holder: &str| {
+            format!(
+                r#"{{"amount": "100", "token": {{"id": "{holder}", "owner": {{"id": "{holder}"}}}}}}"#
+            )
+        };
+        let a = "0x1000000000000000000000000000000000000001";
+        let b = "0x2000000000000000000000000000000000000002";
+        // The trust connection of `a` is listed before `a` itself.
+        let json = format!(
+            r#"{{"blockNumber": "1", "safes": [
+                {{"id": "{b}", "organization": false, "outgoing": [], "balances": [{}],
+                  "incoming": [{{"limitPercentage": "50", "canSendToAddress": "{b}", "userAddress": "{a}"}}]}},
+                {{"id": "{a}", "organization": false, "outgoing": [], "incoming": [], "balances": [{}]}}
+            ]}}"#,
+            balance(b),
+            balance(a)
+        );
+        fs::write(&path, &json).unwrap();
+        let db = import_from_safes_json(&path).unwrap();
+        assert_eq!(db.safes().len(), 2);
+        assert_eq!(
+            db.safes()[&Address::from(a)].limit_percentage[&Address::from(b)],
+            50
+        );
+        assert_eq!(db.edges().edge_count(), 1);
+
+        fs::write(&path, json.replace(r#""50""#, r#""150""#)).unwrap();
+        let error = import_from_safes_json(&path).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Invalid limit percentage: 150"));
+
+        fs::write(&path, json.replace("blockNumber", "block")).unwrap();
+        assert!(import_from_safes_json(&path).is_err());
+
+        // A strict import skips invalid entries and reports them.
+        let c = "0x3000000000000000000000000000000000000003";
+        let json = format!(
+            r#"{{"safes": [
+                {{"id": "{a}", "organization": false, "outgoing": [], "balances": [{}],
+                  "incoming": [{{"limitPercentage": "50", "canSendToAddress": "{a}", "userAddress": "{c}"}}]}},
+                {{"id": "0x12", "organization": false, "outgoing": [], "incoming": [], "balances": []}},
+                42,
+                {{"id": "{b}", "organization": "no", "outgoing": [], "incoming": [], "balances": []}},
+                {{"id": "{c}", "organization": false, "outgoing": [], "incoming": [], "balances": [], "extra": 1}}
+            ]}}"#,
+            balance(a)
+        );
+        fs::write(&path, &json).unwrap();
+        assert!(import_from_safes_json(&path).is_err());
+        let (db, report) = import_from_safes_json_strict(&path).unwrap();
+        assert_eq!(db.safes().len(), 1);
+        assert_eq!((report.loaded, report.skipped), (1, 4));
+        let errors = report
+            .errors
+            .iter()
+            .map(|e| (e.entry, e.line, e.id.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                (0, 2, None),
+                (1, 4, Some("0x12")),
+                (2, 5, None),
+                (3, 6, Some(b)),
+                (4, 7, Some(c))
+            ]
+        );
+        assert_eq!(
+            report.errors[0].message,
+            format!("Trust connection of unknown safe {c} skipped")
+        );
+        assert_eq!(report.errors[1].message, "Invalid address: 0x12");
+        assert!(report.errors[4]
+            .message
+            .starts_with("unknown field `extra`"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// Writes `json` to a temporary file named after `name` and returns its path.
+    fn temp_file(name: &str, json: &str) -> String {
+        let path = env::temp_dir()
+            .join(format!("pathfinder_{name}_{}.json", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        fs::write(&path, json).unwrap();
+        path
+    }
+
+    fn safe(id: &str) -> String {
+        format!(
+            r#"{{"id": "{id}", "organization": false, "outgoing": [], "incoming": [], "balances": []}}"#
+        )
+    }
+
+    #[test]
+    fn addresses() {
+        let a = "0x1000000000000000000000000000000000000001";
+        assert_eq!(parse_address(a), Ok(Address::from(a)));
+        assert_eq!(
+            parse_address(&a[2..]),
+            Err(format!("Invalid address: {}", &a[2..]))
+        );
+        assert_eq!(
+            parse_address("0x12"),
+            Err("Invalid address: 0x12".to_string())
+        );
+        assert!(parse_address("0x100000000000000000000000000000000000000g").is_err());
+    }
+
+    #[test]
+    fn counts_lines() {
+        let lines = Rc::new(Cell::new(1));
+        let mut reader = LineCounter {
+            inner: &b"a\nb\n\nc"[..],
+            lines: lines.clone(),
+        };
+        let mut buf = [0; 3];
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(lines.get(), 2);
+        let mut rest = vec![];
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"\n\nc");
+        assert_eq!(lines.get(), 4);
+    }
+
+    #[test]
+    fn entries_with_lines() {
+        let entries = |json: &'static str| {
+            let lines = Rc::new(Cell::new(1));
+            let reader = LineCounter {
+                inner: json.as_bytes(),
+                lines: lines.clone(),
+            };
+            let mut deserializer = serde_json::Deserializer::from_reader(reader);
+            EntrySeed(&lines).deserialize(&mut deserializer).unwrap()
+        };
+        let (line, entry) = entries("\n\n  {\"id\": \"0x12\",\n \"x\": [1]}");
+        assert_eq!(line, 3);
+        assert_eq!(entry.unwrap()["id"], "0x12");
+        assert_eq!(entries("\n42"), (2, None));
+        assert_eq!(entries("[{\"id\": 1},\n 2]"), (2, None));
+        assert_eq!(entries("null"), (1, None));
+    }
+
+    #[test]
+    fn duplicate_safes() {
+        let a = "0x1000000000000000000000000000000000000001";
+        let json = format!(
+            r#"{{"safes": [{},
+            {}]}}"#,
+            safe(a),
+            safe(a)
+        );
+        let path = temp_file("duplicate_safes", &json);
+        // Without validation, the later entry replaces the earlier one.
+        assert_eq!(import_from_safes_json(&path).unwrap().safes().len(), 1);
+        let (db, report) = import_from_safes_json_strict(&path).unwrap();
+        assert_eq!(db.safes().len(), 1);
+        assert_eq!(
+            report,
+            ImportReport {
+                loaded: 1,
+                skipped: 1,
+                errors: vec![ImportError {
+                    entry: 1,
+                    line: 2,
+                    id: Some(a.to_string()),
+                    message: format!("Duplicate safe {}", Address::from(a)),
+                }],
+            }
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reports_positions() {
+        let a = "0x1000000000000000000000000000000000000001";
+        let json = format!(
+            "{{\"safes\": [\n{},\n{}\n],\n  \"extra\": 1}}",
+            safe("0xab"),
+            safe(a).replace("\"balances\"", "\"colour\": 1, \"balances\"")
+        );
+        let path = temp_file("reports_positions", &json);
+        // Invalid files fail with the position the error is found at, the end of the
+        // invalid entry or the unknown field.
+        let error = import_from_safes_json(&path).unwrap_err().to_string();
+        assert_eq!(error, "Invalid address: 0xab at line 3 column 1");
+        let error = import_from_safes_json_strict(&path)
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("unknown field `extra`"));
+        assert!(error.ends_with("at line 5 column 10"));
+
+        let path = temp_file("reports_positions", &json.replace(",\n  \"extra\": 1", ""));
+        let (_, report) = import_from_safes_json_strict(&path).unwrap();
+        assert_eq!((report.loaded, report.skipped), (0, 2));
+        assert_eq!(
+            report.errors.iter().map(|e| e.line).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(report.errors[0].message, "Invalid address: 0xab");
+        assert!(report.errors[1]
+            .message
+            .starts_with("unknown field `colour`"));
+        assert_eq!(
+            report.to_json(1),
+            json::object! {
+                loaded: 0,
+                skipped: 2,
+                errorCount: 2,
+                errors: [{ entry: 0, line: 2, id: "0xab", message: "Invalid address: 0xab" }],
+            }
+        );
+        fs::remove_file(&path).unwrap();
+    }
 }
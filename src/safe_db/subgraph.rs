@@ -0,0 +1,388 @@
+//! Import of the safes, trust connections and balances from the Circles subgraph
+//! over GraphQL.
+//!
+//! All pages are queried at the latest block the subgraph has indexed, so that they are
+//! consistent. Entities are paged by id (`where: {id_gt: <last id>}`), which unlike
+//! `skip` also works beyond a few thousand entities. Failed queries are retried.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use super::db::DB;
+use crate::error::PathfinderError;
+use crate::types::{AccountType, Address, Safe, U256};
+
+/// The number of times a query is sent before giving up.
+const ATTEMPTS: u32 = 5;
+
+/// The time to connect and the longest time without receiving data.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct Subgraph {
+    agent: ureq::Agent,
+    url: String,
+    /// The number of entities per query, at most 1000 for The Graph.
+    page_size: usize,
+    /// The time before the first retry, doubled for each further one.
+    retry_delay: Duration,
+}
+
+impl Subgraph {
+    pub fn new(url: &str) -> Subgraph {
+        Subgraph {
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(TIMEOUT)
+                .timeout_read(TIMEOUT)
+                .build(),
+            url: url.to_string(),
+            page_size: 1000,
+            retry_delay: Duration::from_secs(1),
+        }
+    }
+
+    /// The latest block the subgraph has indexed.
+    pub fn block_number(&self) -> Result<u64, Box<dyn Error>> {
+        #[derive(Deserialize)]
+        struct Meta {
+            #[serde(rename = "_meta")]
+            meta: MetaBlock,
+        }
+        #[derive(Deserialize)]
+        struct MetaBlock {
+            block: Block,
+        }
+        #[derive(Deserialize)]
+        struct Block {
+            number: u64,
+        }
+        let meta: Meta = self.query("{ _meta { block { number } } }")?;
+        Ok(meta.meta.block.number)
+    }
+
+    /// Imports the safes as of `block_number`.
+    pub fn import(&self, block_number: u64) -> Result<DB, Box<dyn Error>> {
+        let mut safes = BTreeMap::new();
+        for safe in self.all::<SubgraphSafe>("safes", "id organization", block_number)? {
+            let safe_address = parse_address(&safe.id)?;
+            let safe = Safe {
+                account_type: match safe.organization {
+                    true => AccountType::Organization,
+                    false => AccountType::Human,
+                },
+                ..Default::default()
+            };
+            safes.insert(safe_address, safe);
+        }
+
+        let mut token_owner = BTreeMap::new();
+        let fields = "id amount owner { id } token { id owner { id } }";
+        for balance in self.all::<Balance>("balances", fields, block_number)? {
+            let holder = parse_address(&balance.owner.id)?;
+            let token = parse_address(&balance.token.id)?;
+            let owner = parse_address(&balance.token.owner.id)?;
+            token_owner.insert(token, owner);
+            if let Some(safe) = safes.get_mut(&holder) {
+                safe.balances.insert(token, balance.amount);
+                if owner == holder {
+                    safe.token_address = token;
+                }
+            }
+        }
+
+        let fields = "id userAddress canSendToAddress limitPercentage";
+        for trust in self.all::<Trust>("trusts", fields, block_number)? {
+            let user = parse_address(&trust.user_address)?;
+            let send_to = parse_address(&trust.can_send_to_address)?;
+            let limit_percentage: u8 = trust
+                .limit_percentage
+                .parse()
+                .ok()
+                .filter(|percentage| *percentage <= 100)
+                .ok_or_else(|| format!("Invalid limit percentage: {}", trust.limit_percentage))?;
+            if user == send_to || limit_percentage == 0 {
+                continue;
+            }
+            if let Some(safe) = safes.get_mut(&user) {
+                safe.limit_percentage.insert(send_to, limit_percentage);
+            }
+        }
+        Ok(DB::new(safes, token_owner))
+    }
+
+    /// All entities of the collection `name`, with the given fields (which include `id`).
+    fn all<T: DeserializeOwned + HasId>(
+        &self,
+        name: &str,
+        fields: &str,
+        block_number: u64,
+    ) -> Result<Vec<T>, Box<dyn Error>> {
+        let mut entities: Vec<T> = vec![];
+        loop {
+            let last = entities.last().map(|e| e.id()).unwrap_or_default();
+            let page_size = self.page_size;
+            let query = format!(
+                r#"{{ page: {name}(first: {page_size}, orderBy: id, orderDirection: asc, where: {{id_gt: "{last}"}}, block: {{number: {block_number}}}) {{ {fields} }} }}"#
+            );
+            let page: Page<T> = self.query(&query)?;
+            let complete = page.page.len() < page_size;
+            entities.extend(page.page);
+            if complete {
+                tracing::debug!("Read {} {name} from the subgraph.", entities.len());
+                return Ok(entities);
+            }
+        }
+    }
+
+    /// Sends a query, retrying if it fails.
+    fn query<T: DeserializeOwned>(&self, query: &str) -> Result<T, Box<dyn Error>> {
+        let mut delay = self.retry_delay;
+        let mut attempt = 1;
+        loop {
+            match self.try_query(query) {
+                Ok(data) => return Ok(data),
+                Err(e) if attempt < ATTEMPTS => {
+                    tracing::debug!("Subgraph query failed, retrying: {e}");
+                    thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn try_query<T: DeserializeOwned>(&self, query: &str) -> Result<T, Box<dyn Error>> {
+        let request = json::object! { query: query };
+        let response = self
+            .agent
+            .post(&self.url)
+            .set("Content-Type", "application/json")
+            .send_string(&request.dump())?
+            .into_string()?;
+        let response: Response<T> = serde_json::from_str(&response)?;
+        match (response.data, response.errors) {
+            (_, Some(errors)) if !errors.is_empty() => Err(PathfinderError::Chain(format!(
+                "The subgraph returned errors: {}",
+                errors
+                    .iter()
+                    .map(|e| e.message.as_str())
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            ))
+            .into()),
+            (Some(data), _) => Ok(data),
+            (None, _) => {
+                Err(PathfinderError::Chain("The subgraph returned no data.".into()).into())
+            }
+        }
+    }
+}
+
+fn parse_address(address: &str) -> Result<Address, String> {
+    match address.starts_with("0x") {
+        true => address.parse(),
+        false => Err(format!("Invalid address: {address}")),
+    }
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    data: Option<T>,
+    errors: Option<Vec<GraphQlError>>,
+}
+
+#[derive(Deserialize)]
+struct GraphQlError {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct Page<T> {
+    page: Vec<T>,
+}
+
+trait HasId {
+    fn id(&self) -> String;
+}
+
+#[derive(Deserialize)]
+struct SubgraphSafe {
+    id: String,
+    organization: bool,
+}
+
+#[derive(Deserialize)]
+struct Balance {
+    id: String,
+    amount: U256,
+    owner: Entity,
+    token: Token,
+}
+
+#[derive(Deserialize)]
+struct Token {
+    id: String,
+    owner: Entity,
+}
+
+#[derive(Deserialize)]
+struct Entity {
+    id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Trust {
+    id: String,
+    user_address: String,
+    can_send_to_address: String,
+    limit_percentage: String,
+}
+
+impl HasId for SubgraphSafe {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+}
+
+impl HasId for Balance {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+}
+
+impl HasId for Trust {
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::Edge;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn paged_import() {
+        let (a, b, c) = (
+            "0x".to_string() + &"1".repeat(40),
+            "0x".to_string() + &"2".repeat(40),
+            "0x".to_string() + &"3".repeat(40),
+        );
+        let safes = json::array![
+            { id: a.clone(), organization: false },
+            { id: b.clone(), organization: false },
+            { id: c.clone(), organization: true },
+        ];
+        let balances = json::array![
+            { id: "1", amount: "100", owner: { id: a.clone() }, token: { id: a.clone(), owner: { id: a.clone() } } },
+            { id: "2", amount: "40", owner: { id: b.clone() }, token: { id: a.clone(), owner: { id: a.clone() } } },
+        ];
+        let trusts = json::array![
+            { id: "1", userAddress: a.clone(), canSendToAddress: b.clone(), limitPercentage: "50" },
+            { id: "2", userAddress: a.clone(), canSendToAddress: c.clone(), limitPercentage: "0" },
+        ];
+
+        // Answers with pages of two entities, after failing the first request.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/subgraph", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut queries = vec![];
+            for request in 0.. {
+                let (mut socket, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(socket.try_clone().unwrap());
+                let mut length = 0;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    line.clear();
+                }
+                let mut body = vec![0u8; length];
+                reader.read_exact(&mut body).unwrap();
+                let query =
+                    json::parse(&String::from_utf8(body).unwrap()).unwrap()["query"].to_string();
+                let last = query
+                    .split_once("id_gt: \"")
+                    .and_then(|(_, rest)| rest.split_once('"'))
+                    .map(|(last, _)| last.to_string())
+                    .unwrap_or_default();
+                let page = |entities: &json::JsonValue| {
+                    let entities = entities
+                        .members()
+                        .filter(|e| e["id"].as_str().unwrap() > last.as_str())
+                        .take(2)
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    json::object! { data: { page: entities } }
+                };
+                let response = if request == 0 {
+                    None
+                } else if query.contains("_meta") {
+                    Some(json::object! { data: { _meta: { block: { number: 7 } } } })
+                } else if query.contains("safes(") {
+                    Some(page(&safes))
+                } else if query.contains("balances(") {
+                    Some(page(&balances))
+                } else {
+                    Some(page(&trusts))
+                };
+                match response {
+                    None => write!(
+                        socket,
+                        "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n"
+                    ),
+                    Some(response) => {
+                        let body = response.dump();
+                        write!(
+                            socket,
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+                            body.len()
+                        )
+                    }
+                }
+                .unwrap();
+                queries.push(query);
+                if queries.iter().filter(|q| q.contains("trusts(")).count() == 2 {
+                    return queries;
+                }
+            }
+            unreachable!()
+        });
+
+        let mut subgraph = Subgraph::new(&url);
+        subgraph.page_size = 2;
+        subgraph.retry_delay = Duration::from_millis(1);
+        assert_eq!(subgraph.block_number().unwrap(), 7);
+        let db = subgraph.import(7).unwrap();
+        let queries = server.join().unwrap();
+        assert!(queries[2].contains("block: {number: 7}"));
+
+        assert_eq!(db.safes().len(), 3);
+        let (a, b, c) = (
+            Address::from(a.as_str()),
+            Address::from(b.as_str()),
+            Address::from(c.as_str()),
+        );
+        assert_eq!(db.safes()[&c].account_type, AccountType::Organization);
+        assert_eq!(db.safes()[&b].balances[&a], U256::from(40u128));
+        assert_eq!(db.safes()[&a].limit_percentage.get(&b), Some(&50));
+        assert_eq!(db.safes()[&a].limit_percentage.get(&c), None);
+        let edges = db.edges_with_trust_limits();
+        let trust = Edge {
+            from: a,
+            to: b,
+            token: a,
+            capacity: U256::from(0u128),
+        };
+        assert_eq!(edges.limit_percentage(&trust), Some(50));
+    }
+}
@@ -1,271 +1,2651 @@
+use crate::auth::{ApiKeys, AuthError, Permit};
+use crate::cache::LruCache;
+use crate::config::{self, Config, ConfigSource};
+use crate::cors::AllowedOrigins;
+use crate::diff;
+use crate::error::{error_code, PathfinderError, RequestError};
 use crate::graph;
-use crate::io::{import_from_safes_binary, read_edges_binary, read_edges_csv};
+use crate::graph::{
+    Bottleneck, Cancellation, FlowAlgorithm, FlowError, FlowLimits, FlowOptions, TokenFilter,
+};
+use crate::indexer::balances::{self, Shortfall};
+use crate::indexer::rpc::EthClient;
+use crate::io::{
+    import_from_safes_binary, map_edges, read_edges_binary, read_edges_csv, read_edges_file,
+    read_trust_csv, wal::Change, write_edges_binary, write_edges_binary_to, write_edges_csv,
+    write_graph, write_trust_csv, GraphFormat,
+};
+use crate::metrics::Metrics;
+use crate::safe_db::safes_json::import_from_safes_json_strict;
+use crate::shutdown::{self, InFlight, InFlightGuard};
+use crate::stats::MAX_HUBS;
+use crate::tls::{self, Connection};
 use crate::types::edge::EdgeDB;
-use crate::types::{Address, Edge, U256};
+use crate::types::{Address, Demurrage, Edge, TokenType, U256};
+use crate::websocket;
+use crate::work_queue::{JobError, WorkQueue};
 use json::JsonValue;
 use num_bigint::BigUint;
-use regex::Regex;
+use rustls::ServerConfig;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::error::Error;
-use std::fmt::{Debug, Display, Formatter};
 use std::io::Read;
 use std::io::{BufRead, BufReader, Write};
-use std::net::{TcpListener, TcpStream};
-use std::ops::Deref;
-use std::str::FromStr;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::mpsc::TrySendError;
-use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::sync::{mpsc, Arc, Mutex, OnceLock, RwLock};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::span::EnteredSpan;
+use tracing::{field, Span};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
 
-struct JsonRpcRequest {
-    id: JsonValue,
-    method: String,
-    params: JsonValue,
+mod archive;
+#[cfg(feature = "parquet")]
+mod export;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod indexing;
+mod network;
+mod refresh;
+mod schema;
+pub(crate) mod snapshot;
+mod subgraph;
+
+use network::{Network, Networks};
+pub use refresh::download_edges;
+use snapshot::{Snapshot, VersionedGraph};
+
+pub struct JsonRpcRequest {
+    pub id: JsonValue,
+    pub method: String,
+    pub params: JsonValue,
+}
+
+fn validate_and_parse_ethereum_address(address: &str) -> Result<Address, Box<dyn Error>> {
+    match address.starts_with("0x") {
+        true => address.parse::<Address>(),
+        false => Err(format!("Invalid address: {address}")),
+    }
+    .map_err(|e| Box::new(PathfinderError::from(RequestError::InvalidAddress(e))) as Box<dyn Error>)
+}
+
+/// Parses the `from` and `to` parameters of a transfer, which must be different accounts.
+fn parse_transfer_endpoints(params: &JsonValue) -> Result<(Address, Address), Box<dyn Error>> {
+    let from = validate_and_parse_ethereum_address(&params["from"].to_string())?;
+    let to = validate_and_parse_ethereum_address(&params["to"].to_string())?;
+    check_different_accounts(&from, &to)?;
+    Ok((from, to))
+}
+
+/// Fails for a flow from an account to itself, which would always be empty.
+fn check_different_accounts(source: &Address, sink: &Address) -> Result<(), Box<dyn Error>> {
+    match source == sink {
+        true => Err(
+            PathfinderError::from(RequestError::SameAccount(source.to_checksummed_hex())).into(),
+        ),
+        false => Ok(()),
+    }
+}
+
+/// Parses the `value` parameter of a transfer, the maximum if it is omitted, but not zero.
+fn parse_transfer_value(params: &JsonValue, units: Units) -> Result<U256, Box<dyn Error>> {
+    let value = parse_value_param(&params["value"], units)?;
+    if value.is_zero() {
+        Err(PathfinderError::from(RequestError::ZeroValue))?;
+    }
+    Ok(value)
+}
+
+fn validate_and_parse_u256(value_str: &str) -> Result<U256, Box<dyn Error>> {
+    value_str.parse::<U256>().map_err(|e| {
+        Box::new(PathfinderError::Rpc(format!(
+            "Invalid value: {}. Couldn't parse value: {}",
+            value_str, e
+        ))) as Box<dyn Error>
+    })
+}
+
+/// The units of values in requests and responses.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Units {
+    /// The smallest unit, as used on-chain (the default).
+    Atto,
+    /// Whole Circles, i.e. 10^18 atto-Circles, with up to 18 decimals.
+    Circles,
+}
+
+const CIRCLES_DECIMALS: u8 = 18;
+
+impl Units {
+    fn parse(&self, value_str: &str) -> Result<U256, Box<dyn Error>> {
+        match self {
+            Units::Atto => validate_and_parse_u256(value_str),
+            Units::Circles => U256::from_decimal_units(value_str, CIRCLES_DECIMALS).map_err(|e| {
+                Box::new(PathfinderError::Rpc(format!(
+                    "Invalid value: {value_str}. Couldn't parse value: {e}"
+                ))) as Box<dyn Error>
+            }),
+        }
+    }
+
+    fn format(&self, value: U256) -> String {
+        match self {
+            Units::Atto => value.to_decimal(),
+            Units::Circles => value.to_decimal_units(CIRCLES_DECIMALS),
+        }
+    }
+}
+
+/// Parses the optional `units` parameter: `atto` (the default) or `circles`.
+fn parse_units_param(params: &JsonValue) -> Result<Units, Box<dyn Error>> {
+    match params["units"].as_str() {
+        None | Some("atto") => Ok(Units::Atto),
+        Some("circles") => Ok(Units::Circles),
+        Some(units) => Err(Box::new(PathfinderError::Rpc(format!(
+            "Unknown units: {units}. Expected atto or circles."
+        )))),
+    }
+}
+
+/// Parses the requested value of a transfer. If it is omitted, the maximum
+/// transferable value is computed.
+fn parse_value_param(value: &JsonValue, units: Units) -> Result<U256, Box<dyn Error>> {
+    if value.is_null() {
+        Ok(U256::MAX)
+    } else if let Some(value_str) = value.as_str() {
+        units.parse(value_str)
+    } else if value.is_number() {
+        units.parse(&value.dump())
+    } else {
+        Err(Box::new(PathfinderError::Rpc(format!(
+            "Invalid value: {value}. Expected a decimal string."
+        ))))
+    }
+}
+
+/// A page of the transfer steps of `compute_transfer`, selected by the optional
+/// `offset` and `limit` parameters.
+#[derive(Debug, Clone, Copy)]
+struct Page {
+    offset: usize,
+    limit: Option<usize>,
+}
+
+impl Page {
+    /// Replaces the transfer steps of a result by the page and adds their total number.
+    fn apply(&self, result: &mut JsonValue) {
+        // Taking the steps of a result without them would add them as null.
+        if !result["transferSteps"].is_array() {
+            return;
+        }
+        let JsonValue::Array(steps) = result["transferSteps"].take() else {
+            return;
+        };
+        result["transferCount"] = steps.len().into();
+        result["transferSteps"] = steps
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect::<Vec<_>>()
+            .into();
+    }
+}
+
+fn parse_page_params(params: &JsonValue) -> Result<Option<Page>, Box<dyn Error>> {
+    let parse = |name: &str| match &params[name] {
+        JsonValue::Null => Ok(None),
+        value => value.as_usize().map(Some).ok_or_else(|| {
+            PathfinderError::Rpc(format!(
+                "Invalid {name}: {value}. Expected a non-negative integer."
+            ))
+        }),
+    };
+    let (offset, limit) = (parse("offset")?, parse("limit")?);
+    if offset.is_none() && limit.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(Page {
+        offset: offset.unwrap_or_default(),
+        limit,
+    }))
+}
+
+/// Parses an optional integer parameter of at most `max`, `default` if it is omitted.
+fn parse_bounded_param(
+    params: &JsonValue,
+    name: &str,
+    default: usize,
+    max: usize,
+) -> Result<usize, PathfinderError> {
+    match &params[name] {
+        JsonValue::Null => Ok(default),
+        value => value.as_usize().filter(|v| *v <= max).ok_or_else(|| {
+            PathfinderError::Rpc(format!(
+                "Invalid {name}: {value}. Expected an integer from 0 to {max}."
+            ))
+        }),
+    }
+}
+
+/// Logs to stdout, as text or with `PATHFINDER_LOG_FORMAT=json` as JSON objects, at the
+/// level of `PATHFINDER_LOG` (`info` by default). The close of a span logs its duration.
+fn init_logging() {
+    let filter =
+        EnvFilter::try_from_env("PATHFINDER_LOG").unwrap_or_else(|_| EnvFilter::new("info"));
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE);
+    // Fails if a subscriber is already installed, e.g. by an application embedding the server.
+    let _ = match std::env::var("PATHFINDER_LOG_FORMAT").as_deref() {
+        Ok("json") => subscriber.json().try_init(),
+        _ => subscriber.try_init(),
+    };
+}
+
+/// Starts the server with the configuration from `source`: `threads` threads handle
+/// connections, of which at most `queue_size` wait for a thread. Flow computations run
+/// on `flow_workers` separate threads, and at most `flow_queue_size` of them wait for a
+/// worker. Feasibility checks and small transfers run on `fast_workers` threads of their
+/// own, so that they do not wait behind large computations. Connections and computations
+/// beyond that are rejected with a "queue full" error. Each computation is stopped after
+/// `flow_timeout`. The results of up to `cache_size` transfers are cached until the edges
+/// change. The configuration is reloaded on SIGHUP, see `config`.
+/// Connections are served over TLS if a certificate is configured, see `tls`, and
+/// require an API key if keys are configured, see `auth`. The edges are updated
+/// periodically from the configured `edge_source`: a `refresh_url` (see `refresh`), a node
+/// (see `indexing`) or the subgraph (see `subgraph`). Each configured network has its own
+/// edges and background updates, see `network`.
+/// Returns after SIGTERM or SIGINT once the accepted connections are answered.
+pub fn start_server(source: ConfigSource) {
+    init_logging();
+    let config = source.load().expect("Could not load configuration.");
+    let (listen_at, queue_size, threads) =
+        (config.listen_at.clone(), config.queue_size, config.threads);
+    let server = Arc::new(Server::new(
+        WorkQueue::new(config.flow_workers, config.flow_queue_size),
+        WorkQueue::new(config.fast_workers, config.flow_queue_size),
+        config,
+        tls::config_from_env().expect("Could not load TLS certificate."),
+        ApiKeys::from_env().expect("Could not load API keys."),
+    ));
+    let reload_server = server.clone();
+    config::on_reload(move || match source.load() {
+        Ok(config) => reload_server.reload(config),
+        Err(e) => tracing::error!("Error reloading configuration, keeping the previous one: {e}"),
+    })
+    .expect("Could not handle signals.");
+    for network in server.networks.iter() {
+        let restored = network.graph.snapshot();
+        if restored.edges.edge_count() > 0 {
+            tracing::info!(
+                "Restored {} edges of chain {}.",
+                restored.edges.edge_count(),
+                network.chain_id
+            );
+            server.graph_updated(network, &restored);
+        }
+        refresh::start(server.clone(), network.chain_id);
+        indexing::start(server.clone(), network.chain_id);
+        subgraph::start(server.clone(), network.chain_id);
+    }
+
+    #[cfg(feature = "parquet")]
+    export::start(server.clone());
+    #[cfg(not(feature = "parquet"))]
+    if server.config.read().unwrap().parquet_export_file.is_some() {
+        tracing::error!(
+            "parquet_export_file needs the feature parquet, the edges are not exported."
+        );
+    }
+
+    #[cfg(feature = "grpc")]
+    if let Ok(address) = std::env::var("PATHFINDER_GRPC_ADDRESS") {
+        grpc::start(server.clone(), &address);
+    }
+
+    // Queued connections count as in flight, so that they are answered before shutdown.
+    let (sender, receiver) = mpsc::sync_channel::<(TcpStream, InFlightGuard)>(queue_size);
+    let protected_receiver = Arc::new(Mutex::new(receiver));
+    for _ in 0..threads {
+        let rec = protected_receiver.clone();
+        let server = server.clone();
+        thread::spawn(move || loop {
+            let (socket, _in_flight) = rec.lock().unwrap().recv().unwrap();
+            server.metrics.dequeued();
+            let result = Connection::new(socket, server.tls.as_ref())
+                .map_err(Box::from)
+                .and_then(|connection| handle_connection(&server, connection));
+            if let Err(e) = result {
+                tracing::error!("Error handling connection: {e}");
+            }
+        });
+    }
+    let listener = TcpListener::bind(&listen_at).expect("Could not create server.");
+    let address = listener.local_addr().expect("Could not create server.");
+    let shutdown_server = server.clone();
+    shutdown::on_termination(move || {
+        tracing::info!("Shutting down, no longer accepting connections.");
+        shutdown_server.shutting_down.store(true, Ordering::SeqCst);
+        // Wakes up the accept loop.
+        let _ = TcpStream::connect(connectable(address));
+    })
+    .expect("Could not handle signals.");
+    loop {
+        let accepted = listener.accept();
+        if server.shutting_down.load(Ordering::SeqCst) {
+            break;
+        }
+        match accepted {
+            Ok((socket, _)) => match sender.try_send((socket, server.in_flight.start())) {
+                Ok(()) => server.metrics.enqueued(),
+                Err(TrySendError::Full((mut socket, _))) => {
+                    server.metrics.rejected();
+                    // Over TLS, the connection is closed without a response
+                    // instead of blocking on the handshake.
+                    if server.tls.is_none() {
+                        let error = job_error(JsonValue::Null, JobError::QueueFull);
+                        let response = http_response_with_status(TOO_MANY_REQUESTS, &error);
+                        let _ = socket.write_all(response.as_bytes());
+                    }
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    panic!("Internal communication channel disconnected.");
+                }
+            },
+            Err(e) => tracing::error!("Error accepting connection: {e}"),
+        }
+    }
+    drop(listener);
+    // Flow computations are stopped after the flow timeout, so by then
+    // all requests should be answered.
+    let remaining = server
+        .in_flight
+        .wait(server.flow_timeout() + SHUTDOWN_GRACE_PERIOD);
+    if remaining == 0 {
+        tracing::info!("All requests answered, exiting.");
+    } else {
+        tracing::error!("Exiting with {remaining} requests in progress.");
+    }
+}
+
+/// The time to answer requests after flow computations are stopped when shutting down.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// An address to connect to the listener at, the loopback address if it listens on all.
+fn connectable(mut address: SocketAddr) -> SocketAddr {
+    if address.ip().is_unspecified() {
+        match address {
+            SocketAddr::V4(_) => address.set_ip(Ipv4Addr::LOCALHOST.into()),
+            SocketAddr::V6(_) => address.set_ip(Ipv6Addr::LOCALHOST.into()),
+        }
+    }
+    address
+}
+
+/// Answers JSON-RPC requests in-process without listening for connections, with the
+/// validation and responses of the server. Used by the subcommands of `pathfinder2`.
+pub struct LocalServer {
+    server: Arc<Server>,
+}
+
+impl LocalServer {
+    /// A server for the edges, without background updates.
+    pub fn new(config: Config, edges: EdgeDB) -> Result<LocalServer, Box<dyn Error>> {
+        let server = Arc::new(Server::new(
+            WorkQueue::new(config.flow_workers, config.flow_queue_size),
+            WorkQueue::new(config.fast_workers, config.flow_queue_size),
+            config,
+            None,
+            None,
+        ));
+        let network = server.networks.main();
+        let snapshot = network.graph.replace(edges)?;
+        server.graph_updated(network, &snapshot);
+        Ok(LocalServer { server })
+    }
+
+    /// Calls `method` and returns the JSON-RPC response, with either `result` or `error`.
+    pub fn call(&self, method: &str, params: JsonValue) -> JsonValue {
+        let request = JsonRpcRequest {
+            id: 1.into(),
+            method: method.to_string(),
+            params,
+        };
+        json::parse(&handle_request(&self.server, request)).expect("Invalid JSON-RPC response.")
+    }
+}
+
+/// The state shared by all connections.
+struct Server {
+    networks: Networks,
+    subscriptions: Subscriptions,
+    metrics: Metrics,
+    /// The queue of flow computations.
+    flow_queue: WorkQueue,
+    /// The queue of cheap flow computations, see `is_fast_query`.
+    fast_queue: WorkQueue,
+    /// The settings that can change at runtime are taken from here.
+    config: RwLock<Config>,
+    /// The final results of `compute_transfer`.
+    transfer_cache: Mutex<LruCache<TransferKey, JsonValue>>,
+    /// The origins browsers may call the server from.
+    allowed_origins: AllowedOrigins,
+    /// The TLS configuration, if connections are encrypted.
+    tls: Option<Arc<ServerConfig>>,
+    /// The API keys, if requests have to be authorized.
+    api_keys: Option<ApiKeys>,
+    /// Set when the server stops accepting connections.
+    shutting_down: AtomicBool,
+    /// The connections accepted but not yet answered.
+    in_flight: Arc<InFlight>,
+}
+
+/// Identifies the result of `compute_transfer`: the chain id, the graph version, the day
+/// the balances were computed for and the parameters of the request.
+type TransferKey = (u64, u64, Option<u64>, String);
+
+/// The methods that compute flows and run on the flow queue.
+const FLOW_METHODS: &[&str] = &[
+    "compute_transfer",
+    "compute_transfer_to_sinks",
+    "compute_transfer_from_sources",
+    "compute_alternate_routes",
+    "is_transfer_possible",
+    "compute_capacity_matrix",
+];
+
+/// Transfers of at most this many Circles run on the fast queue.
+const FAST_QUERY_MAX_CIRCLES: u128 = 100;
+
+/// The methods that are counted separately in the metrics.
+const METHODS: &[&str] = &[
+    "load_edges_binary",
+    "load_edges_csv",
+    "load_edges_mmap",
+    "load_safes_binary",
+    "load_safes_json",
+    "compute_transfer",
+    "compute_transfer_to_sinks",
+    "compute_transfer_from_sources",
+    "compute_alternate_routes",
+    "is_transfer_possible",
+    "compute_capacity_matrix",
+    "add_wrappers",
+    "update_edges",
+    "apply_trust_events",
+    "update_balances",
+    "import_trust_csv",
+    "export_edges_binary",
+    "export_edges_csv",
+    "export_edges_parquet",
+    "export_trust_csv",
+    "export_graph",
+    "diff_edges",
+    "graph_stats",
+    "get_neighborhood",
+    "find_trust_path",
+    "verify_flow",
+    "rpc_describe",
+];
+
+impl Server {
+    fn new(
+        flow_queue: WorkQueue,
+        fast_queue: WorkQueue,
+        config: Config,
+        tls: Option<Arc<ServerConfig>>,
+        api_keys: Option<ApiKeys>,
+    ) -> Server {
+        Server {
+            networks: Networks::new(&config),
+            subscriptions: Default::default(),
+            metrics: Default::default(),
+            flow_queue,
+            fast_queue,
+            transfer_cache: Mutex::new(LruCache::new(config.cache_size)),
+            config: RwLock::new(config),
+            allowed_origins: AllowedOrigins::from_env(),
+            tls,
+            api_keys,
+            shutting_down: Default::default(),
+            in_flight: Default::default(),
+        }
+    }
+
+    /// The time after which flow computations are stopped.
+    fn flow_timeout(&self) -> Duration {
+        self.config.read().unwrap().flow_timeout
+    }
+
+    /// Applies the settings of a reloaded configuration that can change at runtime.
+    fn reload(&self, config: Config) {
+        let restart_required = self.config.read().unwrap().restart_required(&config);
+        if !restart_required.is_empty() {
+            tracing::error!(
+                "Changes to {} take effect after a restart.",
+                restart_required.join(", ")
+            );
+        }
+        self.transfer_cache
+            .lock()
+            .unwrap()
+            .set_capacity(config.cache_size);
+        for network in self.networks.iter() {
+            network
+                .graph
+                .set_retained_versions(config.retained_versions);
+        }
+        *self.config.write().unwrap() = config;
+        tracing::info!("Configuration reloaded.");
+    }
+
+    /// Adds the configured defaults to the parameters of a flow computation and checks
+    /// that its limits are non-negative integers.
+    fn with_defaults(
+        &self,
+        mut request: JsonRpcRequest,
+    ) -> Result<JsonRpcRequest, PathfinderError> {
+        if !request.params.is_object() {
+            return Ok(request);
+        }
+        let params = &mut request.params;
+        for name in ["max_hops", "timeout_ms", "max_transfers"] {
+            if !params[name].is_null() && params[name].as_u64().is_none() {
+                return Err(PathfinderError::Rpc(format!(
+                    "The parameter {name} must be a non-negative integer."
+                )));
+            }
+        }
+        let config = self.config.read().unwrap();
+        if params["algorithm"].is_null() && params["optimization"].is_null() {
+            params["algorithm"] = config.algorithm.as_str().into();
+        }
+        if params["max_hops"].is_null() {
+            if let Some(max_hops) = config.max_hops {
+                params["max_hops"] = max_hops.into();
+            }
+        }
+        Ok(request)
+    }
+
+    fn cached_transfer(&self, key: &TransferKey) -> Option<JsonValue> {
+        let result = self.transfer_cache.lock().unwrap().get(key).cloned();
+        self.metrics.record_cache(result.is_some());
+        result
+    }
+
+    /// The queue a flow computation runs on.
+    fn queue_for(&self, request: &JsonRpcRequest) -> &WorkQueue {
+        if is_fast_query(request) {
+            &self.fast_queue
+        } else {
+            &self.flow_queue
+        }
+    }
+
+    /// The settings of the network with the given chain id, None if it is no longer
+    /// configured.
+    fn network_config(&self, chain_id: u64) -> Option<Config> {
+        self.config.read().unwrap().network(chain_id)
+    }
+
+    /// Records a successful load or update of the edges of a network.
+    fn graph_updated(&self, network: &Network, snapshot: &Snapshot) {
+        // Results are cached by version, older ones are not used anymore.
+        self.transfer_cache.lock().unwrap().clear();
+        *network.loaded_at.lock().unwrap() = Some(unix_time());
+        // The metrics describe the main network.
+        if network.chain_id == self.networks.main().chain_id {
+            let edge_count = snapshot.edges.edge_count();
+            self.metrics
+                .record_graph_size(edge_count, snapshot.edges.node_count());
+        }
+        self.subscriptions.graph_updated(network.chain_id, snapshot);
+    }
+
+    /// Records the duration of a successful load of the edges.
+    fn record_load(&self, start: Instant) {
+        let elapsed = start.elapsed();
+        self.metrics.record_load(elapsed);
+        tracing::info!(load_ms = elapsed.as_millis() as u64, "Edges loaded.");
+    }
+
+    fn record_request(&self, method: &str, start: Instant, success: bool) {
+        let method = if METHODS.contains(&method) {
+            method
+        } else {
+            "unknown"
+        };
+        self.metrics
+            .record_request(method, start.elapsed(), success);
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Clients subscribed to graph updates over WebSocket connections.
+#[derive(Default)]
+struct Subscriptions {
+    next_id: AtomicU64,
+    subscribers: Mutex<HashMap<u64, mpsc::Sender<Outgoing>>>,
+}
+
+impl Subscriptions {
+    fn subscribe(&self, sender: mpsc::Sender<Outgoing>) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.subscribers.lock().unwrap().insert(id, sender);
+        id
+    }
+
+    fn unsubscribe(&self, id: u64) -> bool {
+        self.subscribers.lock().unwrap().remove(&id).is_some()
+    }
+
+    /// Notifies all subscribers that a new edge DB is in use.
+    fn graph_updated(&self, chain_id: u64, snapshot: &Snapshot) {
+        self.subscribers.lock().unwrap().retain(|id, sender| {
+            let notification = json::object! {
+                jsonrpc: "2.0",
+                method: "graph_updated",
+                params: {
+                    subscription: *id,
+                    chain_id: chain_id,
+                    edge_count: snapshot.edges.edge_count(),
+                    graph_version: snapshot.version,
+                },
+            };
+            // Subscribers whose connection was closed are removed.
+            sender.send(Outgoing::Text(notification.dump())).is_ok()
+        });
+    }
+}
+
+/// A message to be sent on a WebSocket connection.
+enum Outgoing {
+    Text(String),
+    Pong(Vec<u8>),
+}
+
+fn handle_connection(server: &Arc<Server>, mut socket: Connection) -> Result<(), Box<dyn Error>> {
+    let request = read_http_request(&mut socket)?;
+    if request.headers.get("upgrade").map(|u| u.to_lowercase()) == Some("websocket".to_string()) {
+        let permit = match authorize(server, &request) {
+            Ok(permit) => permit,
+            Err(e) => {
+                socket.write_all(auth_error_response(e).as_bytes())?;
+                return Ok(());
+            }
+        };
+        let key = request
+            .headers
+            .get("sec-websocket-key")
+            .ok_or("Missing Sec-WebSocket-Key")?
+            .clone();
+        socket.write_all(websocket::handshake_response(&key).as_bytes())?;
+        // WebSocket connections are long-lived, so they get their own thread.
+        let server = server.clone();
+        thread::spawn(move || {
+            if let Err(e) = serve_websocket(&server, socket, permit) {
+                tracing::error!("Error handling WebSocket connection: {e}");
+            }
+        });
+        return Ok(());
+    }
+    let result = handle_http_request(server, &mut socket, request);
+    socket.close();
+    result
+}
+
+fn handle_http_request(
+    server: &Arc<Server>,
+    socket: &mut Connection,
+    request: HttpRequest,
+) -> Result<(), Box<dyn Error>> {
+    let origin = request.headers.get("origin").map(String::as_str);
+    if request.method == "OPTIONS" {
+        let response = server.allowed_origins.preflight_response(origin);
+        socket.write_all(response.as_bytes())?;
+        return Ok(());
+    }
+    let cors_headers = server.allowed_origins.headers(origin);
+    let respond = |response: String| with_headers(response, &cors_headers);
+    match request.path.as_str() {
+        "/health" => {
+            let status = json::object! { status: "ok" };
+            socket.write_all(respond(http_response(&status.dump())).as_bytes())?;
+            return Ok(());
+        }
+        "/ready" => {
+            let (status, readiness) = readiness(server);
+            let response = http_response_with_status(status, &readiness.dump());
+            socket.write_all(respond(response).as_bytes())?;
+            return Ok(());
+        }
+        "/metrics" => {
+            socket.write_all(respond(http_response(&server.metrics.render())).as_bytes())?;
+            return Ok(());
+        }
+        _ => {}
+    }
+    let permit = match authorize(server, &request) {
+        Ok(permit) => permit,
+        Err(e) => {
+            socket.write_all(respond(auth_error_response(e)).as_bytes())?;
+            return Ok(());
+        }
+    };
+    let permit = permit.as_ref();
+    if let Some(query) = download_query(&request.path) {
+        if let Some(Err(e)) = permit.map(Permit::request) {
+            socket.write_all(respond(auth_error_response(e)).as_bytes())?;
+            return Ok(());
+        }
+        return serve_edges_download(server, socket, query, &cors_headers);
+    }
+    let request = parse_body(request.body)?;
+    if let JsonValue::Array(calls) = request {
+        let response = match handle_batch(server, permit, calls) {
+            Some(response) => http_response(&response),
+            // A batch of notifications is not answered.
+            None => "HTTP/1.1 204 No Content\r\n\r\n".to_string(),
+        };
+        socket.write_all(respond(response).as_bytes())?;
+        return Ok(());
+    }
+    let request = parse_request(request)?;
+    if let Err((status, error)) = authorize_call(permit, &request) {
+        let response = http_response_with_status(status, &error);
+        socket.write_all(respond(response).as_bytes())?;
+        return Ok(());
+    }
+    if request.method == "compute_transfer" {
+        // Intermediate results are streamed using chunked encoding,
+        // which starts once the computation is not rejected. Each result is
+        // serialized directly into chunks, one per line.
+        let id = request.id.clone();
+        let mut started = false;
+        let result = stream_transfer(server, request, &mut |payload| {
+            if !started {
+                socket.write_all(respond(chunked_header()).as_bytes())?;
+                started = true;
+            }
+            let mut writer = ChunkedWriter::new(&mut *socket);
+            payload.write(&mut writer)?;
+            writer.write_all(b"\r\n")?;
+            writer.flush()
+        });
+        match result {
+            Ok(()) if started => socket.write_all(chunked_close().as_bytes())?,
+            Ok(()) => socket.write_all(respond(http_response("")).as_bytes())?,
+            Err(JobError::QueueFull) => {
+                let error = job_error(id, JobError::QueueFull);
+                let response = http_response_with_status(TOO_MANY_REQUESTS, &error);
+                socket.write_all(respond(response).as_bytes())?
+            }
+            Err(e) => return Err(Box::new(e)),
+        }
+    } else {
+        let id = request.id.clone();
+        let response = handle_request(server, request);
+        let status = if response == job_error(id, JobError::QueueFull) {
+            TOO_MANY_REQUESTS
+        } else {
+            "200 OK"
+        };
+        let response = http_response_with_status(status, &response);
+        socket.write_all(respond(response).as_bytes())?;
+    }
+    Ok(())
+}
+
+/// The query of a request for `/edges.dat`, empty if there is none.
+fn download_query(path: &str) -> Option<&str> {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    (path == "/edges.dat").then_some(query)
+}
+
+/// Streams the current edges of the network given by the query parameter `chain_id`
+/// (the main network without it) as a binary edge file, see `io::write_edges_binary`.
+fn serve_edges_download(
+    server: &Server,
+    socket: &mut impl Write,
+    query: &str,
+    cors_headers: &str,
+) -> Result<(), Box<dyn Error>> {
+    let params = match query
+        .split('&')
+        .find_map(|parameter| parameter.strip_prefix("chain_id="))
+    {
+        Some(chain_id) => match chain_id.parse::<u64>() {
+            Ok(chain_id) => json::object! { chain_id: chain_id },
+            Err(_) => json::object! { chain_id: chain_id },
+        },
+        None => JsonValue::Null,
+    };
+    let network = match server.networks.for_params(&params) {
+        Ok(network) => network,
+        Err(e) => {
+            let error = jsonrpc_error(JsonValue::Null, -32602, &format!("Invalid params: {e}"));
+            let response = http_response_with_status("404 Not Found", &error);
+            socket.write_all(with_headers(response, cors_headers).as_bytes())?;
+            return Ok(());
+        }
+    };
+    let edges = current_snapshot(network).edges.clone();
+    let header = "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\n\
+                  Content-Disposition: attachment; filename=\"edges.dat\"\r\n\
+                  Transfer-Encoding: chunked\r\n\r\n";
+    socket.write_all(with_headers(header.to_string(), cors_headers).as_bytes())?;
+    let mut writer = ChunkedWriter::new(&mut *socket);
+    write_edges_binary_to(&edges, &mut writer)?;
+    writer.flush()?;
+    socket.write_all(chunked_close().as_bytes())?;
+    Ok(())
+}
+
+/// Checks the API key of a connection, if keys are configured.
+fn authorize(server: &Server, request: &HttpRequest) -> Result<Option<Permit>, AuthError> {
+    match &server.api_keys {
+        Some(api_keys) => {
+            let key = request.headers.get("x-api-key").map(String::as_str);
+            api_keys.authorize(key).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+fn auth_error_response(error: AuthError) -> String {
+    let status = match error {
+        AuthError::Unauthorized => "401 Unauthorized",
+        AuthError::RateLimited | AuthError::TooManyConnections => TOO_MANY_REQUESTS,
+    };
+    http_response_with_status(status, &auth_error(JsonValue::Null, error))
+}
+
+fn auth_error(id: JsonValue, error: AuthError) -> String {
+    let code = match error {
+        AuthError::Unauthorized => -32001,
+        AuthError::RateLimited | AuthError::TooManyConnections => -32006,
+    };
+    jsonrpc_error(id, code, &error.to_string())
+}
+
+/// Counts a call towards the rate limit of the API key and checks that it does
+/// not compute a flow of more than the maximum value of the key. Returns the HTTP
+/// status and the error response otherwise.
+fn authorize_call(
+    permit: Option<&Permit>,
+    request: &JsonRpcRequest,
+) -> Result<(), (&'static str, String)> {
+    let Some(permit) = permit else {
+        return Ok(());
+    };
+    if let Err(e) = permit.request() {
+        return Err((TOO_MANY_REQUESTS, auth_error(request.id.clone(), e)));
+    }
+    if let Some(max_value) = permit.max_value() {
+        if FLOW_METHODS.contains(&request.method.as_str())
+            && requested_value(request).is_some_and(|value| value > max_value)
+        {
+            let message = format!(
+                "Invalid params: The value exceeds the maximum of the API key: {}",
+                max_value.to_decimal()
+            );
+            return Err((
+                "200 OK",
+                jsonrpc_error(request.id.clone(), -32602, &message),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The value (in atto-Circles) a flow method is asked to transfer, the maximum if no
+/// value is given. None if the value is invalid, which is reported by the method.
+fn requested_value(request: &JsonRpcRequest) -> Option<U256> {
+    let units = parse_units_param(&request.params).ok()?;
+    if request.method == "compute_transfer_to_sinks" {
+        request.params["sinks"]
+            .members()
+            .try_fold(U256::from(0u128), |total, sink| {
+                Some(total.saturating_add(parse_value_param(&sink["value"], units).ok()?))
+            })
+    } else {
+        parse_value_param(&request.params["value"], units).ok()
+    }
+}
+
+/// Checks that a flow method is not asked for more than the `max_value` setting.
+fn check_max_value(request: &JsonRpcRequest, config: &Config) -> Result<(), PathfinderError> {
+    match config.max_value {
+        Some(max_value) if requested_value(request).is_some_and(|value| value > max_value) => {
+            Err(PathfinderError::Rpc(format!(
+                "The value exceeds the maximum of the server: {}",
+                max_value.to_decimal()
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The limits of the flow computations on `edges`, see the `max_explored_percent`
+/// and `max_decomposition_steps` settings.
+fn flow_limits(config: &Config, edges: &EdgeDB) -> FlowLimits {
+    let percent = usize::from(config.max_explored_percent);
+    FlowLimits {
+        max_explored_accounts: (percent < 100).then(|| edges.node_count() * percent / 100),
+        max_decomposition_steps: config.max_decomposition_steps,
+    }
+}
+
+/// The server is ready once edges have been loaded successfully, for each network.
+/// The readiness of the networks besides the main one is listed as `networks`.
+fn readiness(server: &Server) -> (&'static str, JsonValue) {
+    let network_readiness = |network: &Network| {
+        let loaded_at = *network.loaded_at.lock().unwrap();
+        let edges = network.graph.snapshot().edges.clone();
+        let ready = loaded_at.is_some() && edges.edge_count() > 0;
+        json::object! {
+            ready: ready,
+            chainId: network.chain_id,
+            blockNumber: edges.block_number(),
+            edgeCount: edges.edge_count(),
+            loadedAt: loaded_at,
+        }
+    };
+    let shutting_down = server.shutting_down.load(Ordering::SeqCst);
+    let main = server.networks.main();
+    let mut readiness = network_readiness(main);
+    let others = server
+        .networks
+        .iter()
+        .filter(|network| network.chain_id != main.chain_id)
+        .map(network_readiness)
+        .collect::<Vec<_>>();
+    let ready = readiness["ready"].as_bool().unwrap_or_default()
+        && others.iter().all(|other| other["ready"] == true)
+        && !shutting_down;
+    readiness["ready"] = ready.into();
+    if !others.is_empty() {
+        readiness["networks"] = others.into();
+    }
+    if shutting_down {
+        readiness["shuttingDown"] = true.into();
+    }
+    if ready {
+        ("200 OK", readiness)
+    } else {
+        ("503 Service Unavailable", readiness)
+    }
+}
+
+/// Handles JSON-RPC calls and batches sent as WebSocket text messages until the
+/// connection is closed. In addition to the HTTP methods, clients can `subscribe`
+/// to `graph_updated` notifications, which are sent whenever a new edge DB is loaded.
+/// The connection counts towards the concurrency limit of its API key until it is closed.
+fn serve_websocket(
+    server: &Arc<Server>,
+    socket: Connection,
+    permit: Option<Permit>,
+) -> Result<(), Box<dyn Error>> {
+    let subscriptions = &server.subscriptions;
+    let (sender, receiver) = mpsc::channel();
+    let (mut socket, mut writer) = socket.split()?;
+    let writer_thread = thread::spawn(move || {
+        for message in receiver {
+            let result = match message {
+                Outgoing::Text(text) => websocket::write_text(&mut writer, &text),
+                Outgoing::Pong(payload) => websocket::write_pong(&mut writer, &payload),
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        let _ = websocket::write_close(&mut writer);
+    });
+    let mut own_subscriptions = HashSet::new();
+    loop {
+        let text = match websocket::read_message(&mut socket) {
+            Ok(websocket::Message::Text(text)) => text,
+            Ok(websocket::Message::Ping(payload)) => {
+                sender.send(Outgoing::Pong(payload))?;
+                continue;
+            }
+            Ok(websocket::Message::Close) | Err(_) => break,
+        };
+        let request = match json::parse(&text) {
+            Ok(request) => request,
+            Err(e) => {
+                let error = jsonrpc_error(JsonValue::Null, -32700, &format!("Parse error: {e}"));
+                sender.send(Outgoing::Text(error))?;
+                continue;
+            }
+        };
+        if let JsonValue::Array(calls) = request {
+            if let Some(response) = handle_batch(server, permit.as_ref(), calls) {
+                sender.send(Outgoing::Text(response))?;
+            }
+            continue;
+        }
+        let request = match parse_request(request) {
+            Ok(request) => request,
+            Err(_) => {
+                let error = jsonrpc_error(JsonValue::Null, -32600, "Invalid Request");
+                sender.send(Outgoing::Text(error))?;
+                continue;
+            }
+        };
+        if let Err((_, error)) = authorize_call(permit.as_ref(), &request) {
+            sender.send(Outgoing::Text(error))?;
+            continue;
+        }
+        let response = match request.method.as_str() {
+            "subscribe" => match request.params[0].as_str() {
+                Some("graph_updated") => {
+                    let id = subscriptions.subscribe(sender.clone());
+                    own_subscriptions.insert(id);
+                    jsonrpc_result(request.id, id)
+                }
+                _ => jsonrpc_error(
+                    request.id,
+                    -32602,
+                    "Invalid arguments: Expected [\"graph_updated\"].",
+                ),
+            },
+            "unsubscribe" => {
+                let removed = request.params[0]
+                    .as_u64()
+                    .filter(|id| own_subscriptions.remove(id))
+                    .is_some_and(|id| subscriptions.unsubscribe(id));
+                jsonrpc_result(request.id, removed)
+            }
+            "compute_transfer" => {
+                // Intermediate results are sent as separate messages.
+                let id = request.id.clone();
+                let result = stream_transfer(server, request, &mut |payload| {
+                    sender
+                        .send(Outgoing::Text(payload.dump()))
+                        .map_err(std::io::Error::other)
+                });
+                match result {
+                    Ok(()) => continue,
+                    Err(e) => job_error(id, e),
+                }
+            }
+            _ => handle_request(server, request),
+        };
+        sender.send(Outgoing::Text(response))?;
+    }
+    for id in own_subscriptions {
+        subscriptions.unsubscribe(id);
+    }
+    drop(sender);
+    writer_thread
+        .join()
+        .map_err(|_| "WebSocket writer thread panicked")?;
+    Ok(())
+}
+
+/// The maximum number of calls in a batch request.
+const MAX_BATCH_CALLS: usize = 100;
+/// The number of threads the calls of a batch request run on.
+const BATCH_THREADS: usize = 8;
+
+/// Handles the calls of a batch request in parallel and returns the array
+/// of responses, without the responses to notifications (calls without id).
+fn handle_batch(
+    server: &Arc<Server>,
+    permit: Option<&Permit>,
+    calls: Vec<JsonValue>,
+) -> Option<String> {
+    if calls.is_empty() {
+        return Some(jsonrpc_error(JsonValue::Null, -32600, "Invalid Request"));
+    }
+    if calls.len() > MAX_BATCH_CALLS {
+        return Some(jsonrpc_error(
+            JsonValue::Null,
+            -32007,
+            &format!(
+                "The batch consists of {} calls, more than the maximum of {MAX_BATCH_CALLS}.",
+                calls.len()
+            ),
+        ));
+    }
+    // The calls run on threads of their own rather than on the rayon pool or a work
+    // queue: flow calls wait for a flow worker, which needs the rayon pool to explore
+    // the graph.
+    let next = AtomicUsize::new(0);
+    let responses = calls.iter().map(|_| OnceLock::new()).collect::<Vec<_>>();
+    thread::scope(|scope| {
+        for _ in 0..calls.len().min(BATCH_THREADS) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(call) = calls.get(i) else {
+                    break;
+                };
+                let notification = call.is_object() && !call.has_key("id");
+                let response = match parse_request(call.clone()) {
+                    Ok(request) => match authorize_call(permit, &request) {
+                        Ok(()) => handle_request(server, request),
+                        Err((_, error)) => error,
+                    },
+                    Err(_) => jsonrpc_error(JsonValue::Null, -32600, "Invalid Request"),
+                };
+                responses[i]
+                    .set((!notification).then_some(response))
+                    .unwrap();
+            });
+        }
+    });
+    let responses = responses
+        .into_iter()
+        .filter_map(|response| response.into_inner().unwrap())
+        .collect::<Vec<_>>();
+    if responses.is_empty() {
+        None
+    } else {
+        Some(format!("[{}]", responses.join(",")))
+    }
+}
+
+/// Starts the span of a request, with the parameters that identify a transfer.
+fn request_span(request: &JsonRpcRequest) -> EnteredSpan {
+    let span = tracing::info_span!(
+        "request",
+        id = %request.id,
+        method = %request.method,
+        from = field::Empty,
+        to = field::Empty,
+        value = field::Empty,
+        queue_ms = field::Empty,
+        cached = field::Empty,
+    );
+    for param in ["from", "to", "value"] {
+        if !request.params[param].is_null() {
+            span.record(param, field::display(&request.params[param]));
+        }
+    }
+    span.entered()
+}
+
+/// Records the time a request waited on a queue on its span.
+fn record_queue_time(start: Instant) {
+    Span::current().record("queue_ms", start.elapsed().as_millis() as u64);
+}
+
+/// Handles a single call and returns the JSON-RPC response.
+fn handle_request(server: &Arc<Server>, request: JsonRpcRequest) -> String {
+    let start = Instant::now();
+    let method = request.method.clone();
+    let id = request.id.clone();
+    let response = if FLOW_METHODS.contains(&method.as_str()) {
+        match server.with_defaults(request) {
+            Ok(request) => {
+                let worker_server = server.clone();
+                server
+                    .queue_for(&request)
+                    .run(move || {
+                        let _span = request_span(&request);
+                        record_queue_time(start);
+                        call_flow_method(&worker_server, request)
+                    })
+                    .unwrap_or_else(|e| job_error(id.clone(), e))
+            }
+            Err(e) => jsonrpc_error(id.clone(), -32602, &format!("Invalid params: {e}")),
+        }
+    } else {
+        let _span = request_span(&request);
+        call_method(server, request)
+    };
+    server.record_request(&method, start, !is_error_response(&response, id));
+    response
+}
+
+/// Computes a transfer on the flow queue and passes each (intermediate)
+/// JSON-RPC response to `emit`, including errors of the computation.
+fn stream_transfer(
+    server: &Arc<Server>,
+    request: JsonRpcRequest,
+    emit: &mut dyn FnMut(JsonValue) -> std::io::Result<()>,
+) -> Result<(), JobError> {
+    let start = Instant::now();
+    let id = request.id.clone();
+    let request = match server.with_defaults(request) {
+        Ok(request) => request,
+        Err(e) => {
+            server.record_request("compute_transfer", start, false);
+            let error = jsonrpc_error_object(id, -32602, &format!("Invalid params: {e}"));
+            return emit(error).map_err(|_| JobError::Aborted);
+        }
+    };
+    let worker_server = server.clone();
+    let result = server.queue_for(&request).run_streaming(
+        move |emit| {
+            let _span = request_span(&request);
+            record_queue_time(start);
+            let id = request.id.clone();
+            match cached_compute_transfer(&worker_server, request, emit) {
+                Ok(()) => true,
+                Err(e) => {
+                    let message = format!("Error computing transfer: {e}");
+                    let _ = emit(jsonrpc_error_object(id, error_code(&*e), &message));
+                    false
+                }
+            }
+        },
+        emit,
+    );
+    server.record_request("compute_transfer", start, result == Ok(true));
+    result.map(|_| ())
+}
+
+/// Whether a flow computation is cheap: feasibility checks, which stop as soon as
+/// the value is reached and do not extract transfers, and transfers of small values.
+fn is_fast_query(request: &JsonRpcRequest) -> bool {
+    match request.method.as_str() {
+        "is_transfer_possible" => true,
+        "compute_transfer" => parse_units_param(&request.params)
+            .and_then(|units| parse_value_param(&request.params["value"], units))
+            .is_ok_and(|value| {
+                value <= U256::from(FAST_QUERY_MAX_CIRCLES * 10u128.pow(CIRCLES_DECIMALS.into()))
+            }),
+        _ => false,
+    }
+}
+
+/// Handles the methods that compute flows.
+fn call_flow_method(server: &Server, request: JsonRpcRequest) -> String {
+    let cancellation = Cancellation::with_timeout(server.flow_timeout());
+    let network = match server.networks.for_params(&request.params) {
+        Ok(network) => network,
+        Err(e) => return jsonrpc_error(request.id, -32602, &format!("Invalid params: {e}")),
+    };
+    let Some(config) = server.network_config(network.chain_id) else {
+        return jsonrpc_error(request.id, -32602, "The network is no longer configured.");
+    };
+    if let Err(e) = check_max_value(&request, &config) {
+        return jsonrpc_error(request.id, -32602, &format!("Invalid params: {e}"));
+    }
+    // compute_transfer looks up its snapshot itself, as it is also streamed.
+    let snapshot = match pinned_snapshot(network, &request.params) {
+        Ok(snapshot) => snapshot,
+        Err(e) => return jsonrpc_error(request.id, -32602, &format!("Invalid params: {e}")),
+    };
+    match request.method.as_str() {
+        "compute_transfer" => {
+            // Without streaming, only the last result is returned.
+            let id = request.id.clone();
+            let mut last = None;
+            match cached_compute_transfer(server, request, &mut |payload| {
+                last = Some(payload);
+                Ok(())
+            }) {
+                Ok(()) => last.map_or_else(|| jsonrpc_result(id, JsonValue::Null), |r| r.dump()),
+                Err(e) => jsonrpc_error_for(id, "Error computing transfer", &*e),
+            }
+        }
+        "compute_transfer_to_sinks" => {
+            match compute_transfer_to_sinks(&request, &snapshot.edges, &cancellation) {
+                Ok(result) => jsonrpc_result(request.id, with_version(result, &snapshot)),
+                Err(e) => jsonrpc_error_for(request.id, "Error computing transfer", &*e),
+            }
+        }
+        "compute_transfer_from_sources" => {
+            match compute_transfer_from_sources(&request, &snapshot.edges, &cancellation) {
+                Ok(result) => jsonrpc_result(request.id, with_version(result, &snapshot)),
+                Err(e) => jsonrpc_error_for(request.id, "Error computing transfer", &*e),
+            }
+        }
+        "compute_alternate_routes" => {
+            let e = snapshot.edges.clone();
+            match compute_alternate_routes(&request, &e, &cancellation) {
+                Ok(routes) => jsonrpc_result(request.id, routes),
+                Err(e) => jsonrpc_error_for(request.id, "Error computing routes", &*e),
+            }
+        }
+        "compute_capacity_matrix" => {
+            let limits = flow_limits(&config, &snapshot.edges);
+            match compute_capacity_matrix(&request, &snapshot.edges, limits, &cancellation) {
+                Ok(result) => jsonrpc_result(request.id, with_version(result, &snapshot)),
+                Err(e) => jsonrpc_error_for(request.id, "Error computing capacities", &*e),
+            }
+        }
+        "is_transfer_possible" => {
+            let e = snapshot.edges.clone();
+            match is_transfer_possible(&request, &e, &cancellation) {
+                Ok(possible) => jsonrpc_result(request.id, possible),
+                Err(e) => jsonrpc_error_for(request.id, "Error checking transfer", &*e),
+            }
+        }
+        _ => jsonrpc_error(request.id, -32601, "Method not found"),
+    }
+}
+
+/// Handles all other methods.
+fn call_method(server: &Server, request: JsonRpcRequest) -> String {
+    let network = match server.networks.for_params(&request.params) {
+        Ok(network) => network,
+        Err(e) => return jsonrpc_error(request.id, -32602, &format!("Invalid params: {e}")),
+    };
+    let graph = &network.graph;
+    match request.method.as_str() {
+        "load_edges_binary" => {
+            let start = Instant::now();
+            match file_param(&request.params).and_then(|file| load_edges_binary(graph, file)) {
+                Ok(snapshot) => {
+                    server.record_load(start);
+                    server.graph_updated(network, &snapshot);
+                    jsonrpc_result(request.id, snapshot.edges.edge_count())
+                }
+                Err(e) => jsonrpc_error_for(request.id, "Error loading edges", &*e),
+            }
+        }
+        "load_edges_csv" => {
+            let start = Instant::now();
+            match file_param(&request.params).and_then(|file| load_edges_csv(graph, file)) {
+                Ok(snapshot) => {
+                    server.record_load(start);
+                    server.graph_updated(network, &snapshot);
+                    jsonrpc_result(request.id, snapshot.edges.edge_count())
+                }
+                Err(e) => jsonrpc_error_for(request.id, "Error loading edges", &*e),
+            }
+        }
+        "load_edges_mmap" => {
+            let start = Instant::now();
+            match file_param(&request.params).and_then(|file| load_edges_mmap(graph, file)) {
+                Ok(snapshot) => {
+                    server.record_load(start);
+                    server.graph_updated(network, &snapshot);
+                    jsonrpc_result(request.id, snapshot.edges.edge_count())
+                }
+                Err(e) => jsonrpc_error_for(request.id, "Error loading edges", &*e),
+            }
+        }
+        "load_safes_binary" => {
+            let start = Instant::now();
+            match file_param(&request.params).and_then(|file| load_safes_binary(graph, file)) {
+                Ok(snapshot) => {
+                    server.record_load(start);
+                    server.graph_updated(network, &snapshot);
+                    jsonrpc_result(request.id, snapshot.edges.edge_count())
+                }
+                Err(e) => jsonrpc_error_for(request.id, "Error loading edges", &*e),
+            }
+        }
+        "load_safes_json" => {
+            let start = Instant::now();
+            match load_safes_json(graph, &request.params) {
+                Ok((snapshot, mut report)) => {
+                    server.record_load(start);
+                    server.graph_updated(network, &snapshot);
+                    report["edgeCount"] = snapshot.edges.edge_count().into();
+                    jsonrpc_result(request.id, report)
+                }
+                Err(e) => jsonrpc_error_for(request.id, "Error loading edges", &*e),
+            }
+        }
+        "add_wrappers" => match request.params {
+            JsonValue::Array(wrappers) => match add_wrappers(graph, wrappers) {
+                Ok(snapshot) => {
+                    server.graph_updated(network, &snapshot);
+                    jsonrpc_result(request.id, snapshot.edges.edge_count())
+                }
+                Err(e) => jsonrpc_error_for(request.id, "Error adding wrappers", &*e),
+            },
+            _ => jsonrpc_error(request.id, -32602, "Invalid arguments: Expected array."),
+        },
+        "update_edges" => match request.params {
+            JsonValue::Array(updates) => match update_edges(graph, updates) {
+                Ok(snapshot) => {
+                    server.graph_updated(network, &snapshot);
+                    jsonrpc_result(
+                        request.id,
+                        json::object! {
+                            graphVersion: snapshot.version,
+                            edgeCount: snapshot.edges.edge_count(),
+                        },
+                    )
+                }
+                Err(e) => jsonrpc_error_for(request.id, "Error updating edges", &*e),
+            },
+            _ => jsonrpc_error(request.id, -32602, "Invalid arguments: Expected array."),
+        },
+        "apply_trust_events" => match apply_trust_events(graph, &request.params["events"]) {
+            Ok((applied, ignored, snapshot)) => {
+                server.graph_updated(network, &snapshot);
+                jsonrpc_result(
+                    request.id,
+                    json::object! {
+                        applied: applied,
+                        ignored: ignored,
+                        edgeCount: snapshot.edges.edge_count(),
+                    },
+                )
+            }
+            Err(e) => jsonrpc_error_for(request.id, "Error applying trust events", &*e),
+        },
+        "update_balances" => match update_balances(graph, &request.params["updates"]) {
+            Ok(snapshot) => {
+                server.graph_updated(network, &snapshot);
+                jsonrpc_result(request.id, snapshot.edges.edge_count())
+            }
+            Err(e) => jsonrpc_error_for(request.id, "Error updating balances", &*e),
+        },
+        "import_trust_csv" => {
+            match file_param(&request.params).and_then(|file| import_trust_csv(graph, file)) {
+                Ok(snapshot) => {
+                    server.graph_updated(network, &snapshot);
+                    jsonrpc_result(request.id, snapshot.edges.edge_count())
+                }
+                Err(e) => jsonrpc_error_for(request.id, "Error importing trust relations", &*e),
+            }
+        }
+        "export_edges_binary" => {
+            let current = current_snapshot(network).edges.clone();
+            match export_edges_binary(&current, &request.params) {
+                Ok(()) => jsonrpc_result(request.id, current.edge_count()),
+                Err(e) => jsonrpc_error_for(request.id, "Error exporting edges", &*e),
+            }
+        }
+        "export_edges_csv" => {
+            let current = current_snapshot(network).edges.clone();
+            match file_param(&request.params).and_then(|file| Ok(write_edges_csv(&current, file)?))
+            {
+                Ok(()) => jsonrpc_result(request.id, current.edge_count()),
+                Err(e) => jsonrpc_error_for(request.id, "Error exporting edges", &*e),
+            }
+        }
+        "export_edges_parquet" => {
+            let current = current_snapshot(network).edges.clone();
+            match file_param(&request.params).and_then(|file| export_edges_parquet(&current, file))
+            {
+                Ok(()) => jsonrpc_result(request.id, current.edge_count()),
+                Err(e) => jsonrpc_error_for(request.id, "Error exporting edges", &*e),
+            }
+        }
+        "export_trust_csv" => {
+            let current = current_snapshot(network).edges.clone();
+            match file_param(&request.params).and_then(|file| Ok(write_trust_csv(&current, file)?))
+            {
+                Ok(len) => jsonrpc_result(request.id, len),
+                Err(e) => jsonrpc_error_for(request.id, "Error exporting trust relations", &*e),
+            }
+        }
+        "export_graph" => match export_graph(network, &request.params) {
+            Ok(len) => jsonrpc_result(request.id, len),
+            Err(e) => jsonrpc_error_for(request.id, "Error exporting graph", &*e),
+        },
+        "diff_edges" => match diff_edges(network, &request.params) {
+            Ok(summary) => jsonrpc_result(request.id, summary),
+            Err(e) => jsonrpc_error_for(request.id, "Error comparing edges", &*e),
+        },
+        "graph_stats" => match graph_stats(network, &request.params) {
+            Ok(stats) => jsonrpc_result(request.id, stats),
+            Err(e) => jsonrpc_error_for(request.id, "Error computing statistics", &*e),
+        },
+        "get_neighborhood" => match get_neighborhood(network, &request.params) {
+            Ok(neighborhood) => jsonrpc_result(request.id, neighborhood),
+            Err(e) => jsonrpc_error_for(request.id, "Error computing neighborhood", &*e),
+        },
+        "find_trust_path" => match find_trust_path(network, &request.params) {
+            Ok(path) => jsonrpc_result(request.id, path),
+            Err(e) => jsonrpc_error_for(request.id, "Error finding trust path", &*e),
+        },
+        "verify_flow" => match verify_flow(network, &request.params) {
+            Ok(result) => jsonrpc_result(request.id, result),
+            Err(e) => jsonrpc_error_for(request.id, "Error verifying flow", &*e),
+        },
+        "rpc_describe" => jsonrpc_result(request.id, schema::describe()),
+        _ => jsonrpc_error(request.id, -32601, "Method not found"),
+    }
+}
+
+/// Returns the current snapshot of a network. If balances decay over time, they are first
+/// brought up to date with the current day.
+fn current_snapshot(network: &Network) -> Arc<Snapshot> {
+    let _load = tracing::info_span!("load").entered();
+    update_current_day(&network.graph)
+}
+
+/// Writes the edges as a binary edge file to the parameter `file`.
+fn export_edges_binary(edges: &EdgeDB, params: &JsonValue) -> Result<(), Box<dyn Error>> {
+    Ok(write_edges_binary(edges, file_param(params)?)?)
+}
+
+/// The parameter `file`, the path of a file to read or write.
+fn file_param(params: &JsonValue) -> Result<&str, Box<dyn Error>> {
+    Ok(params["file"]
+        .as_str()
+        .ok_or_else(|| PathfinderError::Rpc("Expected the parameter file.".to_string()))?)
+}
+
+/// Writes the edges as a Parquet file, see `io::write_edges_parquet`.
+fn export_edges_parquet(edges: &EdgeDB, file: &str) -> Result<(), Box<dyn Error>> {
+    #[cfg(feature = "parquet")]
+    return Ok(crate::io::write_edges_parquet(edges, file)?);
+    #[cfg(not(feature = "parquet"))]
+    {
+        let _ = (edges, file);
+        Err("Parquet exports need the feature parquet.".into())
+    }
+}
+
+/// The snapshot of the version given by the optional parameter `graph_version`, so that
+/// the queries of a workflow see the same edges, or the archived snapshot of the latest
+/// block at or before `at_block`, or the current snapshot without either.
+fn pinned_snapshot(
+    network: &Network,
+    params: &JsonValue,
+) -> Result<Arc<Snapshot>, PathfinderError> {
+    let version = &params["graph_version"];
+    if !params["at_block"].is_null() {
+        if !version.is_null() {
+            return Err(PathfinderError::Rpc(
+                "Expected either graph_version or at_block.".to_string(),
+            ));
+        }
+        return archived_snapshot(network, &params["at_block"]);
+    }
+    if version.is_null() {
+        return Ok(current_snapshot(network));
+    }
+    let version = version
+        .as_u64()
+        .ok_or_else(|| PathfinderError::Rpc(format!("Invalid graph_version: {version}")))?;
+    let current = network.graph.snapshot().version;
+    if version == current {
+        return Ok(current_snapshot(network));
+    }
+    network.graph.snapshot_at(version).ok_or_else(|| {
+        PathfinderError::Rpc(format!(
+            "Graph version {version} is not retained, the current version is {current}."
+        ))
+    })
+}
+
+fn archived_snapshot(
+    network: &Network,
+    at_block: &JsonValue,
+) -> Result<Arc<Snapshot>, PathfinderError> {
+    let block_number = at_block
+        .as_u64()
+        .ok_or_else(|| PathfinderError::Rpc(format!("Invalid at_block: {at_block}")))?;
+    let archive = network.graph.archive().ok_or_else(|| {
+        PathfinderError::Rpc("Queries at_block need archive_dir to be configured.".to_string())
+    })?;
+    archive.at_block(block_number)?.ok_or_else(|| {
+        PathfinderError::Rpc(format!(
+            "No edges are archived at or before block {block_number}."
+        ))
+    })
+}
+
+fn update_current_day(graph: &VersionedGraph) -> Arc<Snapshot> {
+    let today = Demurrage::day(unix_time());
+    let outdated =
+        |e: &EdgeDB| e.balances().has_demurrage() && e.balances().current_day() != Some(today);
+    let current = graph.snapshot();
+    if !outdated(&current.edges) {
+        return current;
+    }
+    // Results are cached by the day of the balances, so the version stays the same.
+    graph.refresh(|edges| {
+        outdated(edges).then(|| {
+            let mut updated = edges.clone();
+            updated.balances_mut().set_current_day(today);
+            updated
+        })
+    })
+}
+
+/// Adds the version of the graph and its block number to a result object.
+fn with_version(mut result: JsonValue, snapshot: &Snapshot) -> JsonValue {
+    result["graphVersion"] = snapshot.version.into();
+    if let Some(block_number) = snapshot.edges.block_number() {
+        result["blockNumber"] = block_number.into();
+    }
+    result
+}
+
+fn load_edges_binary(graph: &VersionedGraph, file: &str) -> Result<Arc<Snapshot>, Box<dyn Error>> {
+    Ok(graph.replace(read_edges_binary(file)?)?)
+}
+
+fn load_edges_csv(graph: &VersionedGraph, file: &str) -> Result<Arc<Snapshot>, Box<dyn Error>> {
+    Ok(graph.replace(read_edges_csv(file)?)?)
+}
+
+fn load_edges_mmap(graph: &VersionedGraph, file: &str) -> Result<Arc<Snapshot>, Box<dyn Error>> {
+    Ok(graph.replace(map_edges(file)?)?)
+}
+
+fn load_safes_binary(graph: &VersionedGraph, file: &str) -> Result<Arc<Snapshot>, Box<dyn Error>> {
+    // Keep the trust limit percentages so that capacities follow balance changes.
+    Ok(graph.replace(import_from_safes_binary(file)?.edges_with_trust_limits())?)
+}
+
+/// The number of errors returned by `load_safes_json` by default.
+const IMPORT_ERROR_LIMIT: usize = 100;
+
+/// Imports a safes JSON file in strict mode, skipping invalid entries, and returns
+/// the import report.
+fn load_safes_json(
+    graph: &VersionedGraph,
+    params: &JsonValue,
+) -> Result<(Arc<Snapshot>, JsonValue), Box<dyn Error>> {
+    let (db, report) = import_from_safes_json_strict(file_param(params)?)?;
+    let snapshot = graph.replace(db.edges_with_trust_limits())?;
+    let limit = params["limit"].as_usize().unwrap_or(IMPORT_ERROR_LIMIT);
+    Ok((snapshot, report.to_json(limit)))
+}
+
+/// Like `compute_transfer`, but returns the cached result if the same transfer was
+/// computed on the same graph before, and caches the final result unless it timed out.
+/// Transfers with verified balances are not cached, as the balances on-chain change.
+fn cached_compute_transfer(
+    server: &Server,
+    request: JsonRpcRequest,
+    emit: &mut dyn FnMut(JsonValue) -> std::io::Result<()>,
+) -> Result<(), Box<dyn Error>> {
+    let network = server
+        .networks
+        .for_params(&request.params)
+        .map_err(PathfinderError::Rpc)?;
+    // Queries keep the snapshot they started with, even if a new one is loaded meanwhile.
+    let snapshot = pinned_snapshot(network, &request.params)?;
+    // All pages of a transfer share the cached result.
+    let page = parse_page_params(&request.params)?;
+    let emit = &mut |mut response: JsonValue| {
+        if response["result"].is_object() {
+            response["result"]["graphVersion"] = snapshot.version.into();
+        }
+        if let Some(page) = page.filter(|_| response.has_key("result")) {
+            page.apply(&mut response["result"]);
+        }
+        emit(response)
+    };
+    let mut params = request.params.clone();
+    params.remove("offset");
+    params.remove("limit");
+    let edges = &snapshot.edges;
+    let key = (
+        network.chain_id,
+        snapshot.version,
+        edges.balances().current_day(),
+        params.dump(),
+    );
+    let config = server
+        .network_config(network.chain_id)
+        .ok_or("The network is no longer configured.")?;
+    check_max_value(&request, &config)?;
+    let verification = parse_verify_balances_param(&request.params, &config)?;
+    if verification.is_none() {
+        if let Some(result) = server.cached_transfer(&key) {
+            Span::current().record("cached", true);
+            emit(jsonrpc_result_object(request.id, result))?;
+            return Ok(());
+        }
+    }
+    let cancellation = Cancellation::with_timeout(server.flow_timeout());
+    let result = compute_transfer(
+        request,
+        edges.as_ref(),
+        verification.as_ref(),
+        &config,
+        &server.metrics,
+        &cancellation,
+        emit,
+    )?;
+    if let Some(result) = result.filter(|_| verification.is_none()) {
+        server.transfer_cache.lock().unwrap().insert(key, result);
+    }
+    Ok(())
+}
+
+/// The check of the balances a transfer needs against the chain, see the
+/// `verify_balances` parameter.
+struct BalanceVerification {
+    client: EthClient,
+    hub: Address,
+    /// Whether transfers with stale balances are recomputed instead of rejected.
+    rescale: bool,
+}
+
+impl BalanceVerification {
+    /// The balances that are smaller on-chain than the transfers need.
+    fn stale_balances(&self, transfers: &[Edge]) -> Result<Vec<Shortfall>, Box<dyn Error>> {
+        let pairs = transfers
+            .iter()
+            .map(|transfer| (transfer.from, transfer.token))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        let current = balances::read_balances(&self.client, self.hub, &pairs)?;
+        let current = pairs
+            .into_iter()
+            .zip(current)
+            .filter_map(|(pair, balance)| Some((pair, balance?)))
+            .collect::<HashMap<_, _>>();
+        Ok(balances::shortfalls(transfers, |holder, token| {
+            current.get(&(*holder, *token)).copied()
+        }))
+    }
+}
+
+/// Parses the optional `verify_balances` parameter. Balances are read from the node
+/// configured as `indexer_rpc_url`.
+fn parse_verify_balances_param(
+    params: &JsonValue,
+    config: &Config,
+) -> Result<Option<BalanceVerification>, Box<dyn Error>> {
+    let rescale = match params["verify_balances"].as_str() {
+        None if params["verify_balances"].is_null() => return Ok(None),
+        Some("reject") => false,
+        Some("rescale") => true,
+        _ => Err(PathfinderError::Rpc(format!(
+            "Invalid verify_balances: {}, expected reject or rescale.",
+            params["verify_balances"]
+        )))?,
+    };
+    let url = config
+        .indexer_rpc_url
+        .as_ref()
+        .ok_or("Balances cannot be verified, indexer_rpc_url is not configured.")?;
+    Ok(Some(BalanceVerification {
+        client: EthClient::new(url),
+        hub: config.indexer_hub,
+        rescale,
+    }))
+}
+
+/// Fails if `flow` is not the reference max flow from `source` to `sink`, limited to the
+/// requested value, see the `reference_check` setting.
+fn check_against_reference(
+    source: &Address,
+    sink: &Address,
+    edges: &EdgeDB,
+    options: &FlowOptions,
+    flow: U256,
+) -> Result<(), Box<dyn Error>> {
+    let expected =
+        graph::reference_max_flow(source, sink, edges).min(BigUint::from(options.requested_flow));
+    if BigUint::from(flow) == expected {
+        return Ok(());
+    }
+    let message = format!(
+        "The flow {flow} from {} to {} computed with {} differs from the reference flow {expected}.",
+        source.to_checksummed_hex(),
+        sink.to_checksummed_hex(),
+        options.algorithm.as_str()
+    );
+    tracing::error!("{message}");
+    Err(message.into())
+}
+
+/// The number of stale balances that are corrected before a transfer is rejected with
+/// `verify_balances: "rescale"`.
+const MAX_STALE_BALANCES: usize = 100;
+
+/// Computes the transfer and passes each (intermediate) JSON-RPC response to `emit`.
+/// Returns the final result if the computation finished without timing out.
+/// With `reference_check`, fails if a flow differs from the reference max flow. The
+/// flows are limited by the settings in `config`, see `flow_limits`.
+fn compute_transfer(
+    request: JsonRpcRequest,
+    edges: &EdgeDB,
+    verification: Option<&BalanceVerification>,
+    config: &Config,
+    metrics: &Metrics,
+    cancellation: &Cancellation,
+    emit: &mut dyn FnMut(JsonValue) -> std::io::Result<()>,
+) -> Result<Option<JsonValue>, Box<dyn Error>> {
+    let units = parse_units_param(&request.params)?;
+    let parsed_value_param = parse_transfer_value(&request.params, units)?;
+
+    let from_address = validate_and_parse_ethereum_address(&request.params["from"].to_string())?;
+    let to_address = validate_and_parse_ethereum_address(&request.params["to"].to_string())?;
+    // To deliver wrapped tokens, the flow ends in the wrapper instead of the recipient.
+    let wrapper = parse_wrapped_token_param(&request.params, edges)?;
+    let sink = wrapper.unwrap_or(to_address);
+    check_different_accounts(&from_address, &sink)?;
+
+    let max_hops = request.params["max_hops"].as_u64();
+    let max_distances = if request.params["iterative"].as_bool().unwrap_or_default() {
+        // Try short paths first, then up to the (optional) hop limit.
+        [1, 2]
+            .into_iter()
+            .filter(|d| max_hops.is_none_or(|max_hops| *d < max_hops))
+            .map(Some)
+            .chain([max_hops])
+            .collect()
+    } else {
+        vec![max_hops]
+    };
+
+    let mut options = FlowOptions {
+        requested_flow: parsed_value_param,
+        max_transfers: request.params["max_transfers"].as_u64(),
+        algorithm: parse_algorithm_params(&request.params)?,
+        token_filter: parse_token_filter_params(&request.params)?,
+        max_staleness_blocks: request.params["max_staleness_blocks"].as_u64(),
+        exclude_stopped: request.params["exclude_stopped"]
+            .as_bool()
+            .unwrap_or_default(),
+        limits: flow_limits(config, edges),
+        ..FlowOptions::default()
+    };
+    // The time budget applies to all rounds together.
+    let deadline = request.params["timeout_ms"]
+        .as_u64()
+        .map(|ms| Instant::now() + Duration::from_millis(ms));
+    options.cancellation = match deadline {
+        Some(deadline) => cancellation.until(deadline),
+        None => cancellation.clone(),
+    };
+    let rounds = max_distances.len();
+    // The edges with the balances corrected by verification, and the corrections.
+    let mut edges = Cow::Borrowed(edges);
+    let mut stale_balances: Vec<Shortfall> = vec![];
+    let mut round = 0;
+    while round < rounds {
+        let edges = &mut edges;
+        options.max_distance = max_distances[round];
+        let flow_result =
+            graph::compute_flow_anytime(&from_address, &sink, edges, &options, |_| true)?;
+        let timed_out = options.cancellation.is_cancelled();
+        tracing::info!(
+            "Computed flow with max distance {:?}: {}",
+            options.max_distance,
+            flow_result.flow
+        );
+        if config.reference_check && !timed_out && graph::is_reference_checkable(edges, &options) {
+            check_against_reference(&from_address, &sink, edges, &options, flow_result.flow)?;
+        }
+        let transfers = match wrapper {
+            Some(wrapper) => graph::deliver_wrapped(flow_result.transfers, &wrapper, &to_address),
+            None => flow_result.transfers,
+        };
+        // Reject plans that would revert on-chain instead of returning them.
+        let transfers = match graph::repair_transfer_order(transfers, edges) {
+            Ok(transfers) => transfers,
+            Err(e) => {
+                let error = jsonrpc_error_object(
+                    request.id.clone(),
+                    -32000,
+                    &format!("Transfer would revert: {e}"),
+                );
+                emit(error)?;
+                break;
+            }
+        };
+        let is_final = timed_out || round + 1 == rounds;
+        let verification = verification.filter(|_| is_final && !timed_out);
+        if let Some(verification) = verification {
+            let stale = verification.stale_balances(&transfers)?;
+            if let Some(shortfall) = stale.first().filter(|_| !verification.rescale) {
+                let error = jsonrpc_error_object(
+                    request.id.clone(),
+                    -32000,
+                    &format!(
+                        "Transfer would revert: {} holds {} of the tokens of {} but needs {}",
+                        shortfall.holder.to_checksummed_hex(),
+                        units.format(shortfall.balance),
+                        shortfall.token.to_checksummed_hex(),
+                        units.format(shortfall.needed)
+                    ),
+                );
+                emit(error)?;
+                break;
+            }
+            if !stale.is_empty() {
+                if stale_balances.len() >= MAX_STALE_BALANCES {
+                    let error = jsonrpc_error_object(
+                        request.id.clone(),
+                        -32000,
+                        "Transfer would revert: too many stale balances",
+                    );
+                    emit(error)?;
+                    break;
+                }
+                // Compute the last round again with the current balances.
+                *edges = Cow::Owned(balances::with_balances(edges, &stale));
+                stale_balances.extend(stale);
+                continue;
+            }
+        }
+        if is_final {
+            let circles = flow_result.flow.to_decimal_units(CIRCLES_DECIMALS);
+            metrics.record_flow(circles.parse().unwrap_or_default(), transfers.len());
+        }
+        let mut result = json::object! {
+            maxFlowValue: units.format(flow_result.flow),
+            final: is_final,
+            transferSteps: transfers_to_json(transfers, edges, units),
+        };
+        if deadline.is_some() || timed_out {
+            result["timedOut"] = timed_out.into();
+        }
+        if verification.is_some() {
+            result["balancesVerified"] = true.into();
+            result["staleBalances"] = stale_balances
+                .iter()
+                .map(|shortfall| {
+                    json::object! {
+                        holder: shortfall.holder.to_checksummed_hex(),
+                        token_owner: shortfall.token.to_checksummed_hex(),
+                        balance: units.format(shortfall.balance),
+                    }
+                })
+                .collect::<Vec<_>>()
+                .into();
+        }
+        if let Some(block_number) = edges.block_number() {
+            result["blockNumber"] = block_number.into();
+        }
+        if let Some(statistics) = flow_result.statistics {
+            result["statistics"] = json::object! {
+                exploredNodes: statistics.explored_nodes,
+                exploredEdges: statistics.explored_arcs,
+                relevantNodes: statistics.relevant_nodes,
+                relevantEdges: statistics.relevant_arcs,
+            };
+        }
+        if let Some(min_cut) = flow_result.min_cut {
+            result["minCut"] = min_cut
+                .into_iter()
+                .map(|bottleneck| bottleneck_to_json(bottleneck, units))
+                .collect::<Vec<_>>()
+                .into();
+        }
+        emit(jsonrpc_result_object(request.id.clone(), result.clone()))?;
+        if timed_out {
+            break;
+        }
+        if is_final {
+            return Ok(Some(result));
+        }
+        round += 1;
+    }
+    Ok(None)
+}
+
+/// Parses the optional `wrapped_token` parameter, the ERC-20 wrapper the
+/// recipient wants to receive.
+fn parse_wrapped_token_param(
+    params: &JsonValue,
+    edges: &EdgeDB,
+) -> Result<Option<Address>, Box<dyn Error>> {
+    if params["wrapped_token"].is_null() {
+        return Ok(None);
+    }
+    let wrapper = validate_and_parse_ethereum_address(&params["wrapped_token"].to_string())?;
+    if edges.wrapped_token(&wrapper).is_none() {
+        return Err(Box::new(PathfinderError::Rpc(format!(
+            "Unknown token wrapper: {wrapper}"
+        ))));
+    }
+    Ok(Some(wrapper))
+}
+
+/// Parses the optional `accepted_tokens` and `excluded_tokens` parameters.
+fn parse_token_filter_params(params: &JsonValue) -> Result<TokenFilter, Box<dyn Error>> {
+    let parse_tokens = |tokens: &JsonValue| {
+        tokens
+            .members()
+            .map(|token| validate_and_parse_ethereum_address(&token.to_string()))
+            .collect::<Result<HashSet<_>, _>>()
+    };
+    Ok(TokenFilter {
+        accepted: if params["accepted_tokens"].is_null() {
+            None
+        } else {
+            Some(parse_tokens(&params["accepted_tokens"])?)
+        },
+        excluded: parse_tokens(&params["excluded_tokens"])?,
+    })
+}
+
+/// Parses the `algorithm` and `optimization` parameters.
+fn parse_algorithm_params(params: &JsonValue) -> Result<FlowAlgorithm, Box<dyn Error>> {
+    let algorithm = match params["algorithm"].as_str() {
+        Some(algorithm) => algorithm
+            .parse::<FlowAlgorithm>()
+            .map_err(PathfinderError::Rpc)?,
+        None => FlowAlgorithm::default(),
+    };
+    Ok(match params["optimization"].as_str() {
+        None => algorithm,
+        Some("min_hops") if params["algorithm"].is_null() => FlowAlgorithm::MinCost,
+        Some("min_hops") => Err(PathfinderError::Rpc(
+            "Optimization min_hops cannot be combined with an algorithm.".to_string(),
+        ))?,
+        Some(optimization) => Err(PathfinderError::Rpc(format!(
+            "Unknown optimization: {optimization}"
+        )))?,
+    })
+}
+
+/// The limits of the parameters of `compute_capacity_matrix`.
+const MAX_MATRIX_ADDRESSES: usize = 50;
+const DEFAULT_PAIR_TIMEOUT_MS: usize = 100;
+const MAX_PAIR_TIMEOUT_MS: usize = 10_000;
+
+/// The maximum values that can be transferred between all ordered pairs of `addresses`,
+/// each computed for at most `pair_timeout_ms`. The values of pairs that timed out are
+/// the best flows found until then and are listed in `approximate`.
+fn compute_capacity_matrix(
+    request: &JsonRpcRequest,
+    edges: &EdgeDB,
+    limits: FlowLimits,
+    cancellation: &Cancellation,
+) -> Result<JsonValue, Box<dyn Error>> {
+    let units = parse_units_param(&request.params)?;
+    let addresses = request.params["addresses"]
+        .members()
+        .map(|a| validate_and_parse_ethereum_address(&a.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if addresses.is_empty() || addresses.len() > MAX_MATRIX_ADDRESSES {
+        return Err(Box::new(PathfinderError::Rpc(format!(
+            "Expected an array of 1 to {MAX_MATRIX_ADDRESSES} addresses."
+        ))));
+    }
+    let pair_timeout = parse_bounded_param(
+        &request.params,
+        "pair_timeout_ms",
+        DEFAULT_PAIR_TIMEOUT_MS,
+        MAX_PAIR_TIMEOUT_MS,
+    )?;
+    let mut options = FlowOptions {
+        max_distance: request.params["max_hops"].as_u64(),
+        algorithm: parse_algorithm_params(&request.params)?,
+        limits,
+        ..FlowOptions::default()
+    };
+    let mut approximate = vec![];
+    let mut matrix = vec![];
+    for (i, from) in addresses.iter().enumerate() {
+        let mut row = vec![];
+        for (j, to) in addresses.iter().enumerate() {
+            if i == j {
+                row.push(JsonValue::Null);
+                continue;
+            }
+            let deadline = Instant::now() + Duration::from_millis(pair_timeout as u64);
+            options.cancellation = cancellation.until(deadline);
+            let flow = match graph::compute_flow_anytime(from, to, edges, &options, |_| true) {
+                Ok(result) => result.flow,
+                Err(FlowError::Cancelled) => U256::from(0u128),
+                Err(e) => return Err(e.into()),
+            };
+            if options.cancellation.is_cancelled() {
+                approximate.push(json::array![i, j]);
+            }
+            row.push(units.format(flow).into());
+        }
+        matrix.push(JsonValue::Array(row));
+    }
+    Ok(json::object! {
+        addresses: addresses.iter().map(|a| a.to_checksummed_hex()).collect::<Vec<_>>(),
+        matrix: matrix,
+        approximate: approximate,
+    })
+}
+
+fn compute_transfer_to_sinks(
+    request: &JsonRpcRequest,
+    edges: &EdgeDB,
+    cancellation: &Cancellation,
+) -> Result<JsonValue, Box<dyn Error>> {
+    let units = parse_units_param(&request.params)?;
+    let from_address = validate_and_parse_ethereum_address(&request.params["from"].to_string())?;
+    let mut sinks: BTreeMap<Address, U256> = BTreeMap::new();
+    for sink in request.params["sinks"].members() {
+        let address = validate_and_parse_ethereum_address(&sink["to"].to_string())?;
+        check_different_accounts(&from_address, &address)?;
+        let value = parse_transfer_value(sink, units)?;
+        // Values for the same sink are added up.
+        let total = sinks.entry(address).or_default();
+        *total = total.checked_add(value).ok_or_else(|| {
+            PathfinderError::Rpc(format!(
+                "Value for sink {} too large.",
+                address.to_checksummed_hex()
+            ))
+        })?;
+    }
+    if sinks.is_empty() {
+        return Err(Box::new(PathfinderError::Rpc(
+            "Expected a non-empty array of sinks.".to_string(),
+        )));
+    }
+    let (received, transfers) = graph::compute_flow_to_sinks(
+        &from_address,
+        &sinks,
+        edges,
+        request.params["max_hops"].as_u64(),
+        request.params["max_transfers"].as_u64(),
+        parse_algorithm_params(&request.params)?,
+        cancellation,
+    )?;
+    let flow = received
+        .values()
+        .try_fold(U256::from(0u128), |sum, v| sum.checked_add(*v))
+        .ok_or(FlowError::CapacityOverflow)?;
+    Ok(json::object! {
+        maxFlowValue: units.format(flow),
+        sinks: sinks.keys().map(|sink| json::object! {
+            to: sink.to_checksummed_hex(),
+            value: units.format(received.get(sink).copied().unwrap_or_default()),
+        }).collect::<Vec<_>>(),
+        transferSteps: transfers_to_json(transfers, edges, units),
+    })
+}
+
+fn compute_transfer_from_sources(
+    request: &JsonRpcRequest,
+    edges: &EdgeDB,
+    cancellation: &Cancellation,
+) -> Result<JsonValue, Box<dyn Error>> {
+    let units = parse_units_param(&request.params)?;
+    let sources = request.params["sources"]
+        .members()
+        .map(|source| validate_and_parse_ethereum_address(&source.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if sources.is_empty() {
+        return Err(Box::new(PathfinderError::Rpc(
+            "Expected a non-empty array of sources.".to_string(),
+        )));
+    }
+    let to_address = validate_and_parse_ethereum_address(&request.params["to"].to_string())?;
+    for source in &sources {
+        check_different_accounts(source, &to_address)?;
+    }
+    let contributions = graph::compute_flow_from_sources(
+        &sources,
+        &to_address,
+        edges,
+        parse_transfer_value(&request.params, units)?,
+        request.params["max_hops"].as_u64(),
+        request.params["max_transfers"].as_u64(),
+        parse_algorithm_params(&request.params)?,
+        cancellation,
+    )?;
+    let flow = contributions
+        .iter()
+        .try_fold(U256::from(0u128), |sum, (_, value, _)| {
+            sum.checked_add(*value)
+        })
+        .ok_or(FlowError::CapacityOverflow)?;
+    Ok(json::object! {
+        maxFlowValue: units.format(flow),
+        sources: contributions.into_iter().map(|(source, value, transfers)| json::object! {
+            from: source.to_checksummed_hex(),
+            value: units.format(value),
+            transferSteps: transfers_to_json(transfers, edges, units),
+        }).collect::<Vec<_>>(),
+    })
 }
 
-struct InputValidationError(String);
-impl Error for InputValidationError {}
-
-impl Debug for InputValidationError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Error: {}", self.0)
-    }
+fn compute_alternate_routes(
+    request: &JsonRpcRequest,
+    edges: &EdgeDB,
+    cancellation: &Cancellation,
+) -> Result<JsonValue, Box<dyn Error>> {
+    let units = parse_units_param(&request.params)?;
+    let value = parse_transfer_value(&request.params, units)?;
+    let (from_address, to_address) = parse_transfer_endpoints(&request.params)?;
+    let count = request.params["count"].as_usize().unwrap_or(3);
+    let routes = graph::compute_alternate_routes(
+        &from_address,
+        &to_address,
+        edges,
+        value,
+        request.params["max_hops"].as_u64(),
+        request.params["max_transfers"].as_u64(),
+        count,
+        cancellation,
+    )?;
+    Ok(routes
+        .into_iter()
+        .map(|route| {
+            json::object! {
+                maxFlowValue: units.format(route.flow),
+                hops: route.hops(),
+                tokens: route.token_count(),
+                transferSteps: transfers_to_json(route.transfers, edges, units),
+            }
+        })
+        .collect::<Vec<_>>()
+        .into())
 }
-impl Display for InputValidationError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Error: {}", self.0)
+
+fn is_transfer_possible(
+    request: &JsonRpcRequest,
+    edges: &EdgeDB,
+    cancellation: &Cancellation,
+) -> Result<bool, Box<dyn Error>> {
+    let units = parse_units_param(&request.params)?;
+    if request.params["value"].is_null() {
+        return Err(Box::new(PathfinderError::Rpc("Missing value.".to_string())));
     }
+    let value = parse_transfer_value(&request.params, units)?;
+    let (from_address, to_address) = parse_transfer_endpoints(&request.params)?;
+    Ok(graph::is_transfer_possible(
+        &from_address,
+        &to_address,
+        edges,
+        value,
+        cancellation,
+    )?)
 }
 
-fn validate_and_parse_ethereum_address(address: &str) -> Result<Address, Box<dyn Error>> {
-    let re = Regex::new(r"^0x[0-9a-fA-F]{40}$").unwrap();
-    if re.is_match(address) {
-        Ok(Address::from(address))
-    } else {
-        Err(Box::new(InputValidationError(format!(
-            "Invalid Ethereum address: {}",
-            address
-        ))))
+fn bottleneck_to_json(bottleneck: Bottleneck, units: Units) -> JsonValue {
+    match bottleneck {
+        Bottleneck::Balance {
+            account,
+            token,
+            capacity,
+        } => json::object! {
+            type: "balance",
+            from: account.to_checksummed_hex(),
+            token_owner: token.to_checksummed_hex(),
+            capacity: units.format(capacity),
+        },
+        Bottleneck::Edge(edge) => json::object! {
+            type: "edge",
+            from: edge.from.to_checksummed_hex(),
+            to: edge.to.to_checksummed_hex(),
+            token_owner: edge.token.to_checksummed_hex(),
+            capacity: units.format(edge.capacity),
+        },
+        Bottleneck::Trust {
+            account,
+            token,
+            capacity,
+        } => json::object! {
+            type: "trust",
+            to: account.to_checksummed_hex(),
+            token_owner: token.to_checksummed_hex(),
+            capacity: units.format(capacity),
+        },
     }
 }
 
-fn validate_and_parse_u256(value_str: &str) -> Result<U256, Box<dyn Error>> {
-    match BigUint::from_str(value_str) {
-        Ok(parsed_value) => {
-            if parsed_value > U256::MAX.into() {
-                Err(Box::new(InputValidationError(format!(
-                    "Value {} is too large. Maximum value is {}.",
-                    parsed_value,
-                    U256::MAX
-                ))))
-            } else {
-                Ok(U256::from_bigint_truncating(parsed_value))
-            }
-        }
-        Err(e) => Err(Box::new(InputValidationError(format!(
-            "Invalid value: {}. Couldn't parse value: {}",
-            value_str, e
-        )))),
-    }
+fn transfers_to_json(transfers: Vec<Edge>, edges: &EdgeDB, units: Units) -> Vec<JsonValue> {
+    transfers
+        .into_iter()
+        .map(|e| {
+            json::object! {
+                type: edges.edge_kind(&e).as_str(),
+                from: e.from.to_checksummed_hex(),
+                to: e.to.to_checksummed_hex(),
+                token_owner: e.token.to_checksummed_hex(),
+                token_type: edges.token_type(&e.token).as_str(),
+                value: units.format(e.capacity),
+            }
+        })
+        .collect()
 }
 
-pub fn start_server(listen_at: &str, queue_size: usize, threads: u64) {
-    let edges: Arc<RwLock<Arc<EdgeDB>>> = Arc::new(RwLock::new(Arc::new(EdgeDB::default())));
+fn add_wrappers(
+    graph: &VersionedGraph,
+    wrappers: Vec<JsonValue>,
+) -> Result<Arc<Snapshot>, Box<dyn Error>> {
+    let changes = wrappers
+        .into_iter()
+        .map(|w| {
+            Ok(Change::Wrapper {
+                wrapper: validate_and_parse_ethereum_address(&w["wrapper"].to_string())?,
+                token: validate_and_parse_ethereum_address(&w["token_owner"].to_string())?,
+            })
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    Ok(graph.apply(&changes)?)
+}
 
-    let (sender, receiver) = mpsc::sync_channel(queue_size);
-    let protected_receiver = Arc::new(Mutex::new(receiver));
-    for _ in 0..threads {
-        let rec = protected_receiver.clone();
-        let e = edges.clone();
-        thread::spawn(move || loop {
-            let socket = rec.lock().unwrap().recv().unwrap();
-            if let Err(e) = handle_connection(e.deref(), socket) {
-                println!("Error handling connection: {e}");
+/// Applies a batch of edge updates, and removals `{from, to, token_owner, delete: true}`,
+/// all together under a new version. If any of them is invalid, none is applied.
+fn update_edges(
+    graph: &VersionedGraph,
+    updates: Vec<JsonValue>,
+) -> Result<Arc<Snapshot>, Box<dyn Error>> {
+    let changes = updates
+        .into_iter()
+        .map(|e| {
+            let delete = e["delete"].as_bool().unwrap_or_default();
+            let edge = Edge {
+                from: validate_and_parse_ethereum_address(&e["from"].to_string())?,
+                to: validate_and_parse_ethereum_address(&e["to"].to_string())?,
+                token: validate_and_parse_ethereum_address(&e["token_owner"].to_string())?,
+                capacity: match delete {
+                    true => U256::from(0u128),
+                    false => validate_and_parse_u256(&e["capacity"].to_string())?,
+                },
+            };
+            if delete {
+                return Ok(Change::Remove { edge });
             }
-        });
+            let token_type = match e["token_type"].as_str() {
+                Some(token_type) => Some(token_type.parse::<TokenType>()?),
+                None => None,
+            };
+            Ok(Change::Update {
+                edge,
+                block_number: e["block_number"].as_u64(),
+                token_type,
+            })
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    if changes.is_empty() {
+        return Ok(graph.snapshot());
     }
-    let listener = TcpListener::bind(listen_at).expect("Could not create server.");
-    loop {
-        match listener.accept() {
-            Ok((socket, _)) => match sender.try_send(socket) {
-                Ok(()) => {}
-                Err(TrySendError::Full(mut socket)) => {
-                    let _ = socket.write_all(b"HTTP/1.1 503 Service Unavailable\r\n\r\n");
-                }
-                Err(TrySendError::Disconnected(_)) => {
-                    panic!("Internal communication channel disconnected.");
+    Ok(graph.apply(&changes)?)
+}
+
+/// Sets the balances `{holder, token_owner, balance}` or changes them by a signed
+/// `{holder, token_owner, delta}`, together with the capacities derived from them,
+/// see `balances::balance_changes`.
+fn update_balances(
+    graph: &VersionedGraph,
+    updates: &JsonValue,
+) -> Result<Arc<Snapshot>, Box<dyn Error>> {
+    if !updates.is_array() {
+        return Err("Expected the parameter updates as an array.".into());
+    }
+    let current = graph.snapshot();
+    let mut edges = current.edges.as_ref().clone();
+    let mut changes = vec![];
+    for update in updates.members() {
+        let holder = validate_and_parse_ethereum_address(&update["holder"].to_string())?;
+        let token = validate_and_parse_ethereum_address(&update["token_owner"].to_string())?;
+        let amount = match (update["balance"].as_str(), update["delta"].as_str()) {
+            (Some(balance), None) => validate_and_parse_u256(balance)?,
+            (None, Some(delta)) => {
+                let balance = match edges.balances().is_empty() {
+                    false => edges.balances().balance(&holder, &token),
+                    // Without balances, the edge back to the owner has the balance.
+                    true => edges
+                        .outgoing(&holder)
+                        .into_iter()
+                        .find(|e| e.to == token && e.token == token)
+                        .map(|e| e.capacity)
+                        .unwrap_or_default(),
+                };
+                match delta.strip_prefix('-') {
+                    Some(decrease) => {
+                        let decrease = validate_and_parse_u256(decrease)?;
+                        if decrease > balance {
+                            return Err(format!(
+                                "The balance of {} in the tokens of {} would be negative.",
+                                holder.to_checksummed_hex(),
+                                token.to_checksummed_hex()
+                            )
+                            .into());
+                        }
+                        balance - decrease
+                    }
+                    None => balance.saturating_add(validate_and_parse_u256(delta)?),
                 }
-            },
-            Err(e) => println!("Error accepting connection: {e}"),
+            }
+            _ => return Err("Expected either balance or delta as a string.".into()),
+        };
+        // Later updates of the same balance build on the earlier ones.
+        for change in balances::balance_changes(holder, token, amount) {
+            change.apply(&mut edges);
+            changes.push(change);
         }
     }
+    if changes.is_empty() {
+        return Ok(current);
+    }
+    Ok(graph.apply(&changes)?)
 }
 
-fn handle_connection(
-    edges: &RwLock<Arc<EdgeDB>>,
-    mut socket: TcpStream,
-) -> Result<(), Box<dyn Error>> {
-    let request = read_request(&mut socket)?;
-    match request.method.as_str() {
-        "load_edges_binary" => {
-            let response = match load_edges_binary(edges, &request.params["file"].to_string()) {
-                Ok(len) => jsonrpc_response(request.id, len),
-                Err(e) => {
-                    jsonrpc_error_response(request.id, -32000, &format!("Error loading edges: {e}"))
-                }
-            };
-            socket.write_all(response.as_bytes())?;
-        }
-        "load_edges_csv" => {
-            let response = match load_edges_csv(edges, &request.params["file"].to_string()) {
-                Ok(len) => jsonrpc_response(request.id, len),
-                Err(e) => {
-                    jsonrpc_error_response(request.id, -32000, &format!("Error loading edges: {e}"))
-                }
-            };
-            socket.write_all(response.as_bytes())?;
-        }
-        "load_safes_binary" => {
-            let response = match load_safes_binary(edges, &request.params["file"].to_string()) {
-                Ok(len) => jsonrpc_response(request.id, len),
-                Err(e) => {
-                    jsonrpc_error_response(request.id, -32000, &format!("Error loading edges: {e}"))
-                }
-            };
-            socket.write_all(response.as_bytes())?;
-        }
-        "compute_transfer" => {
-            println!("Computing flow");
-            let e = edges.read().unwrap().clone();
-            compute_transfer(request, e.as_ref(), socket)?;
-        }
-        "update_edges" => {
-            let response = match request.params {
-                JsonValue::Array(updates) => match update_edges(edges, updates) {
-                    Ok(len) => jsonrpc_response(request.id, len),
-                    Err(e) => jsonrpc_error_response(
-                        request.id,
-                        -32000,
-                        &format!("Error updating edges: {e}"),
-                    ),
-                },
-                _ => {
-                    jsonrpc_error_response(request.id, -32602, "Invalid arguments: Expected array.")
-                }
+/// Sets the trust limit percentages of the trust relations in a CSV file.
+/// Their capacities are computed from the balances, see `load_safes_binary`.
+fn import_trust_csv(graph: &VersionedGraph, file: &str) -> Result<Arc<Snapshot>, Box<dyn Error>> {
+    let changes = read_trust_csv(file)?
+        .into_iter()
+        .map(|(truster, trustee, limit)| Change::LimitPercentage {
+            edge: Edge {
+                from: trustee,
+                to: truster,
+                token: trustee,
+                capacity: U256::from(0u128),
+            },
+            percentage: limit,
+            block_number: None,
+        })
+        .collect::<Vec<_>>();
+    Ok(graph.apply(&changes)?)
+}
+
+/// Applies changes of trust limits `{truster, trustee, limit, block_number}` in the
+/// order of their blocks. A change at an older block than the last change of the same
+/// trust relation is ignored, so events can be replayed. Returns the number of applied
+/// and ignored changes.
+fn apply_trust_events(
+    graph: &VersionedGraph,
+    events: &JsonValue,
+) -> Result<(usize, usize, Arc<Snapshot>), Box<dyn Error>> {
+    if !events.is_array() {
+        return Err("Expected the parameter events as an array.".into());
+    }
+    let mut events = events
+        .members()
+        .map(|e| {
+            let trustee = validate_and_parse_ethereum_address(&e["trustee"].to_string())?;
+            let edge = Edge {
+                from: trustee,
+                to: validate_and_parse_ethereum_address(&e["truster"].to_string())?,
+                token: trustee,
+                capacity: U256::from(0u128),
             };
-            socket.write_all(response.as_bytes())?;
-        }
-        _ => socket
-            .write_all(jsonrpc_error_response(request.id, -32601, "Method not found").as_bytes())?,
-    };
-    Ok(())
+            let limit = e["limit"]
+                .as_u8()
+                .filter(|limit| *limit <= 100)
+                .ok_or_else(|| format!("Invalid limit: {}", e["limit"]))?;
+            let block_number = e["block_number"]
+                .as_u64()
+                .ok_or_else(|| format!("Invalid block_number: {}", e["block_number"]))?;
+            Ok((edge, limit, block_number))
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    events.sort_by_key(|(_, _, block_number)| *block_number);
+    let count = events.len();
+    let current = graph.snapshot();
+    let changes = events
+        .into_iter()
+        .filter(|(edge, _, block_number)| {
+            current
+                .edges
+                .updated_at(edge)
+                .is_none_or(|at| at <= *block_number)
+        })
+        .map(|(edge, percentage, block_number)| Change::LimitPercentage {
+            edge,
+            percentage,
+            block_number: Some(block_number),
+        })
+        .collect::<Vec<_>>();
+    let ignored = count - changes.len();
+    if changes.is_empty() {
+        return Ok((0, ignored, current));
+    }
+    Ok((changes.len(), ignored, graph.apply(&changes)?))
 }
 
-fn load_edges_binary(edges: &RwLock<Arc<EdgeDB>>, file: &String) -> Result<usize, Box<dyn Error>> {
-    let updated_edges = read_edges_binary(file)?;
-    let len = updated_edges.edge_count();
-    *edges.write().unwrap() = Arc::new(updated_edges);
-    Ok(len)
+/// Writes the edges with a non-zero capacity, or only those in the neighborhood
+/// of `address`, as a graph for visualization and returns their number.
+fn export_graph(network: &Network, params: &JsonValue) -> Result<usize, Box<dyn Error>> {
+    let file = file_param(params)?;
+    let format = match params["format"].as_str() {
+        Some(format) => format.parse()?,
+        None => GraphFormat::from_path(file)
+            .ok_or("Unknown graph format, expected the parameter format (graphml or dot).")?,
+    };
+    let current = current_snapshot(network).edges.clone();
+    let exported = if params.has_key("address") {
+        let address = validate_and_parse_ethereum_address(&params["address"].to_string())?;
+        current.neighborhood(&address, params["hops"].as_u64().unwrap_or(1))
+    } else {
+        current
+            .effective_edges()
+            .into_iter()
+            .filter(|e| e.capacity != U256::from(0u128))
+            .collect()
+    };
+    write_graph(&exported, format, file)?;
+    Ok(exported.len())
 }
 
-fn load_edges_csv(edges: &RwLock<Arc<EdgeDB>>, file: &String) -> Result<usize, Box<dyn Error>> {
-    let updated_edges = read_edges_csv(file)?;
-    let len = updated_edges.edge_count();
-    *edges.write().unwrap() = Arc::new(updated_edges);
-    Ok(len)
+/// The number of accounts with the most changes returned by `diff_edges` by default.
+const DIFF_ACCOUNT_LIMIT: usize = 100;
+
+/// Compares the edges in the file `new` with those in the file `old`,
+/// or with the current edges if `old` is not given.
+fn diff_edges(network: &Network, params: &JsonValue) -> Result<JsonValue, Box<dyn Error>> {
+    let old = match params["old"].as_str() {
+        Some(file) => Arc::new(read_edges_file(file)?),
+        None => current_snapshot(network).edges.clone(),
+    };
+    let new = read_edges_file(
+        params["new"]
+            .as_str()
+            .ok_or("Expected the parameter new.")?,
+    )?;
+    let limit = params["limit"].as_usize().unwrap_or(DIFF_ACCOUNT_LIMIT);
+    Ok(diff::diff(&old, &new).to_json(limit))
 }
 
-fn load_safes_binary(edges: &RwLock<Arc<EdgeDB>>, file: &str) -> Result<usize, Box<dyn Error>> {
-    let updated_edges = import_from_safes_binary(file)?.edges().clone();
-    let len = updated_edges.edge_count();
-    *edges.write().unwrap() = Arc::new(updated_edges);
-    Ok(len)
+/// The number of hubs returned by `graph_stats` by default.
+const DEFAULT_HUBS: usize = 10;
+
+/// The statistics of the current edges, computed once per version.
+fn graph_stats(network: &Network, params: &JsonValue) -> Result<JsonValue, Box<dyn Error>> {
+    let hubs = parse_bounded_param(params, "hubs", DEFAULT_HUBS, MAX_HUBS)?;
+    let snapshot = pinned_snapshot(network, params)?;
+    Ok(with_version(snapshot.stats().to_json(hubs), &snapshot))
 }
 
-fn compute_transfer(
-    request: JsonRpcRequest,
-    edges: &EdgeDB,
-    mut socket: TcpStream,
-) -> Result<(), Box<dyn Error>> {
-    socket.write_all(chunked_header().as_bytes())?;
+/// The limits of the parameters of `get_neighborhood`.
+const MAX_NEIGHBORHOOD_DEPTH: usize = 3;
+const DEFAULT_NEIGHBORS_PER_HOP: usize = 50;
+const MAX_NEIGHBORS_PER_HOP: usize = 1000;
 
-    let parsed_value_param = match request.params["value"].as_str() {
-        Some(value_str) => validate_and_parse_u256(value_str)?,
-        None => U256::MAX,
+/// The edges between the accounts at most `depth` hops away from `address`, with at most
+/// `max_per_hop` accounts added per hop, see `EdgeDB::bounded_neighborhood`.
+fn get_neighborhood(network: &Network, params: &JsonValue) -> Result<JsonValue, Box<dyn Error>> {
+    let units = parse_units_param(params)?;
+    let address = validate_and_parse_ethereum_address(&params["address"].to_string())?;
+    let depth = parse_bounded_param(params, "depth", 1, MAX_NEIGHBORHOOD_DEPTH)?;
+    let max_per_hop = parse_bounded_param(
+        params,
+        "max_per_hop",
+        DEFAULT_NEIGHBORS_PER_HOP,
+        MAX_NEIGHBORS_PER_HOP,
+    )?;
+    let snapshot = pinned_snapshot(network, params)?;
+    let (edges, truncated) =
+        snapshot
+            .edges
+            .bounded_neighborhood(&address, depth as u64, max_per_hop);
+    let accounts = edges
+        .iter()
+        .flat_map(|e| [e.from, e.to])
+        .chain([address])
+        .collect::<BTreeSet<_>>();
+    let result = json::object! {
+        address: address.to_checksummed_hex(),
+        depth: depth,
+        accounts: accounts.iter().map(|a| a.to_checksummed_hex()).collect::<Vec<_>>(),
+        edges: edges.iter().map(|e| json::object! {
+            from: e.from.to_checksummed_hex(),
+            to: e.to.to_checksummed_hex(),
+            token_owner: e.token.to_checksummed_hex(),
+            capacity: units.format(e.capacity),
+        }).collect::<Vec<_>>(),
+        truncated: truncated,
     };
+    Ok(with_version(result, &snapshot))
+}
 
-    let from_address = validate_and_parse_ethereum_address(&request.params["from"].to_string())?;
-    let to_address = validate_and_parse_ethereum_address(&request.params["to"].to_string())?;
+/// The limits of the parameter `max_hops` of `find_trust_path`.
+const DEFAULT_TRUST_PATH_HOPS: usize = 6;
+const MAX_TRUST_PATH_HOPS: usize = 20;
 
-    let max_distances = if request.params["iterative"].as_bool().unwrap_or_default() {
-        vec![Some(1), Some(2), None]
-    } else {
-        vec![None]
+/// The shortest trust path from `from` to `to`, ignoring balances and capacities,
+/// see `EdgeDB::trust_path`.
+fn find_trust_path(network: &Network, params: &JsonValue) -> Result<JsonValue, Box<dyn Error>> {
+    let from = validate_and_parse_ethereum_address(&params["from"].to_string())?;
+    let to = validate_and_parse_ethereum_address(&params["to"].to_string())?;
+    let max_hops = parse_bounded_param(
+        params,
+        "max_hops",
+        DEFAULT_TRUST_PATH_HOPS,
+        MAX_TRUST_PATH_HOPS,
+    )?;
+    let snapshot = pinned_snapshot(network, params)?;
+    let path = snapshot.edges.trust_path(&from, &to, max_hops as u64);
+    let result = json::object! {
+        exists: path.is_some(),
+        hops: path.as_ref().map(|path| path.len() - 1),
+        path: path.map(|path| {
+            path.iter()
+                .map(|a| a.to_checksummed_hex())
+                .collect::<Vec<_>>()
+        }),
     };
-
-    let max_transfers = request.params["max_transfers"].as_u64();
-    for max_distance in max_distances {
-        let (flow, transfers) = graph::compute_flow(
-            &from_address,
-            &to_address,
-            edges,
-            parsed_value_param,
-            max_distance,
-            max_transfers,
-        );
-        println!("Computed flow with max distance {max_distance:?}: {flow}");
-        socket.write_all(
-            chunked_response(
-                &(jsonrpc_result(
-                    request.id.clone(),
-                    json::object! {
-                        maxFlowValue: flow.to_decimal(),
-                        final: max_distance.is_none(),
-                        transferSteps: transfers.into_iter().map(|e| json::object! {
-                            from: e.from.to_checksummed_hex(),
-                            to: e.to.to_checksummed_hex(),
-                            token_owner: e.token.to_checksummed_hex(),
-                            value: e.capacity.to_decimal(),
-                        }).collect::<Vec<_>>(),
-                    },
-                ) + "\r\n"),
-            )
-            .as_bytes(),
-        )?;
-    }
-    socket.write_all(chunked_close().as_bytes())?;
-    Ok(())
+    Ok(with_version(result, &snapshot))
 }
 
-fn update_edges(
-    edges: &RwLock<Arc<EdgeDB>>,
-    updates: Vec<JsonValue>,
-) -> Result<usize, Box<dyn Error>> {
-    let updates = updates
-        .into_iter()
-        .map(|e| Edge {
-            from: Address::from(e["from"].to_string().as_str()),
-            to: Address::from(e["to"].to_string().as_str()),
-            token: Address::from(e["token_owner"].to_string().as_str()),
-            capacity: U256::from(e["capacity"].to_string().as_str()),
-        })
-        .collect::<Vec<_>>();
-    if updates.is_empty() {
-        return Ok(edges.read().unwrap().edge_count());
-    }
-
-    let mut updating_edges = edges.read().unwrap().as_ref().clone();
-    for update in updates {
-        updating_edges.update(update);
+/// Checks the transfer steps `transfers` from `from` to `to` against the current edges,
+/// see `graph::verify_flow`.
+fn verify_flow(network: &Network, params: &JsonValue) -> Result<JsonValue, Box<dyn Error>> {
+    let units = parse_units_param(params)?;
+    let from = validate_and_parse_ethereum_address(&params["from"].to_string())?;
+    let to = validate_and_parse_ethereum_address(&params["to"].to_string())?;
+    // Unlike in transfers, values cannot be omitted.
+    let parse_value = |value: &JsonValue| -> Result<U256, Box<dyn Error>> {
+        match value.is_null() {
+            true => Err(PathfinderError::Rpc("Missing value.".to_string()).into()),
+            false => parse_value_param(value, units),
+        }
+    };
+    let value = parse_value(&params["value"])?;
+    if !params["transfers"].is_array() {
+        return Err(PathfinderError::Rpc("Expected an array of transfers.".to_string()).into());
     }
-    let len = updating_edges.edge_count();
-    *edges.write().unwrap() = Arc::new(updating_edges);
-    Ok(len)
+    let transfers = params["transfers"]
+        .members()
+        .map(|t| {
+            Ok(Edge {
+                from: validate_and_parse_ethereum_address(&t["from"].to_string())?,
+                to: validate_and_parse_ethereum_address(&t["to"].to_string())?,
+                token: validate_and_parse_ethereum_address(&t["token_owner"].to_string())?,
+                capacity: parse_value(&t["value"])?,
+            })
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    let snapshot = pinned_snapshot(network, params)?;
+    let error = graph::verify_flow(&transfers, &from, &to, value, &snapshot.edges).err();
+    let result = json::object! {
+        valid: error.is_none(),
+        error: error.map(|e| e.to_string()),
+    };
+    Ok(with_version(result, &snapshot))
 }
 
-fn read_request(socket: &mut TcpStream) -> Result<JsonRpcRequest, Box<dyn Error>> {
-    let payload = read_payload(socket)?;
-    let mut request = json::parse(&String::from_utf8(payload)?)?;
-    println!("Request: {request}");
+fn parse_request(mut request: JsonValue) -> Result<JsonRpcRequest, Box<dyn Error>> {
     let id = request["id"].take();
     let params = request["params"].take();
     match request["method"].as_str() {
@@ -274,50 +2654,140 @@ fn read_request(socket: &mut TcpStream) -> Result<JsonRpcRequest, Box<dyn Error>
             method: method.to_string(),
             params,
         }),
-        _ => Err(From::from("Invalid JSON-RPC request: {request}")),
+        _ => Err(From::from(format!("Invalid JSON-RPC request: {request}"))),
     }
 }
 
-fn read_payload(socket: &mut TcpStream) -> Result<Vec<u8>, Box<dyn Error>> {
+fn parse_body(body: Vec<u8>) -> Result<JsonValue, Box<dyn Error>> {
+    Ok(json::parse(&String::from_utf8(body)?)?)
+}
+
+/// Decodes an HTTP request into its JSON-RPC calls the way a connection does, leaving
+/// out invalid calls of a batch. Malformed input results in an error.
+pub fn decode_request(mut input: impl Read) -> Result<Vec<JsonRpcRequest>, Box<dyn Error>> {
+    let request = read_http_request(&mut input)?;
+    match parse_body(request.body)? {
+        JsonValue::Array(calls) => Ok(calls
+            .into_iter()
+            .filter_map(|call| parse_request(call).ok())
+            .collect()),
+        request => Ok(vec![parse_request(request)?]),
+    }
+}
+
+/// The maximum length of the body of an HTTP request.
+const MAX_REQUEST_LENGTH: usize = 64 * 1024 * 1024;
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    /// Header values by lowercase header name.
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+fn read_http_request(socket: &mut impl Read) -> Result<HttpRequest, Box<dyn Error>> {
     let mut reader = BufReader::new(socket);
-    let mut length = 0;
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The protocol version is ignored.
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("POST").to_string();
+    let path = parts.next().unwrap_or("/").to_string();
+    let mut headers = HashMap::new();
     for result in reader.by_ref().lines() {
         let l = result?;
         if l.is_empty() {
             break;
         }
+        if let Some((name, value)) = l.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+    let length = match headers.get("content-length") {
+        Some(length) => length.parse::<usize>()?,
+        None => 0,
+    };
+    if length > MAX_REQUEST_LENGTH {
+        return Err(
+            format!("Request body of {length} bytes exceeds {MAX_REQUEST_LENGTH} bytes").into(),
+        );
+    }
+    let mut body = vec![0u8; length];
+
+    reader.read_exact(body.as_mut_slice())?;
+    Ok(HttpRequest {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
 
-        let header = "content-length: ";
-        if l.to_lowercase().starts_with(header) {
-            length = l[header.len()..].parse::<usize>()?;
+/// Adds headers (each terminated by CRLF) after the status line of `response`.
+fn with_headers(response: String, headers: &str) -> String {
+    match response.split_once("\r\n") {
+        Some((status_line, rest)) if !headers.is_empty() => {
+            format!("{status_line}\r\n{headers}{rest}")
         }
+        _ => response,
     }
-    let mut payload = vec![0u8; length];
+}
 
-    reader.read_exact(payload.as_mut_slice())?;
-    Ok(payload)
+fn http_response(payload: &str) -> String {
+    http_response_with_status("200 OK", payload)
 }
 
-fn jsonrpc_response(id: JsonValue, result: impl Into<json::JsonValue>) -> String {
-    let payload = jsonrpc_result(id, result);
+fn http_response_with_status(status: &str, payload: &str) -> String {
     format!(
-        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+        "HTTP/1.1 {status}\r\nContent-Length: {}\r\n\r\n{}",
         payload.len(),
         payload
     )
 }
 
 fn jsonrpc_result(id: JsonValue, result: impl Into<json::JsonValue>) -> String {
+    jsonrpc_result_object(id, result).dump()
+}
+
+fn jsonrpc_result_object(id: JsonValue, result: impl Into<json::JsonValue>) -> JsonValue {
     json::object! {
         jsonrpc: "2.0",
         id: id,
         result: result.into(),
     }
-    .dump()
 }
 
-fn jsonrpc_error_response(id: JsonValue, code: i64, message: &str) -> String {
-    let payload = json::object! {
+/// Whether `response` is an error response to the call with the given id.
+fn is_error_response(response: &str, id: JsonValue) -> bool {
+    // Responses are serialized with the keys in the order of `jsonrpc_error`.
+    response.starts_with(&format!(
+        "{{\"jsonrpc\":\"2.0\",\"id\":{},\"error\":",
+        id.dump()
+    ))
+}
+
+const TOO_MANY_REQUESTS: &str = "429 Too Many Requests";
+
+/// The JSON-RPC error for a flow computation that was rejected or failed to run.
+fn job_error(id: JsonValue, error: JobError) -> String {
+    match error {
+        JobError::QueueFull => jsonrpc_error(id, -32005, &error.to_string()),
+        JobError::Aborted => jsonrpc_error(id, -32603, "Internal error"),
+    }
+}
+
+/// The JSON-RPC error for a failed call, with the code of the error, see `error_code`.
+fn jsonrpc_error_for(id: JsonValue, context: &str, error: &(dyn Error + 'static)) -> String {
+    jsonrpc_error(id, error_code(error), &format!("{context}: {error}"))
+}
+
+fn jsonrpc_error(id: JsonValue, code: i64, message: &str) -> String {
+    jsonrpc_error_object(id, code, message).dump()
+}
+
+fn jsonrpc_error_object(id: JsonValue, code: i64, message: &str) -> JsonValue {
+    json::object! {
         jsonrpc: "2.0",
         id: id,
         error: {
@@ -325,26 +2795,565 @@ fn jsonrpc_error_response(id: JsonValue, code: i64, message: &str) -> String {
             message: message
         }
     }
-    .dump();
-    format!(
-        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
-        payload.len(),
-        payload
-    )
 }
 
 fn chunked_header() -> String {
     "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n".to_string()
 }
 
-fn chunked_response(data: &str) -> String {
-    if data.is_empty() {
-        String::new()
-    } else {
-        format!("{:x}\r\n{}\r\n", data.len(), data)
+fn chunked_close() -> String {
+    "0\r\n\r\n".to_string()
+}
+
+/// The maximum size of a chunk written by `ChunkedWriter`.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Writes data as HTTP chunks of at most `CHUNK_SIZE` bytes, so that a large
+/// response is sent while it is serialized instead of being built in memory first.
+struct ChunkedWriter<W: Write> {
+    inner: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    fn new(inner: W) -> ChunkedWriter<W> {
+        ChunkedWriter {
+            inner,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    fn write_chunk(&mut self) -> std::io::Result<()> {
+        if !self.buffer.is_empty() {
+            write!(self.inner, "{:x}\r\n", self.buffer.len())?;
+            self.inner.write_all(&self.buffer)?;
+            self.inner.write_all(b"\r\n")?;
+            self.buffer.clear();
+        }
+        Ok(())
     }
 }
 
-fn chunked_close() -> String {
-    "0\r\n\r\n".to_string()
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let length = buf.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..length]);
+        if self.buffer.len() == CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.write_chunk()?;
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::edge::edge;
+    use crate::types::Edge;
+    use std::sync::mpsc;
+
+    fn address(i: u8) -> String {
+        Address::from([i; 20]).to_string()
+    }
+
+    /// A server for the edges from 1 to 3 through 2 and through 10 to 49 (capacity 10 each),
+    /// so that searches from 1 explore levels of more than one account.
+    fn local_server() -> LocalServer {
+        local_server_with(Config::default())
+    }
+
+    fn local_server_with(config: Config) -> LocalServer {
+        let mut edges = vec![
+            edge([1; 20], [2; 20], [1; 20], 10),
+            edge([2; 20], [3; 20], [2; 20], 10),
+        ];
+        for i in 10..50 {
+            edges.extend([
+                edge([1; 20], [i; 20], [1; 20], 10),
+                edge([i; 20], [3; 20], [i; 20], 10),
+            ]);
+        }
+        LocalServer::new(config, EdgeDB::new(edges)).unwrap()
+    }
+
+    fn transfer(id: usize, value: &str) -> JsonValue {
+        json::object! {
+            jsonrpc: "2.0",
+            id: id,
+            method: "compute_transfer",
+            params: { from: address(1), to: address(3), value: value, algorithm: "dinic" },
+        }
+    }
+
+    #[test]
+    fn batch() {
+        let local = local_server();
+        let notification = json::object! {
+            jsonrpc: "2.0",
+            method: "is_transfer_possible",
+            params: { from: address(1), to: address(3), value: "1" },
+        };
+        let calls = vec![
+            transfer(1, "5"),
+            json::object! { jsonrpc: "2.0", id: 2, method: "compute_everything", params: {} },
+            notification.clone(),
+            JsonValue::from(5),
+            transfer(3, "7"),
+        ];
+        let responses = json::parse(&handle_batch(&local.server, None, calls).unwrap()).unwrap();
+        // The responses keep the order of the calls, without the notification.
+        assert_eq!(responses.len(), 4);
+        assert_eq!(responses[0]["id"], 1);
+        assert_eq!(responses[0]["result"]["maxFlowValue"], "5");
+        assert_eq!(responses[1]["id"], 2);
+        assert_eq!(responses[1]["error"]["code"], -32601);
+        assert!(responses[2]["id"].is_null());
+        assert_eq!(responses[2]["error"]["code"], -32600);
+        assert_eq!(responses[3]["id"], 3);
+        assert_eq!(responses[3]["result"]["maxFlowValue"], "7");
+
+        let empty = json::parse(&handle_batch(&local.server, None, vec![]).unwrap()).unwrap();
+        assert!(empty["id"].is_null());
+        assert_eq!(empty["error"]["code"], -32600);
+
+        let notifications = vec![notification.clone(), notification];
+        assert_eq!(handle_batch(&local.server, None, notifications), None);
+
+        let too_many = vec![transfer(1, "5"); MAX_BATCH_CALLS + 1];
+        let too_many = json::parse(&handle_batch(&local.server, None, too_many).unwrap()).unwrap();
+        assert_eq!(too_many["error"]["code"], -32007);
+        assert_eq!(
+            too_many["error"]["message"],
+            "The batch consists of 101 calls, more than the maximum of 100."
+        );
+    }
+
+    #[test]
+    fn transfer_validation() {
+        let local = local_server();
+        let error = |from: &str, to: &str, value: &str| {
+            let params = json::object! { from: from, to: to, value: value };
+            local.call("compute_transfer", params)["error"].clone()
+        };
+        let same_account = error(&address(1), &address(1), "5");
+        assert_eq!(same_account["code"], -32011);
+        assert_eq!(
+            same_account["message"],
+            format!(
+                "Error computing transfer: The source and the sink are the same account {}.",
+                Address::from([1; 20]).to_checksummed_hex()
+            )
+        );
+        let zero = error(&address(1), &address(3), "0");
+        assert_eq!(zero["code"], -32012);
+        assert_eq!(
+            zero["message"],
+            "Error computing transfer: The value is zero."
+        );
+        let malformed = error(&address(1), &address(3), "5x");
+        assert_eq!(malformed["code"], -32602);
+        assert!(malformed["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Error computing transfer: Invalid value: 5x."));
+        let address_error = error("0x12", &address(3), "5");
+        assert_eq!(address_error["code"], -32010);
+        assert_eq!(
+            address_error["message"],
+            "Error computing transfer: Invalid address: 0x12"
+        );
+
+        for (name, limit) in [
+            ("max_hops", JsonValue::from(-1)),
+            ("timeout_ms", JsonValue::from(1.5)),
+            ("max_transfers", JsonValue::from("3")),
+        ] {
+            let mut params = json::object! { from: address(1), to: address(3), value: "5" };
+            params[name] = limit;
+            let error = &local.call("compute_transfer", params)["error"];
+            assert_eq!(error["code"], -32602);
+            assert_eq!(
+                error["message"],
+                format!("Invalid params: The parameter {name} must be a non-negative integer.")
+            );
+        }
+
+        // The multi-sink and multi-source methods validate the same way.
+        let to_sinks = |to: &str, value: &str| {
+            let params = json::object! { from: address(1), sinks: [{ to: to, value: value }] };
+            local.call("compute_transfer_to_sinks", params)["error"]["code"].clone()
+        };
+        assert_eq!(to_sinks(&address(1), "5"), -32011);
+        assert_eq!(to_sinks(&address(3), "0"), -32012);
+        assert_eq!(to_sinks("0x12", "5"), -32010);
+        let from_sources = |from: &str, value: &str| {
+            let params = json::object! { sources: [from], to: address(3), value: value };
+            local.call("compute_transfer_from_sources", params)["error"]["code"].clone()
+        };
+        assert_eq!(from_sources(&address(3), "5"), -32011);
+        assert_eq!(from_sources(&address(1), "0"), -32012);
+        assert_eq!(from_sources("0x12", "5"), -32010);
+    }
+
+    #[test]
+    fn limits() {
+        let error = |config: Config, value: &str| {
+            let params = json::object! { from: address(1), to: address(3), value: value };
+            local_server_with(config).call("compute_transfer", params)["error"].clone()
+        };
+        let mut config = Config {
+            max_value: Some(U256::from(100u128)),
+            ..Config::default()
+        };
+        let exceeded = error(config.clone(), "101");
+        assert_eq!(exceeded["code"], -32602);
+        assert_eq!(
+            exceeded["message"],
+            "Invalid params: The value exceeds the maximum of the server: 100"
+        );
+        assert!(error(config.clone(), "100").is_null());
+
+        // 10% of the 43 accounts.
+        config.max_explored_percent = 10;
+        let explored = error(config.clone(), "20");
+        assert_eq!(explored["code"], -32007);
+        assert_eq!(
+            explored["message"],
+            "Error computing transfer: The search reached more than the maximum of 4 accounts."
+        );
+
+        config.max_explored_percent = 100;
+        config.max_decomposition_steps = Some(1);
+        let steps = error(config.clone(), "5");
+        assert_eq!(steps["code"], -32007);
+        assert_eq!(
+            steps["message"],
+            "Error computing transfer: The flow consists of 2 transfers, more than the maximum of 1."
+        );
+        config.max_decomposition_steps = Some(2);
+        assert!(error(config, "5").is_null());
+    }
+
+    #[test]
+    fn counts_all_methods() {
+        let local = local_server();
+        let description = schema::describe();
+        // Subscriptions are only handled on WebSocket connections.
+        let methods = description["methods"]
+            .members()
+            .map(|method| method["name"].as_str().unwrap())
+            .filter(|name| !["subscribe", "unsubscribe"].contains(name));
+        for method in methods {
+            let response = local.call(method, json::object! {});
+            assert_ne!(response["error"]["code"], -32601, "{method} is not handled");
+            assert!(METHODS.contains(&method), "{method} is not counted");
+        }
+        assert!(!local.server.metrics.render().contains("method=\"unknown\""));
+    }
+
+    #[test]
+    fn apply_trust_events() {
+        let local = local_server();
+        let event = |trustee: u8, truster: u8, limit: u8, block_number: u64| {
+            json::object! {
+                trustee: address(trustee),
+                truster: address(truster),
+                limit: limit,
+                block_number: block_number,
+            }
+        };
+        let apply = |events: Vec<JsonValue>| {
+            local.call("apply_trust_events", json::object! { events: events })
+        };
+        let trust = Edge {
+            from: Address::from([1; 20]),
+            to: Address::from([2; 20]),
+            token: Address::from([1; 20]),
+            capacity: U256::from(0u128),
+        };
+        let limit_percentage = || {
+            let edges = local.server.networks.main().graph.snapshot().edges.clone();
+            (edges.limit_percentage(&trust), edges.updated_at(&trust))
+        };
+
+        // The events are applied in the order of their blocks.
+        let applied = apply(vec![
+            event(1, 2, 50, 7),
+            event(1, 2, 30, 5),
+            event(4, 5, 100, 6),
+        ]);
+        assert_eq!(applied["result"]["applied"], 3);
+        assert_eq!(applied["result"]["ignored"], 0);
+        assert_eq!(limit_percentage(), (Some(50), Some(7)));
+
+        // Events older than the last update of an edge are ignored.
+        let stale = apply(vec![event(1, 2, 20, 6), event(1, 2, 0, 8)]);
+        assert_eq!(stale["result"]["applied"], 1);
+        assert_eq!(stale["result"]["ignored"], 1);
+        assert_eq!(limit_percentage(), (Some(0), Some(8)));
+
+        let invalid = apply(vec![event(1, 2, 101, 9)]);
+        assert_eq!(
+            invalid["error"]["message"],
+            "Error applying trust events: Invalid limit: 101"
+        );
+        assert_eq!(limit_percentage(), (Some(0), Some(8)));
+    }
+
+    #[test]
+    fn page() {
+        let steps = |offset: usize, limit: Option<usize>| {
+            let mut result = json::object! { transferSteps: [0, 1, 2] };
+            Page { offset, limit }.apply(&mut result);
+            assert_eq!(result["transferCount"], 3);
+            let steps = result["transferSteps"].members();
+            steps
+                .map(|step| step.as_usize().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(steps(0, None), vec![0, 1, 2]);
+        assert_eq!(steps(1, Some(1)), vec![1]);
+        assert_eq!(steps(2, None), vec![2]);
+        assert_eq!(steps(1, Some(5)), vec![1, 2]);
+        assert!(steps(3, None).is_empty());
+        assert!(steps(7, Some(1)).is_empty());
+        assert!(steps(0, Some(0)).is_empty());
+
+        // Results without transfer steps are left as they are.
+        let mut result = json::object! { maxFlowValue: "0" };
+        Page {
+            offset: 1,
+            limit: None,
+        }
+        .apply(&mut result);
+        assert_eq!(result, json::object! { maxFlowValue: "0" });
+
+        let page = |params: JsonValue| parse_page_params(&params).map_err(|e| e.to_string());
+        assert!(page(json::object! {}).unwrap().is_none());
+        let limited = page(json::object! { limit: 2 }).unwrap().unwrap();
+        assert_eq!((limited.offset, limited.limit), (0, Some(2)));
+        assert_eq!(
+            page(json::object! { offset: -1 }).unwrap_err(),
+            "Invalid offset: -1. Expected a non-negative integer."
+        );
+    }
+
+    #[test]
+    fn fast_queries() {
+        let fast = |method: &str, params: JsonValue| {
+            is_fast_query(&JsonRpcRequest {
+                id: 1.into(),
+                method: method.to_string(),
+                params,
+            })
+        };
+        let transfer =
+            |value: &str| json::object! { from: address(1), to: address(3), value: value };
+        assert!(fast("compute_transfer", transfer("100000000000000000000")));
+        assert!(!fast("compute_transfer", transfer("100000000000000000001")));
+        let mut circles = transfer("100");
+        circles["units"] = "circles".into();
+        assert!(fast("compute_transfer", circles.clone()));
+        circles["value"] = "100.000000000000000001".into();
+        assert!(!fast("compute_transfer", circles));
+        // Without a value, the maximum is transferred.
+        assert!(!fast(
+            "compute_transfer",
+            json::object! { from: address(1), to: address(3) }
+        ));
+        assert!(!fast("compute_transfer", transfer("5x")));
+        assert!(fast("is_transfer_possible", json::object! {}));
+        assert!(!fast("compute_transfer_from_sources", transfer("1")));
+    }
+
+    #[test]
+    fn request_span() {
+        struct Log(Arc<Mutex<Vec<u8>>>);
+        impl Write for Log {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let writer = log.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || Log(writer.clone()))
+            .with_ansi(false)
+            .with_span_events(FmtSpan::CLOSE)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let request = JsonRpcRequest {
+                id: 7.into(),
+                method: "compute_transfer".to_string(),
+                params: json::object! { from: address(1), to: address(3) },
+            };
+            let _span = super::request_span(&request);
+            record_queue_time(Instant::now());
+            tracing::info!("Computing.");
+        });
+        let log = String::from_utf8(log.lock().unwrap().clone()).unwrap();
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let span = format!(
+            "request{{id=7 method=compute_transfer from={} to={} queue_ms=0}}",
+            address(1),
+            address(3)
+        );
+        assert!(lines[0].ends_with(&format!("{span}: pathfinder2::server::test: Computing.")));
+        assert!(lines[1].contains(&format!("{span}: pathfinder2::server: close time.busy=")));
+    }
+
+    #[test]
+    fn get_neighborhood() {
+        let local = local_server();
+        let checksummed = |i: u8| Address::from([i; 20]).to_checksummed_hex();
+        let neighborhood = local.call(
+            "get_neighborhood",
+            json::object! { address: address(2), units: "circles" },
+        );
+        let result = &neighborhood["result"];
+        assert_eq!(result["address"], checksummed(2));
+        assert_eq!(result["depth"], 1);
+        let mut accounts = vec![checksummed(1), checksummed(2), checksummed(3)];
+        accounts.sort();
+        assert_eq!(result["accounts"], JsonValue::from(accounts));
+        assert_eq!(result["edges"].len(), 2);
+        assert_eq!(
+            result["edges"][1],
+            json::object! {
+                from: checksummed(2),
+                to: checksummed(3),
+                token_owner: checksummed(2),
+                capacity: "0.00000000000000001",
+            }
+        );
+        assert_eq!(result["truncated"], false);
+        assert!(result["graphVersion"].is_number());
+
+        // 1 has 41 neighbors, of which only 5 are kept.
+        let params = json::object! { address: address(1), max_per_hop: 5 };
+        let truncated = &local.call("get_neighborhood", params)["result"];
+        assert_eq!(truncated["edges"].len(), 5);
+        assert_eq!(truncated["accounts"].len(), 6);
+        assert_eq!(truncated["truncated"], true);
+
+        let params = json::object! { address: address(1), depth: 4 };
+        let error = &local.call("get_neighborhood", params)["error"];
+        assert_eq!(error["code"], -32602);
+        assert_eq!(
+            error["message"],
+            "Error computing neighborhood: Invalid depth: 4. Expected an integer from 0 to 3."
+        );
+    }
+
+    #[test]
+    fn file_parameter() {
+        let local = local_server();
+        for method in [
+            "load_edges_binary",
+            "load_edges_csv",
+            "load_edges_mmap",
+            "load_safes_binary",
+            "load_safes_json",
+            "import_trust_csv",
+            "export_edges_binary",
+            "export_edges_csv",
+            "export_edges_parquet",
+            "export_trust_csv",
+            "export_graph",
+        ] {
+            for params in [json::object! {}, json::object! { file: 5 }] {
+                let error = &local.call(method, params)["error"];
+                assert_eq!(error["code"], -32602, "{method}");
+                assert!(error["message"]
+                    .as_str()
+                    .unwrap()
+                    .ends_with(": Expected the parameter file."));
+            }
+        }
+    }
+
+    #[test]
+    fn export_edges_binary() {
+        let local = local_server();
+        let missing = local.call("export_edges_binary", json::object! {});
+        assert_eq!(
+            missing["error"]["message"],
+            "Error exporting edges: Expected the parameter file."
+        );
+
+        assert_eq!(download_query("/edges.dat"), Some(""));
+        assert_eq!(
+            download_query("/edges.dat?chain_id=100"),
+            Some("chain_id=100")
+        );
+        assert_eq!(download_query("/edges.dat.zst"), None);
+        let mut response = vec![];
+        serve_edges_download(&local.server, &mut response, "chain_id=100", "").unwrap();
+        let start = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let header = String::from_utf8(response[..start].to_vec()).unwrap();
+        assert!(header.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(header.contains("Transfer-Encoding: chunked\r\n"));
+        // Joins the chunks, each is its length in hex, the data and a line break.
+        let mut chunks = &response[start..];
+        let mut file = vec![];
+        loop {
+            let line_end = chunks.windows(2).position(|w| w == b"\r\n").unwrap();
+            let length = std::str::from_utf8(&chunks[..line_end]).unwrap();
+            let length = usize::from_str_radix(length, 16).unwrap();
+            chunks = &chunks[line_end + 2..];
+            if length == 0 {
+                break;
+            }
+            file.extend_from_slice(&chunks[..length]);
+            chunks = &chunks[length + 2..];
+        }
+        assert_eq!(chunks, b"\r\n");
+        let downloaded = crate::io::read_edges_binary_from(&file[..]).unwrap();
+        let mut edges = local
+            .server
+            .networks
+            .main()
+            .graph
+            .snapshot()
+            .edges
+            .edges()
+            .to_vec();
+        edges.sort();
+        assert_eq!(downloaded.edges(), edges);
+
+        let mut response = vec![];
+        serve_edges_download(&local.server, &mut response, "chain_id=5", "").unwrap();
+        assert!(response.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn batch_of_network_algorithm_calls() {
+        let local = local_server();
+        // More flow calls than rayon threads, which the flow workers need, and than
+        // batch threads.
+        let count = rayon::current_num_threads().max(BATCH_THREADS) + 2;
+        let calls = (0..count).map(|id| transfer(id, "5")).collect::<Vec<_>>();
+        let (sender, receiver) = mpsc::channel();
+        let server = local.server.clone();
+        thread::spawn(move || sender.send(handle_batch(&server, None, calls)));
+        let responses = receiver
+            .recv_timeout(Duration::from_secs(60))
+            .expect("The batch was not answered.")
+            .unwrap();
+        let responses = json::parse(&responses).unwrap();
+        assert_eq!(responses.len(), count);
+        for response in responses.members() {
+            assert_eq!(response["result"]["maxFlowValue"], "5", "{response}");
+        }
+    }
 }
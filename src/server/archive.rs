@@ -0,0 +1,139 @@
+//! An archive of the loaded edges by block number, so that queries can run on the
+//! graph as it was at an earlier block, see the parameter `at_block`.
+//!
+//! Every load of edges with a known block number is written to the archive directory
+//! as `edges-<block number>.dat.zst`, a binary edge file compressed with zstd. Like any
+//! binary edge file, it contains the capacities at the time, not trust limit
+//! percentages or balances.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use super::snapshot::Snapshot;
+use crate::io::{read_edges_binary, write_edges_binary};
+use crate::types::edge::EdgeDB;
+
+const PREFIX: &str = "edges-";
+const SUFFIX: &str = ".dat.zst";
+
+pub struct Archive {
+    directory: PathBuf,
+    /// The snapshot read last, with its block number. Queries of a workflow usually
+    /// ask for the same block.
+    last_read: Mutex<Option<(u64, Arc<Snapshot>)>>,
+}
+
+impl Archive {
+    pub fn new(directory: &str) -> Result<Archive, io::Error> {
+        fs::create_dir_all(directory)?;
+        Ok(Archive {
+            directory: PathBuf::from(directory),
+            last_read: Mutex::new(None),
+        })
+    }
+
+    /// Writes the edges to the archive through a temporary file and returns their
+    /// block number, or None if it is not known and the edges are not archived.
+    pub fn store(&self, edges: &EdgeDB) -> Result<Option<u64>, io::Error> {
+        let Some(block_number) = edges.block_number() else {
+            return Ok(None);
+        };
+        // The extension selects the compression, so the temporary file keeps it.
+        let temporary = self.path(&format!("tmp-{PREFIX}{block_number}{SUFFIX}"));
+        write_edges_binary(edges, &temporary)?;
+        fs::File::open(&temporary)?.sync_all()?;
+        fs::rename(&temporary, self.path(&file_name(block_number)))?;
+        Ok(Some(block_number))
+    }
+
+    /// The block numbers of the archived edges, in ascending order.
+    pub fn block_numbers(&self) -> Result<Vec<u64>, io::Error> {
+        let mut block_numbers = fs::read_dir(&self.directory)?
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name().into_string().ok()?;
+                name.strip_prefix(PREFIX)?
+                    .strip_suffix(SUFFIX)?
+                    .parse()
+                    .ok()
+            })
+            .collect::<Vec<u64>>();
+        block_numbers.sort_unstable();
+        Ok(block_numbers)
+    }
+
+    /// The archived edges of the latest block at or before `block_number`, as a
+    /// snapshot with version 0, or None if there are none.
+    pub fn at_block(&self, block_number: u64) -> Result<Option<Arc<Snapshot>>, io::Error> {
+        let Some(archived) = self
+            .block_numbers()?
+            .into_iter()
+            .rev()
+            .find(|archived| *archived <= block_number)
+        else {
+            return Ok(None);
+        };
+        let mut last_read = self.last_read.lock().unwrap();
+        if let Some((_, snapshot)) = last_read.as_ref().filter(|(b, _)| *b == archived) {
+            return Ok(Some(snapshot.clone()));
+        }
+        let edges = read_edges_binary(&self.path(&file_name(archived)))?;
+        let snapshot = Arc::new(Snapshot::unversioned(edges));
+        *last_read = Some((archived, snapshot.clone()));
+        Ok(Some(snapshot))
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.directory.join(name).to_string_lossy().into_owned()
+    }
+}
+
+fn file_name(block_number: u64) -> String {
+    format!("{PREFIX}{block_number}{SUFFIX}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::{Address, Edge, U256};
+    use std::env;
+
+    #[test]
+    fn closest_older_block() {
+        let directory = env::temp_dir()
+            .join(format!("pathfinder_archive_{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let archive = Archive::new(&directory).unwrap();
+        let edges_at = |block_number: Option<u64>, capacity: u128| {
+            let mut edges = EdgeDB::default();
+            let edge = Edge {
+                from: Address::from([1; 20]),
+                to: Address::from([2; 20]),
+                token: Address::from([1; 20]),
+                capacity: U256::from(capacity),
+            };
+            match block_number {
+                Some(block_number) => edges.update_at_block(edge, block_number),
+                None => edges.update(edge),
+            }
+            edges
+        };
+        assert_eq!(archive.store(&edges_at(None, 1)).unwrap(), None);
+        assert_eq!(archive.store(&edges_at(Some(100), 10)).unwrap(), Some(100));
+        archive.store(&edges_at(Some(200), 20)).unwrap();
+        assert_eq!(archive.block_numbers().unwrap(), vec![100, 200]);
+
+        assert!(archive.at_block(99).unwrap().is_none());
+        let capacity = |block_number| {
+            let snapshot = archive.at_block(block_number).unwrap().unwrap();
+            assert_eq!(snapshot.version, 0);
+            snapshot.edges.edges()[0].capacity
+        };
+        assert_eq!(capacity(100), U256::from(10u128));
+        assert_eq!(capacity(199), U256::from(10u128));
+        assert_eq!(capacity(1000), U256::from(20u128));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
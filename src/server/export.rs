@@ -0,0 +1,50 @@
+//! Periodic export of the edges of the main network as Parquet to `parquet_export_file`,
+//! every `parquet_export_interval_secs`, for analysts who load them into e.g. DuckDB.
+//! The edges are only written if they changed since the last export.
+
+use super::Server;
+use crate::io::write_edges_parquet;
+use crate::types::edge::EdgeDB;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+use std::thread;
+
+/// Exports the edges after each export interval, on its own thread.
+/// Changes of the file and the interval apply from the next export.
+pub fn start(server: Arc<Server>) {
+    thread::spawn(move || {
+        let mut exported_version = None;
+        loop {
+            let (file, interval) = {
+                let config = server.config.read().unwrap();
+                (
+                    config.parquet_export_file.clone(),
+                    config.parquet_export_interval,
+                )
+            };
+            thread::sleep(interval);
+            let Some(file) = file else {
+                continue;
+            };
+            let snapshot = server.networks.main().graph.snapshot();
+            if exported_version == Some(snapshot.version) || snapshot.edges.edge_count() == 0 {
+                continue;
+            }
+            match export(&snapshot.edges, &file) {
+                Ok(()) => {
+                    exported_version = Some(snapshot.version);
+                    tracing::info!("Exported {} edges to {file}.", snapshot.edges.edge_count());
+                }
+                Err(e) => tracing::error!("Error exporting the edges to {file}: {e}"),
+            }
+        }
+    });
+}
+
+/// Writes the edges through a temporary file, so that readers never see a partial file.
+fn export(edges: &EdgeDB, file: &str) -> Result<(), io::Error> {
+    let temporary = format!("{file}.tmp");
+    write_edges_parquet(edges, &temporary)?;
+    fs::rename(&temporary, file)
+}
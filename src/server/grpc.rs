@@ -0,0 +1,319 @@
+//! The gRPC interface, see `proto/pathfinder.proto`. Calls are translated to the
+//! JSON-RPC methods of the same names, so that both interfaces share validation,
+//! queues, limits and metrics.
+
+// `Status` is the error type of all tonic services.
+#![allow(clippy::result_large_err)]
+
+use super::{authorize_call, handle_request, job_error, stream_transfer, JsonRpcRequest, Server};
+use crate::auth::AuthError;
+use json::JsonValue;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::MetadataMap;
+use tonic::{Code, Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("pathfinder");
+}
+
+use proto::compute_transfer_response::Item;
+use proto::pathfinder_server::{Pathfinder, PathfinderServer};
+use proto::{
+    ComputeTransferRequest, ComputeTransferResponse, FlowSummary, LoadRequest, LoadResponse,
+    TransferStep, TransferSteps, UpdateEdgesRequest,
+};
+
+/// The maximum number of transfer steps per message.
+const STEPS_PER_MESSAGE: usize = 100;
+
+/// Serves the gRPC interface on its own thread.
+pub fn start(server: Arc<Server>, listen_at: &str) {
+    let address: SocketAddr = listen_at.parse().expect("Invalid gRPC address.");
+    thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().expect("Could not start gRPC runtime.");
+        let service = PathfinderServer::new(Service { server });
+        let result = runtime.block_on(
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve(address),
+        );
+        if let Err(e) = result {
+            tracing::error!("Error serving gRPC: {e}");
+        }
+    });
+}
+
+struct Service {
+    server: Arc<Server>,
+}
+
+#[tonic::async_trait]
+impl Pathfinder for Service {
+    type ComputeTransferStream = ReceiverStream<Result<ComputeTransferResponse, Status>>;
+
+    async fn compute_transfer(
+        &self,
+        request: Request<ComputeTransferRequest>,
+    ) -> Result<Response<Self::ComputeTransferStream>, Status> {
+        let api_key = api_key(request.metadata());
+        let request = request.into_inner();
+        let params = json::object! {
+            from: request.from,
+            to: request.to,
+            value: request.value,
+            max_hops: request.max_hops,
+            max_transfers: request.max_transfers,
+            algorithm: request.algorithm,
+            iterative: request.iterative,
+            timeout_ms: request.timeout_ms,
+        };
+        let request = json_rpc_request("compute_transfer", params);
+
+        let (sender, receiver) = mpsc::channel(16);
+        let server = self.server.clone();
+        tokio::task::spawn_blocking(move || {
+            let permit = match authorize(&server, api_key.as_deref(), &request) {
+                Ok(permit) => permit,
+                Err(status) => {
+                    let _ = sender.blocking_send(Err(status));
+                    return;
+                }
+            };
+            let result = stream_transfer(&server, request, &mut |payload| {
+                for message in to_messages(&payload) {
+                    sender
+                        .blocking_send(message)
+                        .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+                }
+                Ok(())
+            });
+            drop(permit);
+            if let Err(e) = result {
+                let _ = sender.blocking_send(Err(to_status(&job_error(JsonValue::Null, e))));
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn load_edges_binary(
+        &self,
+        request: Request<LoadRequest>,
+    ) -> Result<Response<LoadResponse>, Status> {
+        let api_key = api_key(request.metadata());
+        let params = json::object! { file: request.into_inner().file };
+        self.load("load_edges_binary", api_key, params).await
+    }
+
+    async fn load_safes_binary(
+        &self,
+        request: Request<LoadRequest>,
+    ) -> Result<Response<LoadResponse>, Status> {
+        let api_key = api_key(request.metadata());
+        let params = json::object! { file: request.into_inner().file };
+        self.load("load_safes_binary", api_key, params).await
+    }
+
+    async fn update_edges(
+        &self,
+        request: Request<UpdateEdgesRequest>,
+    ) -> Result<Response<LoadResponse>, Status> {
+        let api_key = api_key(request.metadata());
+        let updates = request
+            .into_inner()
+            .updates
+            .into_iter()
+            .map(|update| {
+                json::object! {
+                    from: update.from,
+                    to: update.to,
+                    token_owner: update.token_owner,
+                    capacity: update.capacity,
+                    block_number: update.block_number,
+                    token_type: update.token_type,
+                    delete: update.delete,
+                }
+            })
+            .collect::<Vec<_>>();
+        self.load("update_edges", api_key, updates.into()).await
+    }
+}
+
+impl Service {
+    /// Calls a method that loads or updates edges and returns the new number of edges,
+    /// and the new graph version if the method returns it.
+    async fn load(
+        &self,
+        method: &str,
+        api_key: Option<String>,
+        params: JsonValue,
+    ) -> Result<Response<LoadResponse>, Status> {
+        let request = json_rpc_request(method, params);
+        let server = self.server.clone();
+        let response = tokio::task::spawn_blocking(move || {
+            let _permit = authorize(&server, api_key.as_deref(), &request)?;
+            Ok::<_, Status>(handle_request(&server, request))
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))??;
+        let response = json::parse(&response).map_err(|e| Status::internal(e.to_string()))?;
+        if !response["error"].is_null() {
+            return Err(error_status(&response["error"]));
+        }
+        let result = &response["result"];
+        Ok(Response::new(LoadResponse {
+            edge_count: result["edgeCount"]
+                .as_u64()
+                .or(result.as_u64())
+                .unwrap_or_default(),
+            graph_version: result["graphVersion"].as_u64(),
+        }))
+    }
+}
+
+fn json_rpc_request(method: &str, params: JsonValue) -> JsonRpcRequest {
+    JsonRpcRequest {
+        id: 0.into(),
+        method: method.to_string(),
+        params,
+    }
+}
+
+fn api_key(metadata: &MetadataMap) -> Option<String> {
+    metadata
+        .get("x-api-key")
+        .and_then(|key| key.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Checks the API key and the limits of the call, if keys are configured.
+fn authorize(
+    server: &Server,
+    api_key: Option<&str>,
+    request: &JsonRpcRequest,
+) -> Result<Option<crate::auth::Permit>, Status> {
+    let permit = match &server.api_keys {
+        Some(api_keys) => Some(api_keys.authorize(api_key).map_err(|e| match e {
+            AuthError::Unauthorized => Status::unauthenticated(e.to_string()),
+            _ => Status::resource_exhausted(e.to_string()),
+        })?),
+        None => None,
+    };
+    authorize_call(permit.as_ref(), request).map_err(|(_, error)| to_status(&error))?;
+    Ok(permit)
+}
+
+/// Converts a JSON-RPC response of `compute_transfer` to a summary followed by
+/// the transfer steps, or to an error.
+fn to_messages(response: &JsonValue) -> Vec<Result<ComputeTransferResponse, Status>> {
+    if !response["error"].is_null() {
+        return vec![Err(error_status(&response["error"]))];
+    }
+    let result = &response["result"];
+    let steps = result["transferSteps"]
+        .members()
+        .map(|step| TransferStep {
+            from: step["from"].to_string(),
+            to: step["to"].to_string(),
+            token_owner: step["token_owner"].to_string(),
+            value: step["value"].to_string(),
+            r#type: step["type"].to_string(),
+            token_type: step["token_type"].to_string(),
+        })
+        .collect::<Vec<_>>();
+    let summary = FlowSummary {
+        max_flow_value: result["maxFlowValue"].to_string(),
+        r#final: result["final"].as_bool().unwrap_or_default(),
+        timed_out: result["timedOut"].as_bool().unwrap_or_default(),
+        block_number: result["blockNumber"].as_u64(),
+        step_count: steps.len() as u64,
+        graph_version: result["graphVersion"].as_u64().unwrap_or_default(),
+    };
+    let mut messages = vec![Ok(ComputeTransferResponse {
+        item: Some(Item::Summary(summary)),
+    })];
+    for chunk in steps.chunks(STEPS_PER_MESSAGE) {
+        messages.push(Ok(ComputeTransferResponse {
+            item: Some(Item::Steps(TransferSteps {
+                steps: chunk.to_vec(),
+            })),
+        }));
+    }
+    messages
+}
+
+fn to_status(response: &str) -> Status {
+    match json::parse(response) {
+        Ok(response) => error_status(&response["error"]),
+        Err(e) => Status::internal(e.to_string()),
+    }
+}
+
+/// The gRPC status for a JSON-RPC error.
+fn error_status(error: &JsonValue) -> Status {
+    let code = match error["code"].as_i64() {
+        Some(-32001) => Code::Unauthenticated,
+        Some(-32005) | Some(-32006) => Code::ResourceExhausted,
+        Some(-32600) | Some(-32602) => Code::InvalidArgument,
+        Some(-32601) => Code::Unimplemented,
+        Some(-32603) => Code::Internal,
+        _ => Code::Unknown,
+    };
+    Status::new(code, error["message"].to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        let step = json::object! {
+            type: "transfer",
+            from: "0x1",
+            to: "0x2",
+            token_owner: "0x1",
+            token_type: "personal",
+            value: "5",
+        };
+        let response = json::object! {
+            jsonrpc: "2.0",
+            id: 0,
+            result: {
+                maxFlowValue: "5",
+                final: true,
+                transferSteps: vec![step; 150],
+            }
+        };
+        let messages = to_messages(&response)
+            .into_iter()
+            .map(|message| message.unwrap().item.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(messages.len(), 3);
+        match &messages[0] {
+            Item::Summary(summary) => {
+                assert_eq!(summary.max_flow_value, "5");
+                assert!(summary.r#final);
+                assert_eq!(summary.step_count, 150);
+            }
+            _ => panic!("Expected a summary"),
+        }
+        match &messages[2] {
+            Item::Steps(steps) => assert_eq!(steps.steps.len(), 50),
+            _ => panic!("Expected steps"),
+        }
+
+        let error = json::object! {
+            jsonrpc: "2.0",
+            id: 0,
+            error: { code: -32005, message: "Queue full" },
+        };
+        let status = to_messages(&error).remove(0).unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(status.message(), "Queue full");
+    }
+}
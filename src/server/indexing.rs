@@ -0,0 +1,124 @@
+//! Indexing of the edges from the events of the hub (if the edge source is `indexer`),
+//! read from the node configured as `indexer_rpc_url`, see `indexer`. New blocks are indexed every `indexer_interval_secs`,
+//! and if they change any safe, the edges computed from the safes replace the current ones
+//! like `load_edges_binary`.
+//!
+//! If `indexer_ws_url` is configured as well, new blocks are followed as the node announces
+//! them instead, and only the changes they cause are applied to the edges, like `update_edges`.
+//! Blocks that are reorganized away are rolled back. If following fails, e.g. because the
+//! connection is lost, indexing continues as above and then follows blocks again.
+
+use super::network::Network;
+use super::Server;
+use crate::config::EdgeSource;
+use crate::indexer::heads::NewHeads;
+use crate::indexer::rpc::EthClient;
+use crate::indexer::Indexer;
+use std::error::Error;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+/// Indexes the blocks of the network up to the latest one right away and then the new blocks
+/// after each interval, on its own thread. Changes of the URLs and the interval apply from the next sync.
+pub fn start(server: Arc<Server>, chain_id: u64) {
+    thread::spawn(move || {
+        let network = server.networks.get(chain_id).unwrap();
+        let mut indexer = {
+            let config = server.network_config(chain_id).unwrap_or_default();
+            Indexer::new(config.indexer_hub, config.indexer_start_block)
+        };
+        // The changes of the indexer that are part of the edges in use.
+        let mut published = indexer.changes();
+        loop {
+            let (url, ws_url, interval) = {
+                // A network removed from the configuration is no longer updated.
+                let config = server.network_config(chain_id).unwrap_or_default();
+                (
+                    config
+                        .indexer_rpc_url
+                        .clone()
+                        .filter(|_| config.edge_source == EdgeSource::Indexer),
+                    config.indexer_ws_url.clone(),
+                    config.indexer_interval,
+                )
+            };
+            if let Some(url) = url {
+                let client = EthClient::new(&url);
+                match index(&server, network, &mut indexer, &client, &mut published) {
+                    Err(e) => tracing::error!("Error indexing edges from {url}: {e}"),
+                    Ok(()) => {
+                        if let Some(ws_url) = ws_url {
+                            if let Err(e) = follow(
+                                &server,
+                                network,
+                                &mut indexer,
+                                &client,
+                                &ws_url,
+                                &mut published,
+                            ) {
+                                tracing::error!("Error following new blocks from {ws_url}: {e}");
+                            }
+                        }
+                    }
+                }
+            }
+            thread::sleep(interval);
+        }
+    });
+}
+
+fn index(
+    server: &Server,
+    network: &Network,
+    indexer: &mut Indexer,
+    client: &EthClient,
+    published: &mut u64,
+) -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    // Blocks indexed before an error are used as well.
+    let result = indexer.sync(client);
+    if indexer.changes() != *published {
+        let snapshot = network.graph.replace(indexer.edges())?;
+        *published = indexer.changes();
+        server.record_load(start);
+        server.graph_updated(network, &snapshot);
+        tracing::info!(
+            "Indexed {} edges of chain {} up to block {}, graph version {}.",
+            snapshot.edges.edge_count(),
+            network.chain_id,
+            indexer.block_number().unwrap_or_default(),
+            snapshot.version
+        );
+    }
+    result
+}
+
+/// Applies the changes of each new block to the edges, until an error occurs.
+fn follow(
+    server: &Server,
+    network: &Network,
+    indexer: &mut Indexer,
+    client: &EthClient,
+    ws_url: &str,
+    published: &mut u64,
+) -> Result<(), Box<dyn Error>> {
+    let mut heads = NewHeads::subscribe(ws_url)?;
+    tracing::info!("Following new blocks from {ws_url}.");
+    loop {
+        let head = heads.next_head()?;
+        let changes = indexer.follow(client, head)?;
+        // Blocks without relevant events do not change the edges.
+        if indexer.changes() != *published {
+            let snapshot = network.graph.apply(&changes)?;
+            *published = indexer.changes();
+            server.graph_updated(network, &snapshot);
+            tracing::debug!(
+                "Applied {} changes up to block {}, graph version {}.",
+                changes.len(),
+                indexer.block_number().unwrap_or_default(),
+                snapshot.version
+            );
+        }
+    }
+}
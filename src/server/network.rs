@@ -0,0 +1,127 @@
+//! The networks the server hosts a graph for, by chain id. Each network has its own
+//! edges, loaded by the JSON-RPC methods (with the parameter `chain_id`) or by its own
+//! background pipeline configured with `networks`, see `config`. Requests without
+//! `chain_id` use the main network.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use json::JsonValue;
+
+use super::archive::Archive;
+use super::snapshot::VersionedGraph;
+use crate::config::Config;
+
+pub struct Network {
+    pub chain_id: u64,
+    pub graph: VersionedGraph,
+    /// The unix timestamp of the last successful load or update of the edges.
+    pub loaded_at: Mutex<Option<u64>>,
+}
+
+pub struct Networks {
+    main: u64,
+    networks: BTreeMap<u64, Network>,
+}
+
+impl Networks {
+    /// The networks of the configuration. If `data_dir` is set, the edges of the main
+    /// network are persisted there and those of the others in a subdirectory named after
+    /// their chain id. The same applies to the archive in `archive_dir`.
+    pub fn new(config: &Config) -> Networks {
+        let networks = config
+            .chain_ids()
+            .into_iter()
+            .map(|chain_id| {
+                let directory_of = |directory: &String| match chain_id == config.chain_id {
+                    true => directory.clone(),
+                    false => Path::new(directory)
+                        .join(chain_id.to_string())
+                        .to_string_lossy()
+                        .into_owned(),
+                };
+                let mut graph = match &config.data_dir {
+                    Some(directory) => VersionedGraph::persistent(
+                        &directory_of(directory),
+                        config.data_store,
+                        config.compact_after_updates,
+                    )
+                    .expect("Could not restore the edges."),
+                    None => VersionedGraph::default(),
+                };
+                if let Some(directory) = &config.archive_dir {
+                    let archive = Archive::new(&directory_of(directory))
+                        .expect("Could not open the archive.");
+                    graph = graph.with_archive(archive);
+                }
+                graph.set_retained_versions(config.retained_versions);
+                let network = Network {
+                    chain_id,
+                    graph,
+                    loaded_at: Default::default(),
+                };
+                (chain_id, network)
+            })
+            .collect();
+        Networks {
+            main: config.chain_id,
+            networks,
+        }
+    }
+
+    pub fn main(&self) -> &Network {
+        &self.networks[&self.main]
+    }
+
+    pub fn get(&self, chain_id: u64) -> Option<&Network> {
+        self.networks.get(&chain_id)
+    }
+
+    /// The network of a request, given by the optional parameter `chain_id`.
+    pub fn for_params(&self, params: &JsonValue) -> Result<&Network, String> {
+        if params["chain_id"].is_null() {
+            return Ok(self.main());
+        }
+        params["chain_id"]
+            .as_u64()
+            .and_then(|chain_id| self.get(chain_id))
+            .ok_or_else(|| format!("Unknown chain_id: {}", params["chain_id"]))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Network> {
+        self.networks.values()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn networks_by_chain_id() {
+        let mut config = Config::default();
+        config.set("networks", r#"{"10200": {}}"#).unwrap();
+        let networks = Networks::new(&config);
+        assert_eq!(networks.main().chain_id, 100);
+        assert_eq!(networks.iter().count(), 2);
+        let params = |params: &str| json::parse(params).unwrap();
+        assert_eq!(networks.for_params(&params("{}")).unwrap().chain_id, 100);
+        assert_eq!(
+            networks
+                .for_params(&params(r#"{"chain_id": 10200}"#))
+                .unwrap()
+                .chain_id,
+            10200
+        );
+        assert!(networks.for_params(&params(r#"{"chain_id": 1}"#)).is_err());
+        assert!(networks
+            .for_params(&params(r#"{"chain_id": "100"}"#))
+            .is_err());
+        // Parameters passed by position always use the main network.
+        assert_eq!(
+            networks.for_params(&params("[1, 2]")).unwrap().chain_id,
+            100
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! Periodic refresh of the edges from the URL configured as `refresh_url` (if the edge
+//! source is `files`), every
+//! `refresh_interval_secs`. A download is only used if it changed since the last one
+//! (by its `ETag` or `Last-Modified` header), can be read and is not older than the
+//! edges in use, and then replaces them like `load_edges_binary`.
+
+use super::network::Network;
+use super::Server;
+use crate::config::EdgeSource;
+use crate::io::read_edges_file;
+use crate::types::edge::EdgeDB;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The time to connect and the longest time without receiving data while downloading.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Downloads the edges of the network right away and then after each refresh interval, on
+/// its own thread.
+/// Changes of the URL and the interval apply from the next download.
+pub fn start(server: Arc<Server>, chain_id: u64) {
+    thread::spawn(move || {
+        let network = server.networks.get(chain_id).unwrap();
+        let agent = download_agent();
+        let mut last = Validators::default();
+        loop {
+            let (url, interval) = {
+                // A network removed from the configuration is no longer updated.
+                let config = server.network_config(chain_id).unwrap_or_default();
+                let url = config.refresh_url.clone();
+                let url = url.filter(|_| config.edge_source == EdgeSource::Files);
+                (url, config.refresh_interval)
+            };
+            if let Some(url) = url {
+                if let Err(e) = refresh(&server, network, &agent, &url, &mut last) {
+                    tracing::error!("Error refreshing edges from {url}: {e}");
+                }
+            }
+            thread::sleep(interval);
+        }
+    });
+}
+
+fn download_agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout_connect(DOWNLOAD_TIMEOUT)
+        .timeout_read(DOWNLOAD_TIMEOUT)
+        .build()
+}
+
+/// Downloads edges in any format, named like the file of the URL.
+pub fn download_edges(url: &str) -> Result<EdgeDB, Box<dyn Error>> {
+    let (edges, _) = download(&download_agent(), url, &Validators::default())?
+        .ok_or("Unexpected response: Not Modified")?;
+    Ok(edges)
+}
+
+fn refresh(
+    server: &Server,
+    network: &Network,
+    agent: &ureq::Agent,
+    url: &str,
+    last: &mut Validators,
+) -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    let Some((edges, validators)) = download(agent, url, last)? else {
+        return Ok(());
+    };
+    validate(&edges, &network.graph.snapshot().edges)?;
+    let snapshot = network.graph.replace(edges)?;
+    *last = validators;
+    server.record_load(start);
+    server.graph_updated(network, &snapshot);
+    tracing::info!(
+        "Refreshed {} edges from {url}, graph version {}.",
+        snapshot.edges.edge_count(),
+        snapshot.version
+    );
+    Ok(())
+}
+
+/// The headers identifying a downloaded version of the edges.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Validators {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// Downloads and reads the edges, unless they did not change since the download
+/// identified by `last`. Returns them with the validators of this download.
+fn download(
+    agent: &ureq::Agent,
+    url: &str,
+    last: &Validators,
+) -> Result<Option<(EdgeDB, Validators)>, Box<dyn Error>> {
+    let mut request = agent.get(url);
+    if last.url == url {
+        if let Some(etag) = &last.etag {
+            request = request.set("If-None-Match", etag);
+        }
+        if let Some(last_modified) = &last.last_modified {
+            request = request.set("If-Modified-Since", last_modified);
+        }
+    }
+    let response = request.call()?;
+    if response.status() == 304 {
+        return Ok(None);
+    }
+    let validators = Validators {
+        url: url.to_string(),
+        etag: response.header("ETag").map(str::to_string),
+        last_modified: response.header("Last-Modified").map(str::to_string),
+    };
+    // The file is read like a local one, so it keeps the name (and thus the format) of the URL.
+    let path = download_path(url);
+    let result = File::create(&path)
+        .and_then(|mut file| io::copy(&mut response.into_reader(), &mut file))
+        .and_then(|_| read_edges_file(&path));
+    // Mapped edges stay readable after the file is removed.
+    let _ = fs::remove_file(&path);
+    Ok(Some((result?, validators)))
+}
+
+fn download_path(url: &str) -> String {
+    let name = url.split(['?', '#']).next().unwrap_or_default();
+    let name = name.rsplit('/').next().unwrap_or_default();
+    env::temp_dir()
+        .join(format!("pathfinder_refresh_{}_{name}", process::id()))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Rejects downloaded edges that are empty or older than the current edges.
+fn validate(edges: &EdgeDB, current: &EdgeDB) -> Result<(), String> {
+    if edges.edge_count() == 0 {
+        return Err("The downloaded edge DB is empty.".to_string());
+    }
+    match (edges.block_number(), current.block_number()) {
+        (Some(block), Some(current_block)) if block < current_block => Err(format!(
+            "The downloaded edges are at block {block}, before the current edges at block {current_block}."
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::io::write_edges_csv;
+    use crate::types::{Address, Edge, U256};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn conditional_download() {
+        let edges = EdgeDB::new(vec![Edge {
+            from: Address::from([1; 20]),
+            to: Address::from([2; 20]),
+            token: Address::from([1; 20]),
+            capacity: U256::from(10u128),
+        }]);
+        let file = download_path("served.csv");
+        write_edges_csv(&edges, &file).unwrap();
+        let body = fs::read(&file).unwrap();
+        fs::remove_file(&file).unwrap();
+
+        // Answers two requests, the second one with 304 if it has the ETag of the first.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/edges.csv", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                let (mut socket, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(socket.try_clone().unwrap());
+                let mut unchanged = false;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    unchanged |= line.to_lowercase() == "if-none-match: \"v1\"\r\n";
+                    line.clear();
+                }
+                if unchanged {
+                    write!(socket, "HTTP/1.1 304 Not Modified\r\n\r\n").unwrap();
+                } else {
+                    write!(
+                        socket,
+                        "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\n\r\n",
+                        body.len()
+                    )
+                    .unwrap();
+                    socket.write_all(&body).unwrap();
+                }
+            }
+        });
+
+        let agent = ureq::agent();
+        let (downloaded, validators) = download(&agent, &url, &Validators::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(downloaded.edges(), edges.edges());
+        assert_eq!(validators.etag.as_deref(), Some("\"v1\""));
+        assert!(download(&agent, &url, &validators).unwrap().is_none());
+        server.join().unwrap();
+
+        assert!(validate(&downloaded, &edges).is_ok());
+        assert!(validate(&EdgeDB::default(), &edges).is_err());
+    }
+}
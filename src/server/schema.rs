@@ -0,0 +1,1077 @@
+//! The OpenRPC description of the JSON-RPC methods, returned by `rpc_describe`.
+//! The possible values of enumerations are taken from the corresponding types.
+
+use crate::graph::FlowAlgorithm;
+use crate::types::edge::EdgeKind;
+use crate::types::TokenType;
+use json::JsonValue;
+
+/// The OpenRPC document (https://spec.open-rpc.org) describing all methods.
+pub fn describe() -> JsonValue {
+    json::object! {
+        openrpc: "1.2.6",
+        info: {
+            title: "Pathfinder",
+            description: "Computes transfer paths in the Circles trust graph. \
+                Values are decimal strings, in atto-Circles unless `units` is `circles`.",
+            version: env!("CARGO_PKG_VERSION"),
+        },
+        methods: methods(),
+        components: {
+            schemas: schemas(),
+        },
+    }
+}
+
+fn methods() -> Vec<JsonValue> {
+    vec![
+        method(
+            "compute_transfer",
+            "Computes the transfer steps to send `value` (or the maximum) from `from` to `to`. \
+                With `iterative` or when streamed, intermediate results are sent before the final one.",
+            [
+                param("from", "The sender.", reference("Address"), true),
+                param("to", "The recipient.", reference("Address"), true),
+                value_param(),
+                max_hops_param(),
+                max_transfers_param(),
+                units_param(),
+                algorithm_param(),
+                optimization_param(),
+                param(
+                    "iterative",
+                    "Searches short paths first and returns a result for each round.",
+                    json::object! { type: "boolean" },
+                    false,
+                ),
+                param(
+                    "timeout_ms",
+                    "Returns the best flow found within this time, with `timedOut`.",
+                    json::object! { type: "integer", minimum: 0 },
+                    false,
+                ),
+                param(
+                    "wrapped_token",
+                    "The ERC-20 wrapper the recipient receives tokens in.",
+                    reference("Address"),
+                    false,
+                ),
+                param(
+                    "accepted_tokens",
+                    "Only these tokens (by owner) are transferred.",
+                    addresses(),
+                    false,
+                ),
+                param(
+                    "excluded_tokens",
+                    "These tokens (by owner) are not transferred.",
+                    addresses(),
+                    false,
+                ),
+                param(
+                    "offset",
+                    "Returns the transfer steps starting at this index.",
+                    json::object! { type: "integer", minimum: 0 },
+                    false,
+                ),
+                param(
+                    "limit",
+                    "Returns at most this many transfer steps.",
+                    json::object! { type: "integer", minimum: 0 },
+                    false,
+                ),
+                param(
+                    "max_staleness_blocks",
+                    "Only edges updated at most that many blocks before the latest update are used.",
+                    json::object! { type: "integer", minimum: 0 },
+                    false,
+                ),
+                param(
+                    "exclude_stopped",
+                    "Does not use stopped accounts as intermediate hops.",
+                    json::object! { type: "boolean" },
+                    false,
+                ),
+                param(
+                    "verify_balances",
+                    "Checks the balances the transfer needs on-chain and rejects it or \
+                        recomputes it with the current balances if they are smaller.",
+                    json::object! { type: "string", enum: ["reject", "rescale"] },
+                    false,
+                ),
+            ],
+            result("transfer", reference("TransferResult")),
+        ),
+        method(
+            "compute_transfer_to_sinks",
+            "Computes one transfer from `from` to several recipients.",
+            [
+                param("from", "The sender.", reference("Address"), true),
+                param(
+                    "sinks",
+                    "The recipients and the value each of them should receive.",
+                    json::object! {
+                        type: "array",
+                        minItems: 1,
+                        items: {
+                            type: "object",
+                            required: ["to"],
+                            properties: {
+                                to: reference("Address"),
+                                value: reference("Value"),
+                            },
+                        },
+                    },
+                    true,
+                ),
+                max_hops_param(),
+                max_transfers_param(),
+                units_param(),
+                algorithm_param(),
+                optimization_param(),
+            ],
+            result(
+                "transfer",
+                json::object! {
+                    type: "object",
+                    properties: {
+                        maxFlowValue: reference("Value"),
+                        sinks: {
+                            type: "array",
+                            items: {
+                                type: "object",
+                                properties: {
+                                    to: reference("Address"),
+                                    value: reference("Value"),
+                                },
+                            },
+                        },
+                        transferSteps: transfer_steps(),
+                        graphVersion: reference("GraphVersion"),
+                        blockNumber: { type: "integer" },
+                    },
+                },
+            ),
+        ),
+        method(
+            "compute_transfer_from_sources",
+            "Computes how much each of several senders can contribute to a transfer to `to`.",
+            [
+                param("sources", "The senders.", addresses(), true),
+                param("to", "The recipient.", reference("Address"), true),
+                value_param(),
+                max_hops_param(),
+                max_transfers_param(),
+                units_param(),
+                algorithm_param(),
+                optimization_param(),
+            ],
+            result(
+                "transfer",
+                json::object! {
+                    type: "object",
+                    properties: {
+                        maxFlowValue: reference("Value"),
+                        sources: {
+                            type: "array",
+                            items: {
+                                type: "object",
+                                properties: {
+                                    from: reference("Address"),
+                                    value: reference("Value"),
+                                    transferSteps: transfer_steps(),
+                                },
+                            },
+                        },
+                        graphVersion: reference("GraphVersion"),
+                        blockNumber: { type: "integer" },
+                    },
+                },
+            ),
+        ),
+        method(
+            "compute_alternate_routes",
+            "Computes up to `count` transfers of `value` along different routes.",
+            [
+                param("from", "The sender.", reference("Address"), true),
+                param("to", "The recipient.", reference("Address"), true),
+                value_param(),
+                param(
+                    "count",
+                    "The maximum number of routes, 3 by default.",
+                    json::object! { type: "integer", minimum: 1 },
+                    false,
+                ),
+                max_hops_param(),
+                max_transfers_param(),
+                units_param(),
+            ],
+            result(
+                "routes",
+                json::object! {
+                    type: "array",
+                    items: {
+                        type: "object",
+                        properties: {
+                            maxFlowValue: reference("Value"),
+                            hops: { type: "integer" },
+                            tokens: { type: "integer" },
+                            transferSteps: transfer_steps(),
+                        },
+                    },
+                },
+            ),
+        ),
+        method(
+            "compute_capacity_matrix",
+            "Computes the maximum values that can be transferred between all ordered pairs \
+             of `addresses`, e.g. for dashboards of the liquidity of a group. Each pair is \
+             computed for at most `pair_timeout_ms`, the values of pairs that timed out are \
+             the best flows found until then. The flow timeout of the server applies to \
+             the whole matrix.",
+            [
+                param(
+                    "addresses",
+                    "The accounts, at most 50.",
+                    addresses(),
+                    true,
+                ),
+                param(
+                    "pair_timeout_ms",
+                    "The time budget per pair in milliseconds, 100 by default.",
+                    json::object! { type: "integer", minimum: 0, maximum: 10000 },
+                    false,
+                ),
+                max_hops_param(),
+                algorithm_param(),
+                units_param(),
+            ],
+            result(
+                "capacities",
+                json::object! {
+                    type: "object",
+                    properties: {
+                        addresses: addresses(),
+                        matrix: {
+                            description: "The value from the i-th to the j-th address in row i \
+                                and column j, null on the diagonal.",
+                            type: "array",
+                            items: {
+                                type: "array",
+                                items: { oneOf: [reference("Value"), { type: "null" }] },
+                            },
+                        },
+                        approximate: {
+                            description: "The pairs [i, j] that timed out.",
+                            type: "array",
+                            items: { type: "array", items: { type: "integer" } },
+                        },
+                        graphVersion: { type: "integer" },
+                        blockNumber: { type: "integer" },
+                    },
+                },
+            ),
+        ),
+        method(
+            "is_transfer_possible",
+            "Checks whether `value` can be transferred, without computing the transfer steps.",
+            [
+                param("from", "The sender.", reference("Address"), true),
+                param("to", "The recipient.", reference("Address"), true),
+                param("value", "The value to transfer.", reference("Value"), true),
+                units_param(),
+            ],
+            result("possible", json::object! { type: "boolean" }),
+        ),
+        load_method(
+            "load_edges_binary",
+            "Replaces the edges by those in a binary edge file.",
+        ),
+        load_method("load_edges_csv", "Replaces the edges by those in a CSV file."),
+        load_method(
+            "load_edges_mmap",
+            "Replaces the edges by those in a mapped edge file, which are used in place.",
+        ),
+        load_method(
+            "load_safes_binary",
+            "Replaces the edges by those computed from a binary safes file.",
+        ),
+        method(
+            "load_safes_json",
+            "Replaces the edges by those computed from a safes JSON file. Invalid entries \
+             and trust connections are skipped and reported.",
+            [
+                param(
+                    "file",
+                    "The path of the file on the server.",
+                    json::object! { type: "string" },
+                    true,
+                ),
+                param(
+                    "limit",
+                    "The maximum number of errors reported, 100 by default.",
+                    json::object! { type: "integer", minimum: 0 },
+                    false,
+                ),
+            ],
+            result(
+                "report",
+                json::object! {
+                    type: "object",
+                    properties: {
+                        edgeCount: { type: "integer" },
+                        loaded: {
+                            description: "The number of safes imported.",
+                            type: "integer",
+                        },
+                        skipped: {
+                            description: "The number of invalid entries.",
+                            type: "integer",
+                        },
+                        errorCount: { type: "integer" },
+                        errors: {
+                            type: "array",
+                            items: {
+                                type: "object",
+                                properties: {
+                                    entry: {
+                                        description: "The index of the entry in the list of safes.",
+                                        type: "integer",
+                                    },
+                                    line: {
+                                        description: "The line the entry starts at.",
+                                        type: "integer",
+                                    },
+                                    id: { type: ["string", "null"] },
+                                    message: { type: "string" },
+                                },
+                            },
+                        },
+                    },
+                },
+            ),
+        ),
+        method(
+            "update_edges",
+            "Updates the capacities of edges or removes them, given by position as an array. \
+             The batch is applied atomically under a new graph version.",
+            [param(
+                "updates",
+                "The updated edges, and the removed ones with delete.",
+                json::object! {
+                    type: "array",
+                    items: {
+                        type: "object",
+                        required: ["from", "to", "token_owner"],
+                        properties: {
+                            from: reference("Address"),
+                            to: reference("Address"),
+                            token_owner: reference("Address"),
+                            capacity: reference("Value"),
+                            block_number: { type: "integer", minimum: 0 },
+                            token_type: reference("TokenType"),
+                            delete: { type: "boolean" },
+                        },
+                    },
+                },
+                true,
+            )],
+            result(
+                "edgeUpdate",
+                json::object! {
+                    type: "object",
+                    properties: {
+                        graphVersion: { type: "integer" },
+                        edgeCount: { type: "integer" },
+                    },
+                },
+            ),
+        )
+        .by_position(),
+        method(
+            "apply_trust_events",
+            "Applies changes of trust limits in the order of their blocks. Changes at an older \
+             block than the last change of the same trust relation are ignored.",
+            [param(
+                "events",
+                "The changes of trust limits.",
+                json::object! {
+                    type: "array",
+                    items: {
+                        type: "object",
+                        required: ["truster", "trustee", "limit", "block_number"],
+                        properties: {
+                            truster: reference("Address"),
+                            trustee: reference("Address"),
+                            limit: { type: "integer", minimum: 0, maximum: 100 },
+                            block_number: { type: "integer", minimum: 0 },
+                        },
+                    },
+                },
+                true,
+            )],
+            result(
+                "trustEvents",
+                json::object! {
+                    type: "object",
+                    properties: {
+                        applied: { type: "integer" },
+                        ignored: { type: "integer" },
+                        edgeCount: { type: "integer" },
+                    },
+                },
+            ),
+        ),
+        method(
+            "update_balances",
+            "Sets balances or changes them by a signed delta and updates the capacities \
+             derived from them.",
+            [param(
+                "updates",
+                "The updated balances, each with either balance or delta.",
+                json::object! {
+                    type: "array",
+                    items: {
+                        type: "object",
+                        required: ["holder", "token_owner"],
+                        properties: {
+                            holder: reference("Address"),
+                            token_owner: reference("Address"),
+                            balance: reference("Value"),
+                            delta: { type: "string", pattern: "^-?[0-9]+$" },
+                        },
+                    },
+                },
+                true,
+            )],
+            edge_count(),
+        ),
+        file_method(
+            "import_trust_csv",
+            "Sets the trust limits of the trust relations in a CSV file with the columns \
+             truster, trustee and limit (a percentage).",
+            edge_count(),
+        ),
+        file_method(
+            "export_edges_binary",
+            "Writes the edges with their current capacities, including all updates, to a binary \
+             edge file, compressed if the name ends with .gz or .zst. The current edges can also \
+             be downloaded from /edges.dat.",
+            edge_count(),
+        ),
+        file_method(
+            "export_edges_csv",
+            "Writes the edges with their current capacities to a CSV file with the columns \
+             from, to, token and capacity.",
+            edge_count(),
+        ),
+        file_method(
+            "export_edges_parquet",
+            "Writes the edges with their current capacities to a Parquet file with the columns \
+             from, to, token_owner, capacity, token_type and block_number. Needs the feature parquet.",
+            edge_count(),
+        ),
+        file_method(
+            "export_trust_csv",
+            "Writes the trust relations to a CSV file with the columns truster, trustee and limit.",
+            result("trustCount", json::object! { type: "integer" }),
+        ),
+        method(
+            "export_graph",
+            "Writes the graph, or the neighborhood of an address, to a file in GraphML or \
+             Graphviz DOT for visualization. Only edges with a non-zero capacity are written.",
+            [
+                param(
+                    "file",
+                    "The path of the file on the server.",
+                    json::object! { type: "string" },
+                    true,
+                ),
+                param(
+                    "format",
+                    "The format, by default given by the extension of the file \
+                     (.graphml, .dot or .gv).",
+                    json::object! { type: "string", enum: ["graphml", "dot"] },
+                    false,
+                ),
+                param(
+                    "address",
+                    "Only writes the edges between accounts at most `hops` hops away from this \
+                     address, in either direction.",
+                    reference("Address"),
+                    false,
+                ),
+                param(
+                    "hops",
+                    "The radius of the neighborhood of `address`, 1 by default.",
+                    json::object! { type: "integer", minimum: 0 },
+                    false,
+                ),
+            ],
+            edge_count(),
+        ),
+        method(
+            "diff_edges",
+            "Compares two edge files and reports the added, removed and changed edges, \
+             aggregated per account, e.g. to check a new snapshot before loading it.",
+            [
+                param(
+                    "new",
+                    "The path of the new edge file on the server.",
+                    json::object! { type: "string" },
+                    true,
+                ),
+                param(
+                    "old",
+                    "The path of the old edge file on the server, the current edges if omitted.",
+                    json::object! { type: "string" },
+                    false,
+                ),
+                param(
+                    "limit",
+                    "The maximum number of accounts reported, those with the most changes. \
+                     100 by default.",
+                    json::object! { type: "integer", minimum: 0 },
+                    false,
+                ),
+            ],
+            result(
+                "diff",
+                json::object! {
+                    type: "object",
+                    properties: {
+                        added: { type: "integer" },
+                        removed: { type: "integer" },
+                        changed: { type: "integer" },
+                        accountCount: { type: "integer" },
+                        accounts: {
+                            type: "array",
+                            items: {
+                                type: "object",
+                                properties: {
+                                    address: reference("Address"),
+                                    added: { type: "integer" },
+                                    removed: { type: "integer" },
+                                    changed: { type: "integer" },
+                                    capacityBefore: reference("Value"),
+                                    capacityAfter: reference("Value"),
+                                },
+                            },
+                        },
+                    },
+                },
+            ),
+        ),
+        method(
+            "graph_stats",
+            "Returns statistics of the edges with a capacity: their number and total \
+             capacity, the degrees of the accounts (incoming and outgoing edges), the \
+             strongly connected components and the accounts with the highest degree. \
+             They are computed once per graph version.",
+            [param(
+                "hubs",
+                "The number of accounts with the highest degree returned, 10 by default.",
+                json::object! { type: "integer", minimum: 0, maximum: 100 },
+                false,
+            )],
+            result(
+                "stats",
+                json::object! {
+                    type: "object",
+                    properties: {
+                        nodes: { type: "integer" },
+                        edges: { type: "integer" },
+                        totalCapacity: reference("Value"),
+                        degrees: {
+                            type: "object",
+                            properties: {
+                                min: { type: "integer" },
+                                max: { type: "integer" },
+                                mean: { type: "number" },
+                                median: { type: "integer" },
+                            },
+                        },
+                        stronglyConnectedComponents: { type: "integer" },
+                        largestComponent: { type: "integer" },
+                        hubs: {
+                            type: "array",
+                            items: {
+                                type: "object",
+                                properties: {
+                                    address: reference("Address"),
+                                    degree: { type: "integer" },
+                                    incoming: { type: "integer" },
+                                    outgoing: { type: "integer" },
+                                },
+                            },
+                        },
+                        graphVersion: { type: "integer" },
+                        blockNumber: { type: "integer" },
+                    },
+                },
+            ),
+        ),
+        method(
+            "get_neighborhood",
+            "Returns the edges with a capacity between the accounts at most `depth` hops \
+             away from `address`, following edges in either direction, e.g. to render the \
+             local trust graph of a wallet. Each hop adds at most `max_per_hop` accounts, \
+             those with the largest capacity of their edges to the previous hop.",
+            [
+                param(
+                    "address",
+                    "The account at the center.",
+                    reference("Address"),
+                    true,
+                ),
+                param(
+                    "depth",
+                    "The number of hops, 1 by default.",
+                    json::object! { type: "integer", minimum: 0, maximum: 3 },
+                    false,
+                ),
+                param(
+                    "max_per_hop",
+                    "The maximum number of accounts added per hop, 50 by default.",
+                    json::object! { type: "integer", minimum: 0, maximum: 1000 },
+                    false,
+                ),
+                units_param(),
+            ],
+            result(
+                "neighborhood",
+                json::object! {
+                    type: "object",
+                    properties: {
+                        address: reference("Address"),
+                        depth: { type: "integer" },
+                        accounts: addresses(),
+                        edges: {
+                            type: "array",
+                            items: {
+                                type: "object",
+                                properties: {
+                                    from: reference("Address"),
+                                    to: reference("Address"),
+                                    token_owner: reference("Address"),
+                                    capacity: reference("Value"),
+                                },
+                            },
+                        },
+                        truncated: {
+                            description: "Whether accounts were left out because of `max_per_hop`.",
+                            type: "boolean",
+                        },
+                        graphVersion: { type: "integer" },
+                        blockNumber: { type: "integer" },
+                    },
+                },
+            ),
+        ),
+        method(
+            "find_trust_path",
+            "Returns whether a path of trust connections leads from `from` to `to`, \
+             ignoring balances and capacities, and the shortest such path. Each account \
+             on the path trusts the previous one. Onboarding flows can use it to tell new \
+             users whom to get trusted by.",
+            [
+                param("from", "The sender.", reference("Address"), true),
+                param("to", "The recipient.", reference("Address"), true),
+                param(
+                    "max_hops",
+                    "The maximum length of the path, 6 by default.",
+                    json::object! { type: "integer", minimum: 0, maximum: 20 },
+                    false,
+                ),
+            ],
+            result(
+                "trustPath",
+                json::object! {
+                    type: "object",
+                    properties: {
+                        exists: { type: "boolean" },
+                        hops: { type: ["integer", "null"] },
+                        path: {
+                            description: "The accounts from `from` to `to`, null if there is no path.",
+                            type: ["array", "null"],
+                            items: reference("Address"),
+                        },
+                        graphVersion: { type: "integer" },
+                        blockNumber: { type: "integer" },
+                    },
+                },
+            ),
+        ),
+        method(
+            "verify_flow",
+            "Checks a transfer plan, such as the `transferSteps` of a cached `compute_transfer` \
+             result, against the current edges: executed in order, every step respects the \
+             balances and trust limits, all accounts except `from` and `to` receive as much as \
+             they send, and `from` sends and `to` receives exactly `value` in net.",
+            [
+                param("from", "The sender.", reference("Address"), true),
+                param("to", "The recipient.", reference("Address"), true),
+                param("value", "The value the plan transfers.", reference("Value"), true),
+                param(
+                    "transfers",
+                    "The transfer steps in the order of execution.",
+                    json::object! { type: "array", items: reference("TransferStep") },
+                    true,
+                ),
+                units_param(),
+            ],
+            result(
+                "flowVerification",
+                json::object! {
+                    type: "object",
+                    properties: {
+                        valid: { type: "boolean" },
+                        error: {
+                            description: "Why the plan is invalid, null if it is valid.",
+                            type: ["string", "null"],
+                        },
+                        graphVersion: { type: "integer" },
+                        blockNumber: { type: "integer" },
+                    },
+                },
+            ),
+        ),
+        method(
+            "add_wrappers",
+            "Adds ERC-20 wrappers of tokens, given by position as an array.",
+            [param(
+                "wrappers",
+                "The wrappers and the owners of the wrapped tokens.",
+                json::object! {
+                    type: "array",
+                    items: {
+                        type: "object",
+                        required: ["wrapper", "token_owner"],
+                        properties: {
+                            wrapper: reference("Address"),
+                            token_owner: reference("Address"),
+                        },
+                    },
+                },
+                true,
+            )],
+            edge_count(),
+        )
+        .by_position(),
+        method(
+            "subscribe",
+            "WebSocket only: subscribes to `graph_updated` notifications, which carry \
+                the new number of edges. Returns the id of the subscription.",
+            [param(
+                "event",
+                "The event to subscribe to.",
+                json::object! { enum: ["graph_updated"] },
+                true,
+            )],
+            result("subscription", json::object! { type: "integer" }),
+        )
+        .by_position(),
+        method(
+            "unsubscribe",
+            "WebSocket only: ends a subscription of the same connection.",
+            [param(
+                "subscription",
+                "The id returned by `subscribe`.",
+                json::object! { type: "integer" },
+                true,
+            )],
+            result("removed", json::object! { type: "boolean" }),
+        )
+        .by_position(),
+        method(
+            "rpc_describe",
+            "Returns this description.",
+            [],
+            result("description", json::object! { type: "object" }),
+        ),
+    ]
+    .into_iter()
+    .map(|method| method.with_chain_id().with_snapshot_params().0)
+    .collect()
+}
+
+fn schemas() -> JsonValue {
+    json::object! {
+        Address: {
+            type: "string",
+            pattern: "^0x[0-9a-fA-F]{40}$",
+        },
+        Value: {
+            description: "A non-negative decimal value, in atto-Circles unless `units` is `circles`.",
+            type: "string",
+        },
+        TokenType: {
+            enum: TokenType::ALL.map(|t| t.as_str()).to_vec(),
+        },
+        TransferStep: {
+            type: "object",
+            properties: {
+                type: { enum: EdgeKind::ALL.map(|k| k.as_str()).to_vec() },
+                from: reference("Address"),
+                to: reference("Address"),
+                token_owner: reference("Address"),
+                token_type: reference("TokenType"),
+                value: reference("Value"),
+            },
+        },
+        GraphVersion: {
+            description: "The version of the edges the result was computed on, incremented on each load or update.",
+            type: "integer",
+        },
+        TransferResult: {
+            type: "object",
+            properties: {
+                maxFlowValue: reference("Value"),
+                final: {
+                    description: "Whether this is the last result of the call.",
+                    type: "boolean",
+                },
+                timedOut: {
+                    description: "Whether the computation was stopped early.",
+                    type: "boolean",
+                },
+                graphVersion: reference("GraphVersion"),
+                blockNumber: { type: "integer" },
+                transferSteps: transfer_steps(),
+                transferCount: {
+                    description: "The total number of transfer steps, with `offset` or `limit`.",
+                    type: "integer",
+                },
+                minCut: {
+                    description: "The bottlenecks, if less than the requested value can be transferred.",
+                    type: "array",
+                    items: { type: "object" },
+                },
+                balancesVerified: {
+                    description: "Whether the balances were checked on-chain, with `verify_balances`.",
+                    type: "boolean",
+                },
+                staleBalances: {
+                    description: "The balances that were smaller on-chain and corrected, with `verify_balances`.",
+                    type: "array",
+                    items: {
+                        type: "object",
+                        properties: {
+                            holder: reference("Address"),
+                            token_owner: reference("Address"),
+                            balance: reference("Value"),
+                        },
+                    },
+                },
+            },
+        },
+    }
+}
+
+/// A method description being built.
+struct Method(JsonValue);
+
+impl Method {
+    /// Marks the parameters as passed in an array instead of an object.
+    fn by_position(mut self) -> Method {
+        self.0["paramStructure"] = "by-position".into();
+        self
+    }
+
+    /// Adds the optional `chain_id` to methods with named parameters, which all select
+    /// the network with it.
+    fn with_chain_id(mut self) -> Method {
+        if self.0["paramStructure"] == "by-name" && self.0["name"] != "rpc_describe" {
+            let chain_id = param(
+                "chain_id",
+                "The network, by default the main one.",
+                json::object! { type: "integer", minimum: 0 },
+                false,
+            );
+            self.0["params"].push(chain_id).unwrap();
+        }
+        self
+    }
+
+    /// Adds the optional `graph_version` and `at_block` to the methods that query the edges.
+    fn with_snapshot_params(mut self) -> Method {
+        if QUERY_METHODS.iter().any(|name| self.0["name"] == *name) {
+            let graph_version = param(
+                "graph_version",
+                "The version of the edges to query, the current one by default. Older \
+                 versions are available as long as they are retained.",
+                reference("GraphVersion"),
+                false,
+            );
+            let at_block = param(
+                "at_block",
+                "Queries the archived edges of the latest block at or before this one instead.",
+                json::object! { type: "integer", minimum: 0 },
+                false,
+            );
+            self.0["params"].push(graph_version).unwrap();
+            self.0["params"].push(at_block).unwrap();
+        }
+        self
+    }
+}
+
+/// The methods that query the edges and accept `graph_version` and `at_block`.
+const QUERY_METHODS: &[&str] = &[
+    "compute_transfer",
+    "compute_transfer_to_sinks",
+    "compute_transfer_from_sources",
+    "compute_alternate_routes",
+    "is_transfer_possible",
+    "compute_capacity_matrix",
+    "graph_stats",
+    "get_neighborhood",
+    "find_trust_path",
+    "verify_flow",
+];
+
+fn method<const N: usize>(
+    name: &str,
+    summary: &str,
+    params: [JsonValue; N],
+    result: JsonValue,
+) -> Method {
+    Method(json::object! {
+        name: name,
+        summary: summary,
+        paramStructure: "by-name",
+        params: params.to_vec(),
+        result: result,
+    })
+}
+
+fn load_method(name: &str, summary: &str) -> Method {
+    file_method(name, summary, edge_count())
+}
+
+fn file_method(name: &str, summary: &str, result: JsonValue) -> Method {
+    method(
+        name,
+        summary,
+        [param(
+            "file",
+            "The path of the file on the server.",
+            json::object! { type: "string" },
+            true,
+        )],
+        result,
+    )
+}
+
+fn param(name: &str, description: &str, schema: JsonValue, required: bool) -> JsonValue {
+    json::object! {
+        name: name,
+        description: description,
+        required: required,
+        schema: schema,
+    }
+}
+
+fn result(name: &str, schema: JsonValue) -> JsonValue {
+    json::object! { name: name, schema: schema }
+}
+
+fn reference(name: &str) -> JsonValue {
+    json::object! { "$ref": format!("#/components/schemas/{name}") }
+}
+
+fn addresses() -> JsonValue {
+    json::object! { type: "array", items: reference("Address") }
+}
+
+fn transfer_steps() -> JsonValue {
+    json::object! { type: "array", items: reference("TransferStep") }
+}
+
+fn edge_count() -> JsonValue {
+    result("edgeCount", json::object! { type: "integer" })
+}
+
+fn value_param() -> JsonValue {
+    param(
+        "value",
+        "The value to transfer, the maximum if omitted.",
+        reference("Value"),
+        false,
+    )
+}
+
+fn max_hops_param() -> JsonValue {
+    param(
+        "max_hops",
+        "The maximum number of hops of each path.",
+        json::object! { type: "integer", minimum: 1 },
+        false,
+    )
+}
+
+fn max_transfers_param() -> JsonValue {
+    param(
+        "max_transfers",
+        "The maximum number of transfer steps.",
+        json::object! { type: "integer", minimum: 1 },
+        false,
+    )
+}
+
+fn units_param() -> JsonValue {
+    param(
+        "units",
+        "The units of values: `atto` (the default) or `circles`.",
+        json::object! { enum: ["atto", "circles"] },
+        false,
+    )
+}
+
+fn algorithm_param() -> JsonValue {
+    param(
+        "algorithm",
+        "The flow algorithm, `ford_fulkerson` unless another default is configured.",
+        json::object! { enum: FlowAlgorithm::ALL.map(|a| a.as_str()).to_vec() },
+        false,
+    )
+}
+
+fn optimization_param() -> JsonValue {
+    param(
+        "optimization",
+        "`min_hops` prefers short transfer chains; cannot be combined with `algorithm`.",
+        json::object! { enum: ["min_hops"] },
+        false,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn describes_all_methods() {
+        let description = describe();
+        let names = description["methods"]
+            .members()
+            .map(|method| method["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        for method in super::super::METHODS {
+            assert!(names.contains(method), "{method} is not described");
+        }
+        let schemas = &description["components"]["schemas"];
+        for algorithm in FlowAlgorithm::ALL {
+            assert_eq!(algorithm.as_str().parse::<FlowAlgorithm>(), Ok(algorithm));
+        }
+        assert!(schemas["TransferStep"]["properties"]["type"]["enum"]
+            .members()
+            .any(|kind| kind == "wrapped_transfer"));
+        // All references point to a schema.
+        let text = description.dump();
+        for reference in text.split("#/components/schemas/").skip(1) {
+            let name = &reference[..reference.find('"').unwrap()];
+            assert!(!schemas[name].is_null(), "Unknown schema {name}");
+        }
+    }
+}
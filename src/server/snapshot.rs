@@ -0,0 +1,411 @@
+//! Versioned, immutable snapshots of the edges. Loads and updates build a new
+//! snapshot and swap it in atomically: queries keep the snapshot they started
+//! with, new queries get the new one. The last few replaced snapshots can be
+//! retained, so that queries can pin an older version.
+//!
+//! Changes can be persisted: loaded edges are written as a snapshot and updates are
+//! logged, so that the edges are restored on restart, see `io::wal`. With the data
+//! store `sqlite`, the snapshot and the log are tables of a SQLite database instead,
+//! see `io::SqliteEdgeStore`, and with `rocksdb` column families of a RocksDB
+//! database, see `io::RocksDbEdgeStore`.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use arc_swap::ArcSwap;
+
+use super::archive::Archive;
+use crate::config::DataStore;
+use crate::io::read_edges_binary;
+use crate::io::wal::{Change, WriteAheadLog};
+#[cfg(feature = "rocksdb")]
+use crate::io::RocksDbEdgeStore;
+#[cfg(feature = "sqlite")]
+use crate::io::SqliteEdgeStore;
+use crate::stats::GraphStats;
+use crate::types::edge::EdgeDB;
+
+/// The names of the snapshot and of the log in the data directory.
+const SNAPSHOT_FILE: &str = "edges.dat";
+const LOG_FILE: &str = "updates.wal";
+/// The name of the database of the data store `sqlite`.
+#[cfg(feature = "sqlite")]
+const DATABASE_FILE: &str = "edges.sqlite";
+/// The name of the database directory of the data store `rocksdb`.
+#[cfg(feature = "rocksdb")]
+const DATABASE_DIRECTORY: &str = "edges.rocksdb";
+
+/// The edges at one version of the graph.
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    /// Incremented whenever the edges are loaded or updated.
+    pub version: u64,
+    pub edges: Arc<EdgeDB>,
+    stats: OnceLock<GraphStats>,
+}
+
+impl Snapshot {
+    /// A snapshot that is not a version of a graph, e.g. read from the archive.
+    pub fn unversioned(edges: EdgeDB) -> Snapshot {
+        Snapshot {
+            version: 0,
+            edges: Arc::new(edges),
+            stats: OnceLock::new(),
+        }
+    }
+
+    /// The statistics of the edges, computed on first use.
+    pub fn stats(&self) -> &GraphStats {
+        self.stats.get_or_init(|| GraphStats::compute(&self.edges))
+    }
+}
+
+#[derive(Default)]
+pub struct VersionedGraph {
+    current: ArcSwap<Snapshot>,
+    /// Serializes changes, so that no update is lost when two are applied concurrently,
+    /// and they are logged in the order they are applied.
+    changing: Mutex<Option<Persistence>>,
+    /// The replaced snapshots that are kept, the oldest first.
+    retained: Mutex<Retained>,
+    /// Where loaded edges are archived, if anywhere.
+    archive: Option<Archive>,
+}
+
+#[derive(Default)]
+struct Retained {
+    snapshots: VecDeque<Arc<Snapshot>>,
+    /// The number of snapshots to keep.
+    capacity: usize,
+}
+
+impl Retained {
+    fn push(&mut self, snapshot: Arc<Snapshot>) {
+        self.snapshots.push_back(snapshot);
+        self.truncate();
+    }
+
+    fn truncate(&mut self) {
+        while self.snapshots.len() > self.capacity {
+            self.snapshots.pop_front();
+        }
+    }
+}
+
+/// The snapshot of the last loaded edges and the log of the changes since then.
+struct Persistence {
+    store: Store,
+    /// The number of logged changes after which a new snapshot is written.
+    compact_after: usize,
+}
+
+enum Store {
+    Files {
+        snapshot: String,
+        log: WriteAheadLog,
+    },
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqliteEdgeStore),
+    #[cfg(feature = "rocksdb")]
+    RocksDb(RocksDbEdgeStore),
+}
+
+impl Store {
+    fn append(&mut self, changes: &[Change]) -> Result<(), io::Error> {
+        match self {
+            Store::Files { log, .. } => log.append(changes),
+            #[cfg(feature = "sqlite")]
+            Store::Sqlite(store) => store.append(changes),
+            #[cfg(feature = "rocksdb")]
+            Store::RocksDb(store) => store.append(changes),
+        }
+    }
+
+    /// Writes the edges as the new snapshot and empties the log.
+    fn compact(&mut self, edges: &EdgeDB) -> Result<(), io::Error> {
+        match self {
+            Store::Files { snapshot, log } => log.compact(edges, snapshot),
+            #[cfg(feature = "sqlite")]
+            Store::Sqlite(store) => store.replace(edges),
+            #[cfg(feature = "rocksdb")]
+            Store::RocksDb(store) => store.replace(edges),
+        }
+    }
+
+    /// The number of logged changes.
+    fn len(&self) -> usize {
+        match self {
+            Store::Files { log, .. } => log.len(),
+            #[cfg(feature = "sqlite")]
+            Store::Sqlite(store) => store.len(),
+            #[cfg(feature = "rocksdb")]
+            Store::RocksDb(store) => store.len(),
+        }
+    }
+}
+
+impl VersionedGraph {
+    /// Restores the edges from the snapshot and the log in `directory` (if any) and
+    /// persists all changes there, in the files or the database of `data_store`.
+    pub fn persistent(
+        directory: &str,
+        data_store: DataStore,
+        compact_after: usize,
+    ) -> Result<VersionedGraph, io::Error> {
+        fs::create_dir_all(directory)?;
+        let path = |name: &str| {
+            Path::new(directory)
+                .join(name)
+                .to_string_lossy()
+                .into_owned()
+        };
+        let (mut edges, changes, store) = match data_store {
+            DataStore::Files => {
+                let snapshot = path(SNAPSHOT_FILE);
+                let edges = match Path::new(&snapshot).exists() {
+                    true => read_edges_binary(&snapshot)?,
+                    false => EdgeDB::default(),
+                };
+                let (log, changes) = WriteAheadLog::open(&path(LOG_FILE))?;
+                (edges, changes, Store::Files { snapshot, log })
+            }
+            #[cfg(feature = "sqlite")]
+            DataStore::Sqlite => {
+                let store = SqliteEdgeStore::open(&path(DATABASE_FILE))?;
+                (store.edges()?, store.changes()?, Store::Sqlite(store))
+            }
+            #[cfg(not(feature = "sqlite"))]
+            DataStore::Sqlite => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "The data store sqlite needs the feature sqlite.",
+                ))
+            }
+            #[cfg(feature = "rocksdb")]
+            DataStore::RocksDb => {
+                let store = RocksDbEdgeStore::open(&path(DATABASE_DIRECTORY))?;
+                (store.edges()?, store.changes()?, Store::RocksDb(store))
+            }
+            #[cfg(not(feature = "rocksdb"))]
+            DataStore::RocksDb => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "The data store rocksdb needs the feature rocksdb.",
+                ))
+            }
+        };
+        for change in &changes {
+            change.apply(&mut edges);
+        }
+        Ok(VersionedGraph {
+            current: ArcSwap::from_pointee(Snapshot {
+                version: 0,
+                edges: Arc::new(edges),
+                stats: OnceLock::new(),
+            }),
+            changing: Mutex::new(Some(Persistence {
+                store,
+                compact_after,
+            })),
+            retained: Default::default(),
+            archive: None,
+        })
+    }
+
+    /// The current snapshot, without waiting for changes in progress.
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.current.load_full()
+    }
+
+    /// Archives all edges that are loaded from now on.
+    pub fn with_archive(self, archive: Archive) -> VersionedGraph {
+        VersionedGraph {
+            archive: Some(archive),
+            ..self
+        }
+    }
+
+    pub fn archive(&self) -> Option<&Archive> {
+        self.archive.as_ref()
+    }
+
+    /// The snapshot of `version`, if it is the current one or one of the retained ones.
+    pub fn snapshot_at(&self, version: u64) -> Option<Arc<Snapshot>> {
+        let current = self.snapshot();
+        if current.version == version {
+            return Some(current);
+        }
+        let retained = self.retained.lock().unwrap();
+        retained
+            .snapshots
+            .iter()
+            .find(|snapshot| snapshot.version == version)
+            .cloned()
+    }
+
+    /// Keeps the last `versions` replaced snapshots, so that they can still be queried.
+    /// Each one holds a copy of the edges.
+    pub fn set_retained_versions(&self, versions: usize) {
+        let mut retained = self.retained.lock().unwrap();
+        retained.capacity = versions;
+        retained.truncate();
+    }
+
+    /// Replaces the edges by new ones, under a new version. If changes are persisted,
+    /// the edges are first written as the new snapshot. They are archived afterwards,
+    /// if configured, failures to archive are logged.
+    pub fn replace(&self, edges: EdgeDB) -> Result<Arc<Snapshot>, io::Error> {
+        let mut persistence = self.changing.lock().unwrap();
+        if let Some(persistence) = persistence.as_mut() {
+            persistence.store.compact(&edges)?;
+        }
+        let snapshot = self.swap(edges, 1);
+        if let Some(archive) = &self.archive {
+            if let Err(e) = archive.store(&snapshot.edges) {
+                tracing::error!("Error archiving the edges: {e}");
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Applies the changes to a copy of the current edges and swaps the result in under
+    /// a new version. If changes are persisted, they are first logged, and a new snapshot
+    /// is written once enough changes are logged. Nothing changes if logging fails.
+    pub fn apply(&self, changes: &[Change]) -> Result<Arc<Snapshot>, io::Error> {
+        let mut persistence = self.changing.lock().unwrap();
+        let mut edges = self.current.load().edges.as_ref().clone();
+        for change in changes {
+            change.apply(&mut edges);
+        }
+        if let Some(persistence) = persistence.as_mut() {
+            persistence.store.append(changes)?;
+        }
+        let snapshot = self.swap(edges, 1);
+        if let Some(persistence) = persistence
+            .as_mut()
+            .filter(|p| p.store.len() >= p.compact_after)
+        {
+            // The changes are logged already, so the snapshot can be written later.
+            if let Err(e) = persistence.store.compact(&snapshot.edges) {
+                tracing::error!("Error writing a snapshot of the edges: {e}");
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Like `update`, but keeps the version, for changes that do not invalidate results
+    /// computed for it. `update` returns None if no change is needed (anymore).
+    pub fn refresh(&self, update: impl FnOnce(&EdgeDB) -> Option<EdgeDB>) -> Arc<Snapshot> {
+        let _changing = self.changing.lock().unwrap();
+        match update(&self.current.load().edges) {
+            Some(edges) => self.swap(edges, 0),
+            None => self.snapshot(),
+        }
+    }
+
+    fn swap(&self, edges: EdgeDB, increment: u64) -> Arc<Snapshot> {
+        let snapshot = Arc::new(Snapshot {
+            version: self.current.load().version + increment,
+            edges: Arc::new(edges),
+            stats: OnceLock::new(),
+        });
+        let replaced = self.current.swap(snapshot.clone());
+        if increment > 0 {
+            self.retained.lock().unwrap().push(replaced);
+        }
+        snapshot
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::edge::edge;
+    use std::env;
+    use std::thread;
+
+    #[test]
+    fn versions() {
+        let graph = VersionedGraph::default();
+        let empty = graph.snapshot();
+        assert_eq!(empty.version, 0);
+
+        let loaded = graph
+            .replace(EdgeDB::new(vec![edge([1; 20], [2; 20], [1; 20], 1)]))
+            .unwrap();
+        assert_eq!(loaded.version, 1);
+        // Earlier snapshots are not affected.
+        assert_eq!(empty.edges.edge_count(), 0);
+
+        // Concurrent updates are all applied.
+        thread::scope(|scope| {
+            for i in 2..10 {
+                let graph = &graph;
+                scope.spawn(move || {
+                    graph.apply(&[Change::Update {
+                        edge: edge([i; 20], [i + 1; 20], [i; 20], 1),
+                        block_number: None,
+                        token_type: None,
+                    }])
+                });
+            }
+        });
+        let updated = graph.snapshot();
+        assert_eq!(updated.version, 9);
+        assert_eq!(updated.edges.edge_count(), 9);
+
+        assert_eq!(graph.refresh(|edges| Some(edges.clone())).version, 9);
+
+        // Persisted edges are restored from the snapshot and the logged changes.
+        let directory = env::temp_dir()
+            .join(format!("pathfinder_graph_{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let persistent = VersionedGraph::persistent(&directory, DataStore::Files, 2).unwrap();
+        persistent
+            .replace(EdgeDB::new(vec![edge([1; 20], [2; 20], [1; 20], 1)]))
+            .unwrap();
+        for i in 2..5 {
+            persistent
+                .apply(&[Change::Update {
+                    edge: edge([i; 20], [i + 1; 20], [i; 20], 1),
+                    block_number: Some(i as u64),
+                    token_type: None,
+                }])
+                .unwrap();
+        }
+        drop(persistent);
+        let restored = VersionedGraph::persistent(&directory, DataStore::Files, 2)
+            .unwrap()
+            .snapshot();
+        assert_eq!(restored.edges.edge_count(), 4);
+        assert_eq!(restored.edges.block_number(), Some(4));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn retained_versions() {
+        let graph = VersionedGraph::default();
+        graph.set_retained_versions(2);
+        for i in 1..5 {
+            graph
+                .replace(EdgeDB::new(vec![edge([i; 20], [i + 1; 20], [i; 20], 1)]))
+                .unwrap();
+        }
+        assert_eq!(graph.snapshot().version, 4);
+        assert!(graph.snapshot_at(1).is_none());
+        let pinned = graph.snapshot_at(2).unwrap();
+        assert_eq!(pinned.edges.edges(), &[edge([2; 20], [3; 20], [2; 20], 1)]);
+        assert!(graph.snapshot_at(4).is_some());
+        assert!(graph.snapshot_at(5).is_none());
+        // Refreshes keep the version and retain nothing.
+        graph.refresh(|edges| Some(edges.clone()));
+        assert!(graph.snapshot_at(2).is_some());
+        graph.set_retained_versions(1);
+        assert!(graph.snapshot_at(2).is_none());
+        assert!(graph.snapshot_at(3).is_some());
+    }
+}
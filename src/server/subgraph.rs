@@ -0,0 +1,64 @@
+//! Periodic import of the edges from the Circles subgraph (if the edge source is
+//! `subgraph`) at `subgraph_url`, every `refresh_interval_secs`. The edges are only
+//! imported if the subgraph has indexed new blocks since the last import, and then
+//! replace the current ones like `load_safes_json`.
+
+use super::network::Network;
+use super::Server;
+use crate::config::EdgeSource;
+use crate::safe_db::subgraph::Subgraph;
+use std::error::Error;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+/// Imports the edges of the network right away and then after each refresh interval, on its
+/// own thread.
+/// Changes of the URL and the interval apply from the next import.
+pub fn start(server: Arc<Server>, chain_id: u64) {
+    thread::spawn(move || {
+        let network = server.networks.get(chain_id).unwrap();
+        let mut last_block = None;
+        loop {
+            let (url, interval) = {
+                // A network removed from the configuration is no longer updated.
+                let config = server.network_config(chain_id).unwrap_or_default();
+                let url = config.subgraph_url.clone();
+                let url = url.filter(|_| config.edge_source == EdgeSource::Subgraph);
+                (url, config.refresh_interval)
+            };
+            if let Some(url) = url {
+                if let Err(e) = import(&server, network, &Subgraph::new(&url), &mut last_block) {
+                    tracing::error!("Error importing edges from {url}: {e}");
+                }
+            }
+            thread::sleep(interval);
+        }
+    });
+}
+
+fn import(
+    server: &Server,
+    network: &Network,
+    subgraph: &Subgraph,
+    last_block: &mut Option<u64>,
+) -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    let block_number = subgraph.block_number()?;
+    if last_block.is_some_and(|last| last >= block_number) {
+        return Ok(());
+    }
+    let mut edges = subgraph.import(block_number)?.edges_with_trust_limits();
+    edges.set_block_number(block_number);
+    let snapshot = network.graph.replace(edges)?;
+    *last_block = Some(block_number);
+    server.record_load(start);
+    server.graph_updated(network, &snapshot);
+    tracing::info!(
+        "Imported {} edges of chain {} at block {block_number} from the subgraph, graph version {}.",
+        snapshot.edges.edge_count(),
+        network.chain_id,
+        snapshot.version
+    );
+    Ok(())
+}
@@ -0,0 +1,82 @@
+//! Graceful shutdown: on SIGTERM (or SIGINT), the server stops accepting connections
+//! and waits for the connections it already accepted before exiting.
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Calls `on_signal` on a separate thread when the process is asked to terminate.
+pub fn on_termination(on_signal: impl FnOnce() + Send + 'static) -> io::Result<()> {
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    thread::spawn(move || {
+        if signals.forever().next().is_some() {
+            on_signal();
+        }
+    });
+    Ok(())
+}
+
+/// Counts the requests in progress, so that shutdown can wait for them.
+#[derive(Default)]
+pub struct InFlight {
+    count: Mutex<usize>,
+    finished: Condvar,
+}
+
+impl InFlight {
+    /// Counts a request until the returned guard is dropped.
+    pub fn start(self: &Arc<Self>) -> InFlightGuard {
+        *self.count.lock().unwrap() += 1;
+        InFlightGuard {
+            in_flight: self.clone(),
+        }
+    }
+
+    /// Waits until no request is in progress or the timeout is reached and
+    /// returns the number of requests still in progress.
+    pub fn wait(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        let mut count = self.count.lock().unwrap();
+        while *count > 0 {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            count = self.finished.wait_timeout(count, remaining).unwrap().0;
+        }
+        *count
+    }
+}
+
+pub struct InFlightGuard {
+    in_flight: Arc<InFlight>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        *self.in_flight.count.lock().unwrap() -= 1;
+        self.in_flight.finished.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn waits_for_requests() {
+        let in_flight = Arc::new(InFlight::default());
+        assert_eq!(in_flight.wait(Duration::from_secs(10)), 0);
+        let guard = in_flight.start();
+        assert_eq!(in_flight.wait(Duration::from_millis(10)), 1);
+        let worker = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(guard);
+        });
+        assert_eq!(in_flight.wait(Duration::from_secs(10)), 0);
+        worker.join().unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+//! Statistics of the trust graph for monitoring and analysis: its size, the distribution
+//! of the degrees of the accounts, its strongly connected components and its hubs.
+
+use json::JsonValue;
+
+use crate::types::edge::EdgeDB;
+use crate::types::{Address, U256};
+
+/// The number of hubs kept by `GraphStats::compute`.
+pub const MAX_HUBS: usize = 100;
+
+/// The statistics of the edges with a capacity. The degree of an account is the number
+/// of its incoming and outgoing edges.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GraphStats {
+    pub nodes: usize,
+    pub edges: usize,
+    pub total_capacity: U256,
+    pub min_degree: usize,
+    pub max_degree: usize,
+    pub mean_degree: f64,
+    pub median_degree: usize,
+    pub strongly_connected_components: usize,
+    /// The number of accounts in the largest strongly connected component.
+    pub largest_component: usize,
+    /// The accounts with the highest degree, at most `MAX_HUBS`, with their
+    /// incoming and outgoing degree.
+    pub hubs: Vec<(Address, usize, usize)>,
+}
+
+impl GraphStats {
+    pub fn compute(edges: &EdgeDB) -> GraphStats {
+        let addresses = edges.addresses();
+        let mut successors = vec![vec![]; addresses.len()];
+        let mut in_degrees = vec![0; addresses.len()];
+        let mut stats = GraphStats {
+            nodes: addresses.len(),
+            ..GraphStats::default()
+        };
+        for edge in edges.effective_edges() {
+            if edge.capacity == U256::from(0u128) {
+                continue;
+            }
+            let id = |address| addresses.id(address).expect("Edge without id").index();
+            let (from, to) = (id(&edge.from), id(&edge.to));
+            successors[from].push(to);
+            in_degrees[to] += 1;
+            stats.edges += 1;
+            stats.total_capacity = stats.total_capacity.saturating_add(edge.capacity);
+        }
+        let mut degrees = (0..addresses.len())
+            .map(|i| in_degrees[i] + successors[i].len())
+            .collect::<Vec<_>>();
+        let mut hubs = (0..addresses.len()).collect::<Vec<_>>();
+        hubs.sort_by_key(|i| (usize::MAX - degrees[*i], addresses.addresses()[*i]));
+        stats.hubs = hubs
+            .into_iter()
+            .take(MAX_HUBS)
+            .filter(|i| degrees[*i] > 0)
+            .map(|i| (addresses.addresses()[i], in_degrees[i], successors[i].len()))
+            .collect();
+        degrees.sort_unstable();
+        if !degrees.is_empty() {
+            stats.min_degree = degrees[0];
+            stats.max_degree = degrees[degrees.len() - 1];
+            stats.mean_degree = degrees.iter().sum::<usize>() as f64 / degrees.len() as f64;
+            stats.median_degree = degrees[degrees.len() / 2];
+        }
+        let components = strongly_connected_components(&successors);
+        stats.strongly_connected_components = components.len();
+        stats.largest_component = components.into_iter().max().unwrap_or_default();
+        stats
+    }
+
+    /// The statistics with the first `hubs` hubs.
+    pub fn to_json(&self, hubs: usize) -> JsonValue {
+        json::object! {
+            nodes: self.nodes,
+            edges: self.edges,
+            totalCapacity: self.total_capacity.to_decimal(),
+            degrees: {
+                min: self.min_degree,
+                max: self.max_degree,
+                mean: self.mean_degree,
+                median: self.median_degree,
+            },
+            stronglyConnectedComponents: self.strongly_connected_components,
+            largestComponent: self.largest_component,
+            hubs: self.hubs.iter().take(hubs).map(|(address, incoming, outgoing)| json::object! {
+                address: address.to_checksummed_hex(),
+                degree: incoming + outgoing,
+                incoming: *incoming,
+                outgoing: *outgoing,
+            }).collect::<Vec<_>>(),
+        }
+    }
+}
+
+/// The sizes of the strongly connected components of the graph given by the successors
+/// of each node (Tarjan's algorithm, without recursion to not overflow the stack).
+fn strongly_connected_components(successors: &[Vec<usize>]) -> Vec<usize> {
+    const UNVISITED: usize = usize::MAX;
+    let mut index = vec![UNVISITED; successors.len()];
+    let mut low = vec![0; successors.len()];
+    let mut on_stack = vec![false; successors.len()];
+    let mut stack = vec![];
+    let mut next_index = 0;
+    let mut components = vec![];
+    for root in 0..successors.len() {
+        if index[root] != UNVISITED {
+            continue;
+        }
+        // The nodes of the depth-first search with the position of their next successor.
+        let mut path = vec![(root, 0)];
+        index[root] = next_index;
+        low[root] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+        while let Some(&(node, position)) = path.last() {
+            if let Some(&next) = successors[node].get(position) {
+                path.last_mut().unwrap().1 += 1;
+                if index[next] == UNVISITED {
+                    index[next] = next_index;
+                    low[next] = next_index;
+                    next_index += 1;
+                    stack.push(next);
+                    on_stack[next] = true;
+                    path.push((next, 0));
+                } else if on_stack[next] {
+                    low[node] = low[node].min(index[next]);
+                }
+                continue;
+            }
+            path.pop();
+            if let Some(&(parent, _)) = path.last() {
+                low[parent] = low[parent].min(low[node]);
+            }
+            if low[node] == index[node] {
+                let mut size = 0;
+                while let Some(member) = stack.pop() {
+                    on_stack[member] = false;
+                    size += 1;
+                    if member == node {
+                        break;
+                    }
+                }
+                components.push(size);
+            }
+        }
+    }
+    components
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::types::edge::edge;
+
+    #[test]
+    fn stats() {
+        let address = |i: u8| Address::from([i; 20]);
+        // A cycle of 1, 2 and 3, and 3 -> 4 -> 5. 5 -> 1 has no capacity.
+        let edges = EdgeDB::new(vec![
+            edge([1; 20], [2; 20], [1; 20], 10),
+            edge([2; 20], [3; 20], [2; 20], 20),
+            edge([3; 20], [1; 20], [3; 20], 30),
+            edge([3; 20], [4; 20], [3; 20], 40),
+            edge([4; 20], [5; 20], [4; 20], 50),
+            edge([5; 20], [1; 20], [5; 20], 0),
+        ]);
+        let stats = GraphStats::compute(&edges);
+        assert_eq!(stats.nodes, 5);
+        assert_eq!(stats.edges, 5);
+        assert_eq!(stats.total_capacity, U256::from(150u128));
+        assert_eq!((stats.min_degree, stats.max_degree), (1, 3));
+        assert_eq!((stats.mean_degree, stats.median_degree), (2.0, 2));
+        assert_eq!(stats.strongly_connected_components, 3);
+        assert_eq!(stats.largest_component, 3);
+        assert_eq!(stats.hubs[0], (address(3), 1, 2));
+        assert_eq!(stats.hubs.len(), 5);
+
+        let json = stats.to_json(2);
+        assert_eq!(json["hubs"].len(), 2);
+        assert_eq!(json["hubs"][1]["address"], address(1).to_checksummed_hex());
+        assert_eq!(json["degrees"]["median"], 2);
+    }
+}
@@ -0,0 +1,310 @@
+//! Generators of random Circles-like trust graphs for benchmarks, fuzzing the flow
+//! algorithms and load testing the server. The graphs are deterministic for a seed.
+
+use std::collections::BTreeMap;
+
+use crate::safe_db::db::DB;
+use crate::types::{Address, Safe, U256};
+
+/// How the accounts trust each other. Trust is always mutual, as it mostly is in Circles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Topology {
+    /// Every pair of accounts trusts each other with the probability.
+    Random { trust_probability: f64 },
+    /// Grown by preferential attachment: each new account trusts `trusts_per_account`
+    /// existing accounts, chosen with a probability proportional to their number of
+    /// trust connections, so that the degrees follow a power law.
+    ScaleFree { trusts_per_account: usize },
+}
+
+/// The distribution of balances, in whole Circles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BalanceDistribution {
+    Constant(u64),
+    Uniform {
+        min: u64,
+        max: u64,
+    },
+    /// A heavy-tailed distribution of balances of at least `min`: the larger `shape`,
+    /// the fewer large balances.
+    Pareto {
+        min: u64,
+        shape: f64,
+    },
+}
+
+/// The parameters of a generated graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphConfig {
+    pub accounts: usize,
+    pub topology: Topology,
+    /// The number of hub accounts, the first accounts, which in addition trust every
+    /// other account with `hub_trust_probability`.
+    pub hubs: usize,
+    pub hub_trust_probability: f64,
+    /// The limit percentage of all trust connections.
+    pub trust_limit: u8,
+    /// The balance of each account of its own token and, for every other account it
+    /// trusts with a probability of one half, of the token of that account.
+    pub balances: BalanceDistribution,
+    pub seed: u64,
+}
+
+impl Default for GraphConfig {
+    fn default() -> GraphConfig {
+        GraphConfig {
+            accounts: 1000,
+            topology: Topology::Random {
+                trust_probability: 0.01,
+            },
+            hubs: 0,
+            hub_trust_probability: 0.5,
+            trust_limit: 50,
+            balances: BalanceDistribution::Pareto {
+                min: 10,
+                shape: 1.5,
+            },
+            seed: 1,
+        }
+    }
+}
+
+/// The address of the `index`th generated account, which is also the address of its token.
+pub fn account(index: usize) -> Address {
+    let mut bytes = [0; 20];
+    bytes[12..].copy_from_slice(&(index as u64 + 1).to_be_bytes());
+    Address::from(bytes)
+}
+
+impl GraphConfig {
+    /// Generates the safes of the accounts `account(0)` to `account(accounts - 1)`.
+    pub fn generate(&self) -> DB {
+        let mut random = Random::new(self.seed);
+        let mut trusts = match self.topology {
+            Topology::Random { trust_probability } => {
+                random_trusts(self.accounts, trust_probability, &mut random)
+            }
+            Topology::ScaleFree { trusts_per_account } => {
+                scale_free_trusts(self.accounts, trusts_per_account, &mut random)
+            }
+        };
+        for hub in 0..self.hubs.min(self.accounts) {
+            for other in random.sample(self.accounts, self.hub_trust_probability) {
+                trusts.push((hub, other));
+            }
+        }
+
+        let mut safes = (0..self.accounts)
+            .map(|i| {
+                let mut safe = Safe {
+                    token_address: account(i),
+                    ..Safe::default()
+                };
+                let balance = random.balance(&self.balances);
+                safe.balances.insert(account(i), balance);
+                (account(i), safe)
+            })
+            .collect::<BTreeMap<_, _>>();
+        for (a, b) in trusts {
+            if a == b {
+                continue;
+            }
+            for (truster, trusted) in [(a, b), (b, a)] {
+                let safe = safes.get_mut(&account(trusted)).unwrap();
+                if safe
+                    .limit_percentage
+                    .insert(account(truster), self.trust_limit)
+                    .is_none()
+                    && random.below(2) == 0
+                {
+                    let balance = random.balance(&self.balances);
+                    safes
+                        .get_mut(&account(truster))
+                        .unwrap()
+                        .balances
+                        .insert(account(trusted), balance);
+                }
+            }
+        }
+        let token_owner = safes.keys().map(|address| (*address, *address)).collect();
+        DB::new(safes, token_owner)
+    }
+}
+
+/// The pairs of accounts that trust each other in a random graph, by skipping ahead a
+/// geometrically distributed number of candidates, which takes time proportional to the
+/// number of pairs instead of the number of candidates.
+fn random_trusts(accounts: usize, probability: f64, random: &mut Random) -> Vec<(usize, usize)> {
+    (0..accounts)
+        .flat_map(|a| {
+            random
+                .sample(a, probability)
+                .into_iter()
+                .map(move |b| (a, b))
+        })
+        .collect()
+}
+
+fn scale_free_trusts(
+    accounts: usize,
+    trusts_per_account: usize,
+    random: &mut Random,
+) -> Vec<(usize, usize)> {
+    // Every account appears once per trust connection, so that uniform choices from it
+    // are proportional to the degree.
+    let mut endpoints = vec![];
+    let mut trusts = vec![];
+    for new in 0..accounts {
+        let count = trusts_per_account.min(new);
+        for _ in 0..count {
+            let existing = endpoints
+                .get(random.below(endpoints.len().max(1) as u64) as usize)
+                .copied()
+                .unwrap_or(0);
+            trusts.push((new, existing));
+            endpoints.extend([new, existing]);
+        }
+        if count == 0 {
+            endpoints.push(new);
+        }
+    }
+    trusts
+}
+
+/// A deterministic xorshift generator.
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Random {
+        Random(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    /// A uniform value in [0, 1).
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// The numbers below `count` that are each chosen with the probability.
+    fn sample(&mut self, count: usize, probability: f64) -> Vec<usize> {
+        let mut chosen = vec![];
+        if probability <= 0.0 {
+            return chosen;
+        }
+        let mut next = 0usize;
+        loop {
+            // The number of candidates skipped until the next chosen one, always
+            // zero for a probability of one.
+            let skip = ((1.0 - self.unit()).ln() / (1.0 - probability).ln()).floor();
+            next = next.saturating_add(skip as usize);
+            if next >= count {
+                return chosen;
+            }
+            chosen.push(next);
+            next += 1;
+        }
+    }
+
+    fn balance(&mut self, distribution: &BalanceDistribution) -> U256 {
+        let circles = match *distribution {
+            BalanceDistribution::Constant(value) => value,
+            BalanceDistribution::Uniform { min, max } => {
+                min + self.below(max.saturating_sub(min).saturating_add(1).max(1))
+            }
+            BalanceDistribution::Pareto { min, shape } => {
+                (min as f64 / (1.0 - self.unit()).powf(1.0 / shape)) as u64
+            }
+        };
+        U256::from(circles as u128 * 1_000_000_000_000_000_000)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::graph::{compute_flow_with_options, FlowAlgorithm, FlowOptions};
+
+    #[test]
+    fn generate() {
+        let config = GraphConfig {
+            accounts: 200,
+            topology: Topology::ScaleFree {
+                trusts_per_account: 2,
+            },
+            hubs: 2,
+            hub_trust_probability: 0.5,
+            ..GraphConfig::default()
+        };
+        let db = config.generate();
+        assert_eq!(db.safes().len(), 200);
+        assert_eq!(
+            db.edges().edges(),
+            config.generate().edges().edges(),
+            "Generated graphs are deterministic"
+        );
+        let trusted_by = |i| db.safes()[&account(i)].limit_percentage.len();
+        assert!(trusted_by(0) > 50);
+        assert!(trusted_by(199) < 50);
+
+        let random = GraphConfig {
+            accounts: 100,
+            topology: Topology::Random {
+                trust_probability: 0.1,
+            },
+            balances: BalanceDistribution::Uniform { min: 5, max: 10 },
+            ..GraphConfig::default()
+        }
+        .generate();
+        let trusts = random
+            .safes()
+            .values()
+            .map(|safe| safe.limit_percentage.len())
+            .sum::<usize>();
+        // About 2 * 0.1 * 100 * 99 / 2 mutual trusts.
+        assert!((800..1200).contains(&trusts), "{trusts}");
+        let own_balance = random.safes()[&account(7)].balance(&account(7));
+        assert!(own_balance >= U256::from(5_000_000_000_000_000_000u128));
+        assert!(own_balance <= U256::from(10_000_000_000_000_000_000u128));
+    }
+
+    #[test]
+    fn algorithms_agree_on_random_graphs() {
+        for seed in 1..=5 {
+            let db = GraphConfig {
+                accounts: 40,
+                topology: Topology::Random {
+                    trust_probability: 0.1,
+                },
+                hubs: 1,
+                seed,
+                ..GraphConfig::default()
+            }
+            .generate();
+            for (from, to) in [(0, 39), (5, 17), (30, 2)] {
+                let flows = FlowAlgorithm::ALL.map(|algorithm| {
+                    let options = FlowOptions {
+                        algorithm,
+                        ..FlowOptions::default()
+                    };
+                    compute_flow_with_options(&account(from), &account(to), db.edges(), &options)
+                        .unwrap()
+                        .flow
+                });
+                assert!(
+                    flows.iter().all(|flow| *flow == flows[0]),
+                    "Seed {seed}, {from} -> {to}: {flows:?}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! Optional TLS termination for the server.
+//!
+//! If the environment variables `PATHFINDER_TLS_CERT` and `PATHFINDER_TLS_KEY` are set to
+//! the paths of a PEM certificate chain and private key, connections are served over TLS.
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use std::env;
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+
+/// Loads the certificate chain and private key given by the environment, if any.
+pub fn config_from_env() -> Result<Option<Arc<ServerConfig>>, Box<dyn Error>> {
+    match (
+        env::var("PATHFINDER_TLS_CERT"),
+        env::var("PATHFINDER_TLS_KEY"),
+    ) {
+        (Ok(cert), Ok(key)) => Ok(Some(load_config(&cert, &key)?)),
+        (Err(_), Err(_)) => Ok(None),
+        _ => Err("PATHFINDER_TLS_CERT and PATHFINDER_TLS_KEY have to be set together".into()),
+    }
+}
+
+pub fn load_config(cert_path: &str, key_path: &str) -> Result<Arc<ServerConfig>, Box<dyn Error>> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .map_err(|e| format!("Error reading certificates from {cert_path}: {e}"))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| format!("Error reading private key from {key_path}: {e}"))?;
+    let config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+    Ok(Arc::new(config))
+}
+
+/// A client connection, encrypted or not.
+pub enum Connection {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ServerConnection, TcpStream>>),
+}
+
+impl Connection {
+    pub fn new(socket: TcpStream, config: Option<&Arc<ServerConfig>>) -> io::Result<Connection> {
+        match config {
+            None => Ok(Connection::Plain(socket)),
+            Some(config) => {
+                let connection = ServerConnection::new(config.clone()).map_err(io::Error::other)?;
+                Ok(Connection::Tls(Box::new(StreamOwned::new(
+                    connection, socket,
+                ))))
+            }
+        }
+    }
+
+    /// Ends the connection, for TLS with a `close_notify` alert.
+    pub fn close(&mut self) {
+        if let Connection::Tls(stream) = self {
+            stream.conn.send_close_notify();
+            let _ = stream.conn.complete_io(&mut stream.sock);
+        }
+    }
+
+    /// Splits the connection into a reading and a writing half that can be used
+    /// from different threads.
+    pub fn split(self) -> io::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+        match self {
+            Connection::Plain(socket) => {
+                let writer = socket.try_clone()?;
+                Ok((Box::new(socket), Box::new(writer)))
+            }
+            Connection::Tls(stream) => {
+                let StreamOwned { conn, sock } = *stream;
+                let conn = Arc::new(Mutex::new(conn));
+                let writer = TlsWriter {
+                    conn: conn.clone(),
+                    socket: sock.try_clone()?,
+                };
+                let reader = TlsReader {
+                    conn,
+                    socket: sock,
+                    buffer: vec![0; 16 * 1024],
+                };
+                Ok((Box::new(reader), Box::new(writer)))
+            }
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(socket) => socket.read(buf),
+            Connection::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(socket) => socket.write(buf),
+            Connection::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Plain(socket) => socket.flush(),
+            Connection::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// The reading half of a TLS connection. The socket is read without holding the
+/// lock on the TLS state, so that the writing half is not blocked while waiting.
+struct TlsReader {
+    conn: Arc<Mutex<ServerConnection>>,
+    socket: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl Read for TlsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.conn.lock().unwrap().reader().read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+            let length = self.socket.read(&mut self.buffer)?;
+            if length == 0 {
+                return Ok(0);
+            }
+            let mut conn = self.conn.lock().unwrap();
+            let mut received = &self.buffer[..length];
+            while !received.is_empty() {
+                conn.read_tls(&mut received)?;
+                conn.process_new_packets().map_err(io::Error::other)?;
+            }
+            // Alerts and key updates are answered right away.
+            while conn.wants_write() {
+                conn.write_tls(&mut self.socket)?;
+            }
+        }
+    }
+}
+
+/// The writing half of a TLS connection.
+struct TlsWriter {
+    conn: Arc<Mutex<ServerConnection>>,
+    socket: TcpStream,
+}
+
+impl Write for TlsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let length = conn.writer().write(buf)?;
+        while conn.wants_write() {
+            conn.write_tls(&mut self.socket)?;
+        }
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn missing_certificate() {
+        let error = load_config("missing_cert.pem", "missing_key.pem").unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Error reading certificates from missing_cert.pem"));
+    }
+}
@@ -1,6 +1,8 @@
 use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
 
 #[derive(Clone, Copy, Default, Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[repr(transparent)]
 pub struct Address([u8; 20]);
 
 impl Address {
@@ -12,11 +14,40 @@ impl Address {
         self.0
     }
 
+    /// The address in the mixed-case checksum encoding of EIP-55, as used in responses.
+    #[cfg(feature = "native")]
     pub fn to_checksummed_hex(&self) -> String {
         eth_checksum::checksum(&self.to_string())
     }
 }
 
+/// Parses an address in hex with an optional `0x` prefix. Addresses in a single case
+/// are accepted as they are, mixed-case addresses have to carry a valid EIP-55 checksum
+/// (which is only checked with the feature `native`).
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix("0x").unwrap_or(s);
+        if hex.len() != 40 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("Invalid address: {s}"));
+        }
+        let address = Address::from(hex);
+        #[cfg(feature = "native")]
+        {
+            let mixed_case = hex.bytes().any(|b| b.is_ascii_lowercase())
+                && hex.bytes().any(|b| b.is_ascii_uppercase());
+            let checksummed = address.to_checksummed_hex();
+            if mixed_case && hex != &checksummed[2..] {
+                return Err(format!(
+                    "Invalid EIP-55 checksum of address {s}, expected {checksummed}"
+                ));
+            }
+        }
+        Ok(address)
+    }
+}
+
 impl Debug for Address {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         write!(f, "{self}")
@@ -50,3 +81,28 @@ impl Display for Address {
         Ok(())
     }
 }
+
+#[cfg(all(test, feature = "native"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn eip55() {
+        let checksummed = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+        let address = checksummed.parse::<Address>().unwrap();
+        assert_eq!(address.to_checksummed_hex(), checksummed);
+        assert_eq!(address.to_string(), checksummed.to_lowercase());
+        assert_eq!(checksummed.to_lowercase().parse(), Ok(address));
+        assert_eq!(checksummed[2..].to_uppercase().parse(), Ok(address));
+        assert!("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD"
+            .parse::<Address>()
+            .unwrap_err()
+            .contains(checksummed));
+        assert!("0x5aaeb6053f3e94c9b9a09f33669435e7ef1bea"
+            .parse::<Address>()
+            .is_err());
+        assert!("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaeg"
+            .parse::<Address>()
+            .is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use super::U256;
+
+/// The daily demurrage factor of the Circles v2 hub in 64.64 fixed point,
+/// i.e. balances decay by 7% per year of 365.25 days.
+pub const CIRCLES_V2_GAMMA_64X64: u128 = 18443079296116538654;
+
+/// The start of day zero of the Circles v2 hub on Gnosis Chain (2020-10-15 UTC).
+pub const INFLATION_DAY_ZERO: u64 = 1602720000;
+
+const ONE_64X64: u128 = 1 << 64;
+
+/// The decay of Circles v2 balances over time. A balance stored at some day
+/// is worth `gamma^days` times that amount `days` later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Demurrage {
+    /// The factor by which balances decay per day, in 64.64 fixed point.
+    gamma_64x64: u128,
+}
+
+impl Default for Demurrage {
+    fn default() -> Self {
+        Demurrage::new(CIRCLES_V2_GAMMA_64X64)
+    }
+}
+
+impl Demurrage {
+    /// Creates a demurrage with the given daily factor in 64.64 fixed point,
+    /// which has to be at most one.
+    pub fn new(gamma_64x64: u128) -> Demurrage {
+        assert!(gamma_64x64 <= ONE_64X64);
+        Demurrage { gamma_64x64 }
+    }
+
+    /// The day (as counted by the hub) of a unix timestamp in seconds.
+    pub fn day(timestamp: u64) -> u64 {
+        timestamp.saturating_sub(INFLATION_DAY_ZERO) / (24 * 60 * 60)
+    }
+
+    /// The value of `balance` after `days` days, rounded down.
+    pub fn apply(&self, balance: U256, days: u64) -> U256 {
+        if days == 0 {
+            return balance;
+        }
+        // Both factors are less than 2^65, so the products cannot overflow.
+        balance * self.factor(days) / U256::from(ONE_64X64)
+    }
+
+    /// `gamma^days` in 64.64 fixed point, computed by repeated squaring.
+    fn factor(&self, days: u64) -> U256 {
+        let one = U256::from(ONE_64X64);
+        let mut result = one;
+        let mut base = U256::from(self.gamma_64x64);
+        let mut days = days;
+        while days > 0 {
+            if days & 1 == 1 {
+                result = result * base / one;
+            }
+            base = base * base / one;
+            days >>= 1;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decay() {
+        let demurrage = Demurrage::default();
+        let balance = U256::from(1_000_000_000_000_000_000_000u128);
+        assert_eq!(demurrage.apply(balance, 0), balance);
+        assert!(demurrage.apply(balance, 1) < balance);
+        // 7% per year of 365.25 days.
+        let year = demurrage.apply(balance, 365);
+        assert!(year > U256::from(930_000_000_000_000_000_000u128));
+        assert!(year < U256::from(930_100_000_000_000_000_000u128));
+        assert_eq!(Demurrage::new(ONE_64X64).apply(balance, 1000), balance);
+    }
+
+    #[test]
+    fn day() {
+        assert_eq!(Demurrage::day(0), 0);
+        assert_eq!(Demurrage::day(INFLATION_DAY_ZERO + 24 * 60 * 60 - 1), 0);
+        assert_eq!(Demurrage::day(INFLATION_DAY_ZERO + 24 * 60 * 60), 1);
+    }
+}
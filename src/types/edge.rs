@@ -1,9 +1,24 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Deref;
+use std::sync::OnceLock;
 
+use crate::types::interner::AddressInterner;
+use crate::types::safe::trust_transfer_limit;
+use crate::types::AccountType;
 use crate::types::Address;
+use crate::types::Demurrage;
+use crate::types::TokenType;
 use crate::types::U256;
 
+#[cfg(feature = "native")]
+pub mod mapped;
+
+#[cfg(feature = "native")]
+pub use mapped::MappedEdges;
+
+/// The layout is fixed so that edges can be used in place in mapped files, see `mapped`.
 #[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, Ord, PartialOrd)]
+#[repr(C)]
 pub struct Edge {
     pub from: Address,
     pub to: Address,
@@ -17,68 +32,779 @@ pub fn eq_up_to_capacity(e1: &Edge, e2: &Edge) -> bool {
     e1.from == e2.from && e1.to == e2.to && e1.token == e2.token
 }
 
+/// An edge for tests, with the accounts given as anything an address can be made of.
+#[cfg(test)]
+pub(crate) fn edge(
+    from: impl Into<Address>,
+    to: impl Into<Address>,
+    token: impl Into<Address>,
+    capacity: u128,
+) -> Edge {
+    Edge {
+        from: from.into(),
+        to: to.into(),
+        token: token.into(),
+        capacity: U256::from(capacity),
+    }
+}
+
+/// What executing a transfer along an edge means on-chain.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// A transfer of Circles, limited by trust.
+    #[default]
+    Transfer,
+    /// `from` wraps its tokens into the ERC-20 wrapper `to`, which holds them as
+    /// collateral, and receives the same amount of wrapped tokens.
+    Wrap,
+    /// An ERC-20 transfer of wrapped tokens (`token` is the wrapper), not limited by trust.
+    WrappedTransfer,
+}
+
+impl EdgeKind {
+    pub const ALL: [EdgeKind; 3] = [
+        EdgeKind::Transfer,
+        EdgeKind::Wrap,
+        EdgeKind::WrappedTransfer,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EdgeKind::Transfer => "transfer",
+            EdgeKind::Wrap => "wrap",
+            EdgeKind::WrappedTransfer => "wrapped_transfer",
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct EdgeDB {
-    edges: Vec<Edge>,
-    outgoing: HashMap<Address, Vec<usize>>,
-    incoming: HashMap<Address, Vec<usize>>,
+    edges: EdgeStorage,
+    /// Dense ids of the senders and receivers of the edges.
+    addresses: AddressInterner,
+    /// The indices of the outgoing and incoming edges of each account, by id.
+    outgoing: Vec<Vec<usize>>,
+    incoming: Vec<Vec<usize>>,
+    /// Trust limit percentages by edge index. The capacity of these edges
+    /// is computed at query time from the percentage and the balances.
+    limit_percentages: HashMap<usize, u8>,
+    balances: Balances,
+    /// The block number of the last update by edge index, if known.
+    updated_at: HashMap<usize, u64>,
+    /// The latest block number of any update.
+    block_number: Option<u64>,
+    /// Token types by token owner, tokens not contained are personal tokens.
+    token_types: HashMap<Address, TokenType>,
+    /// The token (owner) wrapped by each ERC-20 wrapper.
+    wrappers: HashMap<Address, Address>,
+    /// Effective capacities of all edges, computed on first use after a change.
+    capacities: OnceLock<Vec<U256>>,
+}
+
+/// The edges of an `EdgeDB`, either owned or in a mapped file.
+/// Mapped edges are copied on the first change.
+#[derive(Debug, Clone)]
+enum EdgeStorage {
+    Owned(Vec<Edge>),
+    #[cfg(feature = "native")]
+    Mapped(MappedEdges),
+}
+
+impl Default for EdgeStorage {
+    fn default() -> Self {
+        EdgeStorage::Owned(Vec::new())
+    }
+}
+
+impl EdgeStorage {
+    fn to_mut(&mut self) -> &mut Vec<Edge> {
+        #[cfg(feature = "native")]
+        if let EdgeStorage::Mapped(edges) = self {
+            *self = EdgeStorage::Owned(edges.to_vec());
+        }
+        match self {
+            EdgeStorage::Owned(edges) => edges,
+            #[cfg(feature = "native")]
+            EdgeStorage::Mapped(_) => unreachable!(),
+        }
+    }
+}
+
+impl Deref for EdgeStorage {
+    type Target = [Edge];
+
+    fn deref(&self) -> &[Edge] {
+        match self {
+            EdgeStorage::Owned(edges) => edges,
+            #[cfg(feature = "native")]
+            EdgeStorage::Mapped(edges) => edges,
+        }
+    }
+}
+
+/// Token balances and organization flags, used to compute the capacities
+/// of edges with a trust limit percentage, and the stopped accounts.
+///
+/// Each balance is stored once, by holder and token, and limits the capacities of all
+/// edges of the holder in the token, see `EdgeDB::effective_edges`.
+#[derive(Debug, Default, Clone)]
+pub struct Balances {
+    /// Balances by holder and token (owner), including balances set to zero, which
+    /// are known to be empty.
+    balances: HashMap<(Address, Address), U256>,
+    /// The tokens of each holder and the holders of each token with a known balance.
+    tokens_by_holder: HashMap<Address, BTreeSet<Address>>,
+    holders_by_token: HashMap<Address, BTreeSet<Address>>,
+    /// The day each Circles v2 balance was last updated at. Balances without
+    /// a day do not decay.
+    last_updated_days: HashMap<(Address, Address), u64>,
+    demurrage: Demurrage,
+    /// The day balances are computed for.
+    current_day: Option<u64>,
+    organizations: HashSet<Address>,
+    /// Deactivated safes and accounts with a stopped token.
+    stopped: HashSet<Address>,
+}
+
+impl Balances {
+    /// The balance at the current day, i.e. after demurrage since its last update.
+    pub fn balance(&self, holder: &Address, token: &Address) -> U256 {
+        self.known_balance(holder, token).unwrap_or_default()
+    }
+
+    /// Like `balance`, but None if the balance was never set.
+    pub fn known_balance(&self, holder: &Address, token: &Address) -> Option<U256> {
+        let key = (*holder, *token);
+        let balance = *self.balances.get(&key)?;
+        Some(match (self.current_day, self.last_updated_days.get(&key)) {
+            (Some(current_day), Some(day)) => self
+                .demurrage
+                .apply(balance, current_day.saturating_sub(*day)),
+            _ => balance,
+        })
+    }
+
+    /// Sets a balance that does not decay.
+    pub fn set_balance(&mut self, holder: Address, token: Address, amount: U256) {
+        self.last_updated_days.remove(&(holder, token));
+        self.balances.insert((holder, token), amount);
+        self.tokens_by_holder
+            .entry(holder)
+            .or_default()
+            .insert(token);
+        self.holders_by_token
+            .entry(token)
+            .or_default()
+            .insert(holder);
+    }
+
+    /// Sets a Circles v2 balance as stored by the hub at `day`, which decays
+    /// until the current day.
+    pub fn set_balance_at_day(&mut self, holder: Address, token: Address, amount: U256, day: u64) {
+        self.set_balance(holder, token, amount);
+        if !amount.is_zero() {
+            self.last_updated_days.insert((holder, token), day);
+        }
+    }
+
+    /// The tokens `holder` has a known balance of.
+    pub fn tokens_of(&self, holder: &Address) -> impl Iterator<Item = &Address> {
+        self.tokens_by_holder.get(holder).into_iter().flatten()
+    }
+
+    /// The accounts with a known balance of `token`.
+    pub fn holders_of(&self, token: &Address) -> impl Iterator<Item = &Address> {
+        self.holders_by_token.get(token).into_iter().flatten()
+    }
+
+    /// All known balances by holder and token as they were set, i.e. before demurrage,
+    /// with the day they were set at if they decay, see `set_balance_at_day`.
+    pub fn stored(&self) -> impl Iterator<Item = (Address, Address, U256, Option<u64>)> + '_ {
+        self.balances.iter().map(|(key, amount)| {
+            (
+                key.0,
+                key.1,
+                *amount,
+                self.last_updated_days.get(key).copied(),
+            )
+        })
+    }
+
+    /// Returns true if some balances decay over time.
+    pub fn has_demurrage(&self) -> bool {
+        !self.last_updated_days.is_empty()
+    }
+
+    pub fn current_day(&self) -> Option<u64> {
+        self.current_day
+    }
+
+    /// Sets the day balances are computed for. Balances are not decayed before it is set.
+    pub fn set_current_day(&mut self, day: u64) {
+        self.current_day = Some(day);
+    }
+
+    pub fn set_demurrage(&mut self, demurrage: Demurrage) {
+        self.demurrage = demurrage;
+    }
+
+    /// Returns true if no balances are known, i.e. the capacities are not
+    /// derived from balances.
+    pub fn is_empty(&self) -> bool {
+        self.balances.is_empty()
+    }
+
+    pub fn is_organization(&self, address: &Address) -> bool {
+        self.organizations.contains(address)
+    }
+
+    pub fn organizations(&self) -> impl Iterator<Item = &Address> {
+        self.organizations.iter()
+    }
+
+    pub fn set_organization(&mut self, address: Address, organization: bool) {
+        if organization {
+            self.organizations.insert(address);
+        } else {
+            self.organizations.remove(&address);
+        }
+    }
+
+    pub fn is_stopped(&self, address: &Address) -> bool {
+        self.stopped.contains(address)
+    }
+
+    pub fn set_stopped(&mut self, address: Address, stopped: bool) {
+        if stopped {
+            self.stopped.insert(address);
+        } else {
+            self.stopped.remove(&address);
+        }
+    }
+
+    pub fn stopped(&self) -> impl Iterator<Item = &Address> {
+        self.stopped.iter()
+    }
 }
 
 impl EdgeDB {
     pub fn new(edges: Vec<Edge>) -> EdgeDB {
-        let outgoing = outgoing_index(&edges);
-        let incoming = incoming_index(&edges);
-        EdgeDB {
+        EdgeDB::with_storage(EdgeStorage::Owned(edges))
+    }
+
+    /// Uses the edges in a mapped file in place, only the adjacency index is built in memory.
+    #[cfg(feature = "native")]
+    pub fn mapped(edges: MappedEdges) -> EdgeDB {
+        EdgeDB::with_storage(EdgeStorage::Mapped(edges))
+    }
+
+    fn with_storage(edges: EdgeStorage) -> EdgeDB {
+        let mut db = EdgeDB {
             edges,
-            outgoing,
-            incoming,
+            ..Default::default()
+        };
+        for i in 0..db.edges.len() {
+            db.index(i);
+        }
+        db
+    }
+
+    /// Adds the edge at index `i` to the adjacency index.
+    fn index(&mut self, i: usize) {
+        let edge = self.edges[i];
+        for (address, index) in [
+            (edge.from, &mut self.outgoing),
+            (edge.to, &mut self.incoming),
+        ] {
+            let id = self.addresses.intern(address).index();
+            if id >= index.len() {
+                index.resize_with(id + 1, Vec::new);
+            }
+            index[id].push(i);
         }
     }
 
+    /// Whether the edges are used in place in a mapped file.
+    pub fn is_mapped(&self) -> bool {
+        #[cfg(feature = "native")]
+        return matches!(self.edges, EdgeStorage::Mapped(_));
+        #[cfg(not(feature = "native"))]
+        false
+    }
+
+    /// The number of stored edges, without the edges back to the token owners implied
+    /// by the balances.
     pub fn edge_count(&self) -> usize {
         self.edges.len()
     }
 
-    pub fn edges(&self) -> &Vec<Edge> {
+    /// The number of accounts that have or had an edge. Accounts stay
+    /// interned when their edges are removed.
+    pub fn node_count(&self) -> usize {
+        self.addresses.len()
+    }
+
+    /// The ids of the accounts used in the adjacency index.
+    pub fn addresses(&self) -> &AddressInterner {
+        &self.addresses
+    }
+
+    /// The stored edges. The capacities of edges with a trust limit
+    /// percentage are only computed by `effective_edges`.
+    pub fn edges(&self) -> &[Edge] {
         &self.edges
     }
 
+    /// Returns all edges with their effective capacity: computed from the trust limit
+    /// percentage if the edge has one, otherwise the stored capacity limited by the known
+    /// balance of the sender in the token, or that balance for edges back to the owner.
+    /// The stored edges are followed by the edges back to the owner implied by the
+    /// balances, see `return_edge`.
+    pub fn effective_edges(&self) -> Vec<Edge> {
+        let mut edges = (0..self.edges.len())
+            .map(|i| self.effective(i))
+            .collect::<Vec<_>>();
+        let mut holders = self.balances.tokens_by_holder.keys().collect::<Vec<_>>();
+        holders.sort();
+        for holder in holders {
+            edges.extend(
+                self.balances
+                    .tokens_of(holder)
+                    .filter_map(|token| self.return_edge(holder, token))
+                    .filter(|edge| self.index_of(edge).is_none()),
+            );
+        }
+        edges
+    }
+
+    /// Sets the capacity of an edge, adding it if it does not exist.
+    /// The capacity replaces a trust limit percentage set before.
     pub fn update(&mut self, update: Edge) {
-        match self.index_of(&update) {
-            Some(i) => self.edges[i].capacity = update.capacity,
-            None => {
-                let i = self.edges.len();
-                self.outgoing.entry(update.from).or_default().push(i);
-                self.incoming.entry(update.to).or_default().push(i);
-                self.edges.push(update);
+        let i = self.index_or_insert(&update);
+        self.edges.to_mut()[i].capacity = update.capacity;
+        self.limit_percentages.remove(&i);
+        self.capacities = OnceLock::new();
+    }
+
+    /// Removes a stored edge and its trust limit percentage and block number. Returns
+    /// whether it existed. Edges back to the token owner implied by the balances stay
+    /// as long as the balance.
+    pub fn remove(&mut self, edge: &Edge) -> bool {
+        let Some(i) = self.index_of(edge) else {
+            return false;
+        };
+        // The last edge takes the place of the removed one.
+        let last = self.edges.len() - 1;
+        let removed = self.edges[i];
+        let moved = self.edges[last];
+        self.edges.to_mut().swap_remove(i);
+        for (address, index) in [
+            (removed.from, &mut self.outgoing),
+            (removed.to, &mut self.incoming),
+        ] {
+            let id = self.addresses.id(&address).unwrap().index();
+            index[id].retain(|j| *j != i);
+        }
+        for (address, index) in [
+            (moved.from, &mut self.outgoing),
+            (moved.to, &mut self.incoming),
+        ] {
+            let id = self.addresses.id(&address).unwrap().index();
+            for j in index[id].iter_mut().filter(|j| **j == last) {
+                *j = i;
             }
         }
+        move_index(&mut self.limit_percentages, last, i);
+        move_index(&mut self.updated_at, last, i);
+        self.capacities = OnceLock::new();
+        true
     }
 
-    pub fn outgoing(&self, source: &Address) -> Vec<&Edge> {
-        match self.outgoing.get(source) {
-            Some(out) => out
-                .iter()
-                .map(|i| self.edges.get(*i).unwrap())
-                .filter(|e| e.capacity != U256::from(0))
-                .collect(),
-            None => vec![],
+    /// Sets a trust limit percentage for an edge, adding it if it does not exist.
+    /// The capacity of the edge is then computed at query time as the amount
+    /// of their own tokens `edge.from` can send to `edge.to`, given the percentage
+    /// and the current balances. The capacity of `edge` is ignored.
+    pub fn set_limit_percentage(&mut self, edge: &Edge, percentage: u8) {
+        let i = self.index_or_insert(edge);
+        self.limit_percentages.insert(i, percentage);
+        self.capacities = OnceLock::new();
+    }
+
+    /// Returns the edge with its effective capacity if it exists, also if it is an
+    /// edge back to the token owner implied by the balances.
+    pub fn edge(&self, edge: &Edge) -> Option<Edge> {
+        match self.index_of(edge) {
+            Some(i) => Some(self.effective(i)),
+            None if edge.to == edge.token => self.return_edge(&edge.from, &edge.token),
+            None => None,
+        }
+    }
+
+    /// The edge in which `holder` sends tokens of `token` back to their owner. Anyone can
+    /// always return tokens to their owner regardless of trust, so these edges are not
+    /// stored but implied by every non-zero balance of tokens of another account, with
+    /// the balance as capacity.
+    pub fn return_edge(&self, holder: &Address, token: &Address) -> Option<Edge> {
+        let capacity = self.balances.balance(holder, token);
+        (holder != token && !capacity.is_zero()).then_some(Edge {
+            from: *holder,
+            to: *token,
+            token: *token,
+            capacity,
+        })
+    }
+
+    /// All edges with a trust limit percentage, with the percentage.
+    pub fn limit_percentages(&self) -> impl Iterator<Item = (&Edge, u8)> {
+        self.limit_percentages
+            .iter()
+            .map(|(i, percentage)| (&self.edges[*i], *percentage))
+    }
+
+    /// Returns the trust limit percentage of an edge if it has one.
+    pub fn limit_percentage(&self, edge: &Edge) -> Option<u8> {
+        self.index_of(edge)
+            .and_then(|i| self.limit_percentages.get(&i).copied())
+    }
+
+    /// Like `update`, but also records the block number the capacity was read at.
+    pub fn update_at_block(&mut self, update: Edge, block_number: u64) {
+        self.update(update);
+        self.set_updated_at(&update, block_number);
+    }
+
+    /// Records the block number of the last update of an edge, adding it if it does not exist.
+    pub fn set_updated_at(&mut self, edge: &Edge, block_number: u64) {
+        let i = self.index_or_insert(edge);
+        self.updated_at.insert(i, block_number);
+        self.block_number = self.block_number.max(Some(block_number));
+    }
+
+    /// The block number of the last update of an edge, if known.
+    pub fn updated_at(&self, edge: &Edge) -> Option<u64> {
+        self.index_of(edge)
+            .and_then(|i| self.updated_at.get(&i).copied())
+    }
+
+    /// The latest block number of any update, if known.
+    pub fn block_number(&self) -> Option<u64> {
+        self.block_number
+    }
+
+    /// Sets the block number the edges are at, for edges computed as of a block.
+    pub fn set_block_number(&mut self, block_number: u64) {
+        self.block_number = Some(block_number);
+    }
+
+    pub fn token_type(&self, token: &Address) -> TokenType {
+        self.token_types.get(token).copied().unwrap_or_default()
+    }
+
+    pub fn set_token_type(&mut self, token: Address, token_type: TokenType) {
+        if token_type == TokenType::default() {
+            self.token_types.remove(&token);
+        } else {
+            self.token_types.insert(token, token_type);
+        }
+        self.capacities = OnceLock::new();
+    }
+
+    /// The owners of all tokens that are not personal tokens.
+    pub fn token_types(&self) -> &HashMap<Address, TokenType> {
+        &self.token_types
+    }
+
+    /// The type of an account: groups own group tokens, organizations are flagged
+    /// in the balances.
+    pub fn account_type(&self, address: &Address) -> AccountType {
+        if self.token_type(address) == TokenType::Group {
+            AccountType::Group
+        } else if self.balances.is_organization(address) {
+            AccountType::Organization
+        } else {
+            AccountType::Human
+        }
+    }
+
+    /// Registers an ERC-20 wrapper for a token and adds wrap edges from all
+    /// current holders of the token to the wrapper. The capacity of wrap edges
+    /// is the balance of the holder, if balances are known.
+    pub fn add_wrapper(&mut self, wrapper: Address, token: Address) {
+        self.wrappers.insert(wrapper, token);
+        let holders = self
+            .balances
+            .holders_of(&token)
+            .filter(|holder| !self.balances.balance(holder, &token).is_zero())
+            .copied()
+            .collect::<Vec<_>>();
+        for holder in holders {
+            self.index_or_insert(&Edge {
+                from: holder,
+                to: wrapper,
+                token,
+                capacity: U256::from(0u128),
+            });
+        }
+        self.capacities = OnceLock::new();
+    }
+
+    /// The token wrapped by an ERC-20 wrapper.
+    pub fn wrapped_token(&self, wrapper: &Address) -> Option<Address> {
+        self.wrappers.get(wrapper).copied()
+    }
+
+    /// All ERC-20 wrappers with the token they wrap.
+    pub fn wrappers(&self) -> &HashMap<Address, Address> {
+        &self.wrappers
+    }
+
+    pub fn edge_kind(&self, edge: &Edge) -> EdgeKind {
+        if self.wrappers.get(&edge.to) == Some(&edge.token) {
+            EdgeKind::Wrap
+        } else if self.wrappers.contains_key(&edge.token) {
+            EdgeKind::WrappedTransfer
+        } else {
+            EdgeKind::Transfer
+        }
+    }
+
+    pub fn balances(&self) -> &Balances {
+        &self.balances
+    }
+
+    pub fn balances_mut(&mut self) -> &mut Balances {
+        self.capacities = OnceLock::new();
+        &mut self.balances
+    }
+
+    pub fn outgoing(&self, source: &Address) -> Vec<Edge> {
+        self.outgoing_updated_since(source, None)
+    }
+
+    pub fn incoming(&self, to: &Address) -> Vec<Edge> {
+        self.incoming_updated_since(to, None)
+    }
+
+    /// Like `outgoing`, but if `min_block` is given, only returns edges that were
+    /// updated at or after that block. Edges without a known block number are excluded,
+    /// including the edges back to the token owners implied by the balances.
+    pub fn outgoing_updated_since(&self, source: &Address, min_block: Option<u64>) -> Vec<Edge> {
+        let edges = self.select(self.adjacent(&self.outgoing, source), min_block);
+        match min_block {
+            Some(_) => edges,
+            None => self.with_return_edges(
+                edges,
+                self.balances
+                    .tokens_of(source)
+                    .map(|token| (*source, *token)),
+            ),
+        }
+    }
+
+    /// Like `incoming`, but only returns edges updated at or after `min_block`.
+    pub fn incoming_updated_since(&self, to: &Address, min_block: Option<u64>) -> Vec<Edge> {
+        let edges = self.select(self.adjacent(&self.incoming, to), min_block);
+        match min_block {
+            Some(_) => edges,
+            None => self.with_return_edges(
+                edges,
+                self.balances.holders_of(to).map(|holder| (*holder, *to)),
+            ),
+        }
+    }
+
+    /// The edges with a non-zero capacity between the accounts at most `hops` hops
+    /// away from `center`, following edges in either direction.
+    pub fn neighborhood(&self, center: &Address, hops: u64) -> Vec<Edge> {
+        self.bounded_neighborhood(center, hops, usize::MAX).0
+    }
+
+    /// Like `neighborhood`, but adds at most `max_per_hop` accounts per hop, so that hubs
+    /// do not pull in most of the graph. The accounts with the largest total capacity of
+    /// their edges to the previous hop are kept. Returns the edges and whether accounts
+    /// were left out.
+    pub fn bounded_neighborhood(
+        &self,
+        center: &Address,
+        hops: u64,
+        max_per_hop: usize,
+    ) -> (Vec<Edge>, bool) {
+        let mut accounts = HashSet::from([*center]);
+        let mut frontier = vec![*center];
+        let mut truncated = false;
+        for _ in 0..hops {
+            let mut candidates = HashMap::<Address, U256>::new();
+            for account in &frontier {
+                let outgoing = self.outgoing(account).into_iter().map(|e| (e.to, e));
+                let incoming = self.incoming(account).into_iter().map(|e| (e.from, e));
+                for (neighbor, edge) in outgoing.chain(incoming) {
+                    if !accounts.contains(&neighbor) {
+                        let capacity = candidates.entry(neighbor).or_default();
+                        *capacity = capacity.saturating_add(edge.capacity);
+                    }
+                }
+            }
+            let mut candidates = candidates.into_iter().collect::<Vec<_>>();
+            if candidates.len() > max_per_hop {
+                candidates.sort_by(|(a1, c1), (a2, c2)| c2.cmp(c1).then(a1.cmp(a2)));
+                candidates.truncate(max_per_hop);
+                truncated = true;
+            }
+            frontier = candidates.into_iter().map(|(account, _)| account).collect();
+            accounts.extend(&frontier);
         }
+        let mut edges = accounts
+            .iter()
+            .flat_map(|account| self.outgoing(account))
+            .filter(|e| accounts.contains(&e.to))
+            .collect::<Vec<_>>();
+        edges.sort();
+        (edges, truncated)
     }
 
-    pub fn incoming(&self, to: &Address) -> Vec<&Edge> {
-        match self.incoming.get(to) {
-            Some(incoming) => incoming
+    /// The shortest path of trust connections from `from` to `to` with at most `max_hops`
+    /// hops, ignoring capacities and balances: the accounts along the path, starting with
+    /// `from`. Accounts can send their own tokens to the accounts that trust them, so only
+    /// edges in the token of their sender and edges with a trust limit are followed.
+    pub fn trust_path(&self, from: &Address, to: &Address, max_hops: u64) -> Option<Vec<Address>> {
+        let (source, sink) = (self.addresses.id(from)?, self.addresses.id(to)?);
+        if source == sink {
+            return Some(vec![*from]);
+        }
+        // The account each account was first reached from.
+        let mut predecessors = HashMap::from([(source.index(), source.index())]);
+        let mut frontier = vec![source.index()];
+        for _ in 0..max_hops {
+            let mut next = vec![];
+            for account in frontier {
+                for i in self.outgoing.get(account).into_iter().flatten() {
+                    let edge = self.edges[*i];
+                    if edge.token != edge.from && !self.limit_percentages.contains_key(i) {
+                        continue;
+                    }
+                    let neighbor = self.addresses.id(&edge.to).unwrap().index();
+                    if predecessors.contains_key(&neighbor) {
+                        continue;
+                    }
+                    predecessors.insert(neighbor, account);
+                    if neighbor == sink.index() {
+                        let mut path = vec![neighbor];
+                        while path[path.len() - 1] != source.index() {
+                            path.push(predecessors[&path[path.len() - 1]]);
+                        }
+                        let addresses = self.addresses.addresses();
+                        return Some(path.into_iter().rev().map(|id| addresses[id]).collect());
+                    }
+                    next.push(neighbor);
+                }
+            }
+            frontier = next;
+        }
+        None
+    }
+
+    fn adjacent<'a>(&self, index: &'a [Vec<usize>], address: &Address) -> Option<&'a Vec<usize>> {
+        self.addresses
+            .id(address)
+            .and_then(|id| index.get(id.index()))
+    }
+
+    fn select(&self, indices: Option<&Vec<usize>>, min_block: Option<u64>) -> Vec<Edge> {
+        match indices {
+            Some(indices) => indices
                 .iter()
-                .map(|i| self.edges.get(*i).unwrap())
-                .filter(|e| e.capacity != U256::from(0))
+                .filter(|i| {
+                    min_block.is_none_or(|min| self.updated_at.get(i).is_some_and(|b| *b >= min))
+                })
+                .map(|i| self.effective(*i))
+                .filter(|e| !e.capacity.is_zero())
                 .collect(),
             None => vec![],
         }
     }
 
+    /// Adds the edges back to the owner of the `(holder, token)` pairs to the selected
+    /// `edges` unless they are stored. Stored edges back to the owner have the balance
+    /// as capacity, so they were selected if they have a capacity.
+    fn with_return_edges(
+        &self,
+        mut edges: Vec<Edge>,
+        pairs: impl Iterator<Item = (Address, Address)>,
+    ) -> Vec<Edge> {
+        let stored = edges
+            .iter()
+            .filter(|edge| edge.to == edge.token)
+            .map(|edge| (edge.from, edge.to))
+            .collect::<HashSet<_>>();
+        let implied = pairs
+            .filter(|pair| !stored.contains(pair))
+            .filter_map(|(holder, token)| self.return_edge(&holder, &token))
+            .collect::<Vec<_>>();
+        edges.extend(implied);
+        edges
+    }
+
+    fn effective(&self, i: usize) -> Edge {
+        if self.limit_percentages.is_empty() && self.wrappers.is_empty() && self.balances.is_empty()
+        {
+            return self.edges[i];
+        }
+        let capacities = self.capacities.get_or_init(|| {
+            (0..self.edges.len())
+                .map(|i| self.compute_capacity(i))
+                .collect()
+        });
+        Edge {
+            capacity: capacities[i],
+            ..self.edges[i]
+        }
+    }
+
+    fn compute_capacity(&self, i: usize) -> U256 {
+        let edge = &self.edges[i];
+        if self.wrappers.get(&edge.to) == Some(&edge.token) && !self.balances.is_empty() {
+            return self.balances.balance(&edge.from, &edge.token);
+        }
+        match self.limit_percentages.get(&i) {
+            // Trust in a group is not limited, any amount of group tokens
+            // the sender holds is accepted.
+            Some(_) if self.token_type(&edge.token) == TokenType::Group => {
+                self.balances.balance(&edge.from, &edge.token)
+            }
+            // Organizations have no token that could be trusted.
+            Some(_) if !self.account_type(&edge.token).mints() => U256::from(0u128),
+            // Organizations and groups accept any amount of the tokens they trust.
+            Some(percentage) => trust_transfer_limit(
+                self.balances.balance(&edge.from, &edge.token),
+                self.balances.balance(&edge.to, &edge.token),
+                self.balances.balance(&edge.to, &edge.to),
+                self.account_type(&edge.to),
+                *percentage,
+            ),
+            // Stored capacities only limit what the sender can send of its balance, if
+            // known, except that owners accept any amount of their own tokens.
+            None => match self.balances.known_balance(&edge.from, &edge.token) {
+                Some(balance) if edge.to == edge.token => balance,
+                Some(balance) => edge.capacity.min(balance),
+                None => edge.capacity,
+            },
+        }
+    }
+
+    fn index_or_insert(&mut self, e: &Edge) -> usize {
+        match self.index_of(e) {
+            Some(i) => i,
+            None => {
+                let i = self.edges.len();
+                self.edges.to_mut().push(*e);
+                self.index(i);
+                i
+            }
+        }
+    }
+
     fn index_of(&self, e: &Edge) -> Option<usize> {
-        self.outgoing.get(&e.from).and_then(|out| {
+        self.adjacent(&self.outgoing, &e.from).and_then(|out| {
             for i in out {
                 if eq_up_to_capacity(&self.edges[*i], e) {
                     return Some(*i);
@@ -89,18 +815,370 @@ impl EdgeDB {
     }
 }
 
-fn outgoing_index(edges: &[Edge]) -> HashMap<Address, Vec<usize>> {
-    let mut index: HashMap<Address, Vec<usize>> = HashMap::new();
-    for (i, e) in edges.iter().enumerate() {
-        index.entry(e.from).or_default().push(i)
+/// Moves the value for the edge index `from` to `to` after the edge at `to` was replaced.
+fn move_index<T>(by_index: &mut HashMap<usize, T>, from: usize, to: usize) {
+    by_index.remove(&to);
+    if let Some(value) = by_index.remove(&from) {
+        by_index.insert(to, value);
     }
-    index
 }
 
-fn incoming_index(edges: &[Edge]) -> HashMap<Address, Vec<usize>> {
-    let mut index: HashMap<Address, Vec<usize>> = HashMap::new();
-    for (i, e) in edges.iter().enumerate() {
-        index.entry(e.to).or_default().push(i)
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn updated_since() {
+        let a = Address::from("0x11C7e86fF693e9032A0F41711b5581a04b26Be2E");
+        let b = Address::from("0x22cEDde51198D1773590311E2A340DC06B24cB37");
+        let mut edges = EdgeDB::new(vec![edge(a, b, a, 10)]);
+        edges.update_at_block(edge(a, b, b, 10), 100);
+        assert_eq!(edges.block_number(), Some(100));
+        assert_eq!(edges.updated_at(&edge(a, b, a, 10)), None);
+        assert_eq!(edges.updated_at(&edge(a, b, b, 10)), Some(100));
+        assert_eq!(edges.outgoing(&a).len(), 2);
+        // Edges without a block number are never fresh.
+        assert_eq!(
+            edges.outgoing_updated_since(&a, Some(0)),
+            vec![edge(a, b, b, 10)]
+        );
+        assert_eq!(
+            edges.incoming_updated_since(&b, Some(100)),
+            vec![edge(a, b, b, 10)]
+        );
+        assert!(edges.incoming_updated_since(&b, Some(101)).is_empty());
+    }
+
+    #[test]
+    fn remove() {
+        let a = Address::from("0x11C7e86fF693e9032A0F41711b5581a04b26Be2E");
+        let b = Address::from("0x22cEDde51198D1773590311E2A340DC06B24cB37");
+        let c = Address::from("0x33cEDde51198D1773590311E2A340DC06B24cB37");
+        let mut edges = EdgeDB::new(vec![edge(a, b, a, 10), edge(b, c, b, 10)]);
+        edges.update_at_block(edge(a, c, a, 10), 100);
+        edges.set_limit_percentage(&edge(b, c, b, 10), 50);
+        assert!(edges.remove(&edge(a, b, a, 10)));
+        assert!(!edges.remove(&edge(a, b, a, 10)));
+        assert_eq!(edges.edge_count(), 2);
+        assert_eq!(edges.edge(&edge(a, b, a, 10)), None);
+        assert!(edges.incoming(&b).is_empty());
+        // The last edge moved into the place of the removed one.
+        assert_eq!(edges.outgoing(&a), vec![edge(a, c, a, 10)]);
+        assert_eq!(edges.edge(&edge(a, c, a, 10)), Some(edge(a, c, a, 10)));
+        assert_eq!(edges.updated_at(&edge(a, c, a, 10)), Some(100));
+        assert_eq!(edges.limit_percentage(&edge(b, c, b, 10)), Some(50));
+        // A removed edge is added again on update.
+        edges.update(edge(a, b, a, 10));
+        assert_eq!(edges.edge(&edge(a, b, a, 10)), Some(edge(a, b, a, 10)));
+        assert_eq!(edges.updated_at(&edge(a, b, a, 10)), None);
+    }
+
+    #[test]
+    fn implied_return_edges() {
+        let address = |i: u8| Address::from([i; 20]);
+        let (a, b, c, d) = (address(1), address(2), address(3), address(4));
+        // b trusts a, and a holds tokens of c and d. Only the edge back to d is stored.
+        let mut edges = EdgeDB::new(vec![edge(a, b, a, 10), edge(a, d, d, 100)]);
+        edges.balances_mut().set_balance(a, a, U256::from(10u128));
+        edges.balances_mut().set_balance(a, c, U256::from(30u128));
+        edges.balances_mut().set_balance(a, d, U256::from(40u128));
+        assert_eq!(edges.edge_count(), 2);
+        assert_eq!(edges.edge(&edge(a, c, c, 0)), Some(edge(a, c, c, 30)));
+        assert_eq!(edges.return_edge(&a, &a), None);
+        assert_eq!(
+            edges.outgoing(&a),
+            vec![edge(a, b, a, 10), edge(a, d, d, 40), edge(a, c, c, 30)]
+        );
+        assert_eq!(edges.incoming(&c), vec![edge(a, c, c, 30)]);
+        assert_eq!(
+            edges.effective_edges(),
+            vec![edge(a, b, a, 10), edge(a, d, d, 40), edge(a, c, c, 30)]
+        );
+        // Implied edges have no block number.
+        assert_eq!(edges.outgoing_updated_since(&a, Some(0)), vec![]);
+
+        edges.balances_mut().set_balance(a, c, U256::from(0u128));
+        assert_eq!(edges.edge(&edge(a, c, c, 0)), None);
+        assert!(edges.incoming(&c).is_empty());
+    }
+
+    #[test]
+    fn capacities_follow_balances() {
+        let address = |i: u8| Address::from([i; 20]);
+        // 1 can send its tokens to 2 and 3, which send theirs to 4, and holds tokens of 5.
+        let mut edges = EdgeDB::new(vec![
+            edge(address(1), address(2), address(1), 10),
+            edge(address(1), address(3), address(1), 10),
+            edge(address(2), address(4), address(2), 10),
+            edge(address(3), address(4), address(3), 10),
+            edge(address(1), address(5), address(5), 3),
+        ]);
+        let flow = |edges: &EdgeDB| {
+            crate::graph::compute_flow(
+                &address(1),
+                &address(4),
+                edges,
+                U256::MAX,
+                None,
+                None,
+                crate::graph::FlowAlgorithm::default(),
+            )
+            .unwrap()
+            .0
+        };
+        assert_eq!(flow(&edges), U256::from(10u64));
+        // The balance limits both edges together.
+        edges
+            .balances_mut()
+            .set_balance(address(1), address(1), U256::from(4u64));
+        assert_eq!(
+            edges
+                .edge(&edge(address(1), address(3), address(1), 0))
+                .unwrap()
+                .capacity,
+            U256::from(4u64)
+        );
+        assert_eq!(flow(&edges), U256::from(4u64));
+        // Owners accept the whole balance, edges without a known balance keep their capacity.
+        edges
+            .balances_mut()
+            .set_balance(address(1), address(5), U256::from(8u64));
+        assert_eq!(
+            edges
+                .edge(&edge(address(1), address(5), address(5), 0))
+                .unwrap()
+                .capacity,
+            U256::from(8u64)
+        );
+        assert_eq!(
+            edges
+                .edge(&edge(address(2), address(4), address(2), 0))
+                .unwrap()
+                .capacity,
+            U256::from(10u64)
+        );
+        edges
+            .balances_mut()
+            .set_balance(address(1), address(1), U256::from(0u64));
+        assert!(edges
+            .outgoing(&address(1))
+            .iter()
+            .all(|e| e.token != address(1)));
+        assert_eq!(edges.edges()[0].capacity, U256::from(10u64));
+    }
+
+    #[test]
+    fn neighborhood() {
+        let address = |i: u8| Address::from([i; 20]);
+        // A path 1 -> 2 -> 3 -> 4, with 4 -> 2 and an edge without capacity 1 -> 5.
+        let edges = EdgeDB::new(vec![
+            edge(address(1), address(2), address(1), 10),
+            edge(address(2), address(3), address(2), 10),
+            edge(address(3), address(4), address(3), 10),
+            edge(address(4), address(2), address(4), 10),
+            edge(address(1), address(5), address(1), 0),
+        ]);
+        assert!(edges.neighborhood(&address(1), 0).is_empty());
+        assert_eq!(
+            edges.neighborhood(&address(1), 1),
+            vec![edge(address(1), address(2), address(1), 10)]
+        );
+        // 4 is reached through its edge to 2.
+        assert_eq!(
+            edges.neighborhood(&address(2), 1),
+            vec![
+                edge(address(1), address(2), address(1), 10),
+                edge(address(2), address(3), address(2), 10),
+                edge(address(3), address(4), address(3), 10),
+                edge(address(4), address(2), address(4), 10)
+            ]
+        );
+        assert_eq!(edges.neighborhood(&address(1), 2).len(), 4);
+        // Of the neighbors 1, 3 and 4 of 2, only 3 is kept as it has the most capacity,
+        // 4 is reached from 3 in the second hop.
+        let edges = EdgeDB::new(vec![
+            edge(address(1), address(2), address(1), 10),
+            edge(address(2), address(3), address(2), 30),
+            edge(address(3), address(4), address(3), 10),
+            edge(address(4), address(2), address(4), 10),
+        ]);
+        let (bounded, truncated) = edges.bounded_neighborhood(&address(2), 2, 1);
+        assert_eq!(
+            bounded,
+            vec![
+                edge(address(2), address(3), address(2), 30),
+                edge(address(3), address(4), address(3), 10),
+                edge(address(4), address(2), address(4), 10)
+            ]
+        );
+        assert!(truncated);
+        assert_eq!(edges.bounded_neighborhood(&address(2), 2, 3).0.len(), 4);
+        assert!(!edges.bounded_neighborhood(&address(2), 2, 3).1);
+    }
+
+    #[test]
+    fn trust_path() {
+        let address = |i: u8| Address::from([i; 20]);
+        // 2 and 4 trust 1, 3 trusts 2 (without balance), 1 holds tokens of 5,
+        // 6 trusts 3.
+        let edges = EdgeDB::new(vec![
+            edge(address(1), address(2), address(1), 10),
+            edge(address(1), address(4), address(1), 10),
+            edge(address(2), address(3), address(2), 0),
+            edge(address(1), address(5), address(5), 10),
+            edge(address(3), address(6), address(3), 10),
+        ]);
+        assert_eq!(
+            edges.trust_path(&address(1), &address(3), 2),
+            Some(vec![address(1), address(2), address(3)])
+        );
+        assert_eq!(edges.trust_path(&address(1), &address(6), 2), None);
+        assert_eq!(
+            edges.trust_path(&address(1), &address(6), 3).unwrap().len(),
+            4
+        );
+        // Returning tokens to their owner is no trust connection.
+        assert_eq!(edges.trust_path(&address(1), &address(5), 5), None);
+        assert_eq!(edges.trust_path(&address(3), &address(1), 5), None);
+        assert_eq!(
+            edges.trust_path(&address(4), &address(4), 0),
+            Some(vec![address(4)])
+        );
+    }
+
+    #[test]
+    fn group_tokens() {
+        let a = Address::from("0x11C7e86fF693e9032A0F41711b5581a04b26Be2E");
+        let b = Address::from("0x22cEDde51198D1773590311E2A340DC06B24cB37");
+        let g = Address::from("0x33cEDde51198D1773590311E2A340DC06B24cB37");
+        let mut edges = EdgeDB::default();
+        edges.set_token_type(g, TokenType::Group);
+        // b trusts the group g, the group trusts its member a.
+        edges.set_limit_percentage(&edge(a, b, g, 0), 10);
+        edges.set_limit_percentage(&edge(a, g, a, 0), 50);
+        edges.balances_mut().set_balance(a, g, U256::from(300u128));
+        edges.balances_mut().set_balance(a, a, U256::from(1000u128));
+        // Any amount of group tokens is accepted, regardless of the percentage,
+        // the group accepts any amount of a's tokens as collateral and its own tokens.
+        assert_eq!(
+            edges.outgoing(&a),
+            vec![
+                Edge {
+                    capacity: U256::from(300u128),
+                    ..edge(a, b, g, 0)
+                },
+                Edge {
+                    capacity: U256::from(1000u128),
+                    ..edge(a, g, a, 0)
+                },
+                Edge {
+                    capacity: U256::from(300u128),
+                    ..edge(a, g, g, 0)
+                }
+            ]
+        );
+        assert_eq!(edges.token_type(&g), TokenType::Group);
+        assert_eq!(edges.token_type(&a), TokenType::Personal);
+    }
+
+    #[test]
+    fn demurrage() {
+        let a = Address::from("0x11C7e86fF693e9032A0F41711b5581a04b26Be2E");
+        let b = Address::from("0x22cEDde51198D1773590311E2A340DC06B24cB37");
+        let mut edges = EdgeDB::default();
+        edges.set_limit_percentage(&edge(a, b, a, 0), 100);
+        let balance = U256::from(1_000_000_000_000_000_000u128);
+        edges.balances_mut().set_balance_at_day(a, a, balance, 10);
+        edges.balances_mut().set_balance(b, b, balance);
+        assert_eq!(edges.outgoing(&a)[0].capacity, balance);
+        edges.balances_mut().set_current_day(10);
+        assert_eq!(edges.outgoing(&a)[0].capacity, balance);
+        // a's balance decays, b's balance (without a day) does not.
+        edges.balances_mut().set_current_day(375);
+        let decayed = Demurrage::default().apply(balance, 365);
+        assert!(decayed < balance);
+        assert_eq!(edges.balances().balance(&a, &a), decayed);
+        assert_eq!(edges.balances().balance(&b, &b), balance);
+        assert_eq!(edges.outgoing(&a)[0].capacity, decayed);
+    }
+
+    #[test]
+    fn account_types() {
+        let (a, o, g) = (
+            Address::from([1; 20]),
+            Address::from([2; 20]),
+            Address::from([3; 20]),
+        );
+        let mut edges = EdgeDB::default();
+        edges.set_limit_percentage(&edge(a, o, a, 0), 10);
+        edges.set_limit_percentage(&edge(a, g, a, 0), 10);
+        edges.set_limit_percentage(&edge(o, a, o, 0), 50);
+        edges.balances_mut().set_organization(o, true);
+        edges.set_token_type(g, TokenType::Group);
+        assert_eq!(edges.account_type(&a), AccountType::Human);
+        assert_eq!(edges.account_type(&o), AccountType::Organization);
+        assert_eq!(edges.account_type(&g), AccountType::Group);
+
+        edges.balances_mut().set_balance(a, a, U256::from(1000u128));
+        edges.balances_mut().set_balance(a, o, U256::from(100u128));
+        edges.balances_mut().set_balance(o, o, U256::from(100u128));
+        // Organizations and groups accept any amount of trusted tokens.
+        assert_eq!(
+            edges.edge(&edge(a, o, a, 0)).unwrap().capacity,
+            U256::from(1000u128)
+        );
+        assert_eq!(
+            edges.edge(&edge(a, g, a, 0)).unwrap().capacity,
+            U256::from(1000u128)
+        );
+        // Organizations do not mint, so trusting them allows no transfers.
+        assert_eq!(
+            edges.edge(&edge(o, a, o, 0)).unwrap().capacity,
+            U256::from(0u128)
+        );
+    }
+
+    #[test]
+    fn limit_percentage() {
+        let a = Address::from("0x11C7e86fF693e9032A0F41711b5581a04b26Be2E");
+        let b = Address::from("0x22cEDde51198D1773590311E2A340DC06B24cB37");
+        let edge = edge(a, b, a, 0);
+        let mut edges = EdgeDB::default();
+        edges.set_limit_percentage(&edge, 50);
+        // a has no tokens to send yet.
+        assert!(edges.outgoing(&a).is_empty());
+
+        edges.balances_mut().set_balance(a, a, U256::from(1000u128));
+        edges.balances_mut().set_balance(b, b, U256::from(100u128));
+        // b accepts a's tokens up to 50% of its own balance.
+        assert_eq!(edges.outgoing(&a)[0].capacity, U256::from(50u128));
+
+        edges.balances_mut().set_balance(b, a, U256::from(20u128));
+        assert_eq!(edges.incoming(&b)[0].capacity, U256::from(40u128));
+
+        edges.balances_mut().set_organization(b, true);
+        assert_eq!(edges.outgoing(&a)[0].capacity, U256::from(1000u128));
+
+        // An absolute capacity replaces the percentage.
+        edges.update(Edge {
+            capacity: U256::from(7u128),
+            ..edge
+        });
+        // b can return the tokens of a it holds.
+        assert_eq!(
+            edges.effective_edges(),
+            vec![
+                Edge {
+                    capacity: U256::from(7u128),
+                    ..edge
+                },
+                Edge {
+                    from: b,
+                    to: a,
+                    token: a,
+                    capacity: U256::from(20u128),
+                }
+            ]
+        );
     }
-    index
 }
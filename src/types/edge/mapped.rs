@@ -0,0 +1,81 @@
+//! Edges used in place in a file mapped into memory, see `io::map_edges`.
+
+use std::fmt::{Debug, Formatter};
+use std::mem::{align_of, offset_of, size_of};
+use std::ops::Deref;
+use std::slice;
+use std::sync::Arc;
+
+use memmap2::Mmap;
+
+use crate::types::Edge;
+
+/// The size of an edge in memory and in mapped files: the three addresses,
+/// four zero bytes and the capacity as two 128-bit words, high word first.
+pub const EDGE_SIZE: usize = 96;
+
+const _: () = assert!(size_of::<Edge>() == EDGE_SIZE);
+const _: () = assert!(offset_of!(Edge, capacity) == 64);
+
+/// A read-only view of edges stored in the memory layout of `Edge` in a mapped file.
+#[derive(Clone)]
+pub struct MappedEdges {
+    map: Arc<Mmap>,
+    offset: usize,
+    len: usize,
+}
+
+impl MappedEdges {
+    /// Views `len` edges starting at `offset` in the mapped file. The words of the
+    /// capacities are stored little-endian, so files can only be used in place
+    /// on little-endian targets.
+    pub fn new(map: Arc<Mmap>, offset: usize, len: usize) -> Result<MappedEdges, String> {
+        if cfg!(target_endian = "big") {
+            return Err("Mapped edge files are not supported on big-endian targets".to_string());
+        }
+        if !(map.as_ptr() as usize + offset).is_multiple_of(align_of::<Edge>()) {
+            return Err(format!("Misaligned edges at offset {offset}"));
+        }
+        match len
+            .checked_mul(EDGE_SIZE)
+            .and_then(|l| l.checked_add(offset))
+        {
+            Some(end) if end <= map.len() => Ok(MappedEdges { map, offset, len }),
+            _ => Err(format!(
+                "Expected {len} edges at offset {offset}, but the file has only {} bytes",
+                map.len()
+            )),
+        }
+    }
+}
+
+impl Deref for MappedEdges {
+    type Target = [Edge];
+
+    fn deref(&self) -> &[Edge] {
+        // Safety: the region is within the mapping and aligned (checked in `new`),
+        // `Edge` has a fixed layout and consists of plain bytes and integers, for
+        // which any bit pattern is valid.
+        unsafe { slice::from_raw_parts(self.map.as_ptr().add(self.offset).cast(), self.len) }
+    }
+}
+
+impl Debug for MappedEdges {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "MappedEdges({} edges)", self.len)
+    }
+}
+
+/// The bytes of an edge in mapped files, as it is laid out in memory on little-endian targets.
+pub fn to_bytes(edge: &Edge) -> [u8; EDGE_SIZE] {
+    let mut bytes = [0u8; EDGE_SIZE];
+    bytes[0..20].copy_from_slice(&edge.from.to_bytes());
+    bytes[20..40].copy_from_slice(&edge.to.to_bytes());
+    bytes[40..60].copy_from_slice(&edge.token.to_bytes());
+    let capacity = edge.capacity.to_be_bytes();
+    for (word, be) in bytes[64..].chunks_mut(16).zip(capacity.chunks(16)) {
+        word.copy_from_slice(be);
+        word.reverse();
+    }
+    bytes
+}
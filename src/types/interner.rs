@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use super::Address;
+
+/// The dense id of an address in an `AddressInterner`.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, Ord, PartialOrd)]
+pub struct AddressId(u32);
+
+impl AddressId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Maps the addresses of a graph to dense ids, so that the adjacency structures can be
+/// vectors indexed by id instead of hash maps keyed by 20-byte addresses. Addresses are
+/// only converted at the boundaries, ids are never reused.
+#[derive(Debug, Default, Clone)]
+pub struct AddressInterner {
+    addresses: Vec<Address>,
+    ids: HashMap<Address, AddressId>,
+}
+
+impl AddressInterner {
+    /// The id of the address, assigning the next id if it is new.
+    pub fn intern(&mut self, address: Address) -> AddressId {
+        *self.ids.entry(address).or_insert_with(|| {
+            let id = AddressId(
+                u32::try_from(self.addresses.len()).expect("Too many addresses to intern."),
+            );
+            self.addresses.push(address);
+            id
+        })
+    }
+
+    pub fn id(&self, address: &Address) -> Option<AddressId> {
+        self.ids.get(address).copied()
+    }
+
+    pub fn address(&self, id: AddressId) -> Address {
+        self.addresses[id.index()]
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    /// The addresses in the order of their ids.
+    pub fn addresses(&self) -> &[Address] {
+        &self.addresses
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interning() {
+        let (a, b) = (Address::from([1; 20]), Address::from([2; 20]));
+        let mut interner = AddressInterner::default();
+        let id = interner.intern(a);
+        assert_eq!(interner.intern(b).index(), 1);
+        assert_eq!(interner.intern(a), id);
+        assert_eq!(interner.id(&b).map(|id| interner.address(id)), Some(b));
+        assert_eq!(interner.id(&Address::from([3; 20])), None);
+        assert_eq!(interner.addresses(), [a, b]);
+    }
+}
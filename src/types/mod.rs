@@ -1,11 +1,14 @@
 pub mod address;
+pub mod demurrage;
 pub mod edge;
+pub mod interner;
 pub mod safe;
 pub mod token;
 pub mod u256;
 
 pub use address::Address;
+pub use demurrage::Demurrage;
 pub use edge::Edge;
-pub use safe::Safe;
-pub use token::Token;
-pub use u256::U256;
+pub use safe::{AccountType, Safe};
+pub use token::{Token, TokenType};
+pub use u256::{ParseU256Error, TryFromU256Error, U256};
@@ -1,46 +1,124 @@
-use std::{cmp::min, collections::BTreeMap};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
 use super::{Address, U256};
 
-#[derive(Default, Debug)]
+/// The kind of an account, which determines which tokens it has and accepts.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, Ord, PartialOrd)]
+pub enum AccountType {
+    /// An individual that mints its own token and accepts trusted tokens up to its
+    /// trust limit.
+    #[default]
+    Human,
+    /// An organization, which does not mint a token but accepts any amount of the
+    /// tokens it trusts and forwards the tokens it holds.
+    Organization,
+    /// A Circles v2 group, which mints group tokens and accepts any amount of the tokens
+    /// of its members as collateral.
+    Group,
+}
+
+impl AccountType {
+    pub const ALL: [AccountType; 3] = [
+        AccountType::Human,
+        AccountType::Organization,
+        AccountType::Group,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountType::Human => "human",
+            AccountType::Organization => "organization",
+            AccountType::Group => "group",
+        }
+    }
+
+    /// Whether the account has a token of its own that others can trust.
+    pub fn mints(&self) -> bool {
+        *self != AccountType::Organization
+    }
+}
+
+impl Display for AccountType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for AccountType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(AccountType::Human),
+            "organization" => Ok(AccountType::Organization),
+            "group" => Ok(AccountType::Group),
+            _ => Err(format!("Invalid account type: {s}")),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone)]
 pub struct Safe {
     /// The address of the token, or the address of the safe if
     /// the database does not use the distinction.
     pub token_address: Address,
     pub balances: BTreeMap<Address, U256>,
+    /// The day each Circles v2 balance was last updated at by the hub, by token.
+    pub balance_days: BTreeMap<Address, u64>,
     /// Limit percentage in "send to" direction
     pub limit_percentage: BTreeMap<Address, u8>,
-    pub organization: bool,
+    pub account_type: AccountType,
+    /// The safe was deactivated or its token was stopped. Such accounts can still
+    /// send the tokens they hold, but should not be used to forward transfers.
+    pub stopped: bool,
 }
 
 impl Safe {
     pub fn balance(&self, token: &Address) -> U256 {
-        *self.balances.get(token).unwrap_or(&U256::from(0))
+        *self.balances.get(token).unwrap_or(&U256::from(0u128))
     }
     /// @returns how much of their own tokens a user can send to receiver.
     pub fn trust_transfer_limit(&self, receiver: &Safe, trust_percentage: u8) -> U256 {
-        if receiver.organization {
-            // TODO treat this as "return to owner"
-            // i.e. limited / only constrained by the balance edge.
-            self.balance(&self.token_address)
+        if !self.account_type.mints() {
+            return U256::from(0u128);
+        }
+        trust_transfer_limit(
+            self.balance(&self.token_address),
+            receiver.balance(&self.token_address),
+            receiver.balance(&receiver.token_address),
+            receiver.account_type,
+            trust_percentage,
+        )
+    }
+}
+
+/// @returns how much of their own tokens a sender can send to a receiver that trusts
+/// them with `trust_percentage`, given the sender's balance of their own tokens and
+/// the receiver's balances of the sender's and of their own tokens.
+pub fn trust_transfer_limit(
+    sender_balance: U256,
+    receiver_balance: U256,
+    receiver_own_balance: U256,
+    receiver: AccountType,
+    trust_percentage: u8,
+) -> U256 {
+    if receiver != AccountType::Human {
+        // TODO treat this as "return to owner"
+        // i.e. limited / only constrained by the balance edge.
+        sender_balance
+    } else {
+        let amount =
+            (receiver_own_balance * U256::from(trust_percentage as u128)) / U256::from(100u128);
+        let scaled_receiver_balance =
+            receiver_balance * U256::from((100 - trust_percentage) as u128) / U256::from(100u128);
+        if amount < receiver_balance {
+            U256::from(0u128)
         } else {
-            let receiver_balance = receiver.balance(&self.token_address);
-
-            let amount = (receiver.balance(&receiver.token_address)
-                * U256::from(trust_percentage as u128))
-                / U256::from(100);
-            let scaled_receiver_balance =
-                receiver_balance * U256::from((100 - trust_percentage) as u128) / U256::from(100);
-            if amount < receiver_balance {
-                U256::from(0)
-            } else {
-                // TODO it should not be "min" - the second constraint
-                // is set by the balance edge.
-                min(
-                    amount - scaled_receiver_balance,
-                    self.balance(&self.token_address),
-                )
-            }
+            // TODO it should not be "min" - the second constraint
+            // is set by the balance edge.
+            (amount - scaled_receiver_balance).min(sender_balance)
         }
     }
 }
@@ -1,3 +1,6 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
 use super::Address;
 
 pub struct Token {
@@ -6,3 +9,43 @@ pub struct Token {
     #[allow(dead_code)]
     owner: Address,
 }
+
+/// The kind of a token, identified by the address of its owner.
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, Ord, PartialOrd)]
+pub enum TokenType {
+    /// A personal token minted by an individual account (Circles v1 and v2).
+    #[default]
+    Personal,
+    /// A Circles v2 group token, minted by a group against collateral in the
+    /// tokens of its members. Accounts that trust the group accept any amount of it.
+    Group,
+}
+
+impl TokenType {
+    pub const ALL: [TokenType; 2] = [TokenType::Personal, TokenType::Group];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenType::Personal => "personal",
+            TokenType::Group => "group",
+        }
+    }
+}
+
+impl Display for TokenType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for TokenType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "personal" => Ok(TokenType::Personal),
+            "group" => Ok(TokenType::Group),
+            _ => Err(format!("Invalid token type: {s}")),
+        }
+    }
+}
@@ -1,13 +1,19 @@
+use std::error::Error;
 use std::fmt::Debug;
 use std::fmt::Display;
 use std::fmt::Formatter;
-use std::ops::Div;
-use std::ops::Mul;
+use std::fmt::{Binary, LowerHex, UpperHex};
 use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use std::ops::{BitAnd, BitOr, BitXor, Not, Shl, Shr};
+use std::ops::{Div, Mul, Rem};
+use std::str::FromStr;
 
 use num_bigint::BigUint;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Clone, Copy, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
 pub struct U256([u128; 2]);
 
 impl U256 {
@@ -15,6 +21,7 @@ impl U256 {
         U256([high, low])
     }
     pub const MAX: U256 = U256::new(u128::MAX, u128::MAX);
+    pub const ZERO: U256 = U256::new(0, 0);
     pub fn from_bigint_truncating(input: BigUint) -> U256 {
         let digits = input.to_u64_digits();
         U256([
@@ -25,6 +32,43 @@ impl U256 {
         ])
     }
 
+    pub fn is_zero(&self) -> bool {
+        self.0[0] | self.0[1] == 0
+    }
+
+    /// The smaller of both values. Unlike `Ord::min`, it compares with a single
+    /// subtraction and selects the result without branching.
+    pub fn min(self, other: U256) -> U256 {
+        let (_, less) = self.overflowing_sub(other);
+        U256::select(less, self, other)
+    }
+
+    /// The larger of both values, see `min`.
+    pub fn max(self, other: U256) -> U256 {
+        let (_, less) = self.overflowing_sub(other);
+        U256::select(less, other, self)
+    }
+
+    /// The value restricted to `[low, high]`. Panics if `low > high`.
+    pub fn clamp(self, low: U256, high: U256) -> U256 {
+        assert!(low <= high, "clamp with low > high");
+        self.max(low).min(high)
+    }
+
+    /// `a` if `condition` holds, otherwise `b`.
+    fn select(condition: bool, a: U256, b: U256) -> U256 {
+        let mask = 0u128.wrapping_sub(condition as u128);
+        U256([
+            a.0[0] & mask | b.0[0] & !mask,
+            a.0[1] & mask | b.0[1] & !mask,
+        ])
+    }
+
+    /// The value as `u128`, or `u128::MAX` if it is larger.
+    pub fn as_u128_saturating(self) -> u128 {
+        u128::try_from(self).unwrap_or(u128::MAX)
+    }
+
     pub fn to_decimal(self) -> String {
         let value = BigUint::from(self.0[0]) << 128 | BigUint::from(self.0[1]);
         format!("{value}")
@@ -48,6 +92,70 @@ impl U256 {
         }
     }
 
+    /// `10^exponent`, e.g. the number of atto-Circles in one Circle for an exponent of 18.
+    /// Panics if the result does not fit into 256 bits.
+    pub fn exp10(exponent: u8) -> U256 {
+        (0..exponent).fold(U256::from(1u128), |acc, _| {
+            acc.checked_mul(U256::from(10u128))
+                .expect("Power of ten too large")
+        })
+    }
+
+    /// Parses a decimal string with an optional fractional part as a value with
+    /// `decimals` decimals, e.g. "1.5" with 18 decimals is 1500000000000000000.
+    pub fn from_decimal_units(item: &str, decimals: u8) -> Result<U256, ParseU256Error> {
+        let (integer, fraction) = item.split_once('.').unwrap_or((item, ""));
+        if fraction.len() > decimals as usize {
+            return Err(ParseU256Error::Precision);
+        }
+        if integer.is_empty() && fraction.is_empty() {
+            return Err(ParseU256Error::Empty);
+        }
+        // Both parts are plain decimal digits, `U256::from_str` would also accept
+        // hexadecimal numbers.
+        for part in [integer, fraction] {
+            if part.starts_with("0x") || part.starts_with(['+', '-']) {
+                return Err(ParseU256Error::BadPrefix);
+            }
+            if !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseU256Error::InvalidDigit);
+            }
+        }
+        let integer = if integer.is_empty() {
+            U256::ZERO
+        } else {
+            integer.parse::<U256>()?
+        };
+        let fraction = if fraction.is_empty() {
+            U256::ZERO
+        } else {
+            fraction.parse::<U256>()? * U256::exp10(decimals - fraction.len() as u8)
+        };
+        integer
+            .checked_mul(U256::exp10(decimals))
+            .and_then(|v| v.checked_add(fraction))
+            .ok_or(ParseU256Error::Overflow)
+    }
+
+    /// Formats the value as a decimal number with `decimals` decimals,
+    /// without trailing zeros, e.g. 1500000000000000000 with 18 decimals is "1.5".
+    pub fn to_decimal_units(self, decimals: u8) -> String {
+        let (integer, fraction) = self.div_rem(U256::exp10(decimals));
+        if fraction == U256::ZERO {
+            return integer.to_decimal();
+        }
+        let fraction = format!(
+            "{:0>width$}",
+            fraction.to_decimal(),
+            width = decimals as usize
+        );
+        format!(
+            "{}.{}",
+            integer.to_decimal(),
+            fraction.trim_end_matches('0')
+        )
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut result = Vec::new();
         for i in 0..=1 {
@@ -60,6 +168,211 @@ impl U256 {
         }
         result
     }
+
+    /// Returns the 32 byte big-endian representation.
+    pub fn to_be_bytes(self) -> [u8; 32] {
+        let mut result = [0u8; 32];
+        result[..16].copy_from_slice(&self.0[0].to_be_bytes());
+        result[16..].copy_from_slice(&self.0[1].to_be_bytes());
+        result
+    }
+
+    /// Returns the 32 byte little-endian representation.
+    pub fn to_le_bytes(self) -> [u8; 32] {
+        let mut result = [0u8; 32];
+        result[..16].copy_from_slice(&self.0[1].to_le_bytes());
+        result[16..].copy_from_slice(&self.0[0].to_le_bytes());
+        result
+    }
+
+    pub fn from_be_bytes(bytes: [u8; 32]) -> U256 {
+        let (high, low) = bytes.split_at(16);
+        U256([
+            u128::from_be_bytes(high.try_into().unwrap()),
+            u128::from_be_bytes(low.try_into().unwrap()),
+        ])
+    }
+
+    pub fn from_le_bytes(bytes: [u8; 32]) -> U256 {
+        let (low, high) = bytes.split_at(16);
+        U256([
+            u128::from_le_bytes(high.try_into().unwrap()),
+            u128::from_le_bytes(low.try_into().unwrap()),
+        ])
+    }
+
+    /// Computes `self + rhs`, returning the wrapped result and whether an overflow occurred.
+    pub fn overflowing_add(self, rhs: U256) -> (U256, bool) {
+        let (low, carry) = self.0[1].overflowing_add(rhs.0[1]);
+        let (high, overflow1) = self.0[0].overflowing_add(rhs.0[0]);
+        let (high, overflow2) = high.overflowing_add(carry as u128);
+        (U256([high, low]), overflow1 || overflow2)
+    }
+
+    /// Computes `self - rhs`, returning the wrapped result and whether an underflow occurred.
+    pub fn overflowing_sub(self, rhs: U256) -> (U256, bool) {
+        let (low, borrow) = self.0[1].overflowing_sub(rhs.0[1]);
+        let (high, overflow1) = self.0[0].overflowing_sub(rhs.0[0]);
+        let (high, overflow2) = high.overflowing_sub(borrow as u128);
+        (U256([high, low]), overflow1 || overflow2)
+    }
+
+    /// Computes `self * rhs`, returning the result truncated to 256 bits
+    /// and whether an overflow occurred.
+    pub fn overflowing_mul(self, rhs: U256) -> (U256, bool) {
+        let a = self.to_limbs();
+        let b = rhs.to_limbs();
+        let mut product = [0u64; 8];
+        for i in 0..4 {
+            let mut carry = 0u128;
+            for j in 0..4 {
+                let t = (a[i] as u128) * (b[j] as u128) + (product[i + j] as u128) + carry;
+                product[i + j] = t as u64;
+                carry = t >> 64;
+            }
+            product[i + 4] = carry as u64;
+        }
+        let overflow = product[4..].iter().any(|limb| *limb != 0);
+        (
+            U256::from_limbs([product[0], product[1], product[2], product[3]]),
+            overflow,
+        )
+    }
+
+    pub fn checked_add(self, rhs: U256) -> Option<U256> {
+        match self.overflowing_add(rhs) {
+            (result, false) => Some(result),
+            (_, true) => None,
+        }
+    }
+
+    pub fn checked_sub(self, rhs: U256) -> Option<U256> {
+        match self.overflowing_sub(rhs) {
+            (result, false) => Some(result),
+            (_, true) => None,
+        }
+    }
+
+    pub fn checked_mul(self, rhs: U256) -> Option<U256> {
+        match self.overflowing_mul(rhs) {
+            (result, false) => Some(result),
+            (_, true) => None,
+        }
+    }
+
+    /// Returns `None` if `rhs` is zero.
+    pub fn checked_div(self, rhs: U256) -> Option<U256> {
+        if rhs == U256::ZERO {
+            None
+        } else {
+            Some(self.div_rem(rhs).0)
+        }
+    }
+
+    /// Returns `None` if `rhs` is zero.
+    pub fn checked_rem(self, rhs: U256) -> Option<U256> {
+        if rhs == U256::ZERO {
+            None
+        } else {
+            Some(self.div_rem(rhs).1)
+        }
+    }
+
+    pub fn saturating_add(self, rhs: U256) -> U256 {
+        self.checked_add(rhs).unwrap_or(U256::MAX)
+    }
+
+    pub fn saturating_sub(self, rhs: U256) -> U256 {
+        self.checked_sub(rhs).unwrap_or(U256::ZERO)
+    }
+
+    pub fn saturating_mul(self, rhs: U256) -> U256 {
+        self.checked_mul(rhs).unwrap_or(U256::MAX)
+    }
+
+    /// Computes quotient and remainder of `self / rhs`.
+    /// Panics if `rhs` is zero.
+    pub fn div_rem(self, rhs: U256) -> (U256, U256) {
+        assert!(rhs != U256::ZERO, "attempt to divide by zero");
+        if self.0[0] == 0 && rhs.0[0] == 0 {
+            return (
+                U256::from(self.0[1] / rhs.0[1]),
+                U256::from(self.0[1] % rhs.0[1]),
+            );
+        }
+        if self < rhs {
+            return (U256::ZERO, self);
+        }
+        // Binary long division. The remainder is always smaller than `rhs`
+        // before shifting, so if the shift overflows, the true remainder is
+        // larger than `rhs` and the wrapping subtraction yields the correct value.
+        let mut quotient = [0u128; 2];
+        let mut remainder = U256::ZERO;
+        for i in (0..self.bit_len() as usize).rev() {
+            let overflow = remainder.0[0] >> 127 == 1;
+            remainder = U256([
+                remainder.0[0] << 1 | remainder.0[1] >> 127,
+                remainder.0[1] << 1 | self.bit(i) as u128,
+            ]);
+            if overflow || remainder >= rhs {
+                remainder = remainder.overflowing_sub(rhs).0;
+                quotient[1 - i / 128] |= 1 << (i % 128);
+            }
+        }
+        (U256(quotient), remainder)
+    }
+
+    /// The number of leading zero bits in the 256 bit representation.
+    pub fn leading_zeros(&self) -> u32 {
+        if self.0[0] == 0 {
+            128 + self.0[1].leading_zeros()
+        } else {
+            self.0[0].leading_zeros()
+        }
+    }
+
+    /// The number of bits needed to represent the value, zero for zero.
+    pub fn bit_len(&self) -> u32 {
+        256 - self.leading_zeros()
+    }
+
+    fn bit(&self, i: usize) -> bool {
+        (self.0[1 - i / 128] >> (i % 128)) & 1 == 1
+    }
+
+    /// The digits in base `2^bits_per_digit` without leading zeros, "0" for zero.
+    fn power_of_two_digits(self, bits_per_digit: u32, upper_case: bool) -> String {
+        let count = self.bit_len().div_ceil(bits_per_digit).max(1);
+        (0..count)
+            .rev()
+            .map(|i| {
+                let digit =
+                    (self >> (i * bits_per_digit)).0[1] as u32 & ((1 << bits_per_digit) - 1);
+                let digit = char::from_digit(digit, 16).unwrap();
+                match upper_case {
+                    true => digit.to_ascii_uppercase(),
+                    false => digit,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the value as four 64 bit limbs, least significant first.
+    fn to_limbs(self) -> [u64; 4] {
+        [
+            self.0[1] as u64,
+            (self.0[1] >> 64) as u64,
+            self.0[0] as u64,
+            (self.0[0] >> 64) as u64,
+        ]
+    }
+
+    fn from_limbs(limbs: [u64; 4]) -> U256 {
+        U256([
+            (limbs[3] as u128) << 64 | limbs[2] as u128,
+            (limbs[1] as u128) << 64 | limbs[0] as u128,
+        ])
+    }
 }
 
 impl From<u128> for U256 {
@@ -68,45 +381,120 @@ impl From<u128> for U256 {
     }
 }
 
-// TODO str is using unicode stuff - maybe we should use Vec<u8> for efficiency reasons?
+impl From<u64> for U256 {
+    fn from(item: u64) -> Self {
+        U256([0, item.into()])
+    }
+}
+
+impl From<u32> for U256 {
+    fn from(item: u32) -> Self {
+        U256([0, item.into()])
+    }
+}
+
+/// The error of converting a `U256` into an integer type it does not fit into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TryFromU256Error;
+
+impl Error for TryFromU256Error {}
+
+impl Display for TryFromU256Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "out of range integral type conversion attempted")
+    }
+}
+
+impl TryFrom<U256> for u128 {
+    type Error = TryFromU256Error;
+
+    fn try_from(value: U256) -> Result<Self, Self::Error> {
+        match value.0 {
+            [0, low] => Ok(low),
+            _ => Err(TryFromU256Error),
+        }
+    }
+}
+
+impl TryFrom<U256> for u64 {
+    type Error = TryFromU256Error;
+
+    fn try_from(value: U256) -> Result<Self, Self::Error> {
+        u128::try_from(value)?
+            .try_into()
+            .map_err(|_| TryFromU256Error)
+    }
+}
+
+/// Panics on malformed input, use `str::parse` to handle errors.
 impl From<&str> for U256 {
     fn from(item: &str) -> Self {
-        if item.starts_with("0x") {
-            let len = item.len();
-            assert!(len <= 2 + 32 + 32, "{}", len);
-            let low_start = if len >= 2 + 32 { len - 32 } else { 2 };
-            let low_hex = &item[low_start..];
-            // disallow + and - prefixes
-            assert!(
-                low_hex.as_bytes().first() != Some(&54) && low_hex.as_bytes().first() != Some(&43)
-            );
-            let low = if low_hex.is_empty() {
-                0
-            } else {
-                u128::from_str_radix(low_hex, 16).unwrap()
-            };
-            let high_start = if len >= 2 + 32 + 32 { len - 64 } else { 2 };
-            let high_hex = &item[high_start..low_start];
-            // disallow + and - prefixes
-            assert!(
-                high_hex.as_bytes().first() != Some(&45)
-                    && high_hex.as_bytes().first() != Some(&43)
-            );
-            let high = if high_hex.is_empty() {
-                0
-            } else {
-                u128::from_str_radix(high_hex, 16).unwrap()
-            };
-            U256([high, low])
+        item.parse()
+            .unwrap_or_else(|e| panic!("Invalid U256 \"{item}\": {e}"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseU256Error {
+    Empty,
+    /// A character that is not a digit in the respective base.
+    InvalidDigit,
+    /// The value does not fit into 256 bits.
+    Overflow,
+    /// A sign or an unsupported radix prefix.
+    BadPrefix,
+    /// More fractional digits than decimals.
+    Precision,
+}
+
+impl Error for ParseU256Error {}
+
+impl Display for ParseU256Error {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            ParseU256Error::Empty => write!(f, "cannot parse U256 from empty string"),
+            ParseU256Error::InvalidDigit => write!(f, "invalid digit found in string"),
+            ParseU256Error::Overflow => write!(f, "number too large to fit in 256 bits"),
+            ParseU256Error::BadPrefix => write!(f, "invalid sign or radix prefix"),
+            ParseU256Error::Precision => write!(f, "too many decimal places"),
+        }
+    }
+}
+
+/// Parses a decimal string or a 0x-prefixed hex string.
+/// Leading zeros are allowed, signs are not.
+impl FromStr for U256 {
+    type Err = ParseU256Error;
+
+    fn from_str(item: &str) -> Result<Self, Self::Err> {
+        if let Some(hex) = item.strip_prefix("0x") {
+            if hex.starts_with(['+', '-']) {
+                return Err(ParseU256Error::BadPrefix);
+            }
+            let hex = hex.trim_start_matches('0');
+            if hex.len() > 64 {
+                return Err(ParseU256Error::Overflow);
+            }
+            let mut result = U256::ZERO;
+            for (i, c) in hex.chars().rev().enumerate() {
+                let digit = c.to_digit(16).ok_or(ParseU256Error::InvalidDigit)? as u128;
+                result.0[1 - i / 32] |= digit << (4 * (i % 32));
+            }
+            Ok(result)
         } else {
-            let digits = item.parse::<num_bigint::BigUint>().unwrap().to_u64_digits();
-            assert!(digits.len() <= 4);
-            U256([
-                u128::from(*digits.get(3).unwrap_or(&0)) << 64
-                    | u128::from(*digits.get(2).unwrap_or(&0)),
-                u128::from(*digits.get(1).unwrap_or(&0)) << 64
-                    | u128::from(*digits.first().unwrap_or(&0)),
-            ])
+            if item.starts_with(['+', '-']) || item.starts_with("0X") {
+                return Err(ParseU256Error::BadPrefix);
+            }
+            if item.is_empty() {
+                return Err(ParseU256Error::Empty);
+            }
+            let ten = U256::from(10u128);
+            item.chars().try_fold(U256::ZERO, |acc, c| {
+                let digit = c.to_digit(10).ok_or(ParseU256Error::InvalidDigit)?;
+                acc.checked_mul(ten)
+                    .and_then(|v| v.checked_add(U256::from(digit as u128)))
+                    .ok_or(ParseU256Error::Overflow)
+            })
         }
     }
 }
@@ -117,15 +505,11 @@ impl From<U256> for BigUint {
     }
 }
 
+/// Wrapping addition. Use `checked_add` where an overflow has to be detected.
 impl Add for U256 {
     type Output = Self;
     fn add(self, rhs: Self) -> Self {
-        let (low, carry) = self.0[1].overflowing_add(rhs.0[1]);
-        let mut high = self.0[0].wrapping_add(rhs.0[0]);
-        if carry {
-            high = high.wrapping_add(1);
-        }
-        Self([high, low])
+        self.overflowing_add(rhs).0
     }
 }
 
@@ -138,35 +522,100 @@ impl AddAssign for U256 {
 impl Neg for U256 {
     type Output = Self;
     fn neg(self) -> Self {
-        let result = U256([!self.0[0], !self.0[1]]);
-        result + U256::from(1)
+        !self + U256::from(1u128)
+    }
+}
+
+impl Not for U256 {
+    type Output = Self;
+    fn not(self) -> Self {
+        U256([!self.0[0], !self.0[1]])
+    }
+}
+
+impl BitAnd for U256 {
+    type Output = Self;
+    fn bitand(self, rhs: Self) -> Self {
+        U256([self.0[0] & rhs.0[0], self.0[1] & rhs.0[1]])
+    }
+}
+
+impl BitOr for U256 {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        U256([self.0[0] | rhs.0[0], self.0[1] | rhs.0[1]])
     }
 }
 
+impl BitXor for U256 {
+    type Output = Self;
+    fn bitxor(self, rhs: Self) -> Self {
+        U256([self.0[0] ^ rhs.0[0], self.0[1] ^ rhs.0[1]])
+    }
+}
+
+/// Shifts to the left, bits shifted beyond 256 bits are dropped,
+/// i.e. shifting by 256 or more results in zero.
+impl Shl<u32> for U256 {
+    type Output = Self;
+    fn shl(self, shift: u32) -> Self {
+        let [high, low] = self.0;
+        match shift {
+            0 => self,
+            1..=127 => U256([high << shift | low >> (128 - shift), low << shift]),
+            128..=255 => U256([low << (shift - 128), 0]),
+            _ => U256::ZERO,
+        }
+    }
+}
+
+/// Shifts to the right, shifting by 256 or more results in zero.
+impl Shr<u32> for U256 {
+    type Output = Self;
+    fn shr(self, shift: u32) -> Self {
+        let [high, low] = self.0;
+        match shift {
+            0 => self,
+            1..=127 => U256([high >> shift, low >> shift | high << (128 - shift)]),
+            128..=255 => U256([0, high >> (shift - 128)]),
+            _ => U256::ZERO,
+        }
+    }
+}
+
+/// Wrapping subtraction. Use `checked_sub` where an underflow has to be detected.
 impl Sub for U256 {
     type Output = Self;
     fn sub(self, rhs: Self) -> Self {
-        self + (-rhs)
+        self.overflowing_sub(rhs).0
     }
 }
 
+/// Wrapping multiplication, i.e. the result is truncated to the lower 256 bits.
+/// Use `checked_mul` where an overflow has to be detected.
 impl Mul for U256 {
     type Output = Self;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        let self_big: BigUint = self.into();
-        let rhs_big: BigUint = rhs.into();
-        U256::from_bigint_truncating(self_big * rhs_big)
+        self.overflowing_mul(rhs).0
     }
 }
 
+/// Integer division rounding towards zero. Panics on division by zero.
 impl Div for U256 {
     type Output = Self;
 
     fn div(self, rhs: Self) -> Self::Output {
-        let self_big: BigUint = self.into();
-        let rhs_big: BigUint = rhs.into();
-        U256::from_bigint_truncating(self_big / rhs_big)
+        self.div_rem(rhs).0
+    }
+}
+
+/// Remainder of the integer division. Panics on division by zero.
+impl Rem for U256 {
+    type Output = Self;
+
+    fn rem(self, rhs: Self) -> Self::Output {
+        self.div_rem(rhs).1
     }
 }
 
@@ -176,13 +625,29 @@ impl SubAssign for U256 {
     }
 }
 
+/// Formats as a 0x-prefixed hex number, padded to the width if one is given.
 impl Display for U256 {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        if self.0[0] == 0 {
-            write!(f, "{:#x}", self.0[1])
-        } else {
-            write!(f, "{:#x}{:032x}", self.0[0], self.0[1])
-        }
+        f.pad(&format!("0x{}", self.power_of_two_digits(4, false)))
+    }
+}
+
+/// Formats like the primitive integers, e.g. `{:064x}` for an ABI word.
+impl LowerHex for U256 {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.pad_integral(true, "0x", &self.power_of_two_digits(4, false))
+    }
+}
+
+impl UpperHex for U256 {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.pad_integral(true, "0x", &self.power_of_two_digits(4, true))
+    }
+}
+
+impl Binary for U256 {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.pad_integral(true, "0b", &self.power_of_two_digits(1, false))
     }
 }
 
@@ -192,18 +657,114 @@ impl Debug for U256 {
     }
 }
 
+/// Serializes as a decimal string. Use `#[serde(with = "serde_hex")]`
+/// to serialize as a 0x-prefixed hex string instead.
+impl Serialize for U256 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serde_decimal::serialize(self, serializer)
+    }
+}
+
+/// Deserializes from a decimal string, a 0x-prefixed hex string or an unsigned integer.
+impl<'de> Deserialize<'de> for U256 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(U256Visitor)
+    }
+}
+
+struct U256Visitor;
+
+impl<'de> Visitor<'de> for U256Visitor {
+    type Value = U256;
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "a decimal or 0x-prefixed hex string or an unsigned integer"
+        )
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<U256, E> {
+        Ok(U256::from(v))
+    }
+
+    fn visit_u128<E: de::Error>(self, v: u128) -> Result<U256, E> {
+        Ok(U256::from(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<U256, E> {
+        v.parse()
+            .map_err(|e| E::custom(format!("invalid value \"{v}\": {e}")))
+    }
+}
+
+/// (De-)serialization as a decimal string, for use with `#[serde(with = "...")]`.
+pub mod serde_decimal {
+    use super::U256;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_decimal())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        U256::deserialize(deserializer)
+    }
+}
+
+/// (De-)serialization as a 0x-prefixed hex string, for use with `#[serde(with = "...")]`.
+pub mod serde_hex {
+    use super::U256;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        U256::deserialize(deserializer)
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::U256;
+    use super::{ParseU256Error, TryFromU256Error, U256};
     #[test]
     fn to_string() {
-        assert_eq!(format!("{}", U256::from(0)), "0x0");
+        assert_eq!(format!("{}", U256::from(0u128)), "0x0");
         assert_eq!(
             format!("{}", U256::from(u128::MAX)),
             "0xffffffffffffffffffffffffffffffff"
         );
     }
 
+    #[test]
+    fn formatting() {
+        let large = U256::new(0xab, 0xcd);
+        assert_eq!(format!("{large}"), "0xab000000000000000000000000000000cd");
+        assert_eq!(
+            format!("{}", U256::new(1, 0)),
+            format!("{:#x}", U256::new(1, 0))
+        );
+        assert_eq!(format!("{:>6}", U256::from(255u32)), "  0xff");
+        assert_eq!(format!("{:x}", U256::from(0u32)), "0");
+        assert_eq!(format!("{:x}", large), "ab000000000000000000000000000000cd");
+        assert_eq!(format!("{:X}", U256::from(0xabcu32)), "ABC");
+        assert_eq!(format!("{:#X}", U256::from(0xabcu32)), "0xABC");
+        assert_eq!(format!("{:08x}", U256::from(0xabcu32)), "00000abc");
+        assert_eq!(format!("{:#08x}", U256::from(0xabcu32)), "0x000abc");
+        assert_eq!(format!("{:*<6x}", U256::from(0xabcu32)), "abc***");
+        assert_eq!(format!("{:064x}", U256::MAX), "f".repeat(64));
+        assert_eq!(
+            format!("{:064x}", U256::from(1u32)),
+            format!("{}1", "0".repeat(63))
+        );
+        assert_eq!(format!("{:b}", U256::from(5u32)), "101");
+        assert_eq!(format!("{:#010b}", U256::from(5u32)), "0b00000101");
+        assert_eq!(format!("{:b}", U256::MAX), "1".repeat(256));
+        assert_eq!(format!("{:b}", U256::new(1, 0)).len(), 129);
+    }
+
     #[test]
     fn add() {
         assert_eq!(
@@ -211,21 +772,21 @@ mod test {
             "0x1fffffffffffffffffffffffffffffffe"
         );
         let mut x = U256::from(u128::MAX);
-        x += U256::from(1);
+        x += U256::from(1u128);
         assert_eq!(format!("{x}"), "0x100000000000000000000000000000000");
     }
 
     #[test]
     fn compare() {
-        assert!(U256::from(0) < U256::from(1));
-        assert!(U256::from("0x100000000000000000000000000000000") > U256::from(1));
+        assert!(U256::from(0u128) < U256::from(1u128));
+        assert!(U256::from("0x100000000000000000000000000000000") > U256::from(1u128));
     }
 
     #[test]
     fn from_hex() {
-        assert_eq!(U256::from("0x"), U256::from(0));
-        assert_eq!(U256::from("0x1"), U256::from(1));
-        assert_eq!(U256::from("0x01"), U256::from(1));
+        assert_eq!(U256::from("0x"), U256::from(0u128));
+        assert_eq!(U256::from("0x1"), U256::from(1u128));
+        assert_eq!(U256::from("0x01"), U256::from(1u128));
         assert_eq!(
             U256::from("0x1fffffffffffffffffffffffffffffffe"),
             U256::from(u128::MAX) + U256::from(u128::MAX)
@@ -236,14 +797,14 @@ mod test {
         );
         assert_eq!(
             U256::from("0x100000000000000000000000000000000"),
-            U256::from(u128::MAX) + U256::from(1)
+            U256::from(u128::MAX) + U256::from(1u128)
         );
     }
 
     #[test]
     fn from_decimal() {
-        assert_eq!(U256::from("0"), U256::from(0));
-        assert_eq!(U256::from("10"), U256::from(10));
+        assert_eq!(U256::from("0"), U256::from(0u128));
+        assert_eq!(U256::from("10"), U256::from(10u128));
         assert_eq!(
             U256::from("680564733841876926926749214863536422910"),
             U256::from(u128::MAX) + U256::from(u128::MAX)
@@ -254,7 +815,40 @@ mod test {
         );
         assert_eq!(
             U256::from("340282366920938463463374607431768211456"),
-            U256::from(u128::MAX) + U256::from(1)
+            U256::from(u128::MAX) + U256::from(1u128)
+        );
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("0x".parse(), Ok(U256::from(0u128)));
+        assert_eq!("0x0001".parse(), Ok(U256::from(1u128)));
+        assert_eq!("0xaBc".parse(), Ok(U256::from(0xabc_u128)));
+        assert_eq!("0123".parse(), Ok(U256::from(123u128)));
+        assert_eq!(
+            "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+                .parse(),
+            Ok(U256::MAX)
+        );
+        assert_eq!(
+            "0xffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff".parse(),
+            Ok(U256::MAX)
+        );
+        assert_eq!("".parse::<U256>(), Err(ParseU256Error::Empty));
+        assert_eq!("12a".parse::<U256>(), Err(ParseU256Error::InvalidDigit));
+        assert_eq!("0xfg".parse::<U256>(), Err(ParseU256Error::InvalidDigit));
+        assert_eq!("1.5".parse::<U256>(), Err(ParseU256Error::InvalidDigit));
+        assert_eq!("-1".parse::<U256>(), Err(ParseU256Error::BadPrefix));
+        assert_eq!("0x+1".parse::<U256>(), Err(ParseU256Error::BadPrefix));
+        assert_eq!("0X1".parse::<U256>(), Err(ParseU256Error::BadPrefix));
+        assert_eq!(
+            "115792089237316195423570985008687907853269984665640564039457584007913129639936"
+                .parse::<U256>(),
+            Err(ParseU256Error::Overflow)
+        );
+        assert_eq!(
+            "0x10000000000000000000000000000000000000000000000000000000000000000".parse::<U256>(),
+            Err(ParseU256Error::Overflow)
         );
     }
 
@@ -275,13 +869,62 @@ mod test {
         );
     }
 
+    #[test]
+    fn decimal_units() {
+        let one = U256::exp10(18);
+        assert_eq!(one, U256::from("1000000000000000000"));
+        assert_eq!(U256::from_decimal_units("1", 18), Ok(one));
+        assert_eq!(
+            U256::from_decimal_units("1.5", 18),
+            Ok(U256::from("1500000000000000000"))
+        );
+        assert_eq!(U256::from_decimal_units(".25", 2), Ok(U256::from(25u128)));
+        assert_eq!(U256::from_decimal_units("3.", 2), Ok(U256::from(300u128)));
+        assert_eq!(
+            U256::from_decimal_units("0.001", 2),
+            Err(ParseU256Error::Precision)
+        );
+        assert_eq!(U256::from_decimal_units(".", 2), Err(ParseU256Error::Empty));
+        for prefixed in ["1.-5", "+1.5", "-1", "1.0x1", "0x1.5"] {
+            assert_eq!(
+                U256::from_decimal_units(prefixed, 3),
+                Err(ParseU256Error::BadPrefix),
+                "{prefixed}"
+            );
+        }
+        assert_eq!(
+            U256::from_decimal_units("1.5a", 3),
+            Err(ParseU256Error::InvalidDigit)
+        );
+        assert_eq!(
+            U256::from_decimal_units("1e5", 2),
+            Err(ParseU256Error::InvalidDigit)
+        );
+        assert_eq!(
+            U256::from_decimal_units(&U256::MAX.to_decimal(), 1),
+            Err(ParseU256Error::Overflow)
+        );
+
+        assert_eq!(
+            U256::from("1500000000000000000").to_decimal_units(18),
+            "1.5"
+        );
+        assert_eq!(
+            U256::from(5u128).to_decimal_units(18),
+            "0.000000000000000005"
+        );
+        assert_eq!(U256::from(700u128).to_decimal_units(2), "7");
+        assert_eq!(U256::from(0u128).to_decimal_units(18), "0");
+        assert_eq!(U256::MAX.to_decimal_units(0), U256::MAX.to_decimal());
+    }
+
     #[test]
     fn to_mul_div() {
         let two = U256::from("2");
-        let three = U256::from(3);
+        let three = U256::from(3u128);
         let large = U256::from("0x100000000000000000000000000000000");
-        assert_eq!(two * three, U256::from(6));
-        assert_eq!(three / two, U256::from(1));
+        assert_eq!(two * three, U256::from(6u128));
+        assert_eq!(three / two, U256::from(1u128));
         assert_eq!((large * two) / two, large);
         assert_eq!(
             large / three,
@@ -294,6 +937,194 @@ mod test {
         );
     }
 
+    #[test]
+    fn checked_arithmetic() {
+        let large = U256::from("0x100000000000000000000000000000000");
+        assert_eq!(
+            U256::MAX.overflowing_add(U256::from(2u128)),
+            (U256::from(1u128), true)
+        );
+        assert_eq!(U256::MAX.checked_add(U256::from(1u128)), None);
+        assert_eq!(
+            U256::from(u128::MAX).checked_add(U256::from(1u128)),
+            Some(large)
+        );
+        assert_eq!(U256::from(1u128).checked_sub(U256::from(2u128)), None);
+        assert_eq!(
+            large.checked_sub(U256::from(1u128)),
+            Some(U256::from(u128::MAX))
+        );
+        assert_eq!(
+            U256::from(0u128).overflowing_sub(U256::from(1u128)),
+            (U256::MAX, true)
+        );
+        assert_eq!(U256::MAX.saturating_add(U256::from(7u128)), U256::MAX);
+        assert_eq!(
+            U256::from(3u128).saturating_sub(U256::from(7u128)),
+            U256::from(0u128)
+        );
+        assert_eq!(
+            U256::from(7u128).saturating_sub(U256::from(3u128)),
+            U256::from(4u128)
+        );
+        assert_eq!(large.checked_mul(large), None);
+        assert_eq!(large.saturating_mul(large), U256::MAX);
+        assert_eq!(
+            large.checked_mul(U256::from(u128::MAX)),
+            Some(U256::new(u128::MAX, 0))
+        );
+    }
+
+    #[test]
+    fn div_rem() {
+        let large = U256::from("0x100000000000000000000000000000000");
+        assert_eq!(U256::from(7u128) % U256::from(3u128), U256::from(1u128));
+        assert_eq!((large + U256::from(5u128)) % large, U256::from(5u128));
+        assert_eq!(U256::MAX / U256::MAX, U256::from(1u128));
+        assert_eq!(U256::MAX % U256::MAX, U256::from(0u128));
+        assert_eq!(U256::MAX / large, U256::from(u128::MAX));
+        assert_eq!(U256::MAX % large, U256::from(u128::MAX));
+        assert_eq!(
+            U256::MAX / U256::from(10u128),
+            U256::from("0x1999999999999999999999999999999999999999999999999999999999999999")
+        );
+        assert_eq!(U256::MAX % U256::from(10u128), U256::from(5u128));
+        let divisor =
+            U256::from("0x8000000000000000000000000000000000000000000000000000000000000001");
+        assert_eq!(
+            U256::MAX.div_rem(divisor),
+            (U256::from(1u128), U256::MAX - divisor)
+        );
+        assert_eq!(U256::from(1u128).checked_div(U256::from(0u128)), None);
+        assert_eq!(U256::from(1u128).checked_rem(U256::from(0u128)), None);
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(U256::from(7u32), U256::from(7u128));
+        assert_eq!(U256::from(u64::MAX), U256::new(0, u64::MAX.into()));
+        assert_eq!(u128::try_from(U256::from(u128::MAX)), Ok(u128::MAX));
+        assert_eq!(u128::try_from(U256::new(1, 0)), Err(TryFromU256Error));
+        assert_eq!(u64::try_from(U256::from(u64::MAX)), Ok(u64::MAX));
+        assert_eq!(
+            u64::try_from(U256::from(u64::MAX) + U256::from(1u32)),
+            Err(TryFromU256Error)
+        );
+        assert_eq!(u64::try_from(U256::new(1, 5)), Err(TryFromU256Error));
+        assert_eq!(U256::from(5u32).as_u128_saturating(), 5);
+        assert_eq!(U256::MAX.as_u128_saturating(), u128::MAX);
+    }
+
+    #[test]
+    fn min_max() {
+        let (small, large) = (U256::from(7u32), U256::new(1, 3));
+        assert_eq!(small.min(large), small);
+        assert_eq!(large.min(small), small);
+        assert_eq!(small.max(large), large);
+        assert_eq!(large.max(small), large);
+        assert_eq!(large.min(large), large);
+        assert_eq!(U256::MAX.max(U256::ZERO), U256::MAX);
+        assert_eq!(U256::from(5u32).clamp(small, large), small);
+        assert_eq!(U256::MAX.clamp(small, large), large);
+        assert_eq!(U256::new(1, 0).clamp(small, large), U256::new(1, 0));
+        assert!(U256::ZERO.is_zero());
+        assert!(!U256::new(1, 0).is_zero());
+        assert!(!U256::from(1u32).is_zero());
+    }
+
+    #[test]
+    fn bitwise() {
+        let a = U256::new(0xf0, 0xff00);
+        let b = U256::new(0x3c, 0x0ff0);
+        assert_eq!(a & b, U256::new(0x30, 0x0f00));
+        assert_eq!(a | b, U256::new(0xfc, 0xfff0));
+        assert_eq!(a ^ b, U256::new(0xcc, 0xf0f0));
+        assert_eq!(!U256::ZERO, U256::MAX);
+        assert_eq!(-U256::from(1u128), U256::MAX);
+
+        let one = U256::from(1u128);
+        assert_eq!(one << 0, one);
+        assert_eq!(one << 127, U256::new(0, 1 << 127));
+        assert_eq!(one << 128, U256::new(1, 0));
+        assert_eq!(one << 255, U256::new(1 << 127, 0));
+        assert_eq!(one << 256, U256::ZERO);
+        assert_eq!(U256::new(0, u128::MAX) << 4, U256::new(0xf, u128::MAX << 4));
+        assert_eq!(U256::MAX >> 255, one);
+        assert_eq!(U256::MAX >> 256, U256::ZERO);
+        assert_eq!(U256::new(0xf, 0) >> 4, U256::new(0, 0xf << 124));
+        assert_eq!(U256::new(1, 0) >> 128, one);
+        assert_eq!((U256::MAX >> 3) << 3, U256::MAX & !U256::from(7u128));
+
+        assert_eq!(U256::ZERO.leading_zeros(), 256);
+        assert_eq!(U256::ZERO.bit_len(), 0);
+        assert_eq!(one.bit_len(), 1);
+        assert_eq!(U256::from(u128::MAX).bit_len(), 128);
+        assert_eq!((one << 128).bit_len(), 129);
+        assert_eq!(U256::MAX.leading_zeros(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn div_by_zero() {
+        let _ = U256::from(1u128) / U256::from(0u128);
+    }
+
+    #[test]
+    fn serde() {
+        #[derive(serde::Serialize, serde::Deserialize, PartialEq, Debug)]
+        struct Balances {
+            decimal: U256,
+            #[serde(with = "super::serde_hex")]
+            hex: U256,
+        }
+        let large = U256::from("0x100000000000000000000000000000000");
+        let balances = Balances {
+            decimal: large,
+            hex: large,
+        };
+        let encoded = serde_json::to_string(&balances).unwrap();
+        assert_eq!(
+            encoded,
+            r#"{"decimal":"340282366920938463463374607431768211456","hex":"0x100000000000000000000000000000000"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Balances>(&encoded).unwrap(),
+            balances
+        );
+        assert_eq!(
+            serde_json::from_str::<U256>(r#""0x10""#).unwrap(),
+            U256::from(16u128)
+        );
+        assert_eq!(
+            serde_json::from_str::<U256>("17").unwrap(),
+            U256::from(17u128)
+        );
+        assert!(serde_json::from_str::<U256>(r#""0xzz""#).is_err());
+        assert!(serde_json::from_str::<U256>(r#""-1""#).is_err());
+        assert!(serde_json::from_str::<U256>(
+            r#""0x10000000000000000000000000000000000000000000000000000000000000000""#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn be_le_bytes() {
+        let value = U256::new(0x0102, 0x0304);
+        let mut be = [0u8; 32];
+        be[14] = 1;
+        be[15] = 2;
+        be[30] = 3;
+        be[31] = 4;
+        assert_eq!(value.to_be_bytes(), be);
+        assert_eq!(U256::from_be_bytes(be), value);
+        let mut le = be;
+        le.reverse();
+        assert_eq!(value.to_le_bytes(), le);
+        assert_eq!(U256::from_le_bytes(le), value);
+        assert_eq!(U256::MAX.to_be_bytes(), [255; 32]);
+        assert_eq!(U256::from_be_bytes([0; 32]), U256::from(0u128));
+    }
+
     #[test]
     fn to_bytes() {
         let zero = U256::from("0");
@@ -314,3 +1145,135 @@ mod test {
         );
     }
 }
+
+/// Cross-checks the arithmetic, parsing and formatting against `BigUint` on random
+/// values, mixed with the boundary values around 2^128 and 2^256.
+#[cfg(test)]
+mod properties {
+    use num_bigint::BigUint;
+
+    use super::U256;
+
+    /// A deterministic xorshift generator, so that failures are reproducible.
+    struct Values(u64);
+
+    impl Values {
+        fn next_u64(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn next_u128(&mut self) -> u128 {
+            u128::from(self.next_u64()) << 64 | u128::from(self.next_u64())
+        }
+
+        fn next(&mut self) -> U256 {
+            const BOUNDARIES: [U256; 8] = [
+                U256::ZERO,
+                U256::new(0, 1),
+                U256::new(0, u128::MAX - 1),
+                U256::new(0, u128::MAX),
+                U256::new(1, 0),
+                U256::new(1, 1),
+                U256::new(u128::MAX, u128::MAX - 1),
+                U256::MAX,
+            ];
+            match self.next_u64() % 4 {
+                0 => BOUNDARIES[(self.next_u64() % 8) as usize],
+                // Values of all bit lengths, so that small divisors and carries occur.
+                1 => {
+                    U256::new(self.next_u128(), self.next_u128()) >> (self.next_u64() % 256) as u32
+                }
+                _ => U256::new(self.next_u128(), self.next_u128()),
+            }
+        }
+    }
+
+    fn big(value: U256) -> BigUint {
+        value.into()
+    }
+
+    fn modulus() -> BigUint {
+        BigUint::from(1u32) << 256
+    }
+
+    fn for_pairs(check: impl Fn(U256, U256)) {
+        let mut values = Values(0x2545_f491_4f6c_dd1d);
+        for _ in 0..5000 {
+            check(values.next(), values.next());
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        for_pairs(|a, b| {
+            let (x, y) = (big(a), big(b));
+            let (sum, overflow) = a.overflowing_add(b);
+            assert_eq!(big(sum), (&x + &y) % modulus(), "{a} + {b}");
+            assert_eq!(overflow, &x + &y >= modulus(), "{a} + {b}");
+            let (difference, underflow) = a.overflowing_sub(b);
+            assert_eq!(
+                big(difference),
+                (&x + modulus() - &y) % modulus(),
+                "{a} - {b}"
+            );
+            assert_eq!(underflow, x < y, "{a} - {b}");
+            let (product, overflow) = a.overflowing_mul(b);
+            assert_eq!(big(product), (&x * &y) % modulus(), "{a} * {b}");
+            assert_eq!(overflow, &x * &y >= modulus(), "{a} * {b}");
+            if !b.is_zero() {
+                let (quotient, remainder) = a.div_rem(b);
+                assert_eq!(big(quotient), &x / &y, "{a} / {b}");
+                assert_eq!(big(remainder), &x % &y, "{a} % {b}");
+            }
+            assert_eq!(a.cmp(&b), x.cmp(&y), "{a} <=> {b}");
+            assert_eq!(big(a.min(b)), (&x).min(&y).clone());
+            assert_eq!(big(a.max(b)), (&x).max(&y).clone());
+        });
+    }
+
+    #[test]
+    fn bit_operations() {
+        for_pairs(|a, b| {
+            let (x, y) = (big(a), big(b));
+            assert_eq!(big(a & b), &x & &y);
+            assert_eq!(big(a | b), &x | &y);
+            assert_eq!(big(a ^ b), &x ^ &y);
+            assert_eq!(big(!a), &modulus() - 1u32 - &x);
+            let shift = (b.0[1] % 300) as u32;
+            assert_eq!(big(a << shift), (&x << shift) % modulus(), "{a} << {shift}");
+            assert_eq!(big(a >> shift), &x >> shift, "{a} >> {shift}");
+            assert_eq!(u64::from(a.bit_len()), x.bits(), "{a}");
+        });
+    }
+
+    #[test]
+    fn parse_and_format() {
+        for_pairs(|a, _| {
+            let x = big(a);
+            assert_eq!(a.to_decimal(), x.to_str_radix(10));
+            assert_eq!(format!("{a:x}"), x.to_str_radix(16));
+            assert_eq!(format!("{a:b}"), x.to_str_radix(2));
+            assert_eq!(format!("{a:064x}"), format!("{x:064x}"));
+            assert_eq!(a.to_decimal().parse::<U256>(), Ok(a));
+            assert_eq!(format!("{a}").parse::<U256>(), Ok(a));
+            assert_eq!(format!("0x{a:X}").parse::<U256>(), Ok(a));
+            assert_eq!(format!("0x{a:080x}").parse::<U256>(), Ok(a));
+            assert_eq!(U256::from_be_bytes(a.to_be_bytes()), a);
+            assert_eq!(U256::from_bigint_truncating(x.clone() + modulus()), a);
+        });
+    }
+
+    #[test]
+    fn parse_overflow() {
+        for_pairs(|a, _| {
+            let too_large = big(a) + modulus();
+            let decimal = too_large.to_str_radix(10);
+            let hex = format!("0x{}", too_large.to_str_radix(16));
+            assert!(decimal.parse::<U256>().is_err(), "{decimal}");
+            assert!(hex.parse::<U256>().is_err(), "{hex}");
+        });
+    }
+}
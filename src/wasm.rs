@@ -0,0 +1,169 @@
+//! A JavaScript-friendly API for `wasm32-unknown-unknown` (feature `wasm`, usually without
+//! `native`), so that wallets can compute small routes client-side on a subgraph they
+//! fetched before. Requests and results are JSON strings in the memory of the module:
+//!
+//! ```js
+//! const input = new TextEncoder().encode(JSON.stringify(request));
+//! const ptr = exports.pathfinder_alloc(input.length);
+//! new Uint8Array(exports.memory.buffer, ptr, input.length).set(input);
+//! const result = exports.pathfinder_compute_flow(ptr, input.length);
+//! const length = new DataView(exports.memory.buffer).getUint32(result, true);
+//! const bytes = new Uint8Array(exports.memory.buffer, result + 4, length);
+//! const output = JSON.parse(new TextDecoder().decode(bytes));
+//! exports.pathfinder_free(result, length + 4);
+//! ```
+//!
+//! See `compute_flow_json` for the format of requests and results.
+
+use json::JsonValue;
+
+use crate::graph::{self, FlowOptions};
+use crate::types::edge::EdgeDB;
+use crate::types::{Address, Edge, U256};
+
+/// Computes a transfer on the edges of the request, given as
+/// `{"edges": [{"from", "to", "token_owner", "capacity"}], "from", "to", "value",
+/// "max_distance", "max_transfers"}` with all but the last three required. Returns
+/// `{"flow", "transferSteps": [{"from", "to", "token_owner", "value"}]}` or `{"error"}`.
+/// Values and capacities are decimal strings.
+pub fn compute_flow_json(request: &str) -> String {
+    match compute_flow(request) {
+        Ok(result) => result,
+        Err(message) => json::object! { error: message },
+    }
+    .dump()
+}
+
+fn compute_flow(request: &str) -> Result<JsonValue, String> {
+    let request = json::parse(request).map_err(|e| format!("Invalid JSON: {e}"))?;
+    let edges = request["edges"]
+        .members()
+        .map(|edge| {
+            Ok(Edge {
+                from: address(&edge["from"])?,
+                to: address(&edge["to"])?,
+                token: address(&edge["token_owner"])?,
+                capacity: value(&edge["capacity"])?,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let options = FlowOptions {
+        requested_flow: match &request["value"] {
+            JsonValue::Null => U256::MAX,
+            v => value(v)?,
+        },
+        max_distance: request["max_distance"].as_u64(),
+        max_transfers: request["max_transfers"].as_u64(),
+        ..Default::default()
+    };
+    let result = graph::compute_flow_with_options(
+        &address(&request["from"])?,
+        &address(&request["to"])?,
+        &EdgeDB::new(edges),
+        &options,
+    )
+    .map_err(|e| e.to_string())?;
+    Ok(json::object! {
+        flow: result.flow.to_decimal(),
+        transferSteps: result.transfers.iter().map(|e| json::object! {
+            from: e.from.to_string(),
+            to: e.to.to_string(),
+            token_owner: e.token.to_string(),
+            value: e.capacity.to_decimal(),
+        }).collect::<Vec<_>>(),
+    })
+}
+
+fn address(value: &JsonValue) -> Result<Address, String> {
+    value
+        .as_str()
+        .ok_or_else(|| format!("Invalid address: {value}"))?
+        .parse()
+}
+
+fn value(value: &JsonValue) -> Result<U256, String> {
+    value
+        .as_str()
+        .ok_or_else(|| format!("Invalid value: {value}"))?
+        .parse()
+        .map_err(|e| format!("Invalid value {value}: {e}"))
+}
+
+/// Allocates `length` bytes for a request.
+#[no_mangle]
+pub extern "C" fn pathfinder_alloc(length: usize) -> *mut u8 {
+    Box::into_raw(vec![0u8; length].into_boxed_slice()) as *mut u8
+}
+
+/// Frees memory returned by `pathfinder_alloc` or `pathfinder_compute_flow`.
+///
+/// # Safety
+///
+/// `ptr` and `length` have to be those of a single allocation, which is not used anymore.
+#[no_mangle]
+pub unsafe extern "C" fn pathfinder_free(ptr: *mut u8, length: usize) {
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+        ptr, length,
+    )));
+}
+
+/// Computes a transfer for the JSON request of `length` bytes at `ptr` (see
+/// `compute_flow_json`) and frees the request. Returns the result as its length
+/// (`u32`, little-endian) followed by the JSON, to be freed with `pathfinder_free`.
+///
+/// # Safety
+///
+/// `ptr` and `length` have to be those of an allocation of `pathfinder_alloc`.
+#[no_mangle]
+pub unsafe extern "C" fn pathfinder_compute_flow(ptr: *mut u8, length: usize) -> *mut u8 {
+    let request = Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, length));
+    let result = compute_flow_json(&String::from_utf8_lossy(&request));
+    let mut output = (result.len() as u32).to_le_bytes().to_vec();
+    output.extend(result.as_bytes());
+    Box::into_raw(output.into_boxed_slice()) as *mut u8
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn flow_from_json() {
+        let (a, b, c) = (
+            Address::from([1; 20]).to_string(),
+            Address::from([2; 20]).to_string(),
+            Address::from([3; 20]).to_string(),
+        );
+        let request = json::object! {
+            edges: [
+                { from: a.as_str(), to: b.as_str(), token_owner: a.as_str(), capacity: "10" },
+                { from: b.as_str(), to: c.as_str(), token_owner: b.as_str(), capacity: "7" },
+            ],
+            from: a.as_str(),
+            to: c.as_str(),
+            value: "5",
+        }
+        .dump();
+        let result = json::parse(&compute_flow_json(&request)).unwrap();
+        assert_eq!(result["flow"], "5");
+        assert_eq!(result["transferSteps"].len(), 2);
+        assert_eq!(result["transferSteps"][1]["token_owner"], b.as_str());
+
+        let bytes = request.as_bytes();
+        unsafe {
+            let ptr = pathfinder_alloc(bytes.len());
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len());
+            let output = pathfinder_compute_flow(ptr, bytes.len());
+            let length = u32::from_le_bytes(*(output as *const [u8; 4])) as usize;
+            let json = std::slice::from_raw_parts(output.add(4), length);
+            assert_eq!(
+                json::parse(std::str::from_utf8(json).unwrap()).unwrap(),
+                result
+            );
+            pathfinder_free(output, length + 4);
+        }
+
+        let error = json::parse(&compute_flow_json(r#"{"from": "0x12"}"#)).unwrap();
+        assert_eq!(error["error"], "Invalid address: 0x12");
+    }
+}
@@ -0,0 +1,247 @@
+//! A minimal WebSocket (RFC 6455) implementation for the server and for the
+//! subscription of the indexer to new blocks: the opening handshake and reading
+//! and writing of text frames. Frames sent by a client are masked.
+
+use crypto::digest::Digest;
+use crypto::sha1::Sha1;
+use rustc_serialize::base64::{ToBase64, STANDARD};
+use std::io::{self, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Frames larger than this are rejected.
+const MAX_MESSAGE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// A message received from the client.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Ping(Vec<u8>),
+    Close,
+}
+
+/// Returns the response to the opening handshake with the given `Sec-WebSocket-Key`.
+pub fn handshake_response(key: &str) -> String {
+    format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    )
+}
+
+fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.input_str(&format!("{}{ACCEPT_GUID}", key.trim()));
+    let mut digest = [0u8; 20];
+    sha1.result(&mut digest);
+    digest.to_base64(STANDARD)
+}
+
+/// Performs the opening handshake of a client for `path` on `host`.
+pub fn client_handshake(
+    stream: &mut (impl Read + Write),
+    host: &str,
+    path: &str,
+) -> io::Result<()> {
+    let key = random_bytes::<16>().to_base64(STANDARD);
+    write!(
+        stream,
+        "GET {path} HTTP/1.1\r\nHost: {host}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {key}\r\nSec-WebSocket-Version: 13\r\n\r\n"
+    )?;
+    stream.flush()?;
+    // Reads byte by byte, so that no frame sent right after the response is consumed.
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 8192 {
+            return Err(invalid_data("WebSocket handshake response too long"));
+        }
+        let mut byte = [0u8; 1];
+        stream.read_exact(&mut byte)?;
+        response.push(byte[0]);
+    }
+    let response = String::from_utf8_lossy(&response).to_lowercase();
+    let status = response.lines().next().unwrap_or_default().to_string();
+    if !status.contains(" 101 ") {
+        return Err(invalid_data(&format!(
+            "WebSocket handshake rejected: {status}"
+        )));
+    }
+    let accept = format!("sec-websocket-accept: {}", accept_key(&key).to_lowercase());
+    if !response.lines().any(|line| line.trim() == accept) {
+        return Err(invalid_data("Invalid Sec-WebSocket-Accept header"));
+    }
+    Ok(())
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Bytes that only need to be unpredictable for other parties on the network,
+/// not cryptographically secure.
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut sha1 = Sha1::new();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    sha1.input(&nanos.to_le_bytes());
+    sha1.input(&std::process::id().to_le_bytes());
+    let mut digest = [0u8; 20];
+    sha1.result(&mut digest);
+    let mut bytes = [0u8; N];
+    bytes.copy_from_slice(&digest[..N]);
+    bytes
+}
+
+/// Reads the next message, combining fragmented frames.
+pub fn read_message(reader: &mut impl Read) -> io::Result<Message> {
+    let mut text = Vec::new();
+    loop {
+        let mut header = [0u8; 2];
+        reader.read_exact(&mut header)?;
+        let fin = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0f;
+        let masked = header[1] & 0x80 != 0;
+        let length = match header[1] & 0x7f {
+            126 => {
+                let mut buf = [0u8; 2];
+                reader.read_exact(&mut buf)?;
+                u16::from_be_bytes(buf) as u64
+            }
+            127 => {
+                let mut buf = [0u8; 8];
+                reader.read_exact(&mut buf)?;
+                u64::from_be_bytes(buf)
+            }
+            length => length as u64,
+        };
+        if length + text.len() as u64 > MAX_MESSAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "WebSocket message too large",
+            ));
+        }
+        let mut mask = [0u8; 4];
+        if masked {
+            reader.read_exact(&mut mask)?;
+        }
+        let mut payload = vec![0u8; length as usize];
+        reader.read_exact(&mut payload)?;
+        if masked {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+        match opcode {
+            // Text and continuation frames.
+            0x0 | 0x1 => {
+                text.extend(payload);
+                if fin {
+                    return String::from_utf8(text)
+                        .map(Message::Text)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+            }
+            0x8 => return Ok(Message::Close),
+            0x9 => return Ok(Message::Ping(payload)),
+            // Pongs and binary frames are ignored.
+            _ => {}
+        }
+    }
+}
+
+/// Writes a text message as a single frame.
+pub fn write_text(writer: &mut impl Write, text: &str) -> io::Result<()> {
+    write_frame(writer, 0x1, text.as_bytes(), None)
+}
+
+pub fn write_pong(writer: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    write_frame(writer, 0xa, payload, None)
+}
+
+pub fn write_close(writer: &mut impl Write) -> io::Result<()> {
+    write_frame(writer, 0x8, &[], None)
+}
+
+/// Like `write_text`, but masked, as a client has to.
+pub fn write_client_text(writer: &mut impl Write, text: &str) -> io::Result<()> {
+    write_frame(writer, 0x1, text.as_bytes(), Some(random_bytes()))
+}
+
+pub fn write_client_pong(writer: &mut impl Write, payload: &[u8]) -> io::Result<()> {
+    write_frame(writer, 0xa, payload, Some(random_bytes()))
+}
+
+fn write_frame(
+    writer: &mut impl Write,
+    opcode: u8,
+    payload: &[u8],
+    mask: Option<[u8; 4]>,
+) -> io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        0..=125 => frame.push(mask_bit | payload.len() as u8),
+        126..=0xffff => {
+            frame.push(mask_bit | 126);
+            frame.extend((payload.len() as u16).to_be_bytes());
+        }
+        _ => {
+            frame.push(mask_bit | 127);
+            frame.extend((payload.len() as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(mask) => {
+            frame.extend(mask);
+            frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        }
+        None => frame.extend(payload),
+    }
+    writer.write_all(&frame)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn handshake() {
+        // The example from RFC 6455.
+        assert!(handshake_response("dGhlIHNhbXBsZSBub25jZQ==")
+            .contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    }
+
+    #[test]
+    fn frames() {
+        // "Hello" split into two fragments, masked with the key from RFC 6455.
+        let frames = [
+            0x01, 0x83, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, //
+            0x80, 0x82, 0x37, 0xfa, 0x21, 0x3d, 0x5b, 0x95,
+        ];
+        assert_eq!(
+            read_message(&mut &frames[..]).unwrap(),
+            Message::Text("Hello".to_string())
+        );
+
+        let text = "x".repeat(300);
+        let mut written = Vec::new();
+        write_text(&mut written, &text).unwrap();
+        assert_eq!(written[..4], [0x81, 126, 0x01, 0x2c]);
+        assert_eq!(
+            read_message(&mut &written[..]).unwrap(),
+            Message::Text(text)
+        );
+        let mut written = Vec::new();
+        write_client_text(&mut written, "Hello").unwrap();
+        assert_eq!(written[..2], [0x81, 0x85]);
+        assert_eq!(
+            read_message(&mut &written[..]).unwrap(),
+            Message::Text("Hello".to_string())
+        );
+        let mut written = Vec::new();
+        write_close(&mut written).unwrap();
+        assert_eq!(read_message(&mut &written[..]).unwrap(), Message::Close);
+    }
+}
@@ -0,0 +1,157 @@
+//! A bounded queue of jobs processed by a fixed number of worker threads.
+//! Jobs are rejected instead of queued if the queue is full, so that callers
+//! can tell clients to retry later instead of piling up work.
+
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobError {
+    /// The job was not run because all workers are busy and the queue is full.
+    QueueFull,
+    /// The job panicked or its results could not be passed on.
+    Aborted,
+}
+
+impl Error for JobError {}
+
+impl Display for JobError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobError::QueueFull => write!(f, "Queue full, try again later"),
+            JobError::Aborted => write!(f, "Job aborted"),
+        }
+    }
+}
+
+/// A message from a streaming job: an intermediate result or the final result.
+enum Streamed<I, T> {
+    Item(I),
+    Done(T),
+}
+
+pub struct WorkQueue {
+    sender: SyncSender<Job>,
+}
+
+impl WorkQueue {
+    /// Starts `workers` threads. At most `capacity` jobs wait for a worker.
+    pub fn new(workers: usize, capacity: usize) -> WorkQueue {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..workers {
+            let receiver = receiver.clone();
+            thread::spawn(move || loop {
+                let job = match receiver.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => return,
+                };
+                job();
+            });
+        }
+        WorkQueue { sender }
+    }
+
+    /// Runs `job` on a worker and waits for its result.
+    pub fn run<T: Send + 'static>(
+        &self,
+        job: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T, JobError> {
+        self.run_streaming(
+            move |_: &mut dyn FnMut(()) -> io::Result<()>| job(),
+            &mut |_| Ok(()),
+        )
+    }
+
+    /// Runs `job` on a worker and passes the intermediate results it emits to
+    /// `emit` on the calling thread while waiting for its final result. If `emit`
+    /// fails, the job's calls to its emit function fail from then on and the job
+    /// is aborted.
+    pub fn run_streaming<I: Send + 'static, T: Send + 'static>(
+        &self,
+        job: impl FnOnce(&mut dyn FnMut(I) -> io::Result<()>) -> T + Send + 'static,
+        emit: &mut dyn FnMut(I) -> io::Result<()>,
+    ) -> Result<T, JobError> {
+        let (sender, receiver) = mpsc::channel();
+        let job: Job = Box::new(move || {
+            let result = job(&mut |item| {
+                sender
+                    .send(Streamed::Item(item))
+                    .map_err(|_| io::ErrorKind::BrokenPipe.into())
+            });
+            let _ = sender.send(Streamed::Done(result));
+        });
+        match self.sender.try_send(job) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                return Err(JobError::QueueFull)
+            }
+        }
+        loop {
+            match receiver.recv() {
+                // Returning drops the receiver, which makes the job's emit fail.
+                Ok(Streamed::Item(item)) => emit(item).map_err(|_| JobError::Aborted)?,
+                Ok(Streamed::Done(result)) => return Ok(result),
+                // The job panicked.
+                Err(_) => return Err(JobError::Aborted),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Barrier;
+
+    #[test]
+    fn bounded() {
+        let queue = Arc::new(WorkQueue::new(1, 1));
+        let started = Arc::new(Barrier::new(2));
+        let release = Arc::new(Barrier::new(2));
+        // Occupy the worker and the queue.
+        let busy = {
+            let (queue, started, release) = (queue.clone(), started.clone(), release.clone());
+            thread::spawn(move || {
+                queue.run(move || {
+                    started.wait();
+                    release.wait();
+                    1
+                })
+            })
+        };
+        started.wait();
+        // The worker is busy, so one more job fills the queue.
+        let (done, filler_done) = mpsc::channel();
+        assert!(queue
+            .sender
+            .try_send(Box::new(move || done.send(()).unwrap()))
+            .is_ok());
+        assert_eq!(queue.run(|| 3), Err(JobError::QueueFull));
+        release.wait();
+        assert_eq!(busy.join().unwrap(), Ok(1));
+        filler_done.recv().unwrap();
+        assert_eq!(queue.run(|| 2), Ok(2));
+
+        let mut items = vec![];
+        let result = queue.run_streaming(
+            |emit| {
+                emit("a".to_string()).unwrap();
+                emit("b".to_string()).unwrap();
+                3
+            },
+            &mut |item| {
+                items.push(item);
+                Ok(())
+            },
+        );
+        assert_eq!(result, Ok(3));
+        assert_eq!(items, vec!["a", "b"]);
+    }
+}
@@ -0,0 +1,119 @@
+//! Runs the queries of the fixture graphs in `tests/fixtures` with every flow algorithm and
+//! compares the flows and transfers with the golden outputs in `tests/golden`.
+//!
+//! After an intended change of the results, regenerate the golden outputs with
+//! `UPDATE_GOLDEN=1 cargo test --test golden` and review the diff.
+//!
+//! A fixture has named `accounts`, `edges` between them (`from`, `to`, `token_owner`,
+//! `capacity`) and `queries` (`name`, `from`, `to` and optionally `value`, `max_hops` and
+//! `max_transfers`). Accounts are referred to by name in the golden outputs.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use json::JsonValue;
+use pathfinder2::graph::{compute_flow_with_options, FlowAlgorithm, FlowOptions};
+use pathfinder2::types::edge::EdgeDB;
+use pathfinder2::types::{Address, Edge, U256};
+
+#[test]
+fn golden_flows() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests");
+    let mut fixtures = fs::read_dir(directory.join("fixtures"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    fixtures.sort();
+    assert!(!fixtures.is_empty());
+    let update = env::var("UPDATE_GOLDEN").is_ok_and(|v| v == "1");
+    let mut mismatches = vec![];
+    for fixture in fixtures {
+        let name = fixture.file_name().unwrap().to_str().unwrap().to_string();
+        let output = run_fixture(&fixture);
+        let golden = directory.join("golden").join(&name);
+        if update {
+            fs::write(&golden, output.pretty(2) + "\n").unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&golden)
+            .map(|text| json::parse(&text).unwrap())
+            .unwrap_or(JsonValue::Null);
+        for (query, result) in output.entries() {
+            if expected[query] != *result {
+                mismatches.push(format!(
+                    "{name} {query}:\n  expected: {}\n  actual:   {}",
+                    expected[query].dump(),
+                    result.dump()
+                ));
+            }
+        }
+        if expected.len() != output.len() {
+            mismatches.push(format!("{name}: the golden output has other queries"));
+        }
+    }
+    assert!(
+        mismatches.is_empty(),
+        "Results differ from the golden outputs, run with UPDATE_GOLDEN=1 to accept them:\n{}",
+        mismatches.join("\n")
+    );
+}
+
+/// The results of all queries of the fixture with all algorithms, by
+/// `<query>/<algorithm>`.
+fn run_fixture(path: &PathBuf) -> JsonValue {
+    let fixture = json::parse(&fs::read_to_string(path).unwrap()).unwrap();
+    let accounts = fixture["accounts"]
+        .entries()
+        .map(|(name, address)| (name.to_string(), address.as_str().unwrap().parse().unwrap()))
+        .collect::<BTreeMap<String, Address>>();
+    let names = accounts
+        .iter()
+        .map(|(name, address)| (*address, name.as_str()))
+        .collect::<BTreeMap<_, _>>();
+    let account = |name: &JsonValue| accounts[name.as_str().expect("Expected an account")];
+    let edges = EdgeDB::new(
+        fixture["edges"]
+            .members()
+            .map(|edge| Edge {
+                from: account(&edge["from"]),
+                to: account(&edge["to"]),
+                token: account(&edge["token_owner"]),
+                capacity: decimal(&edge["capacity"]),
+            })
+            .collect(),
+    );
+    let mut output = JsonValue::new_object();
+    for query in fixture["queries"].members() {
+        for algorithm in FlowAlgorithm::ALL {
+            let options = FlowOptions {
+                requested_flow: match &query["value"] {
+                    JsonValue::Null => U256::MAX,
+                    value => decimal(value),
+                },
+                max_distance: query["max_hops"].as_u64(),
+                max_transfers: query["max_transfers"].as_u64(),
+                algorithm,
+                ..FlowOptions::default()
+            };
+            let (from, to) = (account(&query["from"]), account(&query["to"]));
+            let result = compute_flow_with_options(&from, &to, &edges, &options).unwrap();
+            let key = format!("{}/{}", query["name"], algorithm.as_str());
+            output[key] = json::object! {
+                flow: result.flow.to_decimal(),
+                transfers: result.transfers.iter().map(|e| json::object! {
+                    from: names[&e.from],
+                    to: names[&e.to],
+                    token_owner: names[&e.token],
+                    value: e.capacity.to_decimal(),
+                }).collect::<Vec<_>>(),
+            };
+        }
+    }
+    output
+}
+
+fn decimal(value: &JsonValue) -> U256 {
+    value.as_str().expect("Expected a decimal string").into()
+}
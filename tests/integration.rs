@@ -1,4 +1,4 @@
-use pathfinder2::graph::compute_flow;
+use pathfinder2::graph::{compute_flow, FlowAlgorithm};
 use pathfinder2::io::import_from_safes_binary;
 use pathfinder2::types::edge::EdgeDB;
 use pathfinder2::types::{Address, U256};
@@ -19,7 +19,7 @@ fn test_flow_chris_martin() {
         &chriseth,
         &martin,
         &edges,
-        U256::from(71152921504606846976),
+        U256::from(71152921504606846976u128),
         Some(2),
     );
     test_flow(&chriseth, &martin, &read_edges(), U256::MAX, Some(2));
@@ -48,7 +48,16 @@ fn test_flow(
     requested_flow: U256,
     max_distance: Option<u64>,
 ) {
-    let transfers = compute_flow(source, sink, edges, requested_flow, max_distance, None);
+    let transfers = compute_flow(
+        source,
+        sink,
+        edges,
+        requested_flow,
+        max_distance,
+        None,
+        FlowAlgorithm::default(),
+    )
+    .unwrap();
     println!("{transfers:?}");
 
     let token_owners = transfers